@@ -3,6 +3,7 @@
          (for-syntax check-err?)
          (for-syntax test-module)
          get-test-stats
+         get-test-results
          (for-syntax check)
          (for-syntax skip-compile))
 
@@ -14,65 +15,106 @@
 ;; Failed tests
 (define *failures* '())
 
-(define (mark-success)
-  (set! *SUCCESS-COUNT* (+ *SUCCESS-COUNT* 1)))
-
-(define (mark-failed name)
+;; Every check that ran, most recent first
+(define *results* '())
+
+;; The name of the enclosing test-module, if there is one
+(define *current-module* #f)
+
+(define (set-current-module! name)
+  (set! *current-module* name))
+
+(define (record-result name passed? message span)
+  (set! *results*
+        (cons (hash 'module
+                    *current-module*
+                    'name
+                    name
+                    'passed?
+                    passed?
+                    'message
+                    message
+                    'span
+                    span)
+              *results*)))
+
+(define (mark-success name span)
+  (set! *SUCCESS-COUNT* (+ *SUCCESS-COUNT* 1))
+  (record-result name #t #f span))
+
+(define (mark-failed name message span)
   (set! *FAILURE-COUNT* (+ *FAILURE-COUNT* 1))
-  (set! *failures* (cons name *failures*)))
+  (set! *failures* (cons name *failures*))
+  (record-result name #f message span))
 
 (define (mark-skipped)
   (set! *FAILED-TO-COMPILE* (+ *FAILED-TO-COMPILE* 1)))
 
+;; The test runner prints the results itself
 (define (print-success name)
-  (display "test > " name " ... ")
-  (display-color "Ok" 'green)
-  (newline))
+  (unless (test-runner-active?)
+    (display "test > " name " ... ")
+    (display-color "Ok" 'green)
+    (newline)))
 
 (define (print-failure name)
-  (display "test > " name " ... ")
-  (display-color "FAILED" 'red)
-  (newline))
+  (unless (test-runner-active?)
+    (display "test > " name " ... ")
+    (display-color "FAILED" 'red)
+    (newline)))
+
+(define (selected? name)
+  (or (test-selected? name) (test-selected? *current-module*)))
+
+(define (check-failed name message span)
+  (mark-failed name message span)
+  (print-failure name)
+  (unless (test-runner-active?)
+    (displayln "    " message)))
 
 (define-syntax check-equal?
   (syntax-rules ()
     [(check-equal? name input expected)
-     (with-handler (lambda (err)
-                     (mark-failed name)
-                     (print-failure name)
-                     (displayln err))
-                   (test name input expected))]))
+     (when (selected? name)
+       (with-handler (lambda (err) (check-failed name (trim (to-string err)) (#%syntax-span input)))
+                     (test-with-span name input expected (#%syntax-span input))))]))
 
 (define-syntax check-err?
   (syntax-rules ()
     [(check-err? name input expected)
-     (with-handler (lambda (err)
-                     (mark-success)
-                     (print-success name))
-                   (test name input expected))]))
+     (when (selected? name)
+       (with-handler (lambda (err)
+                       (mark-success name (#%syntax-span input))
+                       (print-success name))
+                     (test-with-span name input expected (#%syntax-span input))))]))
 
 ;; Check the equality, and otherwise do some nice printing of the result
 (define (test name input expected)
+  (test-with-span name input expected #f))
+
+(define (test-with-span name input expected span)
   (if (equal? input expected)
       (begin
-        (mark-success)
+        (mark-success name span)
         (print-success name))
-      (begin
-        (mark-failed name)
-        (print-failure name)
-        (displayln "    Expected: " expected ", Found " input))))
+      (check-failed name (trim (to-string "Expected:" expected "Found:" input)) span)))
 
 (define-syntax test-module
   (syntax-rules ()
     [(test-module name expr ...)
-     (when (get-test-mode)
+     ;; The test runner runs each test module in its own engine
+     (when (and (get-test-mode) (test-module-selected? name))
        (begin
-         (displayln "###### Running tests for module " name " ######")
+         (set-current-module! name)
+         (unless (test-runner-active?)
+           (displayln "###### Running tests for module " name " ######"))
          (begin
            expr ...)
-         (displayln "Test result: " *SUCCESS-COUNT* " passed; " *FAILURE-COUNT* " failed;")
-         (display "Failures: ")
-         (displayln *failures*)))]
+         (set-current-module! #f)
+         (unless (test-runner-active?)
+           (displayln "Test result: " *SUCCESS-COUNT* " passed; " *FAILURE-COUNT* " failed;")
+           (display "Failures: ")
+           (displayln *failures*))))]
     [(test-module expr ...)
      (begin
        expr ...)]))
@@ -96,6 +138,11 @@
         'failed-to-compile
         *FAILED-TO-COMPILE*))
 
+;; Each result is a hash with the keys 'module, 'name, 'passed?, 'message and 'span, in the
+;; order the checks ran
+(define (get-test-results)
+  (reverse *results*))
+
 ; (test-module
 ; (check-equal? "Checks that the expressions make sense"
 ;               (+ 10 20 30)
@@ -1,41 +1,259 @@
-use steel::steel_vm::{builtin::BuiltInModule, register_fn::RegisterFn};
-use steel::SteelVal;
+use std::cell::RefCell;
 
-fn hidden_function() -> usize {
+use abi_stable::std_types::{RBoxError, RHashMap, RResult, RVec};
+
+use steel::declare_module;
+use steel::steel_vm::ffi::{FFICallback, FFIModule, FFIStruct, FFIValue, RegisterFFIFn};
+
+fn hidden_function() -> isize {
     10
 }
 
-// thread_local! {
-//     static MODULE: Rc<BuiltInModule> = create_module();
-// }
+fn type_error(expected: &str, found: &FFIValue) -> RBoxError {
+    RBoxError::from_fmt(&format!("expected {expected}, found: {found:?}"))
+}
+
+fn make_symbol(name: String) -> FFIValue {
+    FFIValue::SymbolV(name.into())
+}
+
+fn symbol_length(symbol: FFIValue) -> RResult<FFIValue, RBoxError> {
+    match symbol {
+        FFIValue::SymbolV(s) => RResult::ROk(FFIValue::IntV(s.len() as isize)),
+        other => RResult::RErr(type_error("symbol", &other)),
+    }
+}
+
+// Works with both lists and vectors
+fn sum(values: Vec<isize>) -> isize {
+    values.into_iter().sum()
+}
+
+fn reverse_vector(vector: FFIValue) -> RResult<FFIValue, RBoxError> {
+    match vector {
+        FFIValue::Vector(v) => RResult::ROk(FFIValue::Vector(v.into_iter().rev().collect())),
+        other => RResult::RErr(type_error("vector", &other)),
+    }
+}
+
+fn char_frequencies(text: String) -> FFIValue {
+    let mut frequencies = RHashMap::new();
+
+    for c in text.chars() {
+        let count = match frequencies.get(&FFIValue::from(c)) {
+            Some(FFIValue::IntV(count)) => *count,
+            _ => 0,
+        };
+
+        frequencies.insert(FFIValue::from(c), FFIValue::IntV(count + 1));
+    }
+
+    FFIValue::HashMap(frequencies)
+}
+
+fn xor_bytes(bytes: FFIValue, key: isize) -> RResult<FFIValue, RBoxError> {
+    match bytes {
+        FFIValue::ByteVector(b) => RResult::ROk(FFIValue::ByteVector(
+            b.into_iter().map(|x| x ^ key as u8).collect(),
+        )),
+        other => RResult::RErr(type_error("bytevector", &other)),
+    }
+}
+
+// Expects a struct defined on the Steel side with `(struct point (x y))`
+fn point_transpose(point: FFIValue) -> RResult<FFIValue, RBoxError> {
+    match point {
+        FFIValue::Struct(FFIStruct { name, mut fields }) if name.as_str() == "point" => {
+            fields.swap(0, 1);
+            RResult::ROk(FFIStruct { name, fields }.into())
+        }
+        other => RResult::RErr(type_error("point", &other)),
+    }
+}
+
+fn map_native(function: FFICallback, values: Vec<FFIValue>) -> RResult<FFIValue, RBoxError> {
+    let mut output = RVec::with_capacity(values.len());
+
+    for value in values {
+        match function.call([value]) {
+            RResult::ROk(v) => output.push(v),
+            RResult::RErr(e) => return RResult::RErr(e),
+        }
+    }
 
-// #[no_mangle]
-pub fn create_module() -> Box<BuiltInModule> {
-    let mut module = BuiltInModule::new("external-dylib".to_string());
+    RResult::ROk(FFIValue::List(output))
+}
 
-    module.register_value("outside-value", SteelVal::StringV("Hello world!".into()));
-    module.register_fn("hidden-function", hidden_function);
+thread_local! {
+    static STASHED: RefCell<Option<FFICallback>> = RefCell::new(None);
+}
 
-    Box::new(module)
-    // module
+// Holding on to a callback is allowed, calling it outside of an FFI call is an error
+fn stash_callback(function: FFICallback) {
+    STASHED.with(|x| *x.borrow_mut() = Some(function));
 }
 
-#[no_mangle]
-pub fn generate_module() -> *mut BuiltInModule {
-    Box::into_raw(create_module())
+// Takes the arguments as `FFIValue`s, which could be callbacks, so this is allowed to call back
+fn call_stashed(args: Vec<FFIValue>) -> RResult<FFIValue, RBoxError> {
+    STASHED.with(|x| match x.borrow().as_ref() {
+        Some(function) => function.call(args),
+        None => RResult::RErr(RBoxError::from_fmt("nothing has been stashed")),
+    })
 }
 
-#[no_mangle]
-pub fn build_module(module: &mut BuiltInModule) {
-    module.set_name("external-dylib".to_string());
+declare_module!(create_module);
+
+pub fn create_module() -> FFIModule {
+    let mut module = FFIModule::new("external-dylib");
+
+    module
+        .register_value("outside-value", "Hello world!".to_string())
+        .register_fn("hidden-function", hidden_function)
+        .register_fn("make-symbol", make_symbol)
+        .register_fn("symbol-length", symbol_length)
+        .register_fn("sum", sum)
+        .register_fn("reverse-vector", reverse_vector)
+        .register_fn("char-frequencies", char_frequencies)
+        .register_fn("xor-bytes", xor_bytes)
+        .register_fn("point-transpose", point_transpose)
+        .register_fn("map-native", map_native)
+        .register_fn("stash-callback!", stash_callback)
+        .register_fn("call-stashed", call_stashed);
 
-    module.register_value("outside-value", SteelVal::StringV("Hello world!".into()));
-    module.register_fn("hidden-function", hidden_function);
+    module
 }
 
-#[no_mangle]
-pub fn free_module(ptr: *mut BuiltInModule) {
-    unsafe {
-        let _ = Box::from_raw(ptr);
+#[cfg(test)]
+mod example_dylib_tests {
+    use super::*;
+
+    use steel::steel_vm::engine::Engine;
+    use steel::steel_vm::ffi::{FFIWrappedModule, RBox};
+
+    fn run(program: &str) {
+        let module = FFIWrappedModule::new(RBox::new(create_module()))
+            .unwrap()
+            .build();
+
+        let mut vm = Engine::new();
+        vm.register_module(module);
+
+        vm.compile_and_run_raw_program(&format!("(require-builtin external-dylib) {program}"))
+            .unwrap();
+    }
+
+    #[test]
+    fn primitives() {
+        run(r#"
+            (assert! (equal? outside-value "Hello world!"))
+            (assert! (equal? (hidden-function) 10))
+        "#);
+    }
+
+    #[test]
+    fn symbols() {
+        run(r#"
+            (assert! (equal? (make-symbol "foo") 'foo))
+            (assert! (equal? (symbol-length 'hello) 5))
+        "#);
+    }
+
+    #[test]
+    fn lists_and_vectors() {
+        run(r#"
+            (assert! (equal? (sum (list 1 2 3)) 6))
+            (assert! (equal? (sum (vector 1 2 3)) 6))
+            (assert! (equal? (reverse-vector (vector 1 "two" 'three)) (vector 'three "two" 1)))
+        "#);
+    }
+
+    #[test]
+    fn chars_in_collections() {
+        run(r#"
+            (define frequencies (char-frequencies "hello"))
+            (assert! (equal? (hash-ref frequencies #\l) 2))
+            (assert! (equal? (hash-ref frequencies #\h) 1))
+        "#);
+    }
+
+    #[test]
+    fn bytevectors() {
+        run(r#"
+            (assert! (equal? (xor-bytes (bytes 1 2 3) 1) (bytes 0 3 2)))
+        "#);
+    }
+
+    #[test]
+    fn structs() {
+        run(r#"
+            (struct point (x y))
+            (define transposed (point-transpose (point 1 2)))
+            (assert! (point? transposed))
+            (assert! (equal? (point-x transposed) 2))
+            (assert! (equal? (point-y transposed) 1))
+        "#);
+    }
+
+    #[test]
+    fn callbacks() {
+        run(r#"
+            (define offset 10)
+            (assert! (equal? (map-native (lambda (x) (+ x offset)) (list 1 2 3)) (list 11 12 13)))
+            (assert! (equal? (map-native symbol->string (list 'a 'b)) (list "a" "b")))
+
+            ;; Callbacks can call back into native functions that take callbacks
+            (assert! (equal? (map-native (lambda (l) (map-native (lambda (x) (* x x)) l))
+                                         (list (list 1 2) (list 3)))
+                             (list (list 1 4) (list 9))))
+        "#);
+    }
+
+    #[test]
+    fn callback_errors_are_propagated() {
+        let module = FFIWrappedModule::new(RBox::new(create_module()))
+            .unwrap()
+            .build();
+
+        let mut vm = Engine::new();
+        vm.register_module(module);
+
+        assert!(vm
+            .compile_and_run_raw_program(
+                r#"
+                (require-builtin external-dylib)
+                (map-native (lambda (x) (error "bad value" x)) (list 1))
+            "#
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn callbacks_outside_of_ffi_calls() {
+        run(r#"
+            (stash-callback! (lambda (x) (+ x 10)))
+            ;; Still inside of an FFI call made by the same engine, so this is fine
+            (assert! (equal? (call-stashed (list 1)) 11))
+        "#);
+
+        // Calling the procedure from outside of the VM is refused, rather than touching a VM
+        // that isn't there.
+        assert!(call_stashed(vec![FFIValue::IntV(1)]).into_result().is_err());
+    }
+
+    #[test]
+    fn callbacks_from_other_engines() {
+        run("(stash-callback! (lambda (x) (+ x 10)))");
+
+        let module = FFIWrappedModule::new(RBox::new(create_module()))
+            .unwrap()
+            .build();
+
+        let mut vm = Engine::new();
+        vm.register_module(module);
+
+        // The procedure belongs to the first engine, so this engine can't run it
+        assert!(vm
+            .compile_and_run_raw_program("(require-builtin external-dylib) (call-stashed (list 1))")
+            .is_err());
     }
 }
@@ -71,6 +71,14 @@ fn ackermann(c: &mut Criterion) {
     benchmark_template(c, "ackermann-3-3", script, warmup);
 }
 
+fn float_loop(c: &mut Criterion) {
+    let warmup = r#"
+    (define (float-loop n acc)
+        (if (< n 0.5) acc (float-loop (- n 1.0) (+ acc (* n 0.5)))))"#;
+    let script = r#"(float-loop 10000.0 0.0)"#;
+    benchmark_template(c, "float-loop-10000", script, warmup);
+}
+
 fn ten_thousand_iterations(c: &mut Criterion) {
     let script = "(test 0)";
     let warmup = "(define test (lambda (x) (if (= x 10000) x (test (+ x 1)))))";
@@ -228,6 +236,31 @@ fn engine_creation(c: &mut Criterion) {
     c.bench_function("engine-creation", |b| b.iter(Engine::new));
 }
 
+// Engines are cloned from a per thread image once it exists, so startup is measured
+// on a fresh thread each time
+fn engine_startup(c: &mut Criterion) {
+    let path = std::env::temp_dir().join("steel-bench.image");
+    Engine::new().snapshot(&path).unwrap();
+
+    let mut group = c.benchmark_group("engine-startup");
+    group.sample_size(10);
+
+    group.bench_function("new", |b| {
+        b.iter(|| std::thread::spawn(|| drop(Engine::new())).join().unwrap())
+    });
+
+    group.bench_function("from-snapshot", |b| {
+        b.iter(|| {
+            let path = path.clone();
+            std::thread::spawn(move || drop(Engine::from_snapshot(path).unwrap()))
+                .join()
+                .unwrap()
+        })
+    });
+
+    group.finish();
+}
+
 fn register_function(c: &mut Criterion) {
     let mut vm = Engine::new();
     let f: fn(usize, usize) -> usize = |a: usize, b: usize| a + b;
@@ -410,15 +443,17 @@ criterion_group!(
     trie_sort_with_optimizations,
     fib_28,
     engine_creation,
+    engine_startup,
     register_function,
     multiple_transducers,
     // fib_28_contract,
-    ackermann // trie_sort,
-              // merge_sort,
-              // struct_construct,
-              // struct_construct_bigger,
-              // struct_get,
-              // struct_set
+    ackermann,
+    float_loop // trie_sort,
+               // merge_sort,
+               // struct_construct,
+               // struct_construct_bigger,
+               // struct_get,
+               // struct_set
 );
 
 criterion_main!(benches);
@@ -1,5 +1,13 @@
 // build.rs
 
+// Sequences from the program in `test_super_instructions_match_the_unfused_program`, so that
+// the unit tests run with some super instructions when there is no profile
+const TEST_PROFILE: &str = "\
+; op code sequences, most frequent first
+2 READLOCAL0 CALLGLOBAL:1
+1 LOADINT1 LOADINT2 MOVEREADLOCAL0 MUL:2 SUB:2
+";
+
 fn main() {
     #[cfg(feature = "dynamic")]
     {
@@ -47,5 +55,45 @@ fn main() {
 
     fs::write(dest_path, steel_gen::permutations::code_gen()).unwrap();
 
+    // Super instructions for the op code sequences in a profile written by the vm - see
+    // `Engine::op_code_sequence_profile`
+    let dest_path = Path::new(&out_dir).join("superinstructions.rs");
+
+    let patterns = match env::var_os("STEEL_SUPERINSTRUCTIONS") {
+        Some(profile) => {
+            println!("cargo:rerun-if-changed={}", Path::new(&profile).display());
+
+            let contents = fs::read_to_string(&profile).unwrap_or_else(|e| {
+                panic!("unable to read {}: {e}", Path::new(&profile).display())
+            });
+
+            steel_gen::profile::read_profile(&contents).unwrap_or_else(|e| {
+                panic!("invalid profile {}: {e}", Path::new(&profile).display())
+            })
+        }
+        None => Vec::new(),
+    };
+
+    for pattern in &patterns {
+        if !steel_gen::can_fuse(pattern) {
+            println!("cargo:warning=unable to generate a super instruction for {pattern:?}");
+        }
+    }
+
+    let test_patterns = if patterns.is_empty() {
+        steel_gen::profile::read_profile(TEST_PROFILE).unwrap()
+    } else {
+        patterns.clone()
+    };
+
+    fs::write(dest_path, steel_gen::generate_super_instructions(patterns)).unwrap();
+
+    fs::write(
+        Path::new(&out_dir).join("test_superinstructions.rs"),
+        steel_gen::generate_super_instructions(test_patterns),
+    )
+    .unwrap();
+
+    println!("cargo:rerun-if-env-changed=STEEL_SUPERINSTRUCTIONS");
     println!("cargo:rerun-if-changed=build.rs");
 }
@@ -55,7 +55,7 @@ use super::{
 
 use im_rc::HashMap as ImmutableHashMap;
 
-use std::time::Instant;
+use std::time::{Instant, SystemTime};
 
 // use itertools::Itertools;
 
@@ -495,6 +495,35 @@ impl Compiler {
         self.module_manager.modules()
     }
 
+    pub(crate) fn module_file_metadata(&self) -> &HashMap<PathBuf, SystemTime> {
+        self.module_manager.file_metadata()
+    }
+
+    // Merge previously compiled modules into the module cache, e.g. when restoring a snapshot
+    pub(crate) fn restore_modules(
+        &mut self,
+        compiled_modules: HashMap<PathBuf, CompiledModule>,
+        file_metadata: HashMap<PathBuf, SystemTime>,
+    ) {
+        let mut modules = self.module_manager.modules().clone();
+        let mut metadata = self.module_manager.file_metadata().clone();
+
+        modules.extend(compiled_modules);
+        metadata.extend(file_metadata);
+
+        let search_paths = self.module_manager.search_paths().to_vec();
+
+        self.module_manager = ModuleManager::new(modules, metadata);
+
+        for directory in search_paths {
+            self.module_manager.add_search_directory(directory);
+        }
+    }
+
+    pub fn add_search_directory(&mut self, directory: PathBuf) {
+        self.module_manager.add_search_directory(directory);
+    }
+
     pub fn expand_expressions(
         &mut self,
         exprs: Vec<ExprKind>,
@@ -57,6 +57,10 @@ impl ConstantMap {
         )))
     }
 
+    pub(crate) fn to_vec(&self) -> Vec<SteelVal> {
+        self.0.borrow().clone()
+    }
+
     pub fn from_vec(vec: Vec<SteelVal>) -> ConstantMap {
         ConstantMap(Rc::new(RefCell::new(vec)))
     }
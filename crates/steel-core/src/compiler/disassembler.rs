@@ -0,0 +1,407 @@
+//! Human readable listings of compiled bytecode.
+//!
+//! Every function gets its own listing, with constants and globals resolved to their values
+//! and names, jump targets replaced with labels, and the original source line attached to
+//! each instruction where a span is available.
+
+use std::{collections::HashMap, fmt};
+
+use serde::Serialize;
+
+use crate::{
+    compiler::{constants::ConstantMap, map::SymbolMap},
+    core::{instructions::Instruction, opcode::OpCode},
+    parser::{
+        parser::{SourceId, Sources},
+        tokens::TokenType,
+    },
+    steel_vm::vm::superinstructions::SUPER_INSTRUCTIONS,
+};
+
+/// The disassembled listings of a program, one per top level expression and function.
+#[derive(Debug, Clone, Serialize)]
+pub struct Disassembly {
+    pub functions: Vec<FunctionListing>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FunctionListing {
+    pub name: String,
+    /// The id of the function, or `None` for top level expressions
+    pub id: Option<usize>,
+    pub arity: Option<usize>,
+    pub multi_arity: bool,
+    /// Variables captured by the closure, in capture order
+    pub captures: Vec<String>,
+    pub instructions: Vec<InstructionListing>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InstructionListing {
+    /// Offset from the start of the function body, which is what jumps refer to
+    pub index: usize,
+    /// Set if this instruction is the target of a jump
+    pub label: Option<String>,
+    pub op_code: String,
+    pub payload: usize,
+    pub annotation: Option<String>,
+    pub line: Option<usize>,
+    pub source: Option<String>,
+}
+
+pub(crate) struct Disassembler<'a> {
+    symbol_map: &'a SymbolMap,
+    constant_map: &'a ConstantMap,
+    sources: &'a Sources,
+    line_starts: HashMap<SourceId, Vec<usize>>,
+}
+
+// A function found while walking its parent, to be listed after the parent
+struct PendingFunction<'a> {
+    name: String,
+    id: usize,
+    arity: usize,
+    multi_arity: bool,
+    captures: Vec<String>,
+    body: &'a [Instruction],
+}
+
+impl<'a> Disassembler<'a> {
+    pub(crate) fn new(
+        symbol_map: &'a SymbolMap,
+        constant_map: &'a ConstantMap,
+        sources: &'a Sources,
+    ) -> Self {
+        Self {
+            symbol_map,
+            constant_map,
+            sources,
+            line_starts: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn disassemble(mut self, expressions: &[Vec<Instruction>]) -> Disassembly {
+        let mut functions = Vec::new();
+
+        for (index, expression) in expressions.iter().enumerate() {
+            let listing = FunctionListing {
+                name: format!("toplevel {index}"),
+                id: None,
+                arity: None,
+                multi_arity: false,
+                captures: Vec::new(),
+                instructions: Vec::new(),
+            };
+
+            self.list_function(listing, expression, &mut functions);
+        }
+
+        Disassembly { functions }
+    }
+
+    fn list_function(
+        &mut self,
+        mut listing: FunctionListing,
+        body: &[Instruction],
+        functions: &mut Vec<FunctionListing>,
+    ) {
+        let mut pending = Vec::new();
+        let mut index = 0;
+
+        while let Some(instruction) = body.get(index) {
+            let (annotation, next) = match self.nested_function(body, index) {
+                Some((function, end)) => {
+                    let annotation = format!("function {} ({})", function.id, function.name);
+                    pending.push(function);
+                    (Some(annotation), end + 1)
+                }
+                None => (self.annotate(body, index), index + 1),
+            };
+
+            let (line, source) = self.source_line(instruction).unzip();
+
+            listing.instructions.push(InstructionListing {
+                index,
+                label: None,
+                op_code: format!("{:?}", instruction.op_code),
+                payload: instruction.payload_size,
+                annotation,
+                line,
+                source,
+            });
+
+            index = next;
+        }
+
+        let labels = jump_labels(&listing.instructions);
+
+        for instruction in &mut listing.instructions {
+            instruction.label = labels.get(&instruction.index).cloned();
+
+            if matches!(instruction.op_code.as_str(), "IF" | "JMP") {
+                if let Some(label) = labels.get(&instruction.payload) {
+                    instruction.annotation = Some(format!("-> {label}"));
+                }
+            }
+        }
+
+        functions.push(listing);
+
+        for function in pending {
+            let listing = FunctionListing {
+                name: function.name,
+                id: Some(function.id),
+                arity: Some(function.arity),
+                multi_arity: function.multi_arity,
+                captures: function.captures,
+                instructions: Vec::new(),
+            };
+
+            self.list_function(listing, function.body, functions);
+        }
+    }
+
+    // Functions are laid out inline:
+    //
+    // PUREFUNC (offset to ECLOSURE), PASS (multi arity), PASS (id), body..., ECLOSURE (arity)
+    // NEWSCLOSURE (offset to ECLOSURE), PASS (multi arity), PASS (id), NDEFS (n), captures..., body..., ECLOSURE (arity)
+    fn nested_function<'b>(
+        &self,
+        body: &'b [Instruction],
+        index: usize,
+    ) -> Option<(PendingFunction<'b>, usize)> {
+        let instruction = &body[index];
+
+        let header_length = match instruction.op_code {
+            OpCode::PUREFUNC => 3,
+            OpCode::NEWSCLOSURE => 4,
+            _ => return None,
+        };
+
+        let end = index + instruction.payload_size;
+        let eclosure = body.get(end)?;
+
+        if eclosure.op_code != OpCode::ECLOSURE {
+            return None;
+        }
+
+        let ndefs = if instruction.op_code == OpCode::NEWSCLOSURE {
+            body[index + 3].payload_size
+        } else {
+            0
+        };
+
+        let body_start = index + header_length + ndefs;
+
+        let captures = body
+            .get(index + header_length..body_start)?
+            .iter()
+            .map(|x| contents_to_string(x).unwrap_or_else(|| format!("{:?}", x.op_code)))
+            .collect();
+
+        let name = index
+            .checked_sub(1)
+            .map(|x| &body[x])
+            .filter(|x| x.op_code == OpCode::SDEF)
+            .and_then(contents_to_string)
+            .unwrap_or_else(|| "lambda".to_string());
+
+        Some((
+            PendingFunction {
+                name,
+                id: body[index + 2].payload_size,
+                arity: eclosure.payload_size,
+                multi_arity: body[index + 1].payload_size == 1,
+                captures,
+                body: body.get(body_start..end)?,
+            },
+            end,
+        ))
+    }
+
+    fn annotate(&self, body: &[Instruction], index: usize) -> Option<String> {
+        let instruction = &body[index];
+        let payload = instruction.payload_size;
+        let contents = contents_to_string(instruction);
+
+        match instruction.op_code {
+            OpCode::PUSHCONST => self
+                .constant_map
+                .try_get(payload)
+                .map(|x| format!("constant {x}")),
+            OpCode::LOADINT0 => Some("constant 0".to_string()),
+            OpCode::LOADINT1 => Some("constant 1".to_string()),
+            OpCode::LOADINT2 => Some("constant 2".to_string()),
+            OpCode::PUSH => self.global(payload).map(|x| format!("global {x}")),
+            OpCode::SET => self.global(payload).map(|x| format!("set global {x}")),
+            OpCode::BIND => self.global(payload).map(|x| format!("define global {x}")),
+            OpCode::CALLGLOBAL | OpCode::CALLGLOBALTAIL => body
+                .get(index + 1)
+                .and_then(|x| self.global(x.payload_size))
+                .map(|x| format!("call global {x} with {payload} args")),
+            OpCode::CGLOCALCONST => self.global(payload).map(|x| format!("call global {x}")),
+            OpCode::FUNC | OpCode::TAILCALL => Some(format!("call with {payload} args")),
+            OpCode::TCOJMP => Some(format!("self tail call with {payload} args")),
+            OpCode::READCAPTURED => contents.map(|x| format!("captured {x}")),
+            OpCode::READLOCAL
+            | OpCode::READLOCAL0
+            | OpCode::READLOCAL1
+            | OpCode::READLOCAL2
+            | OpCode::READLOCAL3
+            | OpCode::MOVEREADLOCAL
+            | OpCode::MOVEREADLOCAL0
+            | OpCode::MOVEREADLOCAL1
+            | OpCode::MOVEREADLOCAL2
+            | OpCode::MOVEREADLOCAL3 => contents.map(|x| format!("local {x}")),
+            OpCode::SETLOCAL => contents.map(|x| format!("set local {x}")),
+            OpCode::READALLOC | OpCode::ALLOC => contents.map(|x| format!("heap {x}")),
+            OpCode::SETALLOC => contents.map(|x| format!("set heap {x}")),
+            OpCode::SUPERINSTRUCTION => SUPER_INSTRUCTIONS
+                .get(payload)
+                .map(|x| format!("fused {}", pattern_to_string(x.pattern))),
+            OpCode::PASS | OpCode::Arity | OpCode::ECLOSURE | OpCode::POPPURE => None,
+            _ => contents,
+        }
+    }
+
+    fn global(&self, index: usize) -> Option<String> {
+        self.symbol_map
+            .values()
+            .get(index)
+            .map(|x| x.resolve().to_string())
+    }
+
+    fn source_line(&mut self, instruction: &Instruction) -> Option<(usize, String)> {
+        let span = instruction.contents.as_ref()?.span;
+        let source_id = span.source_id?;
+
+        let sources = self.sources.sources.lock().unwrap();
+        let source = sources.get(source_id)?;
+
+        let line_starts = self.line_starts.entry(source_id).or_insert_with(|| {
+            std::iter::once(0)
+                .chain(source.match_indices('\n').map(|x| x.0 + 1))
+                .collect()
+        });
+
+        let line = match line_starts.binary_search(&span.start) {
+            Ok(line) => line,
+            Err(line) => line.checked_sub(1)?,
+        };
+
+        let start = line_starts[line];
+        let end = line_starts
+            .get(line + 1)
+            .map(|x| x - 1)
+            .unwrap_or(source.len());
+
+        Some((line + 1, source.get(start..end)?.trim_end().to_string()))
+    }
+}
+
+fn contents_to_string(instruction: &Instruction) -> Option<String> {
+    instruction.contents.as_ref().and_then(|x| match &x.ty {
+        TokenType::Identifier(ident) => Some(ident.resolve().to_string()),
+        TokenType::Lambda => Some("lambda".to_string()),
+        other => Some(other.to_string()).filter(|x| !x.is_empty()),
+    })
+}
+
+// Written the same way as in a profile, e.g. `READLOCAL0 LOADINT2 LTE:2 IF`
+fn pattern_to_string(pattern: &[steel_gen::Pattern]) -> String {
+    pattern
+        .iter()
+        .map(|x| match x {
+            steel_gen::Pattern::Single(op) => format!("{op:?}"),
+            steel_gen::Pattern::Double(op, payload) => format!("{op:?}:{payload}"),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn jump_labels(instructions: &[InstructionListing]) -> HashMap<usize, String> {
+    let mut targets = instructions
+        .iter()
+        .filter(|x| matches!(x.op_code.as_str(), "IF" | "JMP"))
+        .map(|x| x.payload)
+        .collect::<Vec<_>>();
+
+    targets.sort_unstable();
+    targets.dedup();
+
+    targets
+        .into_iter()
+        .enumerate()
+        .map(|(label, target)| (target, format!("L{label}")))
+        .collect()
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, function) in self.functions.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+
+            write!(f, "{function}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for FunctionListing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.id, self.arity) {
+            (Some(id), Some(arity)) => {
+                write!(f, ";; function {id}: {}, arity {arity}", self.name)?;
+                if self.multi_arity {
+                    write!(f, " (variadic)")?;
+                }
+                writeln!(f)?;
+            }
+            _ => writeln!(f, ";; {}", self.name)?,
+        }
+
+        if !self.captures.is_empty() {
+            writeln!(f, ";; captures: {}", self.captures.join(", "))?;
+        }
+
+        let op_code_width = self
+            .instructions
+            .iter()
+            .map(|x| x.op_code.len())
+            .max()
+            .unwrap_or_default();
+
+        let mut last_line = None;
+
+        for instruction in &self.instructions {
+            if instruction.line.is_some() && instruction.line != last_line {
+                if let (Some(line), Some(source)) = (instruction.line, &instruction.source) {
+                    writeln!(f, ";; {line:>4} | {}", source.trim())?;
+                }
+
+                last_line = instruction.line;
+            }
+
+            if let Some(label) = &instruction.label {
+                writeln!(f, "{label}:")?;
+            }
+
+            let mut line = format!(
+                "    {:>4}  {:<op_code_width$}  {:<6}",
+                instruction.index, instruction.op_code, instruction.payload
+            );
+
+            if let Some(annotation) = &instruction.annotation {
+                line.push_str("  ; ");
+                line.push_str(annotation);
+            }
+
+            writeln!(f, "{}", line.trim_end())?;
+        }
+
+        Ok(())
+    }
+}
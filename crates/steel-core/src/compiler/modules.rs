@@ -8,9 +8,15 @@ use crate::{
         interner::InternedString,
         kernel::Kernel,
         parser::{ParseError, Parser, Sources, SyntaxObject},
+        span::Span,
         tokens::TokenType,
     },
-    steel_vm::{engine::ModuleContainer, transducers::interleave},
+    steel_vm::{
+        contract_checker::{check_typed_module, strip_typed_language, TypeDeclarations},
+        engine::ModuleContainer,
+        packages::package_search_paths,
+        transducers::interleave,
+    },
 };
 use crate::{parser::expand_visitor::Expander, rvals::Result};
 
@@ -18,12 +24,14 @@ use std::{
     borrow::Cow,
     collections::{HashMap, HashSet},
     io::Read,
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use crate::parser::expander::SteelMacro;
 use crate::stop;
 
+use serde::{Deserialize, Serialize};
+
 use std::time::SystemTime;
 
 use crate::parser::expand_visitor::{expand, extract_macro_defs};
@@ -35,7 +43,10 @@ use crate::parser::ast::IteratorExtensions;
 
 use super::{
     passes::mangle::{collect_globals, NameMangler, NameUnMangler},
-    program::{CONTRACT_OUT, FOR_SYNTAX, ONLY_IN, PREFIX_IN, REQUIRE_IDENT_SPEC},
+    program::{
+        ALL_DEFINED_OUT, ALL_FROM_OUT, CONTRACT_OUT, EXCEPT_IN, EXCEPT_OUT, FOR_SYNTAX, ONLY_IN,
+        PREFIX_IN, RENAME_IN, RENAME_OUT, REQUIRE_IDENT_SPEC,
+    },
 };
 
 const OPTION: &str = include_str!("../scheme/modules/option.scm");
@@ -49,6 +60,9 @@ const RESULT_NAME: &str = "steel/result";
 
 static BUILT_INS: &[(&str, &str)] = &[(OPTION_NAME, OPTION), (RESULT_NAME, RESULT)];
 
+// The module a require resolves to when it names a directory
+const MODULE_COLLECTION_ENTRY: &str = "main.scm";
+
 /// Manages the modules
 /// keeps some visited state on the manager for traversal
 /// Also keeps track of the metadata for each file in order to determine
@@ -58,6 +72,8 @@ pub(crate) struct ModuleManager {
     compiled_modules: HashMap<PathBuf, CompiledModule>,
     file_metadata: HashMap<PathBuf, SystemTime>,
     visited: HashSet<PathBuf>,
+    // Directories to search for required modules, ahead of STEEL_PATH and installed packages
+    search_paths: Vec<PathBuf>,
 }
 
 impl ModuleManager {
@@ -69,13 +85,26 @@ impl ModuleManager {
             compiled_modules,
             file_metadata,
             visited: HashSet::new(),
+            search_paths: Vec::new(),
         }
     }
 
+    pub(crate) fn add_search_directory(&mut self, directory: PathBuf) {
+        self.search_paths.push(directory);
+    }
+
+    pub(crate) fn search_paths(&self) -> &[PathBuf] {
+        &self.search_paths
+    }
+
     pub fn modules(&self) -> &HashMap<PathBuf, CompiledModule> {
         &self.compiled_modules
     }
 
+    pub(crate) fn file_metadata(&self) -> &HashMap<PathBuf, SystemTime> {
+        &self.file_metadata
+    }
+
     pub(crate) fn default() -> Self {
         Self::new(HashMap::new(), HashMap::new())
     }
@@ -104,6 +133,7 @@ impl ModuleManager {
             kernel,
             builtin_modules,
             global_macro_map,
+            &self.search_paths,
         )?;
 
         module_builder.compile()?;
@@ -143,6 +173,7 @@ impl ModuleManager {
             kernel,
             builtin_modules,
             global_macro_map,
+            &self.search_paths,
         )?;
 
         let mut module_statements = module_builder.compile()?;
@@ -156,6 +187,13 @@ impl ModuleManager {
             .map(|x| expand(x, global_macro_map))
             .collect::<Result<Vec<_>>>()?;
 
+        // Everything the main module defines stays visible to later programs run on the
+        // same engine, so all of it escapes
+        if let Some(types) = &module_builder.types {
+            let exported = collect_globals(&ast);
+            check_typed_module(&mut ast, types, &exported)?;
+        }
+
         {
             module_builder.source_ast = ast;
             module_builder.collect_provides();
@@ -172,30 +210,10 @@ impl ModuleManager {
             .map(|x| "mangler".to_string() + x.path.get_path().to_str().unwrap())
             .collect::<Vec<_>>();
 
-        let mut explicit_requires = HashMap::new();
-
         for require_object in &module_builder.require_objects
         // .chain(module_builder.built_ins.iter())
         {
             let path = require_object.path.get_path();
-            explicit_requires.clear();
-
-            // If there _are_ explicit identifiers to import, limit what we import to what
-            // is in the set
-            for ident in &require_object.idents_to_import {
-                match ident {
-                    MaybeRenamed::Normal(i) => {
-                        explicit_requires.insert(i.atom_identifier().unwrap().clone(), None);
-                    }
-
-                    MaybeRenamed::Renamed(from, to) => {
-                        explicit_requires.insert(
-                            from.atom_identifier().unwrap().clone(),
-                            Some(to.atom_identifier().unwrap().clone()),
-                        );
-                    }
-                }
-            }
 
             // println!("{:?}", path);
             let module = if let Some(module) = module_builder.compiled_modules.get(path.as_ref()) {
@@ -205,227 +223,14 @@ impl ModuleManager {
                 continue;
             };
 
-            for provide_expr in &module.provides {
-                // For whatever reason, the value coming into module.provides is an expression like: (provide expr...)
-                for provide in &provide_expr.list().unwrap().args[1..] {
-                    // println!("{}", provide);
-
-                    // println!("Top level provide handler");
-
-                    // Would be nice if this could be handled by some macro expansion...
-                    // See if contract/out
-
-                    let other_module_prefix = "mangler".to_string() + module.name.to_str().unwrap();
-
-                    // TODO: Expand the contract out into something we expect
-                    // Otherwise, this is going to blow up
-                    match provide {
-                        ExprKind::List(l) => {
-                            if let Some(qualifier) = l.first_ident() {
-                                match *qualifier {
-                                    x if x == *CONTRACT_OUT => {
-                                        // Directly expand into define/contract, but with the value just being the hash get below
-
-                                        // (bind/c contract name 'name)
-
-                                        let name = l.args.get(1).unwrap();
-
-                                        if !explicit_requires.is_empty()
-                                            && !name
-                                                .atom_identifier()
-                                                .map(|x| explicit_requires.contains_key(x))
-                                                .unwrap_or_default()
-                                        {
-                                            continue;
-                                        }
-
-                                        // TODO: THe contract has to get mangled with the prefix as well?
-                                        let contract = l.args.get(2).unwrap();
-
-                                        let hash_get = expr_list![
-                                            ExprKind::ident("hash-get"),
-                                            ExprKind::atom(
-                                                "__module-".to_string() + &other_module_prefix
-                                            ),
-                                            ExprKind::Quote(Box::new(Quote::new(
-                                                name.clone(),
-                                                SyntaxObject::default(TokenType::Quote)
-                                            ))),
-                                        ];
-
-                                        let mut owned_name = name.clone();
-
-                                        // If we have the alias listed, we should use it
-                                        if !explicit_requires.is_empty() {
-                                            if let Some(alias) = explicit_requires
-                                                .get(name.atom_identifier().unwrap())
-                                                .copied()
-                                                .flatten()
-                                            {
-                                                *owned_name.atom_identifier_mut().unwrap() =
-                                                    alias.clone();
-                                            }
-                                        }
-
-                                        if let Some(prefix) = &require_object.prefix {
-                                            if let Some(existing) = owned_name.atom_identifier_mut()
-                                            {
-                                                let mut prefixed_identifier = prefix.clone();
-                                                prefixed_identifier.push_str(existing.resolve());
-
-                                                // Update the existing identifier to point to a new one with the prefix applied
-                                                *existing = prefixed_identifier.into();
-                                            }
-                                        }
-
-                                        let define = ExprKind::Define(Box::new(Define::new(
-                                            owned_name,
-                                            // expr_list![
-                                            // ExprKind::ident("bind/c"),
-                                            // contract.clone(),
-                                            hash_get,
-                                            // ExprKind::Quote(Box::new(Quote::new(
-                                            // name.clone(),
-                                            // SyntaxObject::default(TokenType::Quote)
-                                            // ))),
-                                            // ],
-                                            SyntaxObject::default(TokenType::Define),
-                                        )));
-
-                                        require_defines.push(define);
-                                    }
-                                    x if x == *REQUIRE_IDENT_SPEC => {
-                                        // Directly expand into define/contract, but with the value just being the hash get below
-
-                                        // (bind/c contract name 'name)
-
-                                        let name = l.args.get(1).unwrap();
-
-                                        if !explicit_requires.is_empty()
-                                            && !name
-                                                .atom_identifier()
-                                                .map(|x| explicit_requires.contains_key(x))
-                                                .unwrap_or_default()
-                                        {
-                                            continue;
-                                        }
-
-                                        let hash_get = expr_list![
-                                            ExprKind::ident("hash-get"),
-                                            ExprKind::atom(
-                                                "__module-".to_string() + &other_module_prefix
-                                            ),
-                                            ExprKind::Quote(Box::new(Quote::new(
-                                                name.clone(),
-                                                SyntaxObject::default(TokenType::Quote)
-                                            ))),
-                                        ];
-
-                                        let mut owned_name = name.clone();
-
-                                        // If we have the alias listed, we should use it
-                                        if !explicit_requires.is_empty() {
-                                            if let Some(alias) = explicit_requires
-                                                .get(name.atom_identifier().unwrap())
-                                                .copied()
-                                                .flatten()
-                                            {
-                                                *owned_name.atom_identifier_mut().unwrap() =
-                                                    alias.clone();
-                                            }
-                                        }
-
-                                        if let Some(prefix) = &require_object.prefix {
-                                            if let Some(existing) = owned_name.atom_identifier_mut()
-                                            {
-                                                let mut prefixed_identifier = prefix.clone();
-                                                prefixed_identifier.push_str(existing.resolve());
-
-                                                // Update the existing identifier to point to a new one with the prefix applied
-                                                *existing = prefixed_identifier.into();
-                                            }
-                                        }
-
-                                        let define = ExprKind::Define(Box::new(Define::new(
-                                            owned_name,
-                                            // expr_list![
-                                            // ExprKind::ident("bind/c"),
-                                            // contract.clone(),
-                                            hash_get,
-                                            // ExprKind::Quote(Box::new(Quote::new(
-                                            // name.clone(),
-                                            // SyntaxObject::default(TokenType::Quote)
-                                            // ))),
-                                            // ],
-                                            SyntaxObject::default(TokenType::Define),
-                                        )));
-
-                                        require_defines.push(define);
-                                    }
-                                    _ => {
-                                        stop!(TypeMismatch => "foo provide expects either an identifier, (for-syntax <ident>), or (contract/out ...)")
-                                    }
-                                }
-                            } else {
-                                stop!(TypeMismatch => "provide expects either an identifier or a (for-syntax <ident>)")
-                            }
-                        }
-                        ExprKind::Atom(_) => {
-                            if !explicit_requires.is_empty()
-                                && !provide
-                                    .atom_identifier()
-                                    .map(|x| explicit_requires.contains_key(x))
-                                    .unwrap_or_default()
-                            {
-                                continue;
-                            }
-
-                            let hash_get = expr_list![
-                                ExprKind::ident("hash-get"),
-                                ExprKind::atom("__module-".to_string() + &other_module_prefix),
-                                ExprKind::Quote(Box::new(Quote::new(
-                                    provide.clone(),
-                                    SyntaxObject::default(TokenType::Quote)
-                                ))),
-                            ];
-
-                            let mut owned_provide = provide.clone();
-
-                            // If we have the alias listed, we should use it
-                            if !explicit_requires.is_empty() {
-                                if let Some(alias) = explicit_requires
-                                    .get(provide.atom_identifier().unwrap())
-                                    .copied()
-                                    .flatten()
-                                {
-                                    *owned_provide.atom_identifier_mut().unwrap() = alias.clone();
-                                }
-                            }
-
-                            // TODO: If there is a prefix applied, use it here?
-                            if let Some(prefix) = &require_object.prefix {
-                                if let Some(existing) = owned_provide.atom_identifier_mut() {
-                                    let mut prefixed_identifier = prefix.clone();
-                                    prefixed_identifier.push_str(existing.resolve());
-
-                                    // Update the existing identifier to point to a new one with the prefix applied
-                                    *existing = prefixed_identifier.into();
-                                }
-                            }
-
-                            let define = ExprKind::Define(Box::new(Define::new(
-                                owned_provide,
-                                hash_get,
-                                SyntaxObject::default(TokenType::Define),
-                            )));
+            for (name, value) in module.import_bindings(require_object, &module_builder.name)? {
+                let define = ExprKind::Define(Box::new(Define::new(
+                    ExprKind::atom(name),
+                    value,
+                    SyntaxObject::default(TokenType::Define),
+                )));
 
-                            require_defines.push(define);
-                        }
-                        _ => {
-                            stop!(TypeMismatch => "provide expression needs to either be a `contract/out` form or an identifier")
-                        }
-                    }
-                }
+                require_defines.push(define);
             }
         }
 
@@ -589,7 +394,7 @@ impl ModuleManager {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompiledModule {
     name: PathBuf,
     provides: Vec<ExprKind>,
@@ -634,6 +439,87 @@ impl CompiledModule {
         self.emitted = emitted;
     }
 
+    // The names this module provides, along with whether the value is wrapped in a contract
+    fn provided_names(&self) -> Result<Vec<(InternedString, bool)>> {
+        let mut names = Vec::new();
+
+        for provide_expr in &self.provides {
+            // For whatever reason, the value coming into module.provides is an expression like: (provide expr...)
+            for provide in &provide_expr.list().unwrap().args[1..] {
+                match provide {
+                    ExprKind::Atom(_) => match provide.atom_identifier() {
+                        Some(name) => names.push((*name, false)),
+                        None => {
+                            stop!(TypeMismatch => "provide expects an identifier, found: {}", provide)
+                        }
+                    },
+                    ExprKind::List(l) => {
+                        let contracted = match l.first_ident() {
+                            Some(x) if *x == *CONTRACT_OUT => true,
+                            Some(x) if *x == *REQUIRE_IDENT_SPEC => false,
+                            _ => {
+                                stop!(TypeMismatch => "provide expects either an identifier, (for-syntax <ident>), or (contract/out ...)")
+                            }
+                        };
+
+                        match l.args.get(1).and_then(|x| x.atom_identifier()) {
+                            Some(name) => names.push((*name, contracted)),
+                            None => {
+                                stop!(TypeMismatch => "provide expects an identifier to provide, found: {}", provide)
+                            }
+                        }
+                    }
+                    _ => {
+                        stop!(TypeMismatch => "provide expression needs to either be a `contract/out` form or an identifier")
+                    }
+                }
+            }
+        }
+
+        Ok(names)
+    }
+
+    // The definitions a require of this module introduces into `consumer`, as pairs of the
+    // name bound in the consumer and the expression reading the value out of this module
+    fn import_bindings(
+        &self,
+        require_object: &RequireObject,
+        consumer: &Path,
+    ) -> Result<Vec<(InternedString, ExprKind)>> {
+        let module = "__module-mangler".to_string() + self.name.to_str().unwrap();
+        let mut bindings = Vec::new();
+
+        for (name, contracted) in self.provided_names()? {
+            let local = match require_object.import_name(name) {
+                Some(local) => local,
+                None => continue,
+            };
+
+            let mut value = expr_list![
+                ExprKind::ident("hash-get"),
+                ExprKind::atom(module.clone()),
+                ExprKind::Quote(Box::new(Quote::new(
+                    ExprKind::atom(name),
+                    SyntaxObject::default(TokenType::Quote)
+                ))),
+            ];
+
+            // The contract was attached by this module, which doesn't know who is on the other
+            // side of it until now
+            if contracted {
+                value = expr_list![
+                    ExprKind::ident("%contract-consumer"),
+                    value,
+                    ExprKind::string_lit(consumer.display().to_string()),
+                ];
+            }
+
+            bindings.push((local, value));
+        }
+
+        Ok(bindings)
+    }
+
     fn to_top_level_module(
         &self,
         modules: &HashMap<PathBuf, CompiledModule>,
@@ -650,188 +536,26 @@ impl CompiledModule {
         // ;; Refresh the module definition in this namespace
         // (define a-module.rkt-b (hash-get 'b b-module.rkt-b))
 
-        let mut explicit_requires = HashMap::new();
-
-        // TODO: This is the same as the top level, they should be merged
         for require_object in &self.require_objects {
             let path = require_object.path.get_path();
 
-            explicit_requires.clear();
-
-            for ident in &require_object.idents_to_import {
-                match ident {
-                    MaybeRenamed::Normal(i) => {
-                        explicit_requires.insert(i.atom_identifier().unwrap().clone(), None);
-                    }
-                    MaybeRenamed::Renamed(from, to) => {
-                        explicit_requires.insert(
-                            from.atom_identifier().unwrap().clone(),
-                            Some(to.atom_identifier().unwrap().clone()),
-                        );
-                    }
-                }
-            }
-
             // println!("{:?}", path);
             // println!("{:?}", modules.keys().collect::<Vec<_>>());
             let module = modules.get(path.as_ref()).unwrap();
 
-            let other_module_prefix = "mangler".to_string() + module.name.to_str().unwrap();
-
-            for provide_expr in &module.provides {
-                // For whatever reason, the value coming into module.provides is an expression like: (provide expr...)
-                for provide in &provide_expr.list().unwrap().args[1..] {
-                    match provide {
-                        ExprKind::List(l) => {
-                            if let Some(qualifier) = l.first_ident() {
-                                match *qualifier {
-                                    x if x == *CONTRACT_OUT => {
-                                        // Directly expand into define/contract, but with the value just being the hash get below
-
-                                        // (bind/c contract name 'name)
-
-                                        let mut name = l.args.get(1).unwrap().clone();
-                                        let contract = l.args.get(2).unwrap();
-
-                                        if !explicit_requires.is_empty()
-                                            && !name
-                                                .atom_identifier()
-                                                .map(|x| explicit_requires.contains_key(x))
-                                                .unwrap_or_default()
-                                        {
-                                            continue;
-                                        }
-
-                                        // If we have the alias listed, we should use it
-                                        if !explicit_requires.is_empty() {
-                                            if let Some(alias) = explicit_requires
-                                                .get(name.atom_identifier().unwrap())
-                                                .copied()
-                                                .flatten()
-                                            {
-                                                *name.atom_identifier_mut().unwrap() =
-                                                    alias.clone();
-                                            }
-                                        }
-
-                                        if let Some(prefix) = &require_object.prefix {
-                                            if let Some(existing) = name.atom_identifier_mut() {
-                                                let mut prefixed_identifier = prefix.clone();
-                                                prefixed_identifier.push_str(existing.resolve());
-
-                                                // Update the existing identifier to point to a new one with the prefix applied
-                                                *existing = prefixed_identifier.into();
-                                            }
-                                        }
-
-                                        // Since this is now bound to be in the scope of the current working module, we also want
-                                        // this to be mangled. In the event we do something like, qualify the import, then we might
-                                        // have to mangle this differently
-                                        globals.insert(*name.atom_identifier().unwrap());
-
-                                        let hash_get = expr_list![
-                                            ExprKind::ident("hash-get"),
-                                            ExprKind::atom(
-                                                "__module-".to_string() + &other_module_prefix
-                                            ),
-                                            ExprKind::Quote(Box::new(Quote::new(
-                                                name.clone(),
-                                                SyntaxObject::default(TokenType::Quote)
-                                            ))),
-                                        ];
-
-                                        let define = ExprKind::Define(Box::new(Define::new(
-                                            ExprKind::atom(
-                                                prefix.clone()
-                                                    + name.atom_identifier().unwrap().resolve(),
-                                            ),
-                                            // expr_list![
-                                            // ExprKind::ident("bind/c"),
-                                            // contract.clone(),
-                                            hash_get,
-                                            // ExprKind::Quote(Box::new(Quote::new(
-                                            // name.clone(),
-                                            // SyntaxObject::default(TokenType::Quote)
-                                            // ))),
-                                            // ],
-                                            SyntaxObject::default(TokenType::Define),
-                                        )));
-
-                                        provide_definitions.push(define);
-                                    }
-                                    _ => {
-                                        stop!(TypeMismatch => "foo provide expects either an identifier, (for-syntax <ident>), or (contract/out ...)")
-                                    }
-                                }
-                            } else {
-                                stop!(TypeMismatch => "provide expects either an identifier or a (for-syntax <ident>)")
-                            }
-                        }
-                        ExprKind::Atom(_) => {
-                            if !explicit_requires.is_empty()
-                                && !provide
-                                    .atom_identifier()
-                                    .map(|x| explicit_requires.contains_key(x))
-                                    .unwrap_or_default()
-                            {
-                                continue;
-                            }
-
-                            // Mangle with a prefix if necessary
-                            let mut provide = provide.clone();
-
-                            // If we have the alias listed, we should use it
-                            if !explicit_requires.is_empty() {
-                                if let Some(alias) = explicit_requires
-                                    .get(provide.atom_identifier().unwrap())
-                                    .copied()
-                                    .flatten()
-                                {
-                                    *provide.atom_identifier_mut().unwrap() = alias.clone();
-                                }
-                            }
-
-                            if let Some(prefix) = &require_object.prefix {
-                                if let Some(existing) = provide.atom_identifier_mut() {
-                                    let mut prefixed_identifier = prefix.clone();
-                                    prefixed_identifier.push_str(existing.resolve());
-
-                                    // Update the existing identifier to point to a new one with the prefix applied
-                                    *existing = prefixed_identifier.into();
-                                }
-                            }
-
-                            let provide_ident = provide.atom_identifier().unwrap();
-
-                            // Since this is now bound to be in the scope of the current working module, we also want
-                            // this to be mangled. In the event we do something like, qualify the import, then we might
-                            // have to mangle this differently
-                            globals.insert(*provide_ident);
-
-                            let define = ExprKind::Define(Box::new(Define::new(
-                                ExprKind::atom(prefix.clone() + provide_ident.resolve()),
-                                expr_list![
-                                    ExprKind::ident("hash-get"),
-                                    ExprKind::atom("__module-".to_string() + &other_module_prefix),
-                                    ExprKind::Quote(Box::new(Quote::new(
-                                        provide.clone(),
-                                        SyntaxObject::default(TokenType::Quote)
-                                    )))
-                                ],
-                                SyntaxObject::default(TokenType::Define),
-                            )));
-
-                            provide_definitions.push(define);
-                        }
-                        _ => {
-                            stop!(TypeMismatch => "provide expression needs to either be a `contract/out` form or an identifier")
-                        }
-                    }
-                }
+            for (name, value) in module.import_bindings(require_object, &self.name)? {
+                // Since this is now bound to be in the scope of the current working module, we also want
+                // this to be mangled. In the event we do something like, qualify the import, then we might
+                // have to mangle this differently
+                globals.insert(name);
 
-                // exprs.push(define);
+                let define = ExprKind::Define(Box::new(Define::new(
+                    ExprKind::atom(prefix.clone() + name.resolve()),
+                    value,
+                    SyntaxObject::default(TokenType::Define),
+                )));
 
-                // todo!()
+                provide_definitions.push(define);
             }
         }
 
@@ -900,6 +624,9 @@ impl CompiledModule {
                                             l.get(1).unwrap().clone(),
                                             SyntaxObject::default(TokenType::Quote)
                                         ))),
+                                        // The module providing the value is blamed when it doesn't
+                                        // hold up its end of the contract
+                                        ExprKind::string_lit(self.name.display().to_string()),
                                     ],
                                     global_macro_map,
                                 )?;
@@ -1045,18 +772,115 @@ impl CompiledModule {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+// Every identifier that could be provided, going by the provide specs
+fn provided_identifiers(provides: &[ExprKind], ast: &[ExprKind]) -> HashSet<InternedString> {
+    fn walk(expr: &ExprKind, identifiers: &mut HashSet<InternedString>) {
+        match expr {
+            ExprKind::Atom(_) => identifiers.extend(expr.atom_identifier()),
+            ExprKind::List(l) => l.iter().for_each(|x| walk(x, identifiers)),
+            _ => {}
+        }
+    }
+
+    let mut identifiers = HashSet::new();
+
+    for provide in provides {
+        walk(provide, &mut identifiers);
+    }
+
+    if identifiers.contains(&*ALL_DEFINED_OUT) {
+        identifiers.extend(all_defined_out(ast));
+    }
+
+    identifiers
+}
+
+// The definitions (all-defined-out) stands for. Names starting with `__` are private by convention,
+// and mangled or macro generated names (`mangler...`, `##...`) are implementation details.
+fn all_defined_out(ast: &[ExprKind]) -> Vec<InternedString> {
+    let mut names = collect_globals(ast)
+        .into_iter()
+        .filter(|x| {
+            let name = x.resolve();
+            !name.starts_with("__") && !name.starts_with("##")
+        })
+        .collect::<Vec<_>>();
+
+    names.sort_by(|a, b| a.resolve().cmp(b.resolve()));
+
+    names
+}
+
+// The name a single provide binds in the requiring module
+fn provided_name(provide: &ExprKind) -> Option<InternedString> {
+    match provide {
+        ExprKind::List(l) => l.args.get(1).and_then(|x| x.atom_identifier()).copied(),
+        _ => provide.atom_identifier().copied(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 enum MaybeRenamed {
     Normal(ExprKind),
     Renamed(ExprKind, ExprKind),
 }
 
-#[derive(Debug, Clone)]
+// One layer of a require spec, e.g. the (prefix-in p: ...) of (prefix-in p: (only-in "x" a))
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum ImportSpec {
+    Only(Vec<MaybeRenamed>),
+    Except(Vec<ExprKind>),
+    Rename(Vec<(ExprKind, ExprKind)>),
+    Prefix(String),
+}
+
+impl ImportSpec {
+    // The name `name` is bound to after this layer, if it makes it through at all
+    fn apply(&self, name: InternedString) -> Option<InternedString> {
+        match self {
+            Self::Only(idents) => idents.iter().find_map(|ident| match ident {
+                MaybeRenamed::Normal(i) if i.atom_identifier() == Some(&name) => Some(name),
+                MaybeRenamed::Renamed(from, to) if from.atom_identifier() == Some(&name) => {
+                    to.atom_identifier().copied()
+                }
+                _ => None,
+            }),
+            Self::Except(idents) => {
+                if idents.iter().any(|x| x.atom_identifier() == Some(&name)) {
+                    None
+                } else {
+                    Some(name)
+                }
+            }
+            Self::Rename(pairs) => Some(
+                pairs
+                    .iter()
+                    .find(|(from, _)| from.atom_identifier() == Some(&name))
+                    .and_then(|(_, to)| to.atom_identifier().copied())
+                    .unwrap_or(name),
+            ),
+            Self::Prefix(prefix) => Some((prefix.clone() + name.resolve()).into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RequireObject {
     path: PathOrBuiltIn,
     for_syntax: bool,
-    idents_to_import: Vec<MaybeRenamed>,
-    prefix: Option<String>,
+    // Outermost first, in the order they were written
+    imports: Vec<ImportSpec>,
+}
+
+impl RequireObject {
+    // The name a provided identifier is bound to in the requiring module, if it is imported.
+    // The innermost spec applies first, so (prefix-in p: (rename-in "x" [a b])) binds p:b
+    fn import_name(&self, name: InternedString) -> Option<InternedString> {
+        self.imports
+            .iter()
+            .rev()
+            .try_fold(name, |name, spec| spec.apply(name))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -1065,6 +889,41 @@ enum PathOrBuiltIn {
     Path(PathBuf),
 }
 
+// Built in modules are stored by name, and resolved back to the static name on the way in
+#[derive(Serialize, Deserialize)]
+enum SerializedPathOrBuiltIn {
+    BuiltIn(String),
+    Path(PathBuf),
+}
+
+impl Serialize for PathOrBuiltIn {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            Self::BuiltIn(name) => SerializedPathOrBuiltIn::BuiltIn(name.to_string()),
+            Self::Path(path) => SerializedPathOrBuiltIn::Path(path.clone()),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PathOrBuiltIn {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        Ok(match SerializedPathOrBuiltIn::deserialize(deserializer)? {
+            SerializedPathOrBuiltIn::BuiltIn(name) => BUILT_INS
+                .iter()
+                .find(|x| x.0 == name)
+                .map(|x| Self::BuiltIn(x.0))
+                .unwrap_or_else(|| Self::Path(PathBuf::from(name))),
+            SerializedPathOrBuiltIn::Path(path) => Self::Path(path),
+        })
+    }
+}
+
 impl PathOrBuiltIn {
     pub fn get_path(&self) -> Cow<'_, PathBuf> {
         match self {
@@ -1078,9 +937,7 @@ impl PathOrBuiltIn {
 struct RequireObjectBuilder {
     path: Option<PathOrBuiltIn>,
     for_syntax: bool,
-    idents_to_import: Vec<MaybeRenamed>,
-    // Built up prefix
-    prefix: Option<String>,
+    imports: Vec<ImportSpec>,
 }
 
 impl RequireObjectBuilder {
@@ -1092,8 +949,7 @@ impl RequireObjectBuilder {
         Ok(RequireObject {
             path,
             for_syntax: self.for_syntax,
-            idents_to_import: self.idents_to_import,
-            prefix: self.prefix,
+            imports: self.imports,
         })
     }
 }
@@ -1119,6 +975,9 @@ struct ModuleBuilder<'a> {
     kernel: &'a mut Option<Kernel>,
     builtin_modules: ModuleContainer,
     global_macro_map: &'a HashMap<InternedString, SteelMacro>,
+    search_paths: &'a [PathBuf],
+    // Present when the module opted into type checking with `#lang typed/steel`
+    types: Option<TypeDeclarations>,
 }
 
 impl<'a> ModuleBuilder<'a> {
@@ -1134,6 +993,7 @@ impl<'a> ModuleBuilder<'a> {
         kernel: &'a mut Option<Kernel>,
         builtin_modules: ModuleContainer,
         global_macro_map: &'a HashMap<InternedString, SteelMacro>,
+        search_paths: &'a [PathBuf],
     ) -> Result<Self> {
         // TODO don't immediately canonicalize the path unless we _know_ its coming from a path
         // change the path to not always be required
@@ -1163,12 +1023,18 @@ impl<'a> ModuleBuilder<'a> {
             kernel,
             builtin_modules,
             global_macro_map,
+            search_paths,
+            types: None,
         })
     }
 
     fn compile(&mut self) -> Result<Vec<ExprKind>> {
         debug!(target: "requires", "Visiting: {:?}", self.name);
 
+        if strip_typed_language(&mut self.source_ast) {
+            self.types = Some(TypeDeclarations::extract(&mut self.source_ast)?);
+        }
+
         self.collect_requires()?;
         // let contains_provides = self.contains_provides();
         self.collect_provides()?;
@@ -1257,6 +1123,7 @@ impl<'a> ModuleBuilder<'a> {
                     self.kernel,
                     self.builtin_modules.clone(),
                     self.global_macro_map,
+                    self.search_paths,
                 )?;
 
                 // Walk the tree and compile any dependencies
@@ -1326,6 +1193,7 @@ impl<'a> ModuleBuilder<'a> {
                     self.kernel,
                     self.builtin_modules.clone(),
                     self.global_macro_map,
+                    self.search_paths,
                 )?;
 
                 // Walk the tree and compile any dependencies
@@ -1491,11 +1359,24 @@ impl<'a> ModuleBuilder<'a> {
             self.source_ast = ast;
             self.provides = provides;
             self.collect_provides();
+            self.expand_provide_specs()?;
 
             provides = std::mem::take(&mut self.provides);
             ast = std::mem::take(&mut self.source_ast);
         }
 
+        // Typed modules are checked once the module's own expansion is done. define/contract
+        // and friends are global macros, which would otherwise only be expanded later on.
+        if let Some(types) = &self.types {
+            ast = ast
+                .into_iter()
+                .map(|x| expand(x, self.global_macro_map))
+                .collect::<Result<Vec<_>>>()?;
+
+            let exported = provided_identifiers(&provides, &ast);
+            check_typed_module(&mut ast, types, &exported)?;
+        }
+
         // Put the mangled asts at the top
         // then include the ast there
         mangled_asts.append(&mut ast);
@@ -1559,6 +1440,137 @@ impl<'a> ModuleBuilder<'a> {
         Ok(result)
     }
 
+    // Rewrites the provide specs into the only forms a compiled module provides with: identifiers,
+    // (contract/out <name> <contract>) and (%require-ident-spec <name> <expr>)
+    fn expand_provide_specs(&mut self) -> Result<()> {
+        let provides = std::mem::take(&mut self.provides);
+        let search_paths = self.require_search_paths();
+
+        for mut provide in provides {
+            if let ExprKind::List(l) = &mut provide {
+                let mut expanded = vec![l.args[0].clone()];
+
+                for spec in &l.args[1..] {
+                    expanded.extend(self.expand_provide_spec(&search_paths, spec)?);
+                }
+
+                l.args = expanded;
+            }
+
+            self.provides.push(provide);
+        }
+
+        Ok(())
+    }
+
+    fn expand_provide_spec(
+        &self,
+        search_paths: &[PathBuf],
+        spec: &ExprKind,
+    ) -> Result<Vec<ExprKind>> {
+        let l = match spec {
+            ExprKind::List(l) => l,
+            _ => return Ok(vec![spec.clone()]),
+        };
+
+        match l.first_ident() {
+            Some(x) if *x == *ALL_DEFINED_OUT => {
+                if l.len() != 1 {
+                    stop!(ArityMismatch => "all-defined-out expects no arguments");
+                }
+
+                Ok(all_defined_out(&self.source_ast)
+                    .into_iter()
+                    .map(ExprKind::atom)
+                    .collect())
+            }
+
+            Some(x) if *x == *ALL_FROM_OUT => {
+                let mut provides = Vec::new();
+
+                for module in &l.args[1..] {
+                    let span = module
+                        .atom_syntax_object()
+                        .map(|x| x.span)
+                        .unwrap_or_default();
+
+                    let path = match self.resolve_module_spec(search_paths, span, module)? {
+                        Some(path) => path.get_path().into_owned(),
+                        None => {
+                            stop!(BadSyntax => "all-from-out expects a string literal or an identifier referring to a module, found: {}", module)
+                        }
+                    };
+
+                    let require_object = self
+                        .require_objects
+                        .iter()
+                        .find(|x| !x.for_syntax && *x.path.get_path() == path);
+
+                    let (require_object, compiled) =
+                        match require_object.zip(self.compiled_modules.get(&path)) {
+                            Some(found) => found,
+                            None => {
+                                stop!(Generic => format!("all-from-out expects a module required by this one, found: {module}"); span)
+                            }
+                        };
+
+                    // Provided under the name it was imported with
+                    for (name, _) in compiled.provided_names()? {
+                        if let Some(local) = require_object.import_name(name) {
+                            provides.push(ExprKind::atom(local));
+                        }
+                    }
+                }
+
+                Ok(provides)
+            }
+
+            Some(x) if *x == *RENAME_OUT => l.args[1..]
+                .iter()
+                .map(|pair| match pair {
+                    ExprKind::List(p)
+                        if p.len() == 2
+                            && p.args[0].atom_identifier().is_some()
+                            && p.args[1].atom_identifier().is_some() =>
+                    {
+                        Ok(expr_list![
+                            ExprKind::atom(*REQUIRE_IDENT_SPEC),
+                            p.args[1].clone(),
+                            p.args[0].clone(),
+                        ])
+                    }
+                    _ => {
+                        stop!(BadSyntax => "rename-out expects pairs of identifiers, (<from> <to>), found: {}", pair)
+                    }
+                })
+                .collect(),
+
+            Some(x) if *x == *EXCEPT_OUT => {
+                let (first, rest) = match l.args[1..].split_first() {
+                    Some(specs) => specs,
+                    None => stop!(ArityMismatch => "except-out expects a provide spec to remove names from"),
+                };
+
+                let mut excluded = HashSet::new();
+
+                for spec in rest {
+                    for provide in self.expand_provide_spec(search_paths, spec)? {
+                        excluded.extend(provided_name(&provide));
+                    }
+                }
+
+                Ok(self
+                    .expand_provide_spec(search_paths, first)?
+                    .into_iter()
+                    .filter(|x| provided_name(x).map_or(true, |x| !excluded.contains(&x)))
+                    .collect())
+            }
+
+            // contract/out and %require-ident-spec are already as expected
+            _ => Ok(vec![spec.clone()]),
+        }
+    }
+
     fn extract_macro_defs(&mut self) -> Result<()> {
         let mut non_macros = Vec::new();
         let exprs = std::mem::take(&mut self.source_ast);
@@ -1674,199 +1686,294 @@ impl<'a> ModuleBuilder<'a> {
 
     fn parse_require_object(
         &mut self,
-        home: &Option<PathBuf>,
+        search_paths: &[PathBuf],
         r: &crate::parser::ast::Require,
         atom: &ExprKind,
     ) -> Result<RequireObject> {
         let mut object = RequireObjectBuilder::default();
 
-        self.parse_require_object_inner(home, r, atom, &mut object)
+        self.parse_require_object_inner(search_paths, r, atom, &mut object)
             .and_then(|_| object.build())
     }
 
-    // TODO: Recursively crunch the requires to gather up the necessary information
-    fn parse_require_object_inner(
-        &mut self,
-        home: &Option<PathBuf>,
-        r: &crate::parser::ast::Require,
+    // Every location a require spec could refer to, in the order they're tried.
+    //
+    // String requires are relative to the requiring file, then to each search path, unless
+    // they're absolute or explicitly relative (`./`, `../`). Symbolic requires, like
+    // `mylib/util`, only ever come from the search paths. Either kind can name a collection,
+    // which is a directory with a `main.scm` in it.
+    fn require_candidates(
+        &self,
+        search_paths: &[PathBuf],
+        path: &str,
+        symbolic: bool,
+    ) -> Vec<PathBuf> {
+        let mut roots = Vec::new();
+
+        if !symbolic {
+            if Path::new(path).is_absolute() {
+                let path = PathBuf::from(path);
+                let collection = path.join(MODULE_COLLECTION_ENTRY);
+                return vec![path, collection];
+            }
+
+            let mut current = self.name.clone();
+            if current.is_file() {
+                current.pop();
+            }
+            roots.push(current);
+        }
+
+        if symbolic || !(path.starts_with("./") || path.starts_with("../")) {
+            roots.extend(search_paths.iter().cloned());
+        }
+
+        let mut candidates = Vec::new();
+
+        for root in roots {
+            if symbolic {
+                candidates.push(root.join(format!("{path}.scm")));
+            } else {
+                candidates.push(root.join(path));
+            }
+
+            candidates.push(root.join(path).join(MODULE_COLLECTION_ENTRY));
+        }
+
+        candidates
+    }
+
+    fn resolve_require_path(
+        &self,
+        search_paths: &[PathBuf],
+        span: Span,
+        path: &str,
+        symbolic: bool,
+    ) -> Result<PathBuf> {
+        let candidates = self.require_candidates(search_paths, path, symbolic);
+
+        for candidate in &candidates {
+            log::info!("Searching for {:?} at {:?}", path, candidate);
+
+            if candidate.is_file() {
+                return Ok(candidate.clone());
+            }
+        }
+
+        let tried = candidates
+            .iter()
+            .map(|x| format!("\n    {}", x.display()))
+            .collect::<String>();
+
+        stop!(Generic => format!("module not found: {} (required by {}), tried:{}", path, self.name.display(), tried); span)
+    }
+
+    // The directories to search for required modules: the ones configured on the engine,
+    // then STEEL_PATH, then the installed packages
+    fn require_search_paths(&self) -> Vec<PathBuf> {
+        let mut search_paths = self.search_paths.to_vec();
+
+        if let Some(steel_path) = std::env::var_os("STEEL_PATH") {
+            search_paths
+                .extend(std::env::split_paths(&steel_path).filter(|x| !x.as_os_str().is_empty()));
+        }
+
+        search_paths.extend(package_search_paths(&self.name));
+        search_paths
+    }
+
+    // Resolves the module an atom in a require spec refers to, either a string literal path or
+    // a symbolic module name
+    fn resolve_module_spec(
+        &self,
+        search_paths: &[PathBuf],
+        span: Span,
         atom: &ExprKind,
-        require_object: &mut RequireObjectBuilder,
-    ) -> Result<()> {
-        match atom {
+    ) -> Result<Option<PathOrBuiltIn>> {
+        let (path, symbolic) = match atom {
             ExprKind::Atom(Atom {
                 syn:
                     SyntaxObject {
                         ty: TokenType::StringLiteral(s),
                         ..
                     },
-            }) => {
-                if require_object.path.is_some() {
-                    stop!(Generic => "require object only expects one path!")
-                }
+            }) => (s.as_str(), false),
+            // Symbolic requires, e.g. (require mylib/util)
+            ExprKind::Atom(Atom {
+                syn:
+                    SyntaxObject {
+                        ty: TokenType::Identifier(s),
+                        ..
+                    },
+            }) => (s.resolve(), true),
+            _ => return Ok(None),
+        };
 
-                // Try this?
-                if let Some(lib) = BUILT_INS.iter().find(|x| x.0 == s.as_str()) {
-                    // self.built_ins.push(PathBuf::from(lib.0));
+        if let Some(lib) = BUILT_INS.iter().find(|x| x.0 == path) {
+            return Ok(Some(PathOrBuiltIn::BuiltIn(lib.0)));
+        }
 
-                    require_object.path = Some(PathOrBuiltIn::BuiltIn(lib.0));
+        self.resolve_require_path(search_paths, span, path, symbolic)
+            .map(|x| Some(PathOrBuiltIn::Path(x)))
+    }
 
-                    return Ok(());
-                    // continue;
-                }
+    // TODO: Recursively crunch the requires to gather up the necessary information
+    fn parse_require_object_inner(
+        &mut self,
+        search_paths: &[PathBuf],
+        r: &crate::parser::ast::Require,
+        atom: &ExprKind,
+        require_object: &mut RequireObjectBuilder,
+    ) -> Result<()> {
+        if let Some(path) = self.resolve_module_spec(search_paths, r.location.span, atom)? {
+            if require_object.path.is_some() {
+                stop!(Generic => "require object only expects one path!")
+            }
+
+            require_object.path = Some(path);
+
+            return Ok(());
+        }
 
-                let mut current = self.name.clone();
-                if current.is_file() {
-                    current.pop();
+        let l = match atom {
+            ExprKind::List(l) => l,
+            _ => {
+                stop!(Generic => "require expected a string literal or an identifier referring to a file/module"; r.location.span; r.location.source.clone())
+            }
+        };
+
+        match l.first_ident() {
+            Some(x) if *x == *ONLY_IN => {
+                if l.args.len() < 2 {
+                    stop!(BadSyntax => "only-in expects a require-spec and optionally a list of ids to bind (maybe renamed)"; r.location.span; r.location.source.clone());
                 }
-                current.push(s);
 
-                // // If the path exists on its own, we can continue
-                // // But theres the case where we're searching for a module on the STEEL_PATH
-                if !current.exists() {
-                    if let Some(mut home) = home.clone() {
-                        home.push(s);
-                        current = home;
+                let mut idents = Vec::new();
 
-                        log::info!("Searching STEEL_HOME for {:?}", current);
-                    } else {
-                        stop!(Generic => format!("Module not found: {:?}", self.name))
+                for remaining in &l.args[2..] {
+                    match remaining {
+                        ExprKind::Atom(_) => {
+                            idents.push(MaybeRenamed::Normal(remaining.clone()));
+                        }
+                        ExprKind::List(_) => {
+                            let (from, to) = Self::parse_rename_pair(r, "only-in", remaining)?;
+
+                            // (<from> <to>)
+                            idents.push(MaybeRenamed::Renamed(from, to));
+                        }
+                        _ => {
+                            stop!(BadSyntax => "unexpected syntax in only-in form during module requires"; r.location.span; r.location.source.clone())
+                        }
                     }
                 }
 
-                // Get the absolute path and store that
-                // self.requires.push(current)
+                require_object.imports.push(ImportSpec::Only(idents));
 
-                require_object.path = Some(PathOrBuiltIn::Path(current));
+                self.parse_require_object_inner(search_paths, r, &l.args[1], require_object)?;
             }
 
-            // TODO: Requires with qualifiers, that aren't just for-syntax
-            // Perhaps like:
-            // (with-prefix <xyz>)
-            ExprKind::List(l) => {
-                match l.first_ident() {
-                    Some(x) if *x == *ONLY_IN => {
-                        if l.args.len() < 2 {
-                            stop!(BadSyntax => "only-in expects a require-spec and optionally a list of ids to bind (maybe renamed)");
-                        }
+            Some(x) if *x == *RENAME_IN => {
+                if l.args.len() < 2 {
+                    stop!(BadSyntax => "rename-in expects a require-spec and a list of (<from> <to>) pairs"; r.location.span; r.location.source.clone());
+                }
 
-                        self.parse_require_object_inner(home, r, &l.args[1], require_object)?;
+                let pairs = l.args[2..]
+                    .iter()
+                    .map(|x| Self::parse_rename_pair(r, "rename-in", x))
+                    .collect::<Result<Vec<_>>>()?;
 
-                        for remaining in &l.args[2..] {
-                            match remaining {
-                                ExprKind::Atom(_) => {
-                                    require_object
-                                        .idents_to_import
-                                        .push(MaybeRenamed::Normal(remaining.clone()));
-                                }
-                                ExprKind::List(l) => {
-                                    if l.len() != 2 {
-                                        stop!(BadSyntax => "Expected a pair when renaming required identifiers");
-                                    }
-
-                                    let from = &l.args[0];
-                                    let to = &l.args[1];
-
-                                    if from.atom_identifier().is_none()
-                                        || to.atom_identifier().is_none()
-                                    {
-                                        stop!(BadSyntax => "only-in expected identifiers to rename");
-                                    }
-
-                                    // (<from> <to>)
-                                    require_object
-                                        .idents_to_import
-                                        .push(MaybeRenamed::Renamed(from.clone(), to.clone()));
-                                }
-                                _ => {
-                                    stop!(BadSyntax => "unexpected syntax in only-in form during module requires")
-                                }
-                            }
-                        }
-                    }
+                require_object.imports.push(ImportSpec::Rename(pairs));
 
-                    Some(x) if *x == *PREFIX_IN => {
-                        if l.args.len() != 3 {
-                            stop!(BadSyntax => "prefix-in expects a prefix to prefix a given file or module"; r.location.span; r.location.source.clone());
-                        }
+                self.parse_require_object_inner(search_paths, r, &l.args[1], require_object)?;
+            }
 
-                        if let Some(prefix) = l.args[1].atom_identifier() {
-                            match &mut require_object.prefix {
-                                Some(existing_prefix) => {
-                                    // Append the new symbol to the existing prefix
-                                    existing_prefix.push_str(prefix.resolve());
-                                }
-                                None => {
-                                    require_object.prefix = Some(prefix.resolve().to_string());
-                                }
-                            }
+            Some(x) if *x == *EXCEPT_IN => {
+                if l.args.len() < 2 {
+                    stop!(BadSyntax => "except-in expects a require-spec and a list of ids to leave out"; r.location.span; r.location.source.clone());
+                }
 
-                            self.parse_require_object_inner(home, r, &l.args[2], require_object)?;
-                        } else {
-                            stop!(TypeMismatch => "prefix-in expects an identifier to use for the prefix");
-                        }
-                    }
+                if l.args[2..].iter().any(|x| x.atom_identifier().is_none()) {
+                    stop!(BadSyntax => "except-in expects identifiers to leave out"; r.location.span; r.location.source.clone());
+                }
 
-                    Some(x) if *x == *FOR_SYNTAX => {
-                        // We're expecting something like (for-syntax "foo")
-                        if l.args.len() != 2 {
-                            stop!(BadSyntax => "for-syntax expects one string literal referring to a file or module"; r.location.span; r.location.source.clone());
-                        }
+                require_object
+                    .imports
+                    .push(ImportSpec::Except(l.args[2..].to_vec()));
 
-                        if let Some(path) = l.args[1].string_literal() {
-                            let mut current = self.name.clone();
-                            if current.is_file() {
-                                current.pop();
-                            }
-                            current.push(path);
+                self.parse_require_object_inner(search_paths, r, &l.args[1], require_object)?;
+            }
 
-                            if !current.exists() {
-                                if let Some(mut home) = home.clone() {
-                                    home.push(path);
-                                    current = home;
+            Some(x) if *x == *PREFIX_IN => {
+                if l.args.len() != 3 {
+                    stop!(BadSyntax => "prefix-in expects a prefix to prefix a given file or module"; r.location.span; r.location.source.clone());
+                }
 
-                                    log::info!("Searching STEEL_HOME for {:?}", current);
-                                } else {
-                                    stop!(Generic => format!("Module not found: {:?}", self.name))
-                                }
-                            }
+                if let Some(prefix) = l.args[1].atom_identifier() {
+                    require_object
+                        .imports
+                        .push(ImportSpec::Prefix(prefix.resolve().to_string()));
 
-                            require_object.for_syntax = true;
-                            require_object.path = Some(PathOrBuiltIn::Path(current));
-                        } else {
-                            stop!(BadSyntax => "for-syntax expects a string literal referring to a file or module"; r.location.span; r.location.source.clone());
-                        }
+                    self.parse_require_object_inner(search_paths, r, &l.args[2], require_object)?;
+                } else {
+                    stop!(TypeMismatch => "prefix-in expects an identifier to use for the prefix"; r.location.span; r.location.source.clone());
+                }
+            }
+
+            Some(x) if *x == *FOR_SYNTAX => {
+                // We're expecting something like (for-syntax "foo")
+                if l.args.len() != 2 {
+                    stop!(BadSyntax => "for-syntax expects one string literal referring to a file or module"; r.location.span; r.location.source.clone());
+                }
+
+                match self.resolve_module_spec(search_paths, r.location.span, &l.args[1])? {
+                    Some(PathOrBuiltIn::Path(current)) => {
+                        require_object.for_syntax = true;
+                        require_object.path = Some(PathOrBuiltIn::Path(current));
                     }
                     _ => {
-                        stop!(BadSyntax => "require accepts either a string literal or a for-syntax expression"; r.location.span; r.location.source.clone())
+                        stop!(BadSyntax => "for-syntax expects a string literal or an identifier referring to a file or module"; r.location.span; r.location.source.clone());
                     }
                 }
             }
-
             _ => {
-                stop!(Generic => "require expected a string literal referring to a file/module"; r.location.span; r.location.source.clone())
+                stop!(BadSyntax => "require accepts either a string literal, an identifier, or one of: only-in, rename-in, except-in, prefix-in, for-syntax"; r.location.span; r.location.source.clone())
             }
         }
 
         Ok(())
     }
 
+    // (<from> <to>), as used by only-in and rename-in
+    fn parse_rename_pair(
+        r: &crate::parser::ast::Require,
+        form: &str,
+        pair: &ExprKind,
+    ) -> Result<(ExprKind, ExprKind)> {
+        match pair {
+            ExprKind::List(l)
+                if l.len() == 2
+                    && l.args[0].atom_identifier().is_some()
+                    && l.args[1].atom_identifier().is_some() =>
+            {
+                Ok((l.args[0].clone(), l.args[1].clone()))
+            }
+            _ => {
+                stop!(BadSyntax => format!("{form} expected a pair of identifiers when renaming required identifiers"); r.location.span; r.location.source.clone())
+            }
+        }
+    }
+
     fn collect_requires(&mut self) -> Result<()> {
         // unimplemented!()
 
         let mut exprs_without_requires = Vec::new();
         let exprs = std::mem::take(&mut self.source_ast);
 
-        let home = std::env::var("STEEL_HOME")
-            .map(PathBuf::from)
-            .map(|mut x| {
-                x.push("cogs");
-                x
-            })
-            .ok();
+        let search_paths = self.require_search_paths();
 
         fn walk(
             module_builder: &mut ModuleBuilder,
-            home: &Option<PathBuf>,
+            search_paths: &[PathBuf],
             exprs_without_requires: &mut Vec<ExprKind>,
             exprs: Vec<ExprKind>,
         ) -> Result<()> {
@@ -1878,14 +1985,17 @@ impl<'a> ModuleBuilder<'a> {
                         for atom in &r.modules {
                             // TODO: Consider making this not a reference for r
                             let require_object =
-                                module_builder.parse_require_object(&home, &r, atom)?;
+                                module_builder.parse_require_object(search_paths, &r, atom)?;
 
                             module_builder.require_objects.push(require_object);
                         }
                     }
-                    ExprKind::Begin(b) => {
-                        walk(module_builder, home, exprs_without_requires, b.exprs)?
-                    }
+                    ExprKind::Begin(b) => walk(
+                        module_builder,
+                        search_paths,
+                        exprs_without_requires,
+                        b.exprs,
+                    )?,
                     _ => exprs_without_requires.push(expr),
                 }
             }
@@ -1893,7 +2003,7 @@ impl<'a> ModuleBuilder<'a> {
             Ok(())
         }
 
-        walk(self, &home, &mut exprs_without_requires, exprs)?;
+        walk(self, &search_paths, &mut exprs_without_requires, exprs)?;
 
         // for expr in exprs {
         //     match &expr {
@@ -1927,6 +2037,7 @@ impl<'a> ModuleBuilder<'a> {
         kernel: &'a mut Option<Kernel>,
         builtin_modules: ModuleContainer,
         global_macro_map: &'a HashMap<InternedString, SteelMacro>,
+        search_paths: &'a [PathBuf],
     ) -> Result<Self> {
         ModuleBuilder::raw(
             name,
@@ -1937,6 +2048,7 @@ impl<'a> ModuleBuilder<'a> {
             kernel,
             builtin_modules,
             global_macro_map,
+            search_paths,
         )
         .parse_builtin(input)
     }
@@ -1951,6 +2063,7 @@ impl<'a> ModuleBuilder<'a> {
         builtin_modules: ModuleContainer,
 
         global_macro_map: &'a HashMap<InternedString, SteelMacro>,
+        search_paths: &'a [PathBuf],
     ) -> Result<Self> {
         ModuleBuilder::raw(
             name,
@@ -1961,6 +2074,7 @@ impl<'a> ModuleBuilder<'a> {
             kernel,
             builtin_modules,
             global_macro_map,
+            search_paths,
         )
         .parse_from_path()
     }
@@ -1975,6 +2089,7 @@ impl<'a> ModuleBuilder<'a> {
         builtin_modules: ModuleContainer,
 
         global_macro_map: &'a HashMap<InternedString, SteelMacro>,
+        search_paths: &'a [PathBuf],
     ) -> Self {
         ModuleBuilder {
             name,
@@ -1991,6 +2106,8 @@ impl<'a> ModuleBuilder<'a> {
             kernel,
             builtin_modules,
             global_macro_map,
+            search_paths,
+            types: None,
         }
     }
 
@@ -2037,3 +2154,286 @@ impl<'a> ModuleBuilder<'a> {
 
     // fn search_index
 }
+
+#[cfg(test)]
+mod module_resolution_tests {
+    use super::*;
+
+    use crate::steel_vm::engine::Engine;
+    use crate::tests::{test_directory, write_file as write};
+
+    fn directory(name: &str) -> PathBuf {
+        test_directory("modules", name)
+    }
+
+    fn run(engine: &mut Engine, main: PathBuf, source: &str) -> Result<()> {
+        let main = write(main, source);
+        engine
+            .compile_and_run_raw_program_with_path(source, main)
+            .map(|_| ())
+    }
+
+    #[test]
+    fn symbolic_requires_use_the_search_paths() {
+        let root = directory("symbolic");
+        write(
+            root.join("libs/mylib/util.scm"),
+            "(provide double) (define (double x) (* 2 x))",
+        );
+        write(
+            root.join("other/collection/main.scm"),
+            "(provide triple) (define (triple x) (* 3 x))",
+        );
+
+        let mut engine = Engine::new();
+        engine
+            .add_search_directory(root.join("libs"))
+            .add_search_directory(root.join("other"));
+
+        run(
+            &mut engine,
+            root.join("app/main.scm"),
+            "(require mylib/util collection) (define result (+ (double 1) (triple 1)))",
+        )
+        .unwrap();
+
+        assert_eq!(engine.extract::<isize>("result").unwrap(), 5);
+    }
+
+    #[test]
+    fn string_requires_prefer_the_requiring_file() {
+        let root = directory("relative");
+        write(
+            root.join("app/lib.scm"),
+            "(provide origin) (define origin \"local\")",
+        );
+        write(
+            root.join("libs/lib.scm"),
+            "(provide origin) (define origin \"search path\")",
+        );
+        write(
+            root.join("libs/only-here.scm"),
+            "(provide found) (define found #t)",
+        );
+
+        let mut engine = Engine::new();
+        engine.add_search_directory(root.join("libs"));
+
+        run(
+            &mut engine,
+            root.join("app/main.scm"),
+            "(require \"lib.scm\" \"only-here.scm\") (define result origin)",
+        )
+        .unwrap();
+
+        assert_eq!(engine.extract::<String>("result").unwrap(), "local");
+        assert!(engine.extract::<bool>("found").unwrap());
+    }
+
+    #[test]
+    fn explicitly_relative_requires_skip_the_search_paths() {
+        let root = directory("explicit");
+        write(
+            root.join("libs/lib.scm"),
+            "(provide origin) (define origin 10)",
+        );
+
+        let mut engine = Engine::new();
+        engine.add_search_directory(root.join("libs"));
+
+        assert!(run(
+            &mut engine,
+            root.join("app/main.scm"),
+            "(require \"./lib.scm\")"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn missing_modules_list_every_location_tried() {
+        let root = directory("missing");
+
+        let mut engine = Engine::new();
+        engine.add_search_directory(root.join("libs"));
+
+        let source = "(define x 10)\n(require mylib/missing)";
+        let error = run(&mut engine, root.join("app/main.scm"), source).unwrap_err();
+        let message = error.to_string();
+
+        assert!(message.contains(&root.join("libs/mylib/missing.scm").display().to_string()));
+        assert!(message.contains(
+            &root
+                .join("libs/mylib/missing/main.scm")
+                .display()
+                .to_string()
+        ));
+
+        let span = error.span().unwrap();
+        assert_eq!(&source[span.start()..span.end()], "require");
+    }
+
+    #[test]
+    fn require_specs_nest() {
+        let root = directory("require-specs");
+        write(
+            root.join("lib.scm"),
+            "(provide a b c) (define a 1) (define b 2) (define c 3)",
+        );
+
+        let mut engine = Engine::new();
+        run(
+            &mut engine,
+            root.join("main.scm"),
+            "(require (prefix-in p: (rename-in (except-in \"lib.scm\" c) [a first])))
+             (define result (list p:first p:b))",
+        )
+        .unwrap();
+
+        assert_eq!(engine.extract::<Vec<isize>>("result").unwrap(), vec![1, 2]);
+
+        for unbound in ["p:a", "p:c", "c"] {
+            let mut engine = Engine::new();
+            let source = format!("(require (prefix-in p: (rename-in (except-in \"lib.scm\" c) [a first]))) {unbound}");
+
+            assert!(run(&mut engine, root.join("main.scm"), &source).is_err());
+        }
+    }
+
+    #[test]
+    fn provide_specs() {
+        let root = directory("provide-specs");
+        write(
+            root.join("lib.scm"),
+            "(provide (except-out (all-defined-out) helper) (rename-out [internal external]))
+             (define (helper) 1)
+             (define (a) (+ (helper) 1))
+             (define b 2)
+             (define internal 3)",
+        );
+        write(
+            root.join("reexport.scm"),
+            "(require (only-in \"lib.scm\" a [external renamed]))
+             (provide (all-from-out \"lib.scm\") own)
+             (define own 4)",
+        );
+
+        let mut engine = Engine::new();
+        run(
+            &mut engine,
+            root.join("main.scm"),
+            "(require \"reexport.scm\") (define result (list (a) renamed own))",
+        )
+        .unwrap();
+
+        assert_eq!(
+            engine.extract::<Vec<isize>>("result").unwrap(),
+            vec![2, 3, 4]
+        );
+
+        for unbound in ["helper", "b", "internal", "external"] {
+            let mut engine = Engine::new();
+            let source = format!("(require \"reexport.scm\") {unbound}");
+
+            assert!(run(&mut engine, root.join("main.scm"), &source).is_err());
+        }
+    }
+
+    #[test]
+    fn all_defined_out_skips_private_names() {
+        let root = directory("all-defined-out");
+        write(
+            root.join("lib.scm"),
+            "(provide (all-defined-out))
+             (define __cache 1)
+             (define (public) __cache)",
+        );
+
+        let mut engine = Engine::new();
+        run(
+            &mut engine,
+            root.join("main.scm"),
+            "(require \"lib.scm\") (define result (public))",
+        )
+        .unwrap();
+
+        assert_eq!(engine.extract::<isize>("result").unwrap(), 1);
+
+        let mut engine = Engine::new();
+        assert!(run(
+            &mut engine,
+            root.join("main.scm"),
+            "(require \"lib.scm\") __cache"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn contract_violations_blame_the_module_that_broke_the_contract() {
+        let root = directory("contract-blame");
+        let lib = write(
+            root.join("lib.scm"),
+            "(provide (contract/out next-odd (->/c even? odd?)) (contract/out broken (->/c even? odd?)))
+             (define (next-odd x) (+ x 1))
+             (define (broken x) x)",
+        );
+        let main = root.join("main.scm");
+
+        let mut engine = Engine::new();
+        let error = run(
+            &mut engine,
+            main.clone(),
+            "(require \"lib.scm\") (next-odd 1)",
+        )
+        .unwrap_err()
+        .to_string();
+
+        assert!(error.contains(&format!(
+            "blaming: {}, which required it from {}",
+            main.display(),
+            lib.display()
+        )));
+
+        let mut engine = Engine::new();
+        let error = run(&mut engine, main, "(require \"lib.scm\") (broken 2)")
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.contains(&format!(
+            "blaming: {} - broke its own contract",
+            lib.display()
+        )));
+    }
+
+    #[test]
+    fn typed_modules_keep_the_contracts_they_provide() {
+        let root = directory("typed");
+        write(
+            root.join("lib.scm"),
+            "#lang typed/steel
+             (provide add-one double-all)
+             (define/contract (add-one x) (->/c int? int?) (+ x 1))
+             (define/contract (double x) (->/c int? int?) (* x 2))
+             (: double-all (-> (Listof Int) (Listof Int)))
+             (define (double-all xs) (if (null? xs) xs (cons (double (car xs)) (double-all (cdr xs)))))",
+        );
+
+        let mut engine = Engine::new();
+        run(
+            &mut engine,
+            root.join("main.scm"),
+            "(require \"lib.scm\") (define result (car (double-all (list (add-one 1)))))",
+        )
+        .unwrap();
+
+        assert_eq!(engine.extract::<isize>("result").unwrap(), 4);
+
+        let error = run(
+            &mut engine,
+            root.join("main.scm"),
+            "(require \"lib.scm\") (add-one \"one\")",
+        )
+        .unwrap_err();
+
+        assert_eq!(error.kind(), crate::rerrs::ErrorKind::ContractViolation);
+    }
+}
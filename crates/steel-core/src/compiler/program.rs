@@ -9,7 +9,7 @@ use crate::{
     parser::{
         ast::ExprKind,
         interner::InternedString,
-        parser::{RawSyntaxObject, SyntaxObject},
+        parser::{RawSyntaxObject, Sources, SyntaxObject},
         tokens::TokenType,
     },
     rvals::IntoSteelVal,
@@ -25,7 +25,14 @@ use std::time::Instant;
 #[cfg(feature = "profiling")]
 use log::{debug, log_enabled};
 
-use super::{compiler::DebruijnIndicesInterner, map::SymbolMap};
+use crate::steel_vm::vm::superinstructions::SUPER_INSTRUCTIONS;
+use steel_gen::Pattern;
+
+use super::{
+    compiler::DebruijnIndicesInterner,
+    disassembler::{Disassembler, Disassembly},
+    map::SymbolMap,
+};
 
 /// evaluates an atom expression in given environment
 fn eval_atom(t: &SyntaxObject) -> Result<SteelVal> {
@@ -422,6 +429,12 @@ define_symbols! {
     FOR_SYNTAX => "for-syntax",
     PREFIX_IN => "prefix-in",
     ONLY_IN => "only-in",
+    RENAME_IN => "rename-in",
+    EXCEPT_IN => "except-in",
+    ALL_DEFINED_OUT => "all-defined-out",
+    ALL_FROM_OUT => "all-from-out",
+    RENAME_OUT => "rename-out",
+    EXCEPT_OUT => "except-out",
     DATUM_SYNTAX => "datum->syntax",
     SYNTAX_SPAN => "#%syntax-span",
     IF => "if",
@@ -550,6 +563,74 @@ pub fn merge_conditions_with_if(instructions: &mut [Instruction]) {
     }
 }
 
+// Whether the instructions start with the sequence, returning how many instructions it covers
+fn match_super_instruction(instructions: &[Instruction], pattern: &[Pattern]) -> Option<usize> {
+    let mut index = 0;
+
+    for expected in pattern {
+        let instruction = instructions.get(index)?;
+
+        let found = Pattern::from_opcodes(&[(instruction.op_code, instruction.payload_size)]);
+        if found != [*expected] {
+            return None;
+        }
+
+        index += 1;
+
+        // Global calls are followed by the index of the function, and the inlined primitives
+        // leave a PASS behind - the handlers step over both, just like the vm does
+        if matches!(
+            instruction.op_code,
+            OpCode::CALLGLOBAL
+                | OpCode::ADD
+                | OpCode::SUB
+                | OpCode::MUL
+                | OpCode::DIV
+                | OpCode::EQUAL
+                | OpCode::LTE
+        ) {
+            if !instructions.get(index)?.op_code.is_ephemeral_opcode() {
+                return None;
+            }
+
+            index += 1;
+        }
+    }
+
+    Some(index)
+}
+
+/// Replaces the start of each sequence of op codes that a super instruction was generated for
+/// at build time with that super instruction. The rest of the sequence stays where it is, since
+/// the handler still reads payloads from it, and anything jumping into the middle of the
+/// sequence still lands on the original instructions.
+pub fn fuse_super_instructions(instructions: &mut [Instruction]) {
+    if SUPER_INSTRUCTIONS.is_empty() {
+        return;
+    }
+
+    let mut i = 0;
+
+    while i < instructions.len() {
+        let found = SUPER_INSTRUCTIONS
+            .iter()
+            .enumerate()
+            .find_map(|(id, super_instruction)| {
+                match_super_instruction(&instructions[i..], super_instruction.pattern)
+                    .map(|length| (id, length))
+            });
+
+        match found {
+            Some((id, length)) => {
+                instructions[i].op_code = OpCode::SUPERINSTRUCTION;
+                instructions[i].payload_size = id;
+                i += length;
+            }
+            None => i += 1,
+        }
+    }
+}
+
 pub struct ProgramBuilder(Vec<Vec<DenseInstruction>>);
 impl Default for ProgramBuilder {
     fn default() -> Self {
@@ -966,28 +1047,11 @@ impl RawProgramWithSymbols {
         #[cfg(feature = "profiling")]
         let now = Instant::now();
 
-        let mut interner = DebruijnIndicesInterner::default();
-
-        for expression in &mut self.instructions {
-            interner.collect_first_pass_defines(expression, symbol_map)?
-        }
-
-        for expression in &mut self.instructions {
-            interner.collect_second_pass_defines(expression, symbol_map)?
-        }
+        self.resolve_symbols(symbol_map)?;
 
-        // if std::env::var("CODE_GEN_V2").is_err() {
-        // TODO try here - the loop condition local const arity two seems to rely on the
-        // existence of having been already adjusted by the interner
         for instructions in &mut self.instructions {
-            // TODO: Re-enable optimizations
-            // loop_condition_local_const_arity_two(instructions);
-            specialize_constants(instructions)?;
-            // gimmick_super_instruction(instructions);
-            // move_read_local_call_global(instructions);
-            specialize_read_local(instructions);
+            fuse_super_instructions(instructions);
         }
-        // }
 
         let (spans, instructions) = extract_spans(self.instructions);
 
@@ -1013,6 +1077,52 @@ impl RawProgramWithSymbols {
             spans,
         })
     }
+
+    /// Produces the listing of every function in the program, after resolving symbols the same
+    /// way [`build`](RawProgramWithSymbols::build) does.
+    pub fn disassemble(
+        mut self,
+        symbol_map: &mut SymbolMap,
+        sources: &Sources,
+    ) -> Result<Disassembly> {
+        self.resolve_symbols(symbol_map)?;
+
+        // List what will actually run
+        for instructions in &mut self.instructions {
+            fuse_super_instructions(instructions);
+        }
+
+        Ok(Disassembler::new(symbol_map, &self.constant_map, sources)
+            .disassemble(&self.instructions))
+    }
+
+    // Interns the globals into the symbol map, and runs the passes that depend on them
+    fn resolve_symbols(&mut self, symbol_map: &mut SymbolMap) -> Result<()> {
+        let mut interner = DebruijnIndicesInterner::default();
+
+        for expression in &mut self.instructions {
+            interner.collect_first_pass_defines(expression, symbol_map)?
+        }
+
+        for expression in &mut self.instructions {
+            interner.collect_second_pass_defines(expression, symbol_map)?
+        }
+
+        // if std::env::var("CODE_GEN_V2").is_err() {
+        // TODO try here - the loop condition local const arity two seems to rely on the
+        // existence of having been already adjusted by the interner
+        for instructions in &mut self.instructions {
+            // TODO: Re-enable optimizations
+            // loop_condition_local_const_arity_two(instructions);
+            specialize_constants(instructions)?;
+            // gimmick_super_instruction(instructions);
+            // move_read_local_call_global(instructions);
+            specialize_read_local(instructions);
+        }
+        // }
+
+        Ok(())
+    }
 }
 
 // TODO -> replace spans on instructions with index into span vector
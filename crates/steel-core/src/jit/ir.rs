@@ -1,167 +1,145 @@
-use std::collections::HashMap;
+// The typed intermediate representation that hot bytecode is lowered into before it
+// is handed to cranelift. Every value has a single, statically known type - anything that
+// the interpreter could turn into something else (overflowing into a bignum, a division that
+// lands on a whole number) is expressed as a guard which bails out of the compiled code.
 
-/// The AST node for expressions.
-#[derive(Clone, Debug)]
-pub enum Expr {
-    Literal(String),
-    Identifier(String),
-    Assign(String, Box<Expr>),
-    Eq(Box<Expr>, Box<Expr>),
-    Ne(Box<Expr>, Box<Expr>),
-    Lt(Box<Expr>, Box<Expr>),
-    Le(Box<Expr>, Box<Expr>),
-    Gt(Box<Expr>, Box<Expr>),
-    Ge(Box<Expr>, Box<Expr>),
-    Add(Box<Expr>, Box<Expr>),
-    Sub(Box<Expr>, Box<Expr>),
-    Mul(Box<Expr>, Box<Expr>),
-    Div(Box<Expr>, Box<Expr>),
-    IfElse(Box<Expr>, Vec<Expr>, Vec<Expr>),
-    WhileLoop(Box<Expr>, Vec<Expr>),
-    Call(String, Vec<Expr>),
-    GlobalDataAddr(String),
-    Block(Vec<Expr>),
-}
+use std::fmt;
 
-/// The type representing JIT level operations
-/// Given an input type
-#[derive(Clone, Debug, Copy, PartialEq)]
-pub enum JitType {
-    Float,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Type {
     Int,
-    List,
+    Float,
     Bool,
-    Void,
-    Any,
-}
-
-#[derive(Clone, Debug)]
-pub struct JitFunctionSignature {
-    arguments: Vec<JitType>,
-    return_value: JitType,
 }
 
-impl JitFunctionSignature {
-    pub fn new(arguments: Vec<JitType>, return_value: JitType) -> Self {
-        Self {
-            arguments,
-            return_value,
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
         }
     }
 }
 
-pub enum TypedExpr {
-    Literal(String, JitType),
-    Identifier(String),
-    Equal(Box<TypedExpr>, Box<TypedExpr>),
-    Add(Box<TypedExpr>, Box<TypedExpr>),
-    Sub(Box<TypedExpr>, Box<TypedExpr>),
-    Mul(Box<TypedExpr>, Box<TypedExpr>),
-    LtE(Box<TypedExpr>, Box<TypedExpr>),
-    IfElse(Box<TypedExpr>, Vec<TypedExpr>, Vec<TypedExpr>),
-    Call(String, Vec<TypedExpr>),
-}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct Var(pub(crate) usize);
 
-pub fn typecheck(
-    expr: &TypedExpr,
-    bindings: &mut HashMap<String, JitType>,
-    functions: &HashMap<String, JitFunctionSignature>,
-) -> JitType {
-    match expr {
-        TypedExpr::Literal(_, ty) => *ty,
-        TypedExpr::Identifier(name) => bindings
-            .get(name)
-            .copied()
-            .expect(format!("Free Identifier: {}", name).as_str()),
-        TypedExpr::Equal(_, _) | TypedExpr::LtE(_, _) => JitType::Bool,
-        TypedExpr::Add(left, right) | TypedExpr::Sub(left, right) | TypedExpr::Mul(left, right) => {
-            let left_type = typecheck(left, bindings, functions);
-            let right_type = typecheck(right, bindings, functions);
-            if let (JitType::Int, JitType::Int) = (left_type, right_type) {
-                JitType::Int
-            } else {
-                JitType::Any
-            }
-        }
-        TypedExpr::IfElse(_, then_expr, else_expr) => {
-            let then_type = then_expr
-                .iter()
-                .map(|then_expr| typecheck(then_expr, bindings, functions))
-                .last()
-                .expect("Empty then expression");
-            let else_type = else_expr
-                .iter()
-                .map(|else_expr| typecheck(else_expr, bindings, functions))
-                .last()
-                .expect("Empty else expression");
-            if then_type == else_type {
-                then_type
-            } else {
-                JitType::Any
-            }
-        }
-        TypedExpr::Call(func, args) => {
-            let function_signature = functions
-                .get(func)
-                .expect(format!("Free Identifier: {}", func).as_str());
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct BlockId(pub(crate) usize);
 
-            // Type check arguments
-            for (input_arg, func_arg_type) in args.iter().zip(&function_signature.arguments) {
-                let input_arg_type = typecheck(input_arg, bindings, functions);
-                if input_arg_type != *func_arg_type {
-                    return JitType::Any;
-                }
-            }
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Constant {
+    Int(isize),
+    Float(f64),
+    Bool(bool),
+}
 
-            // If we made it to this point, we successfully type checked the function call and can
-            // Assume that the inputs are correct
-            function_signature.return_value
+impl Constant {
+    pub(crate) fn ty(&self) -> Type {
+        match self {
+            Constant::Int(_) => Type::Int,
+            Constant::Float(_) => Type::Float,
+            Constant::Bool(_) => Type::Bool,
         }
     }
 }
 
-#[cfg(test)]
-mod type_checking {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CompareOp {
+    Eq,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+}
 
-    use super::*;
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Inst {
+    Const {
+        dst: Var,
+        value: Constant,
+    },
+    /// Integer operations deoptimize on overflow, since the interpreter would
+    /// promote the result. Division is only ever done on floats.
+    Binary {
+        dst: Var,
+        op: BinaryOp,
+        lhs: Var,
+        rhs: Var,
+    },
+    IntToFloat {
+        dst: Var,
+        src: Var,
+    },
+    /// Compares two values of the same type, producing a bool
+    Compare {
+        dst: Var,
+        op: CompareOp,
+        lhs: Var,
+        rhs: Var,
+    },
+    And {
+        dst: Var,
+        lhs: Var,
+        rhs: Var,
+    },
+    /// Deoptimizes if the float has no fractional part - the interpreter turns
+    /// those results of `/` back into integers.
+    GuardFractional {
+        src: Var,
+    },
+    /// A direct call to the function being compiled. Deoptimizes if the callee does.
+    CallSelf {
+        dst: Var,
+        args: Vec<Var>,
+    },
+}
 
-    #[test]
-    fn fib_test() {
-        let fib_test = TypedExpr::IfElse(
-            Box::new(TypedExpr::LtE(
-                Box::new(TypedExpr::Identifier("n".to_string())),
-                Box::new(TypedExpr::Literal("2".to_string(), JitType::Int)),
-            )),
-            vec![TypedExpr::Literal("1".to_string(), JitType::Int)],
-            vec![TypedExpr::Add(
-                Box::new(TypedExpr::Call(
-                    "fib".to_string(),
-                    vec![TypedExpr::Sub(
-                        Box::new(TypedExpr::Identifier("n".to_string())),
-                        Box::new(TypedExpr::Literal("1".to_string(), JitType::Int)),
-                    )],
-                )),
-                Box::new(TypedExpr::Call(
-                    "fib".to_string(),
-                    vec![TypedExpr::Sub(
-                        Box::new(TypedExpr::Identifier("n".to_string())),
-                        Box::new(TypedExpr::Literal("2".to_string(), JitType::Int)),
-                    )],
-                )),
-            )],
-        );
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Target {
+    Block(BlockId, Vec<Var>),
+    Deopt,
+}
 
-        let mut bindings = HashMap::new();
-        bindings.insert("n".to_string(), JitType::Int);
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Terminator {
+    Jump(Target),
+    Branch {
+        cond: Var,
+        then: Target,
+        otherwise: Target,
+    },
+    Return(Var),
+}
 
-        let mut functions = HashMap::new();
-        functions.insert(
-            "fib".to_string(),
-            JitFunctionSignature::new(vec![JitType::Int], JitType::Int),
-        );
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Block {
+    pub(crate) params: Vec<Var>,
+    pub(crate) insts: Vec<Inst>,
+    pub(crate) terminator: Terminator,
+}
 
-        let output = typecheck(&fib_test, &mut bindings, &functions);
+/// A function specialized to a particular set of argument types. Block 0 is the entry
+/// block, whose parameters are the arguments of the function.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Function {
+    pub(crate) params: Vec<Type>,
+    pub(crate) ret: Type,
+    pub(crate) blocks: Vec<Block>,
+    pub(crate) types: Vec<Type>,
+}
 
-        assert_eq!(output, JitType::Int)
+impl Function {
+    pub(crate) fn ty(&self, var: Var) -> Type {
+        self.types[var.0]
     }
 }
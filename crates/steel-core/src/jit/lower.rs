@@ -1,382 +1,821 @@
-#![allow(unused)]
-
-use crate::parser::ast::ExprKind;
-use crate::parser::tokens::TokenType;
-use crate::parser::visitors::VisitorMut;
-use crate::parser::{ast, interner::InternedString};
-
-use std::collections::HashSet;
-
-use im_rc::HashMap;
-
-use super::ir::Expr;
-
-// ((lambda (x y z) (+ x y z)) 10 20 30)
-// =>
-// (x = 10)
-// (y = 20)
-// (z = 30)
-// (+ x (+ y z))
-struct RenameShadowedVars<'a> {
-    depth: usize,
-    in_scope: HashSet<InternedString>,
-    shadowed: HashMap<InternedString, usize>,
-    args: Option<Vec<InternedString>>,
-    ret_val: Option<InternedString>,
-    legal_vars: &'a HashSet<InternedString>,
+// Lowers the bytecode of a closure into the typed IR, given the types of the arguments
+// it was called with. This is an abstract interpretation of the stack machine: the stack
+// holds IR variables instead of values, and every opcode is translated by mirroring exactly
+// what the interpreter does for those types - including the order the numeric primitives
+// fold their arguments in, so that float results come out bit for bit the same.
+//
+// Anything that falls outside of what can be expressed - a non numeric value, a call to
+// something other than a known primitive or the function itself, captured variables -
+// means the function is not compiled at all.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use fxhash::FxHashMap;
+
+use super::ir::*;
+use crate::core::instructions::DenseInstruction;
+use crate::core::opcode::OpCode;
+use crate::gc::Gc;
+use crate::primitives::nums::{
+    add_primitive, divide_primitive, multiply_primitive, special_add, subtract_primitive,
+};
+use crate::rvals::{FunctionSignature, SteelVal};
+use crate::steel_vm::primitives::{
+    equal_primitive, equality_primitive, gt_primitive, gte_primitive, lt_primitive, lte_primitive,
+};
+use crate::steel_vm::vm::superinstructions::original_instruction;
+use crate::values::functions::ByteCodeLambda;
+
+/// The reason a function could not be lowered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Unsupported(pub(crate) String);
+
+type LowerResult<T> = std::result::Result<T, Unsupported>;
+
+macro_rules! unsupported {
+    ($($arg:tt)*) => {
+        return Err(Unsupported(format!($($arg)*)))
+    };
 }
 
-impl<'a> RenameShadowedVars<'a> {
-    pub fn new(legal_vars: &'a HashSet<InternedString>) -> Self {
-        Self {
-            depth: 0,
-            in_scope: HashSet::new(),
-            shadowed: HashMap::new(),
-            args: None,
-            ret_val: None,
-            legal_vars,
-        }
+/// Where the values the bytecode refers to live
+pub(crate) trait Resolver {
+    fn constant(&self, index: usize) -> SteelVal;
+    fn global(&self, index: usize) -> SteelVal;
+}
+
+pub(crate) struct Lowered {
+    pub(crate) function: Function,
+    /// The globals the compiled code assumes are still bound to these values
+    pub(crate) globals: Vec<(usize, SteelVal)>,
+}
+
+/// The numeric primitives, each with the exact semantics of the function the interpreter uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Primitive {
+    /// `special_add`, used for `+` and the `ADD` opcode
+    SpecialAdd,
+    /// `add_primitive`, used for the register variants of `ADD`
+    Add,
+    /// `add_handler_none_none`, used for `BINOPADD`
+    AddPair,
+    Sub,
+    /// `sub_handler_none_int`, used for `SUBIMMEDIATE`
+    SubImmediate,
+    Mul,
+    Div,
+    /// `equality_primitive`, which is true when given no arguments
+    Equality,
+    /// `=` and `equal?`, which require at least one argument
+    Equal,
+    Compare(CompareOp),
+    /// `lte_handler_none_int`, which compares ints and floats numerically
+    LteImmediate,
+}
+
+impl Primitive {
+    fn from_function(f: FunctionSignature) -> Option<Primitive> {
+        let f = f as usize;
+
+        let known: [(FunctionSignature, Primitive); 10] = [
+            (special_add, Primitive::SpecialAdd),
+            (add_primitive, Primitive::Add),
+            (subtract_primitive, Primitive::Sub),
+            (multiply_primitive, Primitive::Mul),
+            (divide_primitive, Primitive::Div),
+            (equal_primitive, Primitive::Equal),
+            (lt_primitive, Primitive::Compare(CompareOp::Lt)),
+            (lte_primitive, Primitive::Compare(CompareOp::Lte)),
+            (gt_primitive, Primitive::Compare(CompareOp::Gt)),
+            (gte_primitive, Primitive::Compare(CompareOp::Gte)),
+        ];
+
+        known
+            .iter()
+            .find(|(known, _)| *known as usize == f)
+            .map(|(_, primitive)| *primitive)
     }
 }
 
-// If a new variable is introduced, we should just insert it into the map with an empty shadow
-// Removing should only remove if the
-// #[derive(Default)]
-// struct ScopeMap {
-//     inner: HashMap<String, Vec<String>>,
-// }
+/// Lowers `closure`, specialized to `args`. The return type has to be picked up front, since
+/// recursive calls produce it - so each type is tried in turn.
+pub(crate) fn lower_function<R: Resolver>(
+    closure: &Gc<ByteCodeLambda>,
+    args: &[Type],
+    resolver: &R,
+) -> LowerResult<Lowered> {
+    if closure.is_multi_arity || !closure.captures().is_empty() {
+        unsupported!("closures with captured variables or rest arguments are not supported");
+    }
+
+    if closure.arity() != args.len() {
+        unsupported!(
+            "expected {} arguments, found {}",
+            closure.arity(),
+            args.len()
+        );
+    }
 
-// impl ScopeMap {
+    // Super instructions are compiled as the instructions they stand for
+    let instructions: Vec<DenseInstruction> = closure
+        .body_exp()
+        .iter()
+        .map(|instruction| original_instruction(*instruction))
+        .collect();
+    let starts = block_starts(&instructions)?;
 
-//     fn insert()
-// }
+    let mut error = None;
 
-/// Checks that the input is in fact a lowered function
-pub fn lower_function(
-    expr: &ExprKind,
-    bound_vars: &mut HashSet<InternedString>,
-) -> Option<(String, Vec<String>, String, Vec<Expr>)> {
-    // let mut visitor = RenameShadowedVars::default();
+    for ret in [Type::Int, Type::Float, Type::Bool] {
+        let lowerer = Lowerer::new(closure, &instructions, &starts, resolver, args, ret);
 
-    if let ExprKind::Define(d) = expr {
-        let function_name = d.name.atom_identifier_or_else(|| unreachable!()).ok()?;
+        match lowerer.lower() {
+            Ok(lowered) => return Ok(lowered),
+            Err(e) => error = Some(e),
+        }
+    }
 
-        // Register the function itself as a legal function to be used
-        // NOTE: Unbind the function name if compilation fails
-        bound_vars.insert(function_name.to_string());
+    Err(error.unwrap())
+}
 
-        let mut visitor = RenameShadowedVars::new(&bound_vars);
+// The instructions that begin a basic block. Only forward jumps are supported - the
+// only way back is the tail call to the start of the function.
+fn block_starts(instructions: &[DenseInstruction]) -> LowerResult<BTreeSet<usize>> {
+    let mut starts = BTreeSet::new();
 
-        if let ExprKind::LambdaFunction(_) = &d.body {
-            // let func_name = d.name.atom_identifier_or_else(|| unreachable!()).ok()?;
-            // let output = visitor.visit_define(&d)?;
+    for (index, instruction) in instructions.iter().enumerate() {
+        match instruction.op_code {
+            OpCode::IF | OpCode::JMP => {
+                let target = instruction.payload_size as usize;
 
-            match visitor.visit_define(&d) {
-                Some(Expr::Assign(func_name, stmt)) => {
-                    // return Some((func_name, visitor.args?, visitor.ret_val?, vec![*stmt]));
-                    return Some((func_name, visitor.args?, visitor.ret_val?, vec![*stmt]));
+                if target <= index || target >= instructions.len() {
+                    unsupported!("jump from {} to {} is not supported", index, target);
                 }
-                _ => {
-                    // Make this function name illegal if compilation failed
-                    bound_vars.remove(function_name);
+
+                starts.insert(target);
+
+                if instruction.op_code == OpCode::IF {
+                    starts.insert(index + 1);
                 }
             }
+            _ => {}
+        }
+    }
 
-            // Make this function name illegal if compilation failed
-            // bound_vars.remove(function_name);
+    Ok(starts)
+}
+
+struct Lowerer<'a, R> {
+    closure: &'a Gc<ByteCodeLambda>,
+    instructions: &'a [DenseInstruction],
+    starts: &'a BTreeSet<usize>,
+    resolver: &'a R,
+    args: &'a [Type],
+    ret: Type,
+
+    types: Vec<Type>,
+    blocks: Vec<Option<Block>>,
+    // The types on the stack when entering each block
+    entry_types: Vec<Vec<Type>>,
+    block_ids: FxHashMap<usize, BlockId>,
+    pending: BTreeMap<usize, BlockId>,
+    globals: Vec<(usize, SteelVal)>,
+
+    // The block currently being built
+    insts: Vec<Inst>,
+}
 
-            // if let Expr::Assign(func_name, stmt) = output {
-            //     // return Some((func_name, visitor.args?, visitor.ret_val?, vec![*stmt]));
-            //     return Some((func_name, visitor.args?, visitor.ret_val?, vec![*stmt]));
-            // }
+impl<'a, R: Resolver> Lowerer<'a, R> {
+    fn new(
+        closure: &'a Gc<ByteCodeLambda>,
+        instructions: &'a [DenseInstruction],
+        starts: &'a BTreeSet<usize>,
+        resolver: &'a R,
+        args: &'a [Type],
+        ret: Type,
+    ) -> Self {
+        Self {
+            closure,
+            instructions,
+            starts,
+            resolver,
+            args,
+            ret,
+            types: Vec::new(),
+            blocks: Vec::new(),
+            entry_types: Vec::new(),
+            block_ids: FxHashMap::default(),
+            pending: BTreeMap::new(),
+            globals: Vec::new(),
+            insts: Vec::new(),
         }
     }
 
-    None
-}
+    fn lower(mut self) -> LowerResult<Lowered> {
+        // The entry block just jumps to the block for the first instruction, which is
+        // also where self tail calls go back to.
+        self.blocks.push(None);
+        self.entry_types.push(self.args.to_vec());
+
+        let args = self.args;
+        let params: Vec<Var> = args.iter().map(|ty| self.var(*ty)).collect();
+        let stack = params.clone();
+        let target = self.edge(0, &stack);
+
+        self.blocks[0] = Some(Block {
+            params,
+            insts: Vec::new(),
+            terminator: Terminator::Jump(target),
+        });
+
+        // Blocks are visited in the order they appear in the bytecode. Since the only
+        // backwards edges go to the very first block, every block has had all of its
+        // predecessors visited by the time it is reached.
+        while let Some(start) = self.pending.keys().next().copied() {
+            let id = self.pending.remove(&start).unwrap();
+            self.lower_block(start, id)?;
+        }
 
-impl<'a> VisitorMut for RenameShadowedVars<'a> {
-    type Output = Option<Expr>;
+        Ok(Lowered {
+            function: Function {
+                params: self.args.to_vec(),
+                ret: self.ret,
+                blocks: self.blocks.into_iter().map(Option::unwrap).collect(),
+                types: self.types,
+            },
+            globals: self.globals,
+        })
+    }
+
+    fn var(&mut self, ty: Type) -> Var {
+        self.types.push(ty);
+        Var(self.types.len() - 1)
+    }
+
+    fn ty(&self, var: Var) -> Type {
+        self.types[var.0]
+    }
 
-    // Not great, but gets the job done
-    fn visit_if(&mut self, f: &ast::If) -> Self::Output {
-        let test = Box::new(self.visit(&f.test_expr)?);
-        let then_expr = self.visit(&f.then_expr)?;
-        let else_expr = self.visit(&f.else_expr)?;
+    // Passes the stack along to the block starting at `start`. The first edge into a block
+    // decides the types it expects - an edge that doesn't match those deoptimizes.
+    fn edge(&mut self, start: usize, stack: &[Var]) -> Target {
+        let types: Vec<Type> = stack.iter().map(|var| self.ty(*var)).collect();
+
+        let id = match self.block_ids.get(&start) {
+            Some(id) => *id,
+            None => {
+                let id = BlockId(self.blocks.len());
+                self.blocks.push(None);
+                self.entry_types.push(types.clone());
+                self.block_ids.insert(start, id);
+                self.pending.insert(start, id);
+                id
+            }
+        };
 
-        match (&then_expr, &else_expr) {
-            (Expr::Block(t), Expr::Block(e)) => Some(Expr::IfElse(test, t.clone(), e.clone())),
-            (Expr::Block(t), _) => Some(Expr::IfElse(test, t.clone(), vec![else_expr])),
-            (_, Expr::Block(e)) => Some(Expr::IfElse(test, vec![then_expr], e.clone())),
-            (_, _) => Some(Expr::IfElse(test, vec![then_expr], vec![else_expr])),
+        if self.entry_types[id.0] == types {
+            Target::Block(id, stack.to_vec())
+        } else {
+            Target::Deopt
         }
     }
 
-    // Define -> Assignment
-    fn visit_define(&mut self, define: &ast::Define) -> Self::Output {
-        let body = self.visit(&define.body)?;
-        let name = define
-            .name
-            .atom_identifier_or_else(|| unreachable!())
-            .ok()?
-            .to_owned();
-        // self.in_scope.insert(name.clone());
-        Some(Expr::Assign(name, Box::new(body)))
+    fn lower_block(&mut self, start: usize, id: BlockId) -> LowerResult<()> {
+        let params: Vec<Var> = self.entry_types[id.0]
+            .clone()
+            .into_iter()
+            .map(|ty| self.var(ty))
+            .collect();
+
+        let mut stack = params.clone();
+        let mut ip = start;
+
+        let terminator = loop {
+            if ip != start && self.starts.contains(&ip) {
+                break Terminator::Jump(self.edge(ip, &stack));
+            }
+
+            let instruction = match self.instructions.get(ip) {
+                Some(instruction) => *instruction,
+                None => unsupported!("reached the end of the function without returning"),
+            };
+
+            let payload = instruction.payload_size as usize;
+
+            match instruction.op_code {
+                OpCode::PASS | OpCode::BEGINSCOPE | OpCode::LetVar => {
+                    ip += 1;
+                }
+                OpCode::PUSHCONST => {
+                    let value = self.resolver.constant(payload);
+                    let var = self.constant_value(&value)?;
+                    stack.push(var);
+                    ip += 1;
+                }
+                OpCode::LOADINT0 | OpCode::LOADINT1 | OpCode::LOADINT2 => {
+                    let value = match instruction.op_code {
+                        OpCode::LOADINT0 => 0,
+                        OpCode::LOADINT1 => 1,
+                        _ => 2,
+                    };
+                    let var = self.constant(Constant::Int(value));
+                    stack.push(var);
+                    ip += 1;
+                }
+                OpCode::READLOCAL | OpCode::MOVEREADLOCAL => {
+                    stack.push(local(&stack, payload)?);
+                    ip += 1;
+                }
+                OpCode::READLOCAL0 | OpCode::MOVEREADLOCAL0 => {
+                    stack.push(local(&stack, 0)?);
+                    ip += 1;
+                }
+                OpCode::READLOCAL1 | OpCode::MOVEREADLOCAL1 => {
+                    stack.push(local(&stack, 1)?);
+                    ip += 1;
+                }
+                OpCode::READLOCAL2 | OpCode::MOVEREADLOCAL2 => {
+                    stack.push(local(&stack, 2)?);
+                    ip += 1;
+                }
+                OpCode::READLOCAL3 | OpCode::MOVEREADLOCAL3 => {
+                    stack.push(local(&stack, 3)?);
+                    ip += 1;
+                }
+                OpCode::POPN => {
+                    let last = pop(&mut stack)?;
+                    if payload > stack.len() {
+                        unsupported!("popped past the start of the frame");
+                    }
+                    stack.truncate(stack.len() - payload);
+                    stack.push(last);
+                    ip += 1;
+                }
+                OpCode::LETENDSCOPE => {
+                    if payload >= stack.len() {
+                        unsupported!("let scope ended past the start of the frame");
+                    }
+                    let last = stack.len() - 1;
+                    stack.drain(payload..last);
+                    ip += 1;
+                }
+                OpCode::ADD
+                | OpCode::SUB
+                | OpCode::MUL
+                | OpCode::DIV
+                | OpCode::EQUAL
+                | OpCode::LTE => {
+                    let primitive = match instruction.op_code {
+                        OpCode::ADD => Primitive::SpecialAdd,
+                        OpCode::SUB => Primitive::Sub,
+                        OpCode::MUL => Primitive::Mul,
+                        OpCode::DIV => Primitive::Div,
+                        OpCode::EQUAL => Primitive::Equality,
+                        _ => Primitive::Compare(CompareOp::Lte),
+                    };
+                    let args = pop_n(&mut stack, payload)?;
+                    let result = self.primitive(primitive, &args)?;
+                    stack.push(result);
+                    ip += 2;
+                }
+                OpCode::BINOPADD => {
+                    let args = pop_n(&mut stack, 2)?;
+                    let result = self.primitive(Primitive::AddPair, &args)?;
+                    stack.push(result);
+                    ip += 2;
+                }
+                OpCode::ADDREGISTER | OpCode::SUBREGISTER | OpCode::LTEREGISTER => {
+                    let primitive = match instruction.op_code {
+                        OpCode::ADDREGISTER => Primitive::Add,
+                        OpCode::SUBREGISTER => Primitive::Sub,
+                        _ => Primitive::Compare(CompareOp::Lte),
+                    };
+                    let value = self.resolver.constant(self.operand(ip + 1)?);
+                    let args = [local(&stack, payload)?, self.constant_value(&value)?];
+                    let result = self.primitive(primitive, &args)?;
+                    stack.push(result);
+                    ip += 2;
+                }
+                OpCode::ADDIMMEDIATE | OpCode::SUBIMMEDIATE | OpCode::LTEIMMEDIATE => {
+                    let primitive = match instruction.op_code {
+                        OpCode::ADDIMMEDIATE => Primitive::Add,
+                        OpCode::SUBIMMEDIATE => Primitive::SubImmediate,
+                        _ => Primitive::LteImmediate,
+                    };
+                    let immediate = self.constant(Constant::Int(self.operand(ip + 1)? as isize));
+                    let args = [local(&stack, payload)?, immediate];
+                    let result = self.primitive(primitive, &args)?;
+                    stack.push(result);
+                    ip += 2;
+                }
+                OpCode::SUBREGISTER1 => {
+                    let one = self.constant(Constant::Int(1));
+                    let args = [local(&stack, self.operand(ip + 1)?)?, one];
+                    let result = self.primitive(Primitive::Sub, &args)?;
+                    stack.push(result);
+                    ip += 2;
+                }
+                OpCode::LTEIMMEDIATEIF => {
+                    let immediate = self.constant(Constant::Int(self.operand(ip + 1)? as isize));
+                    let args = [local(&stack, payload)?, immediate];
+                    let cond = self.primitive(Primitive::LteImmediate, &args)?;
+                    ip += 2;
+
+                    let otherwise = self.operand(ip)?;
+                    break self.branch(cond, &stack, ip + 1, otherwise);
+                }
+                OpCode::IF => {
+                    let cond = pop(&mut stack)?;
+                    break self.branch(cond, &stack, ip + 1, payload);
+                }
+                OpCode::JMP => {
+                    break Terminator::Jump(self.edge(payload, &stack));
+                }
+                OpCode::CALLGLOBAL | OpCode::CALLGLOBALTAIL => {
+                    let index = self.operand(ip + 1)?;
+                    let function = self.resolver.global(index);
+                    let args = pop_n(&mut stack, payload)?;
+
+                    match &function {
+                        SteelVal::Closure(closure) if Gc::ptr_eq(closure, self.closure) => {
+                            if args.len() != self.args.len() {
+                                unsupported!("recursive call with the wrong number of arguments");
+                            }
+
+                            self.assume_global(index, function.clone());
+
+                            if instruction.op_code == OpCode::CALLGLOBALTAIL {
+                                break Terminator::Jump(self.edge(0, &args));
+                            }
+
+                            let dst = self.var(self.ret);
+                            self.insts.push(Inst::CallSelf { dst, args });
+                            stack.push(dst);
+                        }
+                        SteelVal::FuncV(f) => match Primitive::from_function(*f) {
+                            Some(primitive) => {
+                                self.assume_global(index, function.clone());
+                                let result = self.primitive(primitive, &args)?;
+                                stack.push(result);
+                            }
+                            None => unsupported!("call to an unknown primitive"),
+                        },
+                        _ => unsupported!("call to {} is not supported", function),
+                    }
+
+                    ip += 2;
+                }
+                OpCode::TCOJMP => {
+                    if self.operand(ip + 1)? != 0 || payload != self.args.len() {
+                        unsupported!("tail call to an enclosing function is not supported");
+                    }
+
+                    let args = pop_n(&mut stack, payload)?;
+                    break Terminator::Jump(self.edge(0, &args));
+                }
+                OpCode::POPPURE => {
+                    let value = pop(&mut stack)?;
+
+                    if self.ty(value) != self.ret {
+                        unsupported!(
+                            "function returns a {}, expected a {}",
+                            self.ty(value),
+                            self.ret
+                        );
+                    }
+
+                    break Terminator::Return(value);
+                }
+                op_code => unsupported!("{:?} is not supported", op_code),
+            }
+        };
+
+        self.blocks[id.0] = Some(Block {
+            params,
+            insts: std::mem::take(&mut self.insts),
+            terminator,
+        });
+
+        Ok(())
     }
 
-    // If done correctly, this _should_ be unreachable
-    fn visit_lambda_function(&mut self, lambda_function: &ast::LambdaFunction) -> Self::Output {
-        // TODO identify return variables
-        // Do I need to specify the return or is the last value just there?
-        let args = lambda_function
-            .args
-            .iter()
-            .map(|x| {
-                x.atom_identifier_or_else(|| unreachable!())
-                    .ok()
-                    .map(|x| x.to_string())
-            })
-            .collect::<Option<Vec<_>>>()?;
+    fn operand(&self, index: usize) -> LowerResult<usize> {
+        match self.instructions.get(index) {
+            Some(instruction) => Ok(instruction.payload_size as usize),
+            None => unsupported!("missing operand at {}", index),
+        }
+    }
 
-        // Make sure these are now in scope
-        for arg in &args {
-            self.in_scope.insert(arg.clone());
+    fn assume_global(&mut self, index: usize, value: SteelVal) {
+        if !self.globals.iter().any(|(i, _)| *i == index) {
+            self.globals.push((index, value));
         }
+    }
 
-        self.args = Some(args);
+    // Only `#f` is false, so a condition that isn't a bool always takes the first branch
+    fn branch(&mut self, cond: Var, stack: &[Var], then: usize, otherwise: usize) -> Terminator {
+        if self.ty(cond) != Type::Bool {
+            return Terminator::Jump(self.edge(then, stack));
+        }
 
-        // Badly attach the return value label
-        self.ret_val = Some("###__return-value__###".to_string());
-        let output = Some(Expr::Assign(
-            "###__return-value__###".to_string(),
-            Box::new(self.visit(&lambda_function.body)?),
-        ));
+        let then = self.edge(then, stack);
+        let otherwise = self.edge(otherwise, stack);
 
-        output
+        Terminator::Branch {
+            cond,
+            then,
+            otherwise,
+        }
     }
 
-    fn visit_begin(&mut self, begin: &ast::Begin) -> Self::Output {
-        let body = begin
-            .exprs
-            .iter()
-            .map(|e| self.visit(e))
-            .collect::<Option<Vec<_>>>()?;
-        Some(Expr::Block(body))
+    fn constant(&mut self, value: Constant) -> Var {
+        let dst = self.var(value.ty());
+        self.insts.push(Inst::Const { dst, value });
+        dst
     }
 
-    fn visit_return(&mut self, _r: &ast::Return) -> Self::Output {
-        None
+    fn constant_value(&mut self, value: &SteelVal) -> LowerResult<Var> {
+        let constant = match value {
+            SteelVal::IntV(n) => Constant::Int(*n),
+            SteelVal::NumV(n) => Constant::Float(*n),
+            SteelVal::BoolV(b) => Constant::Bool(*b),
+            _ => unsupported!("constant {} is not supported", value),
+        };
+
+        Ok(self.constant(constant))
     }
 
-    // fn visit_apply(&mut self, _apply: &ast::Apply) -> Self::Output {
-    //     None
-    // }
+    fn binary(&mut self, op: BinaryOp, lhs: Var, rhs: Var) -> Var {
+        debug_assert_eq!(self.ty(lhs), self.ty(rhs));
 
-    // fn visit_panic(&mut self, _p: &ast::Panic) -> Self::Output {
-    //     None
-    // }
+        let dst = self.var(self.ty(lhs));
+        self.insts.push(Inst::Binary { dst, op, lhs, rhs });
+        dst
+    }
 
-    // fn visit_transduce(&mut self, _transduce: &ast::Transduce) -> Self::Output {
-    //     None
-    // }
+    fn promote(&mut self, src: Var) -> Var {
+        if self.ty(src) == Type::Float {
+            return src;
+        }
 
-    // fn visit_execute(&mut self, _execute: &ast::Execute) -> Self::Output {
-    //     None
-    // }
+        let dst = self.var(Type::Float);
+        self.insts.push(Inst::IntToFloat { dst, src });
+        dst
+    }
 
-    fn visit_quote(&mut self, _quote: &ast::Quote) -> Self::Output {
-        None
+    fn compare(&mut self, op: CompareOp, lhs: Var, rhs: Var) -> Var {
+        let dst = self.var(Type::Bool);
+        self.insts.push(Inst::Compare { dst, op, lhs, rhs });
+        dst
     }
 
-    fn visit_macro(&mut self, _m: &ast::Macro) -> Self::Output {
-        None
+    fn all(&mut self, conditions: Vec<Var>) -> Var {
+        conditions
+            .into_iter()
+            .reduce(|lhs, rhs| {
+                let dst = self.var(Type::Bool);
+                self.insts.push(Inst::And { dst, lhs, rhs });
+                dst
+            })
+            .unwrap_or_else(|| self.constant(Constant::Bool(true)))
     }
 
-    // TODO fix this
-    fn visit_atom(&mut self, a: &ast::Atom) -> Self::Output {
-        use TokenType::*;
-        match &a.syn.ty {
-            Identifier(s) => {
-                // Short circuit if we reference a function that we can't yet reference
-                self.validate_identifier(s)?;
-
-                if let Some(depth) = self.shadowed.get(s) {
-                    // Create this busted shadowed nonsense
-                    Some(Expr::Identifier(
-                        "###__depth__".to_string() + depth.to_string().as_str() + s.as_str(),
-                    ))
-                } else {
-                    Some(Expr::Identifier(s.clone()))
-                }
-            }
-            IntegerLiteral(i) => Some(Expr::Literal(i.to_string())),
-            _ => None,
+    fn numbers(&self, args: &[Var]) -> LowerResult<()> {
+        if args.iter().any(|arg| self.ty(*arg) == Type::Bool) {
+            unsupported!("arithmetic on a bool");
         }
+
+        Ok(())
     }
 
-    // Check explicit function application with let
-    fn visit_list(&mut self, l: &ast::List) -> Self::Output {
-        // The first should be the function, and the rest should be the args
-        let mut args = l.iter();
-
-        // The corresponds to either a symbol, or a lambda function in this case
-        // Anything else we don't care about
-        let func = args.next()?;
-
-        match func {
-            ExprKind::LambdaFunction(lam) => {
-                self.depth += 1;
-
-                // These need to be mangled?
-                let variable_names = lam
-                    .args
-                    .iter()
-                    .map(|x| x.atom_identifier_or_else(|| unreachable!()).ok().cloned())
-                    .collect::<Option<Vec<_>>>()?;
-
-                let pre_variables = self.shadowed.clone();
-
-                // These are the lowered assignments
-                let mut assignments = variable_names
-                    .clone()
-                    .into_iter()
-                    .zip(args)
-                    .map(|x| -> Option<Expr> {
-                        Some(Expr::Assign(
-                            {
-                                if self.in_scope.contains(&x.0) {
-                                    "###__depth__".to_string()
-                                        + self.depth.to_string().as_str()
-                                        + x.0.as_str()
-                                } else {
-                                    x.0
-                                }
-                            },
-                            Box::new(self.visit(x.1)?),
-                        ))
-                    })
-                    .collect::<Option<Vec<_>>>()?;
-
-                for variable in &variable_names {
-                    if self.in_scope.contains(variable) {
-                        println!("Shadowing: {}", variable);
-                        self.shadowed.insert(variable.clone(), self.depth);
+    fn primitive(&mut self, primitive: Primitive, args: &[Var]) -> LowerResult<Var> {
+        match primitive {
+            Primitive::SpecialAdd => {
+                self.numbers(args)?;
+
+                let mut ints = None;
+                let mut floats = None;
+
+                for arg in args {
+                    if self.ty(*arg) == Type::Int {
+                        ints = Some(match ints {
+                            Some(sum) => self.binary(BinaryOp::Add, sum, *arg),
+                            None => *arg,
+                        });
+                    } else {
+                        let sum = match floats {
+                            Some(sum) => sum,
+                            None => self.constant(Constant::Float(0.0)),
+                        };
+                        floats = Some(self.binary(BinaryOp::Add, sum, *arg));
                     }
                 }
 
-                let body = self.visit(&lam.body)?;
+                Ok(match (ints, floats) {
+                    (ints, Some(floats)) => {
+                        let ints = match ints {
+                            Some(ints) => self.promote(ints),
+                            None => self.constant(Constant::Float(0.0)),
+                        };
+                        let sum = self.binary(BinaryOp::Add, floats, ints);
+                        // The (empty) bignum sum is added on at the end
+                        let zero = self.constant(Constant::Float(0.0));
+                        self.binary(BinaryOp::Add, sum, zero)
+                    }
+                    (Some(ints), None) => ints,
+                    (None, None) => self.constant(Constant::Int(0)),
+                })
+            }
+            Primitive::Add | Primitive::Mul => {
+                self.numbers(args)?;
 
-                // They're no longer in scope, take them out
-                for variable in &variable_names {
-                    self.in_scope.remove(variable);
-                    self.shadowed.remove(variable);
+                let (op, identity) = if primitive == Primitive::Add {
+                    (BinaryOp::Add, 0)
+                } else {
+                    (BinaryOp::Mul, 1)
+                };
+
+                let mut int = None;
+                let mut float: Option<Var> = None;
+
+                for arg in args {
+                    if let Some(acc) = float {
+                        let arg = self.promote(*arg);
+                        float = Some(self.binary(op, acc, arg));
+                    } else if self.ty(*arg) == Type::Int {
+                        int = Some(match int {
+                            Some(acc) => self.binary(op, acc, *arg),
+                            None => *arg,
+                        });
+                    } else {
+                        let acc = match int {
+                            Some(acc) => self.promote(acc),
+                            None => self.constant(Constant::Float(identity as f64)),
+                        };
+                        float = Some(self.binary(op, acc, *arg));
+                    }
                 }
 
-                // Just reset the state for now
-                self.shadowed = pre_variables;
-
-                self.depth -= 1;
+                Ok(match (int, float) {
+                    (_, Some(float)) => float,
+                    (Some(int), None) => int,
+                    (None, None) => self.constant(Constant::Int(identity)),
+                })
+            }
+            Primitive::AddPair => {
+                self.numbers(args)?;
 
-                assignments.push(body);
+                let (lhs, rhs) = (args[0], args[1]);
 
-                Some(Expr::Block(assignments))
+                Ok(if self.ty(lhs) == self.ty(rhs) {
+                    self.binary(BinaryOp::Add, lhs, rhs)
+                } else {
+                    let lhs = self.promote(lhs);
+                    let rhs = self.promote(rhs);
+                    self.binary(BinaryOp::Add, lhs, rhs)
+                })
+            }
+            Primitive::Sub => {
+                self.numbers(args)?;
+
+                match args {
+                    [] => unsupported!("- requires at least one argument"),
+                    [arg] => {
+                        let zero = match self.ty(*arg) {
+                            Type::Int => Constant::Int(0),
+                            _ => Constant::Float(0.0),
+                        };
+                        let zero = self.constant(zero);
+                        Ok(self.binary(BinaryOp::Sub, zero, *arg))
+                    }
+                    [first, rest @ ..] => {
+                        let mut acc = *first;
+
+                        for arg in rest {
+                            if self.ty(acc) == Type::Float || self.ty(*arg) == Type::Float {
+                                acc = self.promote(acc);
+                                let arg = self.promote(*arg);
+                                acc = self.binary(BinaryOp::Sub, acc, arg);
+                            } else {
+                                acc = self.binary(BinaryOp::Sub, acc, *arg);
+                            }
+                        }
+
+                        Ok(acc)
+                    }
+                }
             }
-            _ => {
-                let ident = func
-                    .atom_identifier_or_else(|| "Not a valid function here")
-                    .ok()?;
+            Primitive::SubImmediate => {
+                self.numbers(args)?;
 
-                if let Some(binop) = symbol_to_binop(ident) {
-                    self.expr_list_to_bin_op(binop, args)
+                let (lhs, rhs) = (args[0], args[1]);
+
+                Ok(if self.ty(lhs) == Type::Float {
+                    let rhs = self.promote(rhs);
+                    self.binary(BinaryOp::Sub, lhs, rhs)
                 } else {
-                    self.validate_function_call_ident(ident)?;
+                    self.binary(BinaryOp::Sub, lhs, rhs)
+                })
+            }
+            Primitive::Div => {
+                self.numbers(args)?;
 
-                    Some(Expr::Call(
-                        ident.to_owned(),
-                        args.map(|x| self.visit(x)).collect::<Option<Vec<_>>>()?,
-                    ))
+                let (first, rest) = match args.split_first() {
+                    Some(split) => split,
+                    None => unsupported!("/ requires at least one argument"),
+                };
+
+                let mut acc = self.promote(*first);
+
+                for arg in rest {
+                    let arg = self.promote(*arg);
+                    acc = self.binary(BinaryOp::Div, acc, arg);
                 }
+
+                self.insts.push(Inst::GuardFractional { src: acc });
+
+                Ok(acc)
             }
-        }
-    }
+            Primitive::Equality | Primitive::Equal => {
+                if primitive == Primitive::Equal && args.is_empty() {
+                    unsupported!("= requires at least one argument");
+                }
 
-    fn visit_syntax_rules(&mut self, _l: &ast::SyntaxRules) -> Self::Output {
-        None
-    }
+                let mut conditions = Vec::new();
 
-    // This should be assignment
-    // but only if the variable being assigned is scoped locally to the function
-    fn visit_set(&mut self, _s: &ast::Set) -> Self::Output {
-        todo!()
-    }
+                for window in args.windows(2) {
+                    let (lhs, rhs) = (window[0], window[1]);
 
-    fn visit_require(&mut self, _s: &ast::Require) -> Self::Output {
-        None
-    }
+                    // Floats are never `equal?` to one another
+                    let condition = match (self.ty(lhs), self.ty(rhs)) {
+                        (Type::Int, Type::Int) | (Type::Bool, Type::Bool) => {
+                            self.compare(CompareOp::Eq, lhs, rhs)
+                        }
+                        _ => self.constant(Constant::Bool(false)),
+                    };
 
-    fn visit_let(&mut self, l: &ast::Let) -> Self::Output {
-        todo!()
-    }
-}
+                    conditions.push(condition);
+                }
 
-impl<'a> RenameShadowedVars<'a> {
-    fn expr_list_to_bin_op<'b>(
-        &mut self,
-        binop: fn(Box<Expr>, Box<Expr>) -> Expr,
-        exprs: impl DoubleEndedIterator<Item = &'b ExprKind>,
-    ) -> Option<Expr> {
-        let mut args_iter = exprs.into_iter();
+                Ok(self.all(conditions))
+            }
+            Primitive::Compare(op) => {
+                if args.is_empty() {
+                    unsupported!("comparison requires at least one argument");
+                }
 
-        let left_initial = Box::new(self.visit(args_iter.next()?)?);
-        let right_initial = Box::new(self.visit(args_iter.next()?)?);
+                let mut conditions = Vec::new();
 
-        args_iter.try_fold(binop(left_initial, right_initial), |accum, next| {
-            Some(binop(Box::new(accum), Box::new(self.visit(next)?)))
-        })
-    }
+                for window in args.windows(2) {
+                    let (lhs, rhs) = (window[0], window[1]);
 
-    fn validate_function_call_ident(&self, ident: &InternedString) -> Option<()> {
-        if self.legal_vars.contains(ident) {
-            println!("Validated: {}", ident);
-            println!("Legal vars: {:#?}", self.legal_vars);
-            Some(())
-        } else {
-            println!(
-                "Found a variable that cannot be referenced, aborting compilation: {}",
-                ident
-            );
-            None
+                    // Values of different types are unordered, which fails any comparison
+                    let condition = match (self.ty(lhs), self.ty(rhs)) {
+                        (Type::Int, Type::Int) | (Type::Float, Type::Float) => {
+                            self.compare(op, lhs, rhs)
+                        }
+                        _ => self.constant(Constant::Bool(false)),
+                    };
+
+                    conditions.push(condition);
+                }
+
+                Ok(self.all(conditions))
+            }
+            Primitive::LteImmediate => {
+                self.numbers(args)?;
+
+                let (lhs, rhs) = (args[0], args[1]);
+
+                Ok(if self.ty(lhs) == Type::Float {
+                    let rhs = self.promote(rhs);
+                    self.compare(CompareOp::Lte, lhs, rhs)
+                } else {
+                    self.compare(CompareOp::Lte, lhs, rhs)
+                })
+            }
         }
     }
+}
 
-    fn validate_identifier(&self, ident: &InternedString) -> Option<()> {
-        if !self.shadowed.contains_key(ident)
-            && !self.in_scope.contains(ident)
-            && !self.legal_vars.contains(ident)
-        {
-            println!(
-                "Found a variable that cannot be referenced, aborting compilation: {}",
-                ident
-            );
-            None
-        } else {
-            Some(())
-        }
+fn local(stack: &[Var], index: usize) -> LowerResult<Var> {
+    match stack.get(index) {
+        Some(var) => Ok(*var),
+        None => unsupported!("read of local {} past the top of the stack", index),
     }
 }
 
-fn symbol_to_binop(symbol: &str) -> Option<fn(Box<Expr>, Box<Expr>) -> Expr> {
-    match symbol {
-        "+" => Some(Expr::Add),
-        "-" => Some(Expr::Sub),
-        "/" => Some(Expr::Div),
-        "*" => Some(Expr::Mul),
-        "=" | "equal?" | "eq?" => Some(Expr::Eq),
-        "<" => Some(Expr::Lt),
-        ">" => Some(Expr::Gt),
-        "!=" => Some(Expr::Ne),
-        ">=" => Some(Expr::Ge),
-        "<=" => Some(Expr::Le),
-        _ => None,
+fn pop(stack: &mut Vec<Var>) -> LowerResult<Var> {
+    match stack.pop() {
+        Some(var) => Ok(var),
+        None => unsupported!("popped an empty stack"),
     }
 }
+
+fn pop_n(stack: &mut Vec<Var>, count: usize) -> LowerResult<Vec<Var>> {
+    if count > stack.len() {
+        unsupported!("popped past the start of the frame");
+    }
+
+    Ok(stack.split_off(stack.len() - count))
+}
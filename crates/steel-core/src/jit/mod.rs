@@ -1,7 +1,378 @@
-#![allow(unused)]
+//! A method JIT for hot numeric functions.
+//!
+//! Once a closure has been called enough times, the VM asks the cache to run it as native
+//! code instead. The closure's bytecode is lowered into a typed IR specialized to the types
+//! of the arguments it was called with (see [`lower`]), which is then compiled with cranelift
+//! (see [`code_gen`]). Only closures that work purely on ints, floats and bools - calling
+//! nothing but the numeric primitives and themselves - can be compiled.
+//!
+//! Compiled code makes assumptions: that the arguments have the types it was specialized
+//! to, that the globals it calls still hold the same functions, and that its arithmetic
+//! stays within the bounds the interpreter would keep it in. When any of those fail, the
+//! call is handed back to the interpreter, which runs it from the start. Closures that keep
+//! failing are left to the interpreter for good.
 
-pub mod code_gen;
-pub mod ir;
-pub mod lower;
-pub mod sig;
-pub mod value;
+mod code_gen;
+mod ir;
+mod lower;
+
+use fxhash::FxHashMap;
+
+use crate::compiler::constants::ConstantMap;
+use crate::env::Env;
+use crate::gc::Gc;
+use crate::rvals::SteelVal;
+use crate::values::functions::ByteCodeLambda;
+
+use self::code_gen::{CodeGen, EntryPoint, TAG_BOOL, TAG_DEOPT, TAG_FLOAT, TAG_INT};
+use self::ir::Type;
+use self::lower::{lower_function, Resolver};
+
+/// Arguments are passed to compiled code through a fixed size buffer
+const MAX_ARGS: usize = 8;
+/// How many different sets of argument types a closure is compiled for
+const MAX_SPECIALIZATIONS: usize = 4;
+/// How many times compiled code can bail out before the closure is left to the interpreter
+const MAX_DEOPTS: usize = 16;
+
+pub(crate) struct VmResolver<'a> {
+    pub(crate) globals: &'a Env,
+    pub(crate) constants: &'a ConstantMap,
+}
+
+impl<'a> Resolver for VmResolver<'a> {
+    fn constant(&self, index: usize) -> SteelVal {
+        self.constants.get(index)
+    }
+
+    fn global(&self, index: usize) -> SteelVal {
+        self.globals.repl_lookup_idx(index)
+    }
+}
+
+struct Specialization {
+    params: Vec<Type>,
+    globals: Vec<(usize, SteelVal)>,
+    entry: EntryPoint,
+}
+
+#[derive(Default)]
+struct CompiledClosure {
+    specializations: Vec<Specialization>,
+    // Argument types the closure could not be compiled for
+    failed: Vec<Vec<Type>>,
+    deopts: usize,
+    disabled: bool,
+}
+
+/// The compiled code for every closure that has been compiled on a thread
+#[derive(Default)]
+pub(crate) struct JitCache {
+    code_gen: Option<CodeGen>,
+    closures: FxHashMap<usize, CompiledClosure>,
+}
+
+// Compiled code belongs to the module that produced it, so a cloned thread starts over
+impl Clone for JitCache {
+    fn clone(&self) -> Self {
+        JitCache::default()
+    }
+}
+
+fn type_of(value: &SteelVal) -> Option<Type> {
+    match value {
+        SteelVal::IntV(_) => Some(Type::Int),
+        SteelVal::NumV(_) => Some(Type::Float),
+        SteelVal::BoolV(_) => Some(Type::Bool),
+        _ => None,
+    }
+}
+
+fn same_global(left: &SteelVal, right: &SteelVal) -> bool {
+    match (left, right) {
+        (SteelVal::Closure(l), SteelVal::Closure(r)) => Gc::ptr_eq(l, r),
+        (SteelVal::FuncV(l), SteelVal::FuncV(r)) => *l as usize == *r as usize,
+        _ => false,
+    }
+}
+
+impl JitCache {
+    /// Calls `closure` with `args` as compiled code, compiling it first if it hasn't been
+    /// compiled for these argument types yet. Returns `None` if the interpreter has to make
+    /// the call instead.
+    pub(crate) fn call<R: Resolver>(
+        &mut self,
+        closure: &Gc<ByteCodeLambda>,
+        args: &[SteelVal],
+        resolver: &R,
+    ) -> Option<SteelVal> {
+        if args.len() > MAX_ARGS {
+            return None;
+        }
+
+        let compiled = self.closures.entry(closure.id).or_default();
+
+        if compiled.disabled {
+            return None;
+        }
+
+        let matches = |params: &[Type]| {
+            params
+                .iter()
+                .zip(args)
+                .all(|(ty, arg)| type_of(arg) == Some(*ty))
+        };
+
+        let index = match compiled
+            .specializations
+            .iter()
+            .position(|specialization| matches(&specialization.params))
+        {
+            Some(index) => index,
+            None => self.compile(closure, args, resolver)?,
+        };
+
+        let compiled = self.closures.get_mut(&closure.id)?;
+        let specialization = &compiled.specializations[index];
+
+        if !specialization
+            .globals
+            .iter()
+            .all(|(index, value)| same_global(&resolver.global(*index), value))
+        {
+            // A global the code depends on was redefined - throw it away, and let the
+            // next call compile it against the new definition
+            compiled.specializations.remove(index);
+            return None;
+        }
+
+        let mut buffer = [0i64; MAX_ARGS];
+        for (slot, arg) in buffer.iter_mut().zip(args) {
+            *slot = match arg {
+                SteelVal::IntV(n) => *n as i64,
+                SteelVal::NumV(n) => n.to_bits() as i64,
+                SteelVal::BoolV(b) => *b as i64,
+                _ => unreachable!(),
+            };
+        }
+
+        let mut bits = 0i64;
+
+        // Safety: the entry point was compiled for exactly these argument types, which
+        // were just checked
+        let tag = unsafe { (specialization.entry)(buffer.as_ptr(), &mut bits) };
+
+        match tag {
+            TAG_INT => Some(SteelVal::IntV(bits as isize)),
+            TAG_FLOAT => Some(SteelVal::NumV(f64::from_bits(bits as u64))),
+            TAG_BOOL => Some(SteelVal::BoolV(bits != 0)),
+            _ => {
+                debug_assert_eq!(tag, TAG_DEOPT);
+
+                compiled.deopts += 1;
+                if compiled.deopts >= MAX_DEOPTS {
+                    log::debug!(target: "jit", "giving up on compiling function {}", closure.id);
+                    compiled.disabled = true;
+                    compiled.specializations.clear();
+                }
+
+                None
+            }
+        }
+    }
+
+    // Compiles a new specialization of `closure`, returning its index
+    fn compile<R: Resolver>(
+        &mut self,
+        closure: &Gc<ByteCodeLambda>,
+        args: &[SteelVal],
+        resolver: &R,
+    ) -> Option<usize> {
+        let params: Vec<Type> = args.iter().map(type_of).collect::<Option<_>>()?;
+
+        let compiled = self.closures.get_mut(&closure.id)?;
+
+        if compiled.failed.contains(&params) {
+            return None;
+        }
+
+        if compiled.specializations.len() + compiled.failed.len() >= MAX_SPECIALIZATIONS {
+            compiled.disabled = true;
+            return None;
+        }
+
+        let code_gen = match &mut self.code_gen {
+            Some(code_gen) => code_gen,
+            None => match CodeGen::new() {
+                Ok(code_gen) => self.code_gen.insert(code_gen),
+                Err(e) => {
+                    log::debug!(target: "jit", "unable to start the jit: {}", e);
+                    compiled.disabled = true;
+                    return None;
+                }
+            },
+        };
+
+        let result = lower_function(closure, &params, resolver)
+            .map_err(|e| e.0)
+            .and_then(|lowered| {
+                code_gen
+                    .compile(&lowered.function)
+                    .map(|entry| (lowered.globals, entry))
+            });
+
+        match result {
+            Ok((globals, entry)) => {
+                log::debug!(target: "jit", "compiled function {} for {:?}", closure.id, params);
+
+                compiled.specializations.push(Specialization {
+                    params,
+                    globals,
+                    entry,
+                });
+
+                Some(compiled.specializations.len() - 1)
+            }
+            Err(e) => {
+                log::debug!(target: "jit", "unable to compile function {}: {}", closure.id, e);
+                compiled.failed.push(params);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod jit_tests {
+    use super::*;
+    use crate::steel_vm::engine::Engine;
+
+    // Runs the program with and without the jit, checking that both agree, and returns the
+    // result with the jit
+    fn differential(program: &str) -> SteelVal {
+        let run = |jit: bool| {
+            let mut engine = Engine::new();
+            engine.with_jit(jit);
+            engine
+                .compile_and_run_raw_program(program)
+                .unwrap()
+                .pop()
+                .unwrap()
+        };
+
+        let interpreted = run(false);
+        let compiled = run(true);
+
+        match (&interpreted, &compiled) {
+            (SteelVal::NumV(l), SteelVal::NumV(r)) => assert_eq!(l.to_bits(), r.to_bits()),
+            _ => assert_eq!(interpreted, compiled),
+        }
+
+        compiled
+    }
+
+    #[test]
+    fn fib() {
+        let program = r#"
+            (define (fib n) (if (<= n 2) 1 (+ (fib (- n 1)) (fib (- n 2)))))
+            (fib 25)
+        "#;
+
+        assert_eq!(differential(program), SteelVal::IntV(75025));
+    }
+
+    #[test]
+    fn ackermann() {
+        let program = r#"
+            (define (ackermann m n)
+                (cond [(equal? m 0) (+ n 1)]
+                      [(equal? n 0) (ackermann (- m 1) 1)]
+                      [else (ackermann (- m 1) (ackermann m (- n 1)))]))
+            (ackermann 2 9)
+        "#;
+
+        assert_eq!(differential(program), SteelVal::IntV(21));
+    }
+
+    #[test]
+    fn float_loop() {
+        let program = r#"
+            (define (sum-to n acc)
+                (if (< n 0.5) acc (sum-to (- n 1) (+ acc (* n 0.1)))))
+            (define (repeat n last)
+                (if (= n 0) last (repeat (- n 1) (sum-to 100.0 0.0))))
+            (repeat 200 0.0)
+        "#;
+
+        differential(program);
+    }
+
+    #[test]
+    fn mixed_arithmetic() {
+        let program = r#"
+            (define (mix n acc)
+                (if (<= n 0)
+                    acc
+                    (mix (- n 1) (+ acc (/ n 4) (* n 1.5) (- 0.25 n)))))
+            (define (repeat n last)
+                (if (= n 0) last (repeat (- n 1) (mix 50 0.0))))
+            (repeat 200 0.0)
+        "#;
+
+        differential(program);
+    }
+
+    #[test]
+    fn overflow_deoptimizes() {
+        let program = r#"
+            (define (grow n acc)
+                (if (= n 0) acc (grow (- n 1) (* acc 3))))
+            (define (repeat n last)
+                (if (= n 0) last (repeat (- n 1) (grow 50 1))))
+            (repeat 200 0)
+        "#;
+
+        assert!(matches!(differential(program), SteelVal::BigNum(_)));
+    }
+
+    #[test]
+    fn changing_argument_types() {
+        let program = r#"
+            (define (double x) (+ x x))
+            (define (repeat n acc)
+                (if (= n 0)
+                    acc
+                    (repeat (- n 1) (+ acc (double n) (double 0.5)))))
+            (repeat 500 0)
+        "#;
+
+        differential(program);
+    }
+
+    #[test]
+    fn redefined_global() {
+        let program = r#"
+            (define (op x y) (+ x y))
+            (define (apply-op n) (op n 1))
+            (define (repeat n acc)
+                (if (= n 0) acc (repeat (- n 1) (apply-op n))))
+            (define first (repeat 500 0))
+            (set! op (lambda (x y) (* x y)))
+            (list first (repeat 500 0))
+        "#;
+
+        differential(program);
+    }
+
+    #[test]
+    fn deep_recursion() {
+        let program = r#"
+            (define (count n) (if (= n 0) 0 (+ 1 (count (- n 1)))))
+            (define (repeat n last)
+                (if (= n 0) last (repeat (- n 1) (count 5000))))
+            (repeat 200 0)
+        "#;
+
+        assert_eq!(differential(program), SteelVal::IntV(5000));
+    }
+}
@@ -8,14 +8,15 @@ pub mod primitives;
 #[macro_use]
 pub mod rerrs;
 pub mod rvals;
+pub mod serde;
 pub mod stdlib;
 #[macro_use]
 pub mod gc;
 mod containers;
 mod conversions;
 
-// #[cfg(feature = "jit")]
-// pub mod jit;
+#[cfg(feature = "jit")]
+pub(crate) mod jit;
 pub mod parser;
 pub mod steel_vm;
 
@@ -26,3 +27,4 @@ pub(crate) mod values;
 pub use self::{rerrs::SteelErr, rvals::SteelVal, stdlib::PRELUDE};
 pub use im_lists::list::List;
 pub use im_rc::HashMap;
+pub use steel_derive::methods;
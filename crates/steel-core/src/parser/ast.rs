@@ -488,6 +488,7 @@ impl TryFrom<&SteelVal> for ExprKind {
             StreamV(_) => Err("Can't convert from stream to expression!"),
             Contract(_) => Err("Can't convert from contract to expression!"),
             ContractedFunction(_) => Err("Can't convert from contracted function to expression!"),
+            ContractedCollection(c) => Self::try_from(c.unchecked()),
             BoxedFunction(_) => Err("Can't convert from boxed function to expression!"),
             ContinuationFunction(_) => Err("Can't convert from continuation to expression!"),
             // #[cfg(feature = "jit")]
@@ -496,6 +497,7 @@ impl TryFrom<&SteelVal> for ExprKind {
             BuiltIn(_) => Err("Can't convert from function to expression!"),
             ReducerV(_) => Err("Can't convert from reducer to expression!"),
             MutableVector(_) => Err("Can't convert from vector to expression!"),
+            ByteVector(_) => Err("Can't convert from bytevector to expression!"),
             CustomStruct(_) => Err("Can't convert from struct to expression!"),
             BoxedIterator(_) => Err("Can't convert from boxed iterator to expression!"),
             Boxed(_) => Err("Can't convert from boxed steel val to expression!"),
@@ -591,7 +593,19 @@ impl fmt::Display for Atom {
 
 impl ToDoc for Atom {
     fn to_doc(&self) -> RcDoc<()> {
-        RcDoc::text(self.syn.ty.to_string())
+        match &self.syn.ty {
+            // Escape the string the way the lexer expects, so that the output reads back in
+            TokenType::StringLiteral(s) => RcDoc::text(format!(
+                "\"{}\"",
+                // Backslashes go first, so the ones added below aren't escaped again
+                s.replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n")
+                    .replace('\r', "\\r")
+                    .replace('\t', "\\t")
+            )),
+            other => RcDoc::text(other.to_string()),
+        }
     }
 }
 
@@ -2354,4 +2368,15 @@ mod pretty_print_tests {
 
         assert!(true)
     }
+
+    #[test]
+    fn pretty_strings_read_back() {
+        let expression = r#"(displayln "quote \" backslash \\ newline \n return \r tab \t")"#;
+        let parsed_expr = parse(expression);
+
+        let output = parsed_expr.to_pretty(80);
+
+        assert_eq!(output, expression);
+        assert_eq!(parse(&output), parsed_expr);
+    }
 }
@@ -0,0 +1,500 @@
+//! Source formatter for Steel code.
+//!
+//! The formatter works on the token stream rather than the `ExprKind` AST, so that comments,
+//! blank lines and the exact spelling of every atom survive. Layout follows the usual lisp
+//! conventions:
+//!
+//! * Anything that fits on the line stays on one line.
+//! * Body forms like `define`, `let` and `lambda` keep their distinguished arguments next to
+//!   the head, and indent their body by `indent` spaces.
+//! * Other calls align their arguments under the first argument.
+//! * Lists that don't start with a symbol, like `let` bindings or `cond` clauses, align their
+//!   elements under the first element.
+//!
+//! Formatting is idempotent - formatting the output again doesn't change it.
+
+use std::collections::HashMap;
+
+use pretty::RcDoc;
+
+use crate::{
+    parser::{lexer::TokenStream, span::Span, tokens::TokenType},
+    rvals::Result,
+};
+
+type Doc = RcDoc<'static, ()>;
+
+// Forms with a body, and how many distinguished arguments come before the body
+const BODY_FORMS: &[(&str, usize)] = &[
+    ("begin", 0),
+    ("case", 1),
+    ("cond", 0),
+    ("define", 1),
+    ("define-syntax", 1),
+    ("define-values", 1),
+    ("define/contract", 1),
+    ("do", 2),
+    ("fn", 1),
+    ("lambda", 1),
+    ("let", 1),
+    ("let*", 1),
+    ("let-values", 1),
+    ("letrec", 1),
+    ("letrec*", 1),
+    ("match", 1),
+    ("module", 1),
+    ("parameterize", 1),
+    ("struct", 1),
+    ("syntax-rules", 1),
+    ("test-module", 1),
+    ("unless", 1),
+    ("when", 1),
+    ("while", 1),
+    ("with-handler", 1),
+    ("λ", 1),
+    ("#%plain-lambda", 1),
+];
+
+/// Options for [`format_source`]
+#[derive(Debug, Clone)]
+pub struct FormatConfig {
+    /// The line width to try to stay under
+    pub width: usize,
+    /// How far the bodies of body forms are indented
+    pub indent: usize,
+    body_forms: HashMap<String, usize>,
+}
+
+impl Default for FormatConfig {
+    fn default() -> Self {
+        FormatConfig {
+            width: 80,
+            indent: 2,
+            body_forms: BODY_FORMS
+                .iter()
+                .map(|(name, distinguished)| (name.to_string(), *distinguished))
+                .collect(),
+        }
+    }
+}
+
+impl FormatConfig {
+    /// Formats forms headed by `name` (usually a user defined macro) like `define` or `let`:
+    /// the first `distinguished` arguments stay next to the head, and the rest are indented
+    /// as a body.
+    pub fn with_body_form(mut self, name: impl Into<String>, distinguished: usize) -> Self {
+        self.body_forms.insert(name.into(), distinguished);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Atom(String),
+    // Quotes and friends, which are attached to the datum that follows them
+    Prefixed(String, Box<Node>),
+    List {
+        open: String,
+        close: String,
+        items: Vec<Item>,
+    },
+    Comment(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Item {
+    node: Node,
+    // There was at least one empty line before this item in the source
+    blank_line_before: bool,
+    // A comment that was on the same line as whatever came before it
+    trailing: bool,
+}
+
+impl Item {
+    fn is_comment(&self) -> bool {
+        matches!(self.node, Node::Comment(_))
+    }
+}
+
+/// Formats Steel source code. Fails if the source can't be read, for example because of
+/// unbalanced parentheses.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate steel;
+/// # use steel::parser::formatter::{format_source, FormatConfig};
+/// let formatted = format_source("(define (square x)   (* x x))", &FormatConfig::default());
+/// assert_eq!(formatted.unwrap(), "(define (square x) (* x x))\n");
+/// ```
+pub fn format_source(source: &str, config: &FormatConfig) -> Result<String> {
+    let items = TreeBuilder::new(source).build()?;
+
+    let mut output = Vec::new();
+    top_level_doc(&items, config)
+        .render(config.width, &mut output)
+        .unwrap();
+
+    let mut output = String::from_utf8(output).unwrap();
+
+    if !output.is_empty() {
+        output.push('\n');
+    }
+
+    Ok(output)
+}
+
+struct TreeBuilder<'a> {
+    source: &'a str,
+    tokens: std::iter::Peekable<TokenStream<'a>>,
+    // Where the previous token ended
+    previous_end: usize,
+}
+
+impl<'a> TreeBuilder<'a> {
+    fn new(source: &'a str) -> Self {
+        TreeBuilder {
+            source,
+            tokens: TokenStream::new(source, false, None).peekable(),
+            previous_end: 0,
+        }
+    }
+
+    fn build(mut self) -> Result<Vec<Item>> {
+        self.items(None)
+    }
+
+    // Reads items up to the closing paren, or to the end of the input at the top level
+    fn items(&mut self, open: Option<Span>) -> Result<Vec<Item>> {
+        let mut items = Vec::new();
+
+        loop {
+            let token = match self.tokens.peek() {
+                Some(token) => token.clone(),
+                None => match open {
+                    Some(span) => stop!(BadSyntax => "unclosed parenthesis"; span),
+                    None => return Ok(items),
+                },
+            };
+
+            if token.ty == TokenType::CloseParen {
+                if open.is_none() {
+                    stop!(BadSyntax => "unexpected closing parenthesis"; token.span);
+                }
+
+                return Ok(items);
+            }
+
+            let gap = &self.source[self.previous_end..token.span.start];
+            let at_start = items.is_empty() && open.is_none();
+
+            let blank_line_before = !at_start && gap.matches('\n').count() > 1;
+            let trailing = !at_start && !gap.contains('\n');
+
+            let node = self.node()?;
+
+            items.push(Item {
+                trailing: trailing && matches!(node, Node::Comment(_)),
+                blank_line_before,
+                node,
+            });
+        }
+    }
+
+    fn node(&mut self) -> Result<Node> {
+        let token = self.tokens.next().unwrap();
+        self.previous_end = token.span.end;
+
+        match token.ty {
+            TokenType::OpenParen => {
+                let items = self.items(Some(token.span))?;
+                let close = self.tokens.next().unwrap();
+                self.previous_end = close.span.end;
+
+                Ok(Node::List {
+                    open: token.source.to_string(),
+                    close: close.source.to_string(),
+                    items,
+                })
+            }
+            TokenType::Comment => Ok(Node::Comment(token.source.trim_end().to_string())),
+            TokenType::Error => {
+                stop!(BadSyntax => format!("unable to read token: {}", token.source); token.span)
+            }
+            TokenType::QuoteTick
+            | TokenType::QuasiQuote
+            | TokenType::Unquote
+            | TokenType::UnquoteSplice
+            | TokenType::QuoteSyntax
+            | TokenType::QuasiQuoteSyntax
+            | TokenType::UnquoteSyntax
+            | TokenType::UnquoteSpliceSyntax => self.prefixed(token.source, token.span),
+            // Vector literals, `#(...)`
+            TokenType::Identifier("#")
+                if matches!(
+                    self.tokens.peek(),
+                    Some(next) if next.ty == TokenType::OpenParen && next.span.start == token.span.end
+                ) =>
+            {
+                self.prefixed(token.source, token.span)
+            }
+            _ => Ok(Node::Atom(token.source.to_string())),
+        }
+    }
+
+    fn prefixed(&mut self, prefix: &str, span: Span) -> Result<Node> {
+        match self.tokens.peek() {
+            Some(next) if next.ty != TokenType::CloseParen && next.ty != TokenType::Comment => {
+                Ok(Node::Prefixed(prefix.to_string(), Box::new(self.node()?)))
+            }
+            // The prefix applies to something after the comment, which moves with it
+            Some(next) if next.ty == TokenType::Comment => Ok(Node::Atom(prefix.to_string())),
+            _ => stop!(BadSyntax => format!("expected an expression after {prefix}"); span),
+        }
+    }
+}
+
+fn top_level_doc(items: &[Item], config: &FormatConfig) -> Doc {
+    let mut doc = RcDoc::nil();
+
+    for (index, item) in items.iter().enumerate() {
+        if index > 0 {
+            doc = doc.append(separator(item, true));
+        }
+
+        doc = doc.append(node_doc(&item.node, config));
+    }
+
+    doc
+}
+
+// Goes between two items in a list. A comment runs to the end of the line, so whatever comes
+// after it always starts a new line - callers force a break whenever a list has comments.
+fn separator(item: &Item, forced_break: bool) -> Doc {
+    if item.trailing {
+        RcDoc::space()
+    } else if item.blank_line_before {
+        // Written as text so the empty line doesn't pick up indentation
+        RcDoc::text("\n").append(RcDoc::hardline())
+    } else if forced_break {
+        RcDoc::hardline()
+    } else {
+        RcDoc::line()
+    }
+}
+
+fn join(items: &[Item], forced_break: bool, config: &FormatConfig) -> Doc {
+    let mut doc = RcDoc::nil();
+    let mut items = items.iter().enumerate().peekable();
+
+    while let Some((index, item)) = items.next() {
+        if index > 0 {
+            doc = doc.append(separator(item, forced_break));
+        }
+
+        doc = doc.append(node_doc(&item.node, config));
+
+        // Keyword arguments stay on the same line as their value
+        if matches!(&item.node, Node::Atom(keyword) if keyword.starts_with("#:")) {
+            if let Some((_, value)) = items.next_if(|(_, x)| !x.is_comment()) {
+                doc = doc
+                    .append(RcDoc::space())
+                    .append(node_doc(&value.node, config));
+            }
+        }
+    }
+
+    doc
+}
+
+fn node_doc(node: &Node, config: &FormatConfig) -> Doc {
+    match node {
+        Node::Atom(text) | Node::Comment(text) => RcDoc::text(text.clone()),
+        Node::Prefixed(prefix, node) => RcDoc::text(prefix.clone()).append(node_doc(node, config)),
+        Node::List { open, close, items } => list_doc(open, close, items, config),
+    }
+}
+
+fn list_doc(open: &str, close: &str, items: &[Item], config: &FormatConfig) -> Doc {
+    let open = RcDoc::text(open.to_string());
+    let close = RcDoc::text(close.to_string());
+
+    // A comment runs to the end of the line, so the closing paren has to go on the next one,
+    // lined up with the rest of the list. The paren goes inside the same nesting as the
+    // hardline, since a hardline takes the indentation of whatever follows it
+    let end = match items.last() {
+        Some(last) if last.is_comment() => RcDoc::hardline(),
+        _ => RcDoc::nil(),
+    };
+
+    let forced_break = items.iter().any(|x| x.is_comment() || x.blank_line_before);
+
+    let head = match items.first().map(|x| &x.node) {
+        // A comment on its own line right after the head can't be pulled up next to it
+        Some(Node::Atom(head)) if !matches!(items.get(1), Some(item) if item.is_comment() && !item.trailing) => {
+            head
+        }
+        _ => {
+            return open
+                .append(align(
+                    join(items, forced_break, config).append(end).append(close),
+                ))
+                .group()
+        }
+    };
+
+    let rest = &items[1..];
+    let head_doc = RcDoc::text(head.clone());
+
+    if let Some(distinguished) = distinguished_arguments(head, rest, config) {
+        let distinguished = distinguished.min(rest.len());
+        let (arguments, body) = rest.split_at(distinguished);
+
+        let mut doc = open.append(head_doc);
+
+        if !arguments.is_empty() {
+            doc = doc
+                .append(RcDoc::space())
+                .append(align(join(arguments, forced_break, config)).group());
+        }
+
+        let mut body_doc = RcDoc::nil();
+
+        for item in body {
+            body_doc = body_doc
+                .append(separator(item, forced_break))
+                .append(node_doc(&item.node, config));
+        }
+
+        return doc
+            .append(
+                body_doc
+                    .append(end)
+                    .append(close)
+                    .nest(config.indent as isize),
+            )
+            .group();
+    }
+
+    if rest.is_empty() {
+        return open.append(head_doc).append(close);
+    }
+
+    open.append(head_doc)
+        .append(RcDoc::space())
+        .append(align(
+            join(rest, forced_break, config).append(end).append(close),
+        ))
+        .group()
+}
+
+fn distinguished_arguments(head: &str, rest: &[Item], config: &FormatConfig) -> Option<usize> {
+    let distinguished = *config.body_forms.get(head)?;
+
+    // Named let, `(let loop ([x 10]) ...)`
+    match (head, rest.first().map(|x| &x.node)) {
+        ("let", Some(Node::Atom(_))) => Some(distinguished + 1),
+        _ => Some(distinguished),
+    }
+}
+
+// Sets the indentation of any line breaks in `doc` to the column it starts at
+fn align(doc: Doc) -> Doc {
+    RcDoc::column(move |column| {
+        let doc = doc.clone();
+        RcDoc::nesting(move |nesting| doc.clone().nest(column as isize - nesting as isize))
+    })
+}
+
+#[cfg(test)]
+mod formatter_tests {
+    use super::*;
+
+    fn format(source: &str) -> String {
+        format_source(source, &FormatConfig::default()).unwrap()
+    }
+
+    // Everything but the whitespace between tokens
+    fn tokens(source: &str) -> Vec<String> {
+        TokenStream::new(source, false, None)
+            .map(|x| x.source.trim_end().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn body_forms_indent_their_body() {
+        let source = "(define (fib n) (if (<= n 2) 1 (+ (fib (- n 1)) (fib (- n 2)) (fib (- n 3)) (fib (- n 4)) 10)))";
+
+        assert_eq!(
+            format(source),
+            "(define (fib n)
+  (if (<= n 2)
+      1
+      (+ (fib (- n 1)) (fib (- n 2)) (fib (- n 3)) (fib (- n 4)) 10)))
+"
+        );
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_kept() {
+        let source = "
+;; Adds one
+(define (add1 x)   ; trailing
+  ; own line
+  (+ x 1))
+
+
+
+(define y 10)
+";
+
+        assert_eq!(
+            format(source),
+            ";; Adds one
+(define (add1 x) ; trailing
+  ; own line
+  (+ x 1))
+
+(define y 10)
+"
+        );
+    }
+
+    #[test]
+    fn configured_macros_format_as_body_forms() {
+        let source =
+            "(my-for (x (range 0 100000)) (displayln x) (displayln (* x x x x x x x x x x x x)))";
+
+        let config = FormatConfig::default().with_body_form("my-for", 1);
+
+        assert_eq!(
+            format_source(source, &config).unwrap(),
+            "(my-for (x (range 0 100000))
+  (displayln x)
+  (displayln (* x x x x x x x x x x x x)))
+"
+        );
+    }
+
+    #[test]
+    fn unbalanced_parens_are_an_error() {
+        assert!(format_source("(define x", &FormatConfig::default()).is_err());
+        assert!(format_source("(define x))", &FormatConfig::default()).is_err());
+    }
+
+    #[test]
+    fn formatting_the_standard_library_is_idempotent() {
+        for source in [
+            crate::stdlib::PRELUDE,
+            crate::stdlib::CONTRACTS,
+            crate::stdlib::DISPLAY,
+            crate::stdlib::KERNEL,
+            crate::stdlib::COMPILER,
+        ] {
+            let formatted = format(source);
+
+            assert_eq!(tokens(source), tokens(&formatted));
+            assert_eq!(formatted, format(&formatted));
+        }
+    }
+}
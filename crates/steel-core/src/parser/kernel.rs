@@ -1,6 +1,6 @@
 use std::{
     collections::HashSet,
-    sync::{Arc, RwLock},
+    sync::{atomic::AtomicBool, Arc, RwLock},
 };
 
 use steel_parser::tokens::TokenType;
@@ -49,7 +49,10 @@ pub struct Kernel {
     // macros: HashSet<InternedString>,
     transformers: Transformers,
     constants: HashSet<InternedString>,
-    engine: Box<Engine>,
+    // Built on first use for kernels that only know the names of their transformers,
+    // see `Kernel::deferred`
+    engine: Option<Box<Engine>>,
+    interrupt: Option<Arc<AtomicBool>>,
 }
 
 impl Default for Kernel {
@@ -60,12 +63,42 @@ impl Default for Kernel {
 
 impl Kernel {
     pub fn new() -> Self {
-        let mut engine = fresh_kernel_image();
-
         let transformers = Transformers {
             set: Arc::new(RwLock::new(HashSet::default())),
         };
 
+        let engine = Self::build_engine(&transformers);
+
+        // let mut macros = HashSet::new();
+        // macros.insert("%better-lambda%".to_string());
+        // macros.insert(*STRUCT_KEYWORD);
+        // macros.insert(*DEFINE_VALUES);
+
+        Kernel {
+            // macros,
+            transformers,
+            constants: HashSet::new(),
+            engine: Some(Box::new(engine)),
+            interrupt: None,
+        }
+    }
+
+    /// A kernel which already knows the names of its transformers, and only boots its
+    /// engine once one of them has to be expanded. Used when restoring snapshots.
+    pub(crate) fn deferred(transformers: impl IntoIterator<Item = InternedString>) -> Self {
+        Kernel {
+            transformers: Transformers {
+                set: Arc::new(RwLock::new(transformers.into_iter().collect())),
+            },
+            constants: HashSet::new(),
+            engine: None,
+            interrupt: None,
+        }
+    }
+
+    fn build_engine(transformers: &Transformers) -> Engine {
+        let mut engine = fresh_kernel_image();
+
         let embedded_transformer_object = transformers.clone();
         engine.register_fn("register-macro-transformer!", move |name: String| {
             embedded_transformer_object
@@ -91,17 +124,40 @@ impl Kernel {
         // Run the script for building the core interface for structs
         engine.compile_and_run_raw_program(KERNEL).unwrap();
 
-        // let mut macros = HashSet::new();
-        // macros.insert("%better-lambda%".to_string());
-        // macros.insert(*STRUCT_KEYWORD);
-        // macros.insert(*DEFINE_VALUES);
+        engine
+    }
 
-        Kernel {
-            // macros,
-            transformers,
-            constants: HashSet::new(),
-            engine: Box::new(engine),
+    fn engine(&mut self) -> &mut Engine {
+        let transformers = &self.transformers;
+        let interrupt = &self.interrupt;
+
+        self.engine.get_or_insert_with(|| {
+            let mut engine = Self::build_engine(transformers);
+
+            if let Some(interrupt) = interrupt {
+                engine.set_interrupt(Arc::clone(interrupt));
+            }
+
+            Box::new(engine)
+        })
+    }
+
+    pub(crate) fn transformers(&self) -> Vec<InternedString> {
+        self.transformers
+            .set
+            .read()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    pub(crate) fn set_interrupt(&mut self, interrupt: Arc<AtomicBool>) {
+        if let Some(engine) = self.engine.as_mut() {
+            engine.set_interrupt(Arc::clone(&interrupt));
         }
+
+        self.interrupt = Some(interrupt);
     }
 
     pub fn is_constant(&self, ident: &InternedString) -> bool {
@@ -144,7 +200,7 @@ impl Kernel {
             return Ok(());
         }
 
-        self.engine.run_raw_program_from_exprs(subset)?;
+        self.engine().run_raw_program_from_exprs(subset)?;
 
         fn set(var: ExprKind, expr: ExprKind) -> ExprKind {
             ExprKind::Set(Box::new(Set::new(
@@ -174,7 +230,7 @@ impl Kernel {
             set_idents.push(set(set_ident, memoize_expr));
         }
 
-        self.engine.run_raw_program_from_exprs(set_idents)?;
+        self.engine().run_raw_program_from_exprs(set_idents)?;
 
         log::debug!("Constant functions loaded");
 
@@ -200,9 +256,10 @@ impl Kernel {
     }
 
     pub fn call_function(&mut self, ident: &InternedString, args: &[SteelVal]) -> Result<SteelVal> {
-        let function = self.engine.extract_value(ident.resolve())?;
+        let engine = self.engine();
+        let function = engine.extract_value(ident.resolve())?;
 
-        self.engine.call_function_with_args(function, args.to_vec())
+        engine.call_function_with_args(function, args.to_vec())
     }
 
     pub(crate) fn expand_syntax_object(
@@ -215,7 +272,8 @@ impl Kernel {
         let syntax_objects =
             super::tryfrom_visitor::SyntaxObjectFromExprKind::try_from_expr_kind(expr.clone())?;
 
-        let function = self.engine.extract_value(ident.resolve())?;
+        let engine = self.engine();
+        let function = engine.extract_value(ident.resolve())?;
 
         // if let SteelVal::ListV(list) = syntax_objects {
         // let mut iter = list.into_iter();
@@ -226,8 +284,7 @@ impl Kernel {
 
         // log::info!(target: "kernel", "Expanding: {:?} with arguments: {:?}", ident, arguments);
 
-        let result = self
-            .engine
+        let result = engine
             .call_function_with_args(function, vec![syntax_objects])
             .map_err(|x| x.set_span(span))?;
 
@@ -2,6 +2,7 @@ pub mod ast;
 pub mod builder;
 pub mod expand_visitor;
 pub mod expander;
+pub mod formatter;
 pub mod interner;
 pub mod kernel;
 pub mod lexer;
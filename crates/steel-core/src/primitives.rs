@@ -1,3 +1,4 @@
+pub mod bytevectors;
 pub mod contracts;
 mod control;
 mod fs;
@@ -7,7 +8,7 @@ mod io;
 pub mod lists;
 pub mod meta_ops;
 pub mod nums;
-mod ports;
+pub(crate) mod ports;
 pub mod process;
 pub mod random;
 mod streams;
@@ -27,11 +28,26 @@ pub mod sqlite;
 #[cfg(feature = "blocking_requests")]
 pub mod blocking_requests;
 
+#[cfg(any(feature = "web", feature = "blocking_requests"))]
+pub mod http;
+
 #[cfg(feature = "colors")]
 pub mod colors;
 
+#[cfg(feature = "watch")]
+pub mod watch;
+
+#[cfg(feature = "toml")]
+pub mod toml;
+
+#[cfg(feature = "yaml")]
+pub mod yaml;
+
+#[cfg(feature = "csv")]
+pub mod csv;
+
 pub use control::ControlOperations;
-pub use fs::FsFunctions;
+pub use fs::{fs_module, path_module, FsFunctions};
 use im_lists::list::List;
 pub use io::IoFunctions;
 pub use meta_ops::MetaOperations;
@@ -432,6 +448,17 @@ impl<'a> PrimitiveAsRef<'a> for &'a Gc<RefCell<Vec<SteelVal>>> {
     }
 }
 
+impl<'a> PrimitiveAsRef<'a> for &'a Gc<RefCell<Vec<u8>>> {
+    #[inline(always)]
+    fn primitive_as_ref(val: &'a SteelVal) -> crate::rvals::Result<Self> {
+        if let SteelVal::ByteVector(p) = val {
+            Ok(p)
+        } else {
+            crate::stop!(ConversionError => format!("Cannot convert steel value: {} to steel bytevector", val))
+        }
+    }
+}
+
 impl<'a> PrimitiveAsRef<'a> for &'a Gc<SteelPort> {
     #[inline(always)]
     fn primitive_as_ref(val: &'a SteelVal) -> crate::rvals::Result<Self> {
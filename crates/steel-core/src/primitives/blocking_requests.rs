@@ -1,78 +1,799 @@
-// use reqwest::{
-//     blocking::{get, Client, RequestBuilder, Response},
-//     StatusCode,
-// };
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use crate::{
-    rvals::Custom,
+    gc::Gc,
+    primitives::bytevectors::bytes_to_steelval,
+    primitives::http::{
+        register_http_error_functions, register_status_code_functions, HttpError, HttpErrorKind,
+        HttpStatus,
+    },
+    rvals::{Custom, FromSteelVal},
     steel_vm::{builtin::BuiltInModule, register_fn::RegisterFn},
+    SteelVal,
 };
 
-use ureq::{Request, Response};
+use cookie_store::CookieStore;
+use serde_json::Value;
+use ureq::{Agent, AgentBuilder, Request};
+use url::Url;
 
-impl Custom for Request {}
-impl Custom for Response {}
-impl Custom for ureq::Error {}
+// ureq is built without its own cookie support, so the jar is kept here and filled in from the
+// responses. Cookies set by the responses of redirects that ureq follows on its own are missed.
+#[derive(Clone)]
+struct CookieJar {
+    store: Arc<Mutex<CookieStore>>,
+}
+
+impl Custom for CookieJar {}
+
+impl CookieJar {
+    fn header(&self, url: &Url) -> Option<String> {
+        let store = self.store.lock().unwrap();
+
+        let cookies = store
+            .get_request_values(url)
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>();
+
+        if cookies.is_empty() {
+            None
+        } else {
+            Some(cookies.join("; "))
+        }
+    }
+
+    fn store_response(&self, response: &ureq::Response) {
+        let Ok(url) = Url::parse(response.get_url()) else {
+            return;
+        };
+
+        let mut store = self.store.lock().unwrap();
+
+        for cookie in response.all("Set-Cookie") {
+            // Cookies the store refuses, like ones for another domain, are dropped
+            let _ = store.parse(cookie, &url);
+        }
+    }
+}
 
-fn get(url: String) -> Request {
-    ureq::get(&url)
+#[derive(Clone)]
+struct Client {
+    agent: Agent,
+    // Sent with every request made through the client
+    headers: Vec<(String, String)>,
+    cookies: Option<CookieJar>,
 }
 
-impl Custom for std::io::Error {}
+impl Custom for Client {}
 
-struct SteelResponse {
-    response: Option<ureq::Response>,
+impl Client {
+    fn new() -> Self {
+        Client {
+            agent: Agent::new(),
+            headers: Vec::new(),
+            cookies: None,
+        }
+    }
+
+    fn request(&self, method: &str, url: &str) -> SteelRequestBuilder {
+        let request = self
+            .headers
+            .iter()
+            .fold(self.agent.request(method, url), |request, (key, value)| {
+                request.set(key, value)
+            });
+
+        SteelRequestBuilder {
+            cookies: self.cookies.clone(),
+            ..request.into()
+        }
+    }
 }
 
-impl SteelResponse {
-    fn into_text(&mut self) -> crate::rvals::Result<String> {
-        let resp = self.response.take();
+#[derive(Clone)]
+enum RequestBody {
+    Empty,
+    Text(String),
+    Bytes(Vec<u8>),
+    Json(Value),
+    Form(Vec<(String, String)>),
+    // Files are streamed as the body, so they're only opened once the request is sent
+    File(PathBuf),
+}
 
-        if let Some(resp) = resp {
-            Ok(resp.into_string()?)
-        } else {
-            crate::stop!(Generic => "Response already consumed, can only consume the response one time!")
+// Parts of a multipart body, kept as data until the request is sent
+#[derive(Debug, Clone)]
+enum MultipartPart {
+    Text(String, String),
+    Bytes(String, Vec<u8>, Option<String>),
+    File(String, PathBuf),
+}
+
+#[derive(Clone)]
+struct SteelRequestBuilder {
+    request: Request,
+    body: RequestBody,
+    multipart: Vec<MultipartPart>,
+    cookies: Option<CookieJar>,
+}
+
+impl Custom for SteelRequestBuilder {}
+
+fn request_error(message: String) -> HttpError {
+    HttpError::new(HttpErrorKind::Request, message)
+}
+
+fn header_pairs(headers: &SteelVal) -> Result<Vec<(String, String)>, HttpError> {
+    let SteelVal::HashMapV(headers) = headers else {
+        return Err(request_error(format!(
+            "expected a hash of headers, found: {headers}"
+        )));
+    };
+
+    headers
+        .iter()
+        .map(|(key, value)| match (key, value) {
+            (SteelVal::StringV(key) | SteelVal::SymbolV(key), SteelVal::StringV(value)) => {
+                Ok((key.to_string(), value.to_string()))
+            }
+            _ => Err(request_error(format!(
+                "headers must map names to strings, found: {key} => {value}"
+            ))),
+        })
+        .collect()
+}
+
+// Flattens the value into pairs the same way the non blocking client encodes queries and
+// forms: either an object, or a list of key value pairs
+fn value_pairs(value: &Value) -> Result<Vec<(String, String)>, HttpError> {
+    fn scalar(value: &Value) -> Result<String, HttpError> {
+        match value {
+            Value::String(s) => Ok(s.clone()),
+            Value::Number(_) | Value::Bool(_) => Ok(value.to_string()),
+            _ => Err(request_error(format!(
+                "expected a string, number or boolean, found: {value}"
+            ))),
+        }
+    }
+
+    match value {
+        Value::Object(map) => map
+            .iter()
+            .map(|(key, value)| Ok((key.clone(), scalar(value)?)))
+            .collect(),
+        Value::Array(pairs) => pairs
+            .iter()
+            .map(|pair| match pair.as_array().map(|x| x.as_slice()) {
+                Some([key, value]) => Ok((scalar(key)?, scalar(value)?)),
+                _ => Err(request_error(format!(
+                    "expected a key value pair, found: {pair}"
+                ))),
+            })
+            .collect(),
+        _ => Err(request_error(format!(
+            "expected an object or a list of pairs, found: {value}"
+        ))),
+    }
+}
+
+fn multipart_body(boundary: &str, parts: Vec<MultipartPart>) -> Result<Vec<u8>, HttpError> {
+    let mut body = Vec::new();
+
+    for part in parts {
+        let (name, file_name, bytes) = match part {
+            MultipartPart::Text(name, value) => (name, None, value.into_bytes()),
+            MultipartPart::Bytes(name, bytes, file_name) => (name, file_name, bytes),
+            MultipartPart::File(name, path) => {
+                let file_name = path.file_name().map(|x| x.to_string_lossy().into_owned());
+                let bytes = std::fs::read(&path)?;
+                (name, file_name, bytes)
+            }
+        };
+
+        body.extend_from_slice(
+            format!("--{boundary}\r\nContent-Disposition: form-data; name=\"{name}\"").as_bytes(),
+        );
+
+        if let Some(file_name) = file_name {
+            body.extend_from_slice(
+                format!("; filename=\"{file_name}\"\r\nContent-Type: application/octet-stream")
+                    .as_bytes(),
+            );
+        }
+
+        body.extend_from_slice(b"\r\n\r\n");
+        body.extend_from_slice(&bytes);
+        body.extend_from_slice(b"\r\n");
+    }
+
+    body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+
+    Ok(body)
+}
+
+impl SteelRequestBuilder {
+    fn map(self, f: impl FnOnce(Request) -> Request) -> Self {
+        Self {
+            request: f(self.request),
+            ..self
+        }
+    }
+
+    fn with_body(self, body: RequestBody) -> Self {
+        Self { body, ..self }
+    }
+
+    fn json(self, value: Value) -> Self {
+        self.with_body(RequestBody::Json(value))
+    }
+
+    fn query(self, value: Value) -> Result<Self, HttpError> {
+        let pairs = value_pairs(&value)?;
+
+        Ok(self.map(|x| {
+            pairs
+                .iter()
+                .fold(x, |request, (key, value)| request.query(key, value))
+        }))
+    }
+
+    fn form(self, value: Value) -> Result<Self, HttpError> {
+        Ok(self.with_body(RequestBody::Form(value_pairs(&value)?)))
+    }
+
+    fn body(self, body: SteelVal) -> Result<Self, HttpError> {
+        match body {
+            SteelVal::StringV(s) => Ok(self.with_body(RequestBody::Text(s.to_string()))),
+            SteelVal::ByteVector(b) => Ok(self.with_body(RequestBody::Bytes(b.borrow().clone()))),
+            _ => Err(request_error(format!(
+                "the body of a request must be a string or bytes, found: {body}"
+            ))),
         }
     }
 
-    fn into_json(&mut self) -> crate::rvals::Result<serde_json::Value> {
-        let resp = self.response.take();
+    fn body_file(self, path: String) -> Self {
+        self.with_body(RequestBody::File(PathBuf::from(path)))
+    }
+
+    fn multipart_text(mut self, name: String, value: String) -> Self {
+        self.multipart.push(MultipartPart::Text(name, value));
+        self
+    }
+
+    fn multipart_bytes(mut self, name: String, bytes: SteelVal, file_name: Option<String>) -> Self {
+        let bytes = match bytes {
+            SteelVal::ByteVector(b) => b.borrow().clone(),
+            SteelVal::StringV(s) => s.as_bytes().to_vec(),
+            _ => Vec::new(),
+        };
+
+        self.multipart
+            .push(MultipartPart::Bytes(name, bytes, file_name));
+        self
+    }
+
+    fn multipart_file(mut self, name: String, path: String) -> Self {
+        self.multipart
+            .push(MultipartPart::File(name, PathBuf::from(path)));
+        self
+    }
+
+    fn timeout(self, millis: usize) -> Self {
+        self.map(|x| x.timeout(Duration::from_millis(millis as u64)))
+    }
+
+    fn header(self, key: String, value: String) -> Self {
+        self.map(|x| x.set(&key, &value))
+    }
+
+    fn headers(self, headers: SteelVal) -> Result<Self, HttpError> {
+        let headers = header_pairs(&headers)?;
+
+        Ok(self.map(|x| {
+            headers
+                .iter()
+                .fold(x, |request, (key, value)| request.set(key, value))
+        }))
+    }
+
+    fn basic_auth(self, username: String, password: Option<String>) -> Self {
+        let credentials = format!("{username}:{}", password.unwrap_or_default());
+        self.header(
+            "Authorization".to_string(),
+            format!("Basic {}", base64::encode(credentials)),
+        )
+    }
+
+    fn bearer_auth(self, token: String) -> Self {
+        self.header("Authorization".to_string(), format!("Bearer {token}"))
+    }
+
+    fn send(self) -> Result<SteelResponse, HttpError> {
+        let mut request = self.request;
+
+        if let Some(jar) = &self.cookies {
+            let url = request
+                .request_url()
+                .map_err(|e| request_error(e.to_string()))?;
 
-        if let Some(resp) = resp {
-            Ok(resp.into_json()?)
+            if let Some(cookies) = jar.header(url.as_url()) {
+                request = request.set("Cookie", &cookies);
+            }
+        }
+
+        let result = if !self.multipart.is_empty() {
+            let boundary = format!(
+                "{:016x}{:016x}",
+                rand::random::<u64>(),
+                rand::random::<u64>()
+            );
+            let body = multipart_body(&boundary, self.multipart)?;
 
-            // fn json(&mut self) -> Option<reqwest::Result<Value>> {
-            //     self.response.take().map(|x| x.json::<Value>())
-            // }
+            request
+                .set(
+                    "Content-Type",
+                    &format!("multipart/form-data; boundary={boundary}"),
+                )
+                .send_bytes(&body)
         } else {
-            crate::stop!(Generic => "Response already consumed, can only consume the response one time!")
+            match self.body {
+                RequestBody::Empty => request.call(),
+                RequestBody::Text(text) => request.send_string(&text),
+                RequestBody::Bytes(bytes) => request.send_bytes(&bytes),
+                RequestBody::Json(value) => request.send_json(value),
+                RequestBody::Form(pairs) => request.send_form(
+                    &pairs
+                        .iter()
+                        .map(|(k, v)| (k.as_str(), v.as_str()))
+                        .collect::<Vec<_>>(),
+                ),
+                RequestBody::File(path) => request.send(std::fs::File::open(path)?),
+            }
+        };
+
+        let response = match result {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(ureq::Error::Transport(transport)) => return Err(transport.into()),
+        };
+
+        if let Some(jar) = &self.cookies {
+            jar.store_response(&response);
+        }
+
+        Ok(response.into())
+    }
+}
+
+impl From<Request> for SteelRequestBuilder {
+    fn from(request: Request) -> Self {
+        SteelRequestBuilder {
+            request,
+            body: RequestBody::Empty,
+            multipart: Vec::new(),
+            cookies: None,
         }
     }
 }
 
+// Builds a client from a hash of options, the same ones accepted by the non blocking client:
+//
+// * 'timeout-ms - for the whole request
+// * 'connect-timeout-ms
+// * 'max-redirects - 0 to not follow redirects at all
+// * 'cookies - #true to keep cookies between requests, or a cookie jar to keep them in
+// * 'user-agent
+// * 'headers - a hash of headers sent with every request
+fn client_with_options(options: SteelVal) -> Result<Client, HttpError> {
+    let SteelVal::HashMapV(options) = &options else {
+        return Err(request_error(format!(
+            "expected a hash of options, found: {options}"
+        )));
+    };
+
+    let mut builder = AgentBuilder::new();
+    let mut headers = Vec::new();
+    let mut cookies = None;
+
+    for (key, value) in options.iter() {
+        let SteelVal::SymbolV(key) = key else {
+            return Err(request_error(format!("unknown client option: {key}")));
+        };
+
+        builder = match (key.as_str(), value) {
+            ("timeout-ms", SteelVal::IntV(millis)) if *millis >= 0 => {
+                builder.timeout(Duration::from_millis(*millis as u64))
+            }
+            ("connect-timeout-ms", SteelVal::IntV(millis)) if *millis >= 0 => {
+                builder.timeout_connect(Duration::from_millis(*millis as u64))
+            }
+            ("max-redirects", SteelVal::IntV(max)) if *max >= 0 => builder.redirects(*max as u32),
+            ("cookies", SteelVal::BoolV(enabled)) => {
+                cookies = enabled.then(cookie_jar);
+                builder
+            }
+            ("cookies", SteelVal::Custom(_)) => match CookieJar::from_steelval(value) {
+                Ok(jar) => {
+                    cookies = Some(jar);
+                    builder
+                }
+                Err(_) => {
+                    return Err(request_error(format!(
+                        "expected a cookie jar for 'cookies, found: {value}"
+                    )))
+                }
+            },
+            ("user-agent", SteelVal::StringV(agent)) => builder.user_agent(agent.as_str()),
+            ("headers", value) => {
+                headers = header_pairs(value)?;
+                builder
+            }
+            _ => {
+                return Err(request_error(format!(
+                    "invalid client option: {key} => {value}"
+                )))
+            }
+        };
+    }
+
+    Ok(Client {
+        agent: builder.build(),
+        headers,
+        cookies,
+    })
+}
+
+fn request_wrapper(
+    client: &Client,
+    method: String,
+    url: String,
+) -> Result<SteelRequestBuilder, HttpError> {
+    if method.is_empty() || !method.bytes().all(|x| x.is_ascii_alphabetic()) {
+        return Err(request_error(format!("invalid method: {method}")));
+    }
+
+    Ok(client.request(&method.to_ascii_uppercase(), &url))
+}
+
+fn post_wrapper(client: &Client, url: String) -> SteelRequestBuilder {
+    client.request("POST", &url)
+}
+
+fn get_wrapper(client: &Client, url: String) -> SteelRequestBuilder {
+    client.request("GET", &url)
+}
+
+fn put_wrapper(client: &Client, url: String) -> SteelRequestBuilder {
+    client.request("PUT", &url)
+}
+
+fn patch_wrapper(client: &Client, url: String) -> SteelRequestBuilder {
+    client.request("PATCH", &url)
+}
+
+fn delete_wrapper(client: &Client, url: String) -> SteelRequestBuilder {
+    client.request("DELETE", &url)
+}
+
+fn head_wrapper(client: &Client, url: String) -> SteelRequestBuilder {
+    client.request("HEAD", &url)
+}
+
+fn basic_get_wrapper(url: String) -> Result<SteelResponse, HttpError> {
+    SteelRequestBuilder::from(ureq::get(&url)).send()
+}
+
+fn parse_url(url: &str) -> Result<Url, HttpError> {
+    Url::parse(url).map_err(|e| request_error(format!("invalid url {url}: {e}")))
+}
+
+fn cookie_jar() -> CookieJar {
+    CookieJar {
+        store: Arc::new(Mutex::new(CookieStore::default())),
+    }
+}
+
+// Adds a cookie, as it would appear in a `Set-Cookie` header, for the url
+fn cookie_jar_add(jar: &CookieJar, cookie: String, url: String) -> Result<(), HttpError> {
+    let url = parse_url(&url)?;
+
+    jar.store
+        .lock()
+        .unwrap()
+        .parse(&cookie, &url)
+        .map_err(|e| request_error(format!("invalid cookie {cookie}: {e}")))?;
+
+    Ok(())
+}
+
+// The cookies that would be sent to the url, as they'd appear in a `Cookie` header
+fn cookie_jar_cookies(jar: &CookieJar, url: String) -> Result<Option<String>, HttpError> {
+    Ok(jar.header(&parse_url(&url)?))
+}
+
+struct SteelResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    url: String,
+    reader: Option<Box<dyn Read + Send + Sync>>,
+}
+
 impl From<ureq::Response> for SteelResponse {
     fn from(value: ureq::Response) -> Self {
+        let headers = value
+            .headers_names()
+            .into_iter()
+            .filter_map(|name| {
+                let header = value.header(&name)?.to_string();
+                Some((name, header))
+            })
+            .collect();
+
         SteelResponse {
-            response: Some(value),
+            status: value.status(),
+            headers,
+            url: value.get_url().to_string(),
+            reader: Some(value.into_reader()),
         }
     }
 }
 
+impl SteelResponse {
+    fn status(&self) -> HttpStatus {
+        HttpStatus(self.status)
+    }
+
+    fn status_code(&self) -> usize {
+        self.status as usize
+    }
+
+    fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    fn headers(&self) -> SteelVal {
+        SteelVal::HashMapV(Gc::new(
+            self.headers
+                .iter()
+                .map(|(key, value)| {
+                    (
+                        SteelVal::StringV(key.as_str().into()),
+                        SteelVal::StringV(value.as_str().into()),
+                    )
+                })
+                .collect(),
+        ))
+    }
+
+    fn header(&self, name: String) -> Option<String> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(&name))
+            .map(|(_, value)| value.clone())
+    }
+
+    fn error_for_status(&self) -> Result<bool, HttpError> {
+        if self.status >= 400 {
+            Err(HttpError::status(self.status, Some(self.url.clone())))
+        } else {
+            Ok(true)
+        }
+    }
+
+    fn identity(&self) -> bool {
+        true
+    }
+
+    fn take(&mut self) -> Result<Box<dyn Read + Send + Sync>, HttpError> {
+        self.reader.take().ok_or_else(HttpError::consumed)
+    }
+
+    fn bytes(&mut self) -> Result<SteelVal, HttpError> {
+        let mut buffer = Vec::new();
+        self.take()?.read_to_end(&mut buffer)?;
+        Ok(bytes_to_steelval(buffer))
+    }
+
+    fn text(&mut self) -> Result<String, HttpError> {
+        let mut buffer = String::new();
+        self.take()?.read_to_string(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn json(&mut self) -> Result<Value, HttpError> {
+        serde_json::from_reader(self.take()?)
+            .map_err(|e| HttpError::new(HttpErrorKind::Decode, e.to_string()))
+    }
+
+    // Reads the next chunk of the body, of at most `max` bytes. The chunk is empty once the
+    // whole body has been read.
+    fn read_bytes(&mut self, max: usize) -> Result<SteelVal, HttpError> {
+        let reader = match self.reader.as_mut() {
+            Some(reader) => reader,
+            None => return Ok(bytes_to_steelval(Vec::new())),
+        };
+
+        let mut buffer = vec![0; max];
+        let read = reader.read(&mut buffer)?;
+        buffer.truncate(read);
+
+        if read == 0 {
+            self.reader = None;
+        }
+
+        Ok(bytes_to_steelval(buffer))
+    }
+
+    // Streams the body into a file, returning how many bytes were written
+    fn copy_to_file(&mut self, path: String) -> Result<usize, HttpError> {
+        let mut reader = self.take()?;
+        let mut file = std::fs::File::create(path)?;
+
+        Ok(std::io::copy(&mut reader, &mut file)? as usize)
+    }
+}
+
 impl Custom for SteelResponse {}
 
 pub fn blocking_requests_module() -> BuiltInModule {
     let mut module = BuiltInModule::new("steel/web/blocking/requests".to_string());
 
     module
-        .register_fn("get", get)
+        .register_fn("request/client", Client::new)
+        .register_fn("request/client-with-options", client_with_options)
+        .register_fn("get", basic_get_wrapper)
+        .register_fn("client/request", request_wrapper)
+        .register_fn("client/post", post_wrapper)
+        .register_fn("client/get", get_wrapper)
+        .register_fn("client/put", put_wrapper)
+        .register_fn("client/patch", patch_wrapper)
+        .register_fn("client/delete", delete_wrapper)
+        .register_fn("client/head", head_wrapper)
+        .register_fn("request/json", SteelRequestBuilder::json)
+        .register_fn("request/query", SteelRequestBuilder::query)
+        .register_fn("request/form", SteelRequestBuilder::form)
+        .register_fn("request/body", SteelRequestBuilder::body)
+        .register_fn("request/body-file", SteelRequestBuilder::body_file)
         .register_fn(
-            "call",
-            |request: Request| -> Result<SteelResponse, ureq::Error> {
-                Request::call(request).map(|x| x.into())
-            },
+            "request/multipart-text",
+            SteelRequestBuilder::multipart_text,
+        )
+        .register_fn(
+            "request/multipart-bytes",
+            SteelRequestBuilder::multipart_bytes,
+        )
+        .register_fn(
+            "request/multipart-file",
+            SteelRequestBuilder::multipart_file,
         )
-        .register_fn("response->text", SteelResponse::into_text)
-        .register_fn("response->json", SteelResponse::into_json);
+        .register_fn("request/timeout", SteelRequestBuilder::timeout)
+        .register_fn("request/send", SteelRequestBuilder::send)
+        .register_fn("request/header", SteelRequestBuilder::header)
+        .register_fn("request/headers", SteelRequestBuilder::headers)
+        .register_fn("request/basic-auth", SteelRequestBuilder::basic_auth)
+        .register_fn("request/bearer-auth", SteelRequestBuilder::bearer_auth)
+        .register_fn("cookie-jar", cookie_jar)
+        .register_fn("cookie-jar/add!", cookie_jar_add)
+        .register_fn("cookie-jar/cookies", cookie_jar_cookies)
+        .register_fn("response/status", SteelResponse::status)
+        .register_fn("response/status-code", SteelResponse::status_code)
+        .register_fn("response/url", SteelResponse::url)
+        .register_fn("response/headers", SteelResponse::headers)
+        .register_fn("response/header", SteelResponse::header)
+        .register_fn("response/error-for-status", SteelResponse::error_for_status)
+        .register_type::<SteelRequestBuilder>("request/builder?")
+        .register_type::<Client>("request/client?")
+        .register_type::<CookieJar>("cookie-jar?")
+        .register_fn("response?", SteelResponse::identity)
+        .register_fn("response->json", SteelResponse::json)
+        .register_fn("response->text", SteelResponse::text)
+        .register_fn("response->bytes", SteelResponse::bytes)
+        .register_fn("response/read-bytes", SteelResponse::read_bytes)
+        .register_fn("response/copy-to-file!", SteelResponse::copy_to_file);
+
+    register_http_error_functions(&mut module);
+    register_status_code_functions(&mut module);
 
     module
 }
+
+#[cfg(all(test, feature = "web"))]
+mod blocking_requests_tests {
+    use super::*;
+    use crate::primitives::http::http_tests::{options, stub_server};
+    use crate::rvals::IntoSteelVal;
+    use crate::tests::{test_directory, write_file};
+
+    #[test]
+    fn requests_against_stub_server() {
+        let (address, handle) = stub_server();
+        let url = |path: &str| format!("{address}{path}");
+
+        let jar = cookie_jar();
+        let client = client_with_options(options(vec![
+            ("max-redirects", SteelVal::IntV(0)),
+            ("timeout-ms", SteelVal::IntV(200)),
+            ("cookies", jar.clone().into_steelval().unwrap()),
+        ]))
+        .unwrap();
+
+        let mut response = put_wrapper(&client, url("/echo"))
+            .body(SteelVal::StringV("hello".into()))
+            .unwrap()
+            .send()
+            .unwrap();
+        assert_eq!(
+            response.text().unwrap(),
+            "PUT /echo cookie= type= body=hello"
+        );
+        assert_eq!(response.text().unwrap_err().kind, HttpErrorKind::Consumed);
+
+        let mut response = patch_wrapper(&client, url("/echo"))
+            .form(serde_json::json!({"a": "b c"}))
+            .unwrap()
+            .send()
+            .unwrap();
+        assert_eq!(
+            response.text().unwrap(),
+            "PATCH /echo cookie= type=application/x-www-form-urlencoded body=a=b+c"
+        );
+
+        let mut response = request_wrapper(&client, "delete".to_string(), url("/echo"))
+            .unwrap()
+            .multipart_text("field".to_string(), "value".to_string())
+            .send()
+            .unwrap();
+        let text = response.text().unwrap();
+        assert!(text.starts_with("DELETE /echo cookie= type=multipart/form-data; boundary="));
+        assert!(text.contains("name=\"field\"\r\n\r\nvalue"));
+
+        let path = write_file(
+            test_directory("blocking_requests", "body_file").join("body.txt"),
+            "from a file",
+        );
+        let mut response = post_wrapper(&client, url("/echo"))
+            .body_file(path.to_string_lossy().into_owned())
+            .send()
+            .unwrap();
+        assert!(response.text().unwrap().ends_with("body=from a file"));
+
+        let response = get_wrapper(&client, url("/redirect")).send().unwrap();
+        assert_eq!(response.status(), HttpStatus(302));
+        assert_eq!(response.header("location".to_string()).unwrap(), "/echo");
+
+        let missing = get_wrapper(&client, url("/missing")).send().unwrap();
+        let error = missing.error_for_status().unwrap_err();
+        assert_eq!(
+            (error.kind, error.status),
+            (HttpErrorKind::Status, Some(404))
+        );
+
+        let mut response = get_wrapper(&client, url("/auth"))
+            .basic_auth("user".to_string(), Some("pass".to_string()))
+            .send()
+            .unwrap();
+        assert_eq!(response.text().unwrap(), "Basic dXNlcjpwYXNz");
+
+        get_wrapper(&client, url("/login")).send().unwrap();
+        assert_eq!(
+            cookie_jar_cookies(&jar, url("/")).unwrap().unwrap(),
+            "session=abc"
+        );
+
+        let mut response = get_wrapper(&client, url("/echo")).send().unwrap();
+        assert_eq!(
+            response.read_bytes(8).unwrap(),
+            bytes_to_steelval(b"GET /ech".to_vec())
+        );
+        assert!(response.text().unwrap().contains("cookie=session=abc"));
+
+        let error = get_wrapper(&client, url("/slow")).send().err().unwrap();
+        assert_eq!(error.kind, HttpErrorKind::Timeout);
+
+        // The server is still busy with the slow request, so this waits on it without a timeout
+        basic_get_wrapper(url("/stop")).unwrap();
+        handle.join().unwrap();
+    }
+}
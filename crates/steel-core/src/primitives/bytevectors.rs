@@ -0,0 +1,182 @@
+use std::cell::RefCell;
+
+use crate::gc::Gc;
+use crate::rvals::{RestArgsIter, Result, SteelString, SteelVal};
+use crate::steel_vm::builtin::BuiltInModule;
+use crate::{stop, throw};
+
+use steel_derive::function;
+
+/// # steel/bytevectors
+///
+/// Bytevectors are mutable, fixed length buffers of raw bytes. They print as `#u8(...)`,
+/// and are the representation used for binary data, for example the output of `value->bytes`.
+#[steel_derive::define_module(name = "steel/bytevectors")]
+pub fn bytevector_module() -> BuiltInModule {
+    let mut module = BuiltInModule::new("steel/bytevectors");
+    module
+        .register_native_fn_definition(BYTES_DEFINITION)
+        .register_native_fn_definition(MAKE_BYTES_DEFINITION)
+        .register_native_fn_definition(BYTESP_DEFINITION)
+        .register_native_fn_definition(BYTES_LENGTH_DEFINITION)
+        .register_native_fn_definition(BYTES_REF_DEFINITION)
+        .register_native_fn_definition(BYTES_SET_DEFINITION)
+        .register_native_fn_definition(BYTES_APPEND_DEFINITION)
+        .register_native_fn_definition(BYTES_TO_LIST_DEFINITION)
+        .register_native_fn_definition(LIST_TO_BYTES_DEFINITION)
+        .register_native_fn_definition(UTF8_TO_STRING_DEFINITION)
+        .register_native_fn_definition(STRING_TO_UTF8_DEFINITION);
+    module
+}
+
+pub(crate) fn bytes_to_steelval(bytes: Vec<u8>) -> SteelVal {
+    SteelVal::ByteVector(Gc::new(RefCell::new(bytes)))
+}
+
+fn int_to_byte(value: isize) -> Result<u8> {
+    u8::try_from(value).ok().ok_or_else(
+        throw!(TypeMismatch => "expected a byte (an integer between 0 and 255), found: {}", value),
+    )
+}
+
+fn steelval_to_byte(value: &SteelVal) -> Result<u8> {
+    match value {
+        SteelVal::IntV(i) => int_to_byte(*i),
+        _ => {
+            stop!(TypeMismatch => "expected a byte (an integer between 0 and 255), found: {}", value)
+        }
+    }
+}
+
+/// Constructs a new bytevector from the given bytes.
+///
+/// (bytes byte? ...) -> bytes?
+///
+/// # Examples
+///
+/// ```scheme
+/// > (bytes 1 2 3) ;; => #u8(1 2 3)
+/// ```
+#[function(name = "bytes")]
+pub fn bytes(rest: RestArgsIter<'_, isize>) -> Result<SteelVal> {
+    rest.map(|x| x.and_then(int_to_byte))
+        .collect::<Result<Vec<_>>>()
+        .map(bytes_to_steelval)
+}
+
+/// Constructs a new bytevector of the given length, with every byte set to `fill` (0 by default).
+///
+/// (make-bytes len [fill]) -> bytes?
+///
+/// * len : int?
+/// * fill : byte?
+#[function(name = "make-bytes")]
+pub fn make_bytes(length: usize, mut rest: RestArgsIter<'_, isize>) -> Result<SteelVal> {
+    let fill = match rest.next() {
+        Some(fill) => int_to_byte(fill?)?,
+        None => 0,
+    };
+
+    if rest.next().is_some() {
+        stop!(ArityMismatch => "make-bytes expects at most 2 arguments");
+    }
+
+    Ok(bytes_to_steelval(vec![fill; length]))
+}
+
+/// Returns `#t` if the value is a bytevector.
+#[function(name = "bytes?", constant = true)]
+pub fn bytesp(value: &SteelVal) -> bool {
+    matches!(value, SteelVal::ByteVector(_))
+}
+
+/// Returns the number of bytes in the bytevector.
+#[function(name = "bytes-length")]
+pub fn bytes_length(value: &Gc<RefCell<Vec<u8>>>) -> usize {
+    value.borrow().len()
+}
+
+/// Returns the byte at the given index.
+///
+/// (bytes-ref bytes index) -> byte?
+#[function(name = "bytes-ref")]
+pub fn bytes_ref(value: &Gc<RefCell<Vec<u8>>>, index: usize) -> Result<SteelVal> {
+    match value.borrow().get(index) {
+        Some(byte) => Ok(SteelVal::IntV(*byte as isize)),
+        None => {
+            stop!(Generic => "bytes-ref: index out of bounds: index: {}, length: {}", index, value.borrow().len())
+        }
+    }
+}
+
+/// Sets the byte at the given index.
+///
+/// (bytes-set! bytes index byte) -> void?
+#[function(name = "bytes-set!")]
+pub fn bytes_set(value: &Gc<RefCell<Vec<u8>>>, index: usize, byte: SteelVal) -> Result<SteelVal> {
+    let byte = steelval_to_byte(&byte)?;
+    let mut guard = value.borrow_mut();
+    let length = guard.len();
+
+    match guard.get_mut(index) {
+        Some(slot) => {
+            *slot = byte;
+            Ok(SteelVal::Void)
+        }
+        None => {
+            stop!(Generic => "bytes-set!: index out of bounds: index: {}, length: {}", index, length)
+        }
+    }
+}
+
+/// Returns a new bytevector with the contents of every given bytevector, in order.
+#[function(name = "bytes-append")]
+pub fn bytes_append(rest: RestArgsIter<'_, &Gc<RefCell<Vec<u8>>>>) -> Result<SteelVal> {
+    let mut output = Vec::new();
+
+    for bytes in rest {
+        output.extend_from_slice(&bytes?.borrow());
+    }
+
+    Ok(bytes_to_steelval(output))
+}
+
+/// Converts the bytevector into a list of integers.
+#[function(name = "bytes->list")]
+pub fn bytes_to_list(value: &Gc<RefCell<Vec<u8>>>) -> SteelVal {
+    SteelVal::ListV(
+        value
+            .borrow()
+            .iter()
+            .map(|x| SteelVal::IntV(*x as isize))
+            .collect(),
+    )
+}
+
+/// Converts a list of integers between 0 and 255 into a bytevector.
+#[function(name = "list->bytes")]
+pub fn list_to_bytes(value: &SteelVal) -> Result<SteelVal> {
+    if let SteelVal::ListV(l) = value {
+        l.iter()
+            .map(steelval_to_byte)
+            .collect::<Result<Vec<_>>>()
+            .map(bytes_to_steelval)
+    } else {
+        stop!(TypeMismatch => "list->bytes expects a list, found: {}", value)
+    }
+}
+
+/// Decodes the bytevector as a UTF-8 string. Raises an error if the bytes are not valid UTF-8.
+#[function(name = "utf8->string")]
+pub fn utf8_to_string(value: &Gc<RefCell<Vec<u8>>>) -> Result<SteelVal> {
+    match std::str::from_utf8(&value.borrow()) {
+        Ok(s) => Ok(SteelVal::StringV(s.into())),
+        Err(e) => stop!(ConversionError => "utf8->string: {}", e),
+    }
+}
+
+/// Encodes the string as UTF-8 bytes.
+#[function(name = "string->utf8")]
+pub fn string_to_utf8(value: &SteelString) -> SteelVal {
+    bytes_to_steelval(value.as_bytes().to_vec())
+}
@@ -1,8 +1,10 @@
+use crate::gc::Gc;
+use crate::parser::span::Span;
 use crate::values::contracts::*;
 use crate::{builtin_stop, stop};
 use crate::{
     rvals::{Result, SteelVal},
-    steel_vm::vm::VmCore,
+    steel_vm::{builtin::get_function_name, vm::VmCore},
 };
 
 pub const MAKE_C: SteelVal = SteelVal::FuncV(make_c);
@@ -10,6 +12,16 @@ pub const MAKE_DEPENDENT_CONTRACT: SteelVal = SteelVal::FuncV(make_dependent_con
 pub const MAKE_FLAT_CONTRACT: SteelVal = SteelVal::FuncV(make_flat_contract);
 pub const MAKE_FUNCTION_CONTRACT: SteelVal = SteelVal::FuncV(make_function_contract);
 pub const BIND_CONTRACT_TO_FUNCTION: SteelVal = SteelVal::BuiltIn(bind_contract_to_function);
+pub const CONTRACT_ACCEPTS: SteelVal = SteelVal::BuiltIn(contract_accepts);
+pub const ATTACH_CONTRACT_CONSUMER: SteelVal = SteelVal::FuncV(attach_contract_consumer);
+pub const LIST_CONTRACT: SteelVal = SteelVal::FuncV(make_list_contract);
+pub const VECTOR_CONTRACT: SteelVal = SteelVal::FuncV(make_vector_contract);
+pub const HASH_CONTRACT: SteelVal = SteelVal::FuncV(make_hash_contract);
+pub const STREAM_CONTRACT: SteelVal = SteelVal::FuncV(make_stream_contract);
+pub const OR_CONTRACT: SteelVal = SteelVal::FuncV(make_or_contract);
+pub const AND_CONTRACT: SteelVal = SteelVal::FuncV(make_and_contract);
+pub const MAKE_STRUCT_CONTRACT: SteelVal = SteelVal::FuncV(make_struct_contract);
+pub const MAKE_PARAMETRIC_CONTRACT: SteelVal = SteelVal::FuncV(make_parametric_contract);
 
 pub fn make_c(args: &[SteelVal]) -> Result<SteelVal> {
     if args.is_empty() {
@@ -84,8 +96,165 @@ pub fn bind_contract_to_function(ctx: &mut VmCore, args: &[SteelVal]) -> Option<
     }
 
     let name = args.get(2).cloned();
+    let provider = args.get(3).cloned();
 
     Some(ContractedFunction::new_from_steelvals(
-        contract, function, name,
+        contract, function, name, provider,
     ))
 }
+
+/// Checks a value against a first order contract, returning whether it passed instead of raising
+/// a contract violation. Backs `flat-contract-predicate`.
+pub fn contract_accepts(ctx: &mut VmCore, args: &[SteelVal]) -> Option<Result<SteelVal>> {
+    if args.len() != 2 {
+        builtin_stop!(ArityMismatch => "%contract-accepts? expects 2 arguments, a contract and a value")
+    }
+
+    let contract = match coerce_contract(&args[0], "%contract-accepts?") {
+        Ok(contract) => contract,
+        Err(e) => return Some(Err(e)),
+    };
+
+    let span = Span::default();
+
+    Some(contract.accepts(&args[1], &span, ctx).map(SteelVal::BoolV))
+}
+
+/// Attaches the requiring module to a value provided with `contract/out`, so that it can be
+/// blamed for passing bad arguments. Anything else is passed through untouched.
+pub fn attach_contract_consumer(args: &[SteelVal]) -> Result<SteelVal> {
+    if args.len() != 2 {
+        stop!(ArityMismatch => "%contract-consumer expects 2 arguments, a value and the name of a module")
+    }
+
+    match (&args[0], &args[1]) {
+        (SteelVal::ContractedFunction(c), SteelVal::StringV(consumer)) => {
+            Ok(c.with_consumer(consumer.to_string()).into())
+        }
+        (_, SteelVal::StringV(_)) => Ok(args[0].clone()),
+        (_, other) => {
+            stop!(TypeMismatch => "%contract-consumer expects a string naming the requiring module, found: {}", other)
+        }
+    }
+}
+
+fn predicate_name(predicate: &SteelVal) -> String {
+    match predicate {
+        SteelVal::FuncV(f) => get_function_name(*f).map(|x| x.name.to_string()),
+        SteelVal::BoxedFunction(f) => f.name().map(|x| x.to_string()),
+        _ => None,
+    }
+    .unwrap_or_else(|| predicate.to_string())
+}
+
+/// Contract combinators accept bare predicates as well as contracts, so `(listof int?)` works
+/// the same as `(listof (make/c int? 'int?))`
+fn coerce_contract(value: &SteelVal, who: &str) -> Result<Gc<ContractType>> {
+    match value {
+        SteelVal::Contract(c) => Ok(c.clone()),
+        v if v.is_function() => Ok(Gc::new(ContractType::Flat(FlatContract::new(
+            v.clone(),
+            predicate_name(v),
+        )))),
+        v => stop!(TypeMismatch => "{} expects contracts or predicates, found: {}", who, v),
+    }
+}
+
+fn make_collection_contract(
+    args: &[SteelVal],
+    kind: CollectionKind,
+    who: &str,
+) -> Result<SteelVal> {
+    if args.len() != 1 {
+        stop!(ArityMismatch => "{} expects 1 argument, the contract for the elements, found {}", who, args.len())
+    }
+
+    let element = coerce_contract(&args[0], who)?;
+
+    Ok(SteelVal::Contract(Gc::new(ContractType::Collection(
+        CollectionContract::new(kind, None, element),
+    ))))
+}
+
+pub fn make_list_contract(args: &[SteelVal]) -> Result<SteelVal> {
+    make_collection_contract(args, CollectionKind::List, "listof")
+}
+
+pub fn make_vector_contract(args: &[SteelVal]) -> Result<SteelVal> {
+    make_collection_contract(args, CollectionKind::Vector, "vectorof")
+}
+
+pub fn make_stream_contract(args: &[SteelVal]) -> Result<SteelVal> {
+    make_collection_contract(args, CollectionKind::Stream, "streamof")
+}
+
+pub fn make_hash_contract(args: &[SteelVal]) -> Result<SteelVal> {
+    if args.len() != 2 {
+        stop!(ArityMismatch => "hash/c expects 2 arguments, the contracts for the keys and the values, found {}", args.len())
+    }
+
+    let key = coerce_contract(&args[0], "hash/c")?;
+    let value = coerce_contract(&args[1], "hash/c")?;
+
+    Ok(SteelVal::Contract(Gc::new(ContractType::Collection(
+        CollectionContract::new(CollectionKind::Hash, Some(key), value),
+    ))))
+}
+
+fn make_combinator_contract(
+    args: &[SteelVal],
+    kind: CombinatorKind,
+    who: &str,
+) -> Result<SteelVal> {
+    if args.is_empty() {
+        stop!(ArityMismatch => "{} expects at least one contract", who)
+    }
+
+    let contracts = args
+        .iter()
+        .map(|x| coerce_contract(x, who))
+        .collect::<Result<Box<_>>>()?;
+
+    Ok(SteelVal::Contract(Gc::new(ContractType::Combinator(
+        CombinatorContract::new(kind, contracts),
+    ))))
+}
+
+pub fn make_or_contract(args: &[SteelVal]) -> Result<SteelVal> {
+    make_combinator_contract(args, CombinatorKind::Or, "or/c")
+}
+
+pub fn make_and_contract(args: &[SteelVal]) -> Result<SteelVal> {
+    make_combinator_contract(args, CombinatorKind::And, "and/c")
+}
+
+pub fn make_struct_contract(args: &[SteelVal]) -> Result<SteelVal> {
+    let (name, fields) = match args.split_first() {
+        Some((SteelVal::SymbolV(name), fields)) => (name.to_string(), fields),
+        Some((other, _)) => {
+            stop!(TypeMismatch => "make-struct/c expects the name of the struct in the first position, found: {}", other)
+        }
+        None => stop!(ArityMismatch => "make-struct/c expects the name of the struct"),
+    };
+
+    let fields = fields
+        .iter()
+        .map(|x| coerce_contract(x, "struct/c"))
+        .collect::<Result<Box<_>>>()?;
+
+    Ok(SteelVal::Contract(Gc::new(ContractType::Struct(
+        StructContract::new(name, fields),
+    ))))
+}
+
+pub fn make_parametric_contract(args: &[SteelVal]) -> Result<SteelVal> {
+    match args {
+        [SteelVal::SymbolV(name)] | [SteelVal::StringV(name)] => Ok(SteelVal::Contract(Gc::new(
+            ContractType::Parametric(ParametricContract::new(name.to_string())),
+        ))),
+        [other] => {
+            stop!(TypeMismatch => "new-∀/c expects a symbol naming the contract variable, found: {}", other)
+        }
+        _ => stop!(ArityMismatch => "new-∀/c expects 1 argument, found {}", args.len()),
+    }
+}
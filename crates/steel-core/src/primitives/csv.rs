@@ -0,0 +1,574 @@
+use std::io::{self, Read};
+
+use csv::{ReaderBuilder, StringRecord, StringRecordsIntoIter, WriterBuilder};
+
+use crate::{
+    gc::Gc,
+    rerrs::{ErrorKind, SteelErr},
+    rvals::{BuiltInDataStructureIterator, RestArgsIter, Result, SteelString, SteelVal},
+    steel_vm::builtin::BuiltInModule,
+    stop,
+    values::{json_vals::KeyStyle, port::SteelPort},
+};
+
+use steel_derive::function;
+
+pub fn csv_module() -> BuiltInModule {
+    let mut module = BuiltInModule::new("steel/csv");
+    module
+        .register_native_fn_definition(STRING_TO_CSV_DEFINITION)
+        .register_native_fn_definition(READ_CSV_DEFINITION)
+        .register_native_fn_definition(CSV_ROWS_DEFINITION)
+        .register_native_fn_definition(VALUE_TO_CSV_STRING_DEFINITION)
+        .register_native_fn_definition(WRITE_CSV_DEFINITION);
+    module
+}
+
+fn csv_error(function: &str, error: csv::Error) -> SteelErr {
+    SteelErr::new(ErrorKind::Generic, format!("{function} failed: {error}"))
+}
+
+fn option<'a>(options: Option<&'a SteelVal>, name: &str) -> Result<Option<&'a SteelVal>> {
+    match options {
+        Some(SteelVal::HashMapV(options)) => Ok(options.get(&SteelVal::SymbolV(name.into()))),
+        Some(other) => stop!(TypeMismatch => "expected a hash of options, found: {}", other),
+        None => Ok(None),
+    }
+}
+
+fn delimiter(options: Option<&SteelVal>) -> Result<u8> {
+    match option(options, "delimiter")? {
+        None => Ok(b','),
+        Some(SteelVal::CharV(c)) if c.is_ascii() => Ok(*c as u8),
+        Some(other) => {
+            stop!(Generic => "the 'delimiter option must be an ascii character, found: {}", other)
+        }
+    }
+}
+
+struct ReadOptions {
+    headers: bool,
+    delimiter: u8,
+    keys: KeyStyle,
+}
+
+impl ReadOptions {
+    // * 'headers - #t to read the first row as column names, and every other row as a hash
+    // * 'delimiter - a character, #\, by default
+    // * 'keys - how the column names are turned into keys, 'symbol (the default) or 'string
+    fn from_options(options: Option<&SteelVal>) -> Result<Self> {
+        let headers = match option(options, "headers")? {
+            None => false,
+            Some(SteelVal::BoolV(headers)) => *headers,
+            Some(other) => {
+                stop!(Generic => "the 'headers option must be a boolean, found: {}", other)
+            }
+        };
+
+        Ok(ReadOptions {
+            headers,
+            delimiter: delimiter(options)?,
+            keys: KeyStyle::from_options(options)?,
+        })
+    }
+
+    fn reader<R: Read>(&self, input: R) -> csv::Reader<R> {
+        ReaderBuilder::new()
+            .has_headers(self.headers)
+            .delimiter(self.delimiter)
+            .from_reader(input)
+    }
+
+    fn header_keys<R: Read>(
+        &self,
+        function: &str,
+        reader: &mut csv::Reader<R>,
+    ) -> Result<Option<Vec<SteelVal>>> {
+        if !self.headers {
+            return Ok(None);
+        }
+
+        let headers = reader.headers().map_err(|e| csv_error(function, e))?;
+
+        Ok(Some(
+            headers
+                .iter()
+                .map(|header| self.keys.key(header.to_string()))
+                .collect(),
+        ))
+    }
+}
+
+// Fields are always strings, CSV doesn't say anything about their types
+fn record_to_steelval(record: &StringRecord, headers: Option<&[SteelVal]>) -> SteelVal {
+    let fields = record.iter().map(|field| SteelVal::StringV(field.into()));
+
+    match headers {
+        Some(headers) => SteelVal::HashMapV(Gc::new(headers.iter().cloned().zip(fields).collect())),
+        None => SteelVal::ListV(fields.collect()),
+    }
+}
+
+fn read_rows<R: Read>(function: &str, input: R, options: Option<&SteelVal>) -> Result<SteelVal> {
+    let options = ReadOptions::from_options(options)?;
+    let mut reader = options.reader(input);
+    let headers = options.header_keys(function, &mut reader)?;
+
+    let mut rows = Vec::new();
+
+    for record in reader.records() {
+        let record = record.map_err(|e| csv_error(function, e))?;
+        rows.push(record_to_steelval(&record, headers.as_deref()));
+    }
+
+    Ok(SteelVal::ListV(rows.into()))
+}
+
+// Reads straight from the port as the rows are asked for
+struct PortReader(Gc<SteelPort>);
+
+impl Read for PortReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0
+            .with_reader(|reader| reader.read(buf))
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?
+    }
+}
+
+struct CsvRows {
+    records: StringRecordsIntoIter<PortReader>,
+    headers: Option<Vec<SteelVal>>,
+    // The rows end after the first error, rather than trying to read past it
+    failed: bool,
+}
+
+impl Iterator for CsvRows {
+    type Item = Result<SteelVal>;
+
+    fn next(&mut self) -> Option<Result<SteelVal>> {
+        if self.failed {
+            return None;
+        }
+
+        match self.records.next()? {
+            Ok(record) => Some(Ok(record_to_steelval(&record, self.headers.as_deref()))),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(csv_error("csv-rows", e)))
+            }
+        }
+    }
+}
+
+/// Parses a string of CSV into a list of rows. Each row is a list of strings, or with the
+/// `'headers` option, a hash from the column names in the first row to the fields.
+///
+/// (string->csv string [options]) -> (listof (or/c list? hash?))
+///
+/// * string : string?
+/// * options : hash?
+///
+/// The options are:
+/// * `'headers` - `#true` to read the first row as column names
+/// * `'delimiter` - the character between fields, `#\,` by default
+/// * `'keys` - `'symbol` (the default) or `'string`, for the column names
+///
+/// # Examples
+/// ```scheme
+/// > (string->csv "name,lang\nsteel,rust") ;; => '(("name" "lang") ("steel" "rust"))
+/// > (string->csv "name,lang\nsteel,rust" (hash 'headers #t)) ;; => '(#hash((name . "steel") (lang . "rust")))
+/// ```
+#[function(name = "string->csv")]
+pub fn string_to_csv(
+    input: &SteelString,
+    mut rest: RestArgsIter<'_, &SteelVal>,
+) -> Result<SteelVal> {
+    let options = rest.next().transpose()?;
+
+    if rest.next().is_some() {
+        stop!(ArityMismatch => "string->csv takes at most two arguments");
+    }
+
+    read_rows("string->csv", input.as_bytes(), options)
+}
+
+/// Reads the rest of an input port as CSV, into a list of rows. Takes the same options as
+/// `string->csv`.
+///
+/// (read-csv port [options]) -> (listof (or/c list? hash?))
+///
+/// * port : input-port?
+/// * options : hash?
+#[function(name = "read-csv")]
+pub fn read_csv(port: &Gc<SteelPort>, mut rest: RestArgsIter<'_, &SteelVal>) -> Result<SteelVal> {
+    let options = rest.next().transpose()?;
+
+    if rest.next().is_some() {
+        stop!(ArityMismatch => "read-csv takes at most two arguments");
+    }
+
+    read_rows("read-csv", PortReader(port.clone()), options)
+}
+
+/// Reads the rows of an input port one at a time, as they're asked for, rather than all up
+/// front. The result can be used as the source of a transducer. Takes the same options as
+/// `string->csv`. A malformed row raises an error when it's reached, and ends the rows.
+///
+/// (csv-rows port [options]) -> iterator?
+///
+/// * port : input-port?
+/// * options : hash?
+///
+/// # Examples
+/// ```scheme
+/// > (transduce (csv-rows (open-input-file "people.csv") (hash 'headers #t))
+///              (mapping (lambda (row) (hash-ref row 'name)))
+///              (into-list))
+/// ```
+#[function(name = "csv-rows")]
+pub fn csv_rows(port: &Gc<SteelPort>, mut rest: RestArgsIter<'_, &SteelVal>) -> Result<SteelVal> {
+    let options = ReadOptions::from_options(rest.next().transpose()?)?;
+
+    if rest.next().is_some() {
+        stop!(ArityMismatch => "csv-rows takes at most two arguments");
+    }
+
+    let mut reader = options.reader(PortReader(port.clone()));
+    let headers = options.header_keys("csv-rows", &mut reader)?;
+
+    let rows = CsvRows {
+        records: reader.into_records(),
+        headers,
+        failed: false,
+    };
+
+    Ok(BuiltInDataStructureIterator::Fallible(Box::new(rows)).into_boxed_iterator())
+}
+
+fn field(function: &str, value: &SteelVal) -> Result<String> {
+    match value {
+        SteelVal::StringV(s) | SteelVal::SymbolV(s) => Ok(s.to_string()),
+        SteelVal::IntV(i) => Ok(i.to_string()),
+        SteelVal::NumV(n) => Ok(n.to_string()),
+        SteelVal::BoolV(b) => Ok(b.to_string()),
+        SteelVal::CharV(c) => Ok(c.to_string()),
+        SteelVal::Void => Ok(String::new()),
+        other => stop!(TypeMismatch => "{} can't write {} as a field", function, other),
+    }
+}
+
+fn hash_field(
+    function: &str,
+    row: &im_rc::HashMap<SteelVal, SteelVal>,
+    header: &SteelVal,
+) -> Result<String> {
+    // Column names can be given as symbols or strings, whichever the rows use
+    let value = match header {
+        SteelVal::SymbolV(s) | SteelVal::StringV(s) => row
+            .get(&SteelVal::SymbolV(s.clone()))
+            .or_else(|| row.get(&SteelVal::StringV(s.clone()))),
+        other => row.get(other),
+    };
+
+    value.map_or(Ok(String::new()), |value| field(function, value))
+}
+
+fn list_items<'a>(
+    function: &str,
+    value: &'a SteelVal,
+) -> Result<Box<dyn Iterator<Item = &'a SteelVal> + 'a>> {
+    match value {
+        SteelVal::ListV(l) => Ok(Box::new(l.iter())),
+        SteelVal::VectorV(v) => Ok(Box::new(v.iter())),
+        other => stop!(TypeMismatch => "{} expects a list, found: {}", function, other),
+    }
+}
+
+// * 'headers - a list of column names, written as the first row. Rows that are hashes are
+//   written in the order of these columns, so they need it.
+// * 'delimiter - a character, #\, by default
+fn write_rows(function: &str, rows: &SteelVal, options: Option<&SteelVal>) -> Result<String> {
+    let headers = option(options, "headers")?
+        .map(|headers| list_items(function, headers).map(|h| h.cloned().collect::<Vec<_>>()))
+        .transpose()?;
+
+    let mut writer = WriterBuilder::new()
+        .delimiter(delimiter(options)?)
+        .flexible(true)
+        .from_writer(Vec::new());
+
+    if let Some(headers) = &headers {
+        let record = headers
+            .iter()
+            .map(|header| field(function, header))
+            .collect::<Result<Vec<_>>>()?;
+
+        writer
+            .write_record(&record)
+            .map_err(|e| csv_error(function, e))?;
+    }
+
+    for row in list_items(function, rows)? {
+        let record = match (row, &headers) {
+            (SteelVal::HashMapV(row), Some(headers)) => headers
+                .iter()
+                .map(|header| hash_field(function, row, header))
+                .collect::<Result<Vec<_>>>()?,
+            (SteelVal::HashMapV(_), None) => {
+                stop!(Generic => "{} needs the 'headers option to write rows that are hashes", function)
+            }
+            (row, _) => list_items(function, row)?
+                .map(|value| field(function, value))
+                .collect::<Result<Vec<_>>>()?,
+        };
+
+        writer
+            .write_record(&record)
+            .map_err(|e| csv_error(function, e))?;
+    }
+
+    let output = writer
+        .into_inner()
+        .map_err(|e| SteelErr::new(ErrorKind::Generic, format!("{function} failed: {e}")))?;
+
+    Ok(String::from_utf8(output).expect("every field was written from a string"))
+}
+
+/// Serializes a list of rows to CSV. Rows are lists of fields, or hashes when the `'headers`
+/// option gives the order of the columns. Strings, symbols, numbers, booleans and characters
+/// can be fields, and void is an empty field.
+///
+/// (value->csv-string rows [options]) -> string?
+///
+/// * rows : (listof (or/c list? hash?))
+/// * options : hash?
+///
+/// The options are:
+/// * `'headers` - a list of column names, written as the first row
+/// * `'delimiter` - the character between fields, `#\,` by default
+///
+/// # Examples
+/// ```scheme
+/// > (value->csv-string (list (hash 'name "steel")) (hash 'headers '(name))) ;; => "name\nsteel\n"
+/// ```
+#[function(name = "value->csv-string")]
+pub fn value_to_csv_string(
+    rows: &SteelVal,
+    mut rest: RestArgsIter<'_, &SteelVal>,
+) -> Result<SteelVal> {
+    let options = rest.next().transpose()?;
+
+    if rest.next().is_some() {
+        stop!(ArityMismatch => "value->csv-string takes at most two arguments");
+    }
+
+    Ok(SteelVal::StringV(
+        write_rows("value->csv-string", rows, options)?.into(),
+    ))
+}
+
+/// Writes a list of rows to an output port as CSV. Takes the same options as
+/// `value->csv-string`.
+///
+/// (write-csv! port rows [options]) -> void?
+///
+/// * port : output-port?
+/// * rows : (listof (or/c list? hash?))
+/// * options : hash?
+#[function(name = "write-csv!")]
+pub fn write_csv(
+    port: &Gc<SteelPort>,
+    rows: &SteelVal,
+    mut rest: RestArgsIter<'_, &SteelVal>,
+) -> Result<SteelVal> {
+    let options = rest.next().transpose()?;
+
+    if rest.next().is_some() {
+        stop!(ArityMismatch => "write-csv! takes at most three arguments");
+    }
+
+    let output = write_rows("write-csv!", rows, options)?;
+    port.with_writer(|writer| writer.write_all(output.as_bytes()))?;
+    Ok(SteelVal::Void)
+}
+
+#[cfg(test)]
+mod csv_tests {
+    use super::*;
+    use crate::tests::{test_directory, write_file};
+    use im_lists::list::List;
+
+    fn strings(fields: &[&str]) -> SteelVal {
+        SteelVal::ListV(
+            fields
+                .iter()
+                .map(|field| SteelVal::StringV((*field).into()))
+                .collect::<List<_>>(),
+        )
+    }
+
+    fn options(pairs: Vec<(&str, SteelVal)>) -> SteelVal {
+        SteelVal::HashMapV(Gc::new(
+            pairs
+                .into_iter()
+                .map(|(key, value)| (SteelVal::SymbolV(key.into()), value))
+                .collect(),
+        ))
+    }
+
+    #[test]
+    fn test_rows_as_lists() {
+        let rows = read_rows("string->csv", "a,b\n\"1,5\",2\n".as_bytes(), None).unwrap();
+
+        assert_eq!(
+            rows,
+            SteelVal::ListV(vec![strings(&["a", "b"]), strings(&["1,5", "2"])].into())
+        );
+    }
+
+    #[test]
+    fn test_rows_as_hashes() {
+        let options = options(vec![
+            ("headers", SteelVal::BoolV(true)),
+            ("delimiter", SteelVal::CharV(';')),
+        ]);
+
+        let rows = read_rows(
+            "string->csv",
+            "name;lang\nsteel;rust\n".as_bytes(),
+            Some(&options),
+        )
+        .unwrap();
+
+        let SteelVal::ListV(rows) = rows else {
+            panic!("expected a list of rows");
+        };
+
+        let Some(SteelVal::HashMapV(row)) = rows.first() else {
+            panic!("expected a hash");
+        };
+
+        assert_eq!(rows.len(), 1);
+        assert_eq!(
+            row.get(&SteelVal::SymbolV("lang".into())),
+            Some(&SteelVal::StringV("rust".into()))
+        );
+    }
+
+    #[test]
+    fn test_write_rows() {
+        let rows = SteelVal::ListV(
+            vec![
+                SteelVal::ListV(
+                    vec![
+                        SteelVal::StringV("a,b".into()),
+                        SteelVal::IntV(10),
+                        SteelVal::BoolV(true),
+                        SteelVal::Void,
+                    ]
+                    .into(),
+                ),
+                options(vec![("name", SteelVal::StringV("steel".into()))]),
+            ]
+            .into(),
+        );
+
+        let header_options = options(vec![(
+            "headers",
+            SteelVal::ListV(vec![SteelVal::SymbolV("name".into())].into()),
+        )]);
+
+        assert_eq!(
+            write_rows("value->csv-string", &rows, Some(&header_options)).unwrap(),
+            "name\n\"a,b\",10,true,\nsteel\n"
+        );
+
+        assert!(write_rows("value->csv-string", &rows, None)
+            .unwrap_err()
+            .to_string()
+            .contains("needs the 'headers option"));
+    }
+
+    fn input_port(path: &std::path::Path) -> Gc<SteelPort> {
+        Gc::new(SteelPort::new_textual_file_input(path.to_str().unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_csv_rows_streams_from_a_port() {
+        let path = write_file(
+            test_directory("csv", "rows").join("people.csv"),
+            "name,lang\nsteel,rust\nracket,scheme,extra\nlast,row\n",
+        );
+
+        let headers = options(vec![("headers", SteelVal::BoolV(true))]);
+        let rows = csv_rows(
+            &input_port(&path),
+            RestArgsIter::from_slice(std::slice::from_ref(&headers)).unwrap(),
+        )
+        .unwrap();
+
+        let SteelVal::BoxedIterator(rows) = rows else {
+            panic!("expected an iterator, found: {rows}");
+        };
+
+        let mut rows = rows.borrow_mut();
+
+        let Some(Ok(SteelVal::HashMapV(row))) = rows.next() else {
+            panic!("expected the first row");
+        };
+        assert_eq!(
+            row.get(&SteelVal::SymbolV("lang".into())),
+            Some(&SteelVal::StringV("rust".into()))
+        );
+
+        // The row with an extra field is an error, and nothing is read after it
+        assert!(rows.next().unwrap().is_err());
+        assert!(rows.next().is_none());
+    }
+
+    #[test]
+    fn test_csv_rows_as_a_transducer_source() {
+        use crate::steel_vm::engine::Engine;
+
+        let directory = test_directory("csv", "transducer");
+        let good = write_file(directory.join("good.csv"), "name\nsteel\nracket\n");
+        let bad = write_file(directory.join("bad.csv"), "a,b\n1,2\n3\n");
+
+        let mut engine = Engine::new();
+
+        let names = engine
+            .run(&format!(
+                r#"
+                (require-builtin steel/csv)
+                (transduce (csv-rows (open-input-file {:?}) (hash 'headers #t))
+                           (mapping (lambda (row) (hash-ref row 'name)))
+                           (into-list))
+                "#,
+                good.to_str().unwrap()
+            ))
+            .unwrap();
+
+        assert_eq!(names.last(), Some(&strings(&["steel", "racket"])));
+
+        assert!(engine
+            .run(&format!(
+                r#"(transduce (csv-rows (open-input-file {:?})) (into-list))"#,
+                bad.to_str().unwrap()
+            ))
+            .is_err());
+    }
+
+    #[test]
+    fn test_write_csv_to_a_port_and_read_it_back() {
+        let path = test_directory("csv", "ports").join("out.csv");
+        let output = Gc::new(SteelPort::new_textual_file_output(path.to_str().unwrap()).unwrap());
+
+        let rows = SteelVal::ListV(vec![strings(&["a", "b"]), strings(&["1", "2,3"])].into());
+
+        write_csv(&output, &rows, RestArgsIter::from_slice(&[]).unwrap()).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a,b\n1,\"2,3\"\n");
+
+        let read = read_csv(&input_port(&path), RestArgsIter::from_slice(&[]).unwrap()).unwrap();
+        assert_eq!(read, rows);
+    }
+}
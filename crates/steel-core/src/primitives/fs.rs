@@ -1,11 +1,83 @@
-use crate::rvals::{Result, SteelVal};
+use crate::rvals::{IntoSteelVal, RestArgsIter, Result, SteelString, SteelVal};
+use crate::steel_vm::builtin::BuiltInModule;
 use crate::stop;
+use chrono::{DateTime, FixedOffset, Utc};
 use std::env::current_dir;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 
 use std::fs;
 use std::io;
 
+use steel_derive::function;
+
+/// How many names `create-temp-file` and `create-temp-directory` try before giving up
+const TEMP_ATTEMPTS: usize = 16;
+
+pub fn fs_module() -> BuiltInModule {
+    let mut module = BuiltInModule::new("steel/filesystem");
+    module
+        .register_value("is-dir?", FsFunctions::is_dir())
+        .register_value("is-file?", FsFunctions::is_file())
+        .register_value("read-dir", FsFunctions::read_dir())
+        .register_value("path-exists?", FsFunctions::path_exists())
+        .register_value(
+            "copy-directory-recursively!",
+            FsFunctions::copy_directory_recursively(),
+        )
+        .register_value("delete-directory!", FsFunctions::delete_directory())
+        .register_value("create-directory!", FsFunctions::create_dir_all())
+        .register_value("file-name", FsFunctions::file_name())
+        .register_value("current-directory", FsFunctions::current_dir())
+        .register_value(
+            "path->extension",
+            SteelVal::FuncV(FsFunctions::get_extension),
+        )
+        .register_native_fn_definition(DELETE_FILE_DEFINITION)
+        .register_native_fn_definition(RENAME_FILE_DEFINITION)
+        .register_native_fn_definition(COPY_FILE_DEFINITION)
+        .register_native_fn_definition(FILE_SIZE_DEFINITION)
+        .register_native_fn_definition(FILE_MODIFIED_TIME_DEFINITION)
+        .register_native_fn_definition(IS_READONLY_DEFINITION)
+        .register_native_fn_definition(SET_READONLY_DEFINITION)
+        .register_native_fn_definition(FILE_PERMISSIONS_DEFINITION)
+        .register_native_fn_definition(SET_FILE_PERMISSIONS_DEFINITION)
+        .register_native_fn_definition(IS_SYMLINK_DEFINITION)
+        .register_native_fn_definition(CREATE_SYMLINK_DEFINITION)
+        .register_native_fn_definition(READ_SYMLINK_DEFINITION)
+        .register_native_fn_definition(CANONICALIZE_PATH_DEFINITION)
+        .register_native_fn_definition(GLOB_DEFINITION)
+        .register_native_fn_definition(WALK_DIRECTORY_DEFINITION)
+        .register_native_fn_definition(TEMP_DIRECTORY_DEFINITION)
+        .register_native_fn_definition(CREATE_TEMP_FILE_DEFINITION)
+        .register_native_fn_definition(CREATE_TEMP_DIRECTORY_DEFINITION);
+
+    #[cfg(feature = "watch")]
+    super::watch::register_watch_functions(&mut module);
+
+    module
+}
+
+pub fn path_module() -> BuiltInModule {
+    let mut module = BuiltInModule::new("steel/path");
+    module
+        .register_native_fn_definition(PATH_JOIN_DEFINITION)
+        .register_native_fn_definition(PATH_SPLIT_DEFINITION)
+        .register_native_fn_definition(PATH_PARENT_DEFINITION)
+        .register_native_fn_definition(PATH_FILE_STEM_DEFINITION)
+        .register_native_fn_definition(PATH_WITH_EXTENSION_DEFINITION)
+        .register_native_fn_definition(IS_ABSOLUTE_DEFINITION)
+        .register_native_fn_definition(IS_RELATIVE_DEFINITION);
+    module
+}
+
+fn path_to_steelval(path: &Path) -> SteelVal {
+    SteelVal::StringV(path.to_string_lossy().into_owned().into())
+}
+
+fn paths_to_list(paths: Vec<PathBuf>) -> SteelVal {
+    SteelVal::ListV(paths.iter().map(|x| path_to_steelval(x)).collect())
+}
+
 fn get_extension_from_filename(filename: &str) -> Option<&str> {
     Path::new(filename)
         .extension()
@@ -219,3 +291,828 @@ impl FsFunctions {
         })
     }
 }
+
+/// Deletes the file at the given path. Raises an error if it does not exist, or is a directory.
+///
+/// (delete-file! path) -> void?
+///
+/// * path : string?
+#[function(name = "delete-file!")]
+pub fn delete_file(path: &SteelString) -> Result<SteelVal> {
+    fs::remove_file(path.as_str())?;
+    Ok(SteelVal::Void)
+}
+
+/// Renames a file or directory, replacing `to` if it is an existing file.
+///
+/// (rename-file! from to) -> void?
+///
+/// * from : string?
+/// * to : string?
+#[function(name = "rename-file!")]
+pub fn rename_file(from: &SteelString, to: &SteelString) -> Result<SteelVal> {
+    fs::rename(from.as_str(), to.as_str())?;
+    Ok(SteelVal::Void)
+}
+
+/// Copies the contents and permissions of a file, replacing `to` if it exists.
+/// Returns the number of bytes copied.
+///
+/// (copy-file! from to) -> int?
+///
+/// * from : string?
+/// * to : string?
+#[function(name = "copy-file!")]
+pub fn copy_file(from: &SteelString, to: &SteelString) -> Result<SteelVal> {
+    fs::copy(from.as_str(), to.as_str())?.into_steelval()
+}
+
+/// Returns the size of a file in bytes.
+///
+/// (file-size path) -> int?
+///
+/// * path : string?
+#[function(name = "file-size")]
+pub fn file_size(path: &SteelString) -> Result<SteelVal> {
+    fs::metadata(path.as_str())?.len().into_steelval()
+}
+
+/// Returns the time a file was last modified, as a datetime in UTC.
+///
+/// (file-modified-time path) -> datetime?
+///
+/// * path : string?
+///
+/// # Examples
+/// ```scheme
+/// > (datetime->unix (file-modified-time "Cargo.toml")) ;; => 1680255000
+/// ```
+#[function(name = "file-modified-time")]
+pub fn file_modified_time(path: &SteelString) -> Result<SteelVal> {
+    let modified = fs::metadata(path.as_str())?.modified()?;
+    let modified: DateTime<FixedOffset> = DateTime::<Utc>::from(modified).into();
+    modified.into_steelval()
+}
+
+/// Checks whether a file or directory is read only.
+///
+/// (file-readonly? path) -> bool?
+///
+/// * path : string?
+#[function(name = "file-readonly?")]
+pub fn is_readonly(path: &SteelString) -> Result<SteelVal> {
+    Ok(SteelVal::BoolV(
+        fs::metadata(path.as_str())?.permissions().readonly(),
+    ))
+}
+
+/// Makes a file or directory read only, or writable again.
+///
+/// (set-file-readonly! path readonly) -> void?
+///
+/// * path : string?
+/// * readonly : bool?
+#[function(name = "set-file-readonly!")]
+pub fn set_readonly(path: &SteelString, readonly: bool) -> Result<SteelVal> {
+    let mut permissions = fs::metadata(path.as_str())?.permissions();
+    permissions.set_readonly(readonly);
+    fs::set_permissions(path.as_str(), permissions)?;
+    Ok(SteelVal::Void)
+}
+
+/// Returns the unix permission bits of a file or directory. Only available on unix.
+///
+/// (file-permissions path) -> int?
+///
+/// * path : string?
+///
+/// # Examples
+/// ```scheme
+/// > (number->string (file-permissions "build.sh") 8) ;; => "100755"
+/// ```
+#[function(name = "file-permissions")]
+pub fn file_permissions(path: &SteelString) -> Result<SteelVal> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::metadata(path.as_str())?
+            .permissions()
+            .mode()
+            .into_steelval()
+    }
+
+    #[cfg(not(unix))]
+    {
+        stop!(Generic => "file-permissions is only available on unix, found: {}", path)
+    }
+}
+
+/// Sets the unix permission bits of a file or directory. Only available on unix.
+///
+/// (set-file-permissions! path mode) -> void?
+///
+/// * path : string?
+/// * mode : int?
+///
+/// # Examples
+/// ```scheme
+/// > (set-file-permissions! "build.sh" #o755)
+/// ```
+#[function(name = "set-file-permissions!")]
+pub fn set_file_permissions(path: &SteelString, mode: u32) -> Result<SteelVal> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path.as_str(), fs::Permissions::from_mode(mode))?;
+        Ok(SteelVal::Void)
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = mode;
+        stop!(Generic => "set-file-permissions! is only available on unix, found: {}", path)
+    }
+}
+
+/// Checks whether the path is a symbolic link, without following it.
+///
+/// (is-symlink? path) -> bool?
+///
+/// * path : string?
+#[function(name = "is-symlink?")]
+pub fn is_symlink(path: &SteelString) -> bool {
+    Path::new(path.as_str()).is_symlink()
+}
+
+/// Creates a symbolic link at `link` pointing to `target`.
+///
+/// (create-symlink! target link) -> void?
+///
+/// * target : string?
+/// * link : string?
+#[function(name = "create-symlink!")]
+pub fn create_symlink(target: &SteelString, link: &SteelString) -> Result<SteelVal> {
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(target.as_str(), link.as_str())?;
+
+    #[cfg(windows)]
+    if Path::new(target.as_str()).is_dir() {
+        std::os::windows::fs::symlink_dir(target.as_str(), link.as_str())?;
+    } else {
+        std::os::windows::fs::symlink_file(target.as_str(), link.as_str())?;
+    }
+
+    Ok(SteelVal::Void)
+}
+
+/// Returns the path a symbolic link points to.
+///
+/// (read-symlink path) -> string?
+///
+/// * path : string?
+#[function(name = "read-symlink")]
+pub fn read_symlink(path: &SteelString) -> Result<SteelVal> {
+    Ok(path_to_steelval(&fs::read_link(path.as_str())?))
+}
+
+/// Returns the absolute form of the path, with every symbolic link and `.` or `..` component
+/// resolved. Raises an error if the path does not exist.
+///
+/// (canonicalize-path path) -> string?
+///
+/// * path : string?
+#[function(name = "canonicalize-path")]
+pub fn canonicalize_path(path: &SteelString) -> Result<SteelVal> {
+    Ok(path_to_steelval(&fs::canonicalize(path.as_str())?))
+}
+
+fn has_wildcard(component: &str) -> bool {
+    component.contains(['*', '?', '['])
+}
+
+fn class_contains(class: &[char], c: char) -> bool {
+    let mut i = 0;
+
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            if class[i] <= c && c <= class[i + 2] {
+                return true;
+            }
+            i += 3;
+        } else {
+            if class[i] == c {
+                return true;
+            }
+            i += 1;
+        }
+    }
+
+    false
+}
+
+// Matches one character of the name against the token at `p`, returning where the next token
+// starts. `*` is handled by `wildcard_match`.
+fn match_token(pattern: &[char], p: usize, c: char) -> Option<usize> {
+    match pattern.get(p)? {
+        '?' => Some(p + 1),
+        '[' => match pattern[p + 1..].iter().position(|x| *x == ']') {
+            Some(end) => {
+                let (negated, class) = match &pattern[p + 1..p + 1 + end] {
+                    ['!' | '^', class @ ..] => (true, class),
+                    class => (false, class),
+                };
+
+                (class_contains(class, c) != negated).then_some(p + end + 2)
+            }
+            // An unclosed `[` is just a bracket
+            None => (c == '[').then_some(p + 1),
+        },
+        x => (*x == c).then_some(p + 1),
+    }
+}
+
+// Matches a single path component, where `*` matches any run of characters, `?` matches
+// any one character, and `[...]` matches one character out of a set like `[a-z_]` or `[!0-9]`.
+//
+// Only the most recent `*` ever needs to be retried, since whatever an earlier one would have
+// swallowed can be swallowed by the later one instead. That keeps a pattern like `*a*a*a*b`
+// from backtracking exponentially on a long name.
+fn wildcard_match(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // Where the pattern continues after the last `*`, and where in the name it was tried
+    let mut star = None;
+
+    while n < name.len() {
+        if pattern.get(p) == Some(&'*') {
+            star = Some((p + 1, n));
+            p += 1;
+        } else if let Some(next) = match_token(pattern, p, name[n]) {
+            p = next;
+            n += 1;
+        } else if let Some((after_star, tried)) = star {
+            // Let the `*` swallow one more character and try again from there
+            star = Some((after_star, tried + 1));
+            p = after_star;
+            n = tried + 1;
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|x| *x == '*')
+}
+
+fn matches_component(pattern: &str, name: &str) -> bool {
+    // Like a shell, wildcards don't match hidden files unless asked to
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+
+    wildcard_match(&pattern, &name)
+}
+
+// The entries of a directory, sorted, with an empty base standing in for the current directory
+fn sorted_entries(base: &Path) -> Vec<(String, PathBuf)> {
+    let dir = if base.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        base
+    };
+
+    let mut entries = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| {
+                    let name = entry.file_name().into_string().ok()?;
+                    let path = base.join(&name);
+                    Some((name, path))
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    entries.sort();
+    entries
+}
+
+fn expand_glob(base: PathBuf, components: &[String], found: &mut Vec<PathBuf>) {
+    let (head, rest) = match components.split_first() {
+        Some(split) => split,
+        None => {
+            if base.exists() || base.is_symlink() {
+                found.push(base);
+            }
+            return;
+        }
+    };
+
+    if head == "**" {
+        // Matches no directories at all, or any number of them
+        expand_glob(base.clone(), rest, found);
+
+        for (name, path) in sorted_entries(&base) {
+            if !name.starts_with('.') && path.is_dir() && !path.is_symlink() {
+                expand_glob(path, components, found);
+            }
+        }
+    } else if has_wildcard(head) {
+        for (name, path) in sorted_entries(&base) {
+            if matches_component(head, &name) {
+                expand_glob(path, rest, found);
+            }
+        }
+    } else {
+        expand_glob(base.join(head), rest, found);
+    }
+}
+
+fn glob_paths(pattern: &str) -> Vec<PathBuf> {
+    let mut base = PathBuf::new();
+    let mut components = Vec::new();
+
+    for component in Path::new(pattern).components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir if components.is_empty() => {
+                base.push(component)
+            }
+            component => components.push(component.as_os_str().to_string_lossy().into_owned()),
+        }
+    }
+
+    let mut found = Vec::new();
+    expand_glob(base, &components, &mut found);
+
+    found.sort();
+    found.dedup();
+    found
+}
+
+/// Returns every path matching a glob pattern, sorted. `*` matches any part of a name, `?`
+/// matches a single character, `[...]` matches one of a set of characters and `**` matches
+/// any number of nested directories. Hidden files are only matched by patterns that start
+/// with a `.`.
+///
+/// (glob pattern) -> (listof string?)
+///
+/// * pattern : string?
+///
+/// # Examples
+/// ```scheme
+/// > (glob "src/**/*.rs") ;; => '("src/lib.rs" "src/primitives/fs.rs")
+/// > (glob "*.toml") ;; => '("Cargo.toml")
+/// ```
+#[function(name = "glob")]
+pub fn glob(pattern: &SteelString) -> SteelVal {
+    paths_to_list(glob_paths(pattern.as_str()))
+}
+
+fn walk(dir: &Path, pattern: Option<&str>, found: &mut Vec<PathBuf>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    entries.sort();
+
+    for path in entries {
+        let keep = match (pattern, path.file_name().and_then(|x| x.to_str())) {
+            (Some(pattern), Some(name)) => matches_component(pattern, name),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+
+        // Symbolic links to directories aren't followed, so a cycle can't send us in circles
+        let descend = path.is_dir() && !path.is_symlink();
+
+        if keep {
+            found.push(path.clone());
+        }
+
+        if descend {
+            walk(&path, pattern, found)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Recursively lists everything inside of a directory, parents before their children.
+/// Given a glob pattern, only the entries whose names match it are returned, although
+/// every directory is still walked.
+///
+/// (walk-directory dir [pattern]) -> (listof string?)
+///
+/// * dir : string?
+/// * pattern : string?
+///
+/// # Examples
+/// ```scheme
+/// > (walk-directory "src") ;; => '("src/lib.rs" "src/primitives" "src/primitives/fs.rs")
+/// > (walk-directory "src" "*.rs") ;; => '("src/lib.rs" "src/primitives/fs.rs")
+/// ```
+#[function(name = "walk-directory")]
+pub fn walk_directory(
+    dir: &SteelString,
+    mut rest: RestArgsIter<'_, &SteelString>,
+) -> Result<SteelVal> {
+    let pattern = rest.next().transpose()?;
+
+    if rest.next().is_some() {
+        stop!(ArityMismatch => "walk-directory takes at most two arguments");
+    }
+
+    let mut found = Vec::new();
+    walk(
+        Path::new(dir.as_str()),
+        pattern.map(|x| x.as_str()),
+        &mut found,
+    )?;
+
+    Ok(paths_to_list(found))
+}
+
+/// Returns the directory temporary files go in.
+///
+/// (temp-directory) -> string?
+#[function(name = "temp-directory")]
+pub fn temp_directory() -> SteelVal {
+    path_to_steelval(&std::env::temp_dir())
+}
+
+fn create_temp(
+    mut rest: RestArgsIter<'_, &SteelString>,
+    create: fn(&Path) -> io::Result<()>,
+) -> Result<SteelVal> {
+    let prefix = rest.next().transpose()?;
+    let prefix = prefix.map(|x| x.as_str()).unwrap_or("steel-");
+
+    for _ in 0..TEMP_ATTEMPTS {
+        let path = std::env::temp_dir().join(format!("{prefix}{:016x}", rand::random::<u64>()));
+
+        match create(&path) {
+            Ok(()) => return Ok(path_to_steelval(&path)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    stop!(Generic => "unable to find an unused name for a temporary file")
+}
+
+/// Creates a new empty file in the temporary directory and returns its path. The file is
+/// not removed automatically.
+///
+/// (create-temp-file [prefix]) -> string?
+///
+/// * prefix : string? = "steel-"
+#[function(name = "create-temp-file")]
+pub fn create_temp_file(rest: RestArgsIter<'_, &SteelString>) -> Result<SteelVal> {
+    create_temp(rest, |path| {
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map(|_| ())
+    })
+}
+
+/// Creates a new empty directory in the temporary directory and returns its path. The
+/// directory is not removed automatically.
+///
+/// (create-temp-directory [prefix]) -> string?
+///
+/// * prefix : string? = "steel-"
+#[function(name = "create-temp-directory")]
+pub fn create_temp_directory(rest: RestArgsIter<'_, &SteelString>) -> Result<SteelVal> {
+    create_temp(rest, |path| fs::create_dir(path))
+}
+
+/// Joins paths together with the platform's separator. A later absolute path replaces
+/// everything before it.
+///
+/// (path-join path ...) -> string?
+///
+/// * path : string?
+///
+/// # Examples
+/// ```scheme
+/// > (path-join "src" "primitives" "fs.rs") ;; => "src/primitives/fs.rs"
+/// > (path-join "src" "/tmp") ;; => "/tmp"
+/// ```
+#[function(name = "path-join")]
+pub fn path_join(rest: RestArgsIter<'_, &SteelString>) -> Result<SteelVal> {
+    let mut path = PathBuf::new();
+
+    for part in rest {
+        path.push(part?.as_str());
+    }
+
+    Ok(path_to_steelval(&path))
+}
+
+/// Splits a path into its components.
+///
+/// (path-split path) -> (listof string?)
+///
+/// * path : string?
+///
+/// # Examples
+/// ```scheme
+/// > (path-split "/usr/local/bin") ;; => '("/" "usr" "local" "bin")
+/// ```
+#[function(name = "path-split")]
+pub fn path_split(path: &SteelString) -> SteelVal {
+    SteelVal::ListV(
+        Path::new(path.as_str())
+            .components()
+            .map(|x| SteelVal::StringV(x.as_os_str().to_string_lossy().into_owned().into()))
+            .collect(),
+    )
+}
+
+/// Returns the path without its last component, or `#false` if there isn't one.
+///
+/// (path-parent path) -> (or/c string? #false)
+///
+/// * path : string?
+///
+/// # Examples
+/// ```scheme
+/// > (path-parent "src/primitives/fs.rs") ;; => "src/primitives"
+/// > (path-parent "/") ;; => #false
+/// ```
+#[function(name = "path-parent")]
+pub fn path_parent(path: &SteelString) -> SteelVal {
+    match Path::new(path.as_str()).parent() {
+        Some(parent) => path_to_steelval(parent),
+        None => SteelVal::BoolV(false),
+    }
+}
+
+/// Returns the file name without its extension, or `#false` if there isn't a file name.
+///
+/// (path-file-stem path) -> (or/c string? #false)
+///
+/// * path : string?
+///
+/// # Examples
+/// ```scheme
+/// > (path-file-stem "archive/notes.tar.gz") ;; => "notes.tar"
+/// ```
+#[function(name = "path-file-stem")]
+pub fn path_file_stem(path: &SteelString) -> SteelVal {
+    match Path::new(path.as_str()).file_stem() {
+        Some(stem) => SteelVal::StringV(stem.to_string_lossy().into_owned().into()),
+        None => SteelVal::BoolV(false),
+    }
+}
+
+/// Replaces the extension of a path, or removes it when given an empty string.
+///
+/// (path-with-extension path extension) -> string?
+///
+/// * path : string?
+/// * extension : string?
+///
+/// # Examples
+/// ```scheme
+/// > (path-with-extension "src/main.scm" "rkt") ;; => "src/main.rkt"
+/// ```
+#[function(name = "path-with-extension")]
+pub fn path_with_extension(path: &SteelString, extension: &SteelString) -> SteelVal {
+    path_to_steelval(&Path::new(path.as_str()).with_extension(extension.as_str()))
+}
+
+/// Checks whether a path is absolute.
+///
+/// (path-absolute? path) -> bool?
+///
+/// * path : string?
+#[function(name = "path-absolute?")]
+pub fn is_absolute(path: &SteelString) -> bool {
+    Path::new(path.as_str()).is_absolute()
+}
+
+/// Checks whether a path is relative.
+///
+/// (path-relative? path) -> bool?
+///
+/// * path : string?
+#[function(name = "path-relative?")]
+pub fn is_relative(path: &SteelString) -> bool {
+    Path::new(path.as_str()).is_relative()
+}
+
+#[cfg(test)]
+mod fs_tests {
+    use super::*;
+
+    use crate::tests::{test_directory, write_file};
+
+    fn string(path: &Path) -> SteelString {
+        path.to_string_lossy().into_owned().into()
+    }
+
+    fn strings(parts: &[&str]) -> Vec<SteelVal> {
+        parts
+            .iter()
+            .map(|x| SteelVal::StringV((*x).into()))
+            .collect()
+    }
+
+    fn temp_tree(name: &str) -> PathBuf {
+        let root = test_directory("fs", name);
+
+        for dir in ["src/nested", "target", ".git"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+
+        for file in [
+            "Cargo.toml",
+            "src/lib.rs",
+            "src/main.scm",
+            "src/nested/fs.rs",
+            "src/nested/fs2.rs",
+            ".git/HEAD",
+        ] {
+            fs::write(root.join(file), "").unwrap();
+        }
+
+        root
+    }
+
+    fn relative(root: &Path, paths: Vec<PathBuf>) -> Vec<String> {
+        paths
+            .into_iter()
+            .map(|x| {
+                x.strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches_component("*.rs", "lib.rs"));
+        assert!(!matches_component("*.rs", "lib.scm"));
+        assert!(matches_component("fs?.rs", "fs2.rs"));
+        assert!(!matches_component("fs?.rs", "fs.rs"));
+        assert!(matches_component("[a-c]*", "build.rs"));
+        assert!(!matches_component("[!a-c]*", "build.rs"));
+        assert!(matches_component("[abc", "[abc"));
+        assert!(!matches_component("*", ".git"));
+        assert!(matches_component(".*", ".git"));
+        assert!(matches_component("*a*b", "xaxxab"));
+        assert!(matches_component("**.rs", "lib.rs"));
+        assert!(matches_component("a*", "a"));
+        assert!(!matches_component("*a*a*a*b", &"a".repeat(200)));
+        assert!(matches_component("[[]x", "[x"));
+        assert!(matches_component("x[", "x["));
+    }
+
+    #[test]
+    fn delete_rename_and_copy() {
+        let root = test_directory("fs", "delete-rename-and-copy");
+        let original = write_file(root.join("original.txt"), "hello");
+        let renamed = root.join("renamed.txt");
+        let copied = root.join("nested/copied.txt");
+
+        rename_file(&string(&original), &string(&renamed)).unwrap();
+        assert!(!original.exists());
+        assert_eq!(fs::read_to_string(&renamed).unwrap(), "hello");
+
+        // The directory of the destination has to exist
+        assert!(copy_file(&string(&renamed), &string(&copied)).is_err());
+        fs::create_dir(root.join("nested")).unwrap();
+        assert_eq!(
+            copy_file(&string(&renamed), &string(&copied)).unwrap(),
+            SteelVal::IntV(5)
+        );
+        assert_eq!(fs::read_to_string(&copied).unwrap(), "hello");
+
+        delete_file(&string(&renamed)).unwrap();
+        assert!(!renamed.exists());
+        assert!(delete_file(&string(&renamed)).is_err());
+        assert!(delete_file(&string(&root.join("nested"))).is_err());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn metadata_and_permissions() {
+        let root = test_directory("fs", "metadata-and-permissions");
+        let file = string(&write_file(root.join("file.txt"), "four"));
+
+        assert_eq!(file_size(&file).unwrap(), SteelVal::IntV(4));
+        assert!(file_size(&string(&root.join("missing"))).is_err());
+        assert!(file_modified_time(&file).is_ok());
+
+        assert_eq!(is_readonly(&file).unwrap(), SteelVal::BoolV(false));
+        set_readonly(&file, true).unwrap();
+        assert_eq!(is_readonly(&file).unwrap(), SteelVal::BoolV(true));
+        set_readonly(&file, false).unwrap();
+        assert_eq!(is_readonly(&file).unwrap(), SteelVal::BoolV(false));
+
+        #[cfg(unix)]
+        {
+            set_file_permissions(&file, 0o640).unwrap();
+            assert_eq!(file_permissions(&file).unwrap(), SteelVal::IntV(0o100640));
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks() {
+        let root = test_directory("fs", "symlinks");
+        let target = write_file(root.join("target.txt"), "");
+        let link = root.join("link.txt");
+
+        assert!(!is_symlink(&string(&target)));
+
+        create_symlink(&string(&target), &string(&link)).unwrap();
+        assert!(is_symlink(&string(&link)));
+        assert_eq!(
+            read_symlink(&string(&link)).unwrap(),
+            SteelVal::StringV(string(&target))
+        );
+        assert_eq!(
+            canonicalize_path(&string(&link)).unwrap(),
+            canonicalize_path(&string(&target)).unwrap()
+        );
+
+        // The link is still there once its target is gone, it just doesn't lead anywhere
+        delete_file(&string(&target)).unwrap();
+        assert!(is_symlink(&string(&link)));
+        assert!(canonicalize_path(&string(&link)).is_err());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn path_join_and_split() {
+        let parts = strings(&["src", "primitives", "fs.rs"]);
+        let joined = path_join(RestArgsIter::from_slice(&parts).unwrap()).unwrap();
+        assert_eq!(joined, SteelVal::StringV("src/primitives/fs.rs".into()));
+
+        // An absolute part replaces everything before it
+        let parts = strings(&["src", "/tmp", "file"]);
+        let joined = path_join(RestArgsIter::from_slice(&parts).unwrap()).unwrap();
+        assert_eq!(joined, SteelVal::StringV("/tmp/file".into()));
+
+        let parts = strings(&[]);
+        let joined = path_join(RestArgsIter::from_slice(&parts).unwrap()).unwrap();
+        assert_eq!(joined, SteelVal::StringV("".into()));
+
+        assert!(path_join(RestArgsIter::from_slice(&[SteelVal::IntV(1)]).unwrap()).is_err());
+
+        assert_eq!(
+            path_split(&"/usr/local/bin".into()),
+            SteelVal::ListV(strings(&["/", "usr", "local", "bin"]).into_iter().collect())
+        );
+        assert_eq!(
+            path_split(&"./src/../lib.rs".into()),
+            SteelVal::ListV(strings(&[".", "src", "..", "lib.rs"]).into_iter().collect())
+        );
+    }
+
+    #[test]
+    fn glob_and_walk() {
+        let root = temp_tree("glob-and-walk");
+        let pattern = |x: &str| root.join(x).to_string_lossy().into_owned();
+
+        assert_eq!(
+            relative(&root, glob_paths(&pattern("src/**/*.rs"))),
+            vec!["src/lib.rs", "src/nested/fs.rs", "src/nested/fs2.rs"]
+        );
+        assert_eq!(
+            relative(&root, glob_paths(&pattern("*/nested/fs?.rs"))),
+            vec!["src/nested/fs2.rs"]
+        );
+        assert_eq!(
+            relative(&root, glob_paths(&pattern("Cargo.toml"))),
+            vec!["Cargo.toml"]
+        );
+        assert!(glob_paths(&pattern("*.lock")).is_empty());
+
+        let mut found = Vec::new();
+        walk(&root.join("src"), Some("*.rs"), &mut found).unwrap();
+        assert_eq!(
+            relative(&root, found),
+            vec!["src/lib.rs", "src/nested/fs.rs", "src/nested/fs2.rs"]
+        );
+
+        let mut found = Vec::new();
+        walk(&root, None, &mut found).unwrap();
+        assert_eq!(found.len(), 10);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
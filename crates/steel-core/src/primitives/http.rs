@@ -0,0 +1,301 @@
+// The error values shared by the http clients, so that failures look the same no matter which
+// client a request went through.
+
+use crate::{
+    rvals::Custom,
+    steel_vm::{builtin::BuiltInModule, register_fn::RegisterFn},
+    SteelVal,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HttpErrorKind {
+    Timeout,
+    Connect,
+    Redirect,
+    // The server answered with a 4xx or 5xx status
+    Status,
+    Body,
+    Decode,
+    Request,
+    Io,
+    // The body of the response was already read
+    Consumed,
+}
+
+impl HttpErrorKind {
+    fn name(self) -> &'static str {
+        match self {
+            HttpErrorKind::Timeout => "timeout",
+            HttpErrorKind::Connect => "connect",
+            HttpErrorKind::Redirect => "redirect",
+            HttpErrorKind::Status => "status",
+            HttpErrorKind::Body => "body",
+            HttpErrorKind::Decode => "decode",
+            HttpErrorKind::Request => "request",
+            HttpErrorKind::Io => "io",
+            HttpErrorKind::Consumed => "consumed",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct HttpError {
+    pub(crate) kind: HttpErrorKind,
+    pub(crate) message: String,
+    pub(crate) status: Option<u16>,
+    pub(crate) url: Option<String>,
+}
+
+impl Custom for HttpError {
+    fn fmt(&self) -> Option<std::result::Result<String, std::fmt::Error>> {
+        Some(Ok(format!(
+            "#<http-error {}: {}>",
+            self.kind.name(),
+            self.message
+        )))
+    }
+}
+
+impl HttpError {
+    pub(crate) fn new(kind: HttpErrorKind, message: impl Into<String>) -> Self {
+        HttpError {
+            kind,
+            message: message.into(),
+            status: None,
+            url: None,
+        }
+    }
+
+    pub(crate) fn consumed() -> Self {
+        HttpError::new(
+            HttpErrorKind::Consumed,
+            "the body of the response can only be read once",
+        )
+    }
+
+    pub(crate) fn status(status: u16, url: Option<String>) -> Self {
+        HttpError {
+            kind: HttpErrorKind::Status,
+            message: format!("the server responded with status {status}"),
+            status: Some(status),
+            url,
+        }
+    }
+
+    fn kind(&self) -> SteelVal {
+        SteelVal::SymbolV(self.kind.name().into())
+    }
+
+    fn message(&self) -> String {
+        self.message.clone()
+    }
+
+    fn status_code(&self) -> Option<usize> {
+        self.status.map(|x| x as usize)
+    }
+
+    fn url(&self) -> Option<String> {
+        self.url.clone()
+    }
+}
+
+impl From<std::io::Error> for HttpError {
+    fn from(value: std::io::Error) -> Self {
+        let kind = match value.kind() {
+            std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => HttpErrorKind::Timeout,
+            std::io::ErrorKind::InvalidData => HttpErrorKind::Decode,
+            _ => HttpErrorKind::Io,
+        };
+
+        HttpError::new(kind, value.to_string())
+    }
+}
+
+#[cfg(feature = "web")]
+impl From<reqwest::Error> for HttpError {
+    fn from(value: reqwest::Error) -> Self {
+        let kind = if value.is_timeout() {
+            HttpErrorKind::Timeout
+        } else if value.is_connect() {
+            HttpErrorKind::Connect
+        } else if value.is_redirect() {
+            HttpErrorKind::Redirect
+        } else if value.is_status() {
+            HttpErrorKind::Status
+        } else if value.is_decode() {
+            HttpErrorKind::Decode
+        } else if value.is_body() {
+            HttpErrorKind::Body
+        } else {
+            HttpErrorKind::Request
+        };
+
+        HttpError {
+            kind,
+            message: value.to_string(),
+            status: value.status().map(|x| x.as_u16()),
+            url: value.url().map(|x| x.to_string()),
+        }
+    }
+}
+
+// Statuses aren't errors here, the response for them is handed back like any other
+#[cfg(feature = "blocking_requests")]
+impl From<ureq::Transport> for HttpError {
+    fn from(value: ureq::Transport) -> Self {
+        use std::error::Error;
+
+        let timed_out = value
+            .source()
+            .and_then(|x| x.downcast_ref::<std::io::Error>())
+            .map(|x| {
+                matches!(
+                    x.kind(),
+                    std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+                )
+            })
+            .unwrap_or(false);
+
+        let kind = match value.kind() {
+            _ if timed_out => HttpErrorKind::Timeout,
+            ureq::ErrorKind::Dns
+            | ureq::ErrorKind::ConnectionFailed
+            | ureq::ErrorKind::ProxyConnect
+            | ureq::ErrorKind::ProxyUnauthorized => HttpErrorKind::Connect,
+            ureq::ErrorKind::TooManyRedirects => HttpErrorKind::Redirect,
+            ureq::ErrorKind::BadStatus | ureq::ErrorKind::BadHeader => HttpErrorKind::Body,
+            ureq::ErrorKind::Io => HttpErrorKind::Io,
+            _ => HttpErrorKind::Request,
+        };
+
+        HttpError {
+            kind,
+            message: value.to_string(),
+            status: None,
+            url: value.url().map(|x| x.to_string()),
+        }
+    }
+}
+
+// The status of a response, kept apart from the client specific types so that both clients
+// answer `response/status` with the same value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct HttpStatus(pub(crate) u16);
+
+impl Custom for HttpStatus {
+    fn fmt(&self) -> Option<std::result::Result<String, std::fmt::Error>> {
+        Some(Ok(format!("#<status-code {}>", self.0)))
+    }
+}
+
+impl HttpStatus {
+    fn as_int(&self) -> usize {
+        self.0 as usize
+    }
+
+    fn is_success(&self) -> bool {
+        (200..300).contains(&self.0)
+    }
+
+    fn is_redirection(&self) -> bool {
+        (300..400).contains(&self.0)
+    }
+
+    fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.0)
+    }
+
+    fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.0)
+    }
+}
+
+pub(crate) fn register_http_error_functions(module: &mut BuiltInModule) {
+    module
+        .register_type::<HttpError>("http-error?")
+        .register_fn("http-error-kind", HttpError::kind)
+        .register_fn("http-error-message", HttpError::message)
+        .register_fn("http-error-status", HttpError::status_code)
+        .register_fn("http-error-url", HttpError::url);
+}
+
+pub(crate) fn register_status_code_functions(module: &mut BuiltInModule) {
+    module
+        .register_type::<HttpStatus>("status-code?")
+        .register_fn("status-code->int", HttpStatus::as_int)
+        .register_fn("status-code/success?", HttpStatus::is_success)
+        .register_fn("status-code/redirect?", HttpStatus::is_redirection)
+        .register_fn("status-code/client-error?", HttpStatus::is_client_error)
+        .register_fn("status-code/server-error?", HttpStatus::is_server_error);
+}
+
+// Shared by the tests of both clients, so that they're checked against the same server
+#[cfg(all(test, feature = "web"))]
+pub(crate) mod http_tests {
+    use std::time::Duration;
+
+    use crate::{gc::Gc, SteelVal};
+
+    // Answers each request with a description of what it received, so that tests can check
+    // what the client sent
+    pub(crate) fn stub_server() -> (String, std::thread::JoinHandle<()>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", server.server_addr().to_ip().unwrap());
+
+        let handle = std::thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body).unwrap();
+
+                let header = |name: &'static str| {
+                    request
+                        .headers()
+                        .iter()
+                        .find(|x| x.field.equiv(name))
+                        .map(|x| x.value.to_string())
+                        .unwrap_or_default()
+                };
+
+                let response = match request.url() {
+                    "/stop" => {
+                        request.respond(tiny_http::Response::empty(200)).unwrap();
+                        return;
+                    }
+                    "/redirect" => tiny_http::Response::from_string("")
+                        .with_status_code(302)
+                        .with_header(tiny_http::Header::from_bytes("Location", "/echo").unwrap()),
+                    "/login" => tiny_http::Response::from_string("").with_header(
+                        tiny_http::Header::from_bytes("Set-Cookie", "session=abc; Path=/").unwrap(),
+                    ),
+                    "/slow" => {
+                        std::thread::sleep(Duration::from_millis(500));
+                        tiny_http::Response::from_string("")
+                    }
+                    "/missing" => tiny_http::Response::from_string("").with_status_code(404),
+                    "/auth" => tiny_http::Response::from_string(header("Authorization")),
+                    _ => tiny_http::Response::from_string(format!(
+                        "{} {} cookie={} type={} body={}",
+                        request.method(),
+                        request.url(),
+                        header("Cookie"),
+                        header("Content-Type"),
+                        body
+                    )),
+                };
+
+                request.respond(response).unwrap();
+            }
+        });
+
+        (address, handle)
+    }
+
+    pub(crate) fn options(pairs: Vec<(&str, SteelVal)>) -> SteelVal {
+        SteelVal::HashMapV(Gc::new(
+            pairs
+                .into_iter()
+                .map(|(key, value)| (SteelVal::SymbolV(key.into()), value))
+                .collect(),
+        ))
+    }
+}
@@ -126,6 +126,22 @@ impl IoFunctions {
         })
     }
 
+    // Without colors, the text is displayed as is
+    #[cfg(not(feature = "colors"))]
+    pub fn display_color() -> SteelVal {
+        SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
+            if args.len() == 2 {
+                match &args[0] {
+                    SteelVal::StringV(s) => print!("{s}"),
+                    other => print!("{other}"),
+                }
+                Ok(SteelVal::Void)
+            } else {
+                stop!(ArityMismatch => "display-color takes two arguments");
+            }
+        })
+    }
+
     pub fn newline() -> SteelVal {
         SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
             if args.is_empty() {
@@ -5,6 +5,7 @@ use crate::steel_vm::{
 use crate::{
     rvals::{IntoSteelVal, Result, SteelVal},
     steel_vm::vm::VmCore,
+    values::contracts::uncontracted,
 };
 use crate::{stop, throw};
 use im_lists::{list, list::List};
@@ -220,7 +221,8 @@ fn is_empty(list: &List<SteelVal>) -> bool {
 /// ```
 #[steel_derive::function(name = "pair?")]
 fn pair(list: &SteelVal) -> bool {
-    list.list()
+    uncontracted(list)
+        .list()
         .map(|x| x.iter().next().is_some())
         .unwrap_or_default()
 }
@@ -245,6 +247,10 @@ fn cons(args: &mut [SteelVal]) -> Result<SteelVal> {
             // Consider moving in a default value instead of cloning?
             Ok(SteelVal::ListV(right.clone()))
         }
+        // Gets the list checked in full and handed back, instead of nesting the contract
+        (_, right @ SteelVal::ContractedCollection(_)) => {
+            stop!(TypeMismatch => "cons expects a list that has been checked, found: {}", right)
+        }
         (left, right) => Ok(SteelVal::ListV(list![left, right.clone()])),
     }
 }
@@ -469,24 +475,22 @@ pub(crate) const APPEND_DOC: DocTemplate<'static> = DocTemplate {
 };
 
 fn append(args: &mut [SteelVal]) -> Result<SteelVal> {
-    if let Some((first, rest)) = args.split_first_mut() {
-        let initial = if let SteelVal::ListV(ref mut l) = first {
-            l
-        } else {
-            stop!(TypeMismatch => "append expects a list, found: {}", &args[0]);
-        };
+    // Nothing is touched until every argument is known to be a list, so that the call can be
+    // made again once any lazily checked lists among them have been checked
+    if let Some(value) = args.iter().find(|x| !matches!(x, SteelVal::ListV(_))) {
+        stop!(TypeMismatch => "append expects a list, found: {}", value);
+    }
 
+    if let Some((SteelVal::ListV(initial), rest)) = args.split_first_mut() {
         for value in rest {
             if let SteelVal::ListV(r) = value {
                 initial.append_mut(r.clone());
-            } else {
-                stop!(TypeMismatch => "append expects a list, found: {}", value);
             }
         }
 
-        return Ok(SteelVal::ListV(initial.clone()));
+        Ok(SteelVal::ListV(initial.clone()))
     } else {
-        return Ok(SteelVal::ListV(List::new()));
+        Ok(SteelVal::ListV(List::new()))
     }
 }
 
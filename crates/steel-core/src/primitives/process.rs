@@ -1,16 +1,22 @@
 use std::cell::RefCell;
-use std::io::{BufReader, BufWriter};
+use std::io::{BufReader, BufWriter, Write};
 use std::process::{Child, Command, ExitStatus, Stdio};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use im_lists::list::List;
 
 use crate::gc::Gc;
+use crate::rvals::{AsRefSteelVal, IntoSteelVal};
+use crate::stop;
 use crate::values::port::SteelPort;
 use crate::SteelVal;
 use crate::{rvals::Custom, steel_vm::builtin::BuiltInModule};
 use crate::{steel_vm::register_fn::RegisterFn, SteelErr};
 
+/// How often `wait/timeout` checks whether the child has exited
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
 pub fn process_module() -> BuiltInModule {
     let mut module = BuiltInModule::new("steel/process".to_string());
 
@@ -18,29 +24,64 @@ pub fn process_module() -> BuiltInModule {
         .register_fn("command", CommandBuilder::new)
         .register_fn("set-current-dir!", CommandBuilder::current_dir)
         .register_fn("set-piped-stdout!", CommandBuilder::stdout_piped)
+        .register_fn("set-piped-stderr!", CommandBuilder::stderr_piped)
+        .register_fn("set-stdin!", CommandBuilder::set_stdin)
+        .register_fn("set-env-var!", CommandBuilder::set_env_var)
+        .register_fn("remove-env-var!", CommandBuilder::remove_env_var)
+        .register_fn("clear-env-vars!", CommandBuilder::clear_env_vars)
         .register_fn("spawn-process", CommandBuilder::spawn_process)
+        .register_fn("run", CommandBuilder::run)
+        .register_value("pipeline", SteelVal::FuncV(pipeline))
         .register_fn("wait", ChildProcess::wait)
         .register_fn("wait->stdout", ChildProcess::wait_with_stdout)
+        .register_fn("wait->output", ChildProcess::wait_with_output)
+        .register_fn("wait/timeout", ChildProcess::wait_timeout)
+        .register_fn("kill", ChildProcess::kill)
+        .register_fn("child-id", ChildProcess::id)
         .register_fn("which", binary_exists_on_path)
         .register_fn("child-stdout", ChildProcess::stdout)
-        .register_fn("child-stdin", ChildProcess::stdin);
+        .register_fn("child-stderr", ChildProcess::stderr)
+        .register_fn("child-stdin", ChildProcess::stdin)
+        .register_fn("exit-status-code", ProcessExitStatus::code)
+        .register_fn("exit-status-signal", ProcessExitStatus::signal)
+        .register_fn("exit-status-success?", ProcessExitStatus::success)
+        .register_fn("process-output-stdout", ProcessOutput::stdout)
+        .register_fn("process-output-stderr", ProcessOutput::stderr)
+        .register_fn("process-output-status", ProcessOutput::status);
 
     module
 }
 
 #[derive(Debug)]
 struct CommandBuilder {
+    // Holds the program, arguments, directory and environment. Everything else is kept on the
+    // side, since a `Command` can't be copied or asked about its stdio.
     command: Command,
+    env_cleared: bool,
+    piped_stdout: bool,
+    piped_stderr: bool,
+    piped_stdin: bool,
+    // Written to the child's stdin once it has been spawned
+    stdin: Option<Vec<u8>>,
 }
 
 #[derive(Debug)]
 struct ChildProcess {
     child: Option<Child>,
+    // The earlier commands of a pipeline, which get waited on along with the last one
+    upstream: Vec<Child>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct ProcessExitStatus {
-    _exit_status: ExitStatus,
+    exit_status: ExitStatus,
+}
+
+#[derive(Debug, Clone)]
+struct ProcessOutput {
+    stdout: String,
+    stderr: String,
+    status: ProcessExitStatus,
 }
 
 fn binary_exists_on_path(binary: String) -> Option<String> {
@@ -50,15 +91,142 @@ fn binary_exists_on_path(binary: String) -> Option<String> {
     }
 }
 
+fn output_to_string(output: Vec<u8>) -> Result<String, SteelErr> {
+    String::from_utf8(output)
+        .map_err(|e| SteelErr::new(crate::rerrs::ErrorKind::ConversionError, e.to_string()))
+}
+
+/// Spawns the commands with the stdout of each one connected to the stdin of the next, and
+/// returns the last one. Only the first command can be given input with `set-stdin!`, otherwise
+/// its stdin is closed. The stderr of every command but the last goes to the stderr of this
+/// process. The commands themselves are left as they were, so they can be spawned again.
+///
+/// (pipeline (command "ls" '()) (command "grep" '("rs"))) -> child-process
+fn pipeline(args: &[SteelVal]) -> crate::rvals::Result<SteelVal> {
+    if args.is_empty() {
+        stop!(ArityMismatch => "pipeline expects at least one command");
+    }
+
+    let mut children: Vec<Child> = Vec::with_capacity(args.len());
+
+    // The pipes are set up on copies of the commands, so the builders can be used again
+    let spawned = args.iter().enumerate().try_for_each(
+        |(index, arg)| -> crate::rvals::Result<()> {
+            let mut nursery = ();
+            let builder = CommandBuilder::as_ref(arg, &mut nursery)?;
+            let mut command = builder.command();
+            let upstream = index != args.len() - 1;
+
+            if let Some(previous) = children.last_mut() {
+                if builder.stdin.is_some() {
+                    stop!(Generic => "pipeline: only the first command can read from set-stdin!");
+                }
+
+                if let Some(stdout) = previous.stdout.take() {
+                    command.stdin(Stdio::from(stdout));
+                }
+            } else if upstream && builder.stdin.is_none() {
+                // Nothing could ever write to it, so a command like `cat` would wait forever
+                command.stdin(Stdio::null());
+            }
+
+            // Only the pipes of the last command are handed out, so nothing would drain these
+            if upstream {
+                command.stdout(Stdio::piped());
+                command.stderr(Stdio::inherit());
+            }
+
+            children.push(builder.spawn_command(command)?);
+
+            Ok(())
+        },
+    );
+
+    if let Err(e) = spawned {
+        for child in &mut children {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+
+        return Err(e);
+    }
+
+    let child = children.pop();
+
+    ChildProcess {
+        child,
+        upstream: children,
+    }
+    .into_steelval()
+}
+
 impl ProcessExitStatus {
-    pub fn new(_exit_status: ExitStatus) -> Self {
-        Self { _exit_status }
+    pub fn new(exit_status: ExitStatus) -> Self {
+        Self { exit_status }
+    }
+
+    /// The exit code, or `#f` if the process was killed by a signal
+    pub fn code(&self) -> Option<i32> {
+        self.exit_status.code()
+    }
+
+    /// The signal that killed the process, if any. This is always `#f` on Windows.
+    pub fn signal(&self) -> Option<i32> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            self.exit_status.signal()
+        }
+
+        #[cfg(not(unix))]
+        {
+            None
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.exit_status.success()
+    }
+}
+
+impl ProcessOutput {
+    pub fn stdout(&self) -> String {
+        self.stdout.clone()
+    }
+
+    pub fn stderr(&self) -> String {
+        self.stderr.clone()
+    }
+
+    pub fn status(&self) -> ProcessExitStatus {
+        self.status.clone()
     }
 }
 
 impl ChildProcess {
     pub fn new(child: Child) -> Self {
-        Self { child: Some(child) }
+        Self {
+            child: Some(child),
+            upstream: Vec::new(),
+        }
+    }
+
+    fn take_child(&mut self) -> Result<Child, SteelErr> {
+        self.child
+            .take()
+            .ok_or_else(crate::throw!(Generic => "Child already awaited!"))
+    }
+
+    fn wait_upstream(&mut self) -> Result<(), SteelErr> {
+        for mut child in self.upstream.drain(..) {
+            child.wait()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn id(&self) -> Option<u32> {
+        self.child.as_ref().map(Child::id)
     }
 
     pub fn stdout(&mut self) -> Option<SteelVal> {
@@ -77,6 +245,14 @@ impl ChildProcess {
         //     todo!()
     }
 
+    pub fn stderr(&mut self) -> Option<SteelVal> {
+        self.child.as_mut().and_then(|x| x.stderr.take()).map(|x| {
+            SteelVal::PortV(Gc::new(SteelPort::ChildStdError(Rc::new(RefCell::new(
+                BufReader::new(x),
+            )))))
+        })
+    }
+
     pub fn stdin(&mut self) -> Option<SteelVal> {
         let stdout = self
             .child
@@ -93,24 +269,68 @@ impl ChildProcess {
         //     todo!()
     }
     pub fn wait(&mut self) -> Result<ProcessExitStatus, SteelErr> {
-        self.child
-            .take()
-            .ok_or_else(crate::throw!(Generic => "Child already awaited!"))?
-            .wait()
-            .map(ProcessExitStatus::new)
-            .map_err(|x| x.into())
+        let status = self.take_child()?.wait().map(ProcessExitStatus::new)?;
+        self.wait_upstream()?;
+        Ok(status)
     }
 
     pub fn wait_with_stdout(&mut self) -> Result<String, SteelErr> {
-        let stdout = self
-            .child
-            .take()
-            .ok_or_else(crate::throw!(Generic => "Child already awaited!"))?
-            .wait_with_output()?
-            .stdout;
+        let stdout = self.take_child()?.wait_with_output()?.stdout;
+        self.wait_upstream()?;
 
-        String::from_utf8(stdout)
-            .map_err(|e| SteelErr::new(crate::rerrs::ErrorKind::ConversionError, e.to_string()))
+        output_to_string(stdout)
+    }
+
+    pub fn wait_with_output(&mut self) -> Result<ProcessOutput, SteelErr> {
+        let output = self.take_child()?.wait_with_output()?;
+        self.wait_upstream()?;
+
+        Ok(ProcessOutput {
+            stdout: output_to_string(output.stdout)?,
+            stderr: output_to_string(output.stderr)?,
+            status: ProcessExitStatus::new(output.status),
+        })
+    }
+
+    /// Waits for at most `millis` milliseconds for the child to exit, returning `#f` if it
+    /// is still running
+    pub fn wait_timeout(&mut self, millis: usize) -> Result<Option<ProcessExitStatus>, SteelErr> {
+        let deadline = Instant::now() + Duration::from_millis(millis as u64);
+
+        loop {
+            let status = self
+                .child
+                .as_mut()
+                .ok_or_else(crate::throw!(Generic => "Child already awaited!"))?
+                .try_wait()?;
+
+            if let Some(status) = status {
+                self.child = None;
+                self.wait_upstream()?;
+                return Ok(Some(ProcessExitStatus::new(status)));
+            }
+
+            let now = Instant::now();
+
+            if now >= deadline {
+                return Ok(None);
+            }
+
+            std::thread::sleep(POLL_INTERVAL.min(deadline - now));
+        }
+    }
+
+    /// Kills the child, along with the rest of its pipeline
+    pub fn kill(&mut self) -> Result<(), SteelErr> {
+        for child in self.upstream.iter_mut() {
+            child.kill()?;
+        }
+
+        self.child
+            .as_mut()
+            .ok_or_else(crate::throw!(Generic => "Child already awaited!"))?
+            .kill()
+            .map_err(|x| x.into())
     }
 }
 
@@ -120,7 +340,14 @@ impl CommandBuilder {
 
         command.args(&args);
 
-        Self { command }
+        Self {
+            command,
+            env_cleared: false,
+            piped_stdout: false,
+            piped_stderr: false,
+            piped_stdin: false,
+            stdin: None,
+        }
     }
 
     pub fn current_dir(&mut self, directory: String) {
@@ -128,19 +355,249 @@ impl CommandBuilder {
     }
 
     pub fn stdout_piped(&mut self) {
-        self.command.stdout(Stdio::piped());
-        self.command.stderr(Stdio::piped());
-        self.command.stdin(Stdio::piped());
+        self.piped_stdout = true;
+        self.piped_stderr = true;
+        self.piped_stdin = true;
     }
 
-    pub fn spawn_process(&mut self) -> Result<ChildProcess, SteelErr> {
-        self.command
-            .spawn()
-            .map(ChildProcess::new)
-            .map_err(|x| x.into())
+    pub fn stderr_piped(&mut self) {
+        self.piped_stderr = true;
+    }
+
+    /// Feeds the child either a string, or everything that can be read from a port
+    pub fn set_stdin(&mut self, input: SteelVal) -> Result<(), SteelErr> {
+        let input = match input {
+            SteelVal::StringV(s) => s.as_bytes().to_vec(),
+            SteelVal::PortV(port) => port.read_all_str()?.1.into_bytes(),
+            other => {
+                stop!(TypeMismatch => format!("set-stdin! expects a string or a port, found: {other}"))
+            }
+        };
+
+        self.stdin = Some(input);
+
+        Ok(())
+    }
+
+    pub fn set_env_var(&mut self, key: String, value: String) {
+        self.command.env(key, value);
+    }
+
+    pub fn remove_env_var(&mut self, key: String) {
+        self.command.env_remove(key);
+    }
+
+    pub fn clear_env_vars(&mut self) {
+        self.command.env_clear();
+        self.env_cleared = true;
+    }
+
+    // A fresh command set up the way this one is, which can be changed without touching the
+    // builder
+    fn command(&self) -> Command {
+        let mut command = Command::new(self.command.get_program());
+
+        command.args(self.command.get_args());
+
+        if let Some(directory) = self.command.get_current_dir() {
+            command.current_dir(directory);
+        }
+
+        // Clearing forgets the variables set before it, so whatever is left came after
+        if self.env_cleared {
+            command.env_clear();
+        }
+
+        for (key, value) in self.command.get_envs() {
+            match value {
+                Some(value) => command.env(key, value),
+                None => command.env_remove(key),
+            };
+        }
+
+        if self.piped_stdout {
+            command.stdout(Stdio::piped());
+        }
+
+        if self.piped_stderr {
+            command.stderr(Stdio::piped());
+        }
+
+        if self.piped_stdin {
+            command.stdin(Stdio::piped());
+        }
+
+        command
+    }
+
+    fn spawn(&self) -> std::io::Result<Child> {
+        self.spawn_command(self.command())
+    }
+
+    fn spawn_command(&self, mut command: Command) -> std::io::Result<Child> {
+        if self.stdin.is_some() {
+            command.stdin(Stdio::piped());
+        }
+
+        let mut child = command.spawn()?;
+
+        if let Some(input) = self.stdin.clone() {
+            if let Some(mut stdin) = child.stdin.take() {
+                // Written from another thread, so a child that fills up its stdout before reading
+                // all of its input can't deadlock with us. The child is free to exit early.
+                std::thread::spawn(move || stdin.write_all(&input));
+            }
+        }
+
+        Ok(child)
+    }
+
+    pub fn spawn_process(&self) -> Result<ChildProcess, SteelErr> {
+        self.spawn().map(ChildProcess::new).map_err(|x| x.into())
+    }
+
+    /// Runs the command to completion, capturing its stdout and stderr
+    pub fn run(&self) -> Result<ProcessOutput, SteelErr> {
+        let mut command = self.command();
+
+        command.stdout(Stdio::piped());
+        command.stderr(Stdio::piped());
+
+        self.spawn_command(command)
+            .map(ChildProcess::new)?
+            .wait_with_output()
     }
 }
 
 impl Custom for CommandBuilder {}
 impl Custom for ChildProcess {}
-impl Custom for ProcessExitStatus {}
+
+impl Custom for ProcessExitStatus {
+    fn fmt(&self) -> Option<std::result::Result<String, std::fmt::Error>> {
+        Some(Ok(format!("#<{}>", self.exit_status)))
+    }
+}
+
+impl Custom for ProcessOutput {}
+
+#[cfg(all(test, unix))]
+mod process_tests {
+    use super::*;
+    use crate::rvals::AsRefMutSteelVal;
+
+    fn sh(script: &str) -> CommandBuilder {
+        CommandBuilder::new(
+            "sh".to_string(),
+            ["-c", script].iter().map(|x| x.to_string()).collect(),
+        )
+    }
+
+    #[test]
+    fn run_captures_output_and_status() {
+        let output = sh("echo out; echo err >&2; exit 3").run().unwrap();
+
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(output.stderr, "err\n");
+        assert_eq!(output.status.code(), Some(3));
+        assert!(!output.status.success());
+    }
+
+    #[test]
+    fn stdin_and_environment() {
+        let mut command = sh("printf \"$GREETING \"; cat");
+        command.clear_env_vars();
+        command.set_env_var("GREETING".to_string(), "hello".to_string());
+        command
+            .set_stdin(SteelVal::StringV("world".into()))
+            .unwrap();
+
+        assert_eq!(command.run().unwrap().stdout, "hello world");
+    }
+
+    #[test]
+    fn wait_with_timeout_and_kill() {
+        let mut child = sh("sleep 10").spawn_process().unwrap();
+
+        assert!(child.wait_timeout(10).unwrap().is_none());
+
+        child.kill().unwrap();
+        let status = child.wait().unwrap();
+
+        assert_eq!(status.code(), None);
+        assert_eq!(status.signal(), Some(9));
+    }
+
+    #[test]
+    fn pipeline_connects_commands() {
+        let first = sh("printf 'a\\nb\\nc\\n'").into_steelval().unwrap();
+
+        let mut last = sh("grep -v b");
+        last.stdout_piped();
+        let last = last.into_steelval().unwrap();
+
+        // The builders are left alone, so the same pipeline can be run again
+        for _ in 0..2 {
+            let child = pipeline(&[first.clone(), last.clone()]).unwrap();
+            let output = ChildProcess::as_mut_ref(&child)
+                .unwrap()
+                .wait_with_output()
+                .unwrap();
+
+            assert_eq!(output.stdout, "a\nc\n");
+            assert!(output.status.success());
+        }
+
+        // And each of them still runs on its own
+        let mut nursery = ();
+        let output = CommandBuilder::as_ref(&first, &mut nursery)
+            .unwrap()
+            .run()
+            .unwrap();
+        assert_eq!(output.stdout, "a\nb\nc\n");
+
+        assert!(pipeline(&[]).is_err());
+        assert!(pipeline(&[SteelVal::Void]).is_err());
+    }
+
+    #[test]
+    fn pipeline_closes_upstream_stdin() {
+        // Piping stdout pipes stdin as well, which used to leave `cat` waiting for input
+        let mut first = sh("cat");
+        first.stdout_piped();
+
+        let mut last = sh("wc -c");
+        last.stdout_piped();
+
+        let child = pipeline(&[
+            first.into_steelval().unwrap(),
+            last.into_steelval().unwrap(),
+        ])
+        .unwrap();
+        let output = ChildProcess::as_mut_ref(&child)
+            .unwrap()
+            .wait_with_output()
+            .unwrap();
+
+        assert_eq!(output.stdout.trim(), "0");
+
+        // Unless it was given input explicitly
+        let mut first = sh("cat");
+        first.stdout_piped();
+        first.set_stdin(SteelVal::StringV("abc".into())).unwrap();
+
+        let mut last = sh("wc -c");
+        last.stdout_piped();
+
+        let child = pipeline(&[
+            first.into_steelval().unwrap(),
+            last.into_steelval().unwrap(),
+        ])
+        .unwrap();
+        let output = ChildProcess::as_mut_ref(&child)
+            .unwrap()
+            .wait_with_output()
+            .unwrap();
+
+        assert_eq!(output.stdout.trim(), "3");
+    }
+}
@@ -1,64 +1,220 @@
+use std::cell::RefCell;
+use std::mem::ManuallyDrop;
+use std::rc::Rc;
+
 use im_lists::list::List;
 use rusqlite::{
-    params_from_iter,
-    types::{FromSql, ToSqlOutput, Value},
-    Connection, Result, Statement, ToSql,
+    types::{FromSql, FromSqlError, ToSqlOutput, Value, ValueRef},
+    Connection, OpenFlags, Result, Row, Rows, Statement, ToSql,
 };
 
 use crate::{
-    rvals::{Custom, SteelString},
-    steel_vm::{builtin::BuiltInModule, register_fn::RegisterFn},
-    SteelVal,
+    gc::Gc,
+    rerrs::ErrorKind,
+    rvals::{BuiltInDataStructureIterator, Custom, FromSteelVal, SteelString},
+    steel_vm::{
+        builtin::BuiltInModule,
+        register_fn::RegisterFn,
+        vm::{VmContext, VmCore},
+    },
+    stop, SteelErr, SteelVal,
 };
 
-impl Custom for Connection {}
-impl Custom for rusqlite::Error {}
+/// A connection to a database. Queries that are streamed hold on to the connection, so it
+/// stays open until both the connection and any streams over it are dropped.
+#[derive(Clone)]
+pub struct SqliteConnection {
+    connection: Rc<Connection>,
+}
+
+impl SqliteConnection {
+    fn new(connection: Connection) -> Self {
+        SqliteConnection {
+            connection: Rc::new(connection),
+        }
+    }
+}
+
+impl Custom for SqliteConnection {}
+
+impl Custom for rusqlite::Error {
+    fn fmt(&self) -> Option<std::result::Result<String, std::fmt::Error>> {
+        Some(Ok(format!("#<sqlite-error {self}>")))
+    }
+}
 
 impl Custom for Statement<'static> {}
 
+fn conversion_error(message: String) -> rusqlite::Error {
+    rusqlite::Error::ToSqlConversionFailure(message.into())
+}
+
 impl ToSql for SteelVal {
     fn to_sql(&self) -> Result<ToSqlOutput<'_>> {
         match self {
-            Self::IntV(b) => Ok(ToSqlOutput::Owned(Value::Integer((*b).try_into().unwrap()))),
+            Self::IntV(b) => Ok(ToSqlOutput::Owned(Value::Integer(*b as i64))),
+            Self::BoolV(b) => Ok(ToSqlOutput::Owned(Value::Integer(*b as i64))),
             Self::StringV(s) => Ok(ToSqlOutput::Owned(Value::Text(s.to_string()))),
+            Self::CharV(c) => Ok(ToSqlOutput::Owned(Value::Text(c.to_string()))),
             Self::NumV(n) => Ok(ToSqlOutput::Owned(Value::Real(*n))),
+            Self::ByteVector(b) => Ok(ToSqlOutput::Owned(Value::Blob(b.borrow().clone()))),
             Self::Void => Ok(ToSqlOutput::Owned(Value::Null)),
-            _ => {
-                todo!("Implement serialization for other types: {:?}", self)
-            }
+            _ => Err(conversion_error(format!(
+                "unable to store value in sqlite: {self}"
+            ))),
         }
     }
 }
 
 impl FromSql for SteelVal {
-    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+    fn column_result(value: ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
         match value {
-            rusqlite::types::ValueRef::Null => Ok(SteelVal::Void),
-            rusqlite::types::ValueRef::Integer(i) => Ok(SteelVal::IntV(i as isize)),
-            rusqlite::types::ValueRef::Real(f) => Ok(SteelVal::NumV(f)),
-            rusqlite::types::ValueRef::Text(t) => Ok(SteelVal::StringV(
-                std::str::from_utf8(t)
-                    .expect("Unable to decode text from sqlite")
-                    .into(),
-            )),
-            rusqlite::types::ValueRef::Blob(_) => {
-                todo!("Implement deserialization for other types")
+            ValueRef::Null => Ok(SteelVal::Void),
+            ValueRef::Integer(i) => Ok(SteelVal::IntV(i as isize)),
+            ValueRef::Real(f) => Ok(SteelVal::NumV(f)),
+            ValueRef::Text(t) => std::str::from_utf8(t)
+                .map(|x| SteelVal::StringV(x.into()))
+                .map_err(|e| FromSqlError::Other(Box::new(e))),
+            ValueRef::Blob(b) => Ok(SteelVal::ByteVector(Gc::new(RefCell::new(b.to_vec())))),
+        }
+    }
+}
+
+// Binds the parameters for a statement. A list or vector is bound by position, and a hash
+// map by name. Names can be given with or without their prefix, so `'id` binds `:id`, `@id`
+// or `$id`, whichever the statement uses.
+fn bind_params(statement: &mut Statement<'_>, params: &SteelVal) -> Result<()> {
+    match params {
+        SteelVal::ListV(l) => bind_positional(statement, l.len(), l.iter()),
+        SteelVal::VectorV(v) => bind_positional(statement, v.len(), v.iter()),
+        SteelVal::HashMapV(map) => {
+            for (key, value) in map.iter() {
+                let name = match key {
+                    SteelVal::StringV(s) => s.to_string(),
+                    SteelVal::SymbolV(s) => s.to_string(),
+                    _ => {
+                        return Err(conversion_error(format!(
+                            "parameter names must be strings or symbols, found: {key}"
+                        )))
+                    }
+                };
+
+                let index = match statement.parameter_index(&name)? {
+                    Some(index) => index,
+                    None => [":", "@", "$"]
+                        .iter()
+                        .find_map(|prefix| {
+                            statement
+                                .parameter_index(&format!("{prefix}{name}"))
+                                .transpose()
+                        })
+                        .transpose()?
+                        .ok_or(rusqlite::Error::InvalidParameterName(name))?,
+                };
+
+                statement.raw_bind_parameter(index, value)?;
             }
+
+            Ok(())
         }
+        _ => Err(conversion_error(format!(
+            "parameters must be a list, vector or hash map, found: {params}"
+        ))),
     }
 }
 
+fn bind_positional<'a>(
+    statement: &mut Statement<'_>,
+    count: usize,
+    params: impl Iterator<Item = &'a SteelVal>,
+) -> Result<()> {
+    let expected = statement.parameter_count();
+
+    if count != expected {
+        return Err(rusqlite::Error::InvalidParameterCount(count, expected));
+    }
+
+    for (index, value) in params.enumerate() {
+        statement.raw_bind_parameter(index + 1, value)?;
+    }
+
+    Ok(())
+}
+
+// Columns declared as booleans come back as booleans, since sqlite stores them as integers
+fn boolean_columns(statement: &Statement<'_>) -> Vec<bool> {
+    statement
+        .columns()
+        .iter()
+        .map(|column| {
+            column
+                .decl_type()
+                .map(|x| x.eq_ignore_ascii_case("boolean") || x.eq_ignore_ascii_case("bool"))
+                .unwrap_or(false)
+        })
+        .collect()
+}
+
+fn row_values(row: &Row<'_>, booleans: &[bool]) -> Result<Vec<SteelVal>> {
+    booleans
+        .iter()
+        .enumerate()
+        .map(|(index, boolean)| match row.get::<_, SteelVal>(index)? {
+            SteelVal::IntV(i) if *boolean => Ok(SteelVal::BoolV(i != 0)),
+            value => Ok(value),
+        })
+        .collect()
+}
+
+fn open(path: SteelString) -> Result<SqliteConnection> {
+    Connection::open(path.as_str()).map(SqliteConnection::new)
+}
+
+fn open_in_memory() -> Result<SqliteConnection> {
+    Connection::open_in_memory().map(SqliteConnection::new)
+}
+
+fn open_flags(flags: &List<SteelVal>) -> Result<OpenFlags> {
+    let mut parsed = OpenFlags::empty();
+
+    for flag in flags {
+        parsed |= match flag {
+            SteelVal::SymbolV(s) if s.as_str() == "read-only" => OpenFlags::SQLITE_OPEN_READ_ONLY,
+            SteelVal::SymbolV(s) if s.as_str() == "read-write" => OpenFlags::SQLITE_OPEN_READ_WRITE,
+            SteelVal::SymbolV(s) if s.as_str() == "create" => OpenFlags::SQLITE_OPEN_CREATE,
+            SteelVal::SymbolV(s) if s.as_str() == "uri" => OpenFlags::SQLITE_OPEN_URI,
+            SteelVal::SymbolV(s) if s.as_str() == "memory" => OpenFlags::SQLITE_OPEN_MEMORY,
+            SteelVal::SymbolV(s) if s.as_str() == "no-mutex" => OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            SteelVal::SymbolV(s) if s.as_str() == "full-mutex" => OpenFlags::SQLITE_OPEN_FULL_MUTEX,
+            SteelVal::SymbolV(s) if s.as_str() == "shared-cache" => {
+                OpenFlags::SQLITE_OPEN_SHARED_CACHE
+            }
+            SteelVal::SymbolV(s) if s.as_str() == "private-cache" => {
+                OpenFlags::SQLITE_OPEN_PRIVATE_CACHE
+            }
+            _ => return Err(conversion_error(format!("unknown open flag: {flag}"))),
+        };
+    }
+
+    Ok(parsed)
+}
+
+fn open_with_flags(path: SteelString, flags: List<SteelVal>) -> Result<SqliteConnection> {
+    Connection::open_with_flags(path.as_str(), open_flags(&flags)?).map(SqliteConnection::new)
+}
+
 fn prepare_and_execute(
-    connection: &Connection,
+    connection: &SqliteConnection,
     sql: SteelString,
-    params: List<im_lists::list::List<SteelVal>>,
+    params: List<SteelVal>,
 ) -> Result<usize> {
-    let mut statement = connection.prepare(sql.as_str())?;
+    let mut statement = connection.connection.prepare_cached(sql.as_str())?;
 
     let mut count = 0;
 
     for group in params {
-        count += statement.execute(params_from_iter(group))?;
+        bind_params(&mut statement, &group)?;
+        count += statement.raw_execute()?;
     }
 
     Ok(count)
@@ -66,77 +222,512 @@ fn prepare_and_execute(
 
 // Consider returning a struct directly...
 fn prepare_and_query(
-    connection: &Connection,
+    connection: &SqliteConnection,
     sql: SteelString,
-    params: List<SteelVal>,
+    params: SteelVal,
 ) -> Result<List<List<SteelVal>>> {
-    let mut statement = connection.prepare(sql.as_str())?;
+    let mut statement = connection.connection.prepare_cached(sql.as_str())?;
+    bind_params(&mut statement, &params)?;
 
-    let mut rows = statement.query(params_from_iter(params))?;
+    let booleans = boolean_columns(&statement);
+    let mut rows = statement.raw_query();
 
     let mut results = Vec::new();
 
-    let mut width: Option<usize> = None;
+    while let Some(row) = rows.next()? {
+        results.push(List::from(row_values(row, &booleans)?));
+    }
+
+    Ok(List::from(results))
+}
+
+fn query_rows(
+    connection: &SqliteConnection,
+    sql: SteelString,
+    params: SteelVal,
+) -> Result<List<SteelVal>> {
+    let mut statement = connection.connection.prepare_cached(sql.as_str())?;
+    bind_params(&mut statement, &params)?;
+
+    let booleans = boolean_columns(&statement);
+    let names = statement
+        .column_names()
+        .into_iter()
+        .map(|x| SteelVal::StringV(x.into()))
+        .collect::<Vec<_>>();
+
+    let mut rows = statement.raw_query();
+
+    let mut results = Vec::new();
 
     while let Some(row) = rows.next()? {
-        if let Some(width) = width {
-            // TODO: Save the row length for the next iteration, so that we can pre allocate
-            // the row width
-            let mut computed_row: Vec<SteelVal> = Vec::with_capacity(width);
+        let map = names
+            .iter()
+            .cloned()
+            .zip(row_values(row, &booleans)?)
+            .collect::<im_rc::HashMap<_, _>>();
 
-            for i in 0..width {
-                computed_row.push(row.get(i).unwrap())
-            }
+        results.push(SteelVal::HashMapV(Gc::new(map)));
+    }
+
+    Ok(List::from(results))
+}
 
-            results.push(List::from(computed_row));
-        } else {
-            // TODO: Save the row length for the next iteration, so that we can pre allocate
-            // the row width
-            let mut computed_row: Vec<SteelVal> = Vec::new();
-            let mut i = 0;
+// A query that is read a row at a time. The rows borrow the statement, which in turn borrows
+// the connection, so both are kept here alongside the rows and torn down in that order.
+struct QueryStream {
+    rows: ManuallyDrop<Rows<'static>>,
+    statement: *mut Statement<'static>,
+    booleans: Vec<bool>,
+    // Set once reading a row fails. The error is handed out once, and the stream ends there.
+    failed: bool,
+    // Dropped after `drop` below has run, once nothing borrows it anymore
+    _connection: Rc<Connection>,
+}
 
-            while let Ok(value) = row.get(i) {
-                computed_row.push(value);
-                i += 1;
-            }
+impl QueryStream {
+    fn new(connection: Rc<Connection>, sql: &str, params: &SteelVal) -> Result<Self> {
+        // SAFETY: The connection lives behind the `Rc`, which is kept alive for as long as the
+        // stream is, so the reference stays valid even though the stream moves around.
+        let borrowed: &'static Connection = unsafe { &*Rc::as_ptr(&connection) };
+
+        let mut statement = borrowed.prepare(sql)?;
+        bind_params(&mut statement, params)?;
+        let booleans = boolean_columns(&statement);
+
+        let statement = Box::into_raw(Box::new(statement));
 
-            width = Some(i);
+        // SAFETY: The statement is only ever reached through the rows from here on, and it
+        // is freed in `drop` only after the rows are.
+        let rows = unsafe { (*statement).raw_query() };
 
-            results.push(List::from(computed_row));
+        Ok(QueryStream {
+            rows: ManuallyDrop::new(rows),
+            statement,
+            booleans,
+            failed: false,
+            _connection: connection,
+        })
+    }
+}
+
+impl Drop for QueryStream {
+    fn drop(&mut self) {
+        // SAFETY: The rows are dropped exactly once, before the statement they borrow, and
+        // the statement came from `Box::into_raw` in `new`.
+        unsafe {
+            ManuallyDrop::drop(&mut self.rows);
+            drop(Box::from_raw(self.statement));
         }
     }
+}
 
-    // todo!()
+impl Iterator for QueryStream {
+    type Item = crate::rvals::Result<SteelVal>;
 
-    Ok(List::from(results))
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let row = match self.rows.next() {
+            Ok(Some(row)) => row_values(row, &self.booleans),
+            Ok(None) => return None,
+            Err(e) => Err(e),
+        };
+
+        match row {
+            Ok(values) => Some(Ok(SteelVal::ListV(values.into()))),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(SteelErr::new(
+                    ErrorKind::Generic,
+                    format!("connection/query-stream!: {e}"),
+                )))
+            }
+        }
+    }
 }
 
-// Consider returning a struct directly...
-fn _query_to_struct_list(
-    _connection: &Connection,
-    _sql: SteelString,
-    _params: List<SteelVal>,
-    _struct_descriptor: SteelVal,
-) -> Result<List<List<SteelVal>>> {
-    todo!("Add pushing a struct constructor directly on to the values returned for one less allocation")
+// Runs the query a row at a time, rather than reading every row up front. The result can be
+// used as the source of a transducer. A row that can't be read raises an error when it is
+// pulled, and ends the stream.
+fn query_stream(
+    connection: &SqliteConnection,
+    sql: SteelString,
+    params: SteelVal,
+) -> Result<SteelVal> {
+    let stream = QueryStream::new(Rc::clone(&connection.connection), sql.as_str(), &params)?;
+
+    Ok(BuiltInDataStructureIterator::Fallible(Box::new(stream)).into_boxed_iterator())
 }
 
 fn connection_wrapper(
-    connection: &Connection,
+    connection: &SqliteConnection,
     sql: SteelString,
-    params: List<SteelVal>,
+    params: SteelVal,
 ) -> Result<usize> {
-    connection.execute(&sql, params_from_iter(params))
+    let mut statement = connection.connection.prepare_cached(sql.as_str())?;
+    bind_params(&mut statement, &params)?;
+    statement.raw_execute()
+}
+
+fn set_statement_cache_capacity(connection: &SqliteConnection, capacity: usize) {
+    connection
+        .connection
+        .set_prepared_statement_cache_capacity(capacity)
+}
+
+fn flush_statement_cache(connection: &SqliteConnection) {
+    connection.connection.flush_prepared_statement_cache()
+}
+
+fn last_insert_rowid(connection: &SqliteConnection) -> isize {
+    connection.connection.last_insert_rowid() as isize
+}
+
+// Transactions nest by way of savepoints, since sqlite can't begin a transaction inside of
+// another one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TransactionKind {
+    Transaction,
+    Savepoint,
+}
+
+fn begin_transaction(connection: &Connection) -> Result<TransactionKind> {
+    if connection.is_autocommit() {
+        connection.execute_batch("BEGIN")?;
+        Ok(TransactionKind::Transaction)
+    } else {
+        connection.execute_batch("SAVEPOINT steel_transaction")?;
+        Ok(TransactionKind::Savepoint)
+    }
+}
+
+fn end_transaction(connection: &Connection, kind: TransactionKind, commit: bool) -> Result<()> {
+    match (kind, commit) {
+        (TransactionKind::Transaction, true) => connection.execute_batch("COMMIT"),
+        (TransactionKind::Transaction, false) => connection.execute_batch("ROLLBACK"),
+        (TransactionKind::Savepoint, true) => connection.execute_batch("RELEASE steel_transaction"),
+        (TransactionKind::Savepoint, false) => {
+            connection.execute_batch("ROLLBACK TO steel_transaction; RELEASE steel_transaction")
+        }
+    }
+}
+
+fn is_err_result(value: &SteelVal) -> bool {
+    match value {
+        SteelVal::CustomStruct(s) => s.borrow().is_err(),
+        _ => false,
+    }
+}
+
+// Calls the thunk inside of a transaction, returning whatever it returns. The transaction is
+// rolled back if the thunk raises an error or returns an `Err`, and committed otherwise.
+//
+// (with-transaction connection thunk) -> any/c
+fn with_transaction(ctx: &mut VmCore, args: &[SteelVal]) -> Option<crate::rvals::Result<SteelVal>> {
+    Some(with_transaction_result(ctx, args))
+}
+
+fn with_transaction_result(ctx: &mut VmCore, args: &[SteelVal]) -> crate::rvals::Result<SteelVal> {
+    if args.len() != 2 {
+        stop!(ArityMismatch => "with-transaction expects two arguments, found: {}", args.len());
+    }
+
+    // Hold on to the connection itself rather than a borrow of the value, so the thunk is
+    // free to use it
+    let connection = SqliteConnection::from_steelval(&args[0])?.connection;
+
+    let thunk = &args[1];
+
+    if !thunk.is_function() {
+        stop!(TypeMismatch => "with-transaction expects a function, found: {}", thunk);
+    }
+
+    let kind = match begin_transaction(&connection) {
+        Ok(kind) => kind,
+        Err(e) => stop!(Generic => "with-transaction: unable to begin transaction: {}", e),
+    };
+
+    let result = ctx.call_function_many_args(thunk, List::new());
+
+    let commit = matches!(&result, Ok(value) if !is_err_result(value));
+
+    if let Err(e) = end_transaction(&connection, kind, commit) {
+        // Leave the connection usable, even if the commit itself failed
+        if commit {
+            let _ = end_transaction(&connection, kind, false);
+        }
+
+        stop!(Generic => "with-transaction: unable to end transaction: {}", e);
+    }
+
+    result
 }
 
 pub fn sqlite_module() -> BuiltInModule {
     let mut module = BuiltInModule::new("steel/sqlite".to_string());
 
     module
-        .register_fn("connection/open-in-memory", Connection::open_in_memory)
+        .register_fn("connection/open", open)
+        .register_fn("connection/open-with-flags", open_with_flags)
+        .register_fn("connection/open-in-memory", open_in_memory)
         .register_fn("connection/execute!", connection_wrapper)
         .register_fn("connection/prepare-and-execute!", prepare_and_execute)
-        .register_fn("connection/prepare-and-query!", prepare_and_query);
+        .register_fn("connection/prepare-and-query!", prepare_and_query)
+        .register_fn("connection/query-rows!", query_rows)
+        .register_fn("connection/query-stream!", query_stream)
+        .register_fn("connection/last-insert-rowid", last_insert_rowid)
+        .register_fn(
+            "connection/set-statement-cache-capacity!",
+            set_statement_cache_capacity,
+        )
+        .register_fn("connection/flush-statement-cache!", flush_statement_cache)
+        .register_value("with-transaction", SteelVal::BuiltIn(with_transaction));
 
     module
 }
+
+#[cfg(test)]
+mod sqlite_tests {
+    use super::*;
+
+    fn connection() -> SqliteConnection {
+        let connection = open_in_memory().unwrap();
+
+        connection
+            .connection
+            .execute_batch(
+                "CREATE TABLE people (id INTEGER PRIMARY KEY, name TEXT, active BOOLEAN, avatar BLOB)",
+            )
+            .unwrap();
+
+        connection
+    }
+
+    fn count(connection: &SqliteConnection) -> i64 {
+        connection
+            .connection
+            .query_row("SELECT COUNT(*) FROM people", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn named_and_positional_parameters() {
+        let connection = connection();
+
+        let named = im_rc::hashmap! {
+            SteelVal::SymbolV("name".into()) => SteelVal::StringV("alice".into()),
+            SteelVal::SymbolV("active".into()) => SteelVal::BoolV(true),
+            SteelVal::SymbolV(":avatar".into()) => SteelVal::ByteVector(Gc::new(RefCell::new(vec![1, 2, 3]))),
+        };
+
+        connection_wrapper(
+            &connection,
+            "INSERT INTO people (name, active, avatar) VALUES (:name, @active, :avatar)".into(),
+            SteelVal::HashMapV(Gc::new(named)),
+        )
+        .unwrap();
+
+        connection_wrapper(
+            &connection,
+            "INSERT INTO people (name, active, avatar) VALUES (?, ?, ?)".into(),
+            SteelVal::ListV(
+                vec![
+                    SteelVal::StringV("bob".into()),
+                    SteelVal::BoolV(false),
+                    SteelVal::Void,
+                ]
+                .into(),
+            ),
+        )
+        .unwrap();
+
+        assert!(connection_wrapper(
+            &connection,
+            "INSERT INTO people (name) VALUES (?)".into(),
+            SteelVal::ListV(List::new()),
+        )
+        .is_err());
+
+        assert!(connection_wrapper(
+            &connection,
+            "INSERT INTO people (name) VALUES (?)".into(),
+            SteelVal::ListV(vec![SteelVal::ListV(List::new())].into()),
+        )
+        .is_err());
+
+        let rows = query_rows(
+            &connection,
+            "SELECT name, active, avatar FROM people ORDER BY id".into(),
+            SteelVal::ListV(List::new()),
+        )
+        .unwrap();
+
+        let first = match &rows[0] {
+            SteelVal::HashMapV(map) => map.clone(),
+            other => panic!("expected a hash map, found: {other}"),
+        };
+
+        assert_eq!(
+            first[&SteelVal::StringV("name".into())],
+            SteelVal::StringV("alice".into())
+        );
+        assert_eq!(
+            first[&SteelVal::StringV("active".into())],
+            SteelVal::BoolV(true)
+        );
+        assert_eq!(
+            first[&SteelVal::StringV("avatar".into())],
+            SteelVal::ByteVector(Gc::new(RefCell::new(vec![1, 2, 3])))
+        );
+
+        let streamed = query_stream(
+            &connection,
+            "SELECT name FROM people ORDER BY id".into(),
+            SteelVal::ListV(List::new()),
+        )
+        .unwrap();
+
+        let names = match streamed {
+            SteelVal::BoxedIterator(iter) => iter
+                .borrow_mut()
+                .by_ref()
+                .collect::<crate::rvals::Result<Vec<_>>>()
+                .unwrap(),
+            other => panic!("expected an iterator, found: {other}"),
+        };
+
+        assert_eq!(
+            names,
+            vec![
+                SteelVal::ListV(vec![SteelVal::StringV("alice".into())].into()),
+                SteelVal::ListV(vec![SteelVal::StringV("bob".into())].into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn query_stream_raises_errors() {
+        let connection = connection();
+
+        connection
+            .connection
+            .execute_batch("INSERT INTO people (id) VALUES (1), (-9223372036854775808), (3)")
+            .unwrap();
+
+        // Taking the absolute value of the smallest integer overflows on the second row
+        let streamed = query_stream(
+            &connection,
+            "SELECT abs(id) FROM people ORDER BY id DESC".into(),
+            SteelVal::ListV(List::new()),
+        )
+        .unwrap();
+
+        let rows = match streamed {
+            SteelVal::BoxedIterator(iter) => iter,
+            other => panic!("expected an iterator, found: {other}"),
+        };
+
+        let mut rows = rows.borrow_mut();
+
+        assert_eq!(
+            rows.next().unwrap().unwrap(),
+            SteelVal::ListV(vec![SteelVal::IntV(3)].into())
+        );
+        assert_eq!(
+            rows.next().unwrap().unwrap(),
+            SteelVal::ListV(vec![SteelVal::IntV(1)].into())
+        );
+        assert!(rows.next().unwrap().is_err());
+        assert!(rows.next().is_none());
+    }
+
+    #[test]
+    fn with_transaction_rolls_back_when_the_body_fails() {
+        use crate::steel_vm::engine::Engine;
+
+        let mut engine = Engine::new();
+
+        engine
+            .run(
+                r#"
+                (require-builtin steel/sqlite)
+                (define connection (Ok->value (connection/open-in-memory)))
+                (connection/execute! connection "CREATE TABLE people (name TEXT)" '())
+                (define (count)
+                  (hash-ref (car (Ok->value (connection/query-rows! connection "SELECT COUNT(*) AS n FROM people" '())))
+                            "n"))
+                "#,
+            )
+            .unwrap();
+
+        // The body raises an error after it has inserted a row
+        assert!(engine
+            .run(
+                r#"
+                (with-transaction connection
+                  (lambda ()
+                    (connection/execute! connection "INSERT INTO people VALUES ('alice')" '())
+                    (error "something went wrong")))
+                "#,
+            )
+            .is_err());
+
+        // The body returns an `Err` from a statement that failed
+        engine
+            .run(
+                r#"
+                (with-transaction connection
+                  (lambda ()
+                    (connection/execute! connection "INSERT INTO people VALUES ('bob')" '())
+                    (connection/execute! connection "INSERT INTO missing VALUES ('bob')" '())))
+                "#,
+            )
+            .unwrap();
+
+        assert_eq!(engine.run("(count)").unwrap(), vec![SteelVal::IntV(0)]);
+
+        engine
+            .run(
+                r#"
+                (with-transaction connection
+                  (lambda ()
+                    (connection/execute! connection "INSERT INTO people VALUES ('carol')" '())))
+                "#,
+            )
+            .unwrap();
+
+        assert_eq!(engine.run("(count)").unwrap(), vec![SteelVal::IntV(1)]);
+    }
+
+    #[test]
+    fn nested_transactions_roll_back() {
+        let connection = connection();
+        let raw = &connection.connection;
+
+        let outer = begin_transaction(raw).unwrap();
+        raw.execute_batch("INSERT INTO people (name) VALUES ('alice')")
+            .unwrap();
+
+        let inner = begin_transaction(raw).unwrap();
+        assert_eq!(inner, TransactionKind::Savepoint);
+        raw.execute_batch("INSERT INTO people (name) VALUES ('bob')")
+            .unwrap();
+        end_transaction(raw, inner, false).unwrap();
+
+        assert_eq!(count(&connection), 1);
+
+        end_transaction(raw, outer, true).unwrap();
+        assert_eq!(count(&connection), 1);
+
+        let outer = begin_transaction(raw).unwrap();
+        raw.execute_batch("INSERT INTO people (name) VALUES ('carol')")
+            .unwrap();
+        end_transaction(raw, outer, false).unwrap();
+
+        assert_eq!(count(&connection), 1);
+        assert!(raw.is_autocommit());
+    }
+}
@@ -1,5 +1,9 @@
-use crate::{rvals::Custom, steel_vm::builtin::MarkdownDoc};
-use chrono::Local;
+use crate::{rvals::Custom, steel_vm::builtin::MarkdownDoc, SteelErr};
+use chrono::{
+    DateTime, Datelike, FixedOffset, Local, Months, NaiveDate, NaiveDateTime, TimeZone, Timelike,
+    Utc,
+};
+use std::fmt::Write;
 use std::{time::Duration, time::Instant};
 
 use crate::steel_vm::builtin::BuiltInModule;
@@ -9,8 +13,8 @@ pub(crate) const TIME_MODULE_DOC: MarkdownDoc<'static> = MarkdownDoc(
     r#"
 
 # steel/time
-    
-Contains direct wrappers around the Rust `std::time::Instant` and `std::time::Duration` modules. 
+
+Contains direct wrappers around the Rust `std::time::Instant` and `std::time::Duration` modules.
 For example, to measure the time something takes:
 
 ```scheme
@@ -19,6 +23,27 @@ For example, to measure the time something takes:
 (displayln (instant/elapsed t))
 ```
 
+## Calendar values
+
+A `datetime` is a point in time along with the offset from UTC it is expressed in, and a `date`
+is a day on the calendar with no time or offset attached. Both are built on `chrono`.
+
+```scheme
+(define release (datetime/parse "2023-03-31T09:30:00+02:00"))
+(datetime->string (datetime->utc release)) ;; => "2023-03-31T07:30:00+00:00"
+(datetime/format release "%A %d %B") ;; => "Friday 31 March"
+
+(define d (datetime->date release))
+(date/end-of-month (date/add-months d 1)) ;; => #<date 2023-04-30>
+(date/weekday d) ;; => 5
+```
+
+`datetime/parse` takes RFC 3339 and ISO 8601 strings, and `date/parse` takes ISO 8601 dates.
+The `parse-with-format` and `format` variants take `strftime` style format strings. A datetime
+without an offset is taken to be in UTC. Weekdays are numbered from 1 for Monday to 7 for Sunday.
+
+Anything that can fail, like parsing or arithmetic that goes out of range, raises an error.
+
 "#,
 );
 
@@ -34,6 +59,265 @@ fn sleep_millis(millis: usize) {
     std::thread::sleep(Duration::from_millis(millis.try_into().unwrap()))
 }
 
+fn time_error(function: &str, message: impl std::fmt::Display) -> SteelErr {
+    SteelErr::new(
+        crate::rerrs::ErrorKind::Generic,
+        format!("{function}: {message}"),
+    )
+}
+
+fn out_of_range(function: &str) -> SteelErr {
+    time_error(function, "result is out of range")
+}
+
+// Formatting with an invalid format string fails while writing it out, rather than up front
+fn format_with(function: &str, value: impl std::fmt::Display) -> Result<String, SteelErr> {
+    let mut output = String::new();
+    write!(output, "{value}").map_err(|_| time_error(function, "invalid format string"))?;
+    Ok(output)
+}
+
+// `chrono::Duration::seconds` panics outside of the range it can hold
+fn signed_seconds(seconds: i64) -> Option<chrono::Duration> {
+    if seconds.unsigned_abs() <= (i64::MAX / 1000) as u64 {
+        Some(chrono::Duration::seconds(seconds))
+    } else {
+        None
+    }
+}
+
+fn signed_days(days: i64) -> Option<chrono::Duration> {
+    days.checked_mul(24 * 60 * 60).and_then(signed_seconds)
+}
+
+fn months(months: i64) -> Option<Months> {
+    u32::try_from(months.unsigned_abs()).ok().map(Months::new)
+}
+
+fn utc_datetime(naive: NaiveDateTime) -> DateTime<FixedOffset> {
+    Utc.from_utc_datetime(&naive).into()
+}
+
+fn datetime_now() -> DateTime<FixedOffset> {
+    Utc::now().into()
+}
+
+fn datetime_now_local() -> DateTime<FixedOffset> {
+    Local::now().into()
+}
+
+fn parse_datetime(input: String) -> Result<DateTime<FixedOffset>, SteelErr> {
+    DateTime::parse_from_rfc3339(&input)
+        .or_else(|_| DateTime::parse_from_str(&input, "%+"))
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(&input, "%Y-%m-%dT%H:%M:%S%.f").map(utc_datetime)
+        })
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(&input, "%Y-%m-%d %H:%M:%S%.f").map(utc_datetime)
+        })
+        .map_err(|e| time_error("datetime/parse", format!("unable to parse {input:?}: {e}")))
+}
+
+fn parse_datetime_with_format(
+    input: String,
+    format: String,
+) -> Result<DateTime<FixedOffset>, SteelErr> {
+    DateTime::parse_from_str(&input, &format)
+        .or_else(|_| NaiveDateTime::parse_from_str(&input, &format).map(utc_datetime))
+        .map_err(|e| {
+            time_error(
+                "datetime/parse-with-format",
+                format!("unable to parse {input:?} with {format:?}: {e}"),
+            )
+        })
+}
+
+fn datetime_to_string(datetime: DateTime<FixedOffset>) -> String {
+    datetime.to_rfc3339()
+}
+
+fn format_datetime(datetime: DateTime<FixedOffset>, format: String) -> Result<String, SteelErr> {
+    format_with("datetime/format", datetime.format(&format))
+}
+
+fn unix_to_datetime(seconds: i64) -> Result<DateTime<FixedOffset>, SteelErr> {
+    Utc.timestamp_opt(seconds, 0)
+        .single()
+        .map(Into::into)
+        .ok_or_else(|| out_of_range("unix->datetime"))
+}
+
+fn unix_millis_to_datetime(millis: i64) -> Result<DateTime<FixedOffset>, SteelErr> {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .map(Into::into)
+        .ok_or_else(|| out_of_range("unix-millis->datetime"))
+}
+
+fn datetime_add(
+    datetime: DateTime<FixedOffset>,
+    duration: Duration,
+) -> Result<DateTime<FixedOffset>, SteelErr> {
+    chrono::Duration::from_std(duration)
+        .ok()
+        .and_then(|duration| datetime.checked_add_signed(duration))
+        .ok_or_else(|| out_of_range("datetime/add"))
+}
+
+fn datetime_sub(
+    datetime: DateTime<FixedOffset>,
+    duration: Duration,
+) -> Result<DateTime<FixedOffset>, SteelErr> {
+    chrono::Duration::from_std(duration)
+        .ok()
+        .and_then(|duration| datetime.checked_sub_signed(duration))
+        .ok_or_else(|| out_of_range("datetime/sub"))
+}
+
+fn datetime_add_seconds(
+    datetime: DateTime<FixedOffset>,
+    seconds: i64,
+) -> Result<DateTime<FixedOffset>, SteelErr> {
+    signed_seconds(seconds)
+        .and_then(|duration| datetime.checked_add_signed(duration))
+        .ok_or_else(|| out_of_range("datetime/add-seconds"))
+}
+
+fn datetime_add_days(
+    datetime: DateTime<FixedOffset>,
+    days: i64,
+) -> Result<DateTime<FixedOffset>, SteelErr> {
+    signed_days(days)
+        .and_then(|duration| datetime.checked_add_signed(duration))
+        .ok_or_else(|| out_of_range("datetime/add-days"))
+}
+
+fn datetime_add_months(
+    datetime: DateTime<FixedOffset>,
+    count: i64,
+) -> Result<DateTime<FixedOffset>, SteelErr> {
+    months(count)
+        .and_then(|months| {
+            if count < 0 {
+                datetime.checked_sub_months(months)
+            } else {
+                datetime.checked_add_months(months)
+            }
+        })
+        .ok_or_else(|| out_of_range("datetime/add-months"))
+}
+
+/// The number of whole seconds from `start` to `end`, which is negative if `end` comes first
+fn datetime_seconds_between(start: DateTime<FixedOffset>, end: DateTime<FixedOffset>) -> isize {
+    end.signed_duration_since(start).num_seconds() as isize
+}
+
+fn datetime_duration_since(
+    datetime: DateTime<FixedOffset>,
+    earlier: DateTime<FixedOffset>,
+) -> Result<Duration, SteelErr> {
+    datetime
+        .signed_duration_since(earlier)
+        .to_std()
+        .map_err(|_| time_error("datetime/duration-since", "the second datetime is later"))
+}
+
+fn datetime_to_utc(datetime: DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+    datetime.with_timezone(&Utc).into()
+}
+
+fn datetime_to_local(datetime: DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+    datetime.with_timezone(&Local).into()
+}
+
+fn datetime_with_offset(
+    datetime: DateTime<FixedOffset>,
+    seconds: i32,
+) -> Result<DateTime<FixedOffset>, SteelErr> {
+    FixedOffset::east_opt(seconds)
+        .map(|offset| datetime.with_timezone(&offset))
+        .ok_or_else(|| time_error("datetime/with-offset", format!("invalid offset: {seconds}")))
+}
+
+fn datetime_offset(datetime: DateTime<FixedOffset>) -> i32 {
+    datetime.offset().local_minus_utc()
+}
+
+fn new_date(year: i32, month: u32, day: u32) -> Result<NaiveDate, SteelErr> {
+    NaiveDate::from_ymd_opt(year, month, day)
+        .ok_or_else(|| time_error("date", format!("invalid date: {year}-{month}-{day}")))
+}
+
+fn date_today() -> NaiveDate {
+    Local::now().date_naive()
+}
+
+fn parse_date(input: String) -> Result<NaiveDate, SteelErr> {
+    input
+        .parse::<NaiveDate>()
+        .map_err(|e| time_error("date/parse", format!("unable to parse {input:?}: {e}")))
+}
+
+fn parse_date_with_format(input: String, format: String) -> Result<NaiveDate, SteelErr> {
+    NaiveDate::parse_from_str(&input, &format).map_err(|e| {
+        time_error(
+            "date/parse-with-format",
+            format!("unable to parse {input:?} with {format:?}: {e}"),
+        )
+    })
+}
+
+fn date_to_string(date: NaiveDate) -> String {
+    date.to_string()
+}
+
+fn format_date(date: NaiveDate, format: String) -> Result<String, SteelErr> {
+    format_with("date/format", date.format(&format))
+}
+
+fn date_add_days(date: NaiveDate, days: i64) -> Result<NaiveDate, SteelErr> {
+    signed_days(days)
+        .and_then(|duration| date.checked_add_signed(duration))
+        .ok_or_else(|| out_of_range("date/add-days"))
+}
+
+fn date_add_months(date: NaiveDate, count: i64) -> Result<NaiveDate, SteelErr> {
+    months(count)
+        .and_then(|months| {
+            if count < 0 {
+                date.checked_sub_months(months)
+            } else {
+                date.checked_add_months(months)
+            }
+        })
+        .ok_or_else(|| out_of_range("date/add-months"))
+}
+
+/// The number of days from `start` to `end`, which is negative if `end` comes first
+fn date_days_between(start: NaiveDate, end: NaiveDate) -> isize {
+    end.signed_duration_since(start).num_days() as isize
+}
+
+fn start_of_month(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).unwrap()
+}
+
+fn end_of_month(date: NaiveDate) -> NaiveDate {
+    start_of_month(date)
+        .checked_add_months(Months::new(1))
+        .and_then(|next| next.pred_opt())
+        // There is no next month after the last one chrono can represent
+        .unwrap_or(NaiveDate::MAX)
+}
+
+fn days_in_month(date: NaiveDate) -> u32 {
+    end_of_month(date).day()
+}
+
+fn date_to_datetime(date: NaiveDate) -> DateTime<FixedOffset> {
+    utc_datetime(date.and_hms_opt(0, 0, 0).unwrap())
+}
+
 pub fn time_module() -> BuiltInModule {
     let mut module = BuiltInModule::new("steel/time".to_string());
 
@@ -48,8 +332,211 @@ pub fn time_module() -> BuiltInModule {
         .register_fn("local-time/now!", current_time_formatted)
         .register_fn("time/sleep-ms", sleep_millis);
 
+    module
+        .register_fn("datetime/now", datetime_now)
+        .register_fn("datetime/now-local", datetime_now_local)
+        .register_fn("datetime/parse", parse_datetime)
+        .register_fn("datetime/parse-with-format", parse_datetime_with_format)
+        .register_fn("datetime->string", datetime_to_string)
+        .register_fn("datetime/format", format_datetime)
+        .register_fn("unix->datetime", unix_to_datetime)
+        .register_fn("unix-millis->datetime", unix_millis_to_datetime)
+        .register_fn("datetime->unix", |datetime: DateTime<FixedOffset>| {
+            datetime.timestamp() as isize
+        })
+        .register_fn(
+            "datetime->unix-millis",
+            |datetime: DateTime<FixedOffset>| datetime.timestamp_millis() as isize,
+        )
+        .register_fn("datetime/add", datetime_add)
+        .register_fn("datetime/sub", datetime_sub)
+        .register_fn("datetime/add-seconds", datetime_add_seconds)
+        .register_fn("datetime/add-days", datetime_add_days)
+        .register_fn("datetime/add-months", datetime_add_months)
+        .register_fn("datetime/seconds-between", datetime_seconds_between)
+        .register_fn("datetime/duration-since", datetime_duration_since)
+        .register_fn("datetime->utc", datetime_to_utc)
+        .register_fn("datetime->local", datetime_to_local)
+        .register_fn("datetime/with-offset", datetime_with_offset)
+        .register_fn("datetime/offset", datetime_offset)
+        .register_fn("datetime->date", |datetime: DateTime<FixedOffset>| {
+            datetime.date_naive()
+        })
+        .register_fn("datetime/year", |datetime: DateTime<FixedOffset>| {
+            datetime.year()
+        })
+        .register_fn("datetime/month", |datetime: DateTime<FixedOffset>| {
+            datetime.month()
+        })
+        .register_fn("datetime/day", |datetime: DateTime<FixedOffset>| {
+            datetime.day()
+        })
+        .register_fn("datetime/hour", |datetime: DateTime<FixedOffset>| {
+            datetime.hour()
+        })
+        .register_fn("datetime/minute", |datetime: DateTime<FixedOffset>| {
+            datetime.minute()
+        })
+        .register_fn("datetime/second", |datetime: DateTime<FixedOffset>| {
+            datetime.second()
+        })
+        .register_fn("datetime/weekday", |datetime: DateTime<FixedOffset>| {
+            datetime.weekday().number_from_monday()
+        })
+        .register_fn(
+            "datetime=?",
+            |left: DateTime<FixedOffset>, right: DateTime<FixedOffset>| left == right,
+        )
+        .register_fn(
+            "datetime<?",
+            |left: DateTime<FixedOffset>, right: DateTime<FixedOffset>| left < right,
+        )
+        .register_fn(
+            "datetime<=?",
+            |left: DateTime<FixedOffset>, right: DateTime<FixedOffset>| left <= right,
+        )
+        .register_fn(
+            "datetime>?",
+            |left: DateTime<FixedOffset>, right: DateTime<FixedOffset>| left > right,
+        )
+        .register_fn(
+            "datetime>=?",
+            |left: DateTime<FixedOffset>, right: DateTime<FixedOffset>| left >= right,
+        );
+
+    module
+        .register_fn("date", new_date)
+        .register_fn("date/today", date_today)
+        .register_fn("date/parse", parse_date)
+        .register_fn("date/parse-with-format", parse_date_with_format)
+        .register_fn("date->string", date_to_string)
+        .register_fn("date/format", format_date)
+        .register_fn("date->datetime", date_to_datetime)
+        .register_fn("date/add-days", date_add_days)
+        .register_fn("date/add-months", date_add_months)
+        .register_fn("date/days-between", date_days_between)
+        .register_fn("date/start-of-month", start_of_month)
+        .register_fn("date/end-of-month", end_of_month)
+        .register_fn("date/days-in-month", days_in_month)
+        .register_fn("date/year", |date: NaiveDate| date.year())
+        .register_fn("date/month", |date: NaiveDate| date.month())
+        .register_fn("date/day", |date: NaiveDate| date.day())
+        .register_fn("date/weekday", |date: NaiveDate| {
+            date.weekday().number_from_monday()
+        })
+        .register_fn("date=?", |left: NaiveDate, right: NaiveDate| left == right)
+        .register_fn("date<?", |left: NaiveDate, right: NaiveDate| left < right)
+        .register_fn("date<=?", |left: NaiveDate, right: NaiveDate| left <= right)
+        .register_fn("date>?", |left: NaiveDate, right: NaiveDate| left > right)
+        .register_fn("date>=?", |left: NaiveDate, right: NaiveDate| left >= right);
+
     module
 }
 
 impl Custom for Instant {}
 impl Custom for Duration {}
+
+impl Custom for DateTime<FixedOffset> {
+    fn fmt(&self) -> Option<std::result::Result<String, std::fmt::Error>> {
+        Some(Ok(format!("#<datetime {}>", self.to_rfc3339())))
+    }
+}
+
+impl Custom for NaiveDate {
+    fn fmt(&self) -> Option<std::result::Result<String, std::fmt::Error>> {
+        Some(Ok(format!("#<date {}>", self)))
+    }
+}
+
+#[cfg(test)]
+mod time_tests {
+    use super::*;
+
+    #[test]
+    fn parse_rfc3339_and_iso8601() {
+        let with_offset = parse_datetime("2023-03-31T09:30:00+02:00".to_string()).unwrap();
+        assert_eq!(datetime_offset(with_offset), 2 * 60 * 60);
+        assert_eq!(
+            datetime_to_string(datetime_to_utc(with_offset)),
+            "2023-03-31T07:30:00+00:00"
+        );
+
+        let without_offset = parse_datetime("2023-03-31T07:30:00".to_string()).unwrap();
+        assert_eq!(without_offset, with_offset);
+
+        assert!(parse_datetime("the thirty first of march".to_string()).is_err());
+    }
+
+    #[test]
+    fn parse_and_format_with_format() {
+        let datetime = parse_datetime_with_format(
+            "31/03/2023 09:30".to_string(),
+            "%d/%m/%Y %H:%M".to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            format_datetime(datetime, "%A %d %B %H:%M".to_string()).unwrap(),
+            "Friday 31 March 09:30"
+        );
+        assert!(format_datetime(datetime, "%Q".to_string()).is_err());
+    }
+
+    #[test]
+    fn unix_timestamps() {
+        let datetime = unix_to_datetime(1_680_255_000).unwrap();
+        assert_eq!(datetime_to_string(datetime), "2023-03-31T09:30:00+00:00");
+        assert_eq!(datetime.timestamp(), 1_680_255_000);
+        assert!(unix_to_datetime(i64::MAX).is_err());
+    }
+
+    #[test]
+    fn datetime_arithmetic() {
+        let start = parse_datetime("2023-01-31T12:00:00Z".to_string()).unwrap();
+
+        let later = datetime_add(start, Duration::from_secs(90)).unwrap();
+        assert_eq!(datetime_seconds_between(start, later), 90);
+        assert_eq!(datetime_seconds_between(later, start), -90);
+        assert_eq!(
+            datetime_duration_since(later, start).unwrap(),
+            Duration::from_secs(90)
+        );
+        assert!(datetime_duration_since(start, later).is_err());
+
+        // Adding months clamps to the end of shorter months
+        let next_month = datetime_add_months(start, 1).unwrap();
+        assert_eq!(next_month.day(), 28);
+        assert_eq!(datetime_add_months(next_month, -1).unwrap().day(), 28);
+
+        assert!(datetime_add_days(start, i64::MAX).is_err());
+    }
+
+    #[test]
+    fn month_boundaries() {
+        let date = new_date(2024, 2, 14).unwrap();
+
+        assert_eq!(start_of_month(date), new_date(2024, 2, 1).unwrap());
+        assert_eq!(end_of_month(date), new_date(2024, 2, 29).unwrap());
+        assert_eq!(days_in_month(new_date(2023, 12, 5).unwrap()), 31);
+        assert_eq!(end_of_month(NaiveDate::MAX), NaiveDate::MAX);
+
+        // 2024-02-14 was a Wednesday
+        assert_eq!(date.weekday().number_from_monday(), 3);
+
+        assert!(new_date(2023, 2, 29).is_err());
+    }
+
+    #[test]
+    fn date_parsing_and_arithmetic() {
+        let date = parse_date("2023-12-30".to_string()).unwrap();
+        let next = date_add_days(date, 3).unwrap();
+
+        assert_eq!(date_to_string(next), "2024-01-02");
+        assert_eq!(date_days_between(date, next), 3);
+        assert_eq!(
+            parse_date_with_format("02.01.2024".to_string(), "%d.%m.%Y".to_string()).unwrap(),
+            next
+        );
+        assert_eq!(date_to_datetime(next).timestamp(), 1_704_153_600);
+    }
+}
@@ -0,0 +1,207 @@
+use std::convert::TryInto;
+
+use toml::Value;
+
+use crate::{
+    gc::Gc,
+    rerrs::{ErrorKind, SteelErr},
+    rvals::{RestArgsIter, Result, SteelString, SteelVal},
+    steel_vm::builtin::BuiltInModule,
+    stop,
+    values::{json_vals::KeyStyle, port::SteelPort},
+};
+
+use steel_derive::function;
+
+pub fn toml_module() -> BuiltInModule {
+    let mut module = BuiltInModule::new("steel/toml");
+    module
+        .register_native_fn_definition(STRING_TO_TOML_DEFINITION)
+        .register_native_fn_definition(READ_TOML_DEFINITION)
+        .register_native_fn_definition(VALUE_TO_TOML_STRING_DEFINITION)
+        .register_native_fn_definition(WRITE_TOML_DEFINITION);
+    module
+}
+
+// Tables become hashes and arrays become lists. Date times have no steel equivalent, so they
+// come through in their TOML form, which `datetime/parse` and `date/parse` from steel/time
+// understand. They stay strings from then on, so writing them back gives quoted strings rather
+// than TOML date times.
+fn toml_to_steelval(value: Value, keys: KeyStyle) -> SteelVal {
+    match value {
+        Value::String(s) => SteelVal::StringV(s.into()),
+        Value::Integer(i) => SteelVal::IntV(i as isize),
+        Value::Float(f) => SteelVal::NumV(f),
+        Value::Boolean(b) => SteelVal::BoolV(b),
+        Value::Datetime(d) => SteelVal::StringV(d.to_string().into()),
+        Value::Array(values) => SteelVal::ListV(
+            values
+                .into_iter()
+                .map(|value| toml_to_steelval(value, keys))
+                .collect(),
+        ),
+        Value::Table(table) => SteelVal::HashMapV(Gc::new(
+            table
+                .into_iter()
+                .map(|(key, value)| (keys.key(key), toml_to_steelval(value, keys)))
+                .collect(),
+        )),
+    }
+}
+
+fn parse(function: &str, input: &str, options: Option<&SteelVal>) -> Result<SteelVal> {
+    let keys = KeyStyle::from_options(options)?;
+
+    match input.parse::<Value>() {
+        Ok(value) => Ok(toml_to_steelval(value, keys)),
+        Err(e) => stop!(Generic => "{} failed: {}", function, e.to_string().trim_end()),
+    }
+}
+
+// Goes through the json conversion, so the same values can be written as either format. The
+// top level has to be a hash, and there's no TOML for void.
+fn to_toml_string(function: &str, value: &SteelVal) -> Result<String> {
+    let json: serde_json::Value = value.clone().try_into()?;
+
+    if !json.is_object() {
+        stop!(TypeMismatch => "{} expects a hash at the top level, found: {}", function, value);
+    }
+
+    toml::to_string(&json)
+        .map_err(|e| SteelErr::new(ErrorKind::Generic, format!("{function} failed: {e}")))
+}
+
+/// Parses a string of TOML. Tables become hashes, arrays become lists, and date times are
+/// left as strings. Nothing marks those strings as date times, so `value->toml-string` writes
+/// them back as plain strings.
+///
+/// (string->toml string [options]) -> hash?
+///
+/// * string : string?
+/// * options : hash? - `'keys` is either `'symbol` (the default) or `'string`
+///
+/// # Examples
+/// ```scheme
+/// > (string->toml "[package]\nname = \"steel\"") ;; => '#hash((package . #hash((name . "steel"))))
+/// ```
+#[function(name = "string->toml")]
+pub fn string_to_toml(
+    input: &SteelString,
+    mut rest: RestArgsIter<'_, &SteelVal>,
+) -> Result<SteelVal> {
+    let options = rest.next().transpose()?;
+
+    if rest.next().is_some() {
+        stop!(ArityMismatch => "string->toml takes at most two arguments");
+    }
+
+    parse("string->toml", input, options)
+}
+
+/// Reads the rest of an input port as TOML.
+///
+/// (read-toml port [options]) -> hash?
+///
+/// * port : input-port?
+/// * options : hash? - `'keys` is either `'symbol` (the default) or `'string`
+#[function(name = "read-toml")]
+pub fn read_toml(port: &Gc<SteelPort>, mut rest: RestArgsIter<'_, &SteelVal>) -> Result<SteelVal> {
+    let options = rest.next().transpose()?;
+
+    if rest.next().is_some() {
+        stop!(ArityMismatch => "read-toml takes at most two arguments");
+    }
+
+    let (_, input) = port.read_all_str()?;
+
+    parse("read-toml", &input, options)
+}
+
+/// Serializes a hash to TOML. Date times read by `string->toml` are written as strings.
+///
+/// (value->toml-string value) -> string?
+///
+/// * value : hash?
+#[function(name = "value->toml-string")]
+pub fn value_to_toml_string(value: &SteelVal) -> Result<SteelVal> {
+    Ok(SteelVal::StringV(
+        to_toml_string("value->toml-string", value)?.into(),
+    ))
+}
+
+/// Writes a hash to an output port as TOML.
+///
+/// (write-toml! port value) -> void?
+///
+/// * port : output-port?
+/// * value : hash?
+#[function(name = "write-toml!")]
+pub fn write_toml(port: &Gc<SteelPort>, value: &SteelVal) -> Result<SteelVal> {
+    let output = to_toml_string("write-toml!", value)?;
+    port.with_writer(|writer| writer.write_all(output.as_bytes()))?;
+    Ok(SteelVal::Void)
+}
+
+#[cfg(test)]
+mod toml_tests {
+    use super::*;
+
+    fn get(value: &SteelVal, key: &str) -> SteelVal {
+        match value {
+            SteelVal::HashMapV(map) => map.get(&SteelVal::SymbolV(key.into())).unwrap().clone(),
+            _ => panic!("expected a hash, found: {value}"),
+        }
+    }
+
+    #[test]
+    fn test_tables_and_arrays() {
+        let value = string_to_toml(
+            &"title = \"example\"\nports = [80, 443]\n\n[owner]\nborn = 1979-05-27\n".into(),
+            RestArgsIter::from_slice(&[]).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(get(&value, "title"), SteelVal::StringV("example".into()));
+        assert_eq!(
+            get(&value, "ports"),
+            SteelVal::ListV(vec![SteelVal::IntV(80), SteelVal::IntV(443)].into())
+        );
+        assert_eq!(
+            get(&get(&value, "owner"), "born"),
+            SteelVal::StringV("1979-05-27".into())
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let input = "[server]\nhost = \"localhost\"\nport = 8080\n";
+        let value = string_to_toml(&input.into(), RestArgsIter::from_slice(&[]).unwrap()).unwrap();
+
+        let output = value_to_toml_string(&value).unwrap();
+
+        assert_eq!(output, SteelVal::StringV(input.into()));
+    }
+
+    #[test]
+    fn test_date_times_are_written_back_as_strings() {
+        let value = string_to_toml(
+            &"born = 1979-05-27\n".into(),
+            RestArgsIter::from_slice(&[]).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            value_to_toml_string(&value).unwrap(),
+            SteelVal::StringV("born = \"1979-05-27\"\n".into())
+        );
+    }
+
+    #[test]
+    fn test_top_level_must_be_a_hash() {
+        let error = value_to_toml_string(&SteelVal::IntV(10)).unwrap_err();
+
+        assert!(error
+            .to_string()
+            .contains("expects a hash at the top level"));
+    }
+}
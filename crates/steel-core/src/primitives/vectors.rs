@@ -4,6 +4,7 @@ use crate::gc::Gc;
 use crate::rvals::SteelVal::*;
 use crate::rvals::{Result, SteelVal};
 use crate::stop;
+use crate::values::contracts::uncontracted;
 use im_rc::Vector;
 
 pub struct VectorOperations {}
@@ -395,7 +396,7 @@ impl VectorOperations {
     pub fn list_vec_null() -> SteelVal {
         SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
             if args.len() == 1 {
-                match &args[0] {
+                match uncontracted(&args[0]) {
                     SteelVal::ListV(l) => Ok(l.is_empty().into()),
                     SteelVal::VectorV(v) => Ok(v.is_empty().into()),
                     _ => Ok(SteelVal::BoolV(false)),
@@ -0,0 +1,472 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::time::Duration;
+
+use notify::event::ModifyKind;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::{
+    AsRefMutSteelVal, AsRefSteelVal, Custom, FromSteelVal, IntoSteelVal, RestArgsIter, Result,
+    SerializableSteelVal, SteelString, SteelVal,
+};
+use crate::steel_vm::builtin::BuiltInModule;
+use crate::steel_vm::vm::{VmContext, VmCore};
+use crate::stop;
+
+use steel_derive::function;
+
+pub(crate) fn register_watch_functions(module: &mut BuiltInModule) {
+    module
+        .register_native_fn_definition(WATCH_DEFINITION)
+        .register_native_fn_definition(WATCH_EVENTS_DEFINITION)
+        .register_native_fn_definition(UNWATCH_DEFINITION)
+        .register_native_fn_definition(FS_EVENT_KIND_DEFINITION)
+        .register_native_fn_definition(FS_EVENT_PATHS_DEFINITION)
+        .register_value("fs/watch-dispatch!", SteelVal::BuiltIn(watch_dispatch));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FsEventKind {
+    Create,
+    Modify,
+    Delete,
+    Rename,
+}
+
+impl FsEventKind {
+    fn name(self) -> &'static str {
+        match self {
+            FsEventKind::Create => "create",
+            FsEventKind::Modify => "modify",
+            FsEventKind::Delete => "delete",
+            FsEventKind::Rename => "rename",
+        }
+    }
+}
+
+/// A change to the filesystem. Renames that the platform can pair up have both the old and
+/// the new path, in that order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FsEvent {
+    kind: FsEventKind,
+    paths: Vec<PathBuf>,
+}
+
+impl FsEvent {
+    fn from_notify(event: notify::Event) -> Option<FsEvent> {
+        let kind = match event.kind {
+            EventKind::Create(_) => FsEventKind::Create,
+            EventKind::Modify(ModifyKind::Name(_)) => FsEventKind::Rename,
+            EventKind::Modify(_) => FsEventKind::Modify,
+            EventKind::Remove(_) => FsEventKind::Delete,
+            // Reads and events the platform couldn't classify aren't changes we can act on
+            EventKind::Access(_) | EventKind::Any | EventKind::Other => return None,
+        };
+
+        Some(FsEvent {
+            kind,
+            paths: event.paths,
+        })
+    }
+}
+
+impl Custom for FsEvent {
+    fn fmt(&self) -> Option<std::result::Result<String, std::fmt::Error>> {
+        let paths = self
+            .paths
+            .iter()
+            .map(|x| x.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        Some(Ok(format!("#<fs-event {} {}>", self.kind.name(), paths)))
+    }
+
+    fn into_serializable_steelval(&mut self) -> Option<SerializableSteelVal> {
+        Some(SerializableSteelVal::Custom(Box::new(self.clone())))
+    }
+}
+
+// Where a watcher delivers its events
+enum Sink {
+    // A channel made with `make-channels`, possibly read on another thread
+    Channel(Sender<SerializableSteelVal>),
+    // Queued up on the watcher, for the engine's thread to pick up
+    Queue(Sender<FsEvent>),
+}
+
+impl Sink {
+    // Returns false once nobody is listening anymore
+    fn deliver(&self, event: FsEvent) -> bool {
+        match self {
+            Sink::Channel(sender) => sender
+                .send(SerializableSteelVal::Custom(Box::new(event)))
+                .is_ok(),
+            Sink::Queue(sender) => sender.send(event).is_ok(),
+        }
+    }
+}
+
+// Forwards events from the watcher to the sink. With a debounce, events are held back until
+// none have come in for that long, and an event that repeats the one right before it is
+// dropped. Repeats with something else in between are kept, so the order stays intact.
+fn forward_events(raw: Receiver<FsEvent>, sink: Sink, debounce: Option<Duration>) {
+    while let Ok(first) = raw.recv() {
+        let mut pending = vec![first];
+
+        if let Some(debounce) = debounce {
+            loop {
+                match raw.recv_timeout(debounce) {
+                    Ok(event) => {
+                        if pending.last() != Some(&event) {
+                            pending.push(event);
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => break,
+                    // Still deliver what came in before the watcher went away
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+        }
+
+        for event in pending {
+            if !sink.deliver(event) {
+                return;
+            }
+        }
+    }
+}
+
+struct FileWatcher {
+    // Dropping this stops the watch, and with it the forwarding thread
+    watcher: Option<RecommendedWatcher>,
+    events: Option<Receiver<FsEvent>>,
+}
+
+impl Custom for FileWatcher {}
+
+struct WatchOptions {
+    recursive: bool,
+    debounce: Option<Duration>,
+    channel: Option<Sender<SerializableSteelVal>>,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions {
+            recursive: true,
+            debounce: None,
+            channel: None,
+        }
+    }
+}
+
+impl WatchOptions {
+    fn from_steelval(options: &SteelVal) -> Result<WatchOptions> {
+        let mut parsed = WatchOptions::default();
+
+        let options = match options {
+            SteelVal::HashMapV(options) => options,
+            _ => stop!(TypeMismatch => "fs/watch expects a hash of options, found: {}", options),
+        };
+
+        for (key, value) in options.iter() {
+            match key {
+                SteelVal::SymbolV(key) if key.as_str() == "recursive" => {
+                    parsed.recursive = bool::from_steelval(value)?;
+                }
+                SteelVal::SymbolV(key) if key.as_str() == "debounce-ms" => {
+                    let millis = usize::from_steelval(value)?;
+                    parsed.debounce = (millis > 0).then(|| Duration::from_millis(millis as u64));
+                }
+                SteelVal::SymbolV(key) if key.as_str() == "channel" => {
+                    parsed.channel = Some(<Sender<SerializableSteelVal>>::from_steelval(value)?);
+                }
+                _ => stop!(Generic => "fs/watch: unknown option: {}", key),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+/// Watches a file or directory for changes. Events are delivered to the channel given with
+/// the `'channel` option, or are otherwise queued up on the watcher, to be picked up with
+/// `fs/watch-events` or `fs/watch-dispatch!`. The watch stops once the watcher is dropped,
+/// or with `fs/unwatch!`.
+///
+/// (fs/watch path [options]) -> fs-watcher?
+///
+/// * path : string?
+/// * options : hash? - with the keys:
+///   * 'recursive : bool? = #true - whether to watch everything under a directory
+///   * 'debounce-ms : int? = 0 - wait for changes to stop for this long before delivering them,
+///     dropping any event that is the same as the one before it
+///   * 'channel : sender? - the sending half of `make-channels`
+///
+/// # Examples
+/// ```scheme
+/// > (define watcher (fs/watch "src" (hash 'debounce-ms 200)))
+/// > (fs/watch-dispatch! watcher (lambda (event) (displayln (fs-event-paths event))))
+///
+/// > (define channels (make-channels))
+/// > (define watcher (fs/watch "src" (hash 'channel (car channels))))
+/// > (fs-event-kind (channel->recv (cadr channels))) ;; => 'modify
+/// ```
+#[function(name = "fs/watch")]
+pub fn watch(path: &SteelString, mut rest: RestArgsIter<'_, &SteelVal>) -> Result<SteelVal> {
+    let options = match rest.next() {
+        Some(options) => WatchOptions::from_steelval(options?)?,
+        None => WatchOptions::default(),
+    };
+
+    if rest.next().is_some() {
+        stop!(ArityMismatch => "fs/watch takes at most two arguments");
+    }
+
+    let (raw_sender, raw_receiver) = channel::<FsEvent>();
+
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) => {
+                if let Some(event) = FsEvent::from_notify(event) {
+                    let _ = raw_sender.send(event);
+                }
+            }
+            Err(e) => log::warn!(target: "watch", "error while watching files: {}", e),
+        })
+        .map_err(watch_error)?;
+
+    let mode = if options.recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+
+    watcher
+        .watch(Path::new(path.as_str()), mode)
+        .map_err(watch_error)?;
+
+    let (sink, events) = match options.channel {
+        Some(channel) => (Sink::Channel(channel), None),
+        None => {
+            let (sender, receiver) = channel();
+            (Sink::Queue(sender), Some(receiver))
+        }
+    };
+
+    let debounce = options.debounce;
+    std::thread::spawn(move || forward_events(raw_receiver, sink, debounce));
+
+    IntoSteelVal::into_steelval(FileWatcher {
+        watcher: Some(watcher),
+        events,
+    })
+}
+
+fn watch_error(error: notify::Error) -> SteelErr {
+    SteelErr::new(ErrorKind::Io, error.to_string())
+}
+
+fn queued_events(watcher: &FileWatcher) -> Result<&Receiver<FsEvent>> {
+    match &watcher.events {
+        Some(events) => Ok(events),
+        None => stop!(Generic => "this watcher delivers its events to a channel"),
+    }
+}
+
+/// Returns the events that have been queued up on the watcher, without waiting for more.
+///
+/// (fs/watch-events watcher) -> (listof fs-event?)
+///
+/// * watcher : fs-watcher?
+#[function(name = "fs/watch-events")]
+pub fn watch_events(watcher: &SteelVal) -> Result<SteelVal> {
+    let mut nursery = ();
+    let watcher = FileWatcher::as_ref(watcher, &mut nursery)?;
+    let events = queued_events(&watcher)?;
+
+    let mut found = Vec::new();
+
+    loop {
+        match events.try_recv() {
+            Ok(event) => found.push(IntoSteelVal::into_steelval(event)?),
+            Err(TryRecvError::Empty | TryRecvError::Disconnected) => break,
+        }
+    }
+
+    Ok(SteelVal::ListV(found.into_iter().collect()))
+}
+
+/// Stops watching for changes. Events that have already been queued can still be read.
+///
+/// (fs/unwatch! watcher) -> void?
+///
+/// * watcher : fs-watcher?
+#[function(name = "fs/unwatch!")]
+pub fn unwatch(watcher: &SteelVal) -> Result<SteelVal> {
+    FileWatcher::as_mut_ref(watcher)?.watcher = None;
+    Ok(SteelVal::Void)
+}
+
+/// Returns the kind of change, one of `'create`, `'modify`, `'delete` or `'rename`.
+///
+/// (fs-event-kind event) -> symbol?
+///
+/// * event : fs-event?
+#[function(name = "fs-event-kind")]
+pub fn fs_event_kind(event: &SteelVal) -> Result<SteelVal> {
+    let mut nursery = ();
+    let event = FsEvent::as_ref(event, &mut nursery)?;
+    Ok(SteelVal::SymbolV(event.kind.name().into()))
+}
+
+/// Returns the paths a change happened to. A rename has the old path first, followed by the
+/// new one, when the platform reports them together.
+///
+/// (fs-event-paths event) -> (listof string?)
+///
+/// * event : fs-event?
+#[function(name = "fs-event-paths")]
+pub fn fs_event_paths(event: &SteelVal) -> Result<SteelVal> {
+    let mut nursery = ();
+    let event = FsEvent::as_ref(event, &mut nursery)?;
+
+    Ok(SteelVal::ListV(
+        event
+            .paths
+            .iter()
+            .map(|x| SteelVal::StringV(x.to_string_lossy().into_owned().into()))
+            .collect(),
+    ))
+}
+
+// Waits for events on the watcher's queue, calling the callback with each of them on the
+// engine's thread. Returns how many events were handled.
+//
+// (fs/watch-dispatch! watcher callback [timeout-ms]) -> int?
+fn watch_dispatch(ctx: &mut VmCore, args: &[SteelVal]) -> Option<Result<SteelVal>> {
+    Some(watch_dispatch_result(ctx, args))
+}
+
+fn watch_dispatch_result(ctx: &mut VmCore, args: &[SteelVal]) -> Result<SteelVal> {
+    if args.len() != 2 && args.len() != 3 {
+        stop!(ArityMismatch => "fs/watch-dispatch! expects two or three arguments, found: {}", args.len());
+    }
+
+    let callback = &args[1];
+
+    if !callback.is_function() {
+        stop!(TypeMismatch => "fs/watch-dispatch! expects a function, found: {}", callback);
+    }
+
+    let timeout = match args.get(2) {
+        Some(timeout) => Some(Duration::from_millis(usize::from_steelval(timeout)? as u64)),
+        None => None,
+    };
+
+    // Collect everything up front, so the callback is free to use the watcher itself
+    let events = {
+        let mut nursery = ();
+        let watcher = FileWatcher::as_ref(&args[0], &mut nursery)?;
+        let events = queued_events(&watcher)?;
+
+        let first = match timeout {
+            Some(timeout) => events.recv_timeout(timeout).ok(),
+            None => events.recv().ok(),
+        };
+
+        first
+            .into_iter()
+            .chain(std::iter::from_fn(|| events.try_recv().ok()))
+            .collect::<Vec<_>>()
+    };
+
+    let count = events.len();
+
+    for event in events {
+        ctx.call_function_one_arg(callback, IntoSteelVal::into_steelval(event)?)?;
+    }
+
+    Ok(SteelVal::IntV(count as isize))
+}
+
+#[cfg(test)]
+mod watch_tests {
+    use super::*;
+    use notify::event::{CreateKind, DataChange, RenameMode};
+
+    fn event(kind: EventKind, paths: &[&str]) -> notify::Event {
+        let mut event = notify::Event::new(kind);
+        event.paths = paths.iter().map(PathBuf::from).collect();
+        event
+    }
+
+    #[test]
+    fn classifies_events() {
+        let create = FsEvent::from_notify(event(EventKind::Create(CreateKind::File), &["a"]));
+        assert_eq!(create.unwrap().kind, FsEventKind::Create);
+
+        let modify = FsEvent::from_notify(event(
+            EventKind::Modify(ModifyKind::Data(DataChange::Content)),
+            &["a"],
+        ));
+        assert_eq!(modify.unwrap().kind, FsEventKind::Modify);
+
+        let rename = FsEvent::from_notify(event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+            &["a", "b"],
+        ))
+        .unwrap();
+        assert_eq!(rename.kind, FsEventKind::Rename);
+        assert_eq!(rename.paths, vec![PathBuf::from("a"), PathBuf::from("b")]);
+
+        assert!(FsEvent::from_notify(event(EventKind::Any, &["a"])).is_none());
+    }
+
+    #[test]
+    fn debounce_drops_consecutive_repeats() {
+        let (raw_sender, raw_receiver) = channel();
+        let (sender, receiver) = channel();
+
+        let modify = FsEvent {
+            kind: FsEventKind::Modify,
+            paths: vec![PathBuf::from("a")],
+        };
+        let delete = FsEvent {
+            kind: FsEventKind::Delete,
+            paths: vec![PathBuf::from("a")],
+        };
+
+        let other = FsEvent {
+            kind: FsEventKind::Modify,
+            paths: vec![PathBuf::from("b")],
+        };
+
+        for event in [
+            modify.clone(),
+            modify.clone(),
+            delete.clone(),
+            modify.clone(),
+            other.clone(),
+            other.clone(),
+            modify.clone(),
+        ] {
+            raw_sender.send(event).unwrap();
+        }
+        drop(raw_sender);
+
+        forward_events(
+            raw_receiver,
+            Sink::Queue(sender),
+            Some(Duration::from_millis(10)),
+        );
+
+        // Only back to back repeats go, so the file still ends up modified after the delete
+        assert_eq!(
+            receiver.iter().collect::<Vec<_>>(),
+            vec![modify.clone(), delete, modify.clone(), other, modify]
+        );
+    }
+}
@@ -1,2 +1,3 @@
 pub mod requests;
+pub mod server;
 pub mod websockets;
@@ -1,11 +1,26 @@
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
 use reqwest::{
-    blocking::{get, Client, RequestBuilder, Response},
-    StatusCode,
+    blocking::{get, multipart, Body, Client, ClientBuilder, RequestBuilder, Response},
+    cookie::{CookieStore, Jar},
+    header::{HeaderMap, HeaderName, HeaderValue},
+    redirect::Policy,
+    Method, StatusCode, Url,
 };
 
 use crate::{
-    rvals::Custom,
+    gc::Gc,
+    primitives::bytevectors::bytes_to_steelval,
+    primitives::http::{
+        register_http_error_functions, register_status_code_functions, HttpError, HttpErrorKind,
+        HttpStatus,
+    },
+    rvals::{Custom, FromSteelVal},
     steel_vm::{builtin::BuiltInModule, register_fn::RegisterFn},
+    SteelVal,
 };
 
 use serde_json::Value;
@@ -14,46 +29,191 @@ impl Custom for Client {}
 
 impl Custom for SteelRequestBuilder {}
 
-impl Custom for StatusCode {}
-
 impl Custom for reqwest::Error {
     fn fmt(&self) -> Option<std::result::Result<String, std::fmt::Error>> {
         Some(Ok(format!("{:?}", self)))
     }
 }
 
+#[derive(Clone)]
+struct CookieJar {
+    jar: Arc<Jar>,
+}
+
+impl Custom for CookieJar {}
+
+// Parts of a multipart body. These are kept as data until the request is sent, since a
+// multipart form can't be cloned once it's built.
+#[derive(Debug, Clone)]
+enum MultipartPart {
+    Text(String, String),
+    Bytes(String, Vec<u8>, Option<String>),
+    File(String, PathBuf),
+}
+
 #[derive(Debug)]
 struct SteelRequestBuilder {
     builder: RequestBuilder,
+    multipart: Vec<MultipartPart>,
+    // Files are streamed as the body, so they're only opened once the request is sent
+    body_file: Option<PathBuf>,
+}
+
+fn request_error(message: String) -> HttpError {
+    HttpError::new(HttpErrorKind::Request, message)
+}
+
+fn header_map(headers: &SteelVal) -> Result<HeaderMap, HttpError> {
+    let headers = match headers {
+        SteelVal::HashMapV(headers) => headers,
+        _ => {
+            return Err(request_error(format!(
+                "expected a hash of headers, found: {headers}"
+            )))
+        }
+    };
+
+    let mut map = HeaderMap::new();
+
+    for (key, value) in headers.iter() {
+        let (SteelVal::StringV(key) | SteelVal::SymbolV(key), SteelVal::StringV(value)) =
+            (key, value)
+        else {
+            return Err(request_error(format!(
+                "headers must map names to strings, found: {key} => {value}"
+            )));
+        };
+
+        let name = HeaderName::from_bytes(key.as_bytes())
+            .map_err(|_| request_error(format!("invalid header name: {key}")))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|_| request_error(format!("invalid header value: {value}")))?;
+
+        map.insert(name, value);
+    }
+
+    Ok(map)
 }
 
 impl SteelRequestBuilder {
     fn new(builder: RequestBuilder) -> Self {
-        Self { builder }
+        Self {
+            builder,
+            multipart: Vec::new(),
+            body_file: None,
+        }
+    }
+
+    fn map(self, f: impl FnOnce(RequestBuilder) -> RequestBuilder) -> Self {
+        Self {
+            builder: f(self.builder),
+            ..self
+        }
     }
 
     fn json(self, value: Value) -> Self {
-        self.builder.json(&value).into()
+        self.map(|x| x.json(&value))
     }
 
     fn query(self, value: Value) -> Self {
-        self.builder.query(&value).into()
+        self.map(|x| x.query(&value))
     }
 
-    fn send(self) -> reqwest::Result<SteelResponse> {
-        self.builder.send().map(SteelResponse::from)
+    fn form(self, value: Value) -> Self {
+        self.map(|x| x.form(&value))
+    }
+
+    fn body(self, body: SteelVal) -> Result<Self, HttpError> {
+        match body {
+            SteelVal::StringV(s) => Ok(self.map(|x| x.body(s.to_string()))),
+            SteelVal::ByteVector(b) => Ok(self.map(|x| x.body(b.borrow().clone()))),
+            _ => Err(request_error(format!(
+                "the body of a request must be a string or bytes, found: {body}"
+            ))),
+        }
+    }
+
+    fn body_file(self, path: String) -> Self {
+        Self {
+            body_file: Some(PathBuf::from(path)),
+            ..self
+        }
+    }
+
+    fn multipart_text(mut self, name: String, value: String) -> Self {
+        self.multipart.push(MultipartPart::Text(name, value));
+        self
+    }
+
+    fn multipart_bytes(mut self, name: String, bytes: SteelVal, file_name: Option<String>) -> Self {
+        let bytes = match bytes {
+            SteelVal::ByteVector(b) => b.borrow().clone(),
+            SteelVal::StringV(s) => s.as_bytes().to_vec(),
+            _ => Vec::new(),
+        };
+
+        self.multipart
+            .push(MultipartPart::Bytes(name, bytes, file_name));
+        self
+    }
+
+    fn multipart_file(mut self, name: String, path: String) -> Self {
+        self.multipart
+            .push(MultipartPart::File(name, PathBuf::from(path)));
+        self
+    }
+
+    fn timeout(self, millis: usize) -> Self {
+        self.map(|x| x.timeout(Duration::from_millis(millis as u64)))
+    }
+
+    fn send(self) -> Result<SteelResponse, HttpError> {
+        let mut builder = self.builder;
+
+        if !self.multipart.is_empty() {
+            let mut form = multipart::Form::new();
+
+            for part in self.multipart {
+                form = match part {
+                    MultipartPart::Text(name, value) => form.text(name, value),
+                    MultipartPart::Bytes(name, bytes, file_name) => {
+                        let mut part = multipart::Part::bytes(bytes);
+
+                        if let Some(file_name) = file_name {
+                            part = part.file_name(file_name);
+                        }
+
+                        form.part(name, part)
+                    }
+                    MultipartPart::File(name, path) => form.file(name, path)?,
+                };
+            }
+
+            builder = builder.multipart(form);
+        }
+
+        if let Some(path) = self.body_file {
+            builder = builder.body(Body::from(std::fs::File::open(path)?));
+        }
+
+        Ok(builder.send()?.into())
     }
 
     fn header(self, key: String, value: String) -> Self {
-        self.builder.header(key, value).into()
+        self.map(|x| x.header(key, value))
+    }
+
+    fn headers(self, headers: SteelVal) -> Result<Self, HttpError> {
+        let headers = header_map(&headers)?;
+        Ok(self.map(|x| x.headers(headers)))
     }
 
     fn basic_auth(self, username: String, password: Option<String>) -> Self {
-        self.builder.basic_auth(username, password).into()
+        self.map(|x| x.basic_auth(username, password))
     }
 
     fn bearer_auth(self, token: String) -> Self {
-        self.builder.bearer_auth(token).into()
+        self.map(|x| x.bearer_auth(token))
     }
 }
 
@@ -66,11 +226,82 @@ impl From<RequestBuilder> for SteelRequestBuilder {
 impl Clone for SteelRequestBuilder {
     fn clone(&self) -> Self {
         Self {
-            builder: self.builder.try_clone().expect("Internal steel error: it should not be possible to pass a stream as a body, which is the only way for this clone operation to fail")
+            builder: self.builder.try_clone().expect("Internal steel error: it should not be possible to pass a stream as a body, which is the only way for this clone operation to fail"),
+            multipart: self.multipart.clone(),
+            body_file: self.body_file.clone(),
         }
     }
 }
 
+// Builds a client from a hash of options:
+//
+// * 'timeout-ms - for the whole request
+// * 'connect-timeout-ms
+// * 'max-redirects - 0 to not follow redirects at all
+// * 'cookies - #true to keep cookies between requests, or a cookie jar to keep them in
+// * 'user-agent
+// * 'headers - a hash of headers sent with every request
+fn client_with_options(options: SteelVal) -> Result<Client, HttpError> {
+    let options = match options {
+        SteelVal::HashMapV(options) => options,
+        _ => {
+            return Err(request_error(format!(
+                "expected a hash of options, found: {options}"
+            )))
+        }
+    };
+
+    let mut builder = ClientBuilder::new();
+
+    for (key, value) in options.iter() {
+        let SteelVal::SymbolV(key) = key else {
+            return Err(request_error(format!("unknown client option: {key}")));
+        };
+
+        builder = match (key.as_str(), value) {
+            ("timeout-ms", SteelVal::IntV(millis)) if *millis >= 0 => {
+                builder.timeout(Duration::from_millis(*millis as u64))
+            }
+            ("connect-timeout-ms", SteelVal::IntV(millis)) if *millis >= 0 => {
+                builder.connect_timeout(Duration::from_millis(*millis as u64))
+            }
+            ("max-redirects", SteelVal::IntV(0)) => builder.redirect(Policy::none()),
+            ("max-redirects", SteelVal::IntV(max)) if *max > 0 => {
+                builder.redirect(Policy::limited(*max as usize))
+            }
+            ("cookies", SteelVal::BoolV(enabled)) => builder.cookie_store(*enabled),
+            ("cookies", SteelVal::Custom(_)) => match CookieJar::from_steelval(value) {
+                Ok(jar) => builder.cookie_provider(jar.jar),
+                Err(_) => {
+                    return Err(request_error(format!(
+                        "expected a cookie jar for 'cookies, found: {value}"
+                    )))
+                }
+            },
+            ("user-agent", SteelVal::StringV(agent)) => builder.user_agent(agent.as_str()),
+            ("headers", headers) => builder.default_headers(header_map(headers)?),
+            _ => {
+                return Err(request_error(format!(
+                    "invalid client option: {key} => {value}"
+                )))
+            }
+        };
+    }
+
+    Ok(builder.build()?)
+}
+
+fn request_wrapper(
+    client: &Client,
+    method: String,
+    url: String,
+) -> Result<SteelRequestBuilder, HttpError> {
+    let method = Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+        .map_err(|_| request_error(format!("invalid method: {method}")))?;
+
+    Ok(client.request(method, url).into())
+}
+
 fn post_wrapper(client: &Client, url: String) -> SteelRequestBuilder {
     client.post(url).into()
 }
@@ -79,41 +310,170 @@ fn get_wrapper(client: &Client, url: String) -> SteelRequestBuilder {
     client.get(url).into()
 }
 
-fn basic_get_wrapper(url: String) -> reqwest::Result<SteelResponse> {
-    get(url).map(|x| x.into())
+fn put_wrapper(client: &Client, url: String) -> SteelRequestBuilder {
+    client.put(url).into()
+}
+
+fn patch_wrapper(client: &Client, url: String) -> SteelRequestBuilder {
+    client.patch(url).into()
+}
+
+fn delete_wrapper(client: &Client, url: String) -> SteelRequestBuilder {
+    client.delete(url).into()
+}
+
+fn head_wrapper(client: &Client, url: String) -> SteelRequestBuilder {
+    client.head(url).into()
+}
+
+fn basic_get_wrapper(url: String) -> Result<SteelResponse, HttpError> {
+    Ok(get(url)?.into())
+}
+
+fn parse_url(url: &str) -> Result<Url, HttpError> {
+    Url::parse(url).map_err(|e| request_error(format!("invalid url {url}: {e}")))
+}
+
+fn cookie_jar() -> CookieJar {
+    CookieJar {
+        jar: Arc::new(Jar::default()),
+    }
+}
+
+// Adds a cookie, as it would appear in a `Set-Cookie` header, for the url
+fn cookie_jar_add(jar: &CookieJar, cookie: String, url: String) -> Result<(), HttpError> {
+    jar.jar.add_cookie_str(&cookie, &parse_url(&url)?);
+    Ok(())
 }
 
-fn status_code_to_int(status_code: &StatusCode) -> usize {
-    status_code.as_u16() as usize
+// The cookies that would be sent to the url, as they'd appear in a `Cookie` header
+fn cookie_jar_cookies(jar: &CookieJar, url: String) -> Result<Option<String>, HttpError> {
+    Ok(jar
+        .jar
+        .cookies(&parse_url(&url)?)
+        .and_then(|x| x.to_str().ok().map(|x| x.to_string())))
 }
 
 struct SteelResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    url: String,
     response: Option<Response>,
 }
 
 impl From<Response> for SteelResponse {
     fn from(value: Response) -> Self {
         SteelResponse {
+            status: value.status(),
+            headers: value.headers().clone(),
+            url: value.url().to_string(),
             response: Some(value),
         }
     }
 }
 
 impl SteelResponse {
-    fn status(&self) -> Option<StatusCode> {
-        self.response.as_ref().map(|x| x.status())
+    fn status(&self) -> HttpStatus {
+        HttpStatus(self.status.as_u16())
+    }
+
+    fn status_code(&self) -> usize {
+        self.status.as_u16() as usize
+    }
+
+    fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    fn headers(&self) -> SteelVal {
+        SteelVal::HashMapV(Gc::new(
+            self.headers
+                .iter()
+                .map(|(key, value)| {
+                    (
+                        SteelVal::StringV(key.as_str().into()),
+                        SteelVal::StringV(
+                            String::from_utf8_lossy(value.as_bytes())
+                                .into_owned()
+                                .into(),
+                        ),
+                    )
+                })
+                .collect(),
+        ))
+    }
+
+    fn header(&self, name: String) -> Option<String> {
+        self.headers
+            .get(name.as_str())
+            .map(|x| String::from_utf8_lossy(x.as_bytes()).into_owned())
+    }
+
+    fn error_for_status(&self) -> Result<bool, HttpError> {
+        if self.status.is_client_error() || self.status.is_server_error() {
+            Err(HttpError::status(
+                self.status.as_u16(),
+                Some(self.url.clone()),
+            ))
+        } else {
+            Ok(true)
+        }
     }
 
     fn identity(&self) -> bool {
         true
     }
 
-    fn json(&mut self) -> Option<reqwest::Result<Value>> {
-        self.response.take().map(|x| x.json::<Value>())
+    fn take(&mut self) -> Result<Response, HttpError> {
+        self.response.take().ok_or_else(HttpError::consumed)
+    }
+
+    // The rest of the body. This goes through `Read` rather than the helpers on the response,
+    // since those ignore anything that was already read with `response/read-bytes`.
+    fn read_rest(&mut self) -> Result<Vec<u8>, HttpError> {
+        let mut buffer = Vec::new();
+        self.take()?.read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn json(&mut self) -> Result<Value, HttpError> {
+        serde_json::from_slice(&self.read_rest()?)
+            .map_err(|e| HttpError::new(HttpErrorKind::Decode, e.to_string()))
+    }
+
+    fn text(&mut self) -> Result<String, HttpError> {
+        Ok(String::from_utf8_lossy(&self.read_rest()?).into_owned())
     }
 
-    fn text(&mut self) -> Option<reqwest::Result<String>> {
-        self.response.take().map(|x| x.text())
+    fn bytes(&mut self) -> Result<SteelVal, HttpError> {
+        Ok(bytes_to_steelval(self.read_rest()?))
+    }
+
+    // Reads the next chunk of the body, of at most `max` bytes. The chunk is empty once the
+    // whole body has been read.
+    fn read_bytes(&mut self, max: usize) -> Result<SteelVal, HttpError> {
+        let response = match self.response.as_mut() {
+            Some(response) => response,
+            None => return Ok(bytes_to_steelval(Vec::new())),
+        };
+
+        let mut buffer = vec![0; max];
+        let read = response.read(&mut buffer)?;
+        buffer.truncate(read);
+
+        if read == 0 {
+            self.response = None;
+        }
+
+        Ok(bytes_to_steelval(buffer))
+    }
+
+    // Streams the body into a file, returning how many bytes were written
+    fn copy_to_file(&mut self, path: String) -> Result<usize, HttpError> {
+        let mut response = self.take()?;
+        let mut file = std::fs::File::create(path)?;
+
+        Ok(response.copy_to(&mut file)? as usize)
     }
 }
 
@@ -124,27 +484,146 @@ pub fn requests_module() -> BuiltInModule {
 
     module
         .register_fn("request/client", Client::new)
+        .register_fn("request/client-with-options", client_with_options)
         .register_fn("get", basic_get_wrapper)
+        .register_fn("client/request", request_wrapper)
         .register_fn("client/post", post_wrapper)
         .register_fn("client/get", get_wrapper)
+        .register_fn("client/put", put_wrapper)
+        .register_fn("client/patch", patch_wrapper)
+        .register_fn("client/delete", delete_wrapper)
+        .register_fn("client/head", head_wrapper)
         .register_fn("request/json", SteelRequestBuilder::json)
         .register_fn("request/query", SteelRequestBuilder::query)
+        .register_fn("request/form", SteelRequestBuilder::form)
+        .register_fn("request/body", SteelRequestBuilder::body)
+        .register_fn("request/body-file", SteelRequestBuilder::body_file)
+        .register_fn(
+            "request/multipart-text",
+            SteelRequestBuilder::multipart_text,
+        )
+        .register_fn(
+            "request/multipart-bytes",
+            SteelRequestBuilder::multipart_bytes,
+        )
+        .register_fn(
+            "request/multipart-file",
+            SteelRequestBuilder::multipart_file,
+        )
+        .register_fn("request/timeout", SteelRequestBuilder::timeout)
         .register_fn("request/send", SteelRequestBuilder::send)
         .register_fn("request/header", SteelRequestBuilder::header)
+        .register_fn("request/headers", SteelRequestBuilder::headers)
         .register_fn("request/basic-auth", SteelRequestBuilder::basic_auth)
         .register_fn("request/bearer-auth", SteelRequestBuilder::bearer_auth)
+        .register_fn("cookie-jar", cookie_jar)
+        .register_fn("cookie-jar/add!", cookie_jar_add)
+        .register_fn("cookie-jar/cookies", cookie_jar_cookies)
         .register_fn("response/status", SteelResponse::status)
-        .register_fn("status-code->int", status_code_to_int)
-        .register_fn("status-code/success?", StatusCode::is_success)
-        .register_fn("status-code/redirect?", StatusCode::is_redirection)
-        .register_fn("status-code/client-error?", StatusCode::is_client_error)
-        .register_fn("status-code/server-error?", StatusCode::is_server_error)
+        .register_fn("response/status-code", SteelResponse::status_code)
+        .register_fn("response/url", SteelResponse::url)
+        .register_fn("response/headers", SteelResponse::headers)
+        .register_fn("response/header", SteelResponse::header)
+        .register_fn("response/error-for-status", SteelResponse::error_for_status)
         .register_type::<SteelRequestBuilder>("request/builder?")
-        .register_type::<StatusCode>("status-code?")
         .register_type::<Client>("request/client?")
+        .register_type::<CookieJar>("cookie-jar?")
         .register_fn("response?", SteelResponse::identity)
         .register_fn("response->json", SteelResponse::json)
-        .register_fn("response->text", SteelResponse::text);
+        .register_fn("response->text", SteelResponse::text)
+        .register_fn("response->bytes", SteelResponse::bytes)
+        .register_fn("response/read-bytes", SteelResponse::read_bytes)
+        .register_fn("response/copy-to-file!", SteelResponse::copy_to_file);
+
+    register_http_error_functions(&mut module);
+    register_status_code_functions(&mut module);
 
     module
 }
+
+#[cfg(test)]
+mod requests_tests {
+    use super::*;
+    use crate::primitives::http::http_tests::{options, stub_server};
+    use crate::rvals::IntoSteelVal;
+
+    #[test]
+    fn requests_against_stub_server() {
+        let (address, handle) = stub_server();
+        let url = |path: &str| format!("{address}{path}");
+
+        let jar = cookie_jar();
+        let client = client_with_options(options(vec![
+            ("max-redirects", SteelVal::IntV(0)),
+            ("timeout-ms", SteelVal::IntV(200)),
+            ("cookies", jar.clone().into_steelval().unwrap()),
+        ]))
+        .unwrap();
+
+        let mut response = put_wrapper(&client, url("/echo"))
+            .body(SteelVal::StringV("hello".into()))
+            .unwrap()
+            .send()
+            .unwrap();
+        assert_eq!(
+            response.text().unwrap(),
+            "PUT /echo cookie= type= body=hello"
+        );
+        assert_eq!(response.text().unwrap_err().kind, HttpErrorKind::Consumed);
+
+        let mut response = patch_wrapper(&client, url("/echo"))
+            .form(serde_json::json!({"a": "b c"}))
+            .send()
+            .unwrap();
+        assert_eq!(
+            response.text().unwrap(),
+            "PATCH /echo cookie= type=application/x-www-form-urlencoded body=a=b+c"
+        );
+
+        let mut response = request_wrapper(&client, "delete".to_string(), url("/echo"))
+            .unwrap()
+            .multipart_text("field".to_string(), "value".to_string())
+            .send()
+            .unwrap();
+        let text = response.text().unwrap();
+        assert!(text.starts_with("DELETE /echo cookie= type=multipart/form-data; boundary="));
+        assert!(text.contains("name=\"field\"\r\n\r\nvalue"));
+
+        let response = get_wrapper(&client, url("/redirect")).send().unwrap();
+        assert_eq!(response.status(), HttpStatus(302));
+        assert_eq!(response.header("location".to_string()).unwrap(), "/echo");
+
+        let missing = get_wrapper(&client, url("/missing")).send().unwrap();
+        let error = missing.error_for_status().unwrap_err();
+        assert_eq!(
+            (error.kind, error.status),
+            (HttpErrorKind::Status, Some(404))
+        );
+
+        let mut response = get_wrapper(&client, url("/auth"))
+            .basic_auth("user".to_string(), Some("pass".to_string()))
+            .send()
+            .unwrap();
+        assert_eq!(response.text().unwrap(), "Basic dXNlcjpwYXNz");
+
+        get_wrapper(&client, url("/login")).send().unwrap();
+        assert_eq!(
+            cookie_jar_cookies(&jar, url("/")).unwrap().unwrap(),
+            "session=abc"
+        );
+
+        let mut response = get_wrapper(&client, url("/echo")).send().unwrap();
+        assert_eq!(
+            response.read_bytes(8).unwrap(),
+            bytes_to_steelval(b"GET /ech".to_vec())
+        );
+        assert!(response.text().unwrap().contains("cookie=session=abc"));
+
+        let error = get_wrapper(&client, url("/slow")).send().err().unwrap();
+        assert_eq!(error.kind, HttpErrorKind::Timeout);
+
+        // The server is still busy with the slow request, so this waits on it without a timeout
+        get_wrapper(&Client::new(), url("/stop")).send().unwrap();
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,1054 @@
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use im_lists::list::List;
+use percent_encoding::percent_decode_str;
+use serde_json::Value;
+
+use crate::gc::Gc;
+use crate::primitives::bytevectors::bytes_to_steelval;
+use crate::rvals::{
+    AsRefMutSteelVal, AsRefSteelVal, Custom, IntoSteelVal, RestArgsIter, Result,
+    SerializableSteelVal, SteelString, SteelVal,
+};
+use crate::steel_vm::builtin::BuiltInModule;
+use crate::steel_vm::vm::{VmContext, VmCore};
+use crate::stop;
+
+use steel_derive::function;
+
+pub fn http_server_module() -> BuiltInModule {
+    let mut module = BuiltInModule::new("steel/http-server");
+
+    module
+        .register_native_fn_definition(HTTP_SERVER_DEFINITION)
+        .register_native_fn_definition(ROUTE_DEFINITION)
+        .register_native_fn_definition(BEFORE_DEFINITION)
+        .register_native_fn_definition(AFTER_DEFINITION)
+        .register_native_fn_definition(STATIC_FILES_DEFINITION)
+        .register_native_fn_definition(LISTEN_DEFINITION)
+        .register_value("http-server/serve!", SteelVal::BuiltIn(serve))
+        .register_native_fn_definition(STOP_DEFINITION)
+        .register_native_fn_definition(SHUTDOWN_HANDLE_DEFINITION)
+        .register_native_fn_definition(REQUEST_METHOD_DEFINITION)
+        .register_native_fn_definition(REQUEST_PATH_DEFINITION)
+        .register_native_fn_definition(REQUEST_PARAMS_DEFINITION)
+        .register_native_fn_definition(REQUEST_PARAM_DEFINITION)
+        .register_native_fn_definition(REQUEST_QUERY_DEFINITION)
+        .register_native_fn_definition(REQUEST_HEADERS_DEFINITION)
+        .register_native_fn_definition(REQUEST_HEADER_DEFINITION)
+        .register_native_fn_definition(REQUEST_BODY_DEFINITION)
+        .register_native_fn_definition(REQUEST_TEXT_DEFINITION)
+        .register_native_fn_definition(REQUEST_JSON_DEFINITION)
+        .register_native_fn_definition(RESPONSE_DEFINITION)
+        .register_native_fn_definition(JSON_RESPONSE_DEFINITION)
+        .register_native_fn_definition(RESPONSE_STATUS_DEFINITION)
+        .register_native_fn_definition(RESPONSE_HEADERS_DEFINITION)
+        .register_native_fn_definition(RESPONSE_BODY_DEFINITION)
+        .register_native_fn_definition(RESPONSE_WITH_HEADER_DEFINITION);
+
+    module
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct HttpRequest {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    // Names are lower cased, since they're case insensitive
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    params: Vec<(String, String)>,
+}
+
+impl Custom for HttpRequest {
+    fn fmt(&self) -> Option<std::result::Result<String, std::fmt::Error>> {
+        Some(Ok(format!("#<http-request {} {}>", self.method, self.path)))
+    }
+}
+
+impl HttpRequest {
+    fn from_tiny_http(request: &mut tiny_http::Request) -> std::io::Result<Self> {
+        let (path, query) = match request.url().split_once('?') {
+            Some((path, query)) => (path, query),
+            None => (request.url(), ""),
+        };
+
+        let path = percent_decode_str(path).decode_utf8_lossy().into_owned();
+
+        let query = url::form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect();
+
+        let headers = request
+            .headers()
+            .iter()
+            .map(|x| {
+                (
+                    x.field.as_str().as_str().to_ascii_lowercase(),
+                    x.value.as_str().to_string(),
+                )
+            })
+            .collect();
+
+        let mut body = Vec::new();
+        request.as_reader().read_to_end(&mut body)?;
+
+        Ok(HttpRequest {
+            method: request.method().as_str().to_ascii_uppercase(),
+            path,
+            query,
+            headers,
+            body,
+            params: Vec::new(),
+        })
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct HttpResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Custom for HttpResponse {
+    fn fmt(&self) -> Option<std::result::Result<String, std::fmt::Error>> {
+        Some(Ok(format!("#<http-response {}>", self.status)))
+    }
+}
+
+impl HttpResponse {
+    fn new(status: u16, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        HttpResponse {
+            status,
+            headers: vec![("content-type".to_string(), content_type.to_string())],
+            body: body.into(),
+        }
+    }
+
+    fn text(status: u16, body: &str) -> Self {
+        HttpResponse::new(status, "text/plain; charset=utf-8", body)
+    }
+
+    fn into_tiny_http(self) -> tiny_http::Response<std::io::Cursor<Vec<u8>>> {
+        let mut response = tiny_http::Response::from_data(self.body).with_status_code(self.status);
+
+        for (name, value) in self.headers {
+            match tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes()) {
+                Ok(header) => response.add_header(header),
+                Err(_) => log::warn!(target: "http-server", "dropping invalid header: {}", name),
+            }
+        }
+
+        response
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    // `:name` matches a single segment
+    Param(String),
+    // `*name` matches the rest of the path, and can only come last
+    Rest(String),
+}
+
+fn parse_pattern(pattern: &str) -> Result<Vec<Segment>> {
+    let segments = pattern
+        .split('/')
+        .filter(|x| !x.is_empty())
+        .map(|x| {
+            if let Some(name) = x.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else if let Some(name) = x.strip_prefix('*') {
+                Segment::Rest(name.to_string())
+            } else {
+                Segment::Literal(x.to_string())
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let rest = segments.iter().position(|x| matches!(x, Segment::Rest(_)));
+
+    if matches!(rest, Some(position) if position != segments.len() - 1) {
+        stop!(Generic => "http-server/route!: a `*` segment can only come last: {}", pattern);
+    }
+
+    Ok(segments)
+}
+
+// Returns the path parameters, if the path matches the pattern
+fn match_path(segments: &[Segment], path: &str) -> Option<Vec<(String, String)>> {
+    let parts = path
+        .split('/')
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>();
+    let mut params = Vec::new();
+
+    for (index, segment) in segments.iter().enumerate() {
+        match segment {
+            Segment::Literal(literal) => {
+                if parts.get(index) != Some(&literal.as_str()) {
+                    return None;
+                }
+            }
+            Segment::Param(name) => params.push((name.clone(), parts.get(index)?.to_string())),
+            Segment::Rest(name) => {
+                params.push((name.clone(), parts[index.min(parts.len())..].join("/")));
+                return Some(params);
+            }
+        }
+    }
+
+    (parts.len() == segments.len()).then_some(params)
+}
+
+#[derive(Clone)]
+struct Route {
+    // `None` matches any method
+    method: Option<String>,
+    segments: Vec<Segment>,
+    handler: SteelVal,
+}
+
+#[derive(Clone)]
+struct StaticFiles {
+    prefix: Vec<Segment>,
+    directory: PathBuf,
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|x| x.to_str()) {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("ico") => "image/x-icon",
+        Some("wasm") => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+impl StaticFiles {
+    fn serve(&self, path: &str) -> Option<HttpResponse> {
+        let relative = PathBuf::from(match_path(&self.prefix, path)?.pop()?.1);
+
+        // Never serve anything outside of the directory
+        if relative
+            .components()
+            .any(|x| !matches!(x, Component::Normal(_)))
+        {
+            return None;
+        }
+
+        let mut file = self.directory.join(relative);
+
+        if file.is_dir() {
+            file.push("index.html");
+        }
+
+        let contents = std::fs::read(&file).ok()?;
+
+        Some(HttpResponse::new(200, content_type(&file), contents))
+    }
+}
+
+// Everything needed to answer a request. This is copied out of the server value for the
+// duration of a request, so that handlers are free to use the server themselves.
+#[derive(Clone, Default)]
+struct Dispatcher {
+    routes: Vec<Route>,
+    before: Vec<SteelVal>,
+    after: Vec<SteelVal>,
+    static_files: Vec<StaticFiles>,
+}
+
+impl Dispatcher {
+    fn dispatch(
+        &self,
+        request: HttpRequest,
+        call: &mut dyn FnMut(&SteelVal, List<SteelVal>) -> Result<SteelVal>,
+    ) -> HttpResponse {
+        let method = request.method.clone();
+        let path = request.path.clone();
+
+        match self.respond(request, call) {
+            Ok(response) => response,
+            Err(e) => {
+                log::error!(target: "http-server", "error handling {} {}: {}", method, path, e);
+                HttpResponse::text(500, "Internal Server Error")
+            }
+        }
+    }
+
+    fn respond(
+        &self,
+        mut request: HttpRequest,
+        call: &mut dyn FnMut(&SteelVal, List<SteelVal>) -> Result<SteelVal>,
+    ) -> Result<HttpResponse> {
+        let mut response = None;
+
+        // Middleware that runs first can either pass along a request, or answer it directly
+        for middleware in &self.before {
+            let result = call(
+                middleware,
+                im_lists::list![request.clone().into_steelval()?],
+            )?;
+
+            let (mut requests, mut responses) = ((), ());
+
+            if let Ok(next) = HttpRequest::as_ref(&result, &mut requests) {
+                request = next.clone();
+            } else if let Ok(answer) = HttpResponse::as_ref(&result, &mut responses) {
+                response = Some(answer.clone());
+                break;
+            } else {
+                stop!(TypeMismatch => "middleware must return a request or a response, found: {}", result);
+            };
+        }
+
+        let mut response = match response {
+            Some(response) => response,
+            None => self.route(&mut request, call)?,
+        };
+
+        for middleware in &self.after {
+            let result = call(
+                middleware,
+                im_lists::list![request.clone().into_steelval()?, response.into_steelval()?],
+            )?;
+
+            response = into_response(&result)?;
+        }
+
+        Ok(response)
+    }
+
+    fn route(
+        &self,
+        request: &mut HttpRequest,
+        call: &mut dyn FnMut(&SteelVal, List<SteelVal>) -> Result<SteelVal>,
+    ) -> Result<HttpResponse> {
+        let mut path_matched = false;
+
+        for route in &self.routes {
+            if let Some(params) = match_path(&route.segments, &request.path) {
+                path_matched = true;
+
+                if route.method.as_ref().map_or(true, |x| *x == request.method) {
+                    request.params = params;
+                    let result = call(
+                        &route.handler,
+                        im_lists::list![request.clone().into_steelval()?],
+                    )?;
+
+                    return into_response(&result);
+                }
+            }
+        }
+
+        if request.method == "GET" || request.method == "HEAD" {
+            if let Some(response) = self
+                .static_files
+                .iter()
+                .find_map(|x| x.serve(&request.path))
+            {
+                return Ok(response);
+            }
+        }
+
+        if path_matched {
+            Ok(HttpResponse::text(405, "Method Not Allowed"))
+        } else {
+            Ok(HttpResponse::text(404, "Not Found"))
+        }
+    }
+}
+
+// Handlers can return a response, or just the body of one
+fn into_response(value: &SteelVal) -> Result<HttpResponse> {
+    match value {
+        SteelVal::StringV(s) => Ok(HttpResponse::text(200, s)),
+        SteelVal::ByteVector(b) => Ok(HttpResponse::new(
+            200,
+            "application/octet-stream",
+            b.borrow().clone(),
+        )),
+        _ => match HttpResponse::as_ref(value, &mut ()) {
+            Ok(response) => Ok(response.clone()),
+            Err(_) => stop!(TypeMismatch => "expected an http response, found: {}", value),
+        },
+    }
+}
+
+// Answers requests until the server is asked to stop. Requests that arrived before that are
+// still answered, so that no client is left hanging.
+fn serve_requests(
+    server: &tiny_http::Server,
+    dispatcher: &Dispatcher,
+    shutdown: &AtomicBool,
+    call: &mut dyn FnMut(&SteelVal, List<SteelVal>) -> Result<SteelVal>,
+) -> std::io::Result<()> {
+    let mut answer = |mut request: tiny_http::Request| {
+        let response = match HttpRequest::from_tiny_http(&mut request) {
+            Ok(parsed) => dispatcher.dispatch(parsed, call),
+            Err(_) => HttpResponse::text(400, "Bad Request"),
+        };
+
+        if let Err(e) = request.respond(response.into_tiny_http()) {
+            log::warn!(target: "http-server", "unable to send response: {}", e);
+        }
+    };
+
+    while !shutdown.load(Ordering::SeqCst) {
+        if let Some(request) = server.recv_timeout(Duration::from_millis(50))? {
+            answer(request);
+        }
+    }
+
+    while let Ok(Some(request)) = server.try_recv() {
+        answer(request);
+    }
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct HttpServer {
+    dispatcher: Dispatcher,
+    server: Option<tiny_http::Server>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl Custom for HttpServer {}
+
+/// Stops a server from another thread. It can be sent over a channel from `make-channels`.
+#[derive(Clone)]
+struct ShutdownHandle {
+    shutdown: Arc<AtomicBool>,
+}
+
+impl Custom for ShutdownHandle {
+    fn into_serializable_steelval(&mut self) -> Option<SerializableSteelVal> {
+        Some(SerializableSteelVal::Custom(Box::new(self.clone())))
+    }
+}
+
+/// Creates a new http server. Add routes, middleware and static files to it, then start it
+/// with `http-server/listen!` and `http-server/serve!`.
+///
+/// (http-server) -> http-server?
+///
+/// # Examples
+/// ```scheme
+/// > (define server (http-server))
+/// > (http-server/route! server 'GET "/users/:id"
+///     (lambda (request) (http-response 200 (http-request-param request "id"))))
+/// > (http-server/listen! server "127.0.0.1:8080") ;; => 8080
+/// > (http-server/serve! server)
+/// ```
+#[function(name = "http-server")]
+pub fn http_server() -> Result<SteelVal> {
+    HttpServer::default().into_steelval()
+}
+
+/// Adds a route to the server. Segments of the path that start with `:` match any one
+/// segment, and a final segment that starts with `*` matches the rest of the path. Both
+/// are available to the handler with `http-request-param`. The method `'ANY` matches every
+/// method.
+///
+/// The handler is called with the request, and returns a response. Returning a string or a
+/// bytevector responds with it as the body, with a status of 200.
+///
+/// (http-server/route! server method path handler) -> void?
+///
+/// * server : http-server?
+/// * method : (or/c symbol? string?) - such as `'GET` or `'POST`
+/// * path : string?
+/// * handler : (-> http-request? (or/c http-response? string? bytes?))
+#[function(name = "http-server/route!")]
+pub fn route(
+    server: &SteelVal,
+    method: &SteelVal,
+    path: &SteelString,
+    handler: &SteelVal,
+) -> Result<SteelVal> {
+    let method = match method {
+        SteelVal::SymbolV(s) | SteelVal::StringV(s) => s.to_ascii_uppercase(),
+        _ => stop!(TypeMismatch => "http-server/route! expects a method, found: {}", method),
+    };
+
+    if !handler.is_function() {
+        stop!(TypeMismatch => "http-server/route! expects a function, found: {}", handler);
+    }
+
+    let route = Route {
+        method: (method != "ANY").then_some(method),
+        segments: parse_pattern(path)?,
+        handler: handler.clone(),
+    };
+
+    HttpServer::as_mut_ref(server)?
+        .dispatcher
+        .routes
+        .push(route);
+
+    Ok(SteelVal::Void)
+}
+
+/// Adds middleware that runs before the request is routed. It's called with the request, and
+/// returns either a request to pass along, or a response to answer with right away.
+///
+/// (http-server/before! server middleware) -> void?
+///
+/// * server : http-server?
+/// * middleware : (-> http-request? (or/c http-request? http-response?))
+#[function(name = "http-server/before!")]
+pub fn before(server: &SteelVal, middleware: &SteelVal) -> Result<SteelVal> {
+    if !middleware.is_function() {
+        stop!(TypeMismatch => "http-server/before! expects a function, found: {}", middleware);
+    }
+
+    HttpServer::as_mut_ref(server)?
+        .dispatcher
+        .before
+        .push(middleware.clone());
+
+    Ok(SteelVal::Void)
+}
+
+/// Adds middleware that runs once there is a response, including ones from earlier middleware.
+/// It's called with the request and the response, and returns the response to send.
+///
+/// (http-server/after! server middleware) -> void?
+///
+/// * server : http-server?
+/// * middleware : (-> http-request? http-response? http-response?)
+#[function(name = "http-server/after!")]
+pub fn after(server: &SteelVal, middleware: &SteelVal) -> Result<SteelVal> {
+    if !middleware.is_function() {
+        stop!(TypeMismatch => "http-server/after! expects a function, found: {}", middleware);
+    }
+
+    HttpServer::as_mut_ref(server)?
+        .dispatcher
+        .after
+        .push(middleware.clone());
+
+    Ok(SteelVal::Void)
+}
+
+/// Serves the files in a directory for `GET` requests under the given prefix, when no route
+/// matches. A request for a directory serves its `index.html`.
+///
+/// (http-server/static! server prefix directory) -> void?
+///
+/// * server : http-server?
+/// * prefix : string? - such as "/assets"
+/// * directory : string?
+#[function(name = "http-server/static!")]
+pub fn static_files(
+    server: &SteelVal,
+    prefix: &SteelString,
+    directory: &SteelString,
+) -> Result<SteelVal> {
+    let mut segments = parse_pattern(prefix)?;
+    segments.push(Segment::Rest("file".to_string()));
+
+    HttpServer::as_mut_ref(server)?
+        .dispatcher
+        .static_files
+        .push(StaticFiles {
+            prefix: segments,
+            directory: PathBuf::from(directory.as_str()),
+        });
+
+    Ok(SteelVal::Void)
+}
+
+/// Binds the server to an address, returning the port it's listening on. Binding to port 0
+/// picks a free port.
+///
+/// (http-server/listen! server address) -> int?
+///
+/// * server : http-server?
+/// * address : string? - such as "127.0.0.1:8080"
+#[function(name = "http-server/listen!")]
+pub fn listen(server: &SteelVal, address: &SteelString) -> Result<SteelVal> {
+    let listener = match tiny_http::Server::http(address.as_str()) {
+        Ok(listener) => listener,
+        Err(e) => stop!(Io => "http-server/listen!: unable to bind to {}: {}", address, e),
+    };
+
+    let port = listener
+        .server_addr()
+        .to_ip()
+        .map(|x| x.port())
+        .unwrap_or(0);
+
+    let mut server = HttpServer::as_mut_ref(server)?;
+    server.server = Some(listener);
+    server.shutdown.store(false, Ordering::SeqCst);
+
+    Ok(SteelVal::IntV(port as isize))
+}
+
+// Answers requests on the engine's thread until the server is stopped, then closes the
+// listener.
+//
+// (http-server/serve! server) -> void?
+fn serve(ctx: &mut VmCore, args: &[SteelVal]) -> Option<Result<SteelVal>> {
+    Some(serve_result(ctx, args))
+}
+
+fn serve_result(ctx: &mut VmCore, args: &[SteelVal]) -> Result<SteelVal> {
+    if args.len() != 1 {
+        stop!(ArityMismatch => "http-server/serve! expects one argument, found: {}", args.len());
+    }
+
+    // Take the listener out for the duration, so handlers can still reach the server
+    let (listener, dispatcher, shutdown) = {
+        let mut server = HttpServer::as_mut_ref(&args[0])?;
+
+        match server.server.take() {
+            Some(listener) => (
+                listener,
+                server.dispatcher.clone(),
+                Arc::clone(&server.shutdown),
+            ),
+            None => {
+                stop!(Generic => "http-server/serve!: the server isn't listening, call http-server/listen! first")
+            }
+        }
+    };
+
+    let mut call =
+        |function: &SteelVal, args: List<SteelVal>| ctx.call_function_many_args(function, args);
+
+    if let Err(e) = serve_requests(&listener, &dispatcher, &shutdown, &mut call) {
+        stop!(Io => "http-server/serve!: {}", e);
+    }
+
+    Ok(SteelVal::Void)
+}
+
+/// Stops the server once it has answered the requests it has already received.
+///
+/// (http-server/stop! server) -> void?
+///
+/// * server : (or/c http-server? http-server/shutdown-handle?)
+#[function(name = "http-server/stop!")]
+pub fn stop(server: &SteelVal) -> Result<SteelVal> {
+    let shutdown = match ShutdownHandle::as_ref(server, &mut ()) {
+        Ok(handle) => Arc::clone(&handle.shutdown),
+        Err(_) => Arc::clone(&HttpServer::as_ref(server, &mut ())?.shutdown),
+    };
+
+    shutdown.store(true, Ordering::SeqCst);
+
+    Ok(SteelVal::Void)
+}
+
+/// Returns a handle that stops the server with `http-server/stop!`, which can be sent to
+/// another thread.
+///
+/// (http-server/shutdown-handle server) -> http-server/shutdown-handle?
+///
+/// * server : http-server?
+#[function(name = "http-server/shutdown-handle")]
+pub fn shutdown_handle(server: &SteelVal) -> Result<SteelVal> {
+    let shutdown = Arc::clone(&HttpServer::as_ref(server, &mut ())?.shutdown);
+    ShutdownHandle { shutdown }.into_steelval()
+}
+
+fn pairs_to_hash(pairs: &[(String, String)]) -> SteelVal {
+    SteelVal::HashMapV(Gc::new(
+        pairs
+            .iter()
+            .map(|(key, value)| {
+                (
+                    SteelVal::StringV(key.as_str().into()),
+                    SteelVal::StringV(value.as_str().into()),
+                )
+            })
+            .collect(),
+    ))
+}
+
+/// Returns the method of the request, such as "GET".
+///
+/// (http-request-method request) -> string?
+#[function(name = "http-request-method")]
+pub fn request_method(request: &SteelVal) -> Result<SteelVal> {
+    Ok(SteelVal::StringV(
+        HttpRequest::as_ref(request, &mut ())?
+            .method
+            .as_str()
+            .into(),
+    ))
+}
+
+/// Returns the path of the request, without the query string.
+///
+/// (http-request-path request) -> string?
+#[function(name = "http-request-path")]
+pub fn request_path(request: &SteelVal) -> Result<SteelVal> {
+    Ok(SteelVal::StringV(
+        HttpRequest::as_ref(request, &mut ())?.path.as_str().into(),
+    ))
+}
+
+/// Returns the parameters matched by the route, keyed by name.
+///
+/// (http-request-params request) -> hash?
+#[function(name = "http-request-params")]
+pub fn request_params(request: &SteelVal) -> Result<SteelVal> {
+    Ok(pairs_to_hash(
+        &HttpRequest::as_ref(request, &mut ())?.params,
+    ))
+}
+
+/// Returns a parameter matched by the route, or `#false` if there isn't one by that name.
+///
+/// (http-request-param request name) -> (or/c string? #false)
+#[function(name = "http-request-param")]
+pub fn request_param(request: &SteelVal, name: &SteelString) -> Result<SteelVal> {
+    HttpRequest::as_ref(request, &mut ())?
+        .params
+        .iter()
+        .find(|(key, _)| key == name.as_str())
+        .map(|(_, value)| value.clone())
+        .into_steelval()
+}
+
+/// Returns the query string of the request, decoded into a hash. When a key is repeated, the
+/// last value wins.
+///
+/// (http-request-query request) -> hash?
+#[function(name = "http-request-query")]
+pub fn request_query(request: &SteelVal) -> Result<SteelVal> {
+    Ok(pairs_to_hash(&HttpRequest::as_ref(request, &mut ())?.query))
+}
+
+/// Returns the headers of the request, keyed by their lower cased names.
+///
+/// (http-request-headers request) -> hash?
+#[function(name = "http-request-headers")]
+pub fn request_headers(request: &SteelVal) -> Result<SteelVal> {
+    Ok(pairs_to_hash(
+        &HttpRequest::as_ref(request, &mut ())?.headers,
+    ))
+}
+
+/// Returns a header of the request, or `#false` if it wasn't sent. Names are case insensitive.
+///
+/// (http-request-header request name) -> (or/c string? #false)
+#[function(name = "http-request-header")]
+pub fn request_header(request: &SteelVal, name: &SteelString) -> Result<SteelVal> {
+    HttpRequest::as_ref(request, &mut ())?
+        .header(name)
+        .map(|x| x.to_string())
+        .into_steelval()
+}
+
+/// Returns the body of the request as bytes.
+///
+/// (http-request-body request) -> bytes?
+#[function(name = "http-request-body")]
+pub fn request_body(request: &SteelVal) -> Result<SteelVal> {
+    Ok(bytes_to_steelval(
+        HttpRequest::as_ref(request, &mut ())?.body.clone(),
+    ))
+}
+
+/// Returns the body of the request as a string.
+///
+/// (http-request-text request) -> string?
+#[function(name = "http-request-text")]
+pub fn request_text(request: &SteelVal) -> Result<SteelVal> {
+    let mut nursery = ();
+    let request = HttpRequest::as_ref(request, &mut nursery)?;
+
+    match std::str::from_utf8(&request.body) {
+        Ok(text) => Ok(SteelVal::StringV(text.into())),
+        Err(e) => stop!(ConversionError => "http-request-text: the body isn't valid utf-8: {}", e),
+    }
+}
+
+/// Parses the body of the request as json.
+///
+/// (http-request-json request) -> any/c
+#[function(name = "http-request-json")]
+pub fn request_json(request: &SteelVal) -> Result<SteelVal> {
+    let mut nursery = ();
+    let request = HttpRequest::as_ref(request, &mut nursery)?;
+
+    match serde_json::from_slice::<Value>(&request.body) {
+        Ok(value) => value.into_steelval(),
+        Err(e) => stop!(ConversionError => "http-request-json: unable to parse the body: {}", e),
+    }
+}
+
+fn header_pairs(headers: Option<&SteelVal>) -> Result<Vec<(String, String)>> {
+    let headers = match headers {
+        Some(SteelVal::HashMapV(headers)) => headers,
+        Some(other) => stop!(TypeMismatch => "expected a hash of headers, found: {}", other),
+        None => return Ok(Vec::new()),
+    };
+
+    headers
+        .iter()
+        .map(|(key, value)| match (key, value) {
+            (SteelVal::StringV(key) | SteelVal::SymbolV(key), SteelVal::StringV(value)) => {
+                Ok((key.to_ascii_lowercase(), value.to_string()))
+            }
+            _ => stop!(TypeMismatch => "headers must map names to strings, found: {} => {}", key, value),
+        })
+        .collect()
+}
+
+fn with_headers(mut response: HttpResponse, headers: Vec<(String, String)>) -> HttpResponse {
+    for (name, value) in headers {
+        response.headers.retain(|(key, _)| *key != name);
+        response.headers.push((name, value));
+    }
+
+    response
+}
+
+/// Creates a response. A string body is sent as plain text and bytes as binary, unless the
+/// headers say otherwise.
+///
+/// (http-response status body [headers]) -> http-response?
+///
+/// * status : int?
+/// * body : (or/c string? bytes? void?)
+/// * headers : hash? - mapping header names to values
+#[function(name = "http-response")]
+pub fn response(
+    status: u32,
+    body: &SteelVal,
+    mut rest: RestArgsIter<'_, &SteelVal>,
+) -> Result<SteelVal> {
+    let headers = header_pairs(rest.next().transpose()?)?;
+
+    let response = match body {
+        SteelVal::StringV(s) => HttpResponse::text(status as u16, s),
+        SteelVal::ByteVector(b) => HttpResponse::new(
+            status as u16,
+            "application/octet-stream",
+            b.borrow().clone(),
+        ),
+        SteelVal::Void => HttpResponse {
+            status: status as u16,
+            headers: Vec::new(),
+            body: Vec::new(),
+        },
+        _ => {
+            stop!(TypeMismatch => "http-response expects a string or bytes as the body, found: {}", body)
+        }
+    };
+
+    with_headers(response, headers).into_steelval()
+}
+
+/// Creates a response with the value serialized as json.
+///
+/// (http-response/json status value [headers]) -> http-response?
+///
+/// * status : int?
+/// * value : any/c
+/// * headers : hash? - mapping header names to values
+#[function(name = "http-response/json")]
+pub fn json_response(
+    status: u32,
+    value: &SteelVal,
+    mut rest: RestArgsIter<'_, &SteelVal>,
+) -> Result<SteelVal> {
+    let headers = header_pairs(rest.next().transpose()?)?;
+    let body = Value::try_from(value.clone())?.to_string();
+
+    with_headers(
+        HttpResponse::new(status as u16, "application/json", body),
+        headers,
+    )
+    .into_steelval()
+}
+
+/// Returns the status of the response.
+///
+/// (http-response-status response) -> int?
+#[function(name = "http-response-status")]
+pub fn response_status(response: &SteelVal) -> Result<SteelVal> {
+    Ok(SteelVal::IntV(
+        HttpResponse::as_ref(response, &mut ())?.status as isize,
+    ))
+}
+
+/// Returns the headers of the response, keyed by their lower cased names.
+///
+/// (http-response-headers response) -> hash?
+#[function(name = "http-response-headers")]
+pub fn response_headers(response: &SteelVal) -> Result<SteelVal> {
+    Ok(pairs_to_hash(
+        &HttpResponse::as_ref(response, &mut ())?.headers,
+    ))
+}
+
+/// Returns the body of the response as bytes.
+///
+/// (http-response-body response) -> bytes?
+#[function(name = "http-response-body")]
+pub fn response_body(response: &SteelVal) -> Result<SteelVal> {
+    Ok(bytes_to_steelval(
+        HttpResponse::as_ref(response, &mut ())?.body.clone(),
+    ))
+}
+
+/// Returns a copy of the response with the header set, replacing any earlier value.
+///
+/// (http-response/with-header response name value) -> http-response?
+#[function(name = "http-response/with-header")]
+pub fn response_with_header(
+    response: &SteelVal,
+    name: &SteelString,
+    value: &SteelString,
+) -> Result<SteelVal> {
+    let response = HttpResponse::as_ref(response, &mut ())?.clone();
+
+    with_headers(
+        response,
+        vec![(name.to_ascii_lowercase(), value.to_string())],
+    )
+    .into_steelval()
+}
+
+#[cfg(test)]
+mod server_tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    #[test]
+    fn route_matching() {
+        let segments = parse_pattern("/users/:id/files/*path").unwrap();
+
+        assert_eq!(
+            match_path(&segments, "/users/42/files/a/b.txt"),
+            Some(vec![
+                ("id".to_string(), "42".to_string()),
+                ("path".to_string(), "a/b.txt".to_string())
+            ])
+        );
+        assert_eq!(match_path(&segments, "/users/42"), None);
+        assert_eq!(match_path(&parse_pattern("/").unwrap(), "/"), Some(vec![]));
+        assert_eq!(match_path(&parse_pattern("/a").unwrap(), "/a/b"), None);
+        assert!(parse_pattern("/*rest/after").is_err());
+    }
+
+    fn get(port: u16, path: &str) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        write!(
+            stream,
+            "GET {path} HTTP/1.1\r\nHost: localhost\r\nX-Token: secret\r\nConnection: close\r\n\r\n"
+        )
+        .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serves_localhost() {
+        let directory = std::env::temp_dir().join(format!("steel-http-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("index.html"), "<p>hi</p>").unwrap();
+
+        let mut static_prefix = parse_pattern("/assets").unwrap();
+        static_prefix.push(Segment::Rest("file".to_string()));
+
+        // Handlers are stood in for by strings, naming what the fake call below does
+        let dispatcher = Dispatcher {
+            routes: vec![Route {
+                method: Some("GET".to_string()),
+                segments: parse_pattern("/users/:id").unwrap(),
+                handler: SteelVal::StringV("user".into()),
+            }],
+            before: vec![SteelVal::StringV("auth".into())],
+            after: vec![],
+            static_files: vec![StaticFiles {
+                prefix: static_prefix,
+                directory: directory.clone(),
+            }],
+        };
+
+        let mut call = |function: &SteelVal, args: List<SteelVal>| -> Result<SteelVal> {
+            let request = HttpRequest::as_ref(&args[0], &mut ())?.clone();
+
+            match function {
+                SteelVal::StringV(s) if s.as_str() == "auth" => {
+                    if request.header("x-token") == Some("secret") {
+                        request.into_steelval()
+                    } else {
+                        HttpResponse::text(401, "Unauthorized").into_steelval()
+                    }
+                }
+                SteelVal::StringV(s) if s.as_str() == "user" => {
+                    let query = request.query.clone();
+                    Ok(SteelVal::StringV(
+                        format!("user {} {:?}", request.params[0].1, query).into(),
+                    ))
+                }
+                _ => stop!(Generic => "unexpected handler"),
+            }
+        };
+
+        let listener = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let port = listener.server_addr().to_ip().unwrap().port();
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let client = {
+            let shutdown = Arc::clone(&shutdown);
+
+            std::thread::spawn(move || {
+                let responses = vec![
+                    get(port, "/users/42?q=a%20b"),
+                    get(port, "/assets/"),
+                    get(port, "/assets/../secret"),
+                    get(port, "/missing"),
+                ];
+                shutdown.store(true, Ordering::SeqCst);
+                responses
+            })
+        };
+
+        serve_requests(&listener, &dispatcher, &shutdown, &mut call).unwrap();
+
+        let responses = client.join().unwrap();
+
+        assert!(responses[0].starts_with("HTTP/1.1 200"));
+        assert!(responses[0].ends_with("user 42 [(\"q\", \"a b\")]"));
+        assert!(responses[1].starts_with("HTTP/1.1 200"));
+        assert!(responses[1].ends_with("<p>hi</p>"));
+        assert!(responses[2].starts_with("HTTP/1.1 404"));
+        assert!(responses[3].starts_with("HTTP/1.1 404"));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,14 +1,34 @@
-use std::net::TcpStream;
+// The errors from tungstenite are large, but they're handed straight over to Steel
+#![allow(clippy::result_large_err)]
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use tungstenite::{
-    connect, handshake::client::Response, stream::MaybeTlsStream, Message, WebSocket,
+    connect,
+    handshake::{client::Response, HandshakeError},
+    protocol::{frame::coding::CloseCode, CloseFrame},
+    stream::MaybeTlsStream,
+    Message, WebSocket,
 };
 
 use crate::{
-    rvals::Custom,
+    primitives::bytevectors::bytes_to_steelval,
+    rvals::{
+        AsRefMutSteelVal, AsRefSteelVal, Custom, IntoSteelVal, Result, SerializableSteelVal,
+        SteelString,
+    },
     steel_vm::{builtin::BuiltInModule, register_fn::RegisterFn},
+    stop, SteelVal,
 };
 
+use steel_derive::function;
+
 type SteelWebSocket = WebSocket<MaybeTlsStream<TcpStream>>;
 
 impl Custom for SteelWebSocket {}
@@ -16,6 +36,10 @@ impl Custom for Message {
     fn fmt(&self) -> Option<std::result::Result<String, std::fmt::Error>> {
         Some(Ok(format!("{:?}", self)))
     }
+
+    fn into_serializable_steelval(&mut self) -> Option<SerializableSteelVal> {
+        Some(SerializableSteelVal::Custom(Box::new(self.clone())))
+    }
 }
 impl Custom for Response {}
 impl Custom for tungstenite::Error {
@@ -44,28 +68,667 @@ pub fn text_payload(message: &Message) -> Option<String> {
     }
 }
 
-// TODO: @Matt -> perhaps explore this and/or explore using tokio tungstenite for this
-fn _make_non_blocking(socket: &mut SteelWebSocket) -> std::result::Result<(), std::io::Error> {
-    match socket.get_mut() {
-        MaybeTlsStream::Plain(s) => s.set_nonblocking(true),
-        MaybeTlsStream::Rustls(s) => s.get_mut().set_nonblocking(true),
-        _ => todo!(),
+/// Extracts the raw bytes of a binary, ping or pong message
+fn binary_payload(message: &Message) -> Option<SteelVal> {
+    match message {
+        Message::Binary(payload) | Message::Ping(payload) | Message::Pong(payload) => {
+            Some(bytes_to_steelval(payload.clone()))
+        }
+        _ => None,
+    }
+}
+
+fn close_code(message: &Message) -> Option<usize> {
+    if let Message::Close(Some(frame)) = message {
+        Some(u16::from(frame.code) as usize)
+    } else {
+        None
+    }
+}
+
+fn close_reason(message: &Message) -> Option<String> {
+    if let Message::Close(Some(frame)) = message {
+        Some(frame.reason.to_string())
+    } else {
+        None
+    }
+}
+
+fn bytes_argument(value: &SteelVal) -> Result<Vec<u8>> {
+    match value {
+        SteelVal::ByteVector(bytes) => Ok(bytes.borrow().clone()),
+        SteelVal::StringV(s) => Ok(s.as_bytes().to_vec()),
+        _ => stop!(TypeMismatch => "expected bytes or a string for the payload, found: {}", value),
+    }
+}
+
+/// Makes a binary message.
+///
+/// (ws/message-binary payload) -> ws-message?
+///
+/// * payload : (or/c bytes? string?)
+#[function(name = "ws/message-binary")]
+pub fn binary_message(payload: &SteelVal) -> Result<SteelVal> {
+    Message::Binary(bytes_argument(payload)?).into_steelval()
+}
+
+/// Makes a ping message. The other side answers it with a pong carrying the same payload.
+///
+/// (ws/message-ping payload) -> ws-message?
+///
+/// * payload : (or/c bytes? string?)
+#[function(name = "ws/message-ping")]
+pub fn ping_message(payload: &SteelVal) -> Result<SteelVal> {
+    Message::Ping(bytes_argument(payload)?).into_steelval()
+}
+
+fn close_frame(code: usize, reason: &str) -> Result<CloseFrame<'static>> {
+    if !(1000..=4999).contains(&code) {
+        stop!(Generic => "invalid close code: {}, expected a code between 1000 and 4999", code);
+    }
+
+    Ok(CloseFrame {
+        code: CloseCode::from(code as u16),
+        reason: Cow::Owned(reason.to_string()),
+    })
+}
+
+/// Makes a close message, with a close code between 1000 and 4999.
+///
+/// (ws/message-close code reason) -> ws-message?
+///
+/// * code : int?
+/// * reason : string?
+#[function(name = "ws/message-close")]
+pub fn close_message(code: usize, reason: &SteelString) -> Result<SteelVal> {
+    Message::Close(Some(close_frame(code, reason.as_str())?)).into_steelval()
+}
+
+fn tcp_stream(socket: &SteelWebSocket) -> &TcpStream {
+    match socket.get_ref() {
+        MaybeTlsStream::Plain(s) => s,
+        MaybeTlsStream::Rustls(s) => s.get_ref(),
+        _ => unreachable!("only rustls is enabled for websockets"),
+    }
+}
+
+fn would_block(error: &tungstenite::Error) -> bool {
+    matches!(
+        error,
+        tungstenite::Error::Io(e)
+            if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut)
+    )
+}
+
+fn read_message(socket: &mut SteelWebSocket) -> std::result::Result<Message, tungstenite::Error> {
+    socket.read_message()
+}
+
+/// Waits at most `timeout` milliseconds for a message, returning #false if none came in. A
+/// timeout of 0 only checks for a message that has already arrived, so that several sockets
+/// can be polled in turn.
+fn read_message_timeout(
+    socket: &mut SteelWebSocket,
+    timeout: usize,
+) -> std::result::Result<Option<Message>, tungstenite::Error> {
+    let stream = tcp_stream(socket);
+
+    if timeout == 0 {
+        stream.set_nonblocking(true)?;
+    } else {
+        stream.set_read_timeout(Some(Duration::from_millis(timeout as u64)))?;
+    }
+
+    let message = socket.read_message();
+
+    let stream = tcp_stream(socket);
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(None)?;
+
+    match message {
+        Ok(message) => Ok(Some(message)),
+        // A message that was only partially read is kept by the socket for the next read
+        Err(e) if would_block(&e) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn write_message(
+    socket: &mut SteelWebSocket,
+    message: Message,
+) -> std::result::Result<(), tungstenite::Error> {
+    socket.write_message(message)
+}
+
+/// Starts closing the connection with the given code. Reading from the socket afterwards
+/// finishes the close handshake.
+///
+/// (ws/close! socket code reason) -> (or/c Ok? Err?)
+///
+/// * socket : ws-socket?
+/// * code : int?
+/// * reason : string?
+#[function(name = "ws/close!")]
+pub fn close(socket: &SteelVal, code: usize, reason: &SteelString) -> Result<SteelVal> {
+    let frame = close_frame(code, reason.as_str())?;
+    SteelWebSocket::as_mut_ref(socket)?
+        .close(Some(frame))
+        .into_steelval()
+}
+
+fn is_open(socket: &SteelWebSocket) -> bool {
+    socket.can_write()
+}
+
+fn handshake(stream: TcpStream) -> std::result::Result<SteelWebSocket, tungstenite::Error> {
+    tungstenite::accept(MaybeTlsStream::Plain(stream)).map_err(|e| match e {
+        HandshakeError::Failure(e) => e,
+        HandshakeError::Interrupted(_) => tungstenite::Error::Io(std::io::Error::new(
+            std::io::ErrorKind::WouldBlock,
+            "the handshake was interrupted",
+        )),
+    })
+}
+
+struct WebSocketListener {
+    listener: TcpListener,
+}
+
+impl Custom for WebSocketListener {}
+
+fn listen(address: String) -> std::result::Result<WebSocketListener, tungstenite::Error> {
+    Ok(WebSocketListener {
+        listener: TcpListener::bind(address)?,
+    })
+}
+
+impl WebSocketListener {
+    fn port(&self) -> std::result::Result<usize, tungstenite::Error> {
+        Ok(self.listener.local_addr()?.port() as usize)
+    }
+
+    /// Waits for the next connection, and completes the websocket handshake with it
+    fn accept(&self) -> std::result::Result<SteelWebSocket, tungstenite::Error> {
+        let (stream, _) = self.listener.accept()?;
+        handshake(stream)
+    }
+
+    /// Like `accept`, but returns #false straight away if nobody is waiting to connect
+    fn try_accept(&self) -> std::result::Result<Option<SteelWebSocket>, tungstenite::Error> {
+        self.listener.set_nonblocking(true)?;
+        let accepted = self.listener.accept();
+        self.listener.set_nonblocking(false)?;
+
+        match accepted {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                handshake(stream).map(Some)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WebSocketEventKind {
+    Open,
+    Message,
+    Close,
+}
+
+/// What happened on one of the connections of a hub
+#[derive(Debug, Clone)]
+struct WebSocketEvent {
+    kind: WebSocketEventKind,
+    connection: usize,
+    message: Option<Message>,
+}
+
+impl Custom for WebSocketEvent {
+    fn fmt(&self) -> Option<std::result::Result<String, std::fmt::Error>> {
+        Some(Ok(format!(
+            "#<ws-event {} {}>",
+            self.kind(),
+            self.connection
+        )))
+    }
+
+    fn into_serializable_steelval(&mut self) -> Option<SerializableSteelVal> {
+        Some(SerializableSteelVal::Custom(Box::new(self.clone())))
+    }
+}
+
+impl WebSocketEvent {
+    fn kind(&self) -> SteelVal {
+        SteelVal::SymbolV(
+            match self.kind {
+                WebSocketEventKind::Open => "open",
+                WebSocketEventKind::Message => "message",
+                WebSocketEventKind::Close => "close",
+            }
+            .into(),
+        )
+    }
+
+    fn connection(&self) -> usize {
+        self.connection
+    }
+
+    fn message(&self) -> Option<Message> {
+        self.message.clone()
+    }
+}
+
+type Connections = Arc<Mutex<HashMap<usize, SteelWebSocket>>>;
+
+/// Accepts connections and reads from all of them on a single background thread, handing
+/// what comes in to a channel. Messages are sent back through the hub by connection id.
+#[derive(Clone)]
+struct WebSocketHub {
+    connections: Connections,
+    running: Arc<AtomicBool>,
+    port: usize,
+}
+
+impl Custom for WebSocketHub {
+    fn into_serializable_steelval(&mut self) -> Option<SerializableSteelVal> {
+        Some(SerializableSteelVal::Custom(Box::new(self.clone())))
+    }
+}
+
+// How long the hub waits before polling again when nothing happened
+const HUB_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+// How long a connection gets to finish its handshake before it's dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Accepts connections for the hub, handing them over once their handshake is done. Each
+// handshake gets a thread of its own, since they're blocking and a half finished one can't be
+// resumed, so a slow client holds up neither the hub nor the other connections.
+fn run_acceptor(listener: TcpListener, running: Arc<AtomicBool>, accepted: Sender<SteelWebSocket>) {
+    while running.load(Ordering::Relaxed) {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(_) => {
+                std::thread::sleep(HUB_POLL_INTERVAL);
+                continue;
+            }
+        };
+
+        let accepted = accepted.clone();
+
+        std::thread::spawn(move || {
+            let connected = stream
+                .set_nonblocking(false)
+                .and_then(|_| stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)))
+                .map_err(tungstenite::Error::from)
+                .and_then(|_| handshake(stream));
+
+            if let Ok(socket) = connected {
+                if tcp_stream(&socket).set_nonblocking(true).is_ok() {
+                    // The hub is gone if this fails, and the connection is dropped with it
+                    let _ = accepted.send(socket);
+                }
+            }
+        });
+    }
+}
+
+fn run_hub(
+    accepted: Receiver<SteelWebSocket>,
+    connections: Connections,
+    running: Arc<AtomicBool>,
+    deliver: impl Fn(WebSocketEvent) -> bool,
+) {
+    let next_id = AtomicUsize::new(0);
+
+    while running.load(Ordering::Relaxed) {
+        let mut idle = true;
+        let mut events = Vec::new();
+
+        while let Ok(socket) = accepted.try_recv() {
+            idle = false;
+
+            let id = next_id.fetch_add(1, Ordering::Relaxed);
+            connections.lock().unwrap().insert(id, socket);
+
+            events.push(WebSocketEvent {
+                kind: WebSocketEventKind::Open,
+                connection: id,
+                message: None,
+            });
+        }
+
+        {
+            let mut connections = connections.lock().unwrap();
+            let mut closed = Vec::new();
+
+            for (id, socket) in connections.iter_mut() {
+                // Anything written from the other threads that didn't fit in the socket yet. The
+                // connection is broken if that fails, so it's dropped and reported as closed.
+                match socket.write_pending() {
+                    Err(e) if !would_block(&e) => {
+                        idle = false;
+                        closed.push((*id, None));
+                        continue;
+                    }
+                    _ => {}
+                }
+
+                match socket.read_message() {
+                    Ok(Message::Close(frame)) => {
+                        idle = false;
+                        let _ = socket.write_pending();
+                        closed.push((*id, Some(Message::Close(frame))));
+                    }
+                    Ok(message) => {
+                        idle = false;
+                        events.push(WebSocketEvent {
+                            kind: WebSocketEventKind::Message,
+                            connection: *id,
+                            message: Some(message),
+                        });
+                    }
+                    Err(e) if would_block(&e) => {}
+                    Err(_) => closed.push((*id, None)),
+                }
+            }
+
+            for (id, message) in closed {
+                connections.remove(&id);
+
+                events.push(WebSocketEvent {
+                    kind: WebSocketEventKind::Close,
+                    connection: id,
+                    message,
+                });
+            }
+        }
+
+        for event in events {
+            // Nobody is listening anymore, so there's no point in keeping the connections
+            if !deliver(event) {
+                running.store(false, Ordering::Relaxed);
+            }
+        }
+
+        if idle {
+            std::thread::sleep(HUB_POLL_INTERVAL);
+        }
+    }
+}
+
+/// Listens for websocket connections on the address, delivering their events to the
+/// channel from `make-channels`.
+fn listen_to_channel(
+    address: String,
+    channel: Sender<SerializableSteelVal>,
+) -> std::result::Result<WebSocketHub, tungstenite::Error> {
+    let listener = TcpListener::bind(address)?;
+    let port = listener.local_addr()?.port() as usize;
+
+    spawn_hub(listener, port, move |event| {
+        channel
+            .send(SerializableSteelVal::Custom(Box::new(event)))
+            .is_ok()
+    })
+}
+
+fn spawn_hub(
+    listener: TcpListener,
+    port: usize,
+    deliver: impl Fn(WebSocketEvent) -> bool + Send + 'static,
+) -> std::result::Result<WebSocketHub, tungstenite::Error> {
+    listener.set_nonblocking(true)?;
+
+    let hub = WebSocketHub {
+        connections: Arc::new(Mutex::new(HashMap::new())),
+        running: Arc::new(AtomicBool::new(true)),
+        port,
+    };
+
+    let (sender, accepted) = channel();
+
+    let running = Arc::clone(&hub.running);
+    std::thread::spawn(move || run_acceptor(listener, running, sender));
+
+    let connections = Arc::clone(&hub.connections);
+    let running = Arc::clone(&hub.running);
+    std::thread::spawn(move || run_hub(accepted, connections, running, deliver));
+
+    Ok(hub)
+}
+
+impl WebSocketHub {
+    fn port(&self) -> usize {
+        self.port
+    }
+
+    fn with_connection(
+        &self,
+        connection: usize,
+        f: impl FnOnce(&mut SteelWebSocket) -> std::result::Result<(), tungstenite::Error>,
+    ) -> std::result::Result<(), tungstenite::Error> {
+        let mut connections = self.connections.lock().unwrap();
+
+        let socket = connections
+            .get_mut(&connection)
+            .ok_or(tungstenite::Error::AlreadyClosed)?;
+
+        match f(socket) {
+            // The rest of the message is written out by the hub once the socket is ready
+            Err(e) if would_block(&e) => Ok(()),
+            other => other,
+        }
+    }
+
+    fn send(
+        &self,
+        connection: usize,
+        message: Message,
+    ) -> std::result::Result<(), tungstenite::Error> {
+        self.with_connection(connection, |socket| socket.write_message(message))
+    }
+
+    fn connections(&self) -> Vec<usize> {
+        let mut connections: Vec<_> = self.connections.lock().unwrap().keys().copied().collect();
+        connections.sort_unstable();
+        connections
+    }
+
+    fn stop(&self) {
+        self.running.store(false, Ordering::Relaxed);
     }
 }
 
+/// Starts closing one of the hub's connections. The hub reports it as closed once the other
+/// side answers.
+///
+/// (ws/hub-close! hub connection code reason) -> (or/c Ok? Err?)
+///
+/// * hub : ws-hub?
+/// * connection : int?
+/// * code : int?
+/// * reason : string?
+#[function(name = "ws/hub-close!")]
+pub fn hub_close(
+    hub: &SteelVal,
+    connection: usize,
+    code: usize,
+    reason: &SteelString,
+) -> Result<SteelVal> {
+    let frame = close_frame(code, reason.as_str())?;
+    WebSocketHub::as_ref(hub, &mut ())?
+        .with_connection(connection, |socket| socket.close(Some(frame)))
+        .into_steelval()
+}
+
 pub fn websockets_module() -> BuiltInModule {
     let mut module = BuiltInModule::new("steel/web/ws");
 
     module
+        .register_type::<Message>("ws/message?")
         .register_fn("ws/message-ping?", Message::is_ping)
+        .register_fn("ws/message-pong?", Message::is_pong)
+        // Deprecated, the misspelled name is kept for the scripts that already use it
         .register_fn("ws/message-pog?", Message::is_pong)
         .register_fn("ws/message-text?", Message::is_text)
+        .register_fn("ws/message-binary?", Message::is_binary)
+        .register_fn("ws/message-close?", Message::is_close)
         .register_fn("ws/message-text", Message::Text)
+        .register_native_fn_definition(BINARY_MESSAGE_DEFINITION)
+        .register_native_fn_definition(PING_MESSAGE_DEFINITION)
+        .register_native_fn_definition(CLOSE_MESSAGE_DEFINITION)
         .register_fn("ws/message-ping->pong", ping_to_pong)
         .register_fn("ws/message->text-payload", text_payload)
+        .register_fn("ws/message->binary-payload", binary_payload)
+        .register_fn("ws/message-close-code", close_code)
+        .register_fn("ws/message-close-reason", close_reason)
         .register_fn("ws/connect", connect::<String>)
-        .register_fn("ws/read-message!", SteelWebSocket::read_message)
-        .register_fn("ws/write-message!", SteelWebSocket::write_message);
+        .register_fn("ws/listen", listen)
+        .register_fn("ws/listener-port", WebSocketListener::port)
+        .register_fn("ws/accept", WebSocketListener::accept)
+        .register_fn("ws/try-accept", WebSocketListener::try_accept)
+        .register_fn("ws/read-message!", read_message)
+        .register_fn("ws/read-message-timeout!", read_message_timeout)
+        .register_fn("ws/write-message!", write_message)
+        .register_native_fn_definition(CLOSE_DEFINITION)
+        .register_fn("ws/open?", is_open)
+        .register_fn("ws/listen-to-channel", listen_to_channel)
+        .register_fn("ws/hub-port", WebSocketHub::port)
+        .register_fn("ws/hub-send!", WebSocketHub::send)
+        .register_native_fn_definition(HUB_CLOSE_DEFINITION)
+        .register_fn("ws/hub-connections", WebSocketHub::connections)
+        .register_fn("ws/hub-stop!", WebSocketHub::stop)
+        .register_type::<WebSocketHub>("ws/hub?")
+        .register_type::<WebSocketEvent>("ws-event?")
+        .register_fn("ws-event-kind", WebSocketEvent::kind)
+        .register_fn("ws-event-connection", WebSocketEvent::connection)
+        .register_fn("ws-event-message", WebSocketEvent::message);
 
     module
 }
+
+#[cfg(test)]
+mod websockets_tests {
+    use super::*;
+
+    fn connect_to(port: usize) -> SteelWebSocket {
+        connect(format!("ws://127.0.0.1:{port}")).unwrap().0
+    }
+
+    #[test]
+    fn server_and_client_exchange_frames() {
+        let listener = listen("127.0.0.1:0".to_string()).unwrap();
+        let port = listener.port().unwrap();
+
+        assert!(listener.try_accept().unwrap().is_none());
+
+        let client = std::thread::spawn(move || {
+            let mut socket = connect_to(port);
+
+            socket
+                .write_message(Message::Binary(vec![1, 2, 3]))
+                .unwrap();
+
+            // Nothing has been sent back yet
+            assert!(read_message_timeout(&mut socket, 20).unwrap().is_none());
+
+            let reply = read_message(&mut socket).unwrap();
+            assert_eq!(reply, Message::Text("done".to_string()));
+
+            socket
+                .close(Some(close_frame(4000, "bye").unwrap()))
+                .unwrap();
+
+            loop {
+                match socket.read_message() {
+                    Ok(_) => {}
+                    Err(tungstenite::Error::ConnectionClosed) => break,
+                    Err(e) => panic!("unexpected error: {e}"),
+                }
+            }
+        });
+
+        let mut server = listener.accept().unwrap();
+
+        let message = read_message(&mut server).unwrap();
+        assert_eq!(
+            binary_payload(&message),
+            Some(bytes_to_steelval(vec![1, 2, 3]))
+        );
+
+        std::thread::sleep(Duration::from_millis(40));
+        write_message(&mut server, Message::Text("done".to_string())).unwrap();
+
+        let message = read_message(&mut server).unwrap();
+        assert_eq!(close_code(&message), Some(4000));
+        assert_eq!(close_reason(&message), Some("bye".to_string()));
+
+        // Reading again sends the reply that finishes the close handshake
+        assert!(matches!(
+            read_message(&mut server),
+            Err(tungstenite::Error::ConnectionClosed)
+        ));
+
+        assert!(!is_open(&server));
+
+        // The client waits for the server to drop the connection
+        drop(server);
+        client.join().unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_close_codes() {
+        assert!(close_frame(999, "").is_err());
+        assert!(close_frame(5000, "").is_err());
+        assert!(close_frame(1000, "").is_ok());
+    }
+
+    #[test]
+    fn hub_delivers_events_to_channel() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port() as usize;
+
+        let (sender, receiver) = channel();
+        let hub = spawn_hub(listener, port, move |event| sender.send(event).is_ok()).unwrap();
+
+        // Never finishes its handshake, which mustn't hold up the connections after it
+        let _stalled = TcpStream::connect(("127.0.0.1", port as u16)).unwrap();
+
+        let mut first = connect_to(port);
+        let open = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(open.kind, WebSocketEventKind::Open);
+
+        let mut second = connect_to(port);
+        assert_eq!(receiver.recv().unwrap().kind, WebSocketEventKind::Open);
+        assert_eq!(hub.connections(), vec![0, 1]);
+
+        second
+            .write_message(Message::Text("hello".to_string()))
+            .unwrap();
+
+        let event = receiver.recv().unwrap();
+        assert_eq!(event.kind, WebSocketEventKind::Message);
+        assert_eq!(event.message, Some(Message::Text("hello".to_string())));
+
+        hub.send(event.connection, Message::Text("hi".to_string()))
+            .unwrap();
+        assert_eq!(
+            second.read_message().unwrap(),
+            Message::Text("hi".to_string())
+        );
+
+        first.close(None).unwrap();
+
+        let event = receiver.recv().unwrap();
+        assert_eq!(event.kind, WebSocketEventKind::Close);
+        assert_eq!(event.connection, open.connection);
+        assert!(hub
+            .send(open.connection, Message::Text("gone".to_string()))
+            .is_err());
+
+        hub.stop();
+    }
+}
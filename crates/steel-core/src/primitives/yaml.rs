@@ -0,0 +1,206 @@
+use std::convert::TryInto;
+
+use serde_yaml::Value;
+
+use crate::{
+    gc::Gc,
+    rerrs::{ErrorKind, SteelErr},
+    rvals::{RestArgsIter, Result, SteelString, SteelVal},
+    steel_vm::builtin::BuiltInModule,
+    stop,
+    values::{json_vals::KeyStyle, port::SteelPort},
+};
+
+use steel_derive::function;
+
+pub fn yaml_module() -> BuiltInModule {
+    let mut module = BuiltInModule::new("steel/yaml");
+    module
+        .register_native_fn_definition(STRING_TO_YAML_DEFINITION)
+        .register_native_fn_definition(READ_YAML_DEFINITION)
+        .register_native_fn_definition(VALUE_TO_YAML_STRING_DEFINITION)
+        .register_native_fn_definition(WRITE_YAML_DEFINITION);
+    module
+}
+
+// Mappings become hashes and sequences become lists. String keys follow the key style, any
+// other keys are converted like values. Tags are dropped, leaving the value they were on.
+fn yaml_to_steelval(value: Value, keys: KeyStyle) -> SteelVal {
+    match value {
+        Value::Null => SteelVal::Void,
+        Value::Bool(b) => SteelVal::BoolV(b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => SteelVal::IntV(i as isize),
+            None => SteelVal::NumV(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => SteelVal::StringV(s.into()),
+        Value::Sequence(values) => SteelVal::ListV(
+            values
+                .into_iter()
+                .map(|value| yaml_to_steelval(value, keys))
+                .collect(),
+        ),
+        Value::Mapping(mapping) => SteelVal::HashMapV(Gc::new(
+            mapping
+                .into_iter()
+                .map(|(key, value)| {
+                    let key = match key {
+                        Value::String(key) => keys.key(key),
+                        other => yaml_to_steelval(other, keys),
+                    };
+
+                    (key, yaml_to_steelval(value, keys))
+                })
+                .collect(),
+        )),
+        Value::Tagged(tagged) => yaml_to_steelval(tagged.value, keys),
+    }
+}
+
+fn parse(function: &str, input: &str, options: Option<&SteelVal>) -> Result<SteelVal> {
+    let keys = KeyStyle::from_options(options)?;
+
+    match serde_yaml::from_str::<Value>(input) {
+        Ok(value) => Ok(yaml_to_steelval(value, keys)),
+        Err(e) => stop!(Generic => "{} failed: {}", function, e),
+    }
+}
+
+// Goes through the json conversion, so the same values can be written as either format
+fn to_yaml_string(function: &str, value: &SteelVal) -> Result<String> {
+    let json: serde_json::Value = value.clone().try_into()?;
+
+    serde_yaml::to_string(&json)
+        .map_err(|e| SteelErr::new(ErrorKind::Generic, format!("{function} failed: {e}")))
+}
+
+/// Parses a string of YAML. Mappings become hashes, sequences become lists and null becomes
+/// void. Only the first document is read.
+///
+/// (string->yaml string [options]) -> any/c
+///
+/// * string : string?
+/// * options : hash? - `'keys` is either `'symbol` (the default) or `'string`
+///
+/// # Examples
+/// ```scheme
+/// > (string->yaml "name: steel\ntags: [lisp, rust]") ;; => '#hash((name . "steel") (tags . ("lisp" "rust")))
+/// ```
+#[function(name = "string->yaml")]
+pub fn string_to_yaml(
+    input: &SteelString,
+    mut rest: RestArgsIter<'_, &SteelVal>,
+) -> Result<SteelVal> {
+    let options = rest.next().transpose()?;
+
+    if rest.next().is_some() {
+        stop!(ArityMismatch => "string->yaml takes at most two arguments");
+    }
+
+    parse("string->yaml", input, options)
+}
+
+/// Reads the rest of an input port as YAML.
+///
+/// (read-yaml port [options]) -> any/c
+///
+/// * port : input-port?
+/// * options : hash? - `'keys` is either `'symbol` (the default) or `'string`
+#[function(name = "read-yaml")]
+pub fn read_yaml(port: &Gc<SteelPort>, mut rest: RestArgsIter<'_, &SteelVal>) -> Result<SteelVal> {
+    let options = rest.next().transpose()?;
+
+    if rest.next().is_some() {
+        stop!(ArityMismatch => "read-yaml takes at most two arguments");
+    }
+
+    let (_, input) = port.read_all_str()?;
+
+    parse("read-yaml", &input, options)
+}
+
+/// Serializes a value to YAML.
+///
+/// (value->yaml-string value) -> string?
+///
+/// * value : any/c
+#[function(name = "value->yaml-string")]
+pub fn value_to_yaml_string(value: &SteelVal) -> Result<SteelVal> {
+    Ok(SteelVal::StringV(
+        to_yaml_string("value->yaml-string", value)?.into(),
+    ))
+}
+
+/// Writes a value to an output port as YAML.
+///
+/// (write-yaml! port value) -> void?
+///
+/// * port : output-port?
+/// * value : any/c
+#[function(name = "write-yaml!")]
+pub fn write_yaml(port: &Gc<SteelPort>, value: &SteelVal) -> Result<SteelVal> {
+    let output = to_yaml_string("write-yaml!", value)?;
+    port.with_writer(|writer| writer.write_all(output.as_bytes()))?;
+    Ok(SteelVal::Void)
+}
+
+#[cfg(test)]
+mod yaml_tests {
+    use super::*;
+
+    fn parse_str(input: &str) -> SteelVal {
+        string_to_yaml(&input.into(), RestArgsIter::from_slice(&[]).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_mappings_and_sequences() {
+        let value = parse_str("name: steel\ntags: [lisp, rust]\nmissing: ~\n1: one\n");
+
+        let SteelVal::HashMapV(map) = value else {
+            panic!("expected a hash");
+        };
+
+        assert_eq!(
+            map.get(&SteelVal::SymbolV("name".into())),
+            Some(&SteelVal::StringV("steel".into()))
+        );
+        assert_eq!(
+            map.get(&SteelVal::SymbolV("tags".into())),
+            Some(&SteelVal::ListV(
+                vec![
+                    SteelVal::StringV("lisp".into()),
+                    SteelVal::StringV("rust".into())
+                ]
+                .into()
+            ))
+        );
+        assert_eq!(
+            map.get(&SteelVal::SymbolV("missing".into())),
+            Some(&SteelVal::Void)
+        );
+        assert_eq!(
+            map.get(&SteelVal::IntV(1)),
+            Some(&SteelVal::StringV("one".into()))
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let input = "server:\n  host: localhost\n  port: 8080\n";
+
+        let output = value_to_yaml_string(&parse_str(input)).unwrap();
+
+        assert_eq!(output, SteelVal::StringV(input.into()));
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let error = string_to_yaml(
+            &"a: [1, 2\nb: 3".into(),
+            RestArgsIter::from_slice(&[]).unwrap(),
+        )
+        .unwrap_err();
+
+        assert!(error.to_string().contains("string->yaml failed"));
+    }
+}
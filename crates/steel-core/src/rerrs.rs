@@ -178,6 +178,10 @@ impl SteelErr {
         self.repr.message.insert_str(0, &message)
     }
 
+    pub fn append_message(&mut self, message: &str) {
+        self.repr.message.push_str(message)
+    }
+
     pub fn new(kind: ErrorKind, message: String) -> Self {
         SteelErr {
             repr: Box::new(Repr {
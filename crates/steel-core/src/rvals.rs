@@ -1,4 +1,5 @@
 pub mod cycles;
+pub mod serialize;
 
 use crate::{
     gc::{unsafe_erased_pointers::OpaqueReference, Gc},
@@ -12,7 +13,7 @@ use crate::{
     steel_vm::vm::{BuiltInSignature, Continuation},
     values::port::SteelPort,
     values::{
-        contracts::{ContractType, ContractedFunction},
+        contracts::{ContractType, ContractedCollection, ContractedFunction},
         functions::ByteCodeLambda,
         lazy_stream::LazyStream,
         transducers::{Reducer, Transducer},
@@ -20,9 +21,6 @@ use crate::{
     values::{functions::BoxedDynFunction, structs::UserDefinedStruct},
 };
 
-// #[cfg(feature = "jit")]
-// use crate::jit::sig::JitFunctionPointer;
-
 use std::{
     any::Any,
     cell::{Ref, RefCell, RefMut},
@@ -156,6 +154,12 @@ pub trait Custom: private::Sealed {
     fn into_serializable_steelval(&mut self) -> Option<SerializableSteelVal> {
         None
     }
+
+    /// Opt in to `value->bytes`. The type must also register a deserializer
+    /// with the engine under the same name.
+    fn to_serialized_bytes(&self) -> Option<Vec<u8>> {
+        None
+    }
 }
 
 pub trait CustomType {
@@ -174,6 +178,10 @@ pub trait CustomType {
     fn as_serializable_steelval(&mut self) -> Option<SerializableSteelVal> {
         None
     }
+
+    fn as_serialized_bytes(&self) -> Option<Vec<u8>> {
+        None
+    }
     // fn as_underlying_type<'a>(&'a self) -> Option<&'a Self>;
 }
 
@@ -195,6 +203,10 @@ impl<T: Custom + 'static> CustomType for T {
     fn as_serializable_steelval(&mut self) -> Option<SerializableSteelVal> {
         self.into_serializable_steelval()
     }
+
+    fn as_serialized_bytes(&self) -> Option<Vec<u8>> {
+        self.to_serialized_bytes()
+    }
 }
 
 impl<T: CustomType + 'static> IntoSteelVal for T {
@@ -1000,6 +1012,8 @@ pub enum SteelVal {
     Contract(Gc<ContractType>),
     /// Contracted Function
     ContractedFunction(Gc<ContractedFunction>),
+    /// List, vector or hash map whose elements are checked against a contract as they are read
+    ContractedCollection(Gc<ContractedCollection>),
     /// Custom closure
     BoxedFunction(Rc<BoxedDynFunction>),
     // Continuation
@@ -1029,6 +1043,9 @@ pub enum SteelVal {
     Reference(Rc<OpaqueReference<'static>>),
 
     BigNum(Gc<num::BigInt>),
+
+    // Mutable buffer of raw bytes
+    ByteVector(Gc<RefCell<Vec<u8>>>),
 }
 
 // TODO: Consider unboxed value types, for optimized usages when compiling segments of code.
@@ -1191,6 +1208,9 @@ pub enum BuiltInDataStructureIterator {
     Map(im_rc::hashmap::ConsumingIter<(SteelVal, SteelVal)>),
     String(Chunks),
     Opaque(Box<dyn Iterator<Item = SteelVal>>),
+    // For sources that can fail part way through, like rows read from a database. The error
+    // goes to whoever pulls the next value.
+    Fallible(Box<dyn Iterator<Item = Result<SteelVal>>>),
 }
 
 impl BuiltInDataStructureIterator {
@@ -1210,16 +1230,19 @@ impl BuiltInDataStructureIterator {
 }
 
 impl Iterator for BuiltInDataStructureIterator {
-    type Item = SteelVal;
+    type Item = Result<SteelVal>;
 
-    fn next(&mut self) -> Option<SteelVal> {
+    fn next(&mut self) -> Option<Result<SteelVal>> {
         match self {
-            Self::List(l) => l.next(),
-            Self::Vector(v) => v.next(),
-            Self::String(s) => s.remaining.next().map(SteelVal::CharV),
-            Self::Set(s) => s.next(),
-            Self::Map(s) => s.next().map(|x| SteelVal::ListV(im_lists::list![x.0, x.1])),
-            Self::Opaque(s) => s.next(),
+            Self::List(l) => l.next().map(Ok),
+            Self::Vector(v) => v.next().map(Ok),
+            Self::String(s) => s.remaining.next().map(|x| Ok(SteelVal::CharV(x))),
+            Self::Set(s) => s.next().map(Ok),
+            Self::Map(s) => s
+                .next()
+                .map(|x| Ok(SteelVal::ListV(im_lists::list![x.0, x.1]))),
+            Self::Opaque(s) => s.next().map(Ok),
+            Self::Fallible(s) => s.next(),
         }
     }
 }
@@ -1239,7 +1262,7 @@ pub fn value_into_iterator(val: SteelVal) -> SteelVal {
 pub fn iterator_next(args: &[SteelVal]) -> Result<SteelVal> {
     match &args[0] {
         SteelVal::BoxedIterator(b) => match b.borrow_mut().next() {
-            Some(v) => Ok(v),
+            Some(v) => v,
             None => Ok(SteelVal::Void),
         },
         _ => stop!(TypeMismatch => "Unexpected argument"),
@@ -1272,6 +1295,9 @@ impl SteelVal {
             (StreamV(l), StreamV(r)) => Gc::ptr_eq(l, r),
             (Contract(l), Contract(r)) => Gc::ptr_eq(l, r),
             (SteelVal::ContractedFunction(l), SteelVal::ContractedFunction(r)) => Gc::ptr_eq(l, r),
+            (SteelVal::ContractedCollection(l), SteelVal::ContractedCollection(r)) => {
+                Gc::ptr_eq(l, r)
+            }
             (BoxedFunction(l), BoxedFunction(r)) => Rc::ptr_eq(l, r),
             (ContinuationFunction(l), ContinuationFunction(r)) => Gc::ptr_eq(l, r),
             // (CompiledFunction(_), CompiledFunction(_)) => todo!(),
@@ -1279,6 +1305,7 @@ impl SteelVal {
             (MutFunc(l), MutFunc(r)) => *l as usize == *r as usize,
             (BuiltIn(l), BuiltIn(r)) => *l as usize == *r as usize,
             (MutableVector(l), MutableVector(r)) => Gc::ptr_eq(l, r),
+            (ByteVector(l), ByteVector(r)) => Gc::ptr_eq(l, r),
             (_, _) => false,
         }
     }
@@ -1340,6 +1367,8 @@ impl Hash for SteelVal {
             HashMapV(hm) => hm.hash(state),
             IterV(s) => s.hash(state),
             HashSetV(hs) => hs.hash(state),
+            ByteVector(b) => b.borrow().hash(state),
+            ContractedCollection(c) => c.unchecked().hash(state),
             _ => {
                 println!("Trying to hash: {self:?}");
                 unimplemented!()
@@ -1647,10 +1676,14 @@ impl PartialEq for SteelVal {
             (HashMapV(l), HashMapV(r)) => l == r,
             (Closure(l), Closure(r)) => l == r,
             (ContractedFunction(l), ContractedFunction(r)) => l == r,
+            (ContractedCollection(l), ContractedCollection(r)) => l == r,
+            (ContractedCollection(l), r) => l.unchecked() == r,
+            (l, ContractedCollection(r)) => l == r.unchecked(),
             (Contract(l), Contract(r)) => l == r,
             (IterV(l), IterV(r)) => l == r,
             (ListV(l), ListV(r)) => l == r,
             (CustomStruct(l), CustomStruct(r)) => l == r,
+            (ByteVector(l), ByteVector(r)) => l == r,
             (FuncV(l), FuncV(r)) => *l as usize == *r as usize,
             //TODO
             (_, _) => false, // (l, r) => {
@@ -55,6 +55,7 @@ impl CycleDetector {
             IntV(x) => write!(f, "{x}"),
             StringV(s) => write!(f, "{s:?}"),
             BigNum(b) => write!(f, "{}", b.as_ref()),
+            ByteVector(b) => write_bytes(&b.borrow(), f),
             CharV(c) => write!(f, "#\\{c}"),
             FuncV(func) => {
                 if let Some(name) = get_function_name(*func) {
@@ -111,6 +112,7 @@ impl CycleDetector {
             StreamV(_) => write!(f, "#<stream>"),
             Contract(c) => write!(f, "{}", **c),
             ContractedFunction(_) => write!(f, "#<contracted-function>"),
+            ContractedCollection(c) => self.top_level_format_with_cycles(c.unchecked(), f),
             BoxedFunction(b) => {
                 if let Some(name) = b.name() {
                     write!(f, "#<function:{}>", name)
@@ -219,6 +221,7 @@ impl CycleDetector {
             StreamV(_) => write!(f, "#<stream>"),
             Contract(c) => write!(f, "{}", **c),
             ContractedFunction(_) => write!(f, "#<contracted-function>"),
+            ContractedCollection(c) => self.format_with_cycles(c.unchecked(), f),
             BoxedFunction(b) => {
                 if let Some(name) = b.name() {
                     write!(f, "#<function:{}>", name)
@@ -265,6 +268,7 @@ impl CycleDetector {
             Boxed(b) => write!(f, "'#&{}", b.borrow()),
             Reference(x) => write!(f, "{}", x.format()?),
             BigNum(b) => write!(f, "{}", b.as_ref()),
+            ByteVector(b) => write_bytes(&b.borrow(), f),
         }
     }
 
@@ -316,3 +320,17 @@ impl CycleDetector {
         }
     }
 }
+
+fn write_bytes(bytes: &[u8], f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "#u8(")?;
+
+    let mut iter = bytes.iter();
+    if let Some(first) = iter.next() {
+        write!(f, "{first}")?;
+        for byte in iter {
+            write!(f, " {byte}")?;
+        }
+    }
+
+    write!(f, ")")
+}
@@ -0,0 +1,1116 @@
+//! A stable, versioned binary format for Steel values.
+//!
+//! Values are flattened into a graph of nodes, where every child is referred to by its index
+//! in the node table. Heap allocated values are only written once, so sharing between values
+//! is preserved, and cycles through mutable values (boxes, mutable vectors and structs)
+//! round trip as well.
+//!
+//! The encoded bytes start with a magic number and the format version, followed by the
+//! `bincode` encoding of the node table.
+//!
+//! The same nodes are used for the engine images written by `Engine::snapshot`, which can hold
+//! closures, struct types and references to native functions as well.
+
+use super::*;
+
+use crate::{
+    core::instructions::DenseInstruction,
+    parser::interner::InternedString,
+    primitives::bytevectors::bytes_to_steelval,
+    steel_vm::{
+        builtin::{BuiltInModule, DocTemplate},
+        vm::VmCore,
+    },
+    values::{
+        closed::{Heap, HeapRef},
+        structs::{
+            lookup_struct_type_descriptor, struct_function, StructFunctionKind,
+            StructTypeDescriptor, UserDefinedStruct,
+        },
+    },
+};
+
+use serde::{Deserialize, Serialize};
+use steel_derive::function;
+
+const MAGIC: &[u8; 4] = b"STLV";
+
+/// The current version of the binary format. Bump this whenever the layout of
+/// [`SerializedNode`] changes.
+pub const FORMAT_VERSION: u16 = 3;
+
+pub type CustomDeserializer = Rc<dyn Fn(&[u8]) -> Result<SteelVal>>;
+
+/// Functions that reconstruct custom types from the bytes produced by
+/// [`Custom::to_serialized_bytes`], keyed by [`CustomType::name`] of the type. Each engine
+/// keeps its own, see `Engine::register_custom_deserializer`.
+pub type CustomDeserializers = std::collections::HashMap<String, CustomDeserializer>;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+enum SerializedNode {
+    Void,
+    Bool(bool),
+    Int(i64),
+    Num(f64),
+    Char(char),
+    BigNum(Vec<u8>),
+    String(String),
+    Symbol(String),
+    ByteVector(Vec<u8>),
+    List(Vec<usize>),
+    Vector(Vec<usize>),
+    MutableVector(Vec<usize>),
+    HashMap(Vec<(usize, usize)>),
+    HashSet(Vec<usize>),
+    Struct {
+        name: String,
+        // Only known for structs declared with `struct`
+        field_names: Option<Vec<String>>,
+        fields: Vec<usize>,
+    },
+    Boxed(usize),
+    Syntax {
+        raw: Option<usize>,
+        syntax: usize,
+        span: Span,
+    },
+    Custom {
+        name: String,
+        bytes: Vec<u8>,
+    },
+    // The rest only appear in engine images
+    Closure {
+        id: usize,
+        body: Vec<DenseInstruction>,
+        arity: usize,
+        is_multi_arity: bool,
+        captures: Vec<usize>,
+        heap_allocated: Vec<usize>,
+        spans: Option<Vec<Span>>,
+    },
+    // A captured variable that was moved to the heap, shared between the closures capturing it
+    HeapCell(usize),
+    Native(NativeName),
+    StructType(usize),
+    TypedStruct {
+        struct_type: usize,
+        fields: Vec<usize>,
+    },
+    StructFunction {
+        struct_type: usize,
+        kind: StructFunctionKind,
+    },
+}
+
+/// How a native function is found again when an engine image is read.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum NativeName {
+    // A value of a builtin module
+    Module { module: String, name: String },
+    // The value of a global, which the engine reading the image has to define
+    Global(String),
+}
+
+impl std::fmt::Display for NativeName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NativeName::Module { module, name } => write!(f, "{name} from {module}"),
+            NativeName::Global(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// Identifies a native function by the address of its code, or of its allocation.
+pub(crate) fn native_address(value: &SteelVal) -> Option<usize> {
+    match value {
+        FuncV(f) => Some(*f as usize),
+        BuiltIn(f) => Some(*f as usize),
+        MutFunc(f) => Some(*f as usize),
+        BoxedFunction(f) => Some(Rc::as_ptr(f) as usize),
+        _ => None,
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SerializedStructType {
+    name: String,
+    field_count: usize,
+    // Struct types of builtin modules exist in every engine, and are looked up by name
+    builtin: bool,
+    proc: Option<usize>,
+    properties: Option<usize>,
+}
+
+/// The global environment, constants and struct types of an engine, see `Engine::snapshot`.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Image {
+    nodes: Vec<SerializedNode>,
+    struct_types: Vec<SerializedStructType>,
+    globals: Vec<usize>,
+    constants: Vec<usize>,
+}
+
+pub(crate) struct RestoredImage {
+    pub(crate) globals: Vec<SteelVal>,
+    pub(crate) constants: Vec<SteelVal>,
+    pub(crate) spans: Vec<(usize, Rc<[Span]>)>,
+}
+
+/// Writes the globals and constants of an engine. Globals that can't be written are handed to
+/// `fallback`, which either names a value to use in their place when the image is read, or fails.
+pub(crate) fn write_image(
+    globals: &[SteelVal],
+    constants: &[SteelVal],
+    natives: &std::collections::HashMap<usize, NativeName>,
+    spans: &dyn Fn(usize) -> Option<Rc<[Span]>>,
+    fallback: &dyn Fn(usize, &SteelVal, SteelErr) -> Result<NativeName>,
+) -> Result<Image> {
+    let mut writer = GraphWriter {
+        image: Some(ImageWriter {
+            natives,
+            spans,
+            struct_types: Vec::new(),
+            struct_type_indices: std::collections::HashMap::new(),
+        }),
+        ..Default::default()
+    };
+
+    let mut global_indices = Vec::with_capacity(globals.len());
+
+    for (index, value) in globals.iter().enumerate() {
+        let checkpoint = writer.checkpoint();
+
+        let node = match writer.write(value) {
+            Ok(node) => node,
+            Err(e) => {
+                // Drop whatever was written before the failure, since the nodes that were
+                // reserved for it are incomplete
+                writer.rollback(checkpoint);
+                let name = fallback(index, value, e)?;
+                writer.push(SerializedNode::Native(name))
+            }
+        };
+
+        global_indices.push(node);
+    }
+
+    let constants = writer.write_all(constants.iter())?;
+    let image = writer.image.take().unwrap();
+
+    Ok(Image {
+        nodes: writer.nodes,
+        struct_types: image.struct_types,
+        globals: global_indices,
+        constants,
+    })
+}
+
+/// Reads an image written by [`write_image`]. Struct types are added to the vtable, and the
+/// captured variables of closures are allocated on the given heap.
+pub(crate) fn read_image(
+    image: &Image,
+    deserializers: &CustomDeserializers,
+    natives: &dyn Fn(&NativeName) -> Option<SteelVal>,
+    heap: &mut Heap,
+) -> Result<RestoredImage> {
+    let struct_types = image
+        .struct_types
+        .iter()
+        .map(|struct_type| {
+            let name: InternedString = struct_type.name.as_str().into();
+
+            if !struct_type.builtin {
+                return Ok(StructTypeDescriptor::new_entry(
+                    name,
+                    struct_type.field_count,
+                ));
+            }
+
+            let descriptor = lookup_struct_type_descriptor(name)
+                .ok_or_else(throw!(ConversionError => "snapshot: unknown struct type: {}", name))?;
+
+            check_struct_layout(descriptor, None, struct_type.field_count)?;
+
+            Ok(descriptor)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut reader = GraphReader::new(
+        &image.nodes,
+        deserializers,
+        Some(ImageReader {
+            natives,
+            heap,
+            struct_types: struct_types.clone(),
+            heap_refs: std::collections::HashMap::new(),
+            spans: Vec::new(),
+        }),
+    )?;
+
+    reader.fill_all()?;
+
+    let globals = reader.read_all(&image.globals)?;
+    let constants = reader.read_all(&image.constants)?;
+
+    for (struct_type, descriptor) in image.struct_types.iter().zip(struct_types) {
+        if let Some(properties) = struct_type.properties {
+            match reader.read(properties)? {
+                HashMapV(properties) => descriptor.set_properties(struct_type.proc, properties),
+                _ => {
+                    stop!(ConversionError => "snapshot: malformed input, struct properties are not a hash")
+                }
+            }
+        }
+    }
+
+    Ok(RestoredImage {
+        globals,
+        constants,
+        spans: reader.image.take().unwrap().spans,
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedGraph {
+    root: usize,
+    nodes: Vec<SerializedNode>,
+}
+
+/// Encode the value into the versioned binary format.
+pub fn to_bytes(value: &SteelVal) -> Result<Vec<u8>> {
+    let mut writer = GraphWriter::default();
+    let root = writer.write(value)?;
+
+    let graph = SerializedGraph {
+        root,
+        nodes: writer.nodes,
+    };
+
+    let mut output = Vec::from(&MAGIC[..]);
+    output.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+
+    bincode::serialize_into(&mut output, &graph)
+        .map_err(|e| SteelErr::new(ErrorKind::ConversionError, e.to_string()))?;
+
+    Ok(output)
+}
+
+/// Decode a value previously encoded with [`to_bytes`]. Custom types are reconstructed with
+/// the given deserializers.
+pub fn from_bytes(bytes: &[u8], deserializers: &CustomDeserializers) -> Result<SteelVal> {
+    if bytes.len() < MAGIC.len() + 2 || &bytes[..MAGIC.len()] != MAGIC {
+        stop!(ConversionError => "bytes->value: input is not a serialized steel value");
+    }
+
+    let version = u16::from_le_bytes([bytes[MAGIC.len()], bytes[MAGIC.len() + 1]]);
+
+    if version != FORMAT_VERSION {
+        stop!(ConversionError => "bytes->value: unsupported format version: {}, expected: {}", version, FORMAT_VERSION);
+    }
+
+    let graph: SerializedGraph = bincode::deserialize(&bytes[MAGIC.len() + 2..])
+        .map_err(|e| SteelErr::new(ErrorKind::ConversionError, e.to_string()))?;
+
+    GraphReader::new(&graph.nodes, deserializers, None)?.read_root(graph.root)
+}
+
+#[derive(Default)]
+struct GraphWriter<'a> {
+    nodes: Vec<SerializedNode>,
+    // Pointer address of a heap allocated value -> index into the node table
+    visited: std::collections::HashMap<usize, usize>,
+    // Lists don't expose the address of their nodes, so they are keyed by the address of their
+    // first element, and told apart with `ptr_eq`
+    lists: std::collections::HashMap<usize, Vec<(List<SteelVal>, usize)>>,
+    // Only set when writing an engine image
+    image: Option<ImageWriter<'a>>,
+}
+
+struct ImageWriter<'a> {
+    natives: &'a std::collections::HashMap<usize, NativeName>,
+    spans: &'a dyn Fn(usize) -> Option<Rc<[Span]>>,
+    struct_types: Vec<SerializedStructType>,
+    struct_type_indices: std::collections::HashMap<StructTypeDescriptor, usize>,
+}
+
+impl<'a> GraphWriter<'a> {
+    fn push(&mut self, node: SerializedNode) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    fn checkpoint(&self) -> (usize, usize) {
+        (
+            self.nodes.len(),
+            self.image
+                .as_ref()
+                .map(|x| x.struct_types.len())
+                .unwrap_or(0),
+        )
+    }
+
+    fn rollback(&mut self, (nodes, struct_types): (usize, usize)) {
+        self.nodes.truncate(nodes);
+        self.visited.retain(|_, index| *index < nodes);
+        self.lists.retain(|_, lists| {
+            lists.retain(|(_, index)| *index < nodes);
+            !lists.is_empty()
+        });
+
+        if let Some(image) = self.image.as_mut() {
+            image.struct_types.truncate(struct_types);
+            image
+                .struct_type_indices
+                .retain(|_, index| *index < struct_types);
+        }
+    }
+
+    fn write_struct_type(&mut self, descriptor: StructTypeDescriptor) -> Result<usize> {
+        let image = self.image.as_mut().unwrap();
+
+        if let Some(index) = image.struct_type_indices.get(&descriptor) {
+            return Ok(*index);
+        }
+
+        let builtin = descriptor.is_builtin();
+        let index = image.struct_types.len();
+
+        image.struct_types.push(SerializedStructType {
+            name: descriptor.name_and_fields().0.resolve().to_string(),
+            field_count: descriptor.field_count(),
+            builtin,
+            proc: descriptor.proc(),
+            properties: None,
+        });
+        image.struct_type_indices.insert(descriptor, index);
+
+        if !builtin {
+            let properties = self.write(&HashMapV(descriptor.properties()))?;
+            self.image.as_mut().unwrap().struct_types[index].properties = Some(properties);
+        }
+
+        Ok(index)
+    }
+
+    fn write_closure(&mut self, function: &ByteCodeLambda) -> Result<SerializedNode> {
+        if function.get_contract_information().is_some() {
+            stop!(ConversionError => "snapshot: unable to write a function with a contract attached");
+        }
+
+        let captures = self.write_all(function.captures().iter())?;
+
+        let heap_allocated = function
+            .heap_allocated()
+            .borrow()
+            .clone()
+            .into_iter()
+            .map(|cell| {
+                self.shared(cell.as_ptr() as usize, |w| {
+                    w.write(&cell.get()).map(SerializedNode::HeapCell)
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        let spans = (self.image.as_ref().unwrap().spans)(function.id).map(|x| x.to_vec());
+
+        Ok(SerializedNode::Closure {
+            id: function.id,
+            body: function.body_exp().to_vec(),
+            arity: function.arity(),
+            is_multi_arity: function.is_multi_arity,
+            captures,
+            heap_allocated,
+            spans,
+        })
+    }
+
+    fn write_native(&mut self, value: &SteelVal) -> Result<usize> {
+        if let BoxedFunction(f) = value {
+            if let Some((descriptor, kind)) = f.struct_function {
+                let struct_type = self.write_struct_type(descriptor)?;
+                return Ok(self.push(SerializedNode::StructFunction { struct_type, kind }));
+            }
+        }
+
+        let name = native_address(value)
+            .and_then(|x| self.image.as_ref().unwrap().natives.get(&x).cloned())
+            .ok_or_else(
+                throw!(ConversionError => "snapshot: unable to write native function: {}", value),
+            )?;
+
+        Ok(self.push(SerializedNode::Native(name)))
+    }
+
+    // Reserve the slot before visiting the children, so that a cycle back to this value
+    // resolves to the index of the slot.
+    fn shared(
+        &mut self,
+        address: usize,
+        thunk: impl FnOnce(&mut Self) -> Result<SerializedNode>,
+    ) -> Result<usize> {
+        if let Some(index) = self.visited.get(&address) {
+            return Ok(*index);
+        }
+
+        let index = self.push(SerializedNode::Void);
+        self.visited.insert(address, index);
+
+        self.nodes[index] = thunk(self)?;
+
+        Ok(index)
+    }
+
+    fn shared_list(&mut self, address: usize, list: &List<SteelVal>) -> Result<usize> {
+        let existing = self
+            .lists
+            .get(&address)
+            .and_then(|x| x.iter().find(|(other, _)| other.ptr_eq(list)));
+
+        if let Some((_, index)) = existing {
+            return Ok(*index);
+        }
+
+        let index = self.push(SerializedNode::Void);
+        self.lists
+            .entry(address)
+            .or_default()
+            .push((list.clone(), index));
+
+        self.nodes[index] = SerializedNode::List(self.write_all(list.iter())?);
+
+        Ok(index)
+    }
+
+    fn write_all<'v>(&mut self, values: impl Iterator<Item = &'v SteelVal>) -> Result<Vec<usize>> {
+        values.map(|x| self.write(x)).collect()
+    }
+
+    fn write(&mut self, value: &SteelVal) -> Result<usize> {
+        match value {
+            Closure(c) if self.image.is_some() => {
+                self.shared(c.as_ptr() as usize, |w| w.write_closure(c))
+            }
+            FuncV(_) | BuiltIn(_) | MutFunc(_) | BoxedFunction(_) if self.image.is_some() => {
+                self.write_native(value)
+            }
+            CustomStruct(s) if self.image.is_some() => self.shared(s.as_ptr() as usize, |w| {
+                let guard = s.borrow();
+
+                Ok(SerializedNode::TypedStruct {
+                    struct_type: w.write_struct_type(guard.type_descriptor)?,
+                    fields: w.write_all(guard.fields.iter())?,
+                })
+            }),
+            Custom(c) if self.image.is_some() && as_struct_type(c).is_some() => {
+                let struct_type = self.write_struct_type(as_struct_type(c).unwrap())?;
+                Ok(self.push(SerializedNode::StructType(struct_type)))
+            }
+            Void => Ok(self.push(SerializedNode::Void)),
+            BoolV(b) => Ok(self.push(SerializedNode::Bool(*b))),
+            IntV(i) => Ok(self.push(SerializedNode::Int(*i as i64))),
+            NumV(n) => Ok(self.push(SerializedNode::Num(*n))),
+            CharV(c) => Ok(self.push(SerializedNode::Char(*c))),
+            StringV(s) => Ok(self.push(SerializedNode::String(s.to_string()))),
+            SymbolV(s) => Ok(self.push(SerializedNode::Symbol(s.to_string()))),
+            BigNum(b) => self.shared(b.as_ptr() as usize, |_| {
+                Ok(SerializedNode::BigNum(b.to_signed_bytes_le()))
+            }),
+            ByteVector(b) => self.shared(b.as_ptr() as usize, |_| {
+                Ok(SerializedNode::ByteVector(b.borrow().clone()))
+            }),
+            ListV(l) => match l.first() {
+                Some(first) => self.shared_list(first as *const SteelVal as usize, l),
+                None => Ok(self.push(SerializedNode::List(Vec::new()))),
+            },
+            VectorV(v) => self.shared(v.as_ptr() as usize, |w| {
+                w.write_all(v.iter()).map(SerializedNode::Vector)
+            }),
+            MutableVector(v) => self.shared(v.as_ptr() as usize, |w| {
+                w.write_all(v.borrow().iter())
+                    .map(SerializedNode::MutableVector)
+            }),
+            HashMapV(h) => self.shared(h.as_ptr() as usize, |w| {
+                h.iter()
+                    .map(|(key, value)| Ok((w.write(key)?, w.write(value)?)))
+                    .collect::<Result<_>>()
+                    .map(SerializedNode::HashMap)
+            }),
+            HashSetV(h) => self.shared(h.as_ptr() as usize, |w| {
+                w.write_all(h.iter()).map(SerializedNode::HashSet)
+            }),
+            CustomStruct(s) => self.shared(s.as_ptr() as usize, |w| {
+                let guard = s.borrow();
+
+                Ok(SerializedNode::Struct {
+                    name: guard.name.resolve().to_string(),
+                    field_names: guard
+                        .field_names()
+                        .map(|names| names.iter().map(|x| x.to_string()).collect()),
+                    fields: w.write_all(guard.fields.iter())?,
+                })
+            }),
+            Boxed(b) => self.shared(b.as_ptr() as usize, |w| {
+                w.write(&b.borrow()).map(SerializedNode::Boxed)
+            }),
+            SyntaxObject(s) => self.shared(s.as_ptr() as usize, |w| {
+                let raw = s.raw.as_ref().map(|x| w.write(x)).transpose()?;
+
+                Ok(SerializedNode::Syntax {
+                    raw,
+                    syntax: w.write(&s.syntax)?,
+                    span: s.span,
+                })
+            }),
+            Custom(c) => self.shared(c.as_ptr() as usize, |_| {
+                let guard = c.borrow();
+
+                match guard.as_serialized_bytes() {
+                    Some(bytes) => Ok(SerializedNode::Custom {
+                        name: guard.name().to_string(),
+                        bytes,
+                    }),
+                    None => {
+                        stop!(ConversionError => "value->bytes: custom type does not support serialization: {}", guard.name())
+                    }
+                }
+            }),
+            _ => stop!(ConversionError => "value->bytes: unable to serialize value: {}", value),
+        }
+    }
+}
+
+fn as_struct_type(value: &Gc<RefCell<Box<dyn CustomType>>>) -> Option<StructTypeDescriptor> {
+    value
+        .borrow()
+        .as_any_ref()
+        .downcast_ref::<StructTypeDescriptor>()
+        .copied()
+}
+
+// The struct type has to have the same shape as the one that was written, otherwise the
+// accessors would read the wrong field, or past the end of the fields.
+fn check_struct_layout(
+    descriptor: StructTypeDescriptor,
+    field_names: Option<&[String]>,
+    field_count: usize,
+) -> Result<()> {
+    let (name, current_names) = descriptor.name_and_fields();
+
+    if descriptor.field_count() != field_count {
+        stop!(ConversionError => "bytes->value: struct {} was serialized with {} fields, but is now defined with {}", name, field_count, descriptor.field_count());
+    }
+
+    if let (Some(field_names), Some(current_names)) = (field_names, current_names) {
+        if field_names
+            .iter()
+            .zip(current_names.iter())
+            .any(|(old, new)| old.as_str() != new.as_str())
+        {
+            stop!(ConversionError => "bytes->value: struct {} was serialized with the fields {:?}, but is now defined with {:?}", name, field_names, current_names.iter().map(|x| x.as_str()).collect::<Vec<_>>());
+        }
+    }
+
+    Ok(())
+}
+
+fn image_only<T>(image: Option<T>) -> Result<T> {
+    image.ok_or_else(
+        throw!(ConversionError => "bytes->value: malformed input, found a node that only appears in engine images"),
+    )
+}
+
+struct GraphReader<'a> {
+    nodes: &'a [SerializedNode],
+    deserializers: &'a CustomDeserializers,
+    values: Vec<Option<SteelVal>>,
+    in_progress: Vec<bool>,
+    // Mutable values are allocated up front and filled in at the end, which is what
+    // allows cycles between them to be reconstructed.
+    mutable: Vec<usize>,
+    // Only set when reading an engine image
+    image: Option<ImageReader<'a>>,
+}
+
+struct ImageReader<'a> {
+    natives: &'a dyn Fn(&NativeName) -> Option<SteelVal>,
+    heap: &'a mut Heap,
+    struct_types: Vec<StructTypeDescriptor>,
+    heap_refs: std::collections::HashMap<usize, HeapRef>,
+    spans: Vec<(usize, Rc<[Span]>)>,
+}
+
+impl<'a> ImageReader<'a> {
+    fn struct_type(&self, index: usize) -> Result<StructTypeDescriptor> {
+        self.struct_types.get(index).copied().ok_or_else(
+            throw!(ConversionError => "snapshot: malformed input, struct type index out of bounds: {}", index),
+        )
+    }
+}
+
+impl<'a> GraphReader<'a> {
+    fn new(
+        nodes: &'a [SerializedNode],
+        deserializers: &'a CustomDeserializers,
+        mut image: Option<ImageReader<'a>>,
+    ) -> Result<Self> {
+        let mut values = vec![None; nodes.len()];
+        let mut mutable = Vec::new();
+
+        for (index, node) in nodes.iter().enumerate() {
+            let shell = match node {
+                SerializedNode::MutableVector(_) => {
+                    SteelVal::MutableVector(Gc::new(RefCell::new(Vec::new())))
+                }
+                SerializedNode::Boxed(_) => SteelVal::Boxed(Gc::new(RefCell::new(Void))),
+                SerializedNode::Struct {
+                    name,
+                    field_names,
+                    fields,
+                } => {
+                    let name: InternedString = name.as_str().into();
+                    let descriptor = lookup_struct_type_descriptor(name).ok_or_else(
+                        throw!(ConversionError => "bytes->value: unknown struct type: {}", name),
+                    )?;
+
+                    check_struct_layout(descriptor, field_names.as_deref(), fields.len())?;
+
+                    SteelVal::CustomStruct(Gc::new(RefCell::new(UserDefinedStruct::new(
+                        name,
+                        descriptor,
+                        &vec![Void; fields.len()],
+                    ))))
+                }
+                SerializedNode::TypedStruct {
+                    struct_type,
+                    fields,
+                } => {
+                    let descriptor = image_only(image.as_ref())?.struct_type(*struct_type)?;
+
+                    if descriptor.field_count() != fields.len() {
+                        stop!(ConversionError => "snapshot: malformed input, struct field count mismatch");
+                    }
+
+                    SteelVal::CustomStruct(Gc::new(RefCell::new(UserDefinedStruct::new(
+                        descriptor.name_and_fields().0,
+                        descriptor,
+                        &vec![Void; fields.len()],
+                    ))))
+                }
+                // Captured variables aren't values of their own, they're only reachable
+                // through the closures that captured them
+                SerializedNode::HeapCell(_) => {
+                    let image = image_only(image.as_mut())?;
+                    let cell = image.heap.allocate_unrooted(Void);
+                    image.heap_refs.insert(index, cell);
+
+                    mutable.push(index);
+                    continue;
+                }
+                _ => continue,
+            };
+
+            values[index] = Some(shell);
+            mutable.push(index);
+        }
+
+        Ok(Self {
+            nodes,
+            deserializers,
+            values,
+            in_progress: vec![false; nodes.len()],
+            mutable,
+            image,
+        })
+    }
+
+    fn fill_all(&mut self) -> Result<()> {
+        // Fill in from the back - children are written after their parents, so this way
+        // values placed into hash maps are complete before they are hashed.
+        for index in std::mem::take(&mut self.mutable).into_iter().rev() {
+            self.fill(index)?;
+        }
+
+        Ok(())
+    }
+
+    fn read_root(mut self, root: usize) -> Result<SteelVal> {
+        self.fill_all()?;
+        self.read(root)
+    }
+
+    fn node(&self, index: usize) -> Result<&'a SerializedNode> {
+        self.nodes.get(index).ok_or_else(
+            throw!(ConversionError => "bytes->value: malformed input, node index out of bounds: {}", index),
+        )
+    }
+
+    fn read_all(&mut self, indices: &[usize]) -> Result<Vec<SteelVal>> {
+        indices.iter().map(|x| self.read(*x)).collect()
+    }
+
+    fn heap_ref(&mut self, index: usize) -> Result<HeapRef> {
+        image_only(self.image.as_ref())?
+            .heap_refs
+            .get(&index)
+            .cloned()
+            .ok_or_else(
+                throw!(ConversionError => "snapshot: malformed input, expected a captured variable at: {}", index),
+            )
+    }
+
+    fn fill(&mut self, index: usize) -> Result<()> {
+        if let SerializedNode::HeapCell(inner) = self.node(index)? {
+            let value = self.read(*inner)?;
+            self.heap_ref(index)?.set_interior_mut(value);
+            return Ok(());
+        }
+
+        let shell = self.values[index].clone().unwrap();
+
+        match (self.node(index)?, shell) {
+            (SerializedNode::MutableVector(items), MutableVector(v)) => {
+                *v.borrow_mut() = self.read_all(items)?;
+            }
+            (SerializedNode::Boxed(inner), Boxed(b)) => {
+                *b.borrow_mut() = self.read(*inner)?;
+            }
+            (SerializedNode::Struct { fields, .. }, CustomStruct(s))
+            | (SerializedNode::TypedStruct { fields, .. }, CustomStruct(s)) => {
+                let fields = self.read_all(fields)?;
+                s.borrow_mut().fields = fields.into_iter().collect();
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    fn read(&mut self, index: usize) -> Result<SteelVal> {
+        if let Some(value) = self.values.get(index).cloned().flatten() {
+            return Ok(value);
+        }
+
+        if self.in_progress.get(index).copied().unwrap_or_default() {
+            stop!(ConversionError => "bytes->value: malformed input, cycle through an immutable value");
+        }
+
+        let node = self.node(index)?;
+        self.in_progress[index] = true;
+
+        let value = match node {
+            SerializedNode::Void => Void,
+            SerializedNode::Bool(b) => BoolV(*b),
+            SerializedNode::Int(i) => IntV(*i as isize),
+            SerializedNode::Num(n) => NumV(*n),
+            SerializedNode::Char(c) => CharV(*c),
+            SerializedNode::BigNum(b) => BigNum(Gc::new(num::BigInt::from_signed_bytes_le(b))),
+            SerializedNode::String(s) => StringV(s.as_str().into()),
+            SerializedNode::Symbol(s) => SymbolV(s.as_str().into()),
+            SerializedNode::ByteVector(b) => bytes_to_steelval(b.clone()),
+            SerializedNode::List(items) => ListV(self.read_all(items)?.into()),
+            SerializedNode::Vector(items) => {
+                VectorV(Gc::new(self.read_all(items)?.into_iter().collect()))
+            }
+            SerializedNode::HashMap(pairs) => HashMapV(Gc::new(
+                pairs
+                    .iter()
+                    .map(|(key, value)| Ok((self.read(*key)?, self.read(*value)?)))
+                    .collect::<Result<_>>()?,
+            )),
+            SerializedNode::HashSet(items) => {
+                HashSetV(Gc::new(self.read_all(items)?.into_iter().collect()))
+            }
+            SerializedNode::Syntax { raw, syntax, span } => {
+                let raw = raw.map(|x| self.read(x)).transpose()?;
+                let syntax = self.read(*syntax)?;
+
+                SyntaxObject(Gc::new(Syntax {
+                    raw,
+                    syntax,
+                    span: *span,
+                }))
+            }
+            SerializedNode::Custom { name, bytes } => {
+                let deserializer = self.deserializers.get(name).ok_or_else(
+                    throw!(ConversionError => "bytes->value: no deserializer registered for custom type: {}", name),
+                )?;
+
+                deserializer(bytes)?
+            }
+            SerializedNode::Closure {
+                id,
+                body,
+                arity,
+                is_multi_arity,
+                captures,
+                heap_allocated,
+                spans,
+            } => {
+                image_only(self.image.as_ref())?;
+
+                let captures = self.read_all(captures)?;
+                let heap_allocated = heap_allocated
+                    .iter()
+                    .map(|x| self.heap_ref(*x))
+                    .collect::<Result<Vec<_>>>()?;
+
+                if let Some(spans) = spans {
+                    let image = self.image.as_mut().unwrap();
+                    image.spans.push((*id, spans.as_slice().into()));
+                }
+
+                Closure(Gc::new(ByteCodeLambda::new(
+                    *id,
+                    body.as_slice().into(),
+                    *arity,
+                    *is_multi_arity,
+                    captures,
+                    heap_allocated,
+                )))
+            }
+            SerializedNode::Native(name) => {
+                let natives = image_only(self.image.as_ref())?.natives;
+
+                natives(name).ok_or_else(
+                    throw!(Generic => "snapshot references {}, which is not defined in this engine", name),
+                )?
+            }
+            SerializedNode::StructType(index) => image_only(self.image.as_ref())?
+                .struct_type(*index)?
+                .into_steelval()?,
+            SerializedNode::StructFunction { struct_type, kind } => {
+                let descriptor = image_only(self.image.as_ref())?.struct_type(*struct_type)?;
+                struct_function(descriptor, *kind)
+            }
+            // These have already been allocated up front
+            SerializedNode::MutableVector(_)
+            | SerializedNode::Boxed(_)
+            | SerializedNode::Struct { .. }
+            | SerializedNode::TypedStruct { .. } => unreachable!(),
+            SerializedNode::HeapCell(_) => {
+                stop!(ConversionError => "snapshot: malformed input, a captured variable is not a value")
+            }
+        };
+
+        self.values[index] = Some(value.clone());
+
+        Ok(value)
+    }
+}
+
+/// Serializes the value into a bytevector, using a stable, versioned binary format.
+/// Sharing and cycles between values are preserved. Functions, ports and other runtime
+/// values cannot be serialized.
+///
+/// (value->bytes any/c) -> bytes?
+///
+/// # Examples
+///
+/// ```scheme
+/// > (bytes->value (value->bytes (list 1 2 3))) ;; => '(1 2 3)
+/// ```
+#[function(name = "value->bytes")]
+pub fn value_to_bytes(value: &SteelVal) -> Result<SteelVal> {
+    to_bytes(value).map(bytes_to_steelval)
+}
+
+const BYTES_TO_VALUE_DOC: DocTemplate<'static> = DocTemplate {
+    signature: "(bytes->value bytes?) -> any/c",
+    params: &["bytes : bytes?"],
+    description: r#"Reconstructs a value from a bytevector produced by `value->bytes`. Structs are
+reconstructed by the name of the struct type, which must be defined with the same fields.
+Custom types use the deserializers registered with the engine."#,
+    examples: &[],
+};
+
+// Needs the engine, for its custom deserializers
+fn bytes_to_value(ctx: &mut VmCore, args: &[SteelVal]) -> Option<Result<SteelVal>> {
+    Some(bytes_to_value_result(ctx, args))
+}
+
+fn bytes_to_value_result(ctx: &mut VmCore, args: &[SteelVal]) -> Result<SteelVal> {
+    match args {
+        [ByteVector(bytes)] => from_bytes(&bytes.borrow(), &ctx.thread.custom_deserializers),
+        [other] => stop!(TypeMismatch => "bytes->value expects a bytevector, found: {}", other),
+        _ => stop!(ArityMismatch => "bytes->value expects one argument, found: {}", args.len()),
+    }
+}
+
+pub fn serialization_module() -> BuiltInModule {
+    let mut module = BuiltInModule::new("steel/serialize");
+    module
+        .register_native_fn_definition(VALUE_TO_BYTES_DEFINITION)
+        .register_value_with_doc(
+            "bytes->value",
+            SteelVal::BuiltIn(bytes_to_value),
+            BYTES_TO_VALUE_DOC,
+        );
+    module
+}
+
+#[cfg(test)]
+mod serialize_tests {
+    use super::*;
+    use crate::steel_vm::engine::Engine;
+
+    fn round_trip(value: &SteelVal) -> SteelVal {
+        from_bytes(&to_bytes(value).unwrap(), &CustomDeserializers::new()).unwrap()
+    }
+
+    #[test]
+    fn round_trip_primitives() {
+        let values = [
+            SteelVal::Void,
+            SteelVal::BoolV(true),
+            SteelVal::IntV(-42),
+            SteelVal::CharV('λ'),
+            SteelVal::StringV("hello".into()),
+            SteelVal::SymbolV("world".into()),
+            SteelVal::BigNum(Gc::new(num::BigInt::from(isize::MAX) * 1000)),
+        ];
+
+        for value in values {
+            assert_eq!(round_trip(&value), value);
+        }
+
+        assert!(matches!(round_trip(&SteelVal::NumV(1.5)), SteelVal::NumV(n) if n == 1.5));
+    }
+
+    #[test]
+    fn round_trip_collections() {
+        let value = crate::list![
+            SteelVal::VectorV(Gc::new(im_rc::vector![SteelVal::IntV(1)])),
+            SteelVal::HashMapV(Gc::new(im_rc::hashmap! {
+                SteelVal::CharV('a') => SteelVal::IntV(1)
+            })),
+            SteelVal::HashSetV(Gc::new(im_rc::hashset! { SteelVal::IntV(10) }))
+        ];
+
+        assert_eq!(round_trip(&value), value);
+    }
+
+    #[test]
+    fn sharing_is_preserved() {
+        let shared = SteelVal::MutableVector(Gc::new(RefCell::new(vec![SteelVal::IntV(1)])));
+        let value = crate::list![shared.clone(), shared];
+
+        if let SteelVal::ListV(l) = round_trip(&value) {
+            assert!(l[0].ptr_eq(&l[1]));
+        } else {
+            panic!("Expected a list");
+        }
+    }
+
+    #[test]
+    fn sharing_of_lists_is_preserved() {
+        let shared = crate::list![SteelVal::IntV(1), SteelVal::IntV(2)];
+        let value = SteelVal::VectorV(Gc::new(im_rc::vector![
+            shared.clone(),
+            shared,
+            crate::list![SteelVal::IntV(1), SteelVal::IntV(2)]
+        ]));
+
+        assert_eq!(round_trip(&value), value);
+
+        if let SteelVal::VectorV(v) = round_trip(&value) {
+            assert!(v[0].ptr_eq(&v[1]));
+            assert!(!v[0].ptr_eq(&v[2]));
+        } else {
+            panic!("Expected a vector");
+        }
+    }
+
+    #[test]
+    fn cycles_through_boxes() {
+        let boxed = Gc::new(RefCell::new(SteelVal::Void));
+        *boxed.borrow_mut() = crate::list![SteelVal::Boxed(boxed.clone())];
+
+        if let SteelVal::Boxed(b) = round_trip(&SteelVal::Boxed(boxed.clone())) {
+            let inner = b.borrow().clone();
+            if let SteelVal::ListV(l) = inner {
+                assert!(matches!(&l[0], SteelVal::Boxed(inner) if Gc::ptr_eq(inner, &b)));
+            } else {
+                panic!("Expected a list");
+            }
+        } else {
+            panic!("Expected a box");
+        }
+
+        // Break the cycle so the test does not leak
+        *boxed.borrow_mut() = SteelVal::Void;
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let mut bytes = to_bytes(&SteelVal::IntV(1)).unwrap();
+        bytes[MAGIC.len()] = 0xff;
+
+        assert!(from_bytes(&bytes, &CustomDeserializers::new()).is_err());
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Celsius(f64);
+
+    impl Custom for Celsius {
+        fn to_serialized_bytes(&self) -> Option<Vec<u8>> {
+            Some(self.0.to_le_bytes().to_vec())
+        }
+    }
+
+    #[test]
+    fn custom_values_use_the_engine_deserializers() {
+        let mut engine = Engine::new();
+
+        engine.register_custom_deserializer::<Celsius>(|bytes| {
+            let bytes: [u8; 8] = bytes.try_into().map_err(|_| {
+                SteelErr::new(ErrorKind::ConversionError, "expected 8 bytes".to_string())
+            })?;
+
+            Ok(Celsius(f64::from_le_bytes(bytes)))
+        });
+
+        let value = Celsius(21.5).into_steelval().unwrap();
+        let bytes = engine.serialize_value(&value).unwrap();
+
+        let result = engine.deserialize_value(&bytes).unwrap();
+        assert_eq!(Celsius::from_steelval(&result).unwrap(), Celsius(21.5));
+
+        engine.register_value("reading", value);
+        let result = engine
+            .run("(bytes->value (value->bytes reading))")
+            .unwrap()
+            .pop()
+            .unwrap();
+        assert_eq!(Celsius::from_steelval(&result).unwrap(), Celsius(21.5));
+
+        // The deserializers belong to the engine they were registered with
+        assert!(Engine::new().deserialize_value(&bytes).is_err());
+    }
+
+    #[test]
+    fn structs_must_have_the_same_fields() {
+        let mut engine = Engine::new();
+        engine.run("(struct Point (x y))").unwrap();
+
+        let point = engine.run("(Point 1 2)").unwrap().pop().unwrap();
+        let bytes = engine.serialize_value(&point).unwrap();
+
+        assert!(engine.deserialize_value(&bytes).is_ok());
+
+        let mut more_fields = Engine::new();
+        more_fields.run("(struct Point (x y z))").unwrap();
+
+        let error = more_fields.deserialize_value(&bytes).unwrap_err();
+        assert!(error.to_string().contains("is now defined with 3"));
+
+        let mut renamed = Engine::new();
+        renamed.run("(struct Point (y x))").unwrap();
+
+        assert!(renamed.deserialize_value(&bytes).is_err());
+    }
+
+    #[test]
+    fn functions_are_not_serializable() {
+        assert!(to_bytes(&SteelVal::FuncV(|_| Ok(SteelVal::Void))).is_err());
+    }
+}
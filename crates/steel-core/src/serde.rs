@@ -0,0 +1,1361 @@
+//! A bridge between Steel values and any Rust type implementing `serde`'s `Serialize` or
+//! `Deserialize`, without going through `serde_json::Value`.
+//!
+//! The mapping is:
+//!
+//! * Integers become `int`s, or big integers if they don't fit, and floats become `number`s.
+//! * Strings, chars and bools map to their Steel counterparts, and byte buffers to bytevectors.
+//! * `None` becomes `#f` and `Some(x)` becomes `x`, the same as [`IntoSteelVal`](crate::rvals::IntoSteelVal)
+//!   for `Option`. `()` and unit structs become void.
+//! * Sequences and tuples become lists. Lists, vectors and mutable vectors can all be read back.
+//! * Maps become hashmaps.
+//! * Structs become a hashmap from field name symbols to values, unless they have been tied to a
+//!   Steel struct with [`register_struct`], in which case they become an instance of that struct.
+//!   Steel structs declared with `struct` can be read into any Rust struct by their field names.
+//! * Unit variants become a symbol with the variant name. The variants of `Result` and `Option`
+//!   become the builtin structs - so `Ok(x)` maps to `(Ok x)` - and other variants become a
+//!   hashmap with a single entry from the variant name to its contents.
+//!
+//! Errors include the path to the offending value, for example `.servers[2].port`.
+//!
+//! # Examples
+//!
+//! ```
+//! # extern crate steel;
+//! # use steel::serde::{from_steelval, to_steelval};
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize, PartialEq, Debug)]
+//! struct Server {
+//!     host: String,
+//!     port: u16,
+//! }
+//!
+//! let server = Server { host: "localhost".to_string(), port: 8080 };
+//! let value = to_steelval(&server).unwrap();
+//!
+//! assert_eq!(from_steelval::<Server>(&value).unwrap(), server);
+//! ```
+
+use std::{cell::RefCell, collections::HashMap, fmt};
+
+use im_lists::list::List;
+use num::{BigInt, ToPrimitive};
+use serde::{
+    de::{
+        self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess,
+        SeqAccess, VariantAccess, Visitor,
+    },
+    forward_to_deserialize_any, ser, Serialize,
+};
+
+use crate::{
+    gc::Gc,
+    parser::interner::InternedString,
+    rerrs::{ErrorKind, SteelErr},
+    rvals::{Result, SteelString, SteelVal},
+    values::structs::{lookup_struct_type_descriptor, StructTypeDescriptor, UserDefinedStruct},
+};
+
+/// Converts any serializable value into a [`SteelVal`].
+pub fn to_steelval<T: Serialize + ?Sized>(value: &T) -> Result<SteelVal> {
+    value.serialize(Serializer).map_err(SteelErr::from)
+}
+
+/// Converts a [`SteelVal`] into any deserializable type.
+pub fn from_steelval<T: DeserializeOwned>(value: &SteelVal) -> Result<T> {
+    T::deserialize(Deserializer::new(value.clone())).map_err(SteelErr::from)
+}
+
+thread_local! {
+    // Rust structs that convert to Steel structs, keyed by their serde name
+    static REGISTERED_STRUCTS: RefCell<HashMap<&'static str, RegisteredStruct>> =
+        RefCell::new(HashMap::new());
+}
+
+#[derive(Clone, Debug)]
+struct RegisteredStruct {
+    rust_name: &'static str,
+    steel_name: InternedString,
+    fields: &'static [&'static str],
+}
+
+/// Converts the Rust struct `T` to an instance of the Steel struct called `steel_name`, rather
+/// than a hashmap, and reads it back from one.
+///
+/// The Steel struct must have the same fields as `T`, in the same order. This is checked on
+/// every conversion, since the Steel struct can be defined after the registration or redefined
+/// later, and a conversion fails if the two don't match.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate steel;
+/// # use steel::steel_vm::engine::Engine;
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct Point {
+///     x: isize,
+///     y: isize,
+/// }
+///
+/// let mut vm = Engine::new();
+/// vm.run("(struct Point (x y))").unwrap();
+///
+/// steel::serde::register_struct::<Point>("Point").unwrap();
+///
+/// vm.register_serde_value("origin", &Point { x: 0, y: 0 }).unwrap();
+/// vm.run("(Point-x origin)").unwrap();
+/// ```
+pub fn register_struct<T: DeserializeOwned>(steel_name: &str) -> Result<()> {
+    let mut shape = StructShape::default();
+    let _ = T::deserialize(&mut shape);
+
+    let (rust_name, fields) = match shape.shape {
+        Some(shape) => shape,
+        None => {
+            stop!(TypeMismatch => "register_struct: {} is not a struct with named fields",
+                std::any::type_name::<T>())
+        }
+    };
+
+    REGISTERED_STRUCTS.with(|x| {
+        x.borrow_mut().insert(
+            rust_name,
+            RegisteredStruct {
+                rust_name,
+                steel_name: steel_name.into(),
+                fields,
+            },
+        )
+    });
+
+    Ok(())
+}
+
+fn registered(rust_name: &str) -> Option<RegisteredStruct> {
+    REGISTERED_STRUCTS.with(|x| x.borrow().get(rust_name).cloned())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum PathSegment {
+    Field(String),
+    Index(usize),
+}
+
+/// An error from converting to or from a [`SteelVal`], along with where in the value it
+/// happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    message: String,
+    // Outermost first
+    path: Vec<PathSegment>,
+}
+
+impl Error {
+    fn new(message: impl Into<String>) -> Self {
+        Error {
+            message: message.into(),
+            path: Vec::new(),
+        }
+    }
+
+    // Errors are built from the inside out, so each level adds itself to the front
+    fn within(mut self, segment: PathSegment) -> Self {
+        self.path.insert(0, segment);
+        self
+    }
+
+    /// The path to the value that caused the error, like `.servers[2].port`. Empty if the
+    /// error is about the top level value.
+    pub fn path(&self) -> String {
+        self.path
+            .iter()
+            .map(|segment| match segment {
+                PathSegment::Field(name) => format!(".{name}"),
+                PathSegment::Index(index) => format!("[{index}]"),
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{} at {}", self.message, self.path())
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::new(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error::new(msg.to_string())
+    }
+}
+
+impl From<Error> for SteelErr {
+    fn from(e: Error) -> Self {
+        SteelErr::new(ErrorKind::ConversionError, e.to_string())
+    }
+}
+
+type SerdeResult<T> = std::result::Result<T, Error>;
+
+impl RegisteredStruct {
+    // Fails unless a Steel struct with these fields lines up with the Rust struct
+    fn check_fields(&self, field_count: usize, names: Option<Vec<SteelString>>) -> SerdeResult<()> {
+        if field_count != self.fields.len() {
+            return Err(Error::new(format!(
+                "struct {} has {} fields, but {} has {}",
+                self.steel_name,
+                field_count,
+                self.rust_name,
+                self.fields.len()
+            )));
+        }
+
+        match names {
+            Some(names)
+                if !names
+                    .iter()
+                    .map(|x| x.as_str())
+                    .eq(self.fields.iter().copied()) =>
+            {
+                Err(Error::new(format!(
+                    "struct {} has the fields ({}), but {} has ({})",
+                    self.steel_name,
+                    names
+                        .iter()
+                        .map(|x| x.as_str())
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    self.rust_name,
+                    self.fields.join(" ")
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn instance(&self, fields: Vec<(&'static str, SteelVal)>) -> SerdeResult<SteelVal> {
+        let descriptor = lookup_struct_type_descriptor(self.steel_name).ok_or_else(|| {
+            Error::new(format!(
+                "{} is registered as struct {}, but it hasn't been defined",
+                self.rust_name, self.steel_name
+            ))
+        })?;
+
+        let (_, names) = descriptor.name_and_fields();
+        self.check_fields(descriptor.field_count(), names)?;
+
+        // Fields left out with `skip_serializing_if` would shift the rest into the wrong place
+        if !fields.iter().map(|x| x.0).eq(self.fields.iter().copied()) {
+            return Err(Error::new(format!(
+                "{} serialized the fields ({}), but struct {} needs ({})",
+                self.rust_name,
+                fields.iter().map(|x| x.0).collect::<Vec<_>>().join(" "),
+                self.steel_name,
+                self.fields.join(" ")
+            )));
+        }
+
+        let values = fields.into_iter().map(|x| x.1).collect::<Vec<_>>();
+
+        Ok(SteelVal::CustomStruct(Gc::new(RefCell::new(
+            UserDefinedStruct::new(self.steel_name, descriptor, &values),
+        ))))
+    }
+}
+
+// Finds the serde name and fields of a struct, by asking it to deserialize itself
+#[derive(Default)]
+struct StructShape {
+    shape: Option<(&'static str, &'static [&'static str])>,
+}
+
+impl<'de, 'a> de::Deserializer<'de> for &'a mut StructShape {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> SerdeResult<V::Value> {
+        Err(Error::new("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> SerdeResult<V::Value> {
+        self.shape = Some((name, fields));
+        Err(Error::new("found the struct"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+        ignored_any
+    }
+}
+
+// The variants of `Result` and `Option` become the builtin structs, every other variant is
+// tagged with its name
+fn tagged(variant: &'static str, fields: Vec<SteelVal>, untagged: SteelVal) -> SteelVal {
+    let name: InternedString = variant.into();
+
+    match lookup_struct_type_descriptor(name) {
+        Some(descriptor) if is_builtin_variant(descriptor, fields.len()) => {
+            SteelVal::CustomStruct(Gc::new(RefCell::new(UserDefinedStruct::new(
+                name, descriptor, &fields,
+            ))))
+        }
+        _ => SteelVal::HashMapV(Gc::new(im_rc::hashmap! {
+            SteelVal::SymbolV(variant.into()) => untagged
+        })),
+    }
+}
+
+fn is_builtin_variant(descriptor: StructTypeDescriptor, field_count: usize) -> bool {
+    descriptor.is_builtin() && descriptor.field_count() == field_count
+}
+
+fn integer(value: i128) -> SteelVal {
+    match isize::try_from(value) {
+        Ok(value) => SteelVal::IntV(value),
+        Err(_) => SteelVal::BigNum(Gc::new(BigInt::from(value))),
+    }
+}
+
+/// A `serde` serializer that produces [`SteelVal`]s.
+pub struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = SteelVal;
+    type Error = Error;
+
+    type SerializeSeq = SerializeList;
+    type SerializeTuple = SerializeList;
+    type SerializeTupleStruct = SerializeList;
+    type SerializeTupleVariant = SerializeList;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeStruct;
+    type SerializeStructVariant = SerializeStruct;
+
+    fn serialize_bool(self, v: bool) -> SerdeResult<SteelVal> {
+        Ok(SteelVal::BoolV(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> SerdeResult<SteelVal> {
+        Ok(integer(v as i128))
+    }
+
+    fn serialize_i16(self, v: i16) -> SerdeResult<SteelVal> {
+        Ok(integer(v as i128))
+    }
+
+    fn serialize_i32(self, v: i32) -> SerdeResult<SteelVal> {
+        Ok(integer(v as i128))
+    }
+
+    fn serialize_i64(self, v: i64) -> SerdeResult<SteelVal> {
+        Ok(integer(v as i128))
+    }
+
+    fn serialize_i128(self, v: i128) -> SerdeResult<SteelVal> {
+        Ok(integer(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> SerdeResult<SteelVal> {
+        Ok(integer(v as i128))
+    }
+
+    fn serialize_u16(self, v: u16) -> SerdeResult<SteelVal> {
+        Ok(integer(v as i128))
+    }
+
+    fn serialize_u32(self, v: u32) -> SerdeResult<SteelVal> {
+        Ok(integer(v as i128))
+    }
+
+    fn serialize_u64(self, v: u64) -> SerdeResult<SteelVal> {
+        Ok(integer(v as i128))
+    }
+
+    fn serialize_u128(self, v: u128) -> SerdeResult<SteelVal> {
+        match i128::try_from(v) {
+            Ok(v) => Ok(integer(v)),
+            Err(_) => Ok(SteelVal::BigNum(Gc::new(BigInt::from(v)))),
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> SerdeResult<SteelVal> {
+        Ok(SteelVal::NumV(v as f64))
+    }
+
+    fn serialize_f64(self, v: f64) -> SerdeResult<SteelVal> {
+        Ok(SteelVal::NumV(v))
+    }
+
+    fn serialize_char(self, v: char) -> SerdeResult<SteelVal> {
+        Ok(SteelVal::CharV(v))
+    }
+
+    fn serialize_str(self, v: &str) -> SerdeResult<SteelVal> {
+        Ok(SteelVal::StringV(v.into()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> SerdeResult<SteelVal> {
+        Ok(SteelVal::ByteVector(Gc::new(RefCell::new(v.to_vec()))))
+    }
+
+    fn serialize_none(self) -> SerdeResult<SteelVal> {
+        Ok(SteelVal::BoolV(false))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> SerdeResult<SteelVal> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> SerdeResult<SteelVal> {
+        Ok(SteelVal::Void)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> SerdeResult<SteelVal> {
+        Ok(SteelVal::Void)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> SerdeResult<SteelVal> {
+        Ok(SteelVal::SymbolV(variant.into()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> SerdeResult<SteelVal> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> SerdeResult<SteelVal> {
+        let value = value
+            .serialize(Serializer)
+            .map_err(|e| e.within(PathSegment::Field(variant.to_string())))?;
+
+        Ok(tagged(variant, vec![value.clone()], value))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> SerdeResult<SerializeList> {
+        Ok(SerializeList {
+            items: Vec::with_capacity(len.unwrap_or_default()),
+            variant: None,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> SerdeResult<SerializeList> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> SerdeResult<SerializeList> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> SerdeResult<SerializeList> {
+        Ok(SerializeList {
+            items: Vec::with_capacity(len),
+            variant: Some(variant),
+        })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> SerdeResult<SerializeMap> {
+        Ok(SerializeMap {
+            map: im_rc::HashMap::new(),
+            next_key: None,
+        })
+    }
+
+    fn serialize_struct(self, name: &'static str, len: usize) -> SerdeResult<SerializeStruct> {
+        Ok(SerializeStruct {
+            name,
+            fields: Vec::with_capacity(len),
+            variant: false,
+        })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> SerdeResult<SerializeStruct> {
+        Ok(SerializeStruct {
+            name: variant,
+            fields: Vec::with_capacity(len),
+            variant: true,
+        })
+    }
+}
+
+#[doc(hidden)]
+pub struct SerializeList {
+    items: Vec<SteelVal>,
+    // Set for tuple variants
+    variant: Option<&'static str>,
+}
+
+impl SerializeList {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> SerdeResult<()> {
+        let index = self.items.len();
+
+        let value = value.serialize(Serializer).map_err(|e| {
+            let e = e.within(PathSegment::Index(index));
+
+            match self.variant {
+                Some(variant) => e.within(PathSegment::Field(variant.to_string())),
+                None => e,
+            }
+        })?;
+
+        self.items.push(value);
+        Ok(())
+    }
+
+    fn finish(self) -> SerdeResult<SteelVal> {
+        let list = SteelVal::ListV(self.items.iter().cloned().collect::<List<_>>());
+
+        match self.variant {
+            Some(variant) => Ok(tagged(variant, self.items, list)),
+            None => Ok(list),
+        }
+    }
+}
+
+impl ser::SerializeSeq for SerializeList {
+    type Ok = SteelVal;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> SerdeResult<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> SerdeResult<SteelVal> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SerializeList {
+    type Ok = SteelVal;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> SerdeResult<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> SerdeResult<SteelVal> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeList {
+    type Ok = SteelVal;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> SerdeResult<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> SerdeResult<SteelVal> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeList {
+    type Ok = SteelVal;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> SerdeResult<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> SerdeResult<SteelVal> {
+        self.finish()
+    }
+}
+
+#[doc(hidden)]
+pub struct SerializeMap {
+    map: im_rc::HashMap<SteelVal, SteelVal>,
+    next_key: Option<SteelVal>,
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = SteelVal;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> SerdeResult<()> {
+        self.next_key = Some(key.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> SerdeResult<()> {
+        let key = self
+            .next_key
+            .take()
+            .ok_or_else(|| Error::new("serialize_value called before serialize_key"))?;
+
+        let value = value
+            .serialize(Serializer)
+            .map_err(|e| e.within(PathSegment::Field(key_name(&key))))?;
+
+        self.map.insert(key, value);
+        Ok(())
+    }
+
+    fn end(self) -> SerdeResult<SteelVal> {
+        Ok(SteelVal::HashMapV(Gc::new(self.map)))
+    }
+}
+
+#[doc(hidden)]
+pub struct SerializeStruct {
+    name: &'static str,
+    fields: Vec<(&'static str, SteelVal)>,
+    // Struct variants are named after the variant, and are tagged if there is no Steel struct
+    variant: bool,
+}
+
+impl SerializeStruct {
+    fn push<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> SerdeResult<()> {
+        let value = value.serialize(Serializer).map_err(|e| {
+            let e = e.within(PathSegment::Field(key.to_string()));
+
+            if self.variant {
+                e.within(PathSegment::Field(self.name.to_string()))
+            } else {
+                e
+            }
+        })?;
+
+        self.fields.push((key, value));
+        Ok(())
+    }
+
+    fn finish(self) -> SerdeResult<SteelVal> {
+        if !self.variant {
+            if let Some(registered) = registered(self.name) {
+                return registered.instance(self.fields);
+            }
+        }
+
+        let values = self.fields.iter().map(|x| x.1.clone()).collect::<Vec<_>>();
+
+        let map = SteelVal::HashMapV(Gc::new(
+            self.fields
+                .into_iter()
+                .map(|(key, value)| (SteelVal::SymbolV(key.into()), value))
+                .collect(),
+        ));
+
+        if self.variant {
+            Ok(tagged(self.name, values, map))
+        } else {
+            Ok(map)
+        }
+    }
+}
+
+impl ser::SerializeStruct for SerializeStruct {
+    type Ok = SteelVal;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> SerdeResult<()> {
+        self.push(key, value)
+    }
+
+    fn end(self) -> SerdeResult<SteelVal> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeStruct {
+    type Ok = SteelVal;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> SerdeResult<()> {
+        self.push(key, value)
+    }
+
+    fn end(self) -> SerdeResult<SteelVal> {
+        self.finish()
+    }
+}
+
+// How a hashmap key shows up in an error path
+fn key_name(key: &SteelVal) -> String {
+    match key {
+        SteelVal::StringV(s) | SteelVal::SymbolV(s) => s.to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// A `serde` deserializer that reads from a [`SteelVal`].
+pub struct Deserializer {
+    value: SteelVal,
+}
+
+impl Deserializer {
+    /// Creates a deserializer that reads from `value`.
+    pub fn new(value: SteelVal) -> Self {
+        Deserializer { value }
+    }
+
+    fn unexpected(&self, expected: &str) -> Error {
+        Error::new(format!("expected {expected}, found: {}", self.value))
+    }
+}
+
+// The elements of anything that reads as a sequence
+fn sequence(value: &SteelVal) -> Option<Vec<SteelVal>> {
+    match value {
+        SteelVal::ListV(l) => Some(l.iter().cloned().collect()),
+        SteelVal::VectorV(v) => Some(v.iter().cloned().collect()),
+        SteelVal::MutableVector(v) => Some(v.borrow().clone()),
+        SteelVal::HashSetV(s) => Some(s.iter().cloned().collect()),
+        SteelVal::CustomStruct(s) => Some(s.borrow().fields.iter().cloned().collect()),
+        _ => None,
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        match &self.value {
+            SteelVal::BoolV(b) => visitor.visit_bool(*b),
+            SteelVal::IntV(n) => visitor.visit_i64(*n as i64),
+            SteelVal::NumV(n) => visitor.visit_f64(*n),
+            SteelVal::CharV(c) => visitor.visit_char(*c),
+            SteelVal::StringV(s) | SteelVal::SymbolV(s) => visitor.visit_str(s),
+            SteelVal::Void => visitor.visit_unit(),
+            SteelVal::BigNum(n) => {
+                if let Some(n) = n.to_i128() {
+                    visitor.visit_i128(n)
+                } else if let Some(n) = n.to_u128() {
+                    visitor.visit_u128(n)
+                } else {
+                    Err(Error::new(format!("integer out of range: {}", n.as_ref())))
+                }
+            }
+            SteelVal::ByteVector(b) => visitor.visit_byte_buf(b.borrow().clone()),
+            SteelVal::HashMapV(m) => visitor.visit_map(MapDeserializer::new(m.iter())),
+            SteelVal::Boxed(b) => {
+                let inner = b.borrow().clone();
+                Deserializer::new(inner).deserialize_any(visitor)
+            }
+            other => match sequence(other) {
+                Some(items) => visitor.visit_seq(SeqDeserializer::new(items, None)),
+                None => Err(self.unexpected("a value that can be deserialized")),
+            },
+        }
+    }
+
+    // Steel code often writes whole numbers where floats are expected
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        self.deserialize_f64(visitor)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        if let SteelVal::IntV(n) = self.value {
+            return visitor.visit_f64(n as f64);
+        }
+
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        if matches!(self.value, SteelVal::BoolV(false) | SteelVal::Void) {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    // Bytevectors are read as bytes by `deserialize_any`, but `Vec<u8>` asks for a sequence
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        if let SteelVal::ByteVector(b) = &self.value {
+            let items = b
+                .borrow()
+                .iter()
+                .map(|x| SteelVal::IntV(*x as isize))
+                .collect();
+            return visitor.visit_seq(SeqDeserializer::new(items, None));
+        }
+
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> SerdeResult<V::Value> {
+        match &self.value {
+            SteelVal::Void => visitor.visit_unit(),
+            _ => Err(self.unexpected("void")),
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> SerdeResult<V::Value> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> SerdeResult<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> SerdeResult<V::Value> {
+        match &self.value {
+            SteelVal::CustomStruct(s) => {
+                let (struct_name, items, names) = {
+                    let s = s.borrow();
+                    (
+                        s.name,
+                        s.fields.iter().cloned().collect::<Vec<_>>(),
+                        s.field_names(),
+                    )
+                };
+
+                match registered(name) {
+                    Some(registered) => {
+                        if struct_name != registered.steel_name {
+                            return Err(self.unexpected(&format!(
+                                "an instance of struct {}",
+                                registered.steel_name
+                            )));
+                        }
+
+                        registered.check_fields(items.len(), names)?;
+                        visitor.visit_seq(SeqDeserializer::new(items, Some(fields)))
+                    }
+                    // Without a registration, the fields can only be matched up by name
+                    None => match names {
+                        Some(names) => {
+                            visitor.visit_map(MapDeserializer::named_fields(names, items))
+                        }
+                        None => Err(self.unexpected(&format!(
+                            "a struct with named fields, or one registered for {name}"
+                        ))),
+                    },
+                }
+            }
+            SteelVal::HashMapV(m) => visitor.visit_map(MapDeserializer::new(m.iter())),
+            _ => Err(self.unexpected("a struct or a hashmap")),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> SerdeResult<V::Value> {
+        match &self.value {
+            SteelVal::SymbolV(s) | SteelVal::StringV(s) => visitor.visit_enum(EnumDeserializer {
+                variant: s.to_string(),
+                content: None,
+            }),
+            SteelVal::CustomStruct(s) => {
+                let s = s.borrow();
+
+                visitor.visit_enum(EnumDeserializer {
+                    variant: s.name.to_string(),
+                    content: Some(VariantContent::Fields(
+                        s.fields.iter().cloned().collect(),
+                        s.field_names(),
+                    )),
+                })
+            }
+            SteelVal::HashMapV(m) if m.len() == 1 => {
+                let (key, value) = m.iter().next().unwrap();
+
+                visitor.visit_enum(EnumDeserializer {
+                    variant: key_name(key),
+                    content: Some(VariantContent::Value(value.clone())),
+                })
+            }
+            _ => Err(self.unexpected("a symbol, a struct or a hashmap with a single entry")),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 char str string
+        bytes byte_buf tuple tuple_struct map identifier ignored_any
+    }
+}
+
+struct SeqDeserializer {
+    items: std::vec::IntoIter<SteelVal>,
+    index: usize,
+    // Struct fields read positionally are reported by name
+    fields: Option<&'static [&'static str]>,
+}
+
+impl SeqDeserializer {
+    fn new(items: Vec<SteelVal>, fields: Option<&'static [&'static str]>) -> Self {
+        SeqDeserializer {
+            items: items.into_iter(),
+            index: 0,
+            fields,
+        }
+    }
+
+    fn segment(&self) -> PathSegment {
+        match self.fields.and_then(|x| x.get(self.index)) {
+            Some(name) => PathSegment::Field(name.to_string()),
+            None => PathSegment::Index(self.index),
+        }
+    }
+}
+
+impl<'de> SeqAccess<'de> for SeqDeserializer {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> SerdeResult<Option<T::Value>> {
+        let value = match self.items.next() {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        let result = seed
+            .deserialize(Deserializer::new(value))
+            .map_err(|e| e.within(self.segment()))?;
+
+        self.index += 1;
+        Ok(Some(result))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+struct MapDeserializer {
+    entries: std::vec::IntoIter<(SteelVal, SteelVal)>,
+    value: Option<(String, SteelVal)>,
+}
+
+impl MapDeserializer {
+    fn new<'a>(entries: impl Iterator<Item = (&'a SteelVal, &'a SteelVal)>) -> Self {
+        MapDeserializer {
+            entries: entries
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect::<Vec<_>>()
+                .into_iter(),
+            value: None,
+        }
+    }
+
+    // The fields of a Steel struct, keyed by their names
+    fn named_fields(names: Vec<SteelString>, items: Vec<SteelVal>) -> Self {
+        MapDeserializer {
+            entries: names
+                .into_iter()
+                .map(SteelVal::SymbolV)
+                .zip(items)
+                .collect::<Vec<_>>()
+                .into_iter(),
+            value: None,
+        }
+    }
+}
+
+impl<'de> MapAccess<'de> for MapDeserializer {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> SerdeResult<Option<K::Value>> {
+        let (key, value) = match self.entries.next() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        let name = key_name(&key);
+        let key = seed
+            .deserialize(Deserializer::new(key))
+            .map_err(|e| e.within(PathSegment::Field(name.clone())))?;
+
+        self.value = Some((name, value));
+        Ok(Some(key))
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> SerdeResult<V::Value> {
+        let (name, value) = self
+            .value
+            .take()
+            .ok_or_else(|| Error::new("next_value_seed called before next_key_seed"))?;
+
+        seed.deserialize(Deserializer::new(value))
+            .map_err(|e| e.within(PathSegment::Field(name)))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+enum VariantContent {
+    // The fields of a Steel struct named after the variant, and their names if it has them
+    Fields(Vec<SteelVal>, Option<Vec<SteelString>>),
+    // The value of a single entry hashmap keyed by the variant
+    Value(SteelVal),
+}
+
+struct EnumDeserializer {
+    variant: String,
+    content: Option<VariantContent>,
+}
+
+impl<'de> EnumAccess<'de> for EnumDeserializer {
+    type Error = Error;
+    type Variant = VariantDeserializer;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> SerdeResult<(V::Value, VariantDeserializer)> {
+        let variant = seed.deserialize(self.variant.clone().into_deserializer())?;
+
+        Ok((
+            variant,
+            VariantDeserializer {
+                variant: self.variant,
+                content: self.content,
+            },
+        ))
+    }
+}
+
+struct VariantDeserializer {
+    variant: String,
+    content: Option<VariantContent>,
+}
+
+impl VariantDeserializer {
+    fn error(&self, e: Error) -> Error {
+        e.within(PathSegment::Field(self.variant.clone()))
+    }
+
+    // The contents of a tuple or struct variant
+    fn deserialize_fields<'de, V: Visitor<'de>>(
+        self,
+        fields: Option<&'static [&'static str]>,
+        visitor: V,
+    ) -> SerdeResult<V::Value> {
+        let result = match self.content {
+            // Struct variants are matched up by name, like structs
+            Some(VariantContent::Fields(ref items, ref names)) => match (fields, names) {
+                (None, _) => visitor.visit_seq(SeqDeserializer::new(items.clone(), None)),
+                (Some(_), Some(names)) => {
+                    visitor.visit_map(MapDeserializer::named_fields(names.clone(), items.clone()))
+                }
+                (Some(_), None) => Err(Error::new(format!(
+                    "expected variant {} to be a struct with named fields",
+                    self.variant
+                ))),
+            },
+            Some(VariantContent::Value(ref value)) => match fields {
+                Some(fields) => de::Deserializer::deserialize_struct(
+                    Deserializer::new(value.clone()),
+                    "",
+                    fields,
+                    visitor,
+                ),
+                None => {
+                    de::Deserializer::deserialize_seq(Deserializer::new(value.clone()), visitor)
+                }
+            },
+            None => Err(Error::new(format!(
+                "expected variant {} to have fields",
+                self.variant
+            ))),
+        };
+
+        result.map_err(|e| self.error(e))
+    }
+}
+
+impl<'de> VariantAccess<'de> for VariantDeserializer {
+    type Error = Error;
+
+    fn unit_variant(self) -> SerdeResult<()> {
+        match &self.content {
+            None => Ok(()),
+            Some(VariantContent::Fields(items, _)) if items.is_empty() => Ok(()),
+            Some(VariantContent::Value(SteelVal::Void)) => Ok(()),
+            Some(_) => Err(Error::new(format!(
+                "expected variant {} to have no fields",
+                self.variant
+            ))),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> SerdeResult<T::Value> {
+        let value = match &self.content {
+            Some(VariantContent::Fields(items, _)) if items.len() == 1 => items[0].clone(),
+            Some(VariantContent::Value(value)) => value.clone(),
+            _ => {
+                return Err(Error::new(format!(
+                    "expected variant {} to have a single field",
+                    self.variant
+                )))
+            }
+        };
+
+        seed.deserialize(Deserializer::new(value))
+            .map_err(|e| self.error(e))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> SerdeResult<V::Value> {
+        self.deserialize_fields(None, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> SerdeResult<V::Value> {
+        self.deserialize_fields(Some(fields), visitor)
+    }
+}
+
+#[cfg(test)]
+mod serde_tests {
+    use super::*;
+
+    use crate::steel_vm::engine::Engine;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Server {
+        host: String,
+        port: u16,
+        tags: Vec<String>,
+        weight: Option<f64>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Rectangle { width: f64, height: f64 },
+    }
+
+    fn round_trip<T: Serialize + DeserializeOwned>(value: &T) -> T {
+        from_steelval(&to_steelval(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn structs_become_hashmaps() {
+        let server = Server {
+            host: "localhost".to_string(),
+            port: 8080,
+            tags: vec!["a".to_string(), "b".to_string()],
+            weight: None,
+        };
+
+        let value = to_steelval(&server).unwrap();
+
+        if let SteelVal::HashMapV(m) = &value {
+            assert_eq!(
+                m.get(&SteelVal::SymbolV("port".into())),
+                Some(&SteelVal::IntV(8080))
+            );
+            assert_eq!(
+                m.get(&SteelVal::SymbolV("tags".into())),
+                Some(&crate::list![
+                    SteelVal::StringV("a".into()),
+                    SteelVal::StringV("b".into())
+                ])
+            );
+        } else {
+            panic!("Expected a hashmap");
+        }
+
+        assert_eq!(round_trip(&server), server);
+    }
+
+    #[test]
+    fn enums_are_tagged() {
+        assert_eq!(
+            to_steelval(&Shape::Empty).unwrap(),
+            SteelVal::SymbolV("Empty".into())
+        );
+
+        for shape in [
+            Shape::Empty,
+            Shape::Circle(1.5),
+            Shape::Rectangle {
+                width: 2.0,
+                height: 3.0,
+            },
+        ] {
+            assert_eq!(round_trip(&shape), shape);
+        }
+    }
+
+    #[test]
+    fn results_use_the_builtin_structs() {
+        let value = to_steelval(&std::result::Result::<isize, String>::Ok(10)).unwrap();
+
+        assert!(matches!(&value, SteelVal::CustomStruct(s) if s.borrow().name.resolve() == "Ok"));
+        assert_eq!(
+            from_steelval::<std::result::Result<isize, String>>(&value).unwrap(),
+            Ok(10)
+        );
+    }
+
+    #[test]
+    fn maps_and_vectors_can_be_read() {
+        let mut map = HashMap::new();
+        map.insert("one".to_string(), 1);
+        map.insert("two".to_string(), 2);
+
+        assert_eq!(round_trip(&map), map);
+
+        let vector = SteelVal::VectorV(Gc::new(im_rc::vector![
+            SteelVal::IntV(1),
+            SteelVal::IntV(2)
+        ]));
+
+        assert_eq!(from_steelval::<Vec<u8>>(&vector).unwrap(), vec![1, 2]);
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Point {
+        x: isize,
+        y: isize,
+    }
+
+    #[test]
+    fn registered_structs_become_steel_structs() {
+        let mut vm = Engine::new();
+        vm.run("(struct Point (x y))").unwrap();
+
+        register_struct::<Point>("Point").unwrap();
+
+        let point = Point { x: 1, y: 2 };
+        vm.register_serde_value("point", &point).unwrap();
+
+        assert_eq!(
+            vm.run("(list (Point? point) (Point-y point))").unwrap(),
+            vec![crate::list![SteelVal::BoolV(true), SteelVal::IntV(2)]]
+        );
+        assert_eq!(vm.extract_serde::<Point>("point").unwrap(), point);
+    }
+
+    #[test]
+    fn registered_structs_must_have_the_same_fields() {
+        let mut vm = Engine::new();
+        vm.run("(struct Point (y x))").unwrap();
+
+        register_struct::<Point>("Point").unwrap();
+
+        let error = to_steelval(&Point { x: 1, y: 2 }).unwrap_err();
+        assert!(error.to_string().contains("has the fields (y x)"));
+
+        vm.run("(define point (Point 2 1))").unwrap();
+        assert!(vm.extract_serde::<Point>("point").is_err());
+
+        vm.run("(struct Point3 (x y z))").unwrap();
+        register_struct::<Point>("Point3").unwrap();
+
+        let error = to_steelval(&Point { x: 1, y: 2 }).unwrap_err();
+        assert!(error.to_string().contains("has 3 fields"));
+    }
+
+    #[test]
+    fn unregistered_structs_are_read_by_field_name() {
+        let mut vm = Engine::new();
+        vm.run("(struct Point (y x)) (define point (Point 2 1))")
+            .unwrap();
+
+        assert_eq!(
+            vm.extract_serde::<Point>("point").unwrap(),
+            Point { x: 1, y: 2 }
+        );
+
+        // Without a registration, Rust structs still become hashmaps
+        assert!(matches!(
+            to_steelval(&Point { x: 1, y: 2 }).unwrap(),
+            SteelVal::HashMapV(_)
+        ));
+    }
+
+    #[derive(Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct Address {
+        host: String,
+        port: String,
+    }
+
+    #[test]
+    fn errors_point_to_the_field() {
+        let value = to_steelval(&vec![Server {
+            host: "localhost".to_string(),
+            port: 8080,
+            tags: vec![],
+            weight: Some(1.0),
+        }])
+        .unwrap();
+
+        let error = from_steelval::<Vec<Address>>(&value).unwrap_err();
+        assert!(error.to_string().contains("at [0].port"));
+    }
+}
@@ -112,6 +112,17 @@ pub fn get_function_name(function: FunctionSignature) -> Option<FunctionSignatur
     })
 }
 
+/// Registers the constructor, type predicate and field accessors of a Rust struct. Implemented by
+/// `#[derive(Steel)]` for structs with named fields.
+pub trait RegisterStruct {
+    fn register_struct(module: &mut BuiltInModule);
+}
+
+/// Registers the methods of a Rust type. Implemented by `#[steel::methods]` on an `impl` block.
+pub trait RegisterMethods {
+    fn register_methods(module: &mut BuiltInModule);
+}
+
 pub struct NativeFunctionDefinition {
     pub name: &'static str,
     pub func: fn(&[SteelVal]) -> Result<SteelVal>,
@@ -251,6 +262,19 @@ impl BuiltInModule {
         )
     }
 
+    /// Registers everything generated by `#[derive(Steel)]` for `T`: a constructor named after
+    /// the type, a `T?` predicate, and a `T-field` getter and `set-T-field!` setter for each field.
+    pub fn register_struct<T: RegisterStruct>(&mut self) -> &mut Self {
+        T::register_struct(self);
+        self
+    }
+
+    /// Registers the methods of `T` from its `#[steel::methods]` impl block.
+    pub fn register_methods<T: RegisterMethods>(&mut self) -> &mut Self {
+        T::register_methods(self);
+        self
+    }
+
     pub fn register_doc(
         &mut self,
         definition: impl Into<Cow<'static, str>>,
@@ -289,6 +313,10 @@ impl BuiltInModule {
         self
     }
 
+    pub(crate) fn values(&self) -> impl Iterator<Item = (&Arc<str>, &SteelVal)> {
+        self.values.iter()
+    }
+
     // This _will_ panic given an incorrect value. This will be tied together by macros only allowing legal entries
     pub fn get(&self, name: String) -> SteelVal {
         self.values.get(name.as_str()).unwrap().clone()
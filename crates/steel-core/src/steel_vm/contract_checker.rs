@@ -1,44 +1,293 @@
-/*
-
-#![allow(unused)]
-use std::collections::{BTreeSet, HashMap};
+// Static checking for typed modules.
+//
+// A module opts in with a leading `#lang typed/steel` line. Function signatures come from
+// `define/contract` and from `(: name type)` declarations, for instance:
+//
+// #lang typed/steel
+//
+// (: total (-> (Listof Int) Int))
+// (define (total xs) (if (null? xs) 0 (+ (car xs) (total (cdr xs)))))
+//
+// (define/contract (describe x)
+//     (->/c (or/c int? string?) string?)
+//     (if (int? x) (int->string x) x))
+//
+// The checker is gradual - anything it can't say anything about is `Any`, which is compatible
+// with every type. It runs over the module once the macros have been expanded, so define/contract
+// has already become:
+//
+// (begin
+//     (define describe (lambda (x) ...))
+//     (set! describe (bind/c (make-function/c (make/c (or/c int? string?) 'or/c) ...) describe 'describe)))
+//
+// When the body and every call site of a contracted function are proven to satisfy the contract,
+// and the function never escapes the module, the `set!` is dropped so the contract isn't checked
+// again at runtime.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
 
 use quickscope::ScopeMap;
 
-use crate::{
-    compiler::passes::VisitorMutUnitRef,
-    parser::{tokens::TokenType, visitors::VisitorMut},
+use crate::compiler::passes::VisitorMutUnitRef;
+use crate::parser::{
+    ast::{Atom, Begin, Define, ExprKind, If, LambdaFunction, Let, List, Quote, Set},
+    interner::InternedString,
+    parser::Parser,
+    span::Span,
+    span_visitor::get_span,
+    tokens::TokenType,
+    visitors::VisitorMut,
 };
-use crate::{parser::ast::ExprKind, rvals::Result};
-
-/*
-(define applesauce
-    (bind/c
-        (make-function/c
-            (make/c int? 'int?)
-            (make/c int? 'int?)
-            (make/c int? 'int?)
-            (make/c int? 'int?))
-        (lambda (x y z) (begin (+ x y z)))
-    applesauce))
-
-(define dummy
-    (bind/c
-        (make-function/c
-            (make/c string? string?)
-            (make/c integer? integer?)
-            (make/c
-                (make-function/c
-                    (make/c string? string?)
-                    (make/c string? string?))
-            (make-function/c
-                (make/c string? string?)
-                (make/c string? string?))))
-    (lambda (foo bar)
-        (begin (list (int->string bar) foo))) dummy))
-
-
-*/
+use crate::rerrs::{ErrorKind, SteelErr};
+use crate::rvals::Result;
+
+const LANG: &str = "#lang";
+const TYPED_STEEL: &str = "typed/steel";
+
+// The types of the built in functions the checker knows about. Anything not in here is `Any`.
+static BUILT_IN_SIGNATURES: &[(&str, &str)] = &[
+    ("/", "(-> Number * Number)"),
+    ("=", "(-> Number * Boolean)"),
+    ("<", "(-> Number * Boolean)"),
+    (">", "(-> Number * Boolean)"),
+    ("<=", "(-> Number * Boolean)"),
+    (">=", "(-> Number * Boolean)"),
+    ("abs", "(-> Number Number)"),
+    ("quotient", "(-> Int Int Int)"),
+    ("modulo", "(-> Int Int Int)"),
+    ("remainder", "(-> Int Int Int)"),
+    ("even?", "(-> Int Boolean)"),
+    ("odd?", "(-> Int Boolean)"),
+    ("zero?", "(-> Number Boolean)"),
+    ("not", "(-> Any Boolean)"),
+    ("equal?", "(-> Any Any Boolean)"),
+    ("eq?", "(-> Any Any Boolean)"),
+    ("null?", "(-> Any Boolean)"),
+    ("empty?", "(-> Any Boolean)"),
+    ("car", "(All (a) (-> (Listof a) a))"),
+    ("first", "(All (a) (-> (Listof a) a))"),
+    ("cdr", "(All (a) (-> (Listof a) (Listof a)))"),
+    ("rest", "(All (a) (-> (Listof a) (Listof a)))"),
+    ("cons", "(All (a) (-> a (Listof a) (Listof a)))"),
+    ("length", "(All (a) (-> (Listof a) Int))"),
+    ("reverse", "(All (a) (-> (Listof a) (Listof a)))"),
+    ("append", "(All (a) (-> (Listof a) * (Listof a)))"),
+    ("list-ref", "(All (a) (-> (Listof a) Int a))"),
+    ("map", "(All (a b) (-> (-> a b) (Listof a) (Listof b)))"),
+    ("filter", "(All (a) (-> (-> a Any) (Listof a) (Listof a)))"),
+    ("hash-get", "(All (k v) (-> (Hashof k v) k v))"),
+    ("hash-ref", "(All (k v) (-> (Hashof k v) k v))"),
+    (
+        "hash-insert",
+        "(All (k v) (-> (Hashof k v) k v (Hashof k v)))",
+    ),
+    ("hash-contains?", "(All (k v) (-> (Hashof k v) k Boolean))"),
+    (
+        "hash-keys->list",
+        "(All (k v) (-> (Hashof k v) (Listof k)))",
+    ),
+    (
+        "hash-values->list",
+        "(All (k v) (-> (Hashof k v) (Listof v)))",
+    ),
+    ("string-append", "(-> String * String)"),
+    ("string-length", "(-> String Int)"),
+    ("string-upcase", "(-> String String)"),
+    ("string-downcase", "(-> String String)"),
+    ("substring", "(-> String Int Int String)"),
+    ("int->string", "(-> Int String)"),
+    ("number->string", "(-> Number String)"),
+    ("string->symbol", "(-> String Symbol)"),
+    ("symbol->string", "(-> Symbol String)"),
+    ("string->list", "(-> String (Listof Char))"),
+    ("display", "(-> Any Void)"),
+    ("displayln", "(-> Any * Void)"),
+    ("newline", "(-> Void)"),
+    ("void", "(-> Void)"),
+];
+
+/// Removes a leading `#lang typed/steel` from the module, returning whether it was there.
+pub(crate) fn strip_typed_language(exprs: &mut Vec<ExprKind>) -> bool {
+    let is_typed = matches!(
+        (
+            exprs.first().and_then(|x| x.atom_identifier()),
+            exprs.get(1).and_then(|x| x.atom_identifier())
+        ),
+        (Some(lang), Some(language)) if lang.resolve() == LANG && language.resolve() == TYPED_STEEL
+    );
+
+    if is_typed {
+        exprs.drain(..2);
+    }
+
+    is_typed
+}
+
+/// The annotations pulled out of a typed module before it is expanded.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TypeDeclarations {
+    // (: name type)
+    declarations: Vec<(InternedString, ExprKind)>,
+    // (struct name (fields ...))
+    structs: Vec<(InternedString, Vec<InternedString>)>,
+}
+
+impl TypeDeclarations {
+    /// Takes the `(: name type)` declarations out of the top level, since they aren't valid code,
+    /// and records the structs declared alongside them.
+    ///
+    /// Declarations are only looked for at the top level, including inside a top level `begin`,
+    /// before any macros are expanded - so a macro can't produce one. A declaration anywhere
+    /// else is an error rather than being silently ignored.
+    pub(crate) fn extract(exprs: &mut Vec<ExprKind>) -> Result<Self> {
+        let mut types = TypeDeclarations::default();
+        types.extract_from(exprs)?;
+        Ok(types)
+    }
+
+    fn extract_from(&mut self, exprs: &mut Vec<ExprKind>) -> Result<()> {
+        for mut expr in std::mem::take(exprs) {
+            if let ExprKind::Begin(b) = &mut expr {
+                self.extract_from(&mut b.exprs)?;
+                exprs.push(expr);
+                continue;
+            }
+
+            match &expr {
+                ExprKind::List(l) => match l.first_ident().map(|x| x.resolve()) {
+                    Some(":") => {
+                        if let [_, ExprKind::Atom(name), ty] = l.args.as_slice() {
+                            if let TokenType::Identifier(name) = &name.syn.ty {
+                                self.declarations.push((*name, ty.clone()));
+                                continue;
+                            }
+                        }
+
+                        stop!(BadSyntax => format!("type declarations take the form (: name type), found: {}", expr); get_span(&expr))
+                    }
+                    Some("struct") => {
+                        if let (Some(name), Some(ExprKind::List(fields))) =
+                            (l.get(1).and_then(|x| x.atom_identifier()), l.get(2))
+                        {
+                            let fields = fields
+                                .iter()
+                                .filter_map(|x| x.atom_identifier().copied())
+                                .collect();
+
+                            self.structs.push((*name, fields));
+                        }
+                    }
+                    _ => reject_nested_declarations(&expr)?,
+                },
+                expr => reject_nested_declarations(expr)?,
+            }
+
+            exprs.push(expr);
+        }
+
+        Ok(())
+    }
+
+    // The declared types, along with the constructor, predicate and accessors of each declared
+    // struct. The field types come from a declaration of the constructor, if there is one.
+    fn types(
+        &self,
+        structs: &HashSet<InternedString>,
+    ) -> Result<HashMap<InternedString, TypeInfo>> {
+        let mut types = HashMap::new();
+
+        for (name, ty) in &self.declarations {
+            types.insert(*name, TypeInfo::parse(ty, structs, &[])?);
+        }
+
+        for (name, fields) in &self.structs {
+            let constructor = types.entry(*name).or_insert_with(|| {
+                TypeInfo::FixedArityFunction(
+                    vec![TypeInfo::Unknown; fields.len()],
+                    Box::new(TypeInfo::Struct(*name)),
+                )
+            });
+
+            let field_types = match constructor {
+                TypeInfo::FixedArityFunction(params, _) if params.len() == fields.len() => {
+                    params.clone()
+                }
+                _ => vec![TypeInfo::Unknown; fields.len()],
+            };
+
+            types.insert(
+                format!("{name}?").as_str().into(),
+                TypeInfo::FixedArityFunction(vec![TypeInfo::Any], Box::new(TypeInfo::Boolean)),
+            );
+
+            for (field, ty) in fields.iter().zip(field_types) {
+                types.insert(
+                    format!("{name}-{field}").as_str().into(),
+                    TypeInfo::FixedArityFunction(vec![TypeInfo::Struct(*name)], Box::new(ty)),
+                );
+            }
+        }
+
+        Ok(types)
+    }
+}
+
+fn reject_nested_declarations(expr: &ExprKind) -> Result<()> {
+    let mut finder = NestedDeclarationFinder { found: None };
+    finder.visit(expr);
+
+    if let Some(declaration) = finder.found {
+        let span = Span::coalesce_span(&declaration.args.iter().map(get_span).collect::<Vec<_>>());
+        stop!(BadSyntax => format!("type declarations are only allowed at the top level of a module, found: {declaration}"); span)
+    }
+
+    Ok(())
+}
+
+// Finds a `(: name type)` form below the top level
+struct NestedDeclarationFinder<'a> {
+    found: Option<&'a List>,
+}
+
+impl<'a> VisitorMutUnitRef<'a> for NestedDeclarationFinder<'a> {
+    fn visit_list(&mut self, l: &'a List) {
+        if l.first_ident().map(|x| x.resolve()) == Some(":") {
+            self.found.get_or_insert(l);
+        }
+
+        for expr in &l.args {
+            self.visit(expr);
+        }
+    }
+
+    fn visit_quote(&mut self, _quote: &'a Quote) {}
+}
+
+/// Type checks a typed module and erases the contracts it proved, in place.
+///
+/// `exported` names the identifiers that can be reached from outside of the module - contracts
+/// on those are always kept, since the module can't know how they'll be called.
+pub(crate) fn check_typed_module(
+    ast: &mut Vec<ExprKind>,
+    declarations: &TypeDeclarations,
+    exported: &HashSet<InternedString>,
+) -> Result<()> {
+    let erased = {
+        let global_contract_info =
+            GlobalContractCollector::collect_contracts(ast.iter(), declarations)?;
+        let mut checker = ContractChecker::new(global_contract_info, exported);
+        checker.check(ast.iter())?;
+        checker.erasable()
+    };
+
+    if !erased.is_empty() {
+        log::debug!(target: "types", "Erasing statically proven contracts: {:?}", erased);
+        erase_contracts(ast, &erased);
+    }
+
+    Ok(())
+}
 
 // Corresponds to a concrete type, referenced by a contract
 // For instance, this should be coerced from the contract type given the inference
@@ -56,12 +305,16 @@ pub enum BaseTypeKind<'a> {
     Character,
     Symbol,
     Void,
+    Number,
+    List,
+    Hash,
     Other(&'a str),
 }
 
 impl<'a> BaseTypeKind<'a> {
-    // TODO -> don't have this just refer directly to unknown
-    fn to_type_info(&self) -> TypeInfo {
+    // Predicates we don't know about are `Unknown`, unless they belong to a struct declared in a
+    // typed module
+    fn to_type_info(&self, structs: &HashSet<InternedString>) -> TypeInfo {
         match self {
             BaseTypeKind::Any => TypeInfo::Any,
             BaseTypeKind::Int => TypeInfo::Int,
@@ -71,204 +324,192 @@ impl<'a> BaseTypeKind<'a> {
             BaseTypeKind::Character => TypeInfo::Char,
             BaseTypeKind::Void => TypeInfo::Void,
             BaseTypeKind::Symbol => TypeInfo::Symbol,
-            BaseTypeKind::Other(_) => TypeInfo::Unknown,
+            BaseTypeKind::Number => TypeInfo::Number,
+            BaseTypeKind::List => TypeInfo::ListOf(Box::new(TypeInfo::Any)),
+            BaseTypeKind::Hash => {
+                TypeInfo::HashOf(Box::new(TypeInfo::Any), Box::new(TypeInfo::Any))
+            }
+            BaseTypeKind::Other(name) => match struct_predicate(name, structs) {
+                Some(name) => TypeInfo::Struct(name),
+                None => TypeInfo::Unknown,
+            },
         }
     }
 
-    fn from_str(input: &'a str) -> BaseTypeKind<'a> {
+    fn from_predicate(input: &'a str) -> BaseTypeKind<'a> {
         match input {
+            "any/c" => BaseTypeKind::Any,
             "int?" | "integer?" => BaseTypeKind::Int,
             "string?" => BaseTypeKind::String,
+            "float?" => BaseTypeKind::Float,
+            "number?" | "real?" => BaseTypeKind::Number,
+            "boolean?" | "bool?" => BaseTypeKind::Boolean,
+            "char?" => BaseTypeKind::Character,
+            "symbol?" => BaseTypeKind::Symbol,
+            "void?" => BaseTypeKind::Void,
+            "list?" => BaseTypeKind::List,
+            "hash?" => BaseTypeKind::Hash,
             _ => BaseTypeKind::Other(input),
         }
     }
-}
 
-// Concrete, inferred type
-#[derive(Debug)]
-enum Type {
-    Int,
-    Bool,
-    List(Box<Type>),
-    Func(Vec<Type>, Box<Type>),
+    // Whether the type says exactly what the predicate checks
+    fn is_exact(&self, structs: &HashSet<InternedString>) -> bool {
+        match self {
+            BaseTypeKind::Other(name) => struct_predicate(name, structs).is_some(),
+            _ => true,
+        }
+    }
 }
 
-pub enum BuiltInFunctionContract {
-    // Things that have a fixed arity
-    FixedArity(Vec<BaseTypeKind<'static>>, BaseTypeKind<'static>),
-    // Things that match any number of arity in the precondition but all match
-    // For instance, addition accepts all numbers in the precondition
-    AnyArity(BaseTypeKind<'static>, BaseTypeKind<'static>),
+// `Point?` => `Point`, for the structs declared in a typed module
+fn struct_predicate(name: &str, structs: &HashSet<InternedString>) -> Option<InternedString> {
+    let name: InternedString = name.strip_suffix('?')?.into();
+    structs.contains(&name).then_some(name)
 }
 
 // Generate the bindings for the built in functions
-pub fn built_in_contract_map() -> HashMap<&'static str, TypeInfo> {
+pub fn built_in_contract_map() -> Result<HashMap<InternedString, TypeInfo>> {
     use TypeInfo::*;
     let mut map = HashMap::new();
 
-    map.insert(
-        "+",
-        AnyArityFunction(
-            Box::new(UnionOf(
-                vec![TypeInfo::Int, TypeInfo::Float].into_iter().collect(),
-            )),
-            Box::new(UnionOf(
-                vec![TypeInfo::Int, TypeInfo::Float].into_iter().collect(),
-            )),
-        ),
-    );
-    map.insert(
-        "-",
-        AnyArityFunction(Box::new(TypeInfo::Int), Box::new(TypeInfo::Int)),
-    );
-    map.insert(
-        "int->string",
-        FixedArityFunction(vec![TypeInfo::Int], Box::new(TypeInfo::String)),
-    );
+    // The arithmetic operators keep integers as integers, which is worked out at the call site
+    for op in ["+", "-", "*"] {
+        map.insert(
+            op.into(),
+            AnyArityFunction(Box::new(Number), Box::new(Number)),
+        );
+    }
 
     map.insert(
-        "list",
+        "list".into(),
         DependentFunction(|args: Vec<TypeInfo>| {
-            TypeInfo::UnionOf(
-                args.into_iter()
-                    .chain(std::iter::once(TypeInfo::Any))
-                    .collect(),
-            )
+            ListOf(Box::new(TypeInfo::union(args).unwrap_or(Unknown)))
         }),
     );
 
-    map
+    for (name, signature) in BUILT_IN_SIGNATURES {
+        for expr in Parser::parse(signature)? {
+            map.insert(
+                (*name).into(),
+                TypeInfo::parse(&expr, &HashSet::new(), &[])?,
+            );
+        }
+    }
+
+    Ok(map)
 }
 
 #[derive(Debug, PartialEq, PartialOrd)]
 pub enum StaticContract<'a> {
     Atom(BaseTypeKind<'a>),
     ListOf(Box<StaticContract<'a>>),
+    HashOf(Box<StaticContract<'a>>, Box<StaticContract<'a>>),
     UnionOf(Vec<StaticContract<'a>>),
     IntersectionOf(Vec<StaticContract<'a>>),
     Function(Vec<StaticContract<'a>>, Box<StaticContract<'a>>),
-    // For now just unify the internal representation of built ins vs not
-    AnyArityFunction(Box<StaticContract<'static>>, Box<StaticContract<'static>>),
 }
 
 impl<'a> StaticContract<'a> {
-    fn to_type_info(&self) -> TypeInfo {
+    fn to_type_info(&self, structs: &HashSet<InternedString>) -> TypeInfo {
         match self {
-            StaticContract::Atom(a) => a.to_type_info(),
-            StaticContract::ListOf(l) => TypeInfo::ListOf(Box::new(l.to_type_info())),
-            StaticContract::Function(pre, post) => TypeInfo::FixedArityFunction(
-                pre.iter().map(|x| x.to_type_info()).collect(),
-                Box::new(post.to_type_info()),
+            StaticContract::Atom(a) => a.to_type_info(structs),
+            StaticContract::ListOf(l) => TypeInfo::ListOf(Box::new(l.to_type_info(structs))),
+            StaticContract::HashOf(k, v) => TypeInfo::HashOf(
+                Box::new(k.to_type_info(structs)),
+                Box::new(v.to_type_info(structs)),
             ),
-            StaticContract::AnyArityFunction(pre, post) => TypeInfo::AnyArityFunction(
-                Box::new(pre.to_type_info()),
-                Box::new(post.to_type_info()),
+            StaticContract::Function(pre, post) => TypeInfo::FixedArityFunction(
+                pre.iter().map(|x| x.to_type_info(structs)).collect(),
+                Box::new(post.to_type_info(structs)),
             ),
             StaticContract::UnionOf(u) => {
-                TypeInfo::UnionOf(u.iter().map(|x| x.to_type_info()).collect())
+                TypeInfo::union(u.iter().map(|x| x.to_type_info(structs)))
+                    .unwrap_or(TypeInfo::Unknown)
             }
             StaticContract::IntersectionOf(u) => {
-                TypeInfo::IntersectionOf(u.iter().map(|x| x.to_type_info()).collect())
+                TypeInfo::IntersectionOf(u.iter().map(|x| x.to_type_info(structs)).collect())
             }
         }
     }
 
-    fn from_exprkind(expr: &'a ExprKind) -> Result<StaticContract<'a>> {
-        match expr {
-            ExprKind::Atom(a) => {
-                let name = BaseTypeKind::from_str(a.ident().unwrap()); // TODO -> clean up this error handling
-
-                Ok(StaticContract::Atom(name))
+    // Whether the type says exactly what the contract checks - a contract built from a
+    // predicate we don't know about is `Unknown`, but checking it statically doesn't prove
+    // the contract would pass.
+    fn is_exact(&self, structs: &HashSet<InternedString>) -> bool {
+        match self {
+            StaticContract::Atom(a) => a.is_exact(structs),
+            StaticContract::ListOf(l) => l.is_exact(structs),
+            StaticContract::HashOf(k, v) => k.is_exact(structs) && v.is_exact(structs),
+            StaticContract::UnionOf(u) | StaticContract::IntersectionOf(u) => {
+                u.iter().all(|x| x.is_exact(structs))
             }
-            ExprKind::List(l) => match l.first_ident() {
-                Some("listof") => {
-                    let body = l.get(1).unwrap();
-
-                    Ok(StaticContract::ListOf(Box::new(Self::from_exprkind(body)?)))
-                }
-                Some("make/c") => {
-                    // Just recur on the actual contract here
-                    Self::from_exprkind(l.get(1).unwrap())
-                }
-                Some("make-function/c") => {
-                    // Pre conditions of the function contract
-                    let pre_conditions = &l.args[1..l.args.len() - 1];
-                    // Post condition of the function contract
-                    let post_condition = &l.args[l.args.len() - 1];
-
-                    let pre_condition_contracts = pre_conditions
-                        .iter()
-                        .map(Self::from_exprkind)
-                        .collect::<Result<Vec<_>>>()?;
-
-                    let post_condition_contract = Self::from_exprkind(post_condition)?;
-
-                    Ok(StaticContract::Function(
-                        pre_condition_contracts,
-                        Box::new(post_condition_contract),
-                    ))
-                }
-                _ => {
-                    println!("{expr}");
-                    stop!(Generic => "Unexpected contract combinator")
-                }
-            },
-            _ => {
-                stop!(BadSyntax => "contracts can either be atoms or lists")
+            StaticContract::Function(pre, post) => {
+                pre.iter().all(|x| x.is_exact(structs)) && post.is_exact(structs)
             }
         }
     }
-}
 
-pub type TypeId = usize;
+    // Contracts are ordinary values, so anything that isn't one of the combinators we know
+    // about is an opaque contract rather than an error.
+    fn from_exprkind(expr: &'a ExprKind) -> StaticContract<'a> {
+        let opaque = StaticContract::Atom(BaseTypeKind::Other(""));
 
-// This is a contract bound to the body expression
-#[derive(Debug)]
-pub struct BoundContract<'a> {
-    body: &'a ExprKind,
-    contract: StaticContract<'a>,
-}
+        match expr {
+            ExprKind::Atom(a) => match a.ident() {
+                Some(name) => StaticContract::Atom(BaseTypeKind::from_predicate(name.resolve())),
+                None => opaque,
+            },
+            ExprKind::List(l) => {
+                let args = &l.args[1.min(l.args.len())..];
 
-impl<'a> BoundContract<'a> {
-    // TODO make this resiliant to syntax errors
-    pub fn new(contract: StaticContract<'a>, body: &'a ExprKind) -> Self {
-        // if body.lambda_function().unwrap().args
-        if let StaticContract::Function(pre_conditions, _) = &contract {
-            assert_eq!(
-                body.lambda_function().unwrap().args.len(),
-                pre_conditions.len(),
-                "The arguments and contracts must be of matching lengths"
-            );
+                match (l.first_ident().map(|x| x.resolve()), args) {
+                    (Some("listof"), [body]) => {
+                        StaticContract::ListOf(Box::new(Self::from_exprkind(body)))
+                    }
+                    (Some("hash/c"), [key, value]) => StaticContract::HashOf(
+                        Box::new(Self::from_exprkind(key)),
+                        Box::new(Self::from_exprkind(value)),
+                    ),
+                    // Just recur on the actual contract here
+                    (Some("make/c"), [contract, ..]) => Self::from_exprkind(contract),
+                    (Some("make-function/c" | "->/c"), [pre_conditions @ .., post_condition]) => {
+                        StaticContract::Function(
+                            pre_conditions.iter().map(Self::from_exprkind).collect(),
+                            Box::new(Self::from_exprkind(post_condition)),
+                        )
+                    }
+                    (Some("or/c"), [_, ..]) => {
+                        StaticContract::UnionOf(args.iter().map(Self::from_exprkind).collect())
+                    }
+                    (Some("and/c"), [_, ..]) => StaticContract::IntersectionOf(
+                        args.iter().map(Self::from_exprkind).collect(),
+                    ),
+                    _ => opaque,
+                }
+            }
+            _ => opaque,
         }
-
-        BoundContract { body, contract }
     }
 }
 
 // Is this expression referring to a contract (is this a bind/c instance)
 fn is_contract(expr: &ExprKind) -> bool {
     fn is_contract_option(expr: &ExprKind) -> Option<bool> {
-        expr.list()?.first_ident().map(|x| x == "bind/c")
+        expr.list()?.first_ident().map(|x| x.resolve() == "bind/c")
     }
 
-    if let Some(inner) = is_contract_option(expr) {
-        inner
-    } else {
-        false
-    }
+    is_contract_option(expr).unwrap_or(false)
 }
 
 // Is this bind/c instance referring to a make-function/c instance
-fn function_contract(expr: &ExprKind) -> Option<Result<StaticContract>> {
+fn function_contract(expr: &ExprKind) -> Option<StaticContract<'_>> {
     let body = expr.list()?;
 
-    if body.first_ident()? == "bind/c" {
+    if body.first_ident()?.resolve() == "bind/c" {
         let make_function = body.get(1)?;
 
-        if make_function.list()?.first_ident()? == "make-function/c" {
-            // This just deconstructs the (bind/c into a individual struct for access)
-            // return Some(BindContract::new(make_function, body.get(2)?));
-
+        if make_function.list()?.first_ident()?.resolve() == "make-function/c" {
             return Some(StaticContract::from_exprkind(make_function));
         }
     }
@@ -278,46 +519,122 @@ fn function_contract(expr: &ExprKind) -> Option<Result<StaticContract>> {
 
 #[derive(Default, Debug)]
 pub struct GlobalContractCollector<'a> {
-    contracts: HashMap<String, StaticContract<'a>>,
-    built_ins: HashMap<&'static str, TypeInfo>,
+    contracts: HashMap<InternedString, StaticContract<'a>>,
+    built_ins: HashMap<InternedString, TypeInfo>,
+    // What a typed module declares, see `TypeDeclarations`
+    declared: HashMap<InternedString, TypeInfo>,
+    structs: HashSet<InternedString>,
+    // Visiting can't fail, so the first contract that disagrees with its declaration waits here
+    mismatch: Option<SteelErr>,
 }
 
 impl<'a> GlobalContractCollector<'a> {
-    pub fn collect_contracts(exprs: impl IntoIterator<Item = &'a ExprKind>) -> Self {
+    pub(crate) fn collect_contracts(
+        exprs: impl IntoIterator<Item = &'a ExprKind>,
+        declarations: &TypeDeclarations,
+    ) -> Result<Self> {
+        let structs = declarations.structs.iter().map(|x| x.0).collect();
+
         let mut collector = GlobalContractCollector {
-            // TODO: Attach the built-ins here
-            built_ins: built_in_contract_map(),
+            built_ins: built_in_contract_map()?,
+            declared: declarations.types(&structs)?,
+            structs,
             ..Default::default()
         };
 
         exprs.into_iter().for_each(|x| collector.visit(x));
-        collector
+
+        match collector.mismatch.take() {
+            Some(err) => Err(err),
+            None => Ok(collector),
+        }
+    }
+
+    pub fn get(&self, ident: &InternedString) -> Option<TypeInfo> {
+        self.get_defined(ident)
+            .or_else(|| self.built_ins.get(ident).cloned())
+    }
+
+    // The type of something the module defines, from its declaration or otherwise its contract
+    fn get_defined(&self, ident: &InternedString) -> Option<TypeInfo> {
+        self.declared
+            .get(ident)
+            .cloned()
+            .or_else(|| self.contract(ident))
     }
 
-    pub fn get(&self, ident: &str) -> Option<TypeInfo> {
+    fn contract(&self, ident: &InternedString) -> Option<TypeInfo> {
         self.contracts
             .get(ident)
-            .map(|x| x.to_type_info())
-            .or_else(|| self.built_ins.get(ident).cloned())
+            .map(|x| x.to_type_info(&self.structs))
+    }
+
+    fn insert_contract(
+        &mut self,
+        name: InternedString,
+        contract: StaticContract<'a>,
+        expr: &ExprKind,
+    ) {
+        if let Some(declared) = self.declared.get(&name) {
+            let signature = contract.to_type_info(&self.structs);
+
+            if !signature.is_compatible_with(declared) && self.mismatch.is_none() {
+                self.mismatch = Some(
+                    SteelErr::new(
+                        ErrorKind::TypeMismatch,
+                        format!("the contract on {name} checks {signature}, but it was declared as {declared}"),
+                    )
+                    .with_span(get_span(expr)),
+                );
+            }
+        }
+
+        self.contracts.insert(name, contract);
     }
 }
 
 impl<'a> VisitorMutUnitRef<'a> for GlobalContractCollector<'a> {
-    fn visit_define(&mut self, define: &'a crate::parser::ast::Define) {
-        if let Some(contract) = function_contract(&define.body) {
-            // If we try to collect contracts on something thats not expanded, we'll have problems anyway
-            self.contracts.insert(
-                define.name.atom_identifier().unwrap().to_string(),
-                contract.unwrap(),
-            );
+    fn visit_define(&mut self, define: &'a Define) {
+        if let (Some(name), Some(contract)) = (
+            define.name.atom_identifier(),
+            function_contract(&define.body),
+        ) {
+            self.insert_contract(*name, contract, &define.body);
         }
     }
+
+    // define/contract attaches the contract with a `set!` after the definition
+    fn visit_set(&mut self, s: &'a Set) {
+        if let (Some(name), Some(contract)) =
+            (s.variable.atom_identifier(), function_contract(&s.expr))
+        {
+            self.insert_contract(*name, contract, &s.expr);
+        }
+    }
+
+    // Only the top level defines globals
+    fn visit_lambda_function(&mut self, _lambda_function: &'a LambdaFunction) {}
+
+    fn visit_let(&mut self, _l: &'a Let) {}
+}
+
+// What the checker learned about a function defined with define/contract
+#[derive(Debug)]
+struct ContractProof {
+    // Whether the signature is exactly what the contract checks
+    exact: bool,
+    // Whether the body and every call site so far satisfy the signature
+    proven: bool,
+    // Whether the function is used as a value, exported or mutated, in which case
+    // it could be called with anything
+    escapes: bool,
 }
 
 pub struct ContractChecker<'a> {
     global_contract_info: GlobalContractCollector<'a>,
-    scope_map: ScopeMap<String, TypeInfo>,
-    inferred_globals: HashMap<String, TypeInfo>,
+    scope_map: ScopeMap<InternedString, TypeInfo>,
+    inferred_globals: HashMap<InternedString, TypeInfo>,
+    proofs: HashMap<InternedString, ContractProof>,
 }
 
 impl<'a> std::fmt::Debug for ContractChecker<'a> {
@@ -328,42 +645,228 @@ impl<'a> std::fmt::Debug for ContractChecker<'a> {
                 "scope_map",
                 &self.scope_map.iter_top().collect::<HashMap<_, _>>(),
             )
+            .field("inferred_globals", &self.inferred_globals)
+            .field("proofs", &self.proofs)
             .finish()
     }
 }
 
 impl<'a> ContractChecker<'a> {
-    pub fn new(global_contract_info: GlobalContractCollector<'a>) -> Self {
+    pub fn new(
+        global_contract_info: GlobalContractCollector<'a>,
+        exported: &HashSet<InternedString>,
+    ) -> Self {
+        let proofs = global_contract_info
+            .contracts
+            .iter()
+            .map(|(name, contract)| {
+                let proof = ContractProof {
+                    exact: contract.is_exact(&global_contract_info.structs),
+                    proven: true,
+                    escapes: exported.contains(name),
+                };
+
+                (*name, proof)
+            })
+            .collect();
+
         ContractChecker {
             global_contract_info,
             scope_map: ScopeMap::default(),
             inferred_globals: HashMap::default(),
+            proofs,
         }
     }
 
     pub fn check(&mut self, exprs: impl IntoIterator<Item = &'a ExprKind>) -> Result<()> {
+        let exprs = exprs.into_iter().collect::<Vec<_>>();
+
+        // Globals can be referred to before they are defined
+        for expr in &exprs {
+            self.declare_globals(expr);
+        }
+
         for expr in exprs {
-            let type_checked = self.visit(expr)?;
-            println!("{type_checked:#?}");
+            self.visit(expr)?;
         }
+
         Ok(())
     }
 
-    // TODO -> have a way to make sure that inferred globals can be refined in some capacity if
-    // more information can be applied to them
-    pub fn get_ident(&self, ident: &str) -> Option<TypeInfo> {
+    fn declare_globals(&mut self, expr: &ExprKind) {
+        match expr {
+            ExprKind::Begin(b) => b.exprs.iter().for_each(|x| self.declare_globals(x)),
+            ExprKind::Define(d) => {
+                if let Some(name) = d.name.atom_identifier() {
+                    self.inferred_globals
+                        .entry(*name)
+                        .or_insert(TypeInfo::Unknown);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn get_ident(&self, ident: &InternedString) -> Option<TypeInfo> {
         self.scope_map
             .get(ident)
             .cloned()
-            .or_else(|| self.global_contract_info.get(ident))
+            .or_else(|| self.global_contract_info.get_defined(ident))
             .or_else(|| self.inferred_globals.get(ident).cloned())
+            .or_else(|| self.global_contract_info.built_ins.get(ident).cloned())
+    }
+
+    pub fn local_ident_exists(&self, ident: &InternedString) -> bool {
+        self.scope_map.contains_key(ident)
+    }
+
+    // Whether `ident` refers to the built in, rather than something the module defines
+    fn is_built_in(&self, ident: &InternedString) -> bool {
+        !self.local_ident_exists(ident)
+            && self.global_contract_info.get_defined(ident).is_none()
+            && !self.inferred_globals.contains_key(ident)
+    }
+
+    // The scope map starts out with a single layer, which nothing is defined in
+    fn is_top_level(&self) -> bool {
+        self.scope_map.depth() == 1
+    }
+
+    fn lookup(&self, ident: &InternedString) -> TypeInfo {
+        self.get_ident(ident).unwrap_or(TypeInfo::Unknown)
+    }
+
+    fn bind(&mut self, name: InternedString, ty: TypeInfo) {
+        if self.is_top_level() {
+            self.inferred_globals.insert(name, ty);
+        } else {
+            self.scope_map.define(name, ty);
+        }
+    }
+
+    // A reference to a contracted function outside of a call means it can be called with anything
+    fn mark_escaped(&mut self, ident: &InternedString) {
+        if !self.local_ident_exists(ident) {
+            if let Some(proof) = self.proofs.get_mut(ident) {
+                proof.escapes = true;
+            }
+        }
+    }
+
+    fn visit_refined(
+        &mut self,
+        expr: &ExprKind,
+        name: InternedString,
+        ty: TypeInfo,
+    ) -> Result<TypeInfo> {
+        self.scope_map.push_layer();
+        self.scope_map.define(name, ty);
+        let result = self.visit(expr);
+        self.scope_map.pop_layer();
+        result
+    }
+
+    // Finds tests of the form (pred? x) and (not (pred? x)), returning the variable, the type the
+    // predicate proves and whether the test is true when the predicate is
+    fn occurrence(&self, test: &ExprKind) -> Option<(InternedString, TypeInfo, bool)> {
+        let l = test.list()?;
+
+        match l.args.as_slice() {
+            [function, argument] => {
+                let function = function.atom_identifier()?;
+
+                if function.resolve() == "not" && self.is_built_in(function) {
+                    return self
+                        .occurrence(argument)
+                        .map(|(name, ty, positive)| (name, ty, !positive));
+                }
+
+                let argument = argument.atom_identifier()?;
+
+                if self.local_ident_exists(function) {
+                    return None;
+                }
+
+                match BaseTypeKind::from_predicate(function.resolve())
+                    .to_type_info(&self.global_contract_info.structs)
+                {
+                    TypeInfo::Any | TypeInfo::Unknown => None,
+                    ty => Some((*argument, ty, true)),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // Checks a function body against its signature, returning whether it was proven to return
+    // the right type
+    fn check_lambda(
+        &mut self,
+        name: &InternedString,
+        lambda: &LambdaFunction,
+        expected: &TypeInfo,
+    ) -> Result<bool> {
+        let fixed = if lambda.rest {
+            lambda.args.len() - 1
+        } else {
+            lambda.args.len()
+        };
+
+        let (params, rest, ret) = match expected {
+            TypeInfo::FixedArityFunction(params, ret) if !lambda.rest && fixed == params.len() => {
+                (params.as_slice(), None, ret)
+            }
+            TypeInfo::AnyArityFunction(rest, ret) if lambda.rest && fixed == 0 => {
+                (&[][..], Some(rest), ret)
+            }
+            TypeInfo::FixedArityFunction(..) | TypeInfo::AnyArityFunction(..) => {
+                stop!(ArityMismatch => format!("{name} takes {} arguments, but its type is {expected}", lambda.args.len()); lambda.location.span)
+            }
+            TypeInfo::All(_, t) => return self.check_lambda(name, lambda, t),
+            TypeInfo::Any | TypeInfo::Unknown => {
+                self.visit_lambda_function(lambda)?;
+                return Ok(false);
+            }
+            _ => {
+                stop!(TypeMismatch => format!("{name} was declared as {expected}, but it is a function"); lambda.location.span)
+            }
+        };
+
+        self.scope_map.push_layer();
+
+        for (arg, ty) in lambda.args.iter().zip(params) {
+            if let Some(arg) = arg.atom_identifier() {
+                self.scope_map.define(*arg, ty.clone());
+            }
+        }
+
+        if let (Some(arg), Some(ty)) = (lambda.args.last().and_then(|x| x.atom_identifier()), rest)
+        {
+            self.scope_map.define(*arg, TypeInfo::ListOf(ty.clone()));
+        }
+
+        let found = self.visit(&lambda.body);
+        self.scope_map.pop_layer();
+        let found = found?;
+
+        if !found.is_compatible_with(ret) {
+            stop!(TypeMismatch => format!("{name} should return {ret}, but its body is {found}"); get_span(&lambda.body));
+        }
+
+        Ok(found.is_subtype(ret))
     }
 
-    pub fn local_ident_exists(&self, ident: &str) -> bool {
-        self.scope_map.get(ident).is_some()
+    fn erasable(&self) -> HashSet<InternedString> {
+        self.proofs
+            .iter()
+            .filter(|(_, p)| p.exact && p.proven && !p.escapes)
+            .map(|(name, _)| *name)
+            .collect()
     }
 }
 
+// Dependent functions are compared by address, which is only used to dedupe unions
+#[allow(unpredictable_function_pointer_comparisons)]
 #[derive(Clone, PartialEq, Eq, Hash, Ord, PartialOrd)]
 pub enum TypeInfo {
     // We don't have enough information to say what this type is
@@ -390,6 +893,14 @@ pub enum TypeInfo {
     UnionOf(BTreeSet<TypeInfo>),
     // If the return value of a function must satisfy multiple contracts (and/c)
     IntersectionOf(BTreeSet<TypeInfo>),
+
+    Number,
+    HashOf(Box<TypeInfo>, Box<TypeInfo>),
+    // A struct declared in a typed module
+    Struct(InternedString),
+    // A type variable, bound by an enclosing `All`
+    Var(InternedString),
+    All(Vec<InternedString>, Box<TypeInfo>),
 }
 
 impl std::fmt::Debug for TypeInfo {
@@ -421,104 +932,468 @@ impl std::fmt::Debug for TypeInfo {
                 .finish(),
             Self::UnionOf(arg0) => f.debug_tuple("UnionOf").field(arg0).finish(),
             Self::IntersectionOf(arg0) => f.debug_tuple("IntersectionOf").field(arg0).finish(),
+            Self::Number => write!(f, "Number"),
+            Self::HashOf(arg0, arg1) => f.debug_tuple("HashOf").field(arg0).field(arg1).finish(),
+            Self::Struct(arg0) => f.debug_tuple("Struct").field(arg0).finish(),
+            Self::Var(arg0) => f.debug_tuple("Var").field(arg0).finish(),
+            Self::All(arg0, arg1) => f.debug_tuple("All").field(arg0).field(arg1).finish(),
+        }
+    }
+}
+
+// How types are written in declarations, which is also how they show up in type errors
+impl fmt::Display for TypeInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_all(
+            f: &mut fmt::Formatter<'_>,
+            types: impl IntoIterator<Item = impl fmt::Display>,
+        ) -> fmt::Result {
+            for t in types {
+                write!(f, " {t}")?;
+            }
+            Ok(())
+        }
+
+        match self {
+            // The checker doesn't tell the two apart when they're compared
+            TypeInfo::Unknown | TypeInfo::Any => write!(f, "Any"),
+            TypeInfo::Void => write!(f, "Void"),
+            TypeInfo::Int => write!(f, "Int"),
+            TypeInfo::Float => write!(f, "Float"),
+            TypeInfo::Boolean => write!(f, "Boolean"),
+            TypeInfo::Char => write!(f, "Char"),
+            TypeInfo::String => write!(f, "String"),
+            TypeInfo::Symbol => write!(f, "Symbol"),
+            TypeInfo::Number => write!(f, "Number"),
+            TypeInfo::ListOf(t) => write!(f, "(Listof {t})"),
+            TypeInfo::HashOf(k, v) => write!(f, "(Hashof {k} {v})"),
+            TypeInfo::FixedArityFunction(params, ret) => {
+                write!(f, "(->")?;
+                write_all(f, params)?;
+                write!(f, " {ret})")
+            }
+            TypeInfo::AnyArityFunction(rest, ret) => write!(f, "(-> {rest} * {ret})"),
+            TypeInfo::DependentFunction(_) => write!(f, "Procedure"),
+            TypeInfo::UnionOf(types) => {
+                write!(f, "(U")?;
+                write_all(f, types)?;
+                write!(f, ")")
+            }
+            TypeInfo::IntersectionOf(types) => {
+                write!(f, "(Intersection")?;
+                write_all(f, types)?;
+                write!(f, ")")
+            }
+            TypeInfo::Struct(name) | TypeInfo::Var(name) => write!(f, "{name}"),
+            TypeInfo::All(vars, t) => {
+                write!(f, "(All (")?;
+                for (i, var) in vars.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{var}")?;
+                }
+                write!(f, ") {t})")
+            }
         }
     }
 }
 
 impl TypeInfo {
-    // TODO -> this is almost assuredly wrong
+    /// Parses a type annotation, i.e. the `type` in `(: name type)`
+    fn parse(
+        expr: &ExprKind,
+        structs: &HashSet<InternedString>,
+        vars: &[InternedString],
+    ) -> Result<TypeInfo> {
+        if let Some(name) = expr.atom_identifier() {
+            return Ok(match name.resolve() {
+                "Any" => TypeInfo::Any,
+                "Int" | "Integer" => TypeInfo::Int,
+                "Float" => TypeInfo::Float,
+                "Number" => TypeInfo::Number,
+                "String" => TypeInfo::String,
+                "Boolean" => TypeInfo::Boolean,
+                "Char" => TypeInfo::Char,
+                "Symbol" => TypeInfo::Symbol,
+                "Void" => TypeInfo::Void,
+                _ if vars.contains(name) => TypeInfo::Var(*name),
+                _ if structs.contains(name) => TypeInfo::Struct(*name),
+                _ => stop!(TypeMismatch => format!("unknown type: {name}"); get_span(expr)),
+            });
+        }
+
+        let bad_type = || -> Result<TypeInfo> {
+            stop!(TypeMismatch => format!("malformed type: {expr}"); get_span(expr))
+        };
+
+        let l = match expr {
+            ExprKind::List(l) => l,
+            _ => return bad_type(),
+        };
+
+        let args = &l.args[1.min(l.args.len())..];
+        let parse = |t: &ExprKind| Self::parse(t, structs, vars);
+
+        match (l.first_ident().map(|x| x.resolve()), args) {
+            (Some("Listof"), [t]) => Ok(TypeInfo::ListOf(Box::new(parse(t)?))),
+            (Some("Hashof"), [k, v]) => {
+                Ok(TypeInfo::HashOf(Box::new(parse(k)?), Box::new(parse(v)?)))
+            }
+            (Some("U"), [_, ..]) => Ok(TypeInfo::union(
+                args.iter().map(parse).collect::<Result<Vec<_>>>()?,
+            )
+            .unwrap_or(TypeInfo::Unknown)),
+            (Some("->"), [rest, star, ret])
+                if star.atom_identifier().map(|x| x.resolve()) == Some("*") =>
+            {
+                Ok(TypeInfo::AnyArityFunction(
+                    Box::new(parse(rest)?),
+                    Box::new(parse(ret)?),
+                ))
+            }
+            (Some("->"), [params @ .., ret]) => Ok(TypeInfo::FixedArityFunction(
+                params.iter().map(parse).collect::<Result<_>>()?,
+                Box::new(parse(ret)?),
+            )),
+            (Some("All"), [ExprKind::List(bound), t]) => {
+                let mut bound_vars = Vec::new();
+                for var in bound.iter() {
+                    match var.atom_identifier() {
+                        Some(var) => bound_vars.push(*var),
+                        None => return bad_type(),
+                    }
+                }
+
+                let mut in_scope = vars.to_vec();
+                in_scope.extend(bound_vars.iter().copied());
+
+                Ok(TypeInfo::All(
+                    bound_vars,
+                    Box::new(Self::parse(t, structs, &in_scope)?),
+                ))
+            }
+            _ => bad_type(),
+        }
+    }
+
+    /// Whether every value of `self` is a value of `other`
+    fn is_subtype(&self, other: &TypeInfo) -> bool {
+        match (self, other) {
+            (_, TypeInfo::Any | TypeInfo::Unknown) => true,
+            (left, right) if left == right => true,
+            (TypeInfo::UnionOf(types), _) => types.iter().all(|t| t.is_subtype(other)),
+            (_, TypeInfo::UnionOf(types)) => types.iter().any(|t| self.is_subtype(t)),
+            (TypeInfo::IntersectionOf(types), _) => types.iter().any(|t| t.is_subtype(other)),
+            (_, TypeInfo::IntersectionOf(types)) => types.iter().all(|t| self.is_subtype(t)),
+            (TypeInfo::Int | TypeInfo::Float, TypeInfo::Number) => true,
+            (TypeInfo::ListOf(left), TypeInfo::ListOf(right)) => left.is_subtype(right),
+            (TypeInfo::HashOf(lk, lv), TypeInfo::HashOf(rk, rv)) => {
+                lk.is_subtype(rk) && lv.is_subtype(rv)
+            }
+            (
+                TypeInfo::FixedArityFunction(left, left_ret),
+                TypeInfo::FixedArityFunction(right, right_ret),
+            ) => {
+                left.len() == right.len()
+                    && left.iter().zip(right).all(|(l, r)| r.is_subtype(l))
+                    && left_ret.is_subtype(right_ret)
+            }
+            (
+                TypeInfo::AnyArityFunction(left, left_ret),
+                TypeInfo::AnyArityFunction(right, right_ret),
+            ) => right.is_subtype(left) && left_ret.is_subtype(right_ret),
+            // Something taking any number of arguments can be used as if it took a fixed number
+            (
+                TypeInfo::AnyArityFunction(left, left_ret),
+                TypeInfo::FixedArityFunction(right, right_ret),
+            ) => right.iter().all(|r| r.is_subtype(left)) && left_ret.is_subtype(right_ret),
+            _ => false,
+        }
+    }
+
+    /// Whether a value of `self` could be used where `other` is expected. This is `is_subtype`,
+    /// except that `Any` goes both ways, since the checker doesn't know anything about it.
     pub fn is_compatible_with(&self, other: &TypeInfo) -> bool {
         match (self, other) {
-            (TypeInfo::Any, _) | (_, TypeInfo::Any) => true,
-            // TODO -> there might be a better way of doing this
-            (TypeInfo::UnionOf(l), TypeInfo::UnionOf(r)) => {
-                l.is_subset(r) || l.contains(&TypeInfo::Any) || r.contains(&TypeInfo::Any)
+            (TypeInfo::Any | TypeInfo::Unknown, _) | (_, TypeInfo::Any | TypeInfo::Unknown) => true,
+            (left, right) if left == right => true,
+            (TypeInfo::UnionOf(types), _) => types.iter().all(|t| t.is_compatible_with(other)),
+            (_, TypeInfo::UnionOf(types)) => types.iter().any(|t| self.is_compatible_with(t)),
+            (TypeInfo::IntersectionOf(types), _) => {
+                types.iter().any(|t| t.is_compatible_with(other))
+            }
+            (_, TypeInfo::IntersectionOf(types)) => {
+                types.iter().all(|t| self.is_compatible_with(t))
+            }
+            (TypeInfo::Int | TypeInfo::Float, TypeInfo::Number) => true,
+            (TypeInfo::ListOf(left), TypeInfo::ListOf(right)) => left.is_compatible_with(right),
+            (TypeInfo::HashOf(lk, lv), TypeInfo::HashOf(rk, rv)) => {
+                lk.is_compatible_with(rk) && lv.is_compatible_with(rv)
+            }
+            (
+                TypeInfo::FixedArityFunction(left, left_ret),
+                TypeInfo::FixedArityFunction(right, right_ret),
+            ) => {
+                left.len() == right.len()
+                    && left.iter().zip(right).all(|(l, r)| r.is_compatible_with(l))
+                    && left_ret.is_compatible_with(right_ret)
+            }
+            (
+                TypeInfo::AnyArityFunction(left, left_ret),
+                TypeInfo::AnyArityFunction(right, right_ret),
+            ) => right.is_compatible_with(left) && left_ret.is_compatible_with(right_ret),
+            (
+                TypeInfo::AnyArityFunction(left, left_ret),
+                TypeInfo::FixedArityFunction(right, right_ret),
+            ) => {
+                right.iter().all(|r| r.is_compatible_with(left))
+                    && left_ret.is_compatible_with(right_ret)
+            }
+            // There's no telling what these return until they're called
+            (
+                TypeInfo::DependentFunction(_),
+                TypeInfo::FixedArityFunction(..)
+                | TypeInfo::AnyArityFunction(..)
+                | TypeInfo::DependentFunction(_),
+            ) => true,
+            (TypeInfo::All(_, left), _) => left.is_compatible_with(other),
+            (_, TypeInfo::All(_, right)) => self.is_compatible_with(right),
+            _ => false,
+        }
+    }
 
-                // l == r
+    /// The smallest type containing both `self` and `other`
+    fn join(&self, other: &TypeInfo) -> TypeInfo {
+        if self.is_subtype(other) {
+            return other.clone();
+        }
+
+        if other.is_subtype(self) {
+            return self.clone();
+        }
+
+        let mut types = BTreeSet::new();
+
+        for t in [self, other] {
+            match t {
+                TypeInfo::UnionOf(members) => types.extend(members.iter().cloned()),
+                t => {
+                    types.insert(t.clone());
+                }
             }
-            (value, TypeInfo::UnionOf(set)) => set.contains(value) || set.contains(&TypeInfo::Any),
-            (TypeInfo::UnionOf(set), value) => set.contains(value) || set.contains(&TypeInfo::Any),
-            (TypeInfo::Unknown, _) | (_, TypeInfo::Unknown) => true,
-            (TypeInfo::ListOf(l), TypeInfo::ListOf(r)) => l.is_compatible_with(r),
-            (left, right) => left == right,
         }
+
+        TypeInfo::UnionOf(types)
     }
-}
 
-#[test]
-fn test_list_composition() {
-    let left = TypeInfo::ListOf(Box::new(TypeInfo::UnionOf(
-        vec![TypeInfo::Any].into_iter().collect(),
-    )));
+    fn union(types: impl IntoIterator<Item = TypeInfo>) -> Option<TypeInfo> {
+        types.into_iter().reduce(|left, right| left.join(&right))
+    }
 
-    let right = TypeInfo::ListOf(Box::new(TypeInfo::UnionOf(
-        vec![TypeInfo::Any, TypeInfo::String].into_iter().collect(),
-    )));
+    /// The type of a value of type `self` once `predicate` has returned true for it
+    fn restrict(&self, predicate: &TypeInfo) -> TypeInfo {
+        match self {
+            TypeInfo::Any | TypeInfo::Unknown => predicate.clone(),
+            TypeInfo::UnionOf(types) => {
+                TypeInfo::union(types.iter().filter(|t| t.is_subtype(predicate)).cloned())
+                    .unwrap_or_else(|| predicate.clone())
+            }
+            t if t.is_subtype(predicate) => t.clone(),
+            _ => predicate.clone(),
+        }
+    }
 
-    println!("{}", left.is_compatible_with(&right));
+    /// The type of a value of type `self` once `predicate` has returned false for it
+    fn remove(&self, predicate: &TypeInfo) -> TypeInfo {
+        match self {
+            TypeInfo::UnionOf(types) => {
+                TypeInfo::union(types.iter().filter(|t| !t.is_subtype(predicate)).cloned())
+                    .unwrap_or_else(|| self.clone())
+            }
+            t => t.clone(),
+        }
+    }
 
-    let left = TypeInfo::ListOf(Box::new(TypeInfo::String));
+    // Binds the type variables in `self` by matching it against the type of an argument
+    fn unify(
+        &self,
+        argument: &TypeInfo,
+        vars: &[InternedString],
+        bindings: &mut HashMap<InternedString, TypeInfo>,
+    ) {
+        match (self, argument) {
+            (TypeInfo::Var(var), _) if vars.contains(var) => {
+                let bound = match bindings.get(var) {
+                    Some(bound) => bound.join(argument),
+                    None => argument.clone(),
+                };
+
+                bindings.insert(*var, bound);
+            }
+            (TypeInfo::ListOf(param), TypeInfo::ListOf(argument)) => {
+                param.unify(argument, vars, bindings)
+            }
+            (TypeInfo::HashOf(pk, pv), TypeInfo::HashOf(ak, av)) => {
+                pk.unify(ak, vars, bindings);
+                pv.unify(av, vars, bindings);
+            }
+            (
+                TypeInfo::FixedArityFunction(params, ret),
+                TypeInfo::FixedArityFunction(arguments, argument_ret),
+            ) => {
+                for (p, a) in params.iter().zip(arguments) {
+                    p.unify(a, vars, bindings);
+                }
+                ret.unify(argument_ret, vars, bindings);
+            }
+            (
+                TypeInfo::AnyArityFunction(param, ret),
+                TypeInfo::AnyArityFunction(argument, argument_ret),
+            ) => {
+                param.unify(argument, vars, bindings);
+                ret.unify(argument_ret, vars, bindings);
+            }
+            _ => {}
+        }
+    }
 
-    let right = TypeInfo::ListOf(Box::new(TypeInfo::UnionOf(
-        vec![TypeInfo::Any, TypeInfo::String].into_iter().collect(),
-    )));
+    // Replaces the type variables in `self` with what they were bound to, or `Unknown` if
+    // nothing was
+    fn substitute(
+        &self,
+        vars: &[InternedString],
+        bindings: &HashMap<InternedString, TypeInfo>,
+    ) -> TypeInfo {
+        let substitute = |t: &TypeInfo| t.substitute(vars, bindings);
 
-    println!("{}", left.is_compatible_with(&right));
+        match self {
+            TypeInfo::Var(var) if vars.contains(var) => {
+                bindings.get(var).cloned().unwrap_or(TypeInfo::Unknown)
+            }
+            TypeInfo::ListOf(t) => TypeInfo::ListOf(Box::new(substitute(t))),
+            TypeInfo::HashOf(k, v) => {
+                TypeInfo::HashOf(Box::new(substitute(k)), Box::new(substitute(v)))
+            }
+            TypeInfo::UnionOf(types) => {
+                TypeInfo::union(types.iter().map(substitute)).unwrap_or(TypeInfo::Unknown)
+            }
+            TypeInfo::IntersectionOf(types) => {
+                TypeInfo::IntersectionOf(types.iter().map(substitute).collect())
+            }
+            TypeInfo::FixedArityFunction(params, ret) => TypeInfo::FixedArityFunction(
+                params.iter().map(substitute).collect(),
+                Box::new(substitute(ret)),
+            ),
+            TypeInfo::AnyArityFunction(param, ret) => {
+                TypeInfo::AnyArityFunction(Box::new(substitute(param)), Box::new(substitute(ret)))
+            }
+            t => t.clone(),
+        }
+    }
 }
 
 impl<'a> VisitorMut for ContractChecker<'a> {
     type Output = Result<TypeInfo>;
 
-    fn visit_if(&mut self, f: &crate::parser::ast::If) -> Self::Output {
+    fn visit_if(&mut self, f: &If) -> Self::Output {
         self.visit(&f.test_expr)?;
 
-        let then_expr = self.visit(&f.then_expr)?;
-        let else_expr = self.visit(&f.else_expr)?;
+        let (then_expr, else_expr) = match self.occurrence(&f.test_expr) {
+            Some((name, predicate, positive)) => {
+                let current = self.lookup(&name);
+                let (matched, unmatched) =
+                    (current.restrict(&predicate), current.remove(&predicate));
+                let (then_type, else_type) = if positive {
+                    (matched, unmatched)
+                } else {
+                    (unmatched, matched)
+                };
+
+                (
+                    self.visit_refined(&f.then_expr, name, then_type)?,
+                    self.visit_refined(&f.else_expr, name, else_type)?,
+                )
+            }
+            None => (self.visit(&f.then_expr)?, self.visit(&f.else_expr)?),
+        };
 
-        if then_expr == else_expr {
-            Ok(else_expr)
-        } else {
-            Ok(TypeInfo::UnionOf([then_expr, else_expr].into()))
-        }
+        Ok(then_expr.join(&else_expr))
     }
 
-    fn visit_define(&mut self, define: &crate::parser::ast::Define) -> Self::Output {
-        // self.visit(&define.name)?;
+    fn visit_define(&mut self, define: &Define) -> Self::Output {
+        let name = match define.name.atom_identifier() {
+            Some(name) => *name,
+            None => {
+                self.visit(&define.body)?;
+                return Ok(TypeInfo::Void);
+            }
+        };
+
+        if !self.is_top_level() {
+            // Bind the name first, so that the definition can refer to itself
+            self.bind(name, TypeInfo::Unknown);
+            let ty = self.visit(&define.body)?;
+            self.bind(name, ty);
+            return Ok(TypeInfo::Void);
+        }
+
+        let expected = self
+            .global_contract_info
+            .contract(&name)
+            .or_else(|| self.global_contract_info.declared.get(&name).cloned());
+
+        match (expected, &define.body) {
+            (Some(expected), ExprKind::LambdaFunction(lambda)) => {
+                let proven = self.check_lambda(&name, lambda, &expected)?;
+
+                if let Some(proof) = self.proofs.get_mut(&name) {
+                    proof.proven &= proven;
+                }
+            }
+            (Some(expected), body) => {
+                let found = self.visit(body)?;
+
+                if !found.is_compatible_with(&expected) {
+                    stop!(TypeMismatch => format!("{name} was declared as {expected}, but its value is {found}"); get_span(body));
+                }
+            }
+            (None, body) => {
+                let found = self.visit(body)?;
+                self.inferred_globals.insert(name, found);
+            }
+        }
 
-        self.visit(&define.body)
+        Ok(TypeInfo::Void)
     }
 
-    fn visit_lambda_function(
-        &mut self,
-        lambda_function: &crate::parser::ast::LambdaFunction,
-    ) -> Self::Output {
+    fn visit_lambda_function(&mut self, lambda_function: &LambdaFunction) -> Self::Output {
         self.scope_map.push_layer();
 
         for arg in lambda_function
             .args
             .iter()
-            .map(|x| x.atom_identifier().unwrap())
+            .filter_map(|x| x.atom_identifier())
         {
-            self.scope_map.define(arg.to_string(), TypeInfo::Unknown);
+            self.scope_map.define(*arg, TypeInfo::Unknown);
         }
 
-        let return_value = self.visit(&lambda_function.body)?;
-
-        // Ideally, by the time we get back to this position in the type checking, we've propagated information upwards given what we know about
-        // the usage of the arguments. If these conflict with the type given to the function, this can be
-        let args = lambda_function
-            .args
-            .iter()
-            .map(|x| self.get_ident(x.atom_identifier().unwrap()).unwrap())
-            .collect::<Vec<_>>();
+        let return_value = self.visit(&lambda_function.body);
 
         // We've exited the scope, we're done
         self.scope_map.pop_layer();
 
-        Ok(TypeInfo::FixedArityFunction(args, Box::new(return_value)))
+        if lambda_function.rest {
+            return Ok(TypeInfo::Unknown);
+        }
+
+        Ok(TypeInfo::FixedArityFunction(
+            vec![TypeInfo::Unknown; lambda_function.args.len()],
+            Box::new(return_value?),
+        ))
     }
 
-    fn visit_begin(&mut self, begin: &crate::parser::ast::Begin) -> Self::Output {
+    fn visit_begin(&mut self, begin: &Begin) -> Self::Output {
         let mut last = TypeInfo::Void;
         for expr in &begin.exprs {
             last = self.visit(expr)?;
@@ -526,235 +1401,476 @@ impl<'a> VisitorMut for ContractChecker<'a> {
         Ok(last)
     }
 
-    // TODO -> return should mark the return value
     fn visit_return(&mut self, r: &crate::parser::ast::Return) -> Self::Output {
-        self.visit(&r.expr)
+        self.visit(&r.expr)?;
+        Ok(TypeInfo::Unknown)
     }
 
-    // TODO -> eval quote to get value
-    fn visit_quote(&mut self, quote: &crate::parser::ast::Quote) -> Self::Output {
+    fn visit_quote(&mut self, quote: &Quote) -> Self::Output {
         match &quote.expr {
-            ExprKind::Atom(a) => match a.syn.ty {
-                TokenType::StringLiteral(_) => Ok(TypeInfo::String),
-                TokenType::IntegerLiteral(_) => Ok(TypeInfo::Int),
-                TokenType::NumberLiteral(_) => Ok(TypeInfo::Float),
-                TokenType::CharacterLiteral(_) => Ok(TypeInfo::Char),
-                TokenType::BooleanLiteral(_) => Ok(TypeInfo::Boolean),
-                TokenType::Identifier(_) => Ok(TypeInfo::Symbol),
-                _ => stop!(Generic => format!("ICE: Not sure what to do here: {}", a.syn.ty)),
-            },
-            // If we get back a generic quoted list, it can be any valid program
-            // which is effectively the union of all constants
-            // TODO -> this can also accept the list of any constants, which then
-            // makes the type definition recursive...
-            ExprKind::List(_) => Ok(TypeInfo::ListOf(Box::new(TypeInfo::UnionOf({
-                let mut hs = BTreeSet::new();
-                hs.insert(TypeInfo::String);
-                hs.insert(TypeInfo::Int);
-                hs.insert(TypeInfo::Float);
-                hs.insert(TypeInfo::Char);
-                hs.insert(TypeInfo::Boolean);
-                hs.insert(TypeInfo::Symbol);
-                // This _should_ be a recursive case on the list of self
-                // but I don't currently have a way to resolve recursive type references
-                hs.insert(TypeInfo::ListOf(Box::new(TypeInfo::Any)));
-                hs
-            })))),
-            _ => stop!(TypeMismatch => "this shouldn't work"),
+            ExprKind::Atom(a) if matches!(a.syn.ty, TokenType::Identifier(_)) => {
+                Ok(TypeInfo::Symbol)
+            }
+            ExprKind::List(_) => Ok(TypeInfo::ListOf(Box::new(TypeInfo::Unknown))),
+            expr => self.visit(expr),
         }
     }
 
     fn visit_macro(&mut self, _m: &crate::parser::ast::Macro) -> Self::Output {
-        panic!("Unexpected macro")
-    }
-
-    // TODO - resolve identifiers with a scope map
-    fn visit_atom(&mut self, a: &crate::parser::ast::Atom) -> Self::Output {
-        match &a.syn.ty {
-            TokenType::Identifier(a) => Ok(self.get_ident(a.as_str()).unwrap_or(TypeInfo::Unknown)),
-            TokenType::StringLiteral(_) => Ok(TypeInfo::String),
-            TokenType::IntegerLiteral(_) => Ok(TypeInfo::Int),
-            TokenType::NumberLiteral(_) => Ok(TypeInfo::Float),
-            TokenType::CharacterLiteral(_) => Ok(TypeInfo::Char),
-            TokenType::BooleanLiteral(_) => Ok(TypeInfo::Boolean),
-            _ => stop!(TypeMismatch => "Unexpected token type"),
-        }
-    }
-
-    fn visit_list(&mut self, l: &crate::parser::ast::List) -> Self::Output {
-        let function_type = self.visit(&l.args[0])?;
-        let expected_arity = l.args.len() - 1;
-
-        println!("Type checking this expression: {l}");
-        println!("Function type: {function_type:?}");
-
-        match &function_type {
-            TypeInfo::Unknown => {
-                // If we're in this situation we don't know what this function is
-                // we can attempt to infer what the corresponding type is based on the types of the argument
-                let argument_types = l.args[1..]
-                    .iter()
-                    .map(|x| self.visit(x))
-                    .collect::<Result<Vec<_>>>()?;
-
-                // In the case where we are calling a function with an unknown return type, propagate an unknown
-                // to try to continue inference
-                let return_type = TypeInfo::Unknown;
-
-                // Inferred function type at this node
-                // TODO -> this should be registered into the inferred globals if necessary
-                let inferred_type =
-                    TypeInfo::FixedArityFunction(argument_types, Box::new(return_type.clone()));
-
-                // TODO: this won't work given out set! works...
-                // might have to come up with some sort of scheme to allow set! type checking if theres a way to
-                // unify the usage with a dummy type var
-                if let Some(ident) = &l.args[0].atom_identifier() {
-                    if let Some(local) = self.scope_map.get_mut(*ident) {
-                        log::debug!(
-                            "Inferring local var: {} with type {:?} - old type: {:?}",
-                            ident,
-                            inferred_type,
-                            local
-                        );
-
-                        *local = inferred_type;
-                    } else {
-                        log::debug!(
-                            "Inferring global var: {} with type {:?}",
-                            ident,
-                            inferred_type
-                        );
-
-                        // TODO ->
-
-                        // self.inferred_globals
-                        //     .insert(ident.to_string(), inferred_type);
-                    }
-                }
+        Ok(TypeInfo::Void)
+    }
 
-                Ok(return_type)
+    fn visit_atom(&mut self, a: &Atom) -> Self::Output {
+        Ok(match &a.syn.ty {
+            TokenType::Identifier(ident) => {
+                self.mark_escaped(ident);
+                self.lookup(ident)
             }
+            TokenType::StringLiteral(_) => TypeInfo::String,
+            TokenType::IntegerLiteral(_) => TypeInfo::Int,
+            TokenType::NumberLiteral(_) => TypeInfo::Float,
+            TokenType::CharacterLiteral(_) => TypeInfo::Char,
+            TokenType::BooleanLiteral(_) => TypeInfo::Boolean,
+            _ => TypeInfo::Unknown,
+        })
+    }
 
-            TypeInfo::DependentFunction(func) => {
-                let argument_types = l.args[1..]
-                    .iter()
-                    .map(|x| self.visit(x))
-                    .collect::<Result<Vec<_>>>()?;
+    fn visit_list(&mut self, l: &List) -> Self::Output {
+        let (operator, args) = match l.args.split_first() {
+            Some(parts) => parts,
+            None => return Ok(TypeInfo::ListOf(Box::new(TypeInfo::Unknown))),
+        };
 
-                let return_type = func(argument_types);
+        let name = operator.atom_identifier().copied();
 
-                Ok(return_type)
-            }
+        // Struct definitions haven't been lowered yet, their declarations cover them
+        if name.map(|x| x.resolve() == "struct" && self.is_built_in(&x)) == Some(true) {
+            return Ok(TypeInfo::Void);
+        }
 
-            TypeInfo::AnyArityFunction(pre, post) => {
-                if l.args.len() == 1 {
-                    return Ok(*(post.clone()));
-                }
+        let span = get_span(operator);
 
-                // Visit the children in order
-                for expr in &l.args[1..] {
-                    let mut found = self.visit(expr)?;
-
-                    // If we've found an unknown, we don't necessarily have enough information
-                    // to say what this type is. We attempt to resolve it by the inferring that the type
-                    // is the type expected by the function call. Later, this will be rejected if there is a
-                    // a mismatch.
-                    if matches!(found, TypeInfo::Unknown) {
-                        if let Some(ident) = expr.atom_identifier() {
-                            if let Some(local) = self.scope_map.get_mut(ident) {
-                                *local = *(pre.clone());
-                            } else {
-                                log::warn!("Unable to resolve reference to variable: {}", ident);
-                            }
-                            found = *(pre.clone());
-                        }
-                    }
+        let function_type = match name {
+            Some(name) => self.lookup(&name),
+            None => self.visit(operator)?,
+        };
 
-                    if !pre.is_compatible_with(&found) {
-                        stop!(ContractViolation => format!("type mismatch: expected {pre:?}, found {found:?}"))
-                    }
-                }
+        let mut argument_types = Vec::with_capacity(args.len());
+        for arg in args {
+            argument_types.push(self.visit(arg)?);
+        }
 
-                Ok(*(post.clone()))
+        if let Some(name) = name.filter(|x| self.is_built_in(x)) {
+            if matches!(name.resolve(), "+" | "-" | "*") {
+                return arithmetic(&name, &argument_types, args);
             }
+        }
+
+        let (vars, function_type) = match &function_type {
+            TypeInfo::All(vars, t) => (vars.as_slice(), t.as_ref()),
+            t => (&[][..], t),
+        };
+
+        let display_name = operator.to_string();
+
+        let (params, post) = match function_type {
             TypeInfo::FixedArityFunction(pre, post) => {
-                let found_arity = pre.len();
-                if expected_arity != found_arity {
-                    stop!(ArityMismatch => format!("Function application mismatched with expected - function expected {found_arity} arguments but found {expected_arity}"))
+                if pre.len() != argument_types.len() {
+                    stop!(ArityMismatch => format!("{display_name} expects {} arguments, found {}", pre.len(), argument_types.len()); span);
                 }
 
-                if l.args.len() == 1 {
-                    return Ok(*(post.clone()));
-                }
+                (pre.iter().collect::<Vec<_>>(), post)
+            }
+            TypeInfo::AnyArityFunction(pre, post) => {
+                (vec![pre.as_ref(); argument_types.len()], post)
+            }
+            TypeInfo::DependentFunction(func) => return Ok(func(argument_types)),
+            TypeInfo::Any | TypeInfo::Unknown => return Ok(TypeInfo::Unknown),
+            other => {
+                stop!(TypeMismatch => format!("cannot call {operator}, which is {other}"); span)
+            }
+        };
 
-                let visited = l.args[1..]
-                    .iter()
-                    .map(|x| self.visit(x))
-                    .collect::<Result<Vec<_>>>()?;
+        let mut bindings = HashMap::new();
 
-                for ((expected, mut found), unvisited) in
-                    pre.iter().zip(visited).zip(l.args[1..].iter())
-                {
-                    println!("Found argument type: {found:?}");
-
-                    // If we've found an unknown, we don't necessarily have enough information
-                    // to say what this type is. We attempt to resolve it by the inferring that the type
-                    // is the type expected by the function call. Later, this will be rejected if there is a
-                    // a mismatch.
-                    if matches!(found, TypeInfo::Unknown) {
-                        if let Some(ident) = unvisited.atom_identifier() {
-                            if let Some(local) = self.scope_map.get_mut(ident) {
-                                *local = expected.clone();
-                            } else {
-                                log::warn!("Unable to resolve reference to variable: {}", ident);
-                            }
-                            found = expected.clone();
-                        }
-                    }
+        for (param, found) in params.iter().zip(&argument_types) {
+            param.unify(found, vars, &mut bindings);
+        }
 
-                    if !expected.is_compatible_with(&found) {
-                        stop!(ContractViolation => format!("type mismatch: expected {expected:?}, found {found:?}"))
-                    }
-                }
+        let mut proven = true;
+
+        for ((param, found), arg) in params.iter().zip(&argument_types).zip(args) {
+            let expected = param.substitute(vars, &bindings);
 
-                Ok(*(post.clone()))
+            if !found.is_compatible_with(&expected) {
+                stop!(TypeMismatch => format!("{display_name} expects {expected}, found {found}"); get_span(arg));
             }
-            _ => {
-                stop!(TypeMismatch => format!("Function application not a procedure, expected a function, found: {function_type:?}"))
+
+            proven &= found.is_subtype(&expected);
+        }
+
+        // Calling a contracted function doesn't let it escape, but the call has to be proven
+        if let Some(name) = name.filter(|x| !self.local_ident_exists(x)) {
+            if let Some(proof) = self.proofs.get_mut(&name) {
+                proof.proven &= proven;
             }
         }
+
+        Ok(post.substitute(vars, &bindings))
     }
 
     fn visit_syntax_rules(&mut self, _l: &crate::parser::ast::SyntaxRules) -> Self::Output {
-        panic!("Unexpected syntax rules")
+        Ok(TypeInfo::Void)
     }
 
-    fn visit_set(&mut self, s: &crate::parser::ast::Set) -> Self::Output {
-        let var_type = self.visit(&s.variable)?;
+    fn visit_set(&mut self, s: &Set) -> Self::Output {
+        let name = s.variable.atom_identifier().copied();
+
+        // This is the contract being attached, which is what the checker is looking at
+        if self.is_top_level()
+            && is_contract(&s.expr)
+            && name.map(|x| self.proofs.contains_key(&x)) == Some(true)
+        {
+            return Ok(TypeInfo::Void);
+        }
+
         let assignment_type = self.visit(&s.expr)?;
 
         // In the case of (set! x 10)
         // x might have been defined to be any other type
-        //
-        if !assignment_type.is_compatible_with(&var_type) {
-            stop!(TypeMismatch => "set! expression is incompatible with type");
+        if let Some(name) = name {
+            self.mark_escaped(&name);
+
+            let var_type = self.lookup(&name);
+            if !assignment_type.is_compatible_with(&var_type) {
+                stop!(TypeMismatch => format!("cannot set {name}, which is {var_type}, to {assignment_type}"); s.location.span);
+            }
         }
 
-        Ok(assignment_type)
+        Ok(TypeInfo::Void)
     }
 
     fn visit_require(&mut self, _s: &crate::parser::ast::Require) -> Self::Output {
-        panic!("Unexpected require")
+        Ok(TypeInfo::Void)
     }
 
-    fn visit_let(&mut self, l: &crate::parser::ast::Let) -> Self::Output {
-        for binding in &l.bindings {
-            self.visit(&binding.1)?;
+    fn visit_let(&mut self, l: &Let) -> Self::Output {
+        let mut bindings = Vec::with_capacity(l.bindings.len());
+
+        for (binding, expr) in &l.bindings {
+            let ty = self.visit(expr)?;
+            if let Some(binding) = binding.atom_identifier() {
+                bindings.push((*binding, ty));
+            }
         }
 
-        self.visit(&l.body_expr)
+        self.scope_map.push_layer();
+
+        for (binding, ty) in bindings {
+            self.scope_map.define(binding, ty);
+        }
+
+        let body = self.visit(&l.body_expr);
+        self.scope_map.pop_layer();
+        body
     }
 }
 
-*/
+// The arithmetic operators keep integers as integers, which a signature can't say
+fn arithmetic(
+    name: &InternedString,
+    argument_types: &[TypeInfo],
+    args: &[ExprKind],
+) -> Result<TypeInfo> {
+    for (found, arg) in argument_types.iter().zip(args) {
+        if !found.is_compatible_with(&TypeInfo::Number) {
+            stop!(TypeMismatch => format!("{name} expects Number, found {found}"); get_span(arg));
+        }
+    }
+
+    Ok(
+        if argument_types
+            .iter()
+            .any(|t| matches!(t, TypeInfo::Any | TypeInfo::Unknown))
+        {
+            TypeInfo::Unknown
+        } else if argument_types.iter().all(|t| *t == TypeInfo::Int) {
+            TypeInfo::Int
+        } else if argument_types
+            .iter()
+            .all(|t| t.is_subtype(&TypeInfo::Float) || *t == TypeInfo::Int)
+        {
+            TypeInfo::Float
+        } else {
+            TypeInfo::Number
+        },
+    )
+}
+
+fn erase_contracts(exprs: &mut Vec<ExprKind>, erased: &HashSet<InternedString>) {
+    exprs.retain_mut(|expr| match expr {
+        ExprKind::Begin(b) => {
+            erase_contracts(&mut b.exprs, erased);
+            true
+        }
+        ExprKind::Set(s) => {
+            !is_contract(&s.expr)
+                || !s
+                    .variable
+                    .atom_identifier()
+                    .map(|x| erased.contains(x))
+                    .unwrap_or(false)
+        }
+        _ => true,
+    })
+}
+
+#[cfg(test)]
+mod contract_checker_tests {
+    use super::*;
+
+    use crate::rerrs::ErrorKind;
+    use crate::steel_vm::engine::Engine;
+
+    fn check(program: &str) -> Result<Vec<ExprKind>> {
+        let mut ast = Parser::parse(program)?;
+        let declarations = TypeDeclarations::extract(&mut ast)?;
+        check_typed_module(&mut ast, &declarations, &HashSet::new())?;
+        Ok(ast)
+    }
+
+    fn type_error(program: &str) -> String {
+        let err = check(program).unwrap_err();
+        assert!(
+            matches!(
+                err.kind(),
+                ErrorKind::TypeMismatch | ErrorKind::ArityMismatch
+            ),
+            "{err:?}"
+        );
+        err.to_string()
+    }
+
+    // What define/contract expands to
+    const ADD_ONE: &str = "
+        (begin
+          (define add-one (lambda (x) (+ x 1)))
+          (set! add-one (bind/c (make-function/c (make/c int? 'int?) (make/c int? 'int?)) add-one 'add-one)))
+    ";
+
+    #[test]
+    fn checks_declared_signatures() {
+        check("(: square (-> Int Int)) (define (square x) (* x x)) (square 10)").unwrap();
+
+        let err =
+            type_error(r#"(: square (-> Int Int)) (define (square x) (* x x)) (square "10")"#);
+        assert!(err.contains("square expects Int, found String"), "{err}");
+
+        let err =
+            type_error(r#"(: greet (-> String Int)) (define (greet x) (string-append "hi " x))"#);
+        assert!(
+            err.contains("greet should return Int, but its body is String"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn errors_carry_the_span_of_the_offending_expression() {
+        let program = r#"(: square (-> Int Int)) (define (square x) (* x x)) (square "10")"#;
+        let err = check(program).unwrap_err();
+        let span = err.span().unwrap();
+
+        assert_eq!(&program[span.start..span.end], r#""10""#);
+    }
+
+    #[test]
+    fn parametric_types_are_instantiated_at_the_call_site() {
+        check(
+            "(: total (-> (Listof Int) Int))
+             (define (total xs) (if (null? xs) 0 (+ (car xs) (total (cdr xs)))))
+             (total (list 1 2 3))",
+        )
+        .unwrap();
+
+        let err = type_error(
+            r#"(: total (-> (Listof Int) Int))
+               (define (total xs) (if (null? xs) 0 (+ (car xs) (total (cdr xs)))))
+               (total (list "a" "b"))"#,
+        );
+        assert!(
+            err.contains("expects (Listof Int), found (Listof String)"),
+            "{err}"
+        );
+
+        check(
+            r#"(: lookup (-> (Hashof String Int) String Int))
+               (define (lookup table key) (hash-get table key))"#,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn predicates_narrow_unions() {
+        check(
+            r#"(: describe (-> (U Int String) String))
+               (define (describe x) (if (int? x) (int->string x) x))"#,
+        )
+        .unwrap();
+
+        check(
+            r#"(: describe (-> (U Int String) String))
+               (define (describe x) (if (not (string? x)) (int->string x) x))"#,
+        )
+        .unwrap();
+
+        let err = type_error(
+            r#"(: describe (-> (U Int String) String))
+               (define (describe x) (int->string x))"#,
+        );
+        assert!(
+            err.contains("int->string expects Int, found (U Int String)"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn structs_are_types() {
+        check(
+            "(struct Point (x y))
+             (: Point (-> Int Int Point))
+             (: norm (-> Point Int))
+             (define (norm p) (+ (* (Point-x p) (Point-x p)) (* (Point-y p) (Point-y p))))
+             (norm (Point 1 2))",
+        )
+        .unwrap();
+
+        let err = type_error(
+            "(struct Point (x y))
+             (: norm (-> Point Int))
+             (define (norm p) 10)
+             (norm 10)",
+        );
+        assert!(err.contains("norm expects Point, found Int"), "{err}");
+    }
+
+    #[test]
+    fn contracts_proven_statically_are_erased() {
+        let ast = check(&format!("{ADD_ONE} (add-one 10)")).unwrap();
+        assert!(!ast.iter().any(|x| x.to_string().contains("bind/c")));
+
+        // Unknown arguments mean the contract still has to run
+        let ast = check(&format!("{ADD_ONE} (define (f y) (add-one y))")).unwrap();
+        assert!(ast.iter().any(|x| x.to_string().contains("bind/c")));
+
+        // As does letting the function escape
+        let ast = check(&format!("{ADD_ONE} (map add-one (list 1 2 3))")).unwrap();
+        assert!(ast.iter().any(|x| x.to_string().contains("bind/c")));
+
+        let err = type_error(&format!(r#"{ADD_ONE} (add-one "10")"#));
+        assert!(err.contains("add-one expects Int, found String"), "{err}");
+    }
+
+    #[test]
+    fn typed_modules_run() {
+        let mut engine = Engine::new();
+
+        engine
+            .compile_and_run_raw_program(
+                r#"#lang typed/steel
+                   (: total (-> (Listof Int) Int))
+                   (define (total xs) (if (null? xs) 0 (+ (car xs) (total (cdr xs)))))
+                   (define/contract (describe x)
+                     (->/c (or/c int? string?) string?)
+                     (if (int? x) (int->string x) x))
+                   (define result (describe (total (list 1 2 3))))"#,
+            )
+            .unwrap();
+
+        assert_eq!(engine.extract::<String>("result").unwrap(), "6");
+
+        let err = engine
+            .compile_and_run_raw_program(
+                r#"#lang typed/steel
+                   (define/contract (add-one x) (->/c int? int?) (+ x 1))
+                   (add-one "one")"#,
+            )
+            .unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+    }
+
+    #[test]
+    fn declarations_are_only_allowed_at_the_top_level() {
+        let err = type_error(
+            r#"(begin (: square (-> Int Int)) (define (square x) (* x x)))
+               (square "10")"#,
+        );
+        assert!(err.contains("square expects Int, found String"), "{err}");
+
+        let err = check("(define (f) (: x Int) 10)").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BadSyntax);
+        assert!(
+            err.to_string().contains("only allowed at the top level"),
+            "{err}"
+        );
+
+        // Quoted code isn't a declaration
+        check("(define declaration '(: x Int))").unwrap();
+    }
+
+    #[test]
+    fn main_module_contracts_are_kept() {
+        let mut engine = Engine::new();
+
+        engine
+            .compile_and_run_raw_program(
+                r#"#lang typed/steel
+                   (define/contract (add-one x) (->/c int? int?) (+ x 1))
+                   (add-one 10)"#,
+            )
+            .unwrap();
+
+        // Later programs on the same engine can still call it with anything
+        let err = engine
+            .compile_and_run_raw_program(r#"(add-one "one")"#)
+            .unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::ContractViolation);
+    }
+
+    #[test]
+    fn list_composition() {
+        let left = TypeInfo::ListOf(Box::new(TypeInfo::UnionOf(
+            vec![TypeInfo::Any].into_iter().collect(),
+        )));
+
+        let right = TypeInfo::ListOf(Box::new(TypeInfo::UnionOf(
+            vec![TypeInfo::Any, TypeInfo::String].into_iter().collect(),
+        )));
+
+        assert!(left.is_compatible_with(&right));
+
+        let left = TypeInfo::ListOf(Box::new(TypeInfo::String));
+        let right = TypeInfo::ListOf(Box::new(TypeInfo::UnionOf(
+            vec![TypeInfo::Int, TypeInfo::String].into_iter().collect(),
+        )));
+
+        assert!(left.is_compatible_with(&right));
+        assert!(left.is_subtype(&right));
+        assert!(!right.is_subtype(&left));
+    }
+
+    #[test]
+    fn untyped_modules_are_not_checked() {
+        let mut engine = Engine::new();
+
+        let err = engine
+            .compile_and_run_raw_program(
+                r#"(define/contract (add-one x) (->/c int? int?) (+ x 1))
+                   (add-one "one")"#,
+            )
+            .unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::ContractViolation);
+    }
+}
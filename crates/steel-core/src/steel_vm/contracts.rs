@@ -1,16 +1,25 @@
-use super::vm::VmCore;
+use super::{primitives, vm::VmCore};
 use crate::{
     gc::Gc,
     parser::span::Span,
+    primitives::{hashmaps, lists, VectorOperations},
+    rerrs::{ErrorKind, SteelErr},
     rvals::{Result, SteelVal},
     stop,
-    values::contracts::{
-        Contract, ContractType, ContractedFunction, DependentContract, FlatContract,
-        FunctionContract, FunctionKind,
+    values::{
+        contracts::{
+            CollectionContract, CollectionKind, CombinatorContract, CombinatorKind, Contract,
+            ContractType, ContractedCollection, ContractedFunction, DependentContract,
+            FlatContract, FunctionContract, FunctionKind, StructContract,
+        },
+        lazy_stream::LazyStream,
+        structs::UserDefinedStruct,
     },
 };
 
+use im_rc::{HashMap as ImmutableHashMap, Vector};
 use log::debug;
+use std::{cell::RefCell, collections::HashMap};
 
 impl ContractedFunction {
     pub fn apply(
@@ -39,9 +48,8 @@ impl FlatContract {
     pub fn apply(&self, arg: SteelVal, cur_inst_span: &Span, ctx: &mut VmCore) -> Result<()> {
         // TODO make this not clone the argument
         let output = match self.predicate() {
-            SteelVal::FuncV(func) => func(&[arg.clone()]).map_err(|x| x.set_span(*cur_inst_span)),
-            SteelVal::BoxedFunction(func) => {
-                func.func()(&[arg.clone()]).map_err(|x| x.set_span(*cur_inst_span))
+            predicate @ (SteelVal::FuncV(_) | SteelVal::BoxedFunction(_)) => {
+                call_native(predicate, &mut [arg.clone()], cur_inst_span, ctx)
             }
             SteelVal::Closure(closure) => ctx.call_with_one_arg(closure, arg.clone()),
             SteelVal::ContractedFunction(c) => c.apply(vec![arg.clone()], cur_inst_span, ctx),
@@ -58,6 +66,721 @@ impl FlatContract {
     }
 }
 
+/// Which side of a function contract a value is crossing
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum Position {
+    Domain,
+    Range,
+}
+
+/// The values given to each contract variable during a single application of a function contract
+pub(crate) type ParametricBindings = HashMap<usize, Vec<SteelVal>>;
+
+fn in_context(mut error: SteelErr, context: String) -> SteelErr {
+    error.append_message(&format!("\n    in: {context}"));
+    error
+}
+
+/// Values handed back through a contract variable must be the very ones it was given
+fn same_value(left: &SteelVal, right: &SteelVal) -> bool {
+    match left {
+        SteelVal::IntV(_)
+        | SteelVal::NumV(_)
+        | SteelVal::CharV(_)
+        | SteelVal::BoolV(_)
+        | SteelVal::Void => left == right,
+        _ => left.ptr_eq(right),
+    }
+}
+
+fn wrap_function(fc: &FunctionKind, value: SteelVal, name: &Option<String>) -> SteelVal {
+    match value {
+        SteelVal::ContractedFunction(contracted_function) => {
+            let mut pre_parent = contracted_function.contract.clone();
+            pre_parent.set_attachment_location(contracted_function.name.clone());
+
+            let mut fc = fc.clone();
+            fc.set_parent(Gc::new(pre_parent));
+            fc.set_attachment_location(contracted_function.name.clone());
+
+            ContractedFunction::new(fc, contracted_function.function.clone(), name.clone()).into()
+        }
+        _ => ContractedFunction::new(fc.clone(), value, name.clone()).into(),
+    }
+}
+
+impl ContractType {
+    /// Checks a value against the contract, returning the value to use in its place. Higher order
+    /// contracts wrap the value (or the pieces of it that are functions or streams), as do
+    /// collection contracts whose elements are checked as they are read. The rest hand back
+    /// the value untouched.
+    pub(crate) fn check(
+        &self,
+        value: SteelVal,
+        position: Position,
+        bindings: &mut ParametricBindings,
+        name: &Option<String>,
+        cur_inst_span: &Span,
+        ctx: &mut VmCore,
+    ) -> Result<SteelVal> {
+        match self {
+            Self::Flat(f) => {
+                f.apply(value.clone(), cur_inst_span, ctx)?;
+                Ok(value)
+            }
+            Self::Function(fc) => Ok(wrap_function(fc, value, name)),
+            Self::Struct(sc) => sc.check(value, position, bindings, name, cur_inst_span, ctx),
+            Self::Collection(cc) => cc.check(value, position, bindings, name, cur_inst_span, ctx),
+            Self::Combinator(cc) => cc.check(value, position, bindings, name, cur_inst_span, ctx),
+            Self::Parametric(pc) => {
+                let seen = bindings.entry(pc.id).or_default();
+
+                match position {
+                    Position::Domain => {
+                        seen.push(value.clone());
+                        Ok(value)
+                    }
+                    Position::Range if seen.iter().any(|x| same_value(x, &value)) => Ok(value),
+                    Position::Range => {
+                        stop!(ContractViolation => format!("the contract variable {} produced: {}, which is not a value it was given", pc.name, value); *cur_inst_span)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Like `check`, except that first order contracts are checked all the way down right away
+    /// instead of leaving the elements of collections to be checked as they are read.
+    fn check_now(
+        &self,
+        value: SteelVal,
+        position: Position,
+        bindings: &mut ParametricBindings,
+        name: &Option<String>,
+        cur_inst_span: &Span,
+        ctx: &mut VmCore,
+    ) -> Result<SteelVal> {
+        if self.is_first_order() {
+            self.validate(&value, cur_inst_span, ctx)?;
+            Ok(value)
+        } else {
+            self.check(value, position, bindings, name, cur_inst_span, ctx)
+        }
+    }
+
+    /// Whether the value satisfies a first order contract, for using the contract as a plain
+    /// predicate. Higher order contracts can't be decided by looking at a value, so they are refused.
+    pub(crate) fn accepts(
+        &self,
+        value: &SteelVal,
+        cur_inst_span: &Span,
+        ctx: &mut VmCore,
+    ) -> Result<bool> {
+        if !self.is_first_order() {
+            stop!(TypeMismatch => format!("only first order contracts can be used as predicates, found: {self}"); *cur_inst_span);
+        }
+
+        match self.validate(value, cur_inst_span, ctx) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::ContractViolation => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Checks a value against a first order contract, elements of collections included
+    fn validate(&self, value: &SteelVal, cur_inst_span: &Span, ctx: &mut VmCore) -> Result<()> {
+        match self {
+            Self::Flat(f) => f.apply(value.clone(), cur_inst_span, ctx),
+            Self::Struct(sc) => sc.validate(value, cur_inst_span, ctx),
+            Self::Collection(cc) => cc.validate(value, cur_inst_span, ctx),
+            Self::Combinator(cc) => cc.validate(value, cur_inst_span, ctx),
+            // Never first order, these only ever go through `check`
+            Self::Function(_) | Self::Parametric(_) => Ok(()),
+        }
+    }
+}
+
+impl CombinatorContract {
+    fn check(
+        &self,
+        value: SteelVal,
+        position: Position,
+        bindings: &mut ParametricBindings,
+        name: &Option<String>,
+        cur_inst_span: &Span,
+        ctx: &mut VmCore,
+    ) -> Result<SteelVal> {
+        match self.kind {
+            CombinatorKind::And => {
+                let mut value = value;
+
+                for (i, contract) in self.contracts.iter().enumerate() {
+                    value = contract
+                        .check(value, position, bindings, name, cur_inst_span, ctx)
+                        .map_err(|e| in_context(e, format!("conjunct {i} of {self}")))?;
+                }
+
+                Ok(value)
+            }
+            CombinatorKind::Or => {
+                let mut rejections = Vec::with_capacity(self.contracts.len());
+
+                // The first alternative to accept the value wins, so anything it bound
+                // to a contract variable is kept while the failed attempts are thrown away.
+                // Alternatives are checked in full, otherwise a collection contract would
+                // accept any collection of the right kind.
+                for contract in self.contracts.iter() {
+                    let mut attempt = bindings.clone();
+
+                    match contract.check_now(
+                        value.clone(),
+                        position,
+                        &mut attempt,
+                        name,
+                        cur_inst_span,
+                        ctx,
+                    ) {
+                        Ok(value) => {
+                            *bindings = attempt;
+                            return Ok(value);
+                        }
+                        Err(e) => rejections.push(format!("\n    {}: {e}", **contract)),
+                    }
+                }
+
+                stop!(ContractViolation => format!("none of the alternatives of {} accepted the value: {}{}", self, value, rejections.join("")); *cur_inst_span)
+            }
+        }
+    }
+
+    fn validate(&self, value: &SteelVal, cur_inst_span: &Span, ctx: &mut VmCore) -> Result<()> {
+        match self.kind {
+            CombinatorKind::And => {
+                for (i, contract) in self.contracts.iter().enumerate() {
+                    contract
+                        .validate(value, cur_inst_span, ctx)
+                        .map_err(|e| in_context(e, format!("conjunct {i} of {self}")))?;
+                }
+
+                Ok(())
+            }
+            CombinatorKind::Or => {
+                let mut rejections = Vec::with_capacity(self.contracts.len());
+
+                for contract in self.contracts.iter() {
+                    match contract.validate(value, cur_inst_span, ctx) {
+                        Ok(()) => return Ok(()),
+                        Err(e) => rejections.push(format!("\n    {}: {e}", **contract)),
+                    }
+                }
+
+                stop!(ContractViolation => format!("none of the alternatives of {} accepted the value: {}{}", self, value, rejections.join("")); *cur_inst_span)
+            }
+        }
+    }
+}
+
+impl StructContract {
+    fn check(
+        &self,
+        value: SteelVal,
+        position: Position,
+        bindings: &mut ParametricBindings,
+        name: &Option<String>,
+        cur_inst_span: &Span,
+        ctx: &mut VmCore,
+    ) -> Result<SteelVal> {
+        let instance = self.instance(&value, cur_inst_span)?;
+
+        let mut fields = Vec::with_capacity(self.fields.len());
+
+        // Fields are checked in full, since a field holding a lazily checked collection
+        // would mean copying the struct
+        for (i, (field, contract)) in instance.fields.iter().zip(self.fields.iter()).enumerate() {
+            fields.push(
+                contract
+                    .check_now(field.clone(), position, bindings, name, cur_inst_span, ctx)
+                    .map_err(|e| in_context(e, format!("field {i} of {self}")))?,
+            );
+        }
+
+        // Only copy the struct when a field had to be wrapped, so that instances checked
+        // against plain predicates keep their identity
+        if self.fields.iter().all(|x| x.is_first_order()) {
+            Ok(value)
+        } else {
+            let mut instance = instance;
+            instance.fields = fields.into_iter().collect();
+            Ok(SteelVal::CustomStruct(Gc::new(RefCell::new(instance))))
+        }
+    }
+
+    fn validate(&self, value: &SteelVal, cur_inst_span: &Span, ctx: &mut VmCore) -> Result<()> {
+        let instance = self.instance(value, cur_inst_span)?;
+
+        for (i, (field, contract)) in instance.fields.iter().zip(self.fields.iter()).enumerate() {
+            contract
+                .validate(field, cur_inst_span, ctx)
+                .map_err(|e| in_context(e, format!("field {i} of {self}")))?;
+        }
+
+        Ok(())
+    }
+
+    fn instance(&self, value: &SteelVal, cur_inst_span: &Span) -> Result<UserDefinedStruct> {
+        match value {
+            SteelVal::CustomStruct(s)
+                if s.borrow().name.resolve() == self.name
+                    && s.borrow().fields.len() == self.fields.len() =>
+            {
+                Ok(s.borrow().clone())
+            }
+            _ => {
+                stop!(ContractViolation => format!("{} expected an instance of {}, found: {}", self, self.name, value); *cur_inst_span)
+            }
+        }
+    }
+}
+
+impl CollectionContract {
+    fn check(
+        &self,
+        value: SteelVal,
+        position: Position,
+        bindings: &mut ParametricBindings,
+        name: &Option<String>,
+        cur_inst_span: &Span,
+        ctx: &mut VmCore,
+    ) -> Result<SteelVal> {
+        if self.is_lazy() {
+            let underlying = match &value {
+                SteelVal::ContractedCollection(c) => c.unchecked(),
+                value => value,
+            };
+
+            let empty = match (self.kind, underlying) {
+                (CollectionKind::List, SteelVal::ListV(l)) => l.is_empty(),
+                (CollectionKind::Vector, SteelVal::VectorV(v)) => v.is_empty(),
+                (CollectionKind::Hash, SteelVal::HashMapV(h)) => h.is_empty(),
+                _ => return Err(self.mismatch(&value, cur_inst_span)),
+            };
+
+            // Nothing to check later on in an empty collection
+            if empty {
+                return Ok(value);
+            }
+
+            let blame = match (position, name) {
+                (Position::Domain, Some(name)) => format!("the caller of {name}"),
+                (Position::Domain, None) => "the caller".to_string(),
+                (Position::Range, Some(name)) => format!("{name}, which broke its own contract"),
+                (Position::Range, None) => "the function, which broke its own contract".to_string(),
+            };
+
+            return Ok(ContractedCollection::new(self.clone(), value, name.clone(), blame).into());
+        }
+
+        // The elements get wrapped, which needs the plain collection
+        let value = match value {
+            SteelVal::ContractedCollection(c) => c.force(cur_inst_span, ctx)?,
+            value => value,
+        };
+
+        let output = match (self.kind, &value) {
+            (CollectionKind::List, SteelVal::ListV(l)) => {
+                let mut elements = Vec::with_capacity(l.len());
+
+                for (i, element) in l.iter().enumerate() {
+                    elements.push(
+                        self.element
+                            .check(
+                                element.clone(),
+                                position,
+                                bindings,
+                                name,
+                                cur_inst_span,
+                                ctx,
+                            )
+                            .map_err(|e| {
+                                in_context(e, format!("the element at index {i} of {self}"))
+                            })?,
+                    );
+                }
+
+                SteelVal::ListV(elements.into_iter().collect())
+            }
+            (CollectionKind::Vector, SteelVal::VectorV(v)) => {
+                let mut elements = Vector::new();
+
+                for (i, element) in v.iter().enumerate() {
+                    elements.push_back(
+                        self.element
+                            .check(
+                                element.clone(),
+                                position,
+                                bindings,
+                                name,
+                                cur_inst_span,
+                                ctx,
+                            )
+                            .map_err(|e| {
+                                in_context(e, format!("the element at index {i} of {self}"))
+                            })?,
+                    );
+                }
+
+                SteelVal::VectorV(Gc::new(elements))
+            }
+            (CollectionKind::Hash, SteelVal::HashMapV(h)) => {
+                let mut pairs = ImmutableHashMap::new();
+
+                for (key, element) in h.iter() {
+                    let key = match &self.key {
+                        Some(contract) => contract
+                            .check(key.clone(), position, bindings, name, cur_inst_span, ctx)
+                            .map_err(|e| in_context(e, format!("the key {key} of {self}")))?,
+                        None => key.clone(),
+                    };
+
+                    let element = self
+                        .element
+                        .check(
+                            element.clone(),
+                            position,
+                            bindings,
+                            name,
+                            cur_inst_span,
+                            ctx,
+                        )
+                        .map_err(|e| {
+                            in_context(e, format!("the value for the key {key} of {self}"))
+                        })?;
+
+                    pairs.insert(key, element);
+                }
+
+                SteelVal::HashMapV(Gc::new(pairs))
+            }
+            (CollectionKind::Stream, SteelVal::StreamV(s)) => {
+                if s.empty_stream {
+                    return Ok(value);
+                }
+
+                let initial_value = self
+                    .element
+                    .check(
+                        s.initial_value.clone(),
+                        position,
+                        bindings,
+                        name,
+                        cur_inst_span,
+                        ctx,
+                    )
+                    .map_err(|e| in_context(e, format!("an element of {self}")))?;
+
+                // The rest of the stream is checked as it gets forced, by putting the
+                // same contract on the range of the thunk that produces it
+                let rest = FunctionContract::new(
+                    Box::new([]),
+                    Gc::new(ContractType::Collection(self.clone())),
+                    None,
+                    None,
+                );
+
+                let stream_thunk = ContractedFunction::new(
+                    FunctionKind::Basic(rest),
+                    s.stream_thunk.clone(),
+                    name.clone(),
+                )
+                .into();
+
+                SteelVal::StreamV(Gc::new(LazyStream {
+                    initial_value,
+                    stream_thunk,
+                    empty_stream: false,
+                }))
+            }
+            _ => return Err(self.mismatch(&value, cur_inst_span)),
+        };
+
+        Ok(output)
+    }
+
+    /// Checks every element (and key) of the collection right away
+    fn validate(&self, value: &SteelVal, cur_inst_span: &Span, ctx: &mut VmCore) -> Result<()> {
+        let value = match value {
+            SteelVal::ContractedCollection(c) => c.force(cur_inst_span, ctx)?,
+            value => value.clone(),
+        };
+
+        match (self.kind, &value) {
+            (CollectionKind::List, SteelVal::ListV(l)) => {
+                for (i, element) in l.iter().enumerate() {
+                    self.element
+                        .validate(element, cur_inst_span, ctx)
+                        .map_err(|e| {
+                            in_context(e, format!("the element at index {i} of {self}"))
+                        })?;
+                }
+            }
+            (CollectionKind::Vector, SteelVal::VectorV(v)) => {
+                for (i, element) in v.iter().enumerate() {
+                    self.element
+                        .validate(element, cur_inst_span, ctx)
+                        .map_err(|e| {
+                            in_context(e, format!("the element at index {i} of {self}"))
+                        })?;
+                }
+            }
+            (CollectionKind::Hash, SteelVal::HashMapV(h)) => {
+                for (key, element) in h.iter() {
+                    if let Some(contract) = &self.key {
+                        contract
+                            .validate(key, cur_inst_span, ctx)
+                            .map_err(|e| in_context(e, format!("the key {key} of {self}")))?;
+                    }
+
+                    self.element
+                        .validate(element, cur_inst_span, ctx)
+                        .map_err(|e| {
+                            in_context(e, format!("the value for the key {key} of {self}"))
+                        })?;
+                }
+            }
+            _ => return Err(self.mismatch(&value, cur_inst_span)),
+        }
+
+        Ok(())
+    }
+
+    fn mismatch(&self, value: &SteelVal, cur_inst_span: &Span) -> SteelErr {
+        let expected = match self.kind {
+            CollectionKind::List => "a list",
+            CollectionKind::Vector => "a vector",
+            CollectionKind::Hash => "a hash map",
+            CollectionKind::Stream => "a stream",
+        };
+
+        throw!(ContractViolation => format!("{} expected {}, found: {}", self, expected, value); *cur_inst_span)(
+        )
+    }
+}
+
+/// The native functions that can be handed a lazily checked collection without checking all of it
+#[derive(Clone, Copy)]
+enum Access {
+    /// Looks at the shape of the collection, like its length, and never hands back an element
+    Shape,
+    /// Reads a single element, which is checked on its way out
+    Element,
+    /// Takes the tail of a list, which stays behind the contract
+    Tail,
+}
+
+impl Access {
+    /// Functions are told apart by their pointers. One that isn't recognized
+    /// gets the whole collection checked instead, which is slower but still correct.
+    fn of(func: &SteelVal) -> Option<Access> {
+        let shapes = [
+            SteelVal::FuncV(lists::steel_length),
+            SteelVal::FuncV(lists::steel_is_empty),
+            SteelVal::FuncV(lists::steel_pair),
+            SteelVal::FuncV(primitives::steel_listp),
+            SteelVal::FuncV(primitives::steel_vectorp),
+            SteelVal::FuncV(primitives::steel_hashp),
+            SteelVal::FuncV(hashmaps::steel_hash_length),
+            SteelVal::FuncV(hashmaps::steel_hash_contains),
+            VectorOperations::vec_length(),
+            VectorOperations::list_vec_null(),
+        ];
+
+        let elements = [
+            SteelVal::FuncV(lists::steel_car),
+            SteelVal::FuncV(lists::steel_first),
+            SteelVal::FuncV(lists::steel_list_ref),
+            SteelVal::FuncV(hashmaps::steel_hash_ref),
+            VectorOperations::vec_ref(),
+        ];
+
+        let tails = [lists::CDR, lists::REST];
+
+        if shapes.iter().any(|x| x.ptr_eq(func)) {
+            Some(Access::Shape)
+        } else if elements.iter().any(|x| x.ptr_eq(func)) {
+            Some(Access::Element)
+        } else if tails.iter().any(|x| x.ptr_eq(func)) {
+            Some(Access::Tail)
+        } else {
+            None
+        }
+    }
+}
+
+impl ContractedCollection {
+    /// Checks whatever hasn't been checked yet, and returns the plain collection underneath
+    pub(crate) fn force(&self, cur_inst_span: &Span, ctx: &mut VmCore) -> Result<SteelVal> {
+        if !self.checked.get() {
+            self.contract
+                .validate(&self.collection, cur_inst_span, ctx)
+                .map_err(|e| self.blame(e))?;
+
+            self.checked.set(true);
+        }
+
+        Ok(self.unchecked().clone())
+    }
+
+    /// Reads from the collection with one of the accessors, checking the result against the
+    /// contracts of every layer it passes through on the way out
+    fn read(
+        &self,
+        access: Access,
+        func: &SteelVal,
+        args: &mut [SteelVal],
+        cur_inst_span: &Span,
+        ctx: &mut VmCore,
+    ) -> Result<SteelVal> {
+        let output = match &self.collection {
+            SteelVal::ContractedCollection(inner) => {
+                inner.read(access, func, args, cur_inst_span, ctx)?
+            }
+            collection => {
+                args[0] = collection.clone();
+
+                match func {
+                    SteelVal::FuncV(f) => f(args),
+                    SteelVal::MutFunc(f) => f(args),
+                    _ => stop!(TypeMismatch => "expected a native function, found: {}", func),
+                }
+                .map_err(|x| x.set_span_if_none(*cur_inst_span))?
+            }
+        };
+
+        if self.checked.get() {
+            return Ok(output);
+        }
+
+        match access {
+            Access::Shape => Ok(output),
+            // Nothing left to check in an empty tail, which also keeps `null?` honest
+            Access::Tail if output.list().map(|x| x.is_empty()).unwrap_or_default() => Ok(output),
+            Access::Tail => Ok(ContractedCollection::new(
+                self.contract.clone(),
+                output,
+                self.name.clone(),
+                self.blame.clone(),
+            )
+            .into()),
+            Access::Element => {
+                let context = match (self.contract.kind, args.get(1)) {
+                    (CollectionKind::Hash, Some(key)) => {
+                        format!("the value for the key {key} of {}", self.contract)
+                    }
+                    (_, Some(index)) => {
+                        format!("the element at index {index} of {}", self.contract)
+                    }
+                    (_, None) => format!("the first element of {}", self.contract),
+                };
+
+                // Parametric contracts are never first order, so no bindings are needed here
+                let mut bindings = ParametricBindings::new();
+
+                // The key was found, so it is the same as the one in the map
+                if let (Some(contract), Some(key)) = (&self.contract.key, args.get(1)) {
+                    contract
+                        .check(
+                            key.clone(),
+                            Position::Domain,
+                            &mut bindings,
+                            &self.name,
+                            cur_inst_span,
+                            ctx,
+                        )
+                        .map_err(|e| {
+                            self.blame(in_context(e, format!("the key {key} of {}", self.contract)))
+                        })?;
+                }
+
+                self.contract
+                    .element
+                    .check(
+                        output,
+                        Position::Domain,
+                        &mut bindings,
+                        &self.name,
+                        cur_inst_span,
+                        ctx,
+                    )
+                    .map_err(|e| self.blame(in_context(e, context)))
+            }
+        }
+    }
+
+    fn blame(&self, mut error: SteelErr) -> SteelErr {
+        error.append_message(&format!("\n    blaming: {}", self.blame));
+        error
+    }
+}
+
+/// Native functions know nothing about lazily checked collections, so they fail when they are
+/// handed one. Only then are the arguments looked at, which keeps calls that succeed from paying
+/// for contracts. The accessors that read a single element check just what they read, anything
+/// else is called again once the collections in its arguments have been checked in full.
+#[cold]
+pub(crate) fn retry_with_contracted_collections(
+    func: &SteelVal,
+    args: &mut [SteelVal],
+    error: SteelErr,
+    cur_inst_span: &Span,
+    ctx: &mut VmCore,
+) -> Result<SteelVal> {
+    if !args
+        .iter()
+        .any(|x| matches!(x, SteelVal::ContractedCollection(_)))
+    {
+        return Err(error);
+    }
+
+    for arg in args.iter_mut().skip(1) {
+        if let SteelVal::ContractedCollection(c) = arg {
+            *arg = Gc::clone(c).force(cur_inst_span, ctx)?;
+        }
+    }
+
+    if let Some(SteelVal::ContractedCollection(c)) = args.first() {
+        let collection = Gc::clone(c);
+
+        match Access::of(func) {
+            Some(access) => return collection.read(access, func, args, cur_inst_span, ctx),
+            None => args[0] = collection.force(cur_inst_span, ctx)?,
+        }
+    }
+
+    call_native(func, args, cur_inst_span, ctx)
+}
+
+/// Calls a native function from inside of a contract
+fn call_native(
+    func: &SteelVal,
+    args: &mut [SteelVal],
+    cur_inst_span: &Span,
+    ctx: &mut VmCore,
+) -> Result<SteelVal> {
+    let result = match func {
+        SteelVal::FuncV(f) => f(args),
+        SteelVal::MutFunc(f) => f(args),
+        SteelVal::BoxedFunction(f) => ctx.call_native_function(f, args),
+        SteelVal::FutureFunc(f) => f(args).map(|x| SteelVal::FutureV(Gc::new(x))),
+        _ => {
+            stop!(TypeMismatch => format!("contract expected a function, found: {func:?}"); *cur_inst_span)
+        }
+    };
+
+    result
+        .or_else(|e| retry_with_contracted_collections(func, args, e, cur_inst_span, ctx))
+        .map_err(|x| x.set_span(*cur_inst_span))
+}
+
 /// Extension trait for the application of function contracts
 pub(crate) trait FunctionContractExt {
     fn apply(
@@ -96,6 +819,7 @@ impl FunctionContractExt for DependentContract {
         cur_inst_span: &Span,
         ctx: &mut VmCore,
     ) -> Result<SteelVal> {
+        let mut bindings = ParametricBindings::new();
         let mut verified_args: Vec<SteelVal> = Vec::new();
 
         for (i, (arg, dependent_pair)) in
@@ -122,22 +846,6 @@ impl FunctionContractExt for DependentContract {
             };
 
             match contract.as_ref() {
-                ContractType::Flat(f) => {
-                    debug!("applying flat contract in pre condition: {}", f.name);
-
-                    if let Err(e) = f.apply(arg.clone(), cur_inst_span, ctx) {
-                        debug!(
-                            "Blame locations: {:?}, {:?}",
-                            self.contract_attachment_location, name
-                        );
-
-                        let message = format!("This function call caused an error - it occured in the domain position: {}, with the contract: {}, {}, blaming: {:?} (callsite)", i, self, e, self.contract_attachment_location);
-
-                        stop!(ContractViolation => message; *cur_inst_span);
-                    }
-
-                    verified_args.push(arg.clone());
-                }
                 ContractType::Function(fc) => match arg {
                     SteelVal::ContractedFunction(contracted_function) => {
                         let mut pre_parent = contracted_function.contract.clone();
@@ -172,18 +880,41 @@ impl FunctionContractExt for DependentContract {
                         ContractedFunction::new(fc.clone(), arg.clone(), name.clone()).into(),
                     ),
                 },
+                contract => {
+                    debug!("applying contract in pre condition: {}", contract);
+
+                    match contract.check(
+                        arg.clone(),
+                        Position::Domain,
+                        &mut bindings,
+                        name,
+                        cur_inst_span,
+                        ctx,
+                    ) {
+                        Ok(arg) => verified_args.push(arg),
+                        Err(e) => {
+                            debug!(
+                                "Blame locations: {:?}, {:?}",
+                                self.contract_attachment_location, name
+                            );
+
+                            let message = match &self.boundary {
+                                Some(boundary) => format!("This function call caused an error - it occured in the domain position: {}, with the contract: {}, {}, blaming: {}", i, self, e, boundary.blame_domain()),
+                                None => format!("This function call caused an error - it occured in the domain position: {}, with the contract: {}, {}, blaming: {:?} (callsite)", i, self, e, self.contract_attachment_location),
+                            };
+
+                            stop!(ContractViolation => message; *cur_inst_span);
+                        }
+                    }
+                }
             }
         }
 
         let output = match function {
             SteelVal::Closure(function) => ctx.call_with_args(function, verified_args)?,
-            SteelVal::BoxedFunction(f) => {
-                f.func()(&verified_args).map_err(|x| x.set_span(*cur_inst_span))?
+            SteelVal::BoxedFunction(_) | SteelVal::FuncV(_) | SteelVal::FutureFunc(_) => {
+                call_native(function, &mut verified_args, cur_inst_span, ctx)?
             }
-            SteelVal::FuncV(f) => f(&verified_args).map_err(|x| x.set_span(*cur_inst_span))?,
-            SteelVal::FutureFunc(f) => SteelVal::FutureV(Gc::new(
-                f(&verified_args).map_err(|x| x.set_span(*cur_inst_span))?,
-            )),
             _ => {
                 todo!("Implement contract application for non bytecode values");
             }
@@ -211,41 +942,6 @@ impl FunctionContractExt for DependentContract {
         };
 
         match contract.as_ref() {
-            ContractType::Flat(f) => {
-                debug!("applying flat contract in post condition: {}", f.name);
-
-                if let Err(e) = f.apply(output.clone(), cur_inst_span, ctx) {
-                    debug!(
-                        "Blame locations: {:?}, {:?}",
-                        self.contract_attachment_location, name
-                    );
-
-                    debug!("Parent exists: {}", self.parent().is_some());
-
-                    let blame_location = if self.contract_attachment_location.is_none() {
-                        name
-                    } else {
-                        &self.contract_attachment_location
-                    };
-
-                    // TODO clean this up
-                    if let Some(blame_location) = blame_location {
-                        let error_message = format!("this function call resulted in an error - occured in the range position of this contract: {self} \n
-                        {e}
-                        blaming: {blame_location} - broke its own contract");
-
-                        stop!(ContractViolation => error_message; *cur_inst_span);
-                    } else {
-                        let error_message = format!("this function call resulted in an error - occured in the range position of this contract: {self} \n
-                        {e}
-                        blaming: None - broke its own contract");
-
-                        stop!(ContractViolation => error_message; *cur_inst_span);
-                    }
-                }
-
-                Ok(output)
-            }
             ContractType::Function(fc) => match output {
                 SteelVal::ContractedFunction(contracted_function) => {
                     let mut pre_parent = contracted_function.contract.clone();
@@ -277,6 +973,52 @@ impl FunctionContractExt for DependentContract {
 
                 _ => Ok(ContractedFunction::new(fc.clone(), output, name.clone()).into()),
             },
+            contract => {
+                debug!("applying contract in post condition: {}", contract);
+
+                match contract.check(
+                    output,
+                    Position::Range,
+                    &mut bindings,
+                    name,
+                    cur_inst_span,
+                    ctx,
+                ) {
+                    Ok(output) => Ok(output),
+                    Err(e) => {
+                        debug!(
+                            "Blame locations: {:?}, {:?}",
+                            self.contract_attachment_location, name
+                        );
+
+                        debug!("Parent exists: {}", self.parent().is_some());
+
+                        // Across a module boundary, the providing module is the one that broke it
+                        let blame_location = if let Some(boundary) = &self.boundary {
+                            Some(boundary.blame_range())
+                        } else if self.contract_attachment_location.is_none() {
+                            name.clone()
+                        } else {
+                            self.contract_attachment_location.clone()
+                        };
+
+                        // TODO clean this up
+                        if let Some(blame_location) = blame_location {
+                            let error_message = format!("this function call resulted in an error - occured in the range position of this contract: {self} \n
+                            {e}
+                            blaming: {blame_location} - broke its own contract");
+
+                            stop!(ContractViolation => error_message; *cur_inst_span);
+                        } else {
+                            let error_message = format!("this function call resulted in an error - occured in the range position of this contract: {self} \n
+                            {e}
+                            blaming: None - broke its own contract");
+
+                            stop!(ContractViolation => error_message; *cur_inst_span);
+                        }
+                    }
+                }
+            }
         }
     }
 }
@@ -290,7 +1032,9 @@ impl FunctionContract {
         cur_inst_span: &Span,
         ctx: &mut VmCore,
     ) -> Result<SteelVal> {
-        let verified_args = self.verify_preconditions(arguments, cur_inst_span, ctx, name)?;
+        let mut bindings = ParametricBindings::new();
+        let mut verified_args =
+            self.verify_preconditions(arguments, cur_inst_span, ctx, name, &mut bindings)?;
 
         // TODO use actual VM with real stack instead
 
@@ -305,56 +1049,15 @@ impl FunctionContract {
                 // What we should do is actually leverage the stack in the VM directly instead of making a recursive call here
                 ctx.call_with_args(function, verified_args.into_iter())?
             }
-            SteelVal::BoxedFunction(f) => {
-                f.func()(&verified_args).map_err(|x| x.set_span(*cur_inst_span))?
+            SteelVal::BoxedFunction(_) | SteelVal::FuncV(_) | SteelVal::FutureFunc(_) => {
+                call_native(function, &mut verified_args, cur_inst_span, ctx)?
             }
-            SteelVal::FuncV(f) => f(&verified_args).map_err(|x| x.set_span(*cur_inst_span))?,
-            SteelVal::FutureFunc(f) => SteelVal::FutureV(Gc::new(
-                f(&verified_args).map_err(|x| x.set_span(*cur_inst_span))?,
-            )),
             _ => {
                 todo!("Implement contract application for non bytecode values");
             }
         };
 
         match self.post_condition().as_ref() {
-            ContractType::Flat(f) => {
-                // unimplemented!();
-
-                debug!("applying flat contract in post condition: {}", f.name);
-
-                if let Err(e) = f.apply(output.clone(), cur_inst_span, ctx) {
-                    debug!(
-                        "Blame locations: {:?}, {:?}",
-                        self.contract_attachment_location, name
-                    );
-
-                    debug!("Parent exists: {}", self.parent().is_some());
-
-                    let blame_location = if self.contract_attachment_location.is_none() {
-                        name
-                    } else {
-                        &self.contract_attachment_location
-                    };
-
-                    // TODO clean this up
-                    if let Some(blame_location) = blame_location {
-                        let error_message = format!("this function call resulted in an error - occured in the range position of this contract: {self} \n
-                        {e}
-                        blaming: {blame_location} - broke its own contract");
-
-                        stop!(ContractViolation => error_message; *cur_inst_span);
-                    } else {
-                        let error_message = format!("this function call resulted in an error - occured in the range position of this contract: {self} \n
-                        {e}
-                        blaming: None - broke its own contract");
-
-                        stop!(ContractViolation => error_message; *cur_inst_span);
-                    }
-                }
-
-                Ok(output)
-            }
             ContractType::Function(fc) => match output {
                 SteelVal::ContractedFunction(contracted_function) => {
                     let mut pre_parent = contracted_function.contract.clone();
@@ -386,6 +1089,52 @@ impl FunctionContract {
 
                 _ => Ok(ContractedFunction::new(fc.clone(), output, name.clone()).into()),
             },
+            contract => {
+                debug!("applying contract in post condition: {}", contract);
+
+                match contract.check(
+                    output,
+                    Position::Range,
+                    &mut bindings,
+                    name,
+                    cur_inst_span,
+                    ctx,
+                ) {
+                    Ok(output) => Ok(output),
+                    Err(e) => {
+                        debug!(
+                            "Blame locations: {:?}, {:?}",
+                            self.contract_attachment_location, name
+                        );
+
+                        debug!("Parent exists: {}", self.parent().is_some());
+
+                        // Across a module boundary, the providing module is the one that broke it
+                        let blame_location = if let Some(boundary) = &self.boundary {
+                            Some(boundary.blame_range())
+                        } else if self.contract_attachment_location.is_none() {
+                            name.clone()
+                        } else {
+                            self.contract_attachment_location.clone()
+                        };
+
+                        // TODO clean this up
+                        if let Some(blame_location) = blame_location {
+                            let error_message = format!("this function call resulted in an error - occured in the range position of this contract: {self} \n
+                            {e}
+                            blaming: {blame_location} - broke its own contract");
+
+                            stop!(ContractViolation => error_message; *cur_inst_span);
+                        } else {
+                            let error_message = format!("this function call resulted in an error - occured in the range position of this contract: {self} \n
+                            {e}
+                            blaming: None - broke its own contract");
+
+                            stop!(ContractViolation => error_message; *cur_inst_span);
+                        }
+                    }
+                }
+            }
         }
     }
 }
@@ -397,6 +1146,7 @@ impl FunctionContract {
         cur_inst_span: &Span,
         ctx: &mut VmCore,
         name: &Option<String>,
+        bindings: &mut ParametricBindings,
     ) -> Result<Vec<SteelVal>> {
         let mut verified_args = Vec::new();
 
@@ -406,22 +1156,6 @@ impl FunctionContract {
             .enumerate()
         {
             match contract.as_ref() {
-                ContractType::Flat(f) => {
-                    debug!("applying flat contract in pre condition: {}", f.name);
-
-                    if let Err(e) = f.apply(arg.clone(), cur_inst_span, ctx) {
-                        debug!(
-                            "Blame locations: {:?}, {:?}",
-                            self.contract_attachment_location, name
-                        );
-
-                        let message = format!("This function call caused an error - it occured in the domain position: {}, with the contract: {}, {}, blaming: {:?} (callsite)", i, self, e, self.contract_attachment_location);
-
-                        stop!(ContractViolation => message; *cur_inst_span);
-                    }
-
-                    verified_args.push(arg.clone());
-                }
                 ContractType::Function(fc) => match arg {
                     SteelVal::ContractedFunction(contracted_function) => {
                         let mut pre_parent = contracted_function.contract.clone();
@@ -456,6 +1190,33 @@ impl FunctionContract {
                         ContractedFunction::new(fc.clone(), arg.clone(), name.clone()).into(),
                     ),
                 },
+                contract => {
+                    debug!("applying contract in pre condition: {}", contract);
+
+                    match contract.check(
+                        arg.clone(),
+                        Position::Domain,
+                        bindings,
+                        name,
+                        cur_inst_span,
+                        ctx,
+                    ) {
+                        Ok(arg) => verified_args.push(arg),
+                        Err(e) => {
+                            debug!(
+                                "Blame locations: {:?}, {:?}",
+                                self.contract_attachment_location, name
+                            );
+
+                            let message = match &self.boundary {
+                                Some(boundary) => format!("This function call caused an error - it occured in the domain position: {}, with the contract: {}, {}, blaming: {}", i, self, e, boundary.blame_domain()),
+                                None => format!("This function call caused an error - it occured in the domain position: {}, with the contract: {}, {}, blaming: {:?} (callsite)", i, self, e, self.contract_attachment_location),
+                            };
+
+                            stop!(ContractViolation => message; *cur_inst_span);
+                        }
+                    }
+                }
             }
         }
 
@@ -465,8 +1226,16 @@ impl FunctionContract {
 
 #[cfg(test)]
 mod contract_tests {
+    use crate::steel_vm::engine::Engine;
     use crate::steel_vm::test_util::{assert_script, assert_script_error};
 
+    fn contract_error(script: &str) -> String {
+        Engine::new()
+            .compile_and_run_raw_program(script)
+            .unwrap_err()
+            .to_string()
+    }
+
     #[test]
     fn simple_flat_contract() {
         let script = r#"
@@ -754,4 +1523,350 @@ mod contract_tests {
         "#;
         assert_script(script);
     }
+
+    #[test]
+    fn struct_contract() {
+        let script = r#"
+          (struct point (x y))
+
+          (define/contract (sum-point p)
+            (->/c (struct/c point int? int?) int?)
+            (+ (point-x p) (point-y p)))
+
+          (assert! (equal? (sum-point (point 1 2)) 3))
+        "#;
+        assert_script(script);
+    }
+
+    #[test]
+    fn struct_contract_field_violation() {
+        let script = r#"
+          (struct point (x y))
+
+          (define/contract (sum-point p)
+            (->/c (struct/c point int? int?) int?)
+            (+ (point-x p) (point-y p)))
+
+          (sum-point (point 1 "two"))
+        "#;
+        assert!(contract_error(script).contains("field 1 of (struct/c point"));
+    }
+
+    #[test]
+    fn struct_contract_wraps_function_fields() {
+        let script = r#"
+          (struct handler (run))
+
+          (define/contract (invoke h)
+            (->/c (struct/c handler (->/c even? even?)) int?)
+            ((handler-run h) 2))
+
+          (invoke (handler (lambda (x) (+ x 1))))
+        "#;
+        assert_script_error(script);
+    }
+
+    #[test]
+    fn list_contract() {
+        let script = r#"
+          (define/contract (sum lst)
+            (->/c (listof int?) int?)
+            (apply + lst))
+
+          (define numbers (list 1 2 3 4))
+
+          (assert! (equal? (sum numbers) 10))
+          (assert! (equal? (sum numbers) 10))
+          (assert! (equal? (sum '()) 0))
+        "#;
+        assert_script(script);
+    }
+
+    #[test]
+    fn list_contract_points_at_the_bad_element() {
+        let script = r#"
+          (define/contract (sum lst)
+            (->/c (listof int?) int?)
+            (apply + lst))
+
+          (sum (list 1 2 "three" 4))
+        "#;
+        assert!(contract_error(script).contains("the element at index 2 of (listof"));
+    }
+
+    #[test]
+    fn vector_and_hash_contracts() {
+        let script = r#"
+          (define/contract (size v)
+            (->/c (vectorof int?) int?)
+            (vector-length v))
+
+          (define/contract (lookup table key)
+            (->/c (hash/c string? int?) string? int?)
+            (hash-ref table key))
+
+          (assert! (equal? (size (vector 1 2 3)) 3))
+          (assert! (equal? (lookup (hash "a" 1 "b" 2) "b") 2))
+        "#;
+        assert_script(script);
+
+        let script = r#"
+          (define/contract (lookup table key)
+            (->/c (hash/c string? int?) string? int?)
+            (hash-ref table key))
+
+          (assert! (equal? (lookup (hash "a" 1 "b" #f) "a") 1))
+          (lookup (hash "a" 1 "b" #f) "b")
+        "#;
+        assert!(contract_error(script).contains("the value for the key \"b\" of (hash/c"));
+    }
+
+    #[test]
+    fn list_contract_checks_elements_as_they_are_read() {
+        let script = r#"
+          (define/contract (second-of lst)
+            (->/c (listof int?) int?)
+            (list-ref lst 1))
+
+          (assert! (equal? (second-of (list 1 2 "three")) 2))
+        "#;
+        assert_script(script);
+
+        let script = r#"
+          (define/contract (third-of lst)
+            (->/c (listof int?) int?)
+            (list-ref lst 2))
+
+          (third-of (list 1 2 "three"))
+        "#;
+        let error = contract_error(script);
+        assert!(error.contains("the element at index 2 of (listof"));
+        assert!(error.contains("blaming: the caller of third-of"));
+    }
+
+    #[test]
+    fn list_contract_follows_the_tail() {
+        let script = r#"
+          (define (add-up lst acc)
+            (if (null? lst)
+                acc
+                (add-up (cdr lst) (+ acc (car lst)))))
+
+          (define/contract (sum lst)
+            (->/c (listof int?) int?)
+            (add-up lst 0))
+
+          (assert! (equal? (sum (list 1 2 3 4)) 10))
+          (sum (list 1 2 "three" 4))
+        "#;
+        assert!(contract_error(script).contains("the first element of (listof"));
+    }
+
+    #[test]
+    fn list_contract_on_the_range_blames_the_function() {
+        let script = r#"
+          (define/contract (numbers)
+            (->/c (listof int?))
+            (list 1 2 'three))
+
+          (assert! (equal? (car (numbers)) 1))
+          (assert! (equal? (length (numbers)) 3))
+          (list-ref (numbers) 2)
+        "#;
+        assert!(contract_error(script).contains("blaming: numbers, which broke its own contract"));
+    }
+
+    #[test]
+    fn nested_list_contracts() {
+        let script = r#"
+          (define/contract (corner grid)
+            (->/c (listof (listof int?)) int?)
+            (car (car grid)))
+
+          (assert! (equal? (corner (list (list 1 'x) (list "y"))) 1))
+        "#;
+        assert_script(script);
+    }
+
+    #[test]
+    fn contracted_collections_are_checked_in_full_when_a_native_needs_them() {
+        let script = r#"
+          (define/contract (same lst)
+            (->/c (listof int?) (listof int?))
+            lst)
+
+          (define/contract (size lst)
+            (->/c (vectorof int?) int?)
+            (vector-length lst))
+
+          (assert! (list? (same (list 1 2))))
+          (assert! (equal? (same (list 1 2)) (list 1 2)))
+          (assert! (equal? (reverse (same (list 1 2))) (list 2 1)))
+          (assert! (equal? (size (vector 1 "two")) 2))
+          (define numbers (same (list 1 2 "three")))
+          (assert! (equal? (car numbers) 1))
+          (reverse numbers)
+        "#;
+        assert!(contract_error(script).contains("the element at index 2 of (listof"));
+    }
+
+    #[test]
+    fn contracted_collections_are_stored_as_is() {
+        let script = r#"
+          (define/contract (same lst)
+            (->/c (listof int?) (listof int?))
+            lst)
+
+          (define numbers (same (list 1 2 "three")))
+          (define boxed (list numbers))
+
+          (assert! (list? numbers))
+          (assert! (pair? numbers))
+          (assert! (not (null? numbers)))
+          (assert! (equal? (car (car boxed)) 1))
+          (assert! (equal? (apply car boxed) 1))
+          (assert! (equal? (cons 0 (same (list 1 2))) (list 0 1 2)))
+          (assert! (equal? (append (list 0) (same (list 1 2)) (list 3)) (list 0 1 2 3)))
+          (list-ref (car boxed) 2)
+        "#;
+        assert!(contract_error(script).contains("the element at index 2 of (listof"));
+
+        let script = r#"
+          (define/contract (same lst)
+            (->/c (listof int?) (listof int?))
+            lst)
+
+          (define front (list 0))
+          (append front (same (list 1 "two")) (list 3))
+        "#;
+        assert!(contract_error(script).contains("the element at index 1 of (listof"));
+
+        let script = r#"
+          (define/contract (same lst)
+            (->/c (listof int?) (listof int?))
+            lst)
+
+          (cons 0 (same (list 1 "two")))
+        "#;
+        assert!(contract_error(script).contains("the element at index 1 of (listof"));
+    }
+
+    #[test]
+    fn first_order_contracts_as_predicates() {
+        let script = r#"
+          (define int-list? (flat-contract-predicate (listof int?)))
+
+          (assert! (int-list? (list 1 2 3)))
+          (assert! (not (int-list? (list 1 "two"))))
+          (assert! (not (int-list? 10)))
+          (assert! ((flat-contract-predicate (or/c int? string?)) "ten"))
+        "#;
+        assert_script(script);
+    }
+
+    #[test]
+    fn stream_contract_is_checked_lazily() {
+        let script = r#"
+          (define (stream-cdr stream)
+            ((stream-cdr' stream)))
+
+          (define (count-from n)
+            (stream-cons n (lambda () (count-from (+ n 1)))))
+
+          (define/contract (evens-then-odds)
+            (->/c (streamof even?))
+            (count-from 0))
+
+          (assert! (equal? (stream-car (evens-then-odds)) 0))
+        "#;
+        assert_script(script);
+
+        let script = r#"
+          (define (stream-cdr stream)
+            ((stream-cdr' stream)))
+
+          (define (count-from n)
+            (stream-cons n (lambda () (count-from (+ n 1)))))
+
+          (define/contract (evens-then-odds)
+            (->/c (streamof even?))
+            (count-from 0))
+
+          (stream-car (stream-cdr (evens-then-odds)))
+        "#;
+        assert!(contract_error(script).contains("an element of (streamof"));
+    }
+
+    #[test]
+    fn or_contract_lists_every_alternative() {
+        let script = r#"
+          (define/contract (describe x)
+            (->/c (or/c int? string?) boolean?)
+            #t)
+
+          (assert! (describe 10))
+          (assert! (describe "ten"))
+          (describe 'ten)
+        "#;
+        assert!(contract_error(script).contains("none of the alternatives of (or/c"));
+    }
+
+    #[test]
+    fn and_contract_names_the_failing_conjunct() {
+        let script = r#"
+          (define/contract (halve x)
+            (->/c (and/c int? even?) number?)
+            (/ x 2))
+
+          (assert! (= (halve 4) 2))
+          (halve 3)
+        "#;
+        assert!(contract_error(script).contains("conjunct 1 of (and/c"));
+    }
+
+    #[test]
+    fn parametric_contract() {
+        let script = r#"
+          (define a (new-∀/c 'a))
+
+          (define/contract (identity x)
+            (->/c a a)
+            x)
+
+          (define/contract (first-of lst)
+            (->/c (listof a) a)
+            (car lst))
+
+          (assert! (equal? (identity 10) 10))
+          (assert! (equal? (first-of (list "x" "y")) "x"))
+        "#;
+        assert_script(script);
+    }
+
+    #[test]
+    fn parametric_contract_rejects_new_values() {
+        let script = r#"
+          (define a (new-∀/c 'a))
+
+          (define/contract (not-identity x)
+            (->/c a a)
+            (+ x 1))
+
+          (not-identity 10)
+        "#;
+        assert!(contract_error(script).contains("the contract variable a produced: 11"));
+    }
+
+    #[test]
+    fn dependent_contract_with_arrow_i() {
+        let script = r#"
+          (define/contract (subtract x y)
+            (->i ([x int?] [y (x) (</c x)]) [result (x y) (>/c 0)])
+            (- x y))
+
+          (assert! (equal? (subtract 10 3) 7))
+          (subtract 3 10)
+        "#;
+        assert_script_error(script);
+    }
 }
@@ -15,6 +15,9 @@ use super::dylib::DylibContainers;
 use crate::{
     compiler::{
         compiler::Compiler,
+        constants::ConstantMap,
+        disassembler::Disassembly,
+        map::SymbolMap,
         modules::CompiledModule,
         program::{Executable, RawProgramWithSymbols, SerializableRawProgramWithSymbols},
     },
@@ -33,17 +36,22 @@ use crate::{
         kernel::{fresh_kernel_image, Kernel},
         parser::{ParseError, Parser, Sources},
     },
-    rerrs::{back_trace, back_trace_to_string},
-    rvals::{FromSteelVal, IntoSteelVal, Result, SteelVal},
+    rerrs::{back_trace, back_trace_to_string, ErrorKind},
+    rvals::{serialize, CustomType, FromSteelVal, IntoSteelVal, Result, SteelVal},
     steel_vm::register_fn::RegisterFn,
     stop, throw,
-    values::functions::BoxedDynFunction,
+    values::{functions::BoxedDynFunction, json_vals::syntax_to_json},
     SteelErr,
 };
-use std::{collections::HashMap, path::PathBuf, rc::Rc, sync::Arc};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{atomic::AtomicBool, Arc},
+};
 
 use im_rc::HashMap as ImmutableHashMap;
-use lasso::ThreadedRodeo;
+use lasso::{Key, ThreadedRodeo};
 use serde::{Deserialize, Serialize};
 
 use crate::parser::ast::IteratorExtensions;
@@ -83,6 +91,9 @@ pub struct Engine {
     sources: Sources,
     #[cfg(feature = "dylibs")]
     dylibs: DylibContainers,
+    // The number of globals the engine started out with. Only set for engines
+    // that can be snapshotted, see `Engine::snapshot`
+    snapshot_base: Option<usize>,
 }
 
 impl Default for Engine {
@@ -111,6 +122,25 @@ struct StartupBootstrapImage {
     macros: HashMap<InternedString, SteelMacro>,
 }
 
+// The whole state of an engine. The globals, constants and struct types are restored as they
+// were, so none of the code that was run gets run again.
+#[derive(Serialize, Deserialize)]
+struct EngineSnapshot {
+    version: String,
+    interner: Arc<ThreadedRodeo>,
+    syntax_object_id: usize,
+    function_id: usize,
+    sources: Sources,
+    symbol_map: SymbolMap,
+    image: serialize::Image,
+    macros: HashMap<InternedString, SteelMacro>,
+    compiled_modules: HashMap<PathBuf, CompiledModule>,
+    module_file_metadata: HashMap<PathBuf, std::time::SystemTime>,
+    builtin_modules: Vec<String>,
+    snapshot_base: usize,
+    kernel_transformers: Vec<InternedString>,
+}
+
 // #[test]
 fn run_bootstrap() {
     Engine::create_bootstrap_from_programs();
@@ -206,6 +236,7 @@ impl Engine {
             sources: Sources::new(),
             #[cfg(feature = "dylibs")]
             dylibs: DylibContainers::new(),
+            snapshot_base: None,
         };
 
         register_builtin_modules(&mut vm);
@@ -288,6 +319,7 @@ impl Engine {
             sources: Sources::new(),
             #[cfg(feature = "dylibs")]
             dylibs: DylibContainers::new(),
+            snapshot_base: None,
         };
 
         if let Some(programs) = Engine::load_from_bootstrap(&mut vm) {
@@ -402,6 +434,7 @@ impl Engine {
             sources: Sources::new(),
             #[cfg(feature = "dylibs")]
             dylibs: DylibContainers::new(),
+            snapshot_base: None,
         };
 
         register_builtin_modules(&mut vm);
@@ -466,6 +499,7 @@ impl Engine {
             sources: Sources::new(),
             #[cfg(feature = "dylibs")]
             dylibs: DylibContainers::new(),
+            snapshot_base: None,
         };
 
         register_builtin_modules(&mut vm);
@@ -527,6 +561,7 @@ impl Engine {
             sources: Sources::new(),
             #[cfg(feature = "dylibs")]
             dylibs: DylibContainers::new(),
+            snapshot_base: None,
         }
     }
 
@@ -583,6 +618,52 @@ impl Engine {
         self
     }
 
+    /// Turn compiling hot functions to native code on or off
+    #[cfg(feature = "jit")]
+    pub fn with_jit(&mut self, jit: bool) -> &mut Self {
+        self.virtual_machine.with_jit(jit);
+        self
+    }
+
+    /// Start counting the sequences of op codes that get executed, for
+    /// [`op_code_sequence_profile`](Engine::op_code_sequence_profile)
+    #[cfg(feature = "profiling")]
+    pub fn record_op_code_sequences(&mut self) -> &mut Self {
+        self.virtual_machine.record_op_code_sequences();
+        self
+    }
+
+    /// The `count` most frequently executed sequences of op codes since
+    /// [`record_op_code_sequences`](Engine::record_op_code_sequences) was called. Building steel
+    /// with `STEEL_SUPERINSTRUCTIONS` set to a file holding this profile fuses each of them into
+    /// a single instruction.
+    #[cfg(feature = "profiling")]
+    pub fn op_code_sequence_profile(&self, count: usize) -> String {
+        self.virtual_machine.op_code_sequence_profile(count)
+    }
+
+    // Runs in test mode, with the results reported back to the test runner. When `module` is set,
+    // only the test module with that name runs.
+    pub(crate) fn enable_test_runner(
+        &mut self,
+        filter: Option<String>,
+        module: Option<String>,
+    ) -> &mut Self {
+        self.virtual_machine.enable_test_runner(filter, module);
+        self
+    }
+
+    // Setting the flag stops the running program at its next function call or loop iteration.
+    // Constant functions run in the kernel while compiling, so the kernel gets the flag too.
+    pub(crate) fn set_interrupt(&mut self, interrupt: Arc<AtomicBool>) -> &mut Self {
+        if let Some(kernel) = self.compiler.kernel.as_mut() {
+            kernel.set_interrupt(Arc::clone(&interrupt));
+        }
+
+        self.virtual_machine.set_interrupt(interrupt);
+        self
+    }
+
     #[inline]
     pub fn new_sandboxed() -> Self {
         let mut vm = Engine::new_raw();
@@ -791,10 +872,324 @@ impl Engine {
         let mut engine = fresh_kernel_image();
 
         engine.compiler.kernel = Some(Kernel::new());
+        engine.snapshot_base = Some(engine.compiler.symbol_map.len());
 
         engine
     }
 
+    /// Writes the state of the engine to the file at `path`, so that it can be restored
+    /// later with [`from_snapshot`](crate::steel_vm::engine::Engine::from_snapshot). This includes
+    /// the values of the globals, the constants and struct types, macros, compiled modules,
+    /// sources and the symbol interner. Native functions are recorded by the builtin module they
+    /// belong to, or else by the global they're bound to, and looked up again when restoring.
+    ///
+    /// Values that can't be written, like ports, are only allowed in the globals the engine
+    /// started out with, which keep the value of the restoring engine.
+    ///
+    /// Only engines created with [`Engine::new`](crate::steel_vm::engine::Engine::new) can be snapshotted.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// let mut vm = Engine::new();
+    /// vm.run("(define (square x) (* x x))").unwrap();
+    /// vm.snapshot("steel.image").unwrap();
+    ///
+    /// let mut restored = Engine::from_snapshot("steel.image").unwrap();
+    /// restored.run("(square 10)").unwrap();
+    /// ```
+    pub fn snapshot(&self, path: impl AsRef<Path>) -> Result<()> {
+        let base = self.snapshot_base.ok_or_else(throw!(
+            Generic => "snapshot: only engines created with Engine::new can be snapshotted"
+        ))?;
+
+        let names = self.compiler.symbol_map.values();
+        let globals = &self.virtual_machine.global_env.bindings_vec;
+
+        // Native functions are found by their address, first in the builtin modules
+        let mut natives = HashMap::new();
+
+        for (index, value) in globals.iter().enumerate() {
+            if let Some(address) = serialize::native_address(value) {
+                natives.insert(
+                    address,
+                    serialize::NativeName::Global(names[index].resolve().to_string()),
+                );
+            }
+        }
+
+        for (module_name, module) in self.modules.inner() {
+            for (name, value) in module.values() {
+                if let Some(address) = serialize::native_address(value) {
+                    natives.insert(
+                        address,
+                        serialize::NativeName::Module {
+                            module: module_name.to_string(),
+                            name: name.to_string(),
+                        },
+                    );
+                }
+            }
+        }
+
+        let image = serialize::write_image(
+            globals,
+            &self.compiler.constant_map.to_vec(),
+            &natives,
+            &|id| self.virtual_machine.closure_spans(id),
+            &|index, value, error| {
+                let registered = matches!(
+                    value,
+                    SteelVal::FuncV(_)
+                        | SteelVal::BuiltIn(_)
+                        | SteelVal::MutFunc(_)
+                        | SteelVal::BoxedFunction(_)
+                ) || matches!(value, SteelVal::Custom(c) if c.borrow().as_any_ref().is::<BuiltInModule>());
+
+                if index < base || registered {
+                    Ok(serialize::NativeName::Global(
+                        names[index].resolve().to_string(),
+                    ))
+                } else {
+                    stop!(Generic => "snapshot: unable to write the value of {}: {}", names[index], error)
+                }
+            },
+        )?;
+
+        let snapshot = EngineSnapshot {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            interner: take_interner(),
+            syntax_object_id: SYNTAX_OBJECT_ID.load(std::sync::atomic::Ordering::Relaxed),
+            function_id: crate::compiler::code_gen::FUNCTION_ID
+                .load(std::sync::atomic::Ordering::Relaxed),
+            sources: self.sources.clone(),
+            symbol_map: self.compiler.symbol_map.clone(),
+            image,
+            macros: self.compiler.macro_env.clone(),
+            compiled_modules: self.compiler.modules().clone(),
+            module_file_metadata: self.compiler.module_file_metadata().clone(),
+            builtin_modules: self.modules.inner().keys().map(|x| x.to_string()).collect(),
+            snapshot_base: base,
+            kernel_transformers: self
+                .compiler
+                .kernel
+                .as_ref()
+                .map(|x| x.transformers())
+                .unwrap_or_default(),
+        };
+
+        let file = std::fs::File::create(path)?;
+
+        bincode::serialize_into(std::io::BufWriter::new(file), &snapshot)
+            .map_err(|e| SteelErr::new(ErrorKind::Generic, format!("snapshot: {e}")))
+    }
+
+    /// Instantiates a new engine from a snapshot written by [`snapshot`](crate::steel_vm::engine::Engine::snapshot).
+    /// This skips parsing, expansion, compilation and running of everything that was loaded into
+    /// the original engine.
+    pub fn from_snapshot(path: impl AsRef<Path>) -> Result<Self> {
+        let snapshot = Self::read_snapshot(path.as_ref())?;
+
+        // If nothing has been interned yet, adopt the interner directly. Otherwise it
+        // gets reconciled with the existing one when restoring.
+        if get_interner().is_none() {
+            let _ = crate::parser::interner::initialize_with(snapshot.interner.clone());
+        }
+
+        // The prelude and contracts are part of the image, so only the builtin modules have
+        // to be registered and required before restoring. The kernel only boots once a
+        // transformer like `struct` is expanded.
+        let mut vm = Engine {
+            virtual_machine: SteelThread::new(),
+            compiler: Compiler::default(),
+            constants: None,
+            modules: ModuleContainer::default(),
+            sources: Sources::new(),
+            #[cfg(feature = "dylibs")]
+            dylibs: DylibContainers::new(),
+            snapshot_base: None,
+        };
+
+        register_builtin_modules(&mut vm);
+
+        vm.compile_and_run_raw_program(crate::steel_vm::primitives::ALL_MODULES)?;
+
+        #[cfg(feature = "dylibs")]
+        {
+            vm.dylibs.load_modules();
+
+            let modules = vm.dylibs.modules();
+
+            for module in modules {
+                vm.register_external_module(module)?;
+            }
+        }
+
+        // Registered before restoring as well, since the image refers to it
+        vm.register_fn("report-error!", |_: SteelErr| {});
+
+        let kernel = Kernel::deferred(snapshot.kernel_transformers.iter().cloned());
+        let snapshot_base = snapshot.snapshot_base;
+
+        vm.restore_from_snapshot(snapshot)?;
+
+        let sources = vm.sources.clone();
+
+        vm.register_fn("report-error!", move |error: SteelErr| {
+            raise_error(&sources, error);
+        });
+
+        vm.compiler.kernel = Some(kernel);
+        vm.snapshot_base = Some(snapshot_base);
+
+        Ok(vm)
+    }
+
+    /// Restores a snapshot into this engine, replacing its globals. Any custom modules that were
+    /// registered on the engine that wrote the snapshot, and any functions registered directly
+    /// on it, must be registered on this engine first.
+    pub fn restore_snapshot(&mut self, path: impl AsRef<Path>) -> Result<&mut Self> {
+        let snapshot = Self::read_snapshot(path.as_ref())?;
+        self.restore_from_snapshot(snapshot)?;
+        Ok(self)
+    }
+
+    fn read_snapshot(path: &Path) -> Result<EngineSnapshot> {
+        let file = std::fs::File::open(path)?;
+
+        let snapshot: EngineSnapshot = bincode::deserialize_from(std::io::BufReader::new(file))
+            .map_err(|e| {
+                SteelErr::new(
+                    ErrorKind::Generic,
+                    format!("unable to read snapshot {}: {e}", path.display()),
+                )
+            })?;
+
+        if snapshot.version != env!("CARGO_PKG_VERSION") {
+            stop!(Generic => "snapshot was created with steel {}, but this is steel {}", snapshot.version, env!("CARGO_PKG_VERSION"));
+        }
+
+        Ok(snapshot)
+    }
+
+    // The snapshot refers to symbols by their interned key, so every key has to resolve to the
+    // same string in this process. This is checked without interning anything, and only then
+    // are the symbols this process hasn't seen yet interned, in the order of their keys.
+    fn reconcile_interner(snapshot: &ThreadedRodeo) -> Result<()> {
+        let interner = get_interner().ok_or_else(throw!(
+            Generic => "snapshot: the symbol interner has not been initialized"
+        ))?;
+
+        let mut symbols = snapshot.iter().collect::<Vec<_>>();
+        symbols.sort_by_key(|x| x.0);
+
+        let mut missing = Vec::new();
+
+        for (key, symbol) in symbols {
+            match interner.get(symbol) {
+                Some(existing) if existing == key => {}
+                None if interner.try_resolve(&key).is_none() => missing.push((key, symbol)),
+                _ => {
+                    stop!(Generic => "snapshot is incompatible with the symbols already interned in this process")
+                }
+            }
+        }
+
+        // New keys are handed out in order, so the missing symbols have to be exactly the
+        // next ones
+        let next = interner.len();
+
+        if missing
+            .iter()
+            .enumerate()
+            .any(|(offset, (key, _))| key.into_usize() != next + offset)
+        {
+            stop!(Generic => "snapshot is incompatible with the symbols already interned in this process");
+        }
+
+        for (_, symbol) in missing {
+            interner.get_or_intern(symbol);
+        }
+
+        Ok(())
+    }
+
+    fn restore_from_snapshot(&mut self, snapshot: EngineSnapshot) -> Result<()> {
+        // Builtin functions are referenced through their modules, so the modules
+        // have to be available under the same name
+        let missing = snapshot
+            .builtin_modules
+            .iter()
+            .filter(|x| !self.modules.inner().contains_key(x.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if !missing.is_empty() {
+            stop!(Generic => "snapshot references modules which are not registered: {}", missing.join(", "));
+        }
+
+        Self::reconcile_interner(&snapshot.interner)?;
+
+        SYNTAX_OBJECT_ID.fetch_max(
+            snapshot.syntax_object_id,
+            std::sync::atomic::Ordering::Relaxed,
+        );
+        crate::compiler::code_gen::FUNCTION_ID
+            .fetch_max(snapshot.function_id, std::sync::atomic::Ordering::Relaxed);
+
+        // Natives resolve against the modules and globals of this engine, as they are
+        // before the snapshot replaces them
+        let restored = {
+            let modules = &self.modules;
+            let symbol_map = &self.compiler.symbol_map;
+            let thread = &self.virtual_machine;
+
+            let natives = |name: &serialize::NativeName| match name {
+                serialize::NativeName::Module { module, name } => modules
+                    .inner()
+                    .get(module.as_str())
+                    .and_then(|x| x.values().find(|x| x.0.as_ref() == name.as_str()))
+                    .map(|x| x.1.clone()),
+                serialize::NativeName::Global(name) => InternedString::try_get(name)
+                    .and_then(|x| symbol_map.get(&x).ok())
+                    .and_then(|x| thread.extract_value(x)),
+            };
+
+            let mut heap = self.virtual_machine.heap.clone();
+
+            let restored = serialize::read_image(
+                &snapshot.image,
+                &self.virtual_machine.custom_deserializers,
+                &natives,
+                &mut heap,
+            )?;
+
+            (restored, heap)
+        };
+
+        let (restored, heap) = restored;
+        let constant_map = ConstantMap::from_vec(restored.constants);
+
+        self.virtual_machine.heap = heap;
+        self.virtual_machine.restore_globals(
+            restored.globals,
+            constant_map.clone(),
+            restored.spans,
+        );
+
+        self.compiler.symbol_map = snapshot.symbol_map;
+        self.compiler.constant_map = constant_map;
+
+        self.sources = snapshot.sources;
+        self.compiler.macro_env.extend(snapshot.macros);
+        self.compiler
+            .restore_modules(snapshot.compiled_modules, snapshot.module_file_metadata);
+
+        Ok(())
+    }
+
     /// Consumes the current `Engine` and emits a new `Engine` with the prelude added
     /// to the environment. The prelude won't work unless the primitives are also enabled.
     ///
@@ -859,6 +1254,13 @@ impl Engine {
         self
     }
 
+    /// Adds a directory to search when resolving `require`s. Directories are searched in the
+    /// order they're added, before the ones in `STEEL_PATH` and the installed packages.
+    pub fn add_search_directory(&mut self, directory: PathBuf) -> &mut Self {
+        self.compiler.add_search_directory(directory);
+        self
+    }
+
     #[cfg(feature = "dylibs")]
     pub fn register_external_module(
         &mut self,
@@ -919,6 +1321,34 @@ impl Engine {
         program.debug_build(name, &mut self.compiler.symbol_map)
     }
 
+    /// Compiles the program and returns a readable listing of the bytecode for every top level
+    /// expression and function, without running it. The listing can be printed directly, or
+    /// serialized since it implements `Serialize`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// let mut vm = Engine::new();
+    /// let listing = vm.disassemble("(define (square x) (* x x))", None).unwrap();
+    /// assert!(listing.functions.iter().any(|x| x.name == "square"));
+    /// println!("{listing}");
+    /// ```
+    pub fn disassemble(&mut self, expr: &str, path: Option<PathBuf>) -> Result<Disassembly> {
+        let program = match path {
+            Some(path) => self.emit_raw_program(expr, path)?,
+            None => self.emit_raw_program_no_path(expr)?,
+        };
+
+        // Nothing is defined until the program actually runs
+        let symbol_map_offset = self.compiler.symbol_map.len();
+        let result = program.disassemble(&mut self.compiler.symbol_map, &self.sources);
+        self.compiler.symbol_map.roll_back(symbol_map_offset);
+
+        result
+    }
+
     pub fn globals(&self) -> &Vec<InternedString> {
         self.compiler.symbol_map.values()
     }
@@ -1100,6 +1530,19 @@ impl Engine {
             .join("\n\n"))
     }
 
+    /// Emit the fully expanded AST as JSON, one array element per top level expression.
+    pub fn emit_fully_expanded_ast_to_json(
+        &mut self,
+        expr: &str,
+        path: Option<PathBuf>,
+    ) -> Result<serde_json::Value> {
+        self.emit_fully_expanded_ast(expr, path)?
+            .into_iter()
+            .map(|x| syntax_to_json(SteelVal::try_from(x)?))
+            .collect::<Result<_>>()
+            .map(serde_json::Value::Array)
+    }
+
     /// Emits the fully expanded AST directly.
     pub fn emit_fully_expanded_ast(
         &mut self,
@@ -1139,6 +1582,32 @@ impl Engine {
         Ok(self.register_value(name, converted))
     }
 
+    /// Registers any value implementing [`Serialize`] under the name `name`, converting it with
+    /// [`to_steelval`](crate::serde::to_steelval).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// use std::collections::HashMap;
+    ///
+    /// let mut vm = Engine::new();
+    /// let mut config = HashMap::new();
+    /// config.insert("port".to_string(), 8080);
+    ///
+    /// vm.register_serde_value("config", &config).unwrap();
+    /// vm.run(r#"(hash-get config "port")"#).unwrap();
+    /// ```
+    pub fn register_serde_value<T: Serialize + ?Sized>(
+        &mut self,
+        name: &str,
+        value: &T,
+    ) -> Result<&mut Self> {
+        let converted = crate::serde::to_steelval(value)?;
+        Ok(self.register_value(name, converted))
+    }
+
     /// Registers a [`SteelVal`](crate::rvals::SteelVal) under the name `name` in the `Engine`'s internal environment.
     ///
     /// # Examples
@@ -1296,6 +1765,63 @@ impl Engine {
         T::from_steelval(&self.extract_value(name)?)
     }
 
+    /// Extracts a value with the given identifier `name` from the internal environment, and converts it to any type
+    /// implementing [`DeserializeOwned`](serde::de::DeserializeOwned) with [`from_steelval`](crate::serde::from_steelval).
+    /// The error points to the part of the value that didn't match the type.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// let mut vm = Engine::new();
+    /// vm.run("(define a (list 1 2 3))").unwrap();
+    /// assert_eq!(vm.extract_serde::<Vec<u8>>("a").unwrap(), vec![1, 2, 3]);
+    /// ```
+    pub fn extract_serde<T: serde::de::DeserializeOwned>(&self, name: &str) -> Result<T> {
+        crate::serde::from_steelval(&self.extract_value(name)?)
+    }
+
+    /// Serializes a value into a stable, versioned binary format. This is the same format used by
+    /// `value->bytes`. Sharing and cycles between values are preserved, however functions,
+    /// ports and other runtime values cannot be serialized.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate steel;
+    /// # use steel::steel_vm::engine::Engine;
+    /// use steel::rvals::SteelVal;
+    /// let mut vm = Engine::new();
+    /// vm.run("(define a (list 1 2 3))").unwrap();
+    /// let bytes = vm.serialize_value(&vm.extract_value("a").unwrap()).unwrap();
+    /// assert_eq!(vm.deserialize_value(&bytes).unwrap(), vm.extract_value("a").unwrap());
+    /// ```
+    pub fn serialize_value(&self, value: &SteelVal) -> Result<Vec<u8>> {
+        serialize::to_bytes(value)
+    }
+
+    /// Reconstructs a value from the bytes produced by [`serialize_value`](crate::steel_vm::engine::Engine::serialize_value).
+    /// Any struct types referenced by the value must already be defined in this engine, with
+    /// the same fields.
+    pub fn deserialize_value(&self, bytes: &[u8]) -> Result<SteelVal> {
+        serialize::from_bytes(bytes, &self.virtual_machine.custom_deserializers)
+    }
+
+    /// Registers the function used to reconstruct the custom type `T` when deserializing, in
+    /// this engine and in `bytes->value` run by it. The type opts in to serialization by
+    /// implementing `to_serialized_bytes` on the `Custom` trait.
+    pub fn register_custom_deserializer<T: CustomType + 'static>(
+        &mut self,
+        deserializer: impl Fn(&[u8]) -> Result<T> + 'static,
+    ) -> &mut Self {
+        self.virtual_machine.custom_deserializers.insert(
+            std::any::type_name::<T>().to_string(),
+            Rc::new(move |bytes| deserializer(bytes)?.into_steelval()),
+        );
+        self
+    }
+
     /// Raise the error within the stack trace
     pub fn raise_error(&self, error: SteelErr) {
         raise_error(&self.sources, error)
@@ -1441,6 +1967,10 @@ impl Engine {
         self.compiler.modules()
     }
 
+    pub(crate) fn sources(&self) -> &Sources {
+        &self.sources
+    }
+
     pub fn global_exists(&self, ident: &str) -> bool {
         let spur = if let Some(spur) = InternedString::try_get(ident) {
             spur
@@ -1695,4 +2225,224 @@ mod engine_api_tests {
             .compile_and_run_raw_program("(external-get-value-imm *external*)")
             .is_err());
     }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let path = std::env::temp_dir().join(format!("steel-snapshot-{}", std::process::id()));
+
+        let mut engine = Engine::new();
+        engine
+            .compile_and_run_raw_program(
+                r#"
+            (struct Point (x y))
+            (define-syntax swap (syntax-rules () [(swap a b) (list b a)]))
+            (define (distance p) (+ (Point-x p) (Point-y p)))
+            (define origin (Point 1 2))
+            (define total 0)
+            (set! total 10)
+            (define (make-counter)
+              (let ([n 0])
+                (lambda () (set! n (+ n 1)) n)))
+            (define counter (make-counter))
+            (counter)
+            (counter)
+            "#,
+            )
+            .unwrap();
+
+        engine.snapshot(&path).unwrap();
+
+        let mut restored = Engine::from_snapshot(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Restored engines can be snapshotted again
+        restored.snapshot(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let res = restored
+            .compile_and_run_raw_program(
+                "(list (distance (Point 1 2)) (swap 1 2) (Point? origin) (Point-y origin) total (counter))",
+            )
+            .unwrap();
+
+        assert_eq!(
+            res[0],
+            crate::list![
+                SteelVal::IntV(3),
+                crate::list![SteelVal::IntV(2), SteelVal::IntV(1)],
+                SteelVal::BoolV(true),
+                SteelVal::IntV(2),
+                SteelVal::IntV(10),
+                SteelVal::IntV(3)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_snapshot_does_not_rerun_programs() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let path =
+            std::env::temp_dir().join(format!("steel-snapshot-effects-{}", std::process::id()));
+
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let mut engine = Engine::new();
+        let counter = runs.clone();
+        engine.register_fn("record!", move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        engine
+            .compile_and_run_raw_program("(record!) (define (record-twice) (record!) (record!))")
+            .unwrap();
+
+        engine.snapshot(&path).unwrap();
+
+        // Functions registered directly on the engine have to be registered again
+        assert!(Engine::from_snapshot(&path).is_err());
+
+        let restored_runs = Arc::new(AtomicUsize::new(0));
+
+        let mut restored = Engine::new();
+        let counter = restored_runs.clone();
+        restored.register_fn("record!", move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        restored.restore_snapshot(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(restored_runs.load(Ordering::SeqCst), 0);
+
+        restored
+            .compile_and_run_raw_program("(record-twice)")
+            .unwrap();
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(restored_runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_snapshot_rejects_unwritable_values() {
+        let path =
+            std::env::temp_dir().join(format!("steel-snapshot-unwritable-{}", std::process::id()));
+
+        let mut engine = Engine::new();
+        engine
+            .compile_and_run_raw_program("(define output (stdin))")
+            .unwrap();
+
+        let error = engine.snapshot(&path).unwrap_err();
+        assert!(error.to_string().contains("output"));
+    }
+
+    #[test]
+    fn test_snapshot_requires_recording_engine() {
+        let path = std::env::temp_dir().join("steel-snapshot-base");
+        assert!(Engine::new_base().snapshot(path).is_err());
+    }
+
+    #[test]
+    fn test_disassemble_resolves_names_and_labels() {
+        let mut vm = Engine::new();
+        let listing = vm
+            .disassemble(
+                "(define (count-down n) (if (= n 0) \"done\" (count-down (- n 1))))",
+                None,
+            )
+            .unwrap();
+
+        let function = listing
+            .functions
+            .iter()
+            .find(|x| x.name == "count-down")
+            .unwrap();
+
+        assert_eq!(function.arity, Some(1));
+        assert!(function.instructions.iter().any(|x| x.label.is_some()));
+        assert!(function
+            .instructions
+            .iter()
+            .any(|x| x.annotation.as_deref() == Some("constant \"done\"")));
+
+        // Disassembling doesn't define anything
+        assert!(vm.extract_value("count-down").is_err());
+    }
+
+    #[test]
+    fn test_super_instructions_match_the_unfused_program() {
+        use crate::core::opcode::OpCode;
+        use crate::steel_vm::vm::superinstructions::original_instruction;
+
+        let program = r#"
+            (define (total xs) (if (null? xs) 0 (+ (car xs) (total (cdr xs)))))
+            (define (scale n) (- 1 (* 2 n)))
+            (list (total (list 1 2 3)) (map scale (list 5 2.5 -1099511627776)))
+        "#;
+
+        let mut vm = Engine::new();
+        let listing = vm.disassemble(program, None).unwrap();
+
+        let fused = listing
+            .functions
+            .iter()
+            .flat_map(|x| &x.instructions)
+            .filter(|x| x.op_code == "SUPERINSTRUCTION")
+            .collect::<Vec<_>>();
+
+        assert!(!fused.is_empty());
+        assert!(fused
+            .iter()
+            .all(|x| x.annotation.as_deref().unwrap().starts_with("fused ")));
+
+        let expected = vm.run(program).unwrap();
+
+        let mut vm = Engine::new();
+        let constants = vm.constants();
+        let raw_program = vm
+            .compiler
+            .compile_executable(
+                program,
+                None,
+                constants,
+                vm.modules.clone(),
+                &mut vm.sources,
+            )
+            .unwrap();
+        let mut executable = vm.raw_program_to_executable(raw_program).unwrap();
+
+        executable.instructions = executable
+            .instructions
+            .iter()
+            .map(|x| x.iter().map(|x| original_instruction(*x)).collect())
+            .collect();
+
+        assert!(executable
+            .instructions
+            .iter()
+            .flat_map(|x| x.iter())
+            .all(|x| x.op_code != OpCode::SUPERINSTRUCTION));
+
+        // Floats never compare equal as values, so compare how the results print
+        assert_eq!(
+            format!("{:?}", vm.run_executable(&executable).unwrap()),
+            format!("{expected:?}")
+        );
+    }
+
+    #[test]
+    fn test_expanded_ast_round_trips() {
+        let program = r#"
+            (define (greet name) (string-append "hello \"" name "\"\n"))
+            (greet "steel")
+        "#;
+
+        let mut vm = Engine::new();
+        let expected = vm.run(program).unwrap();
+
+        let expanded = Engine::new()
+            .emit_fully_expanded_ast_to_string(program, None)
+            .unwrap();
+
+        assert_eq!(Engine::new().run(&expanded).unwrap(), expected);
+    }
 }
@@ -1,6 +1,8 @@
 use std::{
     borrow::Cow,
     cell::{Ref, RefCell, RefMut},
+    ffi::c_void,
+    hash::Hash,
     marker::PhantomData,
     rc::Rc,
     sync::{Arc, Mutex},
@@ -8,11 +10,16 @@ use std::{
 
 use crate::{
     gc::{unsafe_erased_pointers::OpaqueReference, Gc},
+    parser::interner::InternedString,
     rerrs::ErrorKind,
     rvals::{
         as_underlying_type, Custom, CustomType, FutureResult, IntoSteelVal, Result, SRef, SteelVal,
     },
-    values::functions::{BoxedDynFunction, StaticOrRcStr},
+    steel_vm::vm::{callback_engine, with_callback_context, EngineId, VmContext},
+    values::{
+        functions::{BoxedDynFunction, StaticOrRcStr},
+        structs::{lookup_struct_type_descriptor, UserDefinedStruct},
+    },
     SteelErr,
 };
 
@@ -21,6 +28,7 @@ use abi_stable::{
     StableAbi,
 };
 use futures_util::FutureExt;
+use im_lists::list::List;
 
 pub use async_ffi::{FfiFuture, FutureExt as FfiFutureExt};
 
@@ -102,6 +110,10 @@ pub trait IntoFFIVal: Sized {
 
 // Probably can do a blanket impl here?
 pub trait FromFFIVal: Sized {
+    /// Whether the value can hold on to a Steel procedure. Functions that take one are allowed
+    /// to call back into the VM, which costs a little extra on every call.
+    const TAKES_CALLBACKS: bool = false;
+
     fn from_ffi_val(val: FFIValue) -> RResult<Self, RBoxError>;
 }
 
@@ -239,9 +251,54 @@ impl From<()> for FFIValue {
     }
 }
 
+impl FromFFIVal for char {
+    fn from_ffi_val(val: FFIValue) -> RResult<Self, RBoxError> {
+        if let FFIValue::CharV { c } = val {
+            RResult::ROk(c)
+        } else {
+            conversion_error!(char, val)
+        }
+    }
+}
+
+impl From<char> for FFIValue {
+    fn from(c: char) -> Self {
+        FFIValue::CharV { c }
+    }
+}
+
+// Lets functions take any value, i.e. symbols or structs, and inspect it themselves
+impl FromFFIVal for FFIValue {
+    const TAKES_CALLBACKS: bool = true;
+
+    fn from_ffi_val(val: FFIValue) -> RResult<Self, RBoxError> {
+        RResult::ROk(val)
+    }
+}
+
+impl FromFFIVal for FFICallback {
+    const TAKES_CALLBACKS: bool = true;
+
+    fn from_ffi_val(val: FFIValue) -> RResult<Self, RBoxError> {
+        if let FFIValue::Callback(callback) = val {
+            RResult::ROk(callback)
+        } else {
+            conversion_error!(procedure, val)
+        }
+    }
+}
+
+impl From<FFIStruct> for FFIValue {
+    fn from(value: FFIStruct) -> Self {
+        FFIValue::Struct(value)
+    }
+}
+
 impl<T: FromFFIVal> FromFFIVal for Vec<T> {
+    const TAKES_CALLBACKS: bool = T::TAKES_CALLBACKS;
+
     fn from_ffi_val(val: FFIValue) -> RResult<Self, RBoxError> {
-        if let FFIValue::Vector(v) = val {
+        if let FFIValue::List(v) | FFIValue::Vector(v) = val {
             let mut collected = Vec::with_capacity(v.len());
 
             for value in v {
@@ -335,7 +392,7 @@ impl<T: CustomType + 'static> AsRefFFIVal for T {
 
 impl From<RVec<FFIValue>> for FFIValue {
     fn from(value: RVec<FFIValue>) -> Self {
-        FFIValue::Vector(value)
+        FFIValue::List(value)
     }
 }
 
@@ -347,7 +404,7 @@ impl<T: IntoFFIVal> IntoFFIVal for Vec<T> {
             output.push(ffi_try!(value.into_ffi_val()));
         }
 
-        RResult::ROk(FFIValue::Vector(output))
+        RResult::ROk(FFIValue::List(output))
     }
 }
 
@@ -388,6 +445,7 @@ impl<RET: IntoFFIVal, SELF: AsRefFFIVal, FN: Fn(&SELF) -> RET + SendSyncStatic>
                 name: RString::from(name),
                 arity: 0,
                 function: Arc::new(f),
+                reentrant: false,
             }),
         );
 
@@ -428,6 +486,7 @@ macro_rules! impl_register_fn_ffi {
                         name: RString::from(name),
                         arity: 0,
                         function: Arc::new(f),
+                        reentrant: false $(|| <$param>::TAKES_CALLBACKS)*,
                     }),
                 );
 
@@ -465,6 +524,7 @@ macro_rules! impl_register_fn_ffi {
                         name: RString::from(name),
                         arity: 0,
                         function: Arc::new(f),
+                        reentrant: false $(|| <$param>::TAKES_CALLBACKS)*,
                     }),
                 );
 
@@ -521,6 +581,7 @@ impl<RET: IntoFFIVal, SELF: AsRefFFIVal, FN: Fn(&mut SELF) -> RET + SendSyncStat
                 name: RString::from(name),
                 arity: 0,
                 function: Arc::new(f),
+                reentrant: false,
             }),
         );
 
@@ -553,6 +614,7 @@ impl<RET: IntoFFIVal, FN: Fn() -> RET + SendSyncStatic> RegisterFFIFn<FN, Wrappe
                 name: RString::from(name),
                 arity: 0,
                 function: Arc::new(f),
+                reentrant: false,
             }),
         );
 
@@ -591,6 +653,7 @@ pub enum FFIValue {
     IntV(isize),
     Void,
     StringV(RString),
+    /// Converts to and from an immutable Steel vector.
     Vector(RVec<FFIValue>),
 
     CharV {
@@ -609,11 +672,41 @@ pub enum FFIValue {
         #[sabi(unsafe_opaque_field)]
         fut: FfiFuture<RResult<FFIValue, RBoxError>>,
     }, // Future(Pin<RBox<dyn Future<Output = RResult<FFIValue, RBoxError>>>>),
+
+    // New variants go at the end, so that the discriminants of the existing ones stay the same
+    SymbolV(RString),
+    /// Converts to and from a Steel list.
+    List(RVec<FFIValue>),
+    ByteVector(RVec<u8>),
+    Struct(FFIStruct),
+    Callback(FFICallback),
 }
 
-impl std::hash::Hash for FFIValue {
-    fn hash<H: std::hash::Hasher>(&self, _state: &mut H) {
-        todo!()
+impl Hash for FFIValue {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+
+        match self {
+            Self::BoolV(b) => b.hash(state),
+            Self::NumV(n) => n.to_bits().hash(state),
+            Self::IntV(i) => i.hash(state),
+            Self::StringV(s) | Self::SymbolV(s) => s.hash(state),
+            Self::List(v) | Self::Vector(v) => v.hash(state),
+            Self::ByteVector(b) => b.hash(state),
+            Self::Struct(s) => {
+                s.name.hash(state);
+                s.fields.hash(state);
+            }
+            Self::CharV { c } => c.hash(state),
+            // Hash maps are unordered, so stick to something that doesn't depend on the order
+            Self::HashMap(h) => h.len().hash(state),
+            // Everything else only compares equal to itself by identity, if at all
+            Self::BoxedFunction(_)
+            | Self::Callback(_)
+            | Self::Void
+            | Self::Custom { .. }
+            | Self::Future { .. } => {}
+        }
     }
 }
 
@@ -624,9 +717,13 @@ impl PartialEq for FFIValue {
             (Self::BoolV(l), Self::BoolV(r)) => l == r,
             (Self::IntV(l), Self::IntV(r)) => l == r,
             (Self::StringV(l), Self::StringV(r)) => l == r,
+            (Self::SymbolV(l), Self::SymbolV(r)) => l == r,
             (Self::CharV { c: l }, Self::CharV { c: r }) => l == r,
             (Self::Void, Self::Void) => true,
+            (Self::List(l), Self::List(r)) => l == r,
             (Self::Vector(l), Self::Vector(r)) => l == r,
+            (Self::ByteVector(l), Self::ByteVector(r)) => l == r,
+            (Self::Struct(l), Self::Struct(r)) => l == r,
             (Self::HashMap(l), Self::HashMap(r)) => l == r,
             (_, _) => false,
         }
@@ -646,7 +743,12 @@ impl std::fmt::Debug for FFIValue {
             FFIValue::CharV { c } => write!(f, "{}", c),
             FFIValue::Void => write!(f, "#<void>"),
             FFIValue::StringV(s) => write!(f, "{}", s),
-            FFIValue::Vector(v) => write!(f, "{:?}", v),
+            FFIValue::SymbolV(s) => write!(f, "'{}", s),
+            FFIValue::List(v) => write!(f, "{:?}", v),
+            FFIValue::Vector(v) => write!(f, "#{:?}", v),
+            FFIValue::ByteVector(b) => write!(f, "#u8{:?}", b),
+            FFIValue::Struct(s) => write!(f, "{:?}", s),
+            FFIValue::Callback(c) => write!(f, "{:?}", c),
             FFIValue::HashMap(h) => write!(f, "{:?}", h),
             FFIValue::Future { .. } => write!(f, "#<future>"),
         }
@@ -666,11 +768,32 @@ impl FFIValue {
             Self::Void => Ok(SteelVal::Void),
             // TODO: I think this might clone the string, its also a little suspect
             Self::StringV(s) => Ok(SteelVal::StringV(s.to_string().into())),
-            Self::Vector(v) => v
+            Self::SymbolV(s) => Ok(SteelVal::SymbolV(s.to_string().into())),
+            Self::List(v) => v
                 .into_iter()
                 .map(|x| x.as_steelval())
                 .collect::<Result<_>>()
                 .map(SteelVal::ListV),
+            Self::Vector(v) => v
+                .into_iter()
+                .map(|x| x.as_steelval())
+                .collect::<Result<_>>()
+                .map(Gc::new)
+                .map(SteelVal::VectorV),
+            Self::ByteVector(b) => Ok(SteelVal::ByteVector(Gc::new(RefCell::new(b.to_vec())))),
+            Self::Struct(s) => {
+                let fields = s
+                    .fields
+                    .iter()
+                    .map(|x| x.as_steelval())
+                    .collect::<Result<Vec<_>>>()?;
+
+                struct_to_steelval(&s.name, &fields)
+            }
+            Self::Callback(c) => c.procedure(),
+            Self::Custom { custom } => Ok(SteelVal::Custom(Gc::new(RefCell::new(Box::new(
+                custom.clone(),
+            ))))),
 
             Self::HashMap(h) => h
                 .into_iter()
@@ -697,8 +820,8 @@ impl FFIValue {
             //         }
             //     }
             // })))
-            _v => {
-                todo!("Missing enum variant not accounted for during FFIValue -> SteelVal conversion!")
+            Self::Future { .. } => {
+                stop!(TypeMismatch => "futures can only be moved across the FFI boundary, not shared")
             }
         }
     }
@@ -718,11 +841,29 @@ impl IntoSteelVal for FFIValue {
             Self::Void => Ok(SteelVal::Void),
             // TODO: I think this might clone the string, its also a little suspect
             Self::StringV(s) => Ok(SteelVal::StringV(s.into_string().into())),
-            Self::Vector(v) => v
+            Self::SymbolV(s) => Ok(SteelVal::SymbolV(s.into_string().into())),
+            Self::List(v) => v
                 .into_iter()
                 .map(|x| x.into_steelval())
                 .collect::<Result<_>>()
                 .map(SteelVal::ListV),
+            Self::Vector(v) => v
+                .into_iter()
+                .map(|x| x.into_steelval())
+                .collect::<Result<_>>()
+                .map(Gc::new)
+                .map(SteelVal::VectorV),
+            Self::ByteVector(b) => Ok(SteelVal::ByteVector(Gc::new(RefCell::new(b.into_vec())))),
+            Self::Struct(s) => {
+                let fields = s
+                    .fields
+                    .into_iter()
+                    .map(|x| x.into_steelval())
+                    .collect::<Result<Vec<_>>>()?;
+
+                struct_to_steelval(&s.name, &fields)
+            }
+            Self::Callback(c) => c.procedure(),
 
             Self::HashMap(h) => h
                 .into_iter()
@@ -762,6 +903,8 @@ pub struct FFIBoxedDynFunction {
     #[sabi(unsafe_opaque_field)]
     pub function:
         Arc<dyn Fn(RVec<FFIValue>) -> RResult<FFIValue, RBoxError> + Send + Sync + 'static>,
+    // Set when the function takes arguments that can be callbacks, see `FromFFIVal::TAKES_CALLBACKS`
+    pub reentrant: bool,
 }
 
 impl std::fmt::Debug for FFIBoxedDynFunction {
@@ -770,6 +913,159 @@ impl std::fmt::Debug for FFIBoxedDynFunction {
     }
 }
 
+/// An instance of a Steel struct. Crossing the boundary, the struct is matched up by name
+/// with a struct type that was defined in Steel, so the fields have to be in the same order.
+#[repr(C)]
+#[derive(StableAbi, Debug, PartialEq)]
+pub struct FFIStruct {
+    pub name: RString,
+    pub fields: RVec<FFIValue>,
+}
+
+fn struct_to_steelval(name: &str, fields: &[SteelVal]) -> Result<SteelVal> {
+    let name: InternedString = name.into();
+
+    if let Some(descriptor) = lookup_struct_type_descriptor(name) {
+        Ok(SteelVal::CustomStruct(Gc::new(RefCell::new(
+            UserDefinedStruct::new(name, descriptor, fields),
+        ))))
+    } else {
+        stop!(TypeMismatch => "struct type {} is not defined, and so the struct cannot be passed across the FFI boundary", name.resolve())
+    }
+}
+
+/// A Steel procedure that was passed to an FFI function.
+///
+/// Calling it runs the procedure on the engine that passed it along, so it has to be called
+/// from within an FFI function call made by that engine. Calling it from anywhere else, for
+/// instance after the call returned or from another engine, returns an error instead.
+///
+/// The callback only crosses the boundary as an opaque pointer and a set of functions
+/// compiled on this side, so the dylib never touches the procedure itself. The context is
+/// reference counted without synchronization, which is why this is neither `Send` nor `Sync`.
+// `FFIValue` holds boxed functions as an `Arc`, which rustc can't prove is FFI safe
+#[allow(improper_ctypes_definitions)]
+#[repr(C)]
+#[derive(StableAbi)]
+pub struct FFICallback {
+    context: *const c_void,
+    call: extern "C" fn(*const c_void, RVec<FFIValue>) -> RResult<FFIValue, RBoxError>,
+    clone: extern "C" fn(*const c_void),
+    drop: extern "C" fn(*const c_void),
+}
+
+// What the context of a callback points to
+struct CallbackContext {
+    procedure: SteelVal,
+    // The engine that was running the FFI call when the procedure was passed along
+    engine: Option<EngineId>,
+}
+
+#[allow(improper_ctypes_definitions)]
+#[sabi_extern_fn]
+fn call_callback(context: *const c_void, args: RVec<FFIValue>) -> RResult<FFIValue, RBoxError> {
+    // SAFETY: The context is kept alive by the callback that is being called
+    let context = unsafe { &*(context as *const CallbackContext) };
+
+    let result = args
+        .into_iter()
+        .map(|x| x.into_steelval())
+        .collect::<Result<List<_>>>()
+        .and_then(|args| {
+            context
+                .engine
+                .as_ref()
+                .and_then(|engine| {
+                    with_callback_context(engine, |ctx| {
+                        ctx.call_function_many_args(&context.procedure, args)
+                    })
+                })
+                .unwrap_or_else(|| {
+                    stop!(Generic => "Steel procedures can only be called from within an FFI function call made by the engine they came from")
+                })
+        })
+        .and_then(|output| as_ffi_value(&output));
+
+    match result {
+        Ok(output) => RResult::ROk(output),
+        Err(e) => RResult::RErr(ffi_error(e.to_string().into())),
+    }
+}
+
+#[sabi_extern_fn]
+fn clone_callback(context: *const c_void) {
+    // SAFETY: The context was created by `Rc::into_raw` in `FFICallback::new`
+    unsafe { Rc::increment_strong_count(context as *const CallbackContext) }
+}
+
+#[sabi_extern_fn]
+fn drop_callback(context: *const c_void) {
+    // SAFETY: Every callback owns one strong count of the context
+    unsafe { Rc::decrement_strong_count(context as *const CallbackContext) }
+}
+
+impl FFICallback {
+    fn new(procedure: SteelVal) -> Self {
+        let context = Rc::new(CallbackContext {
+            procedure,
+            engine: callback_engine(),
+        });
+
+        Self {
+            context: Rc::into_raw(context) as *const c_void,
+            call: call_callback,
+            clone: clone_callback,
+            drop: drop_callback,
+        }
+    }
+
+    pub fn call(&self, args: impl IntoIterator<Item = FFIValue>) -> RResult<FFIValue, RBoxError> {
+        (self.call)(self.context, args.into_iter().collect())
+    }
+
+    // Turns the callback back into the procedure, as long as it was made on this side of the
+    // boundary and is going back into the engine it came from.
+    fn procedure(&self) -> Result<SteelVal> {
+        if self.call as *const () != call_callback as *const () {
+            stop!(TypeMismatch => "callbacks can only be passed back to the Steel instance that created them");
+        }
+
+        // SAFETY: Only `FFICallback::new` pairs `call_callback` with a context
+        let context = unsafe { &*(self.context as *const CallbackContext) };
+
+        if context.engine.is_none() || context.engine != callback_engine() {
+            stop!(TypeMismatch => "callbacks can only be passed back to the engine that created them");
+        }
+
+        Ok(context.procedure.clone())
+    }
+}
+
+impl Clone for FFICallback {
+    fn clone(&self) -> Self {
+        (self.clone)(self.context);
+
+        Self {
+            context: self.context,
+            call: self.call,
+            clone: self.clone,
+            drop: self.drop,
+        }
+    }
+}
+
+impl Drop for FFICallback {
+    fn drop(&mut self) {
+        (self.drop)(self.context)
+    }
+}
+
+impl std::fmt::Debug for FFICallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#<callback:{:p}>", self.context)
+    }
+}
+
 pub fn as_ffi_value(value: &SteelVal) -> Result<FFIValue> {
     match value {
         SteelVal::BoolV(b) => Ok(FFIValue::BoolV(*b)),
@@ -799,9 +1095,32 @@ pub fn as_ffi_value(value: &SteelVal) -> Result<FFIValue> {
             .into_iter()
             .map(as_ffi_value)
             .collect::<Result<_>>()
+            .map(FFIValue::List),
+        SteelVal::VectorV(v) => v
+            .iter()
+            .map(as_ffi_value)
+            .collect::<Result<_>>()
             .map(FFIValue::Vector),
+        SteelVal::ByteVector(b) => Ok(FFIValue::ByteVector(b.borrow().as_slice().into())),
+        SteelVal::CustomStruct(s) => {
+            let s = s.borrow();
+
+            Ok(FFIValue::Struct(FFIStruct {
+                name: s.name.resolve().into(),
+                fields: s.fields.iter().map(as_ffi_value).collect::<Result<_>>()?,
+            }))
+        }
 
         SteelVal::StringV(s) => Ok(FFIValue::StringV(s.as_str().into())),
+        SteelVal::SymbolV(s) => Ok(FFIValue::SymbolV(s.as_str().into())),
+
+        SteelVal::Closure(_)
+        | SteelVal::FuncV(_)
+        | SteelVal::MutFunc(_)
+        | SteelVal::BoxedFunction(_)
+        | SteelVal::ContractedFunction(_) => {
+            Ok(FFIValue::Callback(FFICallback::new(value.clone())))
+        }
 
         _ => {
             stop!(TypeMismatch => "Cannot proceed with the conversion from steelval to FFI Value. This will only succeed for a subset of values deemed as FFI-safe-enough: {:?}", value)
@@ -835,6 +1154,8 @@ impl FFIBoxedDynFunction {
             name: Some(StaticOrRcStr::Owned(Arc::new(name))),
             arity: Some(self.arity),
             function: Arc::new(function),
+            reentrant: self.reentrant,
+            struct_function: None,
         }
     }
 }
@@ -843,6 +1164,7 @@ impl FFIBoxedDynFunction {
 impl From<FFIBoxedDynFunction> for BoxedDynFunction {
     fn from(value: FFIBoxedDynFunction) -> Self {
         let name = value.name.into_string();
+        let reentrant = value.reentrant;
 
         let function = move |args: &[SteelVal]| -> crate::rvals::Result<SteelVal> {
             // Convert the arguments to the FFI types before passing through
@@ -863,6 +1185,8 @@ impl From<FFIBoxedDynFunction> for BoxedDynFunction {
             name: Some(StaticOrRcStr::Owned(Arc::new(name))),
             arity: Some(value.arity),
             function: Arc::new(function),
+            reentrant,
+            struct_function: None,
         }
     }
 }
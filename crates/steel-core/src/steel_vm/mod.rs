@@ -10,8 +10,10 @@ pub mod engine;
 pub mod ffi;
 mod lazy_stream;
 mod meta;
+pub mod packages;
 pub mod primitives;
 pub mod register_fn;
+pub mod test_runner;
 #[cfg(test)]
 mod test_util;
 #[cfg(test)]
@@ -0,0 +1,1182 @@
+//! Installs cogs along with their dependencies.
+//!
+//! A package is a directory with a `cog.scm` manifest:
+//!
+//! ```scheme
+//! (define package-name 'my/app)
+//! (define version "0.1.0")
+//!
+//! (define dependencies
+//!   '((steel/lists "^0.1" #:path "../lists")
+//!     (json "~1.2" #:git "https://github.com/example/json.git")
+//!     (yaml "*" #:git "https://github.com/example/yaml.git" #:rev "main")))
+//!
+//! ;; Optional - crates that are built with `cargo-steel-lib` when the package is installed
+//! (define dylibs '("native"))
+//! ```
+//!
+//! Dependencies either come from a local path, or from a git repository whose tags that parse
+//! as versions (`1.2.0` or `v1.2.0`) are the versions on offer. `#:rev` pins a git dependency to
+//! a branch, tag or commit instead. Resolution picks the newest version of each package that
+//! satisfies every requirement on it, and the result is written to `cog-lock.scm` next to the
+//! manifest. Later installs stick to the locked versions for as long as they still satisfy the
+//! manifest.
+//!
+//! Packages are installed to `cogs/<package-name>` under either the project's `.steel`
+//! directory or `$STEEL_HOME`, both of which are searched by `require`. Native libraries always
+//! go to `$STEEL_HOME/native`, since that is where they are loaded from.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # extern crate steel;
+//! # use steel::steel_vm::packages::PackageManager;
+//! let installed = PackageManager::project("my-app").install("my-app").unwrap();
+//!
+//! for package in installed {
+//!     println!("{} {}", package.name, package.version);
+//! }
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::{self, Write},
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use semver::{Version, VersionReq};
+
+use crate::{
+    parser::{ast::ExprKind, parser::Parser},
+    rerrs::{ErrorKind, SteelErr},
+    rvals::{Result, SteelVal},
+};
+
+pub const MANIFEST_FILE: &str = "cog.scm";
+pub const LOCK_FILE: &str = "cog-lock.scm";
+/// Packages installed for a single project go in this directory, next to the manifest.
+pub const PROJECT_DIRECTORY: &str = ".steel";
+
+// Resolution goes round again whenever a newly picked version brings in new requirements. This
+// is only a guard against requirements that keep flip-flopping between versions.
+const MAX_RESOLUTION_ROUNDS: usize = 64;
+
+// Never copied into an installed package
+const IGNORED_DIRECTORIES: &[&str] = &[".git", PROJECT_DIRECTORY, "target"];
+
+/// The directories `require` searches for installed packages, starting from the file doing the
+/// requiring: the `.steel/cogs` directory of the project the file belongs to, followed by
+/// `$STEEL_HOME/cogs`.
+pub fn package_search_paths(file: &Path) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+
+    let start = if file.as_os_str().is_empty() {
+        std::env::current_dir().ok()
+    } else {
+        Some(file.to_path_buf())
+    };
+
+    if let Some(project) = start.as_deref().and_then(find_project) {
+        let cogs = project.join(PROJECT_DIRECTORY).join("cogs");
+
+        if cogs.is_dir() {
+            paths.push(cogs);
+        }
+    }
+
+    if let Ok(home) = std::env::var("STEEL_HOME") {
+        paths.push(PathBuf::from(home).join("cogs"));
+    }
+
+    paths
+}
+
+// The closest directory containing a manifest
+fn find_project(path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .find(|x| x.join(MANIFEST_FILE).is_file())
+        .map(Path::to_path_buf)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Path(PathBuf),
+    Git { url: String, rev: Option<String> },
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Path(path) => write!(f, "{}", path.display()),
+            Source::Git { url, rev: None } => write!(f, "{url}"),
+            Source::Git {
+                url,
+                rev: Some(rev),
+            } => write!(f, "{url}#{rev}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Dependency {
+    pub name: String,
+    pub requirement: VersionReq,
+    pub source: Source,
+}
+
+/// The contents of a `cog.scm` file.
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    pub name: String,
+    pub version: Version,
+    pub dependencies: Vec<Dependency>,
+    /// Crates to build with `cargo-steel-lib`, relative to the package
+    pub dylibs: Vec<PathBuf>,
+}
+
+impl Manifest {
+    pub fn from_directory(directory: impl AsRef<Path>) -> Result<Self> {
+        let directory = directory.as_ref();
+        let path = directory.join(MANIFEST_FILE);
+
+        let source = fs::read_to_string(&path).map_err(|e| {
+            SteelErr::new(
+                ErrorKind::Io,
+                format!("unable to read {}: {e}", path.display()),
+            )
+        })?;
+
+        Self::parse(&source, directory)
+    }
+
+    /// Parses a manifest, with relative paths taken to be relative to `directory`.
+    pub fn parse(source: &str, directory: &Path) -> Result<Self> {
+        let mut definitions = definitions(source)?;
+
+        let name = match definitions.remove("package-name") {
+            Some(name) => name_of(&name)?,
+            None => stop!(Generic => "{} is missing a package-name", MANIFEST_FILE),
+        };
+
+        let version = match definitions.remove("version") {
+            Some(SteelVal::StringV(s)) => parse_version(&s)?,
+            Some(other) => stop!(TypeMismatch => "version should be a string, found: {}", other),
+            None => stop!(Generic => "{} for {} is missing a version", MANIFEST_FILE, name),
+        };
+
+        let dependencies = match definitions.remove("dependencies") {
+            Some(SteelVal::ListV(l)) => l
+                .iter()
+                .map(|x| parse_dependency(x, directory))
+                .collect::<Result<_>>()?,
+            Some(other) => {
+                stop!(TypeMismatch => "dependencies should be a list, found: {}", other)
+            }
+            None => Vec::new(),
+        };
+
+        let dylibs = match definitions.remove("dylibs") {
+            Some(SteelVal::ListV(l)) => l
+                .iter()
+                .map(|x| match x {
+                    SteelVal::StringV(s) => Ok(PathBuf::from(s.as_str())),
+                    other => {
+                        stop!(TypeMismatch => "dylibs should be a list of paths, found: {}", other)
+                    }
+                })
+                .collect::<Result<_>>()?,
+            Some(other) => stop!(TypeMismatch => "dylibs should be a list, found: {}", other),
+            None => Vec::new(),
+        };
+
+        Ok(Manifest {
+            name,
+            version,
+            dependencies,
+            dylibs,
+        })
+    }
+}
+
+// The values of the top level definitions in a manifest or lockfile. These files are read, not
+// run, so every value has to be a literal.
+fn definitions(source: &str) -> Result<HashMap<String, SteelVal>> {
+    let mut definitions = HashMap::new();
+
+    for expr in Parser::parse(source)? {
+        if let ExprKind::Define(define) = expr {
+            if let Some(name) = define.name.atom_identifier() {
+                let name = name.resolve().to_string();
+                definitions.insert(name, SteelVal::try_from(define.body)?);
+            }
+        }
+    }
+
+    Ok(definitions)
+}
+
+fn name_of(value: &SteelVal) -> Result<String> {
+    match value {
+        SteelVal::SymbolV(s) | SteelVal::StringV(s) => Ok(s.to_string()),
+        other => stop!(TypeMismatch => "expected a package name, found: {}", other),
+    }
+}
+
+fn parse_version(version: &str) -> Result<Version> {
+    Version::parse(version).map_err(|e| {
+        SteelErr::new(
+            ErrorKind::Generic,
+            format!("invalid version {version:?}: {e}"),
+        )
+    })
+}
+
+fn parse_requirement(requirement: &str) -> Result<VersionReq> {
+    VersionReq::parse(requirement).map_err(|e| {
+        SteelErr::new(
+            ErrorKind::Generic,
+            format!("invalid version requirement {requirement:?}: {e}"),
+        )
+    })
+}
+
+// An entry looks like `(name "version" #:path "..." #:git "..." #:rev "...")`, where the version
+// is a requirement in a manifest and an exact version in a lockfile.
+fn parse_entry(entry: &SteelVal, directory: &Path) -> Result<(String, Option<String>, Source)> {
+    let items = match entry {
+        SteelVal::ListV(l) if !l.is_empty() => l.iter().cloned().collect::<Vec<_>>(),
+        other => {
+            stop!(TypeMismatch => "expected a list starting with the package name, found: {}", other)
+        }
+    };
+
+    let name = name_of(&items[0])?;
+
+    let mut rest = &items[1..];
+    let mut version = None;
+
+    if let Some(SteelVal::StringV(s)) = rest.first() {
+        version = Some(s.to_string());
+        rest = &rest[1..];
+    }
+
+    let mut options = HashMap::new();
+
+    for pair in rest.chunks(2) {
+        match pair {
+            [SteelVal::SymbolV(key), SteelVal::StringV(value)] => {
+                options.insert(key.trim_start_matches("#:").to_string(), value.to_string());
+            }
+            _ => {
+                stop!(BadSyntax => "the options for {} should be keyword and string pairs, i.e. #:path \"../lib\"", name)
+            }
+        }
+    }
+
+    let source = match (options.remove("path"), options.remove("git")) {
+        (Some(path), None) => Source::Path(directory.join(path)),
+        (None, Some(url)) => Source::Git {
+            url,
+            rev: options.remove("rev"),
+        },
+        _ => stop!(BadSyntax => "{} needs exactly one of #:path or #:git", name),
+    };
+
+    if let Some(key) = options.keys().next() {
+        stop!(BadSyntax => "unknown option for {}: #:{}", name, key);
+    }
+
+    // These end up on the git command line, where they would be read as options
+    if let Source::Git { url, rev } = &source {
+        if let Some(value) = std::iter::once(url).chain(rev).find(|x| x.starts_with('-')) {
+            stop!(BadSyntax => "invalid git source for {}: {}", name, value);
+        }
+    }
+
+    Ok((name, version, source))
+}
+
+fn parse_dependency(entry: &SteelVal, directory: &Path) -> Result<Dependency> {
+    let (name, requirement, source) = parse_entry(entry, directory)?;
+
+    Ok(Dependency {
+        name,
+        requirement: match requirement {
+            Some(requirement) => parse_requirement(&requirement)?,
+            None => VersionReq::STAR,
+        },
+        source,
+    })
+}
+
+/// A package picked during resolution. Git sources always refer to a commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: Version,
+    pub source: Source,
+}
+
+/// Reads the lockfile of the project in `project`, if there is one.
+pub fn read_lockfile(project: impl AsRef<Path>) -> Result<Option<Vec<LockedPackage>>> {
+    let project = project.as_ref();
+    let path = project.join(LOCK_FILE);
+
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    let mut definitions = definitions(&fs::read_to_string(path)?)?;
+
+    let packages = match definitions.remove("packages") {
+        Some(SteelVal::ListV(l)) => l,
+        _ => stop!(Generic => "{} is missing its list of packages", LOCK_FILE),
+    };
+
+    packages
+        .iter()
+        .map(|entry| {
+            let (name, version, source) = parse_entry(entry, project)?;
+
+            let version = match version {
+                Some(version) => parse_version(&version)?,
+                None => stop!(Generic => "{} is missing the version of {}", LOCK_FILE, name),
+            };
+
+            Ok(LockedPackage {
+                name,
+                version,
+                source,
+            })
+        })
+        .collect::<Result<Vec<_>>>()
+        .map(Some)
+}
+
+fn write_lockfile(project: &Path, packages: &[LockedPackage]) -> Result<()> {
+    let mut output = String::from(";; Generated by `steel pkg`, do not edit by hand\n");
+    output.push_str("(define packages\n  '(");
+
+    for (i, package) in packages.iter().enumerate() {
+        if i > 0 {
+            output.push_str("\n    ");
+        }
+
+        write!(
+            output,
+            "({} {:?}",
+            package.name,
+            package.version.to_string()
+        )
+        .unwrap();
+
+        match &package.source {
+            Source::Path(path) => {
+                // Keep paths relative, so that the lockfile can be checked in
+                let path = path.strip_prefix(project).unwrap_or(path);
+                write!(output, " #:path {:?}", path.to_string_lossy()).unwrap();
+            }
+            Source::Git { url, rev } => {
+                write!(output, " #:git {url:?}").unwrap();
+
+                if let Some(rev) = rev {
+                    write!(output, " #:rev {rev:?}").unwrap();
+                }
+            }
+        }
+
+        output.push(')');
+    }
+
+    output.push_str("))\n");
+
+    fs::write(project.join(LOCK_FILE), output)?;
+
+    Ok(())
+}
+
+/// Where packages get installed, and the operations on them.
+#[derive(Debug, Clone)]
+pub struct PackageManager {
+    root: PathBuf,
+}
+
+impl PackageManager {
+    /// Installs packages into the `.steel` directory of the project in `project`.
+    pub fn project(project: impl AsRef<Path>) -> Self {
+        PackageManager {
+            root: project.as_ref().join(PROJECT_DIRECTORY),
+        }
+    }
+
+    /// Installs packages into `$STEEL_HOME`.
+    pub fn global() -> Result<Self> {
+        match std::env::var("STEEL_HOME") {
+            Ok(home) => Ok(PackageManager {
+                root: PathBuf::from(home),
+            }),
+            Err(_) => stop!(Generic => "STEEL_HOME needs to be set to install packages globally"),
+        }
+    }
+
+    pub fn cogs_directory(&self) -> PathBuf {
+        self.root.join("cogs")
+    }
+
+    // Clones of git dependencies, kept around so that later installs only need to fetch
+    fn git_directory(&self, url: &str) -> PathBuf {
+        let name = url
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+
+        self.root.join("git").join(name)
+    }
+
+    /// Resolves and installs the dependencies of the project in `project`, sticking to the
+    /// versions in its lockfile where they still fit. The lockfile is written out afterwards.
+    pub fn install(&self, project: impl AsRef<Path>) -> Result<Vec<LockedPackage>> {
+        let locked = read_lockfile(project.as_ref())?.unwrap_or_default();
+        self.install_with_lock(project.as_ref(), locked)
+    }
+
+    /// Like [`PackageManager::install`], except the lockfile is ignored, so every dependency is
+    /// moved to the newest version that is allowed.
+    pub fn update(&self, project: impl AsRef<Path>) -> Result<Vec<LockedPackage>> {
+        self.install_with_lock(project.as_ref(), Vec::new())
+    }
+
+    fn install_with_lock(
+        &self,
+        project: &Path,
+        locked: Vec<LockedPackage>,
+    ) -> Result<Vec<LockedPackage>> {
+        let manifest = Manifest::from_directory(project)?;
+
+        let mut resolver = Resolver {
+            manager: self,
+            fetched: HashSet::new(),
+            locked: locked.into_iter().map(|x| (x.name.clone(), x)).collect(),
+        };
+
+        let resolved = resolver.resolve(&manifest)?;
+
+        for package in &resolved {
+            self.install_package(package)?;
+        }
+
+        let packages = resolved.into_iter().map(|x| x.locked).collect::<Vec<_>>();
+
+        write_lockfile(project, &packages)?;
+
+        Ok(packages)
+    }
+
+    /// The manifests of the packages that are currently installed.
+    pub fn installed(&self) -> Result<Vec<Manifest>> {
+        let mut installed = Vec::new();
+        let cogs = self.cogs_directory();
+
+        if cogs.is_dir() {
+            collect_installed(&cogs, &mut installed)?;
+        }
+
+        installed.sort_by(|l, r| l.name.cmp(&r.name));
+
+        Ok(installed)
+    }
+
+    fn install_package(&self, package: &Resolved) -> Result<()> {
+        let source = match &package.locked.source {
+            Source::Path(path) => path.clone(),
+            Source::Git { url, rev } => {
+                let repository = self.git_directory(url);
+                let commit = rev.as_deref().unwrap_or("HEAD");
+
+                git(
+                    Some(&repository),
+                    &["checkout", "--quiet", "--detach", commit, "--"],
+                )?;
+
+                repository
+            }
+        };
+
+        let destination = self.cogs_directory().join(&package.locked.name);
+
+        if destination.exists() {
+            fs::remove_dir_all(&destination)?;
+        }
+
+        copy_directory(&source, &destination)?;
+
+        for dylib in &package.manifest.dylibs {
+            build_dylib(&source.join(dylib))?;
+        }
+
+        log::info!(
+            "Installed {} {} to {}",
+            package.locked.name,
+            package.locked.version,
+            destination.display()
+        );
+
+        Ok(())
+    }
+}
+
+// Packages can be namespaced, i.e. `steel/lists` lives in `cogs/steel/lists`
+fn collect_installed(directory: &Path, installed: &mut Vec<Manifest>) -> Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+
+        if !path.is_dir() {
+            continue;
+        }
+
+        if path.join(MANIFEST_FILE).is_file() {
+            installed.push(Manifest::from_directory(&path)?);
+        } else {
+            collect_installed(&path, installed)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn copy_directory(source: &Path, destination: &Path) -> Result<()> {
+    fs::create_dir_all(destination)?;
+
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let path = entry.path();
+        let target = destination.join(entry.file_name());
+
+        if path.is_dir() {
+            if IGNORED_DIRECTORIES.iter().any(|x| entry.file_name() == *x) {
+                continue;
+            }
+
+            copy_directory(&path, &target)?;
+        } else {
+            fs::copy(&path, &target)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn build_dylib(path: &Path) -> Result<()> {
+    log::info!("Building native library in {}", path.display());
+
+    let status = Command::new("cargo-steel-lib")
+        .current_dir(path)
+        .status()
+        .map_err(|e| {
+            SteelErr::new(
+                ErrorKind::Io,
+                format!("unable to run cargo-steel-lib, is it installed? {e}"),
+            )
+        })?;
+
+    if !status.success() {
+        stop!(Generic => "failed to build the native library in {}", path.display());
+    }
+
+    Ok(())
+}
+
+fn git(directory: Option<&Path>, args: &[&str]) -> Result<String> {
+    let mut command = Command::new("git");
+
+    if let Some(directory) = directory {
+        command.arg("-C").arg(directory);
+    }
+
+    let output = command
+        .args(args)
+        .output()
+        .map_err(|e| SteelErr::new(ErrorKind::Io, format!("unable to run git: {e}")))?;
+
+    if !output.status.success() {
+        stop!(Generic => "git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// A version of a package that could be picked, before its manifest has been read
+struct Candidate {
+    version: Version,
+    source: Source,
+}
+
+struct Resolved {
+    locked: LockedPackage,
+    manifest: Manifest,
+}
+
+struct Requirement<'a> {
+    requirement: &'a VersionReq,
+    source: &'a Source,
+    required_by: &'a str,
+}
+
+struct Resolver<'a> {
+    manager: &'a PackageManager,
+    // Git repositories that are up to date for this run
+    fetched: HashSet<String>,
+    locked: HashMap<String, LockedPackage>,
+}
+
+impl<'a> Resolver<'a> {
+    fn resolve(&mut self, root: &Manifest) -> Result<Vec<Resolved>> {
+        let mut selected: BTreeMap<String, Resolved> = BTreeMap::new();
+
+        for _ in 0..MAX_RESOLUTION_ROUNDS {
+            let mut requirements: BTreeMap<&str, Vec<Requirement>> = BTreeMap::new();
+
+            for manifest in std::iter::once(root).chain(selected.values().map(|x| &x.manifest)) {
+                for dependency in &manifest.dependencies {
+                    // Packages can't depend on the project they're being installed for
+                    if dependency.name == root.name {
+                        continue;
+                    }
+
+                    requirements
+                        .entry(dependency.name.as_str())
+                        .or_default()
+                        .push(Requirement {
+                            requirement: &dependency.requirement,
+                            source: &dependency.source,
+                            required_by: &manifest.name,
+                        });
+                }
+            }
+
+            let mut picked = Vec::new();
+
+            for (name, requirements) in &requirements {
+                let satisfied = selected.get(*name).map_or(false, |x| {
+                    requirements
+                        .iter()
+                        .all(|r| r.requirement.matches(&x.locked.version))
+                });
+
+                if !satisfied {
+                    picked.push(self.select(name, requirements)?);
+                }
+            }
+
+            let unused = selected
+                .keys()
+                .filter(|x| !requirements.contains_key(x.as_str()))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if picked.is_empty() && unused.is_empty() {
+                return Ok(selected.into_values().collect());
+            }
+
+            for name in unused {
+                selected.remove(&name);
+            }
+
+            for package in picked {
+                selected.insert(package.locked.name.clone(), package);
+            }
+        }
+
+        stop!(Generic => "unable to settle on a set of package versions, the requirements keep changing")
+    }
+
+    // Picks the newest version that satisfies all of the requirements, preferring the locked
+    // version if it still does. The first requirement decides where the package comes from,
+    // which puts the project's own dependencies first.
+    fn select(&mut self, name: &str, requirements: &[Requirement]) -> Result<Resolved> {
+        let source = requirements[0].source;
+        let fits = |version: &Version| requirements.iter().all(|r| r.requirement.matches(version));
+
+        let locked = self
+            .locked
+            .get(name)
+            .filter(|x| fits(&x.version) && same_origin(&x.source, source))
+            .map(|x| Candidate {
+                version: x.version.clone(),
+                source: x.source.clone(),
+            });
+
+        let candidate = match locked {
+            Some(candidate) => candidate,
+            None => {
+                let mut candidates = self.candidates(name, source)?;
+                candidates.sort_by(|l, r| r.version.cmp(&l.version));
+
+                match candidates.iter().position(|x| fits(&x.version)) {
+                    Some(index) => candidates.swap_remove(index),
+                    None => {
+                        let wanted = requirements
+                            .iter()
+                            .map(|r| format!("{} (required by {})", r.requirement, r.required_by))
+                            .collect::<Vec<_>>()
+                            .join(", ");
+
+                        let available = candidates
+                            .iter()
+                            .map(|x| x.version.to_string())
+                            .collect::<Vec<_>>()
+                            .join(", ");
+
+                        stop!(Generic => "no version of {} from {} matches {} - available versions: {}", name, source, wanted, if available.is_empty() { "none" } else { &available });
+                    }
+                }
+            }
+        };
+
+        let manifest = self.manifest(&candidate.source)?;
+
+        if manifest.name != name {
+            stop!(Generic => "expected to find {} at {}, found {} instead", name, candidate.source, manifest.name);
+        }
+
+        if manifest.version != candidate.version {
+            stop!(Generic => "{} at {} is version {}, expected {}", name, candidate.source, manifest.version, candidate.version);
+        }
+
+        Ok(Resolved {
+            locked: LockedPackage {
+                name: name.to_string(),
+                version: candidate.version,
+                source: candidate.source,
+            },
+            manifest,
+        })
+    }
+
+    fn candidates(&mut self, name: &str, source: &Source) -> Result<Vec<Candidate>> {
+        match source {
+            Source::Path(path) => {
+                let manifest = Manifest::from_directory(path)?;
+
+                if manifest.name != name {
+                    stop!(Generic => "expected to find {} at {}, found {} instead", name, path.display(), manifest.name);
+                }
+
+                Ok(vec![Candidate {
+                    version: manifest.version,
+                    source: source.clone(),
+                }])
+            }
+            Source::Git {
+                url,
+                rev: Some(rev),
+            } => {
+                let repository = self.fetch(url)?;
+                let commit = resolve_commit(&repository, rev)?;
+                let source = Source::Git {
+                    url: url.clone(),
+                    rev: Some(commit),
+                };
+
+                Ok(vec![Candidate {
+                    version: self.manifest(&source)?.version,
+                    source,
+                }])
+            }
+            Source::Git { url, rev: None } => {
+                let repository = self.fetch(url)?;
+
+                git(Some(&repository), &["tag", "--list"])?
+                    .lines()
+                    .filter_map(|tag| {
+                        let version = Version::parse(tag.trim_start_matches('v')).ok()?;
+                        Some((tag, version))
+                    })
+                    .map(|(tag, version)| {
+                        Ok(Candidate {
+                            version,
+                            source: Source::Git {
+                                url: url.clone(),
+                                rev: Some(resolve_commit(&repository, tag)?),
+                            },
+                        })
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    fn manifest(&mut self, source: &Source) -> Result<Manifest> {
+        match source {
+            Source::Path(path) => Manifest::from_directory(path),
+            Source::Git { url, rev } => {
+                let repository = self.fetch(url)?;
+                let commit = rev.as_deref().unwrap_or("HEAD");
+                let contents = git(
+                    Some(&repository),
+                    &["show", &format!("{commit}:{MANIFEST_FILE}"), "--"],
+                )?;
+
+                Manifest::parse(&contents, &repository)
+            }
+        }
+    }
+
+    // Clones the repository the first time around, and fetches on later runs
+    fn fetch(&mut self, url: &str) -> Result<PathBuf> {
+        let repository = self.manager.git_directory(url);
+
+        if self.fetched.contains(url) {
+            return Ok(repository);
+        }
+
+        if repository.join(".git").is_dir() {
+            git(Some(&repository), &["fetch", "--quiet", "--tags", "origin"])?;
+        } else {
+            fs::create_dir_all(&repository)?;
+            git(
+                None,
+                &["clone", "--quiet", "--", url, &repository.to_string_lossy()],
+            )?;
+        }
+
+        self.fetched.insert(url.to_string());
+
+        Ok(repository)
+    }
+}
+
+fn resolve_commit(repository: &Path, rev: &str) -> Result<String> {
+    // Prefer the remote branch, so that a branch follows upstream rather than the local clone
+    let remote = format!("origin/{rev}^{{commit}}");
+    let output = git(
+        Some(repository),
+        &[
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            "--end-of-options",
+            &remote,
+        ],
+    )
+    .or_else(|_| {
+        git(
+            Some(repository),
+            &[
+                "rev-parse",
+                "--verify",
+                "--end-of-options",
+                &format!("{rev}^{{commit}}"),
+            ],
+        )
+    })?;
+
+    Ok(output.trim().to_string())
+}
+
+// Whether a locked source can stand in for the one in the manifest
+fn same_origin(locked: &Source, wanted: &Source) -> bool {
+    match (locked, wanted) {
+        (Source::Path(l), Source::Path(r)) => l == r,
+        // A locked commit is only good for a pinned revision if it came from that revision,
+        // which can't be known without fetching, so pinned revisions are always resolved again
+        (Source::Git { url: l, .. }, Source::Git { url: r, rev: None }) => l == r,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod packages_tests {
+    use super::*;
+
+    use crate::tests::test_directory;
+
+    fn directory(name: &str) -> PathBuf {
+        test_directory("packages", name)
+    }
+
+    fn package(directory: &Path, name: &str, version: &str, dependencies: &str) {
+        fs::create_dir_all(directory).unwrap();
+        fs::write(
+            directory.join(MANIFEST_FILE),
+            format!(
+                "(define package-name '{name})\n(define version {version:?})\n(define dependencies '({dependencies}))\n"
+            ),
+        )
+        .unwrap();
+    }
+
+    fn git_repository(directory: &Path, name: &str, versions: &[&str]) -> String {
+        fs::create_dir_all(directory).unwrap();
+        git(Some(directory), &["init", "--quiet"]).unwrap();
+
+        for version in versions {
+            package(directory, name, version, "");
+            fs::write(
+                directory.join("lib.scm"),
+                format!("(provide version) (define version {version:?})"),
+            )
+            .unwrap();
+
+            git(Some(directory), &["add", "-A"]).unwrap();
+            git(
+                Some(directory),
+                &[
+                    "-c",
+                    "user.name=steel",
+                    "-c",
+                    "user.email=steel@example.com",
+                    "commit",
+                    "--quiet",
+                    "-m",
+                    version,
+                ],
+            )
+            .unwrap();
+            git(Some(directory), &["tag", &format!("v{version}")]).unwrap();
+        }
+
+        format!("file://{}", directory.display())
+    }
+
+    #[test]
+    fn parses_manifests() {
+        let manifest = Manifest::parse(
+            r#"
+            (define package-name 'my/app)
+            (define version "0.1.0")
+            (define dependencies '((steel/lists "^0.1" #:path "../lists")
+                                   (json #:git "https://example.com/json.git" #:rev "main")))
+            (define dylibs '("native"))
+            "#,
+            Path::new("/projects/app"),
+        )
+        .unwrap();
+
+        assert_eq!(manifest.name, "my/app");
+        assert_eq!(manifest.version, Version::new(0, 1, 0));
+        assert_eq!(manifest.dylibs, vec![PathBuf::from("native")]);
+
+        assert_eq!(manifest.dependencies[0].name, "steel/lists");
+        assert_eq!(
+            manifest.dependencies[0].source,
+            Source::Path(PathBuf::from("/projects/app/../lists"))
+        );
+        assert!(manifest.dependencies[0]
+            .requirement
+            .matches(&Version::new(0, 1, 5)));
+
+        assert_eq!(manifest.dependencies[1].requirement, VersionReq::STAR);
+        assert_eq!(
+            manifest.dependencies[1].source,
+            Source::Git {
+                url: "https://example.com/json.git".to_string(),
+                rev: Some("main".to_string())
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_bad_manifests() {
+        let directory = Path::new(".");
+
+        assert!(Manifest::parse("(define version \"0.1.0\")", directory).is_err());
+        assert!(Manifest::parse(
+            "(define package-name 'a) (define version \"not a version\")",
+            directory
+        )
+        .is_err());
+        assert!(Manifest::parse(
+            "(define package-name 'a) (define version \"0.1.0\") (define dependencies '((b \"1\")))",
+            directory
+        )
+        .is_err());
+
+        // Git sources can't smuggle in options for git
+        assert!(Manifest::parse(
+            "(define package-name 'a) (define version \"0.1.0\") (define dependencies '((b #:git \"--upload-pack=touch pwned\")))",
+            directory
+        )
+        .is_err());
+        assert!(Manifest::parse(
+            "(define package-name 'a) (define version \"0.1.0\") (define dependencies '((b #:git \"https://example.com/b.git\" #:rev \"--output=x\")))",
+            directory
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn installs_path_dependencies_transitively() {
+        let root = directory("transitive");
+
+        package(
+            &root.join("app"),
+            "app",
+            "0.1.0",
+            "(lib \"^1\" #:path \"../lib\")",
+        );
+        package(
+            &root.join("lib"),
+            "lib",
+            "1.2.0",
+            "(util \"0.3\" #:path \"../util\")",
+        );
+        package(&root.join("util"), "util", "0.3.1", "");
+
+        let project = root.join("app");
+        let installed = PackageManager::project(&project).install(&project).unwrap();
+
+        let versions = installed
+            .iter()
+            .map(|x| (x.name.as_str(), x.version.to_string()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            versions,
+            vec![("lib", "1.2.0".to_string()), ("util", "0.3.1".to_string())]
+        );
+
+        assert!(project.join(".steel/cogs/lib/cog.scm").is_file());
+        assert!(project.join(".steel/cogs/util/cog.scm").is_file());
+
+        let lockfile = read_lockfile(&project).unwrap().unwrap();
+        assert_eq!(lockfile, installed);
+
+        let contents = fs::read_to_string(project.join(LOCK_FILE)).unwrap();
+        assert!(contents.contains("(lib \"1.2.0\" #:path \"../lib\")"));
+    }
+
+    #[test]
+    fn reports_unsatisfiable_requirements() {
+        let root = directory("unsatisfiable");
+
+        package(
+            &root.join("app"),
+            "app",
+            "0.1.0",
+            "(lib \"^2\" #:path \"../lib\")",
+        );
+        package(&root.join("lib"), "lib", "1.2.0", "");
+
+        let project = root.join("app");
+        let error = PackageManager::project(&project)
+            .install(&project)
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.contains("no version of lib"), "{error}");
+        assert!(error.contains("^2 (required by app)"), "{error}");
+        assert!(error.contains("available versions: 1.2.0"), "{error}");
+    }
+
+    #[test]
+    fn picks_the_newest_git_tag_that_fits_every_requirement() {
+        let root = directory("git-tags");
+
+        let url = git_repository(
+            &root.join("json"),
+            "json",
+            &["1.0.0", "1.1.0", "1.4.2", "2.0.0"],
+        );
+
+        package(
+            &root.join("app"),
+            "app",
+            "0.1.0",
+            &format!("(json \"^1\" #:git {url:?}) (other \"*\" #:path \"../other\")"),
+        );
+        package(
+            &root.join("other"),
+            "other",
+            "0.1.0",
+            &format!("(json \"<1.2\" #:git {url:?})"),
+        );
+
+        let project = root.join("app");
+        let installed = PackageManager::project(&project).install(&project).unwrap();
+
+        let json = installed.iter().find(|x| x.name == "json").unwrap();
+        assert_eq!(json.version, Version::new(1, 1, 0));
+
+        let library = fs::read_to_string(project.join(".steel/cogs/json/lib.scm")).unwrap();
+        assert!(library.contains("1.1.0"));
+        assert!(!project.join(".steel/cogs/json/.git").exists());
+    }
+
+    #[test]
+    fn installs_stick_to_the_lockfile_until_updated() {
+        let root = directory("lockfile");
+
+        let repository = root.join("json");
+        let url = git_repository(&repository, "json", &["1.0.0"]);
+
+        package(
+            &root.join("app"),
+            "app",
+            "0.1.0",
+            &format!("(json \"^1\" #:git {url:?})"),
+        );
+
+        let project = root.join("app");
+        let manager = PackageManager::project(&project);
+
+        assert_eq!(
+            manager.install(&project).unwrap()[0].version,
+            Version::new(1, 0, 0)
+        );
+
+        // A new release doesn't change anything until the project is updated
+        git_repository(&repository, "json", &["1.3.0"]);
+
+        assert_eq!(
+            manager.install(&project).unwrap()[0].version,
+            Version::new(1, 0, 0)
+        );
+        assert_eq!(
+            manager.update(&project).unwrap()[0].version,
+            Version::new(1, 3, 0)
+        );
+        assert_eq!(
+            manager.install(&project).unwrap()[0].version,
+            Version::new(1, 3, 0)
+        );
+
+        let installed = manager.installed().unwrap();
+        assert_eq!(installed.len(), 1);
+        assert_eq!(installed[0].version, Version::new(1, 3, 0));
+    }
+
+    #[test]
+    fn requires_find_installed_packages() {
+        let root = directory("requires");
+
+        package(
+            &root.join("app"),
+            "app",
+            "0.1.0",
+            "(my/lib \"*\" #:path \"../lib\")",
+        );
+        package(&root.join("lib"), "my/lib", "0.1.0", "");
+        fs::write(
+            root.join("lib/lib.scm"),
+            "(provide answer) (define answer 42)",
+        )
+        .unwrap();
+
+        let project = root.join("app");
+        PackageManager::project(&project).install(&project).unwrap();
+
+        let main = project.join("main.scm");
+        let source = "(require \"my/lib/lib.scm\") (define result answer)";
+        fs::write(&main, source).unwrap();
+
+        let mut engine = crate::steel_vm::engine::Engine::new();
+        engine
+            .compile_and_run_raw_program_with_path(source, main)
+            .unwrap();
+
+        assert_eq!(engine.extract::<isize>("result").unwrap(), 42);
+    }
+}
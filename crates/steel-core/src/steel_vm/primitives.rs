@@ -5,40 +5,48 @@ use super::{
     cache::WeakMemoizationTable,
     engine::Engine,
     register_fn::RegisterFn,
-    vm::{get_test_mode, list_modules, set_test_mode, VmCore},
+    vm::{
+        get_test_mode, list_modules, set_test_mode, test_module_selected, test_runner_active,
+        test_selected, VmCore,
+    },
 };
 use crate::{
     gc::Gc,
     parser::span::Span,
     primitives::{
-        contracts,
+        bytevectors::bytevector_module,
+        contracts, fs_module,
         hashmaps::hashmap_module,
         hashmaps::{HM_CONSTRUCT, HM_GET, HM_INSERT},
         hashsets::hashset_module,
         lists::{list_module, UnRecoverableResult},
         nums::quotient,
-        port_module,
+        path_module, port_module,
         process::process_module,
         random::random_module,
         string_module,
         time::time_module,
-        ControlOperations, FsFunctions, IoFunctions, MetaOperations, NumOperations,
-        StreamOperations, SymbolOperations, VectorOperations,
+        ControlOperations, IoFunctions, MetaOperations, NumOperations, StreamOperations,
+        SymbolOperations, VectorOperations,
     },
     rerrs::ErrorKind,
-    rvals::FromSteelVal,
+    rvals::{serialize::serialization_module, FromSteelVal},
     steel_vm::{
         builtin::{get_function_name, Arity},
         vm::threads::threading_module,
     },
     values::{
         functions::{attach_contract_struct, get_contract, LambdaMetadataTable},
+        json_vals,
         structs::{build_type_id_module, make_struct_type},
     },
 };
 use crate::{
     rvals::IntoSteelVal,
-    values::structs::{build_option_structs, build_result_structs},
+    values::{
+        contracts::uncontracted,
+        structs::{build_option_structs, build_result_structs},
+    },
 };
 use crate::{
     rvals::{Result, SteelVal},
@@ -46,7 +54,9 @@ use crate::{
 };
 
 #[cfg(feature = "web")]
-use crate::primitives::web::{requests::requests_module, websockets::websockets_module};
+use crate::primitives::web::{
+    requests::requests_module, server::http_server_module, websockets::websockets_module,
+};
 
 #[cfg(feature = "colors")]
 use crate::primitives::colors::string_coloring_module;
@@ -238,6 +248,7 @@ thread_local! {
     pub static SYMBOL_MODULE: BuiltInModule = symbol_module();
     pub static IO_MODULE: BuiltInModule = io_module();
     pub static FS_MODULE: BuiltInModule = fs_module();
+    pub static PATH_MODULE: BuiltInModule = path_module();
     pub static PORT_MODULE: BuiltInModule = port_module();
     pub static META_MODULE: BuiltInModule = meta_module();
     pub static JSON_MODULE: BuiltInModule = json_module();
@@ -253,6 +264,8 @@ thread_local! {
     pub static PRELUDE_MODULE: BuiltInModule = prelude();
     pub static TIME_MODULE: BuiltInModule = time_module();
     pub static THREADING_MODULE: BuiltInModule = threading_module();
+    pub static BYTEVECTOR_MODULE: BuiltInModule = bytevector_module();
+    pub static SERIALIZATION_MODULE: BuiltInModule = serialization_module();
 
     #[cfg(feature = "web")]
     pub static WEBSOCKETS_MODULE: BuiltInModule = websockets_module();
@@ -260,6 +273,9 @@ thread_local! {
     #[cfg(feature = "web")]
     pub static REQUESTS_MODULE: BuiltInModule = requests_module();
 
+    #[cfg(feature = "web")]
+    pub static HTTP_SERVER_MODULE: BuiltInModule = http_server_module();
+
     #[cfg(feature = "blocking_requests")]
     pub static BLOCKING_REQUESTS_MODULE: BuiltInModule = crate::primitives::blocking_requests::blocking_requests_module();
 
@@ -268,6 +284,15 @@ thread_local! {
 
     #[cfg(feature = "sqlite")]
     pub static SQLITE_MODULE: BuiltInModule = crate::primitives::sqlite::sqlite_module();
+
+    #[cfg(feature = "toml")]
+    pub static TOML_MODULE: BuiltInModule = crate::primitives::toml::toml_module();
+
+    #[cfg(feature = "yaml")]
+    pub static YAML_MODULE: BuiltInModule = crate::primitives::yaml::yaml_module();
+
+    #[cfg(feature = "csv")]
+    pub static CSV_MODULE: BuiltInModule = crate::primitives::csv::csv_module();
 }
 
 pub fn prelude() -> BuiltInModule {
@@ -287,6 +312,7 @@ pub fn prelude() -> BuiltInModule {
         .with_module(SYMBOL_MODULE.with(|x| x.clone()))
         .with_module(IO_MODULE.with(|x| x.clone()))
         .with_module(FS_MODULE.with(|x| x.clone()))
+        .with_module(PATH_MODULE.with(|x| x.clone()))
         .with_module(PORT_MODULE.with(|x| x.clone()))
         .with_module(META_MODULE.with(|x| x.clone()))
         .with_module(JSON_MODULE.with(|x| x.clone()))
@@ -298,6 +324,8 @@ pub fn prelude() -> BuiltInModule {
         .with_module(TYPE_ID_MODULE.with(|x| x.clone()))
         .with_module(TIME_MODULE.with(|x| x.clone()))
         .with_module(THREADING_MODULE.with(|x| x.clone()))
+        .with_module(BYTEVECTOR_MODULE.with(|x| x.clone()))
+        .with_module(SERIALIZATION_MODULE.with(|x| x.clone()))
 }
 
 pub fn register_builtin_modules_without_io(engine: &mut Engine) {
@@ -327,6 +355,7 @@ pub fn register_builtin_modules_without_io(engine: &mut Engine) {
         .register_module(SYMBOL_MODULE.with(|x| x.clone()))
         .register_module(SANDBOXED_IO_MODULE.with(|x| x.clone()))
         // .register_module(FS_MODULE.with(|x| x.clone()))
+        .register_module(PATH_MODULE.with(|x| x.clone()))
         // .register_module(PORT_MODULE.with(|x| x.clone()))
         .register_module(SANDBOXED_META_MODULE.with(|x| x.clone()))
         .register_module(JSON_MODULE.with(|x| x.clone()))
@@ -385,6 +414,7 @@ pub fn register_builtin_modules(engine: &mut Engine) {
         .register_module(SYMBOL_MODULE.with(|x| x.clone()))
         .register_module(IO_MODULE.with(|x| x.clone()))
         .register_module(FS_MODULE.with(|x| x.clone()))
+        .register_module(PATH_MODULE.with(|x| x.clone()))
         .register_module(PORT_MODULE.with(|x| x.clone()))
         .register_module(META_MODULE.with(|x| x.clone()))
         .register_module(JSON_MODULE.with(|x| x.clone()))
@@ -397,7 +427,9 @@ pub fn register_builtin_modules(engine: &mut Engine) {
         .register_module(PRELUDE_MODULE.with(|x| x.clone()))
         .register_module(TIME_MODULE.with(|x| x.clone()))
         .register_module(RANDOM_MODULE.with(|x| x.clone()))
-        .register_module(THREADING_MODULE.with(|x| x.clone()));
+        .register_module(THREADING_MODULE.with(|x| x.clone()))
+        .register_module(BYTEVECTOR_MODULE.with(|x| x.clone()))
+        .register_module(SERIALIZATION_MODULE.with(|x| x.clone()));
 
     #[cfg(feature = "colors")]
     engine.register_module(STRING_COLORS_MODULE.with(|x| x.clone()));
@@ -405,13 +437,23 @@ pub fn register_builtin_modules(engine: &mut Engine) {
     #[cfg(feature = "web")]
     engine
         .register_module(WEBSOCKETS_MODULE.with(|x| x.clone()))
-        .register_module(REQUESTS_MODULE.with(|x| x.clone()));
+        .register_module(REQUESTS_MODULE.with(|x| x.clone()))
+        .register_module(HTTP_SERVER_MODULE.with(|x| x.clone()));
 
     #[cfg(feature = "sqlite")]
     engine.register_module(SQLITE_MODULE.with(|x| x.clone()));
 
     #[cfg(feature = "blocking_requests")]
     engine.register_module(BLOCKING_REQUESTS_MODULE.with(|x| x.clone()));
+
+    #[cfg(feature = "toml")]
+    engine.register_module(TOML_MODULE.with(|x| x.clone()));
+
+    #[cfg(feature = "yaml")]
+    engine.register_module(YAML_MODULE.with(|x| x.clone()));
+
+    #[cfg(feature = "csv")]
+    engine.register_module(CSV_MODULE.with(|x| x.clone()));
 }
 
 pub static ALL_MODULES: &str = r#"
@@ -430,6 +472,7 @@ pub static ALL_MODULES: &str = r#"
     (require-builtin steel/transducers)
     (require-builtin steel/io)
     (require-builtin steel/filesystem)
+    (require-builtin steel/path)
     (require-builtin steel/ports)
     (require-builtin steel/meta)
     (require-builtin steel/json)
@@ -440,6 +483,8 @@ pub static ALL_MODULES: &str = r#"
     (require-builtin steel/core/option)
     (require-builtin steel/core/types)
     (require-builtin steel/threads)
+    (require-builtin steel/bytevectors)
+    (require-builtin steel/serialize)
 "#;
 
 pub static SANDBOXED_MODULES: &str = r#"
@@ -517,12 +562,12 @@ fn stringp(value: &SteelVal) -> bool {
 
 #[steel_derive::function(name = "list?", constant = true)]
 fn listp(value: &SteelVal) -> bool {
-    matches!(value, SteelVal::ListV(_))
+    matches!(uncontracted(value), SteelVal::ListV(_))
 }
 
 #[steel_derive::function(name = "vector?", constant = true)]
 fn vectorp(value: &SteelVal) -> bool {
-    matches!(value, SteelVal::VectorV(_))
+    matches!(uncontracted(value), SteelVal::VectorV(_))
 }
 
 #[steel_derive::function(name = "symbol?", constant = true)]
@@ -532,7 +577,7 @@ fn symbolp(value: &SteelVal) -> bool {
 
 #[steel_derive::function(name = "hash?", constant = true)]
 fn hashp(value: &SteelVal) -> bool {
-    matches!(value, SteelVal::HashMapV(_))
+    matches!(uncontracted(value), SteelVal::HashMapV(_))
 }
 
 #[steel_derive::function(name = "continuation?", constant = true)]
@@ -627,13 +672,23 @@ fn contract_module() -> BuiltInModule {
     let mut module = BuiltInModule::new("steel/contracts");
     module
         .register_value("bind/c", contracts::BIND_CONTRACT_TO_FUNCTION)
+        .register_value("%contract-accepts?", contracts::CONTRACT_ACCEPTS)
+        .register_value("%contract-consumer", contracts::ATTACH_CONTRACT_CONSUMER)
         .register_value("make-flat/c", contracts::MAKE_FLAT_CONTRACT)
         .register_value(
             "make-dependent-function/c",
             contracts::MAKE_DEPENDENT_CONTRACT,
         )
         .register_value("make-function/c", contracts::MAKE_FUNCTION_CONTRACT)
-        .register_value("make/c", contracts::MAKE_C);
+        .register_value("make/c", contracts::MAKE_C)
+        .register_value("make-struct/c", contracts::MAKE_STRUCT_CONTRACT)
+        .register_value("listof", contracts::LIST_CONTRACT)
+        .register_value("vectorof", contracts::VECTOR_CONTRACT)
+        .register_value("streamof", contracts::STREAM_CONTRACT)
+        .register_value("hash/c", contracts::HASH_CONTRACT)
+        .register_value("or/c", contracts::OR_CONTRACT)
+        .register_value("and/c", contracts::AND_CONTRACT)
+        .register_value("new-∀/c", contracts::MAKE_PARAMETRIC_CONTRACT);
 
     module
 }
@@ -738,30 +793,44 @@ pub fn lte_primitive(args: &[SteelVal]) -> Result<SteelVal> {
     // Ok(SteelVal::BoolV(true))
 }
 
+// These are named, rather than registered as closures, so that the jit can recognize them
+pub fn lt_primitive(args: &[SteelVal]) -> Result<SteelVal> {
+    let check = ensure_tonicity_two!(|a, b| a < b);
+    check(args)
+}
+
+pub fn gt_primitive(args: &[SteelVal]) -> Result<SteelVal> {
+    let check = ensure_tonicity_two!(|a, b| a > b);
+    check(args)
+}
+
+// `=` and `equal?` - unlike `equality_primitive`, this requires at least one argument
+pub fn equal_primitive(args: &[SteelVal]) -> Result<SteelVal> {
+    let check = ensure_tonicity_two!(|a, b| a == b);
+    check(args)
+}
+
 fn equality_module() -> BuiltInModule {
     let mut module = BuiltInModule::new("steel/equality");
     module
-        .register_value(
-            "equal?",
-            SteelVal::FuncV(ensure_tonicity_two!(|a, b| a == b)),
-        )
+        .register_value("equal?", SteelVal::FuncV(equal_primitive))
         .register_value(
             "eq?",
             SteelVal::FuncV(ensure_tonicity_two!(
                 |a: &SteelVal, b: &SteelVal| a.ptr_eq(b)
             )),
         )
-        .register_value("=", SteelVal::FuncV(ensure_tonicity_two!(|a, b| a == b)));
+        .register_value("=", SteelVal::FuncV(equal_primitive));
     module
 }
 
 fn ord_module() -> BuiltInModule {
     let mut module = BuiltInModule::new("steel/ord");
     module
-        .register_value(">", SteelVal::FuncV(ensure_tonicity_two!(|a, b| a > b)))
+        .register_value(">", SteelVal::FuncV(gt_primitive))
         .register_value(">=", SteelVal::FuncV(gte_primitive))
-        .register_value("<", SteelVal::FuncV(ensure_tonicity_two!(|a, b| a < b)))
-        .register_value("<=", SteelVal::FuncV(ensure_tonicity_two!(|a, b| a <= b)));
+        .register_value("<", SteelVal::FuncV(lt_primitive))
+        .register_value("<=", SteelVal::FuncV(lte_primitive));
     module
 }
 
@@ -813,10 +882,8 @@ fn io_module() -> BuiltInModule {
         .register_value("display", IoFunctions::display())
         .register_value("displayln", IoFunctions::displayln())
         .register_value("newline", IoFunctions::newline())
-        .register_value("read-to-string", IoFunctions::read_to_string());
-
-    #[cfg(feature = "colors")]
-    module.register_value("display-color", IoFunctions::display_color());
+        .register_value("read-to-string", IoFunctions::read_to_string())
+        .register_value("display-color", IoFunctions::display_color());
 
     module
 }
@@ -837,28 +904,6 @@ fn constants_module() -> BuiltInModule {
     module
 }
 
-fn fs_module() -> BuiltInModule {
-    let mut module = BuiltInModule::new("steel/filesystem");
-    module
-        .register_value("is-dir?", FsFunctions::is_dir())
-        .register_value("is-file?", FsFunctions::is_file())
-        .register_value("read-dir", FsFunctions::read_dir())
-        .register_value("path-exists?", FsFunctions::path_exists())
-        .register_value(
-            "copy-directory-recursively!",
-            FsFunctions::copy_directory_recursively(),
-        )
-        .register_value("delete-directory!", FsFunctions::delete_directory())
-        .register_value("create-directory!", FsFunctions::create_dir_all())
-        .register_value("file-name", FsFunctions::file_name())
-        .register_value("current-directory", FsFunctions::current_dir())
-        .register_value(
-            "path->extension",
-            SteelVal::FuncV(FsFunctions::get_extension),
-        );
-    module
-}
-
 fn get_environment_variable(var: String) -> Result<SteelVal> {
     std::env::var(var)
         .map(|x| x.into_steelval().unwrap())
@@ -1024,6 +1069,12 @@ fn meta_module() -> BuiltInModule {
         )
         .register_value("set-test-mode!", SteelVal::BuiltIn(set_test_mode))
         .register_value("get-test-mode", SteelVal::BuiltIn(get_test_mode))
+        .register_value("test-runner-active?", SteelVal::BuiltIn(test_runner_active))
+        .register_value("test-selected?", SteelVal::BuiltIn(test_selected))
+        .register_value(
+            "test-module-selected?",
+            SteelVal::BuiltIn(test_module_selected),
+        )
         .register_fn("run!", super::meta::EngineWrapper::call)
         // .register_fn("get-value", super::meta::EngineWrapper::get_value)
         .register_fn("value->iterator", crate::rvals::value_into_iterator)
@@ -1065,7 +1116,13 @@ fn json_module() -> BuiltInModule {
         .register_value(
             "value->jsexpr-string",
             crate::values::json_vals::serialize_val_to_string(),
-        );
+        )
+        .register_native_fn_definition(json_vals::VALUE_TO_PRETTY_JSEXPR_STRING_DEFINITION)
+        .register_native_fn_definition(json_vals::READ_JSEXPR_DEFINITION)
+        .register_native_fn_definition(json_vals::JSEXPR_POINTER_DEFINITION)
+        .register_native_fn_definition(json_vals::JSEXPR_REF_DEFINITION)
+        .register_native_fn_definition(json_vals::STRUCT_TO_JSON_DEFINITION)
+        .register_native_fn_definition(json_vals::JSON_TO_STRUCT_DEFINITION);
     module
 }
 
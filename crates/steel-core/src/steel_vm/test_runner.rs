@@ -0,0 +1,1072 @@
+//! Discovers and runs Steel test files.
+//!
+//! Every `test-module` of a test file is run as its own test, in a fresh [`Engine`] on its own
+//! thread, so state never leaks between tests. Checks written with `steel/tests/unit-test.scm`
+//! (`check-equal?`, `check-err?`, grouped with `test-module`) are collected into a
+//! [`TestReport`], which can be printed as is or rendered as JUnit XML or TAP.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! # extern crate steel;
+//! # use steel::steel_vm::test_runner::TestRunner;
+//! # use std::time::Duration;
+//! let report = TestRunner::new()
+//!     .with_filter("sorting")
+//!     .with_timeout(Duration::from_secs(30))
+//!     .run_path("cogs/")
+//!     .unwrap();
+//!
+//! println!("{}", report.to_tap());
+//! assert!(report.is_success());
+//! ```
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    compiler::passes::VisitorMutUnit,
+    parser::{
+        ast::{List, Quote},
+        parser::{ParseError, Parser},
+        span::Span,
+    },
+    rvals::{FromSteelVal, Result, SteelVal},
+    steel_vm::engine::Engine,
+};
+
+// The module that the checks are reported through
+const UNIT_TEST_MODULE: &str = "tests/unit-test.scm";
+
+// Engines are created on their own threads, give them the same room as the main thread
+const TEST_THREAD_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+// How long to wait for interrupted tests to stop before leaving them behind
+const INTERRUPT_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct TestRunner {
+    filter: Option<String>,
+    timeout: Option<Duration>,
+    jobs: usize,
+    fail_fast: bool,
+    search_directories: Vec<PathBuf>,
+}
+
+impl Default for TestRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestRunner {
+    pub fn new() -> Self {
+        TestRunner {
+            filter: None,
+            timeout: None,
+            jobs: 1,
+            fail_fast: false,
+            search_directories: Vec::new(),
+        }
+    }
+
+    /// Only run the checks whose name, or enclosing `test-module` name, contains `filter`
+    pub fn with_filter(mut self, filter: impl Into<String>) -> Self {
+        self.filter = Some(filter.into());
+        self
+    }
+
+    /// Give up on a test once it has been running for longer than `timeout`.
+    ///
+    /// The engine running the test is interrupted at its next function call or loop iteration.
+    /// A test that is stuck inside a native function, such as a blocking read, can't be
+    /// interrupted - it is waited on for a few seconds at the end of the run, and then left to
+    /// finish in the background.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Run up to `jobs` tests at the same time
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Stop starting new tests after the first one that fails
+    pub fn with_fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Search these directories when resolving the `require`s of each test file
+    pub fn with_search_directories(mut self, directories: Vec<PathBuf>) -> Self {
+        self.search_directories = directories;
+        self
+    }
+
+    /// Discovers the test files under `path` and runs them
+    pub fn run_path(&self, path: impl AsRef<Path>) -> io::Result<TestReport> {
+        Ok(self.run(&discover_test_files(path)?))
+    }
+
+    pub fn run(&self, files: &[PathBuf]) -> TestReport {
+        self.run_with_progress(files, |_| {})
+    }
+
+    /// Runs the tests in the given files, calling `on_test` with each report as soon as the test
+    /// finishes. Reports are returned in the order of `files`, regardless of which finished first.
+    pub fn run_with_progress(
+        &self,
+        files: &[PathBuf],
+        mut on_test: impl FnMut(&FileReport),
+    ) -> TestReport {
+        let start = Instant::now();
+
+        let (sender, receiver) = mpsc::channel();
+        let mut queue = files.iter().flat_map(|file| test_cases(file)).enumerate();
+        let mut running: HashMap<usize, (TestCase, Instant, Arc<AtomicBool>)> = HashMap::new();
+        let mut interrupted: HashSet<usize> = HashSet::new();
+        let mut finished: Vec<(usize, FileReport)> = Vec::new();
+        let mut stopped = false;
+
+        loop {
+            while !stopped && running.len() < self.jobs {
+                let (index, case) = match queue.next() {
+                    Some(next) => next,
+                    None => break,
+                };
+
+                let sender = sender.clone();
+                let filter = self.filter.clone();
+                let search_directories = self.search_directories.clone();
+                let interrupt = Arc::new(AtomicBool::new(false));
+                let thread_case = case.clone();
+                let thread_interrupt = Arc::clone(&interrupt);
+
+                let spawned = thread::Builder::new()
+                    .name(format!("steel-test-{index}"))
+                    .stack_size(TEST_THREAD_STACK_SIZE)
+                    .spawn(move || {
+                        let report = run_case(
+                            &thread_case,
+                            filter,
+                            &search_directories,
+                            Some(thread_interrupt),
+                        );
+                        // The runner may have given up on us already
+                        let _ = sender.send((index, report));
+                    });
+
+                match spawned {
+                    Ok(_) => {
+                        running.insert(index, (case, Instant::now(), interrupt));
+                    }
+                    Err(e) => {
+                        let report = FileReport::failed(case, FileStatus::Errored(e.to_string()));
+                        on_test(&report);
+                        stopped |= self.fail_fast;
+                        finished.push((index, report));
+                    }
+                }
+            }
+
+            if running.is_empty() {
+                break;
+            }
+
+            let message = match self.timeout {
+                Some(timeout) => {
+                    let earliest = running.values().map(|x| x.1).min().unwrap();
+                    receiver.recv_timeout(
+                        (earliest + timeout).saturating_duration_since(Instant::now()),
+                    )
+                }
+                None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            let reports = match message {
+                // Interrupted tests report back once they have stopped, but they have already
+                // been reported as timed out
+                Ok((index, report)) => match running.remove(&index) {
+                    Some(_) => vec![(index, report)],
+                    None => {
+                        interrupted.remove(&index);
+                        Vec::new()
+                    }
+                },
+                Err(RecvTimeoutError::Timeout) => {
+                    let timeout = self.timeout.unwrap();
+                    let timed_out = running
+                        .iter()
+                        .filter(|(_, (_, started, _))| started.elapsed() >= timeout)
+                        .map(|(index, _)| *index)
+                        .collect::<Vec<_>>();
+
+                    timed_out
+                        .into_iter()
+                        .map(|index| {
+                            let (case, started, interrupt) = running.remove(&index).unwrap();
+                            interrupt.store(true, Ordering::Relaxed);
+                            interrupted.insert(index);
+
+                            let mut report = FileReport::failed(case, FileStatus::TimedOut);
+                            report.duration = started.elapsed();
+                            (index, report)
+                        })
+                        .collect()
+                }
+                // We're holding on to a sender, so this can't happen
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+
+            for (index, report) in reports {
+                on_test(&report);
+                stopped |= self.fail_fast && !report.is_success();
+                finished.push((index, report));
+            }
+        }
+
+        // Give the interrupted tests a chance to wind down, so they don't keep running
+        // alongside whatever comes after the run
+        let deadline = Instant::now() + INTERRUPT_GRACE_PERIOD;
+
+        while !interrupted.is_empty() {
+            match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok((index, _)) => {
+                    interrupted.remove(&index);
+                }
+                Err(_) => break,
+            }
+        }
+
+        finished.sort_by_key(|x| x.0);
+
+        TestReport {
+            files: finished.into_iter().map(|x| x.1).collect(),
+            duration: start.elapsed(),
+        }
+    }
+}
+
+/// A single test: one `test-module` of a file, run in its own engine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestCase {
+    pub path: PathBuf,
+    /// The name of the test module to run, or `None` to run the whole file at once
+    pub module: Option<String>,
+}
+
+/// Splits a test file into its tests, one for each `test-module` in the file. Files without any
+/// test modules, that name a test module with anything but a string literal, or that can't be
+/// read or parsed, are run as a single test.
+pub fn test_cases(path: &Path) -> Vec<TestCase> {
+    let names = fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| test_module_names(&contents).ok().flatten())
+        .filter(|names| !names.is_empty());
+
+    match names {
+        Some(names) => names
+            .into_iter()
+            .map(|name| TestCase {
+                path: path.to_path_buf(),
+                module: Some(name),
+            })
+            .collect(),
+        None => vec![TestCase {
+            path: path.to_path_buf(),
+            module: None,
+        }],
+    }
+}
+
+/// The names of the test modules in `source`, in the order they first appear. Returns `None` if
+/// a test module isn't named with a string literal, since those can't be told apart without
+/// running them.
+pub fn test_module_names(source: &str) -> std::result::Result<Option<Vec<String>>, ParseError> {
+    let mut collector = TestModuleCollector::default();
+
+    for expr in Parser::parse(source)? {
+        collector.visit(&expr);
+    }
+
+    Ok(if collector.unnamed {
+        None
+    } else {
+        Some(collector.names)
+    })
+}
+
+#[derive(Default)]
+struct TestModuleCollector {
+    names: Vec<String>,
+    unnamed: bool,
+}
+
+impl VisitorMutUnit for TestModuleCollector {
+    // Quoted code is data, not a test
+    fn visit_quote(&mut self, _quote: &Quote) {}
+
+    fn visit_list(&mut self, l: &List) {
+        if l.first_ident().map(|x| x.resolve()) == Some("test-module") {
+            match l.args.get(1).and_then(|x| x.string_literal()) {
+                Some(name) if self.names.iter().any(|x| x == name) => {}
+                Some(name) => self.names.push(name.to_string()),
+                None => self.unnamed = true,
+            }
+        }
+
+        for expr in &l.args {
+            self.visit(expr);
+        }
+    }
+}
+
+/// Finds the test files under `path`: any `.scm` file that uses `test-module`. Files that don't
+/// parse are skipped, since they can't be told apart from other scheme files. A path to a single
+/// file is always treated as a test file, so that its parse errors get reported.
+pub fn discover_test_files(path: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+    let path = path.as_ref();
+
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    discover_in_directory(path, &mut files)?;
+    files.sort();
+
+    Ok(files)
+}
+
+fn discover_in_directory(directory: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+
+        let hidden = path
+            .file_name()
+            .and_then(|x| x.to_str())
+            .map(|x| x.starts_with('.') || x == "target")
+            .unwrap_or(false);
+
+        if hidden {
+            continue;
+        }
+
+        if path.is_dir() {
+            discover_in_directory(&path, files)?;
+        } else if path.extension().map(|x| x == "scm").unwrap_or(false) {
+            let is_test = match test_module_names(&fs::read_to_string(&path)?) {
+                Ok(Some(names)) => !names.is_empty(),
+                Ok(None) => true,
+                Err(_) => false,
+            };
+
+            if is_test {
+                files.push(path);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs a single test in a fresh engine, on the current thread
+pub fn run_test(
+    case: &TestCase,
+    filter: Option<String>,
+    search_directories: &[PathBuf],
+) -> FileReport {
+    run_case(case, filter, search_directories, None)
+}
+
+fn run_case(
+    case: &TestCase,
+    filter: Option<String>,
+    search_directories: &[PathBuf],
+    interrupt: Option<Arc<AtomicBool>>,
+) -> FileReport {
+    let start = Instant::now();
+    let path = &case.path;
+
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => return FileReport::failed(case.clone(), FileStatus::Errored(e.to_string())),
+    };
+
+    let mut engine = Engine::new();
+    engine.enable_test_runner(filter, case.module.clone());
+
+    if let Some(interrupt) = interrupt {
+        engine.set_interrupt(interrupt);
+    }
+
+    for directory in search_directories {
+        engine.add_search_directory(directory.clone());
+    }
+
+    engine.register_value("std::env::args", SteelVal::ListV(vec![].into()));
+
+    let status = match engine.compile_and_run_raw_program_with_path(&contents, path.to_path_buf()) {
+        Ok(_) => FileStatus::Completed,
+        Err(e) => FileStatus::Errored(
+            e.emit_result_to_string(&path.to_string_lossy(), &contents)
+                .trim_end()
+                .to_string(),
+        ),
+    };
+
+    let (tests, status) = match collect_results(&mut engine) {
+        Ok(tests) => (tests, status),
+        Err(e) if status == FileStatus::Completed => {
+            (Vec::new(), FileStatus::Errored(e.to_string()))
+        }
+        Err(_) => (Vec::new(), status),
+    };
+
+    FileReport {
+        path: path.to_path_buf(),
+        module: case.module.clone(),
+        status,
+        tests,
+        duration: start.elapsed(),
+    }
+}
+
+fn collect_results(engine: &mut Engine) -> Result<Vec<TestResult>> {
+    let unit_test_module = engine
+        .modules()
+        .keys()
+        .find(|x| x.ends_with(UNIT_TEST_MODULE))
+        .cloned();
+
+    // The file never used the unit test module, so there is nothing to collect
+    let unit_test_module = match unit_test_module {
+        Some(path) => path,
+        None => return Ok(Vec::new()),
+    };
+
+    let results = engine
+        .run(&format!(
+            "(require {:?}) (get-test-results)",
+            unit_test_module.to_string_lossy()
+        ))?
+        .pop()
+        .unwrap_or(SteelVal::Void);
+
+    let results = match results {
+        SteelVal::ListV(results) => results,
+        other => stop!(TypeMismatch => "get-test-results returned a non list value: {}", other),
+    };
+
+    results
+        .iter()
+        .map(|result| {
+            let field = |key: &str| -> Result<SteelVal> {
+                match result {
+                    SteelVal::HashMapV(map) => Ok(map
+                        .get(&SteelVal::SymbolV(key.into()))
+                        .cloned()
+                        .unwrap_or(SteelVal::BoolV(false))),
+                    _ => stop!(TypeMismatch => "test result is not a hash: {}", result),
+                }
+            };
+
+            let outcome = if field("passed?")?.is_truthy() {
+                TestOutcome::Passed
+            } else {
+                TestOutcome::Failed(display_string(&field("message")?))
+            };
+
+            Ok(TestResult {
+                module: Some(field("module")?)
+                    .filter(|x| x.is_truthy())
+                    .map(|x| display_string(&x)),
+                name: display_string(&field("name")?),
+                outcome,
+                location: Span::from_steelval(&field("span")?)
+                    .ok()
+                    .and_then(|span| Location::from_span(engine, span)),
+            })
+        })
+        .collect()
+}
+
+// Strings are shown without their quotes
+fn display_string(value: &SteelVal) -> String {
+    match value {
+        SteelVal::StringV(s) => s.trim().to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Where a check lives in its source file. Lines and columns start from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub path: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+    /// The source line the check starts on
+    pub source_line: String,
+}
+
+impl Location {
+    fn from_span(engine: &Engine, span: Span) -> Option<Self> {
+        let source_id = span.source_id?;
+        let sources = engine.sources().sources.lock().unwrap();
+        let source = sources.get(source_id)?;
+
+        let before = source.get(..span.start)?;
+        let line_start = before.rfind('\n').map(|x| x + 1).unwrap_or(0);
+
+        Some(Location {
+            path: sources.get_path(&source_id).cloned(),
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            source_line: source[line_start..]
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{}:{}:{}", path.display(), self.line, self.column),
+            None => write!(f, "{}:{}", self.line, self.column),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TestOutcome {
+    Passed,
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    /// The name of the enclosing `test-module`, if there is one
+    pub module: Option<String>,
+    pub name: String,
+    pub outcome: TestOutcome,
+    pub location: Option<Location>,
+}
+
+impl TestResult {
+    pub fn full_name(&self) -> String {
+        match &self.module {
+            Some(module) => format!("{module} > {}", self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.outcome == TestOutcome::Passed
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileStatus {
+    /// The file ran to the end, although individual checks may have failed
+    Completed,
+    /// The file failed to compile, or raised an error outside of a check
+    Errored(String),
+    /// The test ran for longer than the timeout, and was interrupted
+    TimedOut,
+}
+
+/// The report of a single [`TestCase`]
+#[derive(Debug, Clone)]
+pub struct FileReport {
+    pub path: PathBuf,
+    /// The test module that was run, or `None` if the whole file was
+    pub module: Option<String>,
+    pub status: FileStatus,
+    pub tests: Vec<TestResult>,
+    pub duration: Duration,
+}
+
+impl FileReport {
+    fn failed(case: TestCase, status: FileStatus) -> Self {
+        FileReport {
+            path: case.path,
+            module: case.module,
+            status,
+            tests: Vec::new(),
+            duration: Duration::ZERO,
+        }
+    }
+
+    /// The path of the file, followed by the name of the test module if there is one
+    pub fn name(&self) -> String {
+        match &self.module {
+            Some(module) => format!("{} > {module}", self.path.display()),
+            None => self.path.display().to_string(),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status == FileStatus::Completed && self.tests.iter().all(TestResult::passed)
+    }
+
+    fn error_message(&self) -> Option<String> {
+        match &self.status {
+            FileStatus::Completed => None,
+            FileStatus::Errored(message) => Some(message.clone()),
+            FileStatus::TimedOut => Some(format!(
+                "timed out after {:.2}s",
+                self.duration.as_secs_f64()
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "running {}", self.name())?;
+
+        for test in &self.tests {
+            let result = if test.passed() { "ok" } else { "FAILED" };
+            writeln!(f, "test {} ... {result}", test.full_name())?;
+        }
+
+        match &self.status {
+            FileStatus::Completed => Ok(()),
+            FileStatus::Errored(_) => writeln!(f, "error: {} failed to run", self.name()),
+            FileStatus::TimedOut => writeln!(f, "error: {}", self.error_message().unwrap()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TestReport {
+    pub files: Vec<FileReport>,
+    pub duration: Duration,
+}
+
+impl TestReport {
+    pub fn tests(&self) -> impl Iterator<Item = (&FileReport, &TestResult)> {
+        self.files
+            .iter()
+            .flat_map(|file| file.tests.iter().map(move |test| (file, test)))
+    }
+
+    pub fn passed(&self) -> usize {
+        self.tests().filter(|x| x.1.passed()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.tests().filter(|x| !x.1.passed()).count()
+    }
+
+    /// Tests that errored or timed out
+    pub fn errored(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|x| x.status != FileStatus::Completed)
+            .count()
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.files.iter().all(FileReport::is_success)
+    }
+
+    /// Renders the report as JUnit XML, with a `testsuite` per test module. Test modules that
+    /// errored or timed out are reported as a single `testcase` with an `error`.
+    pub fn to_junit_xml(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+
+        xml.push_str(&format!(
+            "<testsuites tests=\"{}\" failures=\"{}\" errors=\"{}\" time=\"{:.3}\">\n",
+            self.passed() + self.failed(),
+            self.failed(),
+            self.errored(),
+            self.duration.as_secs_f64()
+        ));
+
+        for file in &self.files {
+            let name = escape_xml(&file.name());
+            let failures = file.tests.iter().filter(|x| !x.passed()).count();
+            let errors = usize::from(file.status != FileStatus::Completed);
+
+            xml.push_str(&format!(
+                "  <testsuite name=\"{name}\" tests=\"{}\" failures=\"{failures}\" errors=\"{errors}\" time=\"{:.3}\">\n",
+                file.tests.len(),
+                file.duration.as_secs_f64()
+            ));
+
+            for test in &file.tests {
+                let class_name = escape_xml(test.module.as_deref().unwrap_or(&name));
+                let test_name = escape_xml(&test.name);
+
+                match &test.outcome {
+                    TestOutcome::Passed => xml.push_str(&format!(
+                        "    <testcase name=\"{test_name}\" classname=\"{class_name}\"/>\n"
+                    )),
+                    TestOutcome::Failed(message) => {
+                        let details = match &test.location {
+                            Some(location) => format!("{message}\n  at {location}"),
+                            None => message.clone(),
+                        };
+
+                        xml.push_str(&format!(
+                            "    <testcase name=\"{test_name}\" classname=\"{class_name}\">\n      <failure message=\"{}\">{}</failure>\n    </testcase>\n",
+                            escape_xml(message),
+                            escape_xml(&details)
+                        ));
+                    }
+                }
+            }
+
+            if let Some(message) = file.error_message() {
+                xml.push_str(&format!(
+                    "    <testcase name=\"{name}\" classname=\"{name}\">\n      <error message=\"{}\">{}</error>\n    </testcase>\n",
+                    escape_xml(message.lines().next().unwrap_or_default()),
+                    escape_xml(&message)
+                ));
+            }
+
+            xml.push_str("  </testsuite>\n");
+        }
+
+        xml.push_str("</testsuites>\n");
+        xml
+    }
+
+    /// Renders the report in the Test Anything Protocol, version 13. Test modules that errored or
+    /// timed out are reported as a failing test point of their own.
+    pub fn to_tap(&self) -> String {
+        let mut points = Vec::new();
+
+        for file in &self.files {
+            for test in &file.tests {
+                let details = match &test.outcome {
+                    TestOutcome::Passed => None,
+                    TestOutcome::Failed(message) => Some((
+                        message.clone(),
+                        test.location.as_ref().map(|x| x.to_string()),
+                    )),
+                };
+
+                points.push((test.full_name(), details));
+            }
+
+            if let Some(message) = file.error_message() {
+                points.push((file.name(), Some((message, None))));
+            }
+        }
+
+        let mut tap = format!("TAP version 13\n1..{}\n", points.len());
+
+        for (number, (name, details)) in points.into_iter().enumerate() {
+            let number = number + 1;
+            let name = name.replace('#', "\\#");
+
+            match details {
+                None => tap.push_str(&format!("ok {number} - {name}\n")),
+                Some((message, location)) => {
+                    tap.push_str(&format!("not ok {number} - {name}\n  ---\n"));
+                    tap.push_str(&format!("  message: {message:?}\n"));
+                    if let Some(location) = location {
+                        tap.push_str(&format!("  at: {location:?}\n"));
+                    }
+                    tap.push_str("  ...\n");
+                }
+            }
+        }
+
+        tap
+    }
+}
+
+impl fmt::Display for TestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.is_success() {
+            writeln!(f, "\nfailures:")?;
+
+            for (_, test) in self.tests().filter(|x| !x.1.passed()) {
+                writeln!(f, "\n---- {} ----", test.full_name())?;
+
+                if let TestOutcome::Failed(message) = &test.outcome {
+                    writeln!(f, "{message}")?;
+                }
+
+                if let Some(location) = &test.location {
+                    writeln!(f, "  --> {location}")?;
+                    writeln!(f, "{:>5} | {}", location.line, location.source_line)?;
+                }
+            }
+
+            for file in self.files.iter() {
+                if let Some(message) = file.error_message() {
+                    writeln!(f, "\n---- {} ----\n{message}", file.name())?;
+                }
+            }
+        }
+
+        writeln!(
+            f,
+            "\ntest result: {}. {} passed; {} failed; {} errored; finished in {:.2}s",
+            if self.is_success() { "ok" } else { "FAILED" },
+            self.passed(),
+            self.failed(),
+            self.errored(),
+            self.duration.as_secs_f64()
+        )
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod test_runner_tests {
+    use super::*;
+
+    use crate::tests::test_directory;
+
+    // Lays out a test directory with its own copy of the unit test module, so the tests don't
+    // depend on STEEL_HOME
+    fn test_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory = test_directory("test-runner", name).join("tests");
+        fs::create_dir_all(&directory).unwrap();
+
+        fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../../cogs/tests/unit-test.scm"),
+            directory.join("unit-test.scm"),
+        )
+        .unwrap();
+
+        for (file, contents) in files {
+            fs::write(
+                directory.join(file),
+                format!("(require \"unit-test.scm\" (for-syntax \"unit-test.scm\"))\n{contents}"),
+            )
+            .unwrap();
+        }
+
+        directory
+    }
+
+    #[test]
+    fn reports_results_with_locations() {
+        let directory = test_files(
+            "results",
+            &[(
+                "math.scm",
+                r#"(test-module "math"
+                     (check-equal? "addition" (+ 1 2) 3)
+                     (check-equal? "multiplication" (* 2 3) 7))"#,
+            )],
+        );
+
+        let report = TestRunner::new().run_path(&directory).unwrap();
+
+        // The unit test module only defines test-module, it doesn't use it
+        assert_eq!(report.files.len(), 1);
+        assert_eq!(report.passed(), 1);
+        assert_eq!(report.failed(), 1);
+        assert!(!report.is_success());
+
+        let (_, failure) = report.tests().find(|x| !x.1.passed()).unwrap();
+        assert_eq!(failure.full_name(), "math > multiplication");
+
+        let location = failure.location.as_ref().unwrap();
+        assert_eq!(location.line, 4);
+        assert!(location.source_line.contains("(* 2 3)"));
+
+        assert!(report.to_tap().contains("not ok 2 - math > multiplication"));
+        assert!(report
+            .to_junit_xml()
+            .contains("<testcase name=\"addition\" classname=\"math\"/>"));
+    }
+
+    #[test]
+    fn runs_each_test_module_in_its_own_engine() {
+        let directory = test_files(
+            "isolation",
+            &[(
+                "isolation.scm",
+                r#"(define counter 0)
+                   (test-module "first"
+                     (set! counter (+ counter 1))
+                     (check-equal? "first sees a fresh counter" counter 1))
+                   (test-module "second"
+                     (set! counter (+ counter 1))
+                     (check-equal? "second sees a fresh counter" counter 1))"#,
+            )],
+        );
+
+        let report = TestRunner::new().run(&[directory.join("isolation.scm")]);
+
+        let modules = report
+            .files
+            .iter()
+            .map(|x| x.module.clone())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            modules,
+            vec![Some("first".to_string()), Some("second".to_string())]
+        );
+        assert_eq!(report.passed(), 2);
+        assert!(report.is_success());
+    }
+
+    #[test]
+    fn discovers_test_modules_from_the_syntax() {
+        let source = r#"
+            ; (test-module "commented out")
+            (define-syntax my-tests
+              (syntax-rules () [(_ body ...) (test-module "in a macro" body ...)]))
+            (define data '(test-module "quoted"))
+            (define message "(test-module \"in a string\")")
+            (test-module "top level" (check-equal? "one" 1 1))
+            (begin
+              (test-module "nested" (check-equal? "two" 2 2)))
+            (test-module "top level" (check-equal? "three" 3 3))"#;
+
+        assert_eq!(
+            test_module_names(source).unwrap(),
+            Some(vec!["top level".to_string(), "nested".to_string()])
+        );
+
+        assert_eq!(
+            test_module_names("(define name \"computed\") (test-module name 1)").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn filters_tests_by_name() {
+        let directory = test_files(
+            "filter",
+            &[(
+                "filter.scm",
+                r#"(test-module "filtering"
+                     (check-equal? "selected" 1 1)
+                     (check-equal? "other" 1 2))"#,
+            )],
+        );
+
+        let report = TestRunner::new()
+            .with_filter("selected")
+            .run(&[directory.join("filter.scm")]);
+
+        let names = report.tests().map(|x| x.1.name.clone()).collect::<Vec<_>>();
+
+        assert_eq!(names, vec!["selected".to_string()]);
+        assert!(report.is_success());
+    }
+
+    #[test]
+    fn discovery_skips_files_that_do_not_parse() {
+        let directory = test_files(
+            "discovery",
+            &[
+                (
+                    "math.scm",
+                    "(test-module \"math\" (check-equal? \"one\" 1 1))",
+                ),
+                ("broken.scm", "(define (unfinished"),
+            ],
+        );
+
+        assert_eq!(
+            discover_test_files(&directory).unwrap(),
+            vec![directory.join("math.scm")]
+        );
+
+        // Unless the file is named explicitly, then the error is reported
+        let broken = directory.join("broken.scm");
+        assert_eq!(discover_test_files(&broken).unwrap(), vec![broken.clone()]);
+
+        let report = TestRunner::new().run(&[broken]);
+        assert!(matches!(report.files[0].status, FileStatus::Errored(_)));
+    }
+
+    #[test]
+    fn reports_errors_and_timeouts() {
+        let directory = test_files(
+            "errors",
+            &[
+                ("error.scm", "(test-module \"error\" (car '()))"),
+                (
+                    "loop.scm",
+                    "(define (spin n) (if (= n 0) 0 (spin (- n 1))))
+                     (test-module \"loop\" (spin 100000000))",
+                ),
+            ],
+        );
+
+        let report = TestRunner::new()
+            .with_jobs(2)
+            .with_timeout(Duration::from_secs(1))
+            .run(&[directory.join("error.scm"), directory.join("loop.scm")]);
+
+        assert!(matches!(report.files[0].status, FileStatus::Errored(_)));
+        assert_eq!(report.files[1].status, FileStatus::TimedOut);
+        assert_eq!(report.errored(), 2);
+    }
+
+    #[test]
+    fn interrupted_tests_stop_running() {
+        let directory = test_files(
+            "interrupt",
+            &[(
+                "loop.scm",
+                "(define (spin n) (if (= n 0) 0 (spin (- n 1))))
+                 (test-module \"loop\" (spin 1000000000000))",
+            )],
+        );
+
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let case = TestCase {
+            path: directory.join("loop.scm"),
+            module: Some("loop".to_string()),
+        };
+
+        let flag = Arc::clone(&interrupt);
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(500));
+            flag.store(true, Ordering::Relaxed);
+        });
+
+        let report = run_case(&case, None, &[], Some(interrupt));
+        stopper.join().unwrap();
+
+        match report.status {
+            FileStatus::Errored(message) => assert!(message.contains("interrupted")),
+            other => panic!("expected the test to be interrupted, found {other:?}"),
+        }
+    }
+}
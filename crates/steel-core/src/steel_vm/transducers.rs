@@ -210,6 +210,9 @@ impl<'global, 'a> VmCore<'a> {
                     Ok(SteelVal::ListV(im_lists::list![x.0.clone(), x.1.clone()]))
                 })))
             }
+            SteelVal::BoxedIterator(b) => {
+                Ok(Box::new(std::iter::from_fn(move || b.borrow_mut().next())))
+            }
             SteelVal::MutableVector(v) => {
                 // Copy over the mutable vector into the nursery
                 *nursery = Some(v.borrow().clone());
@@ -1,7 +1,7 @@
 #![allow(unused)]
 
 use crate::primitives::nums::special_add;
-use crate::values::functions::SerializedLambda;
+use crate::values::functions::{BoxedDynFunction, SerializedLambda};
 use crate::values::structs::UserDefinedStruct;
 use crate::values::{closed::Heap, contracts::ContractType};
 use crate::{
@@ -28,9 +28,19 @@ use crate::{
     values::functions::ByteCodeLambda,
 };
 // use std::env::current_exe;
-use std::{cell::RefCell, collections::HashMap, iter::Iterator, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    iter::Iterator,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
 use super::builtin::DocTemplate;
+use super::contracts::retry_with_contracted_collections;
 
 use im_lists::list::List;
 
@@ -75,7 +85,8 @@ pub fn unlikely(b: bool) -> bool {
 // }
 
 const STACK_LIMIT: usize = 1000000;
-const _JIT_THRESHOLD: usize = 100;
+#[cfg(feature = "jit")]
+const JIT_THRESHOLD: usize = 100;
 
 #[repr(C)]
 #[derive(Clone, Debug, Copy, PartialEq)]
@@ -244,6 +255,64 @@ thread_local! {
     pub(crate) static DEFAULT_CONSTANT_MAP: ConstantMap = ConstantMap::new();
 }
 
+thread_local! {
+    // The VM that is running a reentrant native function on this thread, if any.
+    static CALLBACK_CONTEXT: Cell<*mut VmCore<'static>> = Cell::new(std::ptr::null_mut());
+}
+
+/// Identifies the engine a `SteelThread` belongs to. Cloning a thread makes a separate engine,
+/// so clones get a fresh id.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct EngineId(usize);
+
+impl EngineId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        EngineId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Clone for EngineId {
+    fn clone(&self) -> Self {
+        EngineId::new()
+    }
+}
+
+/// The engine that is running a reentrant native function on this thread, if any.
+pub(crate) fn callback_engine() -> Option<EngineId> {
+    let context = CALLBACK_CONTEXT.with(|x| x.get());
+
+    // SAFETY: See `with_callback_context`, the id is copied out before anything else runs.
+    (!context.is_null()).then(|| EngineId(unsafe { (*context).thread.id.0 }))
+}
+
+/// Calls back into the VM that is currently running a reentrant native function, as long as
+/// it belongs to the given engine.
+///
+/// Returns `None` if there is no such function running on this thread, for example when a
+/// callback outlives the call it was passed to, or when it is run by a different engine.
+pub(crate) fn with_callback_context<T>(
+    engine: &EngineId,
+    thunk: impl FnOnce(&mut VmCore<'_>) -> T,
+) -> Option<T> {
+    if callback_engine().as_ref() != Some(engine) {
+        return None;
+    }
+
+    // Take the context out while the thunk runs, so that there is never more than one live
+    // reference to the VM. Reentrant functions called from the thunk install it again.
+    let context = CALLBACK_CONTEXT.with(|x| x.replace(std::ptr::null_mut()));
+
+    // SAFETY: The pointer is only installed by `VmCore::call_native_function`, which does
+    // not touch the VM until the native function returns and the pointer is removed again.
+    let result = thunk(unsafe { &mut *context });
+
+    CALLBACK_CONTEXT.with(|x| x.set(context));
+
+    Some(result)
+}
+
 // Drain and move across the thread boundary, OR, enforce the restriction that only pure functions
 // can move into a new thread... that might be the easiest way?
 #[derive(Clone)]
@@ -260,12 +329,26 @@ pub struct SteelThread {
     pub(crate) current_frame: StackFrame,
     pub(crate) stack_frames: Vec<StackFrame>,
     pub(crate) constant_map: ConstantMap,
+    // Used by `bytes->value` to reconstruct custom types
+    pub(crate) custom_deserializers: crate::rvals::serialize::CustomDeserializers,
+    #[cfg(feature = "jit")]
+    pub(crate) jit: crate::jit::JitCache,
+    pub(crate) id: EngineId,
 }
 
 #[derive(Clone)]
 pub(crate) struct RunTimeOptions {
     pub(crate) contracts_on: bool,
     pub(crate) test: bool,
+    // Set when the tests are driven by the test runner, which does the reporting itself
+    pub(crate) test_runner: bool,
+    pub(crate) test_filter: Option<String>,
+    // Only this test module runs, when set
+    pub(crate) test_module: Option<String>,
+    // Once set, the VM stops with an error at the next function call or loop iteration
+    pub(crate) interrupt: Option<Arc<AtomicBool>>,
+    #[cfg(feature = "jit")]
+    pub(crate) jit: bool,
 }
 
 impl RunTimeOptions {
@@ -273,6 +356,12 @@ impl RunTimeOptions {
         Self {
             contracts_on: true,
             test: false,
+            test_runner: false,
+            test_filter: None,
+            test_module: None,
+            interrupt: None,
+            #[cfg(feature = "jit")]
+            jit: true,
         }
     }
 }
@@ -326,6 +415,10 @@ impl SteelThread {
             // we'll have each thread default to an empty constant map, and replace it with the map bundled
             // with the executables
             constant_map: DEFAULT_CONSTANT_MAP.with(|x| x.clone()),
+            custom_deserializers: Default::default(),
+            #[cfg(feature = "jit")]
+            jit: crate::jit::JitCache::default(),
+            id: EngineId::new(),
         }
     }
 
@@ -335,6 +428,41 @@ impl SteelThread {
         self
     }
 
+    // Hot functions are compiled to native code unless this is turned off
+    #[cfg(feature = "jit")]
+    pub fn with_jit(&mut self, jit: bool) -> &mut Self {
+        self.runtime_options.jit = jit;
+        self
+    }
+
+    #[cfg(feature = "profiling")]
+    pub fn record_op_code_sequences(&mut self) -> &mut Self {
+        self.profiler.record_sequences();
+        self
+    }
+
+    #[cfg(feature = "profiling")]
+    pub fn op_code_sequence_profile(&self, count: usize) -> String {
+        self.profiler.sequence_profile(count)
+    }
+
+    pub(crate) fn enable_test_runner(
+        &mut self,
+        filter: Option<String>,
+        module: Option<String>,
+    ) -> &mut Self {
+        self.runtime_options.test = true;
+        self.runtime_options.test_runner = true;
+        self.runtime_options.test_filter = filter;
+        self.runtime_options.test_module = module;
+        self
+    }
+
+    pub(crate) fn set_interrupt(&mut self, interrupt: Arc<AtomicBool>) -> &mut Self {
+        self.runtime_options.interrupt = Some(interrupt);
+        self
+    }
+
     pub fn insert_binding(&mut self, idx: usize, value: SteelVal) {
         self.global_env.add_root_value(idx, value);
     }
@@ -343,6 +471,28 @@ impl SteelThread {
         self.global_env.extract(idx)
     }
 
+    // The spans of the instructions of a closure, written into engine images alongside the closure
+    pub(crate) fn closure_spans(&self, id: usize) -> Option<Rc<[Span]>> {
+        self.function_interner.spans.get(&id).cloned()
+    }
+
+    // Replaces the global environment with one restored from an engine image. The closures that
+    // were built so far are only a cache keyed by the function id, and the ids of the image
+    // could overlap with them, so they're dropped.
+    pub(crate) fn restore_globals(
+        &mut self,
+        globals: Vec<SteelVal>,
+        constant_map: ConstantMap,
+        spans: Vec<(usize, Rc<[Span]>)>,
+    ) {
+        self.global_env.bindings_vec = globals;
+        self.constant_map = constant_map;
+
+        self.function_interner.closure_interner.clear();
+        self.function_interner.pure_function_interner.clear();
+        self.function_interner.spans.extend(spans);
+    }
+
     // Run the executable
     pub fn run_executable(&mut self, program: &Executable) -> Result<Vec<SteelVal>> {
         let Executable {
@@ -424,7 +574,9 @@ impl SteelThread {
                 //     throw!(TypeMismatch => format!("application not a procedure: {}", function)),
                 // );
 
-                vm_instance.call_with_args(&closure, args)
+                vm_instance
+                    .call_with_args(&closure, args)
+                    .and_then(|x| vm_instance.settle_contracted_collection(x))
             }
             _ => {
                 stop!(TypeMismatch => format!("application not a procedure: {function}"))
@@ -538,6 +690,8 @@ impl SteelThread {
 
                 return Err(e);
             } else {
+                let result = result.and_then(|x| vm_instance.settle_contracted_collection(x));
+
                 // self.profiler.report();
                 // self.profiler.report_time_spend();
                 // self.profiler.report_basic_blocks();
@@ -931,27 +1085,61 @@ impl<'a> VmCore<'a> {
         err: F,
     ) -> Result<SteelVal> {
         match func {
-            SteelVal::FuncV(func) => {
-                let arg_vec = [arg];
-                func(&arg_vec).map_err(|x| x.set_span_if_none(*cur_inst_span))
+            SteelVal::FuncV(f) => {
+                let mut arg_vec = [arg];
+                f(&arg_vec)
+                    .or_else(|e| {
+                        retry_with_contracted_collections(
+                            func,
+                            &mut arg_vec,
+                            e,
+                            cur_inst_span,
+                            self,
+                        )
+                    })
+                    .map_err(|x| x.set_span_if_none(*cur_inst_span))
             }
-            SteelVal::BoxedFunction(func) => {
-                let arg_vec = [arg];
-                func.func()(&arg_vec).map_err(|x| x.set_span_if_none(*cur_inst_span))
+            SteelVal::BoxedFunction(f) => {
+                let mut arg_vec = [arg];
+                self.call_native_function(f, &arg_vec)
+                    .or_else(|e| {
+                        retry_with_contracted_collections(
+                            func,
+                            &mut arg_vec,
+                            e,
+                            cur_inst_span,
+                            self,
+                        )
+                    })
+                    .map_err(|x| x.set_span_if_none(*cur_inst_span))
             }
             SteelVal::ContractedFunction(cf) => {
                 let arg_vec = vec![arg];
                 cf.apply(arg_vec, cur_inst_span, self)
+                    .and_then(|x| self.settle_contracted_collection(x))
             }
-            SteelVal::MutFunc(func) => {
+            SteelVal::MutFunc(f) => {
                 let mut arg_vec: Vec<_> = vec![arg];
-                func(&mut arg_vec).map_err(|x| x.set_span_if_none(*cur_inst_span))
+                f(&mut arg_vec)
+                    .or_else(|e| {
+                        retry_with_contracted_collections(
+                            func,
+                            &mut arg_vec,
+                            e,
+                            cur_inst_span,
+                            self,
+                        )
+                    })
+                    .map_err(|x| x.set_span_if_none(*cur_inst_span))
             }
             // SteelVal::BuiltIn(func) => {
             //     let arg_vec = [arg];
             //     func(self, &arg_vec).map_err(|x| x.set_span_if_none(*cur_inst_span))
             // }
-            SteelVal::Closure(closure) => self.call_with_one_arg(closure, arg),
+            // The result goes back to native code
+            SteelVal::Closure(closure) => self
+                .call_with_one_arg(closure, arg)
+                .and_then(|x| self.settle_contracted_collection(x)),
             _ => Err(err()),
         }
     }
@@ -966,27 +1154,61 @@ impl<'a> VmCore<'a> {
         err: F,
     ) -> Result<SteelVal> {
         match func {
-            SteelVal::FuncV(func) => {
-                let arg_vec = [arg1, arg2];
-                func(&arg_vec).map_err(|x| x.set_span_if_none(*cur_inst_span))
+            SteelVal::FuncV(f) => {
+                let mut arg_vec = [arg1, arg2];
+                f(&arg_vec)
+                    .or_else(|e| {
+                        retry_with_contracted_collections(
+                            func,
+                            &mut arg_vec,
+                            e,
+                            cur_inst_span,
+                            self,
+                        )
+                    })
+                    .map_err(|x| x.set_span_if_none(*cur_inst_span))
             }
-            SteelVal::BoxedFunction(func) => {
-                let arg_vec = [arg1, arg2];
-                func.func()(&arg_vec).map_err(|x| x.set_span_if_none(*cur_inst_span))
+            SteelVal::BoxedFunction(f) => {
+                let mut arg_vec = [arg1, arg2];
+                self.call_native_function(f, &arg_vec)
+                    .or_else(|e| {
+                        retry_with_contracted_collections(
+                            func,
+                            &mut arg_vec,
+                            e,
+                            cur_inst_span,
+                            self,
+                        )
+                    })
+                    .map_err(|x| x.set_span_if_none(*cur_inst_span))
             }
             SteelVal::ContractedFunction(cf) => {
                 let arg_vec = vec![arg1, arg2];
                 cf.apply(arg_vec, cur_inst_span, self)
+                    .and_then(|x| self.settle_contracted_collection(x))
             }
-            SteelVal::MutFunc(func) => {
+            SteelVal::MutFunc(f) => {
                 let mut arg_vec: Vec<_> = vec![arg1, arg2];
-                func(&mut arg_vec).map_err(|x| x.set_span_if_none(*cur_inst_span))
+                f(&mut arg_vec)
+                    .or_else(|e| {
+                        retry_with_contracted_collections(
+                            func,
+                            &mut arg_vec,
+                            e,
+                            cur_inst_span,
+                            self,
+                        )
+                    })
+                    .map_err(|x| x.set_span_if_none(*cur_inst_span))
             }
             // SteelVal::BuiltIn(func) => {
             //     let arg_vec = [arg1, arg2];
             //     func(self, &arg_vec).map_err(|x| x.set_span_if_none(*cur_inst_span))
             // }
-            SteelVal::Closure(closure) => self.call_with_two_args(closure, arg1, arg2),
+            // The result goes back to native code
+            SteelVal::Closure(closure) => self
+                .call_with_two_args(closure, arg1, arg2)
+                .and_then(|x| self.settle_contracted_collection(x)),
             _ => Err(err()),
         }
     }
@@ -1000,27 +1222,61 @@ impl<'a> VmCore<'a> {
         err: F,
     ) -> Result<SteelVal> {
         match func {
-            SteelVal::FuncV(func) => {
-                let arg_vec: Vec<_> = args.into_iter().collect();
-                func(&arg_vec).map_err(|x| x.set_span_if_none(*cur_inst_span))
+            SteelVal::FuncV(f) => {
+                let mut arg_vec: Vec<_> = args.into_iter().collect();
+                f(&arg_vec)
+                    .or_else(|e| {
+                        retry_with_contracted_collections(
+                            func,
+                            &mut arg_vec,
+                            e,
+                            cur_inst_span,
+                            self,
+                        )
+                    })
+                    .map_err(|x| x.set_span_if_none(*cur_inst_span))
             }
-            SteelVal::BoxedFunction(func) => {
-                let arg_vec: Vec<_> = args.into_iter().collect();
-                func.func()(&arg_vec).map_err(|x| x.set_span_if_none(*cur_inst_span))
+            SteelVal::BoxedFunction(f) => {
+                let mut arg_vec: Vec<_> = args.into_iter().collect();
+                self.call_native_function(f, &arg_vec)
+                    .or_else(|e| {
+                        retry_with_contracted_collections(
+                            func,
+                            &mut arg_vec,
+                            e,
+                            cur_inst_span,
+                            self,
+                        )
+                    })
+                    .map_err(|x| x.set_span_if_none(*cur_inst_span))
             }
             SteelVal::ContractedFunction(cf) => {
                 let arg_vec: Vec<_> = args.into_iter().collect();
                 cf.apply(arg_vec, cur_inst_span, self)
+                    .and_then(|x| self.settle_contracted_collection(x))
             }
-            SteelVal::MutFunc(func) => {
+            SteelVal::MutFunc(f) => {
                 let mut arg_vec: Vec<_> = args.into_iter().collect();
-                func(&mut arg_vec).map_err(|x| x.set_span_if_none(*cur_inst_span))
+                f(&mut arg_vec)
+                    .or_else(|e| {
+                        retry_with_contracted_collections(
+                            func,
+                            &mut arg_vec,
+                            e,
+                            cur_inst_span,
+                            self,
+                        )
+                    })
+                    .map_err(|x| x.set_span_if_none(*cur_inst_span))
             }
             // SteelVal::BuiltIn(func) => {
             //     let arg_vec: Vec<_> = args.into_iter().collect();
             //     func(self, &arg_vec).map_err(|x| x.set_span_if_none(*cur_inst_span))
             // }
-            SteelVal::Closure(closure) => self.call_with_args(closure, args),
+            // The result goes back to native code
+            SteelVal::Closure(closure) => self
+                .call_with_args(closure, args)
+                .and_then(|x| self.settle_contracted_collection(x)),
             _ => Err(err()),
         }
     }
@@ -1226,6 +1482,11 @@ impl<'a> VmCore<'a> {
 
             // assert_eq!(self.spans.len(), self.instructions.len());
 
+            #[cfg(feature = "profiling")]
+            self.thread
+                .profiler
+                .record_instruction(&self.instructions[self.ip]);
+
             #[cfg(feature = "dynamic")]
             if let Some(pat) = self.thread.profiler.process_opcode(
                 &self.instructions[self.ip].op_code,
@@ -1274,6 +1535,16 @@ impl<'a> VmCore<'a> {
             let instr = self.instructions[self.ip];
 
             match instr {
+                DenseInstruction {
+                    op_code: OpCode::SUPERINSTRUCTION,
+                    payload_size,
+                    ..
+                } => {
+                    let super_instruction =
+                        &superinstructions::SUPER_INSTRUCTIONS[payload_size as usize];
+
+                    (super_instruction.handler)(self, payload_size as usize)?;
+                }
                 DenseInstruction {
                     op_code: OpCode::DynSuperInstruction,
                     payload_size,
@@ -1782,6 +2053,8 @@ impl<'a> VmCore<'a> {
                 } => {
                     // println!("At tco jump");
 
+                    self.check_interrupt()?;
+
                     let current_arity = payload_size as usize;
                     // This is the number of (local) functions we need to pop to get back to the place we want to be at
                     let depth = self.instructions[self.ip + 1].payload_size as usize;
@@ -2619,6 +2892,16 @@ impl<'a> VmCore<'a> {
         payload_size: usize,
     ) -> Result<()> {
         self.cut_sequence();
+        self.check_interrupt()?;
+
+        #[cfg(feature = "jit")]
+        {
+            closure.increment_call_count();
+
+            if self.call_compiled_closure(&closure, payload_size) {
+                return Ok(());
+            }
+        }
 
         // Something like:
         // let last_func = self.function_stack.last_mut().unwrap();
@@ -2761,7 +3044,7 @@ impl<'a> VmCore<'a> {
     fn handle_tail_call(&mut self, stack_func: SteelVal, payload_size: usize) -> Result<()> {
         use SteelVal::*;
         match stack_func {
-            BoxedFunction(f) => self.call_boxed_func(f.func(), payload_size),
+            BoxedFunction(f) => self.call_boxed_func(&f, payload_size),
             FuncV(f) => self.call_primitive_func(f, payload_size),
             MutFunc(f) => self.call_primitive_mut_func(f, payload_size),
             ContractedFunction(cf) => self.call_contracted_function_tail_call(&cf, payload_size),
@@ -2778,18 +3061,108 @@ impl<'a> VmCore<'a> {
         }
     }
 
-    // #[inline(always)]
-    fn call_boxed_func(
+    /// Calls a native function again after it failed on the arguments on top of the stack,
+    /// starting at `last_index`, in case that was because of lazily checked collections. The
+    /// arguments are taken off of the stack either way.
+    #[cold]
+    fn retry_on_stack(
         &mut self,
-        func: &dyn Fn(&[SteelVal]) -> Result<SteelVal>,
-        payload_size: usize,
+        func: &SteelVal,
+        last_index: usize,
+        error: SteelErr,
+    ) -> Result<SteelVal> {
+        let mut args = self.thread.stack.split_off(last_index);
+        let span = self.current_span();
+
+        retry_with_contracted_collections(func, &mut args, error, &span, self)
+            .map_err(|x| x.set_span_if_none(span))
+    }
+
+    /// Like `retry_on_stack`, for arguments that have already been taken off of the stack.
+    #[cold]
+    fn retry_native(
+        &mut self,
+        func: &SteelVal,
+        args: &mut [SteelVal],
+        error: SteelErr,
+    ) -> Result<SteelVal> {
+        let span = self.current_span();
+
+        retry_with_contracted_collections(func, args, error, &span, self)
+            .map_err(|x| x.set_span_if_none(span))
+    }
+
+    /// Checks any lazily checked collections among `args` in full, so that a native function
+    /// that failed on them can be called again. Gives back `error` when there are none.
+    #[cold]
+    fn force_contracted_collections(
+        &mut self,
+        args: &mut [SteelVal],
+        error: SteelErr,
     ) -> Result<()> {
+        if !args
+            .iter()
+            .any(|x| matches!(x, SteelVal::ContractedCollection(_)))
+        {
+            return Err(error);
+        }
+
+        let span = self.current_span();
+
+        for arg in args.iter_mut() {
+            if let SteelVal::ContractedCollection(c) = arg {
+                *arg = Gc::clone(c).force(&span, self)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Values that leave the VM, like results and return values of callbacks from native code,
+    /// are checked in full when they are lazily checked collections, since nothing out there
+    /// knows about them.
+    fn settle_contracted_collection(&mut self, value: SteelVal) -> Result<SteelVal> {
+        match value {
+            SteelVal::ContractedCollection(c) => c.force(&self.current_span(), self),
+            value => Ok(value),
+        }
+    }
+
+    // #[inline(always)]
+    fn call_boxed_func(&mut self, func: &BoxedDynFunction, payload_size: usize) -> Result<()> {
         // println!("{:?}, {:?}", self.thread.stack, payload_size);
 
         let last_index = self.thread.stack.len() - payload_size;
 
-        let result = func(&self.thread.stack[last_index..])
-            .map_err(|x| x.set_span_if_none(self.current_span()))?;
+        // Callbacks are free to grow the stack, so reentrant functions can't borrow their
+        // arguments from it. Move them off of the stack instead, like with builtins.
+        if func.reentrant {
+            let mut args = self.thread.stack.split_off(last_index);
+
+            let result = match self.call_native_function(func, &args) {
+                Ok(value) => value,
+                Err(e) => {
+                    let func = SteelVal::BoxedFunction(Rc::new(func.clone()));
+                    self.retry_native(&func, &mut args, e)?
+                }
+            };
+
+            self.thread.stack.push(result);
+            self.ip += 1;
+            return Ok(());
+        }
+
+        let result = match func.func()(&self.thread.stack[last_index..]) {
+            Ok(value) => value,
+            Err(e) => {
+                let func = SteelVal::BoxedFunction(Rc::new(func.clone()));
+                let result = self.retry_on_stack(&func, last_index, e)?;
+
+                self.thread.stack.push(result);
+                self.ip += 1;
+                return Ok(());
+            }
+        };
 
         self.thread.stack.truncate(last_index);
 
@@ -2798,6 +3171,28 @@ impl<'a> VmCore<'a> {
         Ok(())
     }
 
+    /// Calls a native function with arguments that do not live on the stack. Reentrant
+    /// functions get this VM installed as the callback context for the duration of the call.
+    pub(crate) fn call_native_function(
+        &mut self,
+        func: &BoxedDynFunction,
+        args: &[SteelVal],
+    ) -> Result<SteelVal> {
+        if !func.reentrant {
+            return func.func()(args);
+        }
+
+        // The function value itself might get dropped by the callback, i.e. by redefining
+        // the global it lives in, so hold on to our own handle.
+        let function = Arc::clone(&func.function);
+
+        let previous = CALLBACK_CONTEXT.with(|x| x.replace((self as *mut VmCore<'a>).cast()));
+        let result = function(args);
+        CALLBACK_CONTEXT.with(|x| x.set(previous));
+
+        result
+    }
+
     // NOTE: Here, the last element on the stack _is_ the function we're referring to. In this case, just avoid
     // touching the last element and move on.
     fn call_boxed_func_on_stack(
@@ -2817,7 +3212,14 @@ impl<'a> VmCore<'a> {
 
         let result = match func(&self.thread.stack[last_index..len]) {
             Ok(value) => value,
-            Err(e) => return Err(e.set_span_if_none(self.current_span())),
+            Err(e) => {
+                let func = self.thread.stack.pop().unwrap();
+                let result = self.retry_on_stack(&func, last_index, e)?;
+
+                self.thread.stack.push(result);
+                self.ip += 1;
+                return Ok(());
+            }
         };
 
         // This is the old way, but now given that the function is included on the stack, this should work...
@@ -2849,7 +3251,14 @@ impl<'a> VmCore<'a> {
 
         let result = match func(&self.thread.stack[last_index..len]) {
             Ok(value) => value,
-            Err(e) => return Err(e.set_span_if_none(self.current_span())),
+            Err(e) => {
+                self.thread.stack.pop();
+                let result = self.retry_on_stack(&SteelVal::FuncV(func), last_index, e)?;
+
+                self.thread.stack.push(result);
+                self.ip += 1;
+                return Ok(());
+            }
         };
 
         // This is the old way, but now given that the function is included on the stack, this should work...
@@ -2871,11 +3280,26 @@ impl<'a> VmCore<'a> {
         self.ip += 1;
 
         // TODO: Don't do this - just read directly from the stack
-        let args = self
+        let mut args = self
             .thread
             .stack
             .split_off(self.thread.stack.len() - payload_size);
-        let result = func(self, &args).map(|x| {
+
+        let ip = self.ip;
+        let mut result = func(self, &args);
+
+        // Builtins are never accessors, so any lazily checked collections are checked in full
+        // before trying again. Some of them move the instruction pointer, which is put back first.
+        if let Some(Err(e)) = result {
+            self.ip = ip;
+
+            match self.force_contracted_collections(&mut args, e) {
+                Ok(()) => result = func(self, &args),
+                Err(e) => result = Some(Err(e)),
+            }
+        }
+
+        let result = result.map(|x| {
             x.map_err(|x| {
                 // TODO: @Matt 4/24/2022 -> combine this into one function probably
                 if x.has_span() {
@@ -2906,8 +3330,10 @@ impl<'a> VmCore<'a> {
 
         let last_index = self.thread.stack.len() - payload_size;
 
-        let result = f(&mut self.thread.stack[last_index..])
-            .map_err(|x| x.set_span_if_none(self.current_span()))?;
+        let result = match f(&mut self.thread.stack[last_index..]) {
+            Ok(value) => value,
+            Err(e) => self.retry_on_stack(&SteelVal::MutFunc(f), last_index, e)?,
+        };
 
         // TODO -> this can actually just be something like:
         // self.stack.truncate(self.stack.len() - payload_size + 1)
@@ -2941,7 +3367,7 @@ impl<'a> VmCore<'a> {
 
         let result = match f(&self.thread.stack[last_index..]) {
             Ok(value) => value,
-            Err(e) => return Err(e.set_span_if_none(self.current_span())),
+            Err(e) => self.retry_on_stack(&SteelVal::FuncV(f), last_index, e)?,
         };
 
         // println!("Length to truncate to: {:?}", last_index);
@@ -3070,9 +3496,14 @@ impl<'a> VmCore<'a> {
     ) -> Result<()> {
         let last_index = self.thread.stack.len() - payload_size;
 
-        let result = SteelVal::FutureV(Gc::new(f(&self.thread.stack[last_index..])?));
+        let result = match f(&self.thread.stack[last_index..]) {
+            Ok(result) => {
+                self.thread.stack.truncate(last_index);
+                SteelVal::FutureV(Gc::new(result))
+            }
+            Err(e) => self.retry_on_stack(&SteelVal::FutureFunc(f), last_index, e)?,
+        };
 
-        self.thread.stack.truncate(last_index);
         self.thread.stack.push(result);
         self.ip += 1;
         Ok(())
@@ -3172,6 +3603,18 @@ impl<'a> VmCore<'a> {
             println!("stack frame at exit: {:?}", self.thread.stack);
             stop!(Generic => "stack overflowed!"; self.current_span());
         }
+        self.check_interrupt()
+    }
+
+    // Native functions can't be interrupted, so this only catches code that keeps calling
+    // functions or looping
+    #[inline(always)]
+    fn check_interrupt(&self) -> Result<()> {
+        if let Some(interrupt) = &self.thread.runtime_options.interrupt {
+            if unlikely(interrupt.load(Ordering::Relaxed)) {
+                stop!(Generic => "interrupted"; self.current_span());
+            }
+        }
         Ok(())
     }
 
@@ -3192,10 +3635,14 @@ impl<'a> VmCore<'a> {
 
         match &stack_func {
             BoxedFunction(f) => {
-                self.thread.stack.push(
-                    f.func()(&[local, const_value])
-                        .map_err(|x| x.set_span_if_none(self.current_span()))?,
-                );
+                let mut args = [local, const_value];
+
+                let result = match self.call_native_function(f, &args) {
+                    Ok(result) => result,
+                    Err(e) => self.retry_native(&stack_func, &mut args, e)?,
+                };
+
+                self.thread.stack.push(result);
                 self.ip += 4;
             }
             FuncV(f) => {
@@ -3203,9 +3650,14 @@ impl<'a> VmCore<'a> {
                 //     .push(f(&[local, const_value]).map_err(|x| x.set_span_if_none(self.current_span()))?);
                 // self.ip += 4;
 
-                match f(&[local, const_value]) {
+                let mut args = [local, const_value];
+
+                match f(&args) {
                     Ok(value) => self.thread.stack.push(value),
-                    Err(e) => return Err(e.set_span_if_none(self.current_span())),
+                    Err(e) => {
+                        let value = self.retry_native(&stack_func, &mut args, e)?;
+                        self.thread.stack.push(value)
+                    }
                 }
 
                 // self.stack
@@ -3213,10 +3665,12 @@ impl<'a> VmCore<'a> {
                 self.ip += 4;
             }
             FutureFunc(f) => {
-                let result = SteelVal::FutureV(Gc::new(
-                    f(&[local, const_value])
-                        .map_err(|x| x.set_span_if_none(self.current_span()))?,
-                ));
+                let mut args = [local, const_value];
+
+                let result = match f(&args) {
+                    Ok(result) => SteelVal::FutureV(Gc::new(result)),
+                    Err(e) => self.retry_native(&stack_func, &mut args, e)?,
+                };
 
                 self.thread.stack.push(result);
                 self.ip += 4;
@@ -3244,10 +3698,13 @@ impl<'a> VmCore<'a> {
             Closure(closure) => self.handle_lazy_closure(closure, local, const_value)?,
             MutFunc(func) => {
                 let mut args = [local, const_value];
-                self.thread
-                    .stack
-                    .push(func(&mut args).map_err(|x| x.set_span_if_none(self.current_span()))?);
 
+                let result = match func(&mut args) {
+                    Ok(result) => result,
+                    Err(e) => self.retry_native(&stack_func, &mut args, e)?,
+                };
+
+                self.thread.stack.push(result);
                 self.ip += 4;
             }
             CustomStruct(s) => {
@@ -3289,6 +3746,10 @@ impl<'a> VmCore<'a> {
         #[cfg(feature = "jit")]
         {
             closure.increment_call_count();
+
+            if self.call_compiled_closure(&closure, payload_size) {
+                return Ok(());
+            }
         }
 
         self.adjust_stack_for_multi_arity(&closure, payload_size, &mut 0)?;
@@ -3348,26 +3809,41 @@ impl<'a> VmCore<'a> {
         Ok(())
     }
 
-    // #[cfg(feature = "jit")]
-    // // #[inline(always)]
-    // fn call_compiled_function(
-    //     &mut self,
-    //     function: &JitFunctionPointer,
-    //     payload_size: usize,
-    // ) -> Result<()> {
-    //     if function.arity() != payload_size {
-    //         stop!(ArityMismatch => format!("function expected {} arguments, found {}", function.arity(), payload_size); self.current_span());
-    //     }
-
-    //     let result = function.call_func(&mut self.thread.stack);
+    // Once a closure is hot, run it as compiled code - leaving the result on the stack in
+    // place of the arguments, just like a primitive. Returns false if the interpreter still
+    // has to make the call.
+    #[cfg(feature = "jit")]
+    #[inline(always)]
+    fn call_compiled_closure(&mut self, closure: &Gc<ByteCodeLambda>, payload_size: usize) -> bool {
+        if likely(closure.call_count() < JIT_THRESHOLD)
+            || !self.thread.runtime_options.jit
+            || closure.is_multi_arity
+            || closure.arity() != payload_size
+        {
+            return false;
+        }
 
-    //     // println!("Calling function!");
+        let last_index = self.thread.stack.len() - payload_size;
 
-    //     self.thread.stack.push(result);
-    //     self.ip += 1;
+        let resolver = crate::jit::VmResolver {
+            globals: &self.thread.global_env,
+            constants: &self.constants,
+        };
 
-    //     Ok(())
-    // }
+        match self
+            .thread
+            .jit
+            .call(closure, &self.thread.stack[last_index..], &resolver)
+        {
+            Some(result) => {
+                self.thread.stack.truncate(last_index);
+                self.thread.stack.push(result);
+                self.ip += 1;
+                true
+            }
+            None => false,
+        }
+    }
 
     // TODO improve this a bit
     // #[inline(always)]
@@ -3478,11 +3954,18 @@ impl<'a> VmCore<'a> {
 
         // Jit profiling -> Make sure that we really only trace once we pass a certain threshold
         // For instance, if this function
-        #[cfg(feature = "dynamic")]
+        #[cfg(any(feature = "dynamic", feature = "jit"))]
         {
             closure.increment_call_count();
         }
 
+        #[cfg(feature = "jit")]
+        {
+            if self.call_compiled_closure(&closure, payload_size) {
+                return Ok(());
+            }
+        }
+
         self.handle_function_call_closure_jit_without_profiling(closure, payload_size)
     }
 
@@ -3497,11 +3980,18 @@ impl<'a> VmCore<'a> {
 
         // Jit profiling -> Make sure that we really only trace once we pass a certain threshold
         // For instance, if this function
-        #[cfg(feature = "dynamic")]
+        #[cfg(any(feature = "dynamic", feature = "jit"))]
         {
             closure.increment_call_count();
         }
 
+        #[cfg(feature = "jit")]
+        {
+            if self.call_compiled_closure(closure, payload_size) {
+                return Ok(());
+            }
+        }
+
         self.handle_function_call_closure_jit_without_profiling_ref(closure, payload_size)
     }
 
@@ -3516,7 +4006,7 @@ impl<'a> VmCore<'a> {
         match stack_func {
             Closure(closure) => self.handle_function_call_closure_jit_ref(closure, payload_size)?,
             FuncV(f) => self.call_primitive_func(*f, payload_size)?,
-            BoxedFunction(f) => self.call_boxed_func(f.func(), payload_size)?,
+            BoxedFunction(f) => self.call_boxed_func(&f, payload_size)?,
             MutFunc(f) => self.call_primitive_mut_func(*f, payload_size)?,
             FutureFunc(f) => self.call_future_func(f.clone(), payload_size)?,
             ContractedFunction(cf) => self.call_contracted_function(&cf, payload_size)?,
@@ -3548,7 +4038,7 @@ impl<'a> VmCore<'a> {
         match stack_func {
             Closure(closure) => self.handle_function_call_closure_jit(closure, payload_size),
             FuncV(f) => self.call_primitive_func(f, payload_size),
-            BoxedFunction(f) => self.call_boxed_func(f.func(), payload_size),
+            BoxedFunction(f) => self.call_boxed_func(&f, payload_size),
             MutFunc(f) => self.call_primitive_mut_func(f, payload_size),
             FutureFunc(f) => self.call_future_func(f, payload_size),
             ContractedFunction(cf) => self.call_contracted_function(&cf, payload_size),
@@ -3576,19 +4066,19 @@ impl<'a> VmCore<'a> {
 
         self.ip += 1;
 
-        match &stack_func {
+        let result = match &stack_func {
             BoxedFunction(f) => f.func()(args),
             MutFunc(f) => f(args),
             FuncV(f) => f(args),
-            FutureFunc(f) => Ok(SteelVal::FutureV(Gc::new(f(args)?))),
+            FutureFunc(f) => f(args).map(|x| SteelVal::FutureV(Gc::new(x))),
             _ => {
                 log::error!("{stack_func:?}");
                 log::error!("Stack: {:?}", self.thread.stack);
                 stop!(BadSyntax => format!("Function application not a procedure or function type not supported: {stack_func}"); self.current_span());
             }
-        }
+        };
 
-        // Ok(())
+        result.or_else(|e| self.retry_native(&stack_func, args, e))
     }
 
     #[inline(always)]
@@ -3602,21 +4092,27 @@ impl<'a> VmCore<'a> {
         // self.ip += 1;
 
         match stack_func {
-            BoxedFunction(f) => {
+            BoxedFunction(ref f) => {
                 self.ip += 1;
-                self.thread.stack.push(f.func()(args)?)
+                let result = f.func()(args).or_else(|e| self.retry_native(&stack_func, args, e))?;
+                self.thread.stack.push(result)
             }
-            MutFunc(f) => {
+            MutFunc(ref f) => {
                 self.ip += 1;
-                self.thread.stack.push(f(args)?)
+                let result = f(args).or_else(|e| self.retry_native(&stack_func, args, e))?;
+                self.thread.stack.push(result)
             }
-            FuncV(f) => {
+            FuncV(ref f) => {
                 self.ip += 1;
-                self.thread.stack.push(f(args)?)
+                let result = f(args).or_else(|e| self.retry_native(&stack_func, args, e))?;
+                self.thread.stack.push(result)
             }
-            FutureFunc(f) => {
+            FutureFunc(ref f) => {
                 self.ip += 1;
-                self.thread.stack.push(SteelVal::FutureV(Gc::new(f(args)?)))
+                let result = f(args)
+                    .map(|x| SteelVal::FutureV(Gc::new(x)))
+                    .or_else(|e| self.retry_native(&stack_func, args, e))?;
+                self.thread.stack.push(result)
             }
             Closure(closure) => {
                 let arity = args.len();
@@ -3656,7 +4152,7 @@ impl<'a> VmCore<'a> {
         use SteelVal::*;
 
         match stack_func {
-            BoxedFunction(f) => self.call_boxed_func(f.func(), payload_size),
+            BoxedFunction(f) => self.call_boxed_func(&f, payload_size),
             FuncV(f) => self.call_primitive_func(f, payload_size),
             FutureFunc(f) => self.call_future_func(f, payload_size),
             MutFunc(f) => self.call_primitive_mut_func(f, payload_size),
@@ -3889,6 +4385,37 @@ pub(crate) fn set_test_mode(ctx: &mut VmCore, _args: &[SteelVal]) -> Option<Resu
     Some(Ok(ctx.thread.runtime_options.test.into()))
 }
 
+pub(crate) fn test_runner_active(ctx: &mut VmCore, _args: &[SteelVal]) -> Option<Result<SteelVal>> {
+    Some(Ok(ctx.thread.runtime_options.test_runner.into()))
+}
+
+// A test is selected if there isn't a filter, or if any of the given names contains the filter
+pub(crate) fn test_selected(ctx: &mut VmCore, args: &[SteelVal]) -> Option<Result<SteelVal>> {
+    let selected = match &ctx.thread.runtime_options.test_filter {
+        Some(filter) => args.iter().any(|x| match x {
+            SteelVal::StringV(name) => name.contains(filter.as_str()),
+            _ => false,
+        }),
+        None => true,
+    };
+
+    Some(Ok(selected.into()))
+}
+
+pub(crate) fn test_module_selected(
+    ctx: &mut VmCore,
+    args: &[SteelVal],
+) -> Option<Result<SteelVal>> {
+    let selected = match &ctx.thread.runtime_options.test_module {
+        Some(module) => {
+            matches!(args.first(), Some(SteelVal::StringV(name)) if name.as_str() == module)
+        }
+        None => true,
+    };
+
+    Some(Ok(selected.into()))
+}
+
 pub(crate) fn list_modules(ctx: &mut VmCore, _args: &[SteelVal]) -> Option<Result<SteelVal>> {
     use crate::rvals::AsRefSteelVal;
     use crate::steel_vm::builtin::BuiltInModule;
@@ -3956,24 +4483,30 @@ pub(crate) fn apply(ctx: &mut VmCore, args: &[SteelVal]) -> Option<Result<SteelV
                 }
                 // TODO: Reuse the allocation for apply
                 SteelVal::FuncV(f) => {
-                    let args = l.into_iter().cloned().collect::<Vec<_>>();
+                    let mut args = l.into_iter().cloned().collect::<Vec<_>>();
 
-                    let result = f(&args).map_err(|e| e.set_span_if_none(ctx.current_span()));
+                    let result = f(&args)
+                        .or_else(|e| ctx.retry_native(arg1, &mut args, e))
+                        .map_err(|e| e.set_span_if_none(ctx.current_span()));
 
                     Some(result)
                 }
                 SteelVal::MutFunc(f) => {
                     let mut args = l.into_iter().cloned().collect::<Vec<_>>();
 
-                    let result = f(&mut args).map_err(|e| e.set_span_if_none(ctx.current_span()));
+                    let result = f(&mut args)
+                        .or_else(|e| ctx.retry_native(arg1, &mut args, e))
+                        .map_err(|e| e.set_span_if_none(ctx.current_span()));
 
                     Some(result)
                 }
                 SteelVal::BoxedFunction(f) => {
                     let mut args = l.into_iter().cloned().collect::<Vec<_>>();
 
-                    let result =
-                        f.func()(&args).map_err(|e| e.set_span_if_none(ctx.current_span()));
+                    let result = ctx
+                        .call_native_function(f, &args)
+                        .or_else(|e| ctx.retry_native(arg1, &mut args, e))
+                        .map_err(|e| e.set_span_if_none(ctx.current_span()));
 
                     Some(result)
                 }
@@ -4063,6 +4596,10 @@ pub struct OpCodeOccurenceProfiler {
     starting_index: Option<usize>,
     ending_index: Option<usize>,
     sample_count: usize,
+    // How many times each straight line run of op codes was executed. Only recorded once
+    // asked for, since it's meant for generating super instructions from a workload.
+    sequences: Option<HashMap<Vec<steel_gen::Pattern>, usize>>,
+    current_sequence: Vec<steel_gen::Pattern>,
 }
 
 impl OpCodeOccurenceProfiler {
@@ -4073,9 +4610,75 @@ impl OpCodeOccurenceProfiler {
             starting_index: None,
             ending_index: None,
             sample_count: 0,
+            sequences: None,
+            current_sequence: Vec::new(),
         }
     }
 
+    pub fn record_sequences(&mut self) {
+        self.sequences.get_or_insert_with(HashMap::new);
+    }
+
+    #[inline(always)]
+    pub fn record_instruction(&mut self, instruction: &DenseInstruction) {
+        let sequences = match &mut self.sequences {
+            Some(sequences) => sequences,
+            None => return,
+        };
+
+        // These never get dispatched on - the op code before them skips over them
+        if instruction.op_code.is_ephemeral_opcode() {
+            return;
+        }
+
+        let pattern = steel_gen::Pattern::from_opcodes(&[(
+            instruction.op_code,
+            instruction.payload_size as usize,
+        )]);
+
+        // Anything steel-gen can't generate a handler for ends the run, as does anything that
+        // moves somewhere other than the next instruction
+        let ends_sequence = match pattern.first() {
+            Some(pattern) => {
+                self.current_sequence.push(*pattern);
+                matches!(
+                    instruction.op_code,
+                    OpCode::IF | OpCode::CALLGLOBAL | OpCode::TAILCALL
+                )
+            }
+            None => true,
+        };
+
+        if ends_sequence {
+            if self.current_sequence.len() > 1 {
+                *sequences
+                    .entry(std::mem::take(&mut self.current_sequence))
+                    .or_default() += 1;
+            }
+
+            self.current_sequence.clear();
+        }
+    }
+
+    /// The `count` most frequently executed sequences of op codes that a super instruction can
+    /// be generated for, in the format read by steel-gen
+    pub fn sequence_profile(&self, count: usize) -> String {
+        let mut sequences = self
+            .sequences
+            .iter()
+            .flatten()
+            .filter(|(sequence, _)| {
+                steel_gen::can_fuse(&sequence.iter().map(|x| x.to_opcode()).collect::<Vec<_>>())
+            })
+            .map(|(sequence, count)| (sequence.clone(), *count))
+            .collect::<Vec<_>>();
+
+        sequences.sort_by(|l, r| r.1.cmp(&l.1).then_with(|| l.0.cmp(&r.0)));
+        sequences.truncate(count);
+
+        steel_gen::profile::write_profile(&sequences)
+    }
+
     pub fn reset(&mut self) {
         self.occurrences.clear();
         self.time.clear();
@@ -4718,6 +5321,22 @@ fn handle_move_local_1_no_stack(ctx: &mut VmCore<'_>) -> Result<SteelVal> {
     Ok(value)
 }
 
+#[inline(always)]
+fn handle_move_local_2_no_stack(ctx: &mut VmCore<'_>) -> Result<SteelVal> {
+    let offset = ctx.get_offset();
+    let value = ctx.move_from_stack(offset + 2);
+    ctx.ip += 1;
+    Ok(value)
+}
+
+#[inline(always)]
+fn handle_move_local_3_no_stack(ctx: &mut VmCore<'_>) -> Result<SteelVal> {
+    let offset = ctx.get_offset();
+    let value = ctx.move_from_stack(offset + 3);
+    ctx.ip += 1;
+    Ok(value)
+}
+
 #[inline(always)]
 fn handle_local_0_no_stack(ctx: &mut VmCore<'_>) -> Result<SteelVal> {
     let offset = ctx.get_offset();
@@ -4726,6 +5345,30 @@ fn handle_local_0_no_stack(ctx: &mut VmCore<'_>) -> Result<SteelVal> {
     Ok(value)
 }
 
+#[inline(always)]
+fn handle_local_1_no_stack(ctx: &mut VmCore<'_>) -> Result<SteelVal> {
+    let offset = ctx.get_offset();
+    let value = ctx.thread.stack[offset + 1].clone();
+    ctx.ip += 1;
+    Ok(value)
+}
+
+#[inline(always)]
+fn handle_local_2_no_stack(ctx: &mut VmCore<'_>) -> Result<SteelVal> {
+    let offset = ctx.get_offset();
+    let value = ctx.thread.stack[offset + 2].clone();
+    ctx.ip += 1;
+    Ok(value)
+}
+
+#[inline(always)]
+fn handle_local_3_no_stack(ctx: &mut VmCore<'_>) -> Result<SteelVal> {
+    let offset = ctx.get_offset();
+    let value = ctx.thread.stack[offset + 3].clone();
+    ctx.ip += 1;
+    Ok(value)
+}
+
 // OpCode::VOID
 fn void_handler(ctx: &mut VmCore<'_>) -> Result<()> {
     ctx.thread.stack.push(SteelVal::Void);
@@ -5431,14 +6074,20 @@ fn add_handler_float_float(_: &mut VmCore<'_>, l: f64, r: f64) -> f64 {
 }
 
 #[inline(always)]
-fn sub_handler_int_int(_: &mut VmCore<'_>, l: isize, r: isize) -> isize {
-    l - r
+fn sub_handler_int_int(_: &mut VmCore<'_>, l: isize, r: isize) -> Result<SteelVal> {
+    match l.checked_sub(r) {
+        Some(res) => Ok(SteelVal::IntV(res)),
+        None => Ok(SteelVal::BigNum(Gc::new(num::BigInt::from(l) - r))),
+    }
 }
 
 #[inline(always)]
 fn sub_handler_int_none(_: &mut VmCore<'_>, l: isize, r: SteelVal) -> Result<SteelVal> {
     match r {
-        SteelVal::IntV(n) => Ok(SteelVal::IntV(l - n)),
+        SteelVal::IntV(n) => match l.checked_sub(n) {
+            Some(res) => Ok(SteelVal::IntV(res)),
+            None => Ok(SteelVal::BigNum(Gc::new(num::BigInt::from(l) - n))),
+        },
         SteelVal::NumV(r) => Ok(SteelVal::NumV(l as f64 - r)),
         _ => stop!(TypeMismatch => "sub expected a number, found: {}", r),
     }
@@ -5447,7 +6096,10 @@ fn sub_handler_int_none(_: &mut VmCore<'_>, l: isize, r: SteelVal) -> Result<Ste
 #[inline(always)]
 fn sub_handler_none_int(_: &mut VmCore<'_>, l: SteelVal, r: isize) -> Result<SteelVal> {
     match l {
-        SteelVal::IntV(l) => Ok(SteelVal::IntV(l - r)),
+        SteelVal::IntV(l) => match l.checked_sub(r) {
+            Some(res) => Ok(SteelVal::IntV(res)),
+            None => Ok(SteelVal::BigNum(Gc::new(num::BigInt::from(l) - r))),
+        },
         SteelVal::NumV(l) => Ok(SteelVal::NumV(l - r as f64)),
         _ => {
             cold();
@@ -5569,121 +6221,178 @@ fn add_handler_none_none(l: &SteelVal, r: &SteelVal) -> Result<SteelVal> {
     }
 }
 
-#[cfg(feature = "dynamic")]
-mod dynamic {
-    use super::*;
+// The handlers the code generated by steel-gen calls, for both the dynamic super instructions
+// and the ones generated at build time
+macro_rules! binop_opcode_to_ssa_handler {
+    (ADD2, Int, Int) => {
+        add_handler_int_int
+    };
 
-    #[macro_export]
-    macro_rules! binop_opcode_to_ssa_handler {
-        (ADD2, Int, Int) => {
-            add_handler_int_int
-        };
+    (ADD2, Int, Float) => {
+        add_handler_int_float
+    };
 
-        (ADD2, Int, Float) => {
-            add_handler_int_float
-        };
+    (ADD2, Float, Int) => {
+        add_handler_int_float
+    };
 
-        (ADD2, Float, Int) => {
-            add_handler_int_float
-        };
+    (ADD2, Float, Float) => {
+        add_handler_float_float
+    };
 
-        (ADD2, Float, Float) => {
-            add_handler_float_float
-        };
+    (MUL2, Int, Int) => {
+        multiply_handler_int_int
+    };
 
-        (MUL2, Int, Int) => {
-            multiply_handler_int_int
-        };
+    (MUL2, Int, Float) => {
+        multiply_handler_int_float
+    };
 
-        (MUL2, Int, Float) => {
-            multiply_handler_int_float
-        };
+    (MUL2, Float, Int) => {
+        multiply_handler_int_float
+    };
 
-        (MUL2, Float, Int) => {
-            multiply_handler_int_float
-        };
+    (MUL2, Float, Float) => {
+        multiply_handler_float_float
+    };
 
-        (MUL2, Float, Float) => {
-            multiply_handler_float_float
-        };
+    (MUL2, Int, None) => {
+        multiply_handler_int_none
+    };
 
-        (SUB2, Int, Int) => {
-            sub_handler_int_int
-        };
+    (SUB2, Int, Int) => {
+        sub_handler_int_int
+    };
 
-        (SUB2, Int, None) => {
-            sub_handler_int_none
-        };
+    (SUB2, Int, None) => {
+        sub_handler_int_none
+    };
 
-        (SUB2, None, Int) => {
-            sub_handler_none_int
-        };
+    (SUB2, None, Int) => {
+        sub_handler_none_int
+    };
 
-        (SUB2, Int, Float) => {
-            sub_handler_int_float
-        };
+    (SUB2, Int, Float) => {
+        sub_handler_int_float
+    };
 
-        (SUB2, Float, Int) => {
-            sub_handler_float_int
-        };
+    (SUB2, Float, Int) => {
+        sub_handler_float_int
+    };
 
-        (SUB2, Float, Float) => {
-            sub_handler_float_float
-        };
+    (SUB2, Float, Float) => {
+        sub_handler_float_float
+    };
 
-        (DIV2, Int, Int) => {
-            div_handler_int_int
-        };
+    (DIV2, Int, Int) => {
+        div_handler_int_int
+    };
 
-        (DIV2, Int, Float) => {
-            div_handler_int_float
-        };
+    (DIV2, Int, Float) => {
+        div_handler_int_float
+    };
 
-        (DIV2, Float, Int) => {
-            div_handler_float_int
-        };
+    (DIV2, Float, Int) => {
+        div_handler_float_int
+    };
 
-        (DIV2, Float, Float) => {
-            div_handler_float_float
-        };
+    (DIV2, Float, Float) => {
+        div_handler_float_float
+    };
 
-        (LTE2, None, Int) => {
-            lte_handler_none_int
-        };
-    }
+    (LTE2, None, Int) => {
+        lte_handler_none_int
+    };
+}
 
-    macro_rules! if_to_ssa_handler {
-        (IF, Bool) => {
-            if_handler_with_bool
-        };
-        (IF) => {
-            raw_if_handler
-        };
-    }
+macro_rules! if_to_ssa_handler {
+    (IF, Bool) => {
+        if_handler_with_bool
+    };
+    (IF) => {
+        raw_if_handler
+    };
+}
 
-    macro_rules! opcode_to_ssa_handler {
-        (CALLGLOBAL) => {
-            call_global_handler_no_stack
-        };
+macro_rules! opcode_to_ssa_handler {
+    (CALLGLOBAL) => {
+        call_global_handler_no_stack
+    };
 
-        (CALLGLOBAL, Tail) => {
-            call_global_handler_with_args
-        };
+    (CALLGLOBAL, Tail) => {
+        call_global_handler_with_args
+    };
 
-        (MOVEREADLOCAL0) => {
-            handle_move_local_0_no_stack
-        };
+    (MOVEREADLOCAL0) => {
+        handle_move_local_0_no_stack
+    };
 
-        (MOVEREADLOCAL1) => {
-            handle_move_local_1_no_stack
-        };
+    (MOVEREADLOCAL1) => {
+        handle_move_local_1_no_stack
+    };
 
-        (READLOCAL0) => {
-            handle_local_0_no_stack
-        };
-    }
+    (MOVEREADLOCAL2) => {
+        handle_move_local_2_no_stack
+    };
+
+    (MOVEREADLOCAL3) => {
+        handle_move_local_3_no_stack
+    };
+
+    (READLOCAL0) => {
+        handle_local_0_no_stack
+    };
+
+    (READLOCAL1) => {
+        handle_local_1_no_stack
+    };
+
+    (READLOCAL2) => {
+        handle_local_2_no_stack
+    };
+
+    (READLOCAL3) => {
+        handle_local_3_no_stack
+    };
+}
+
+#[cfg(feature = "dynamic")]
+mod dynamic {
+    use super::*;
 
     // Includes the module as a dependency, that being said - this should
     // probably get generated into some specific sub module directly?
     include!(concat!(env!("OUT_DIR"), "/dynamic.rs"));
 }
+
+// Super instructions generated from a profile at build time, see `fuse_super_instructions`
+#[allow(unused)]
+pub(crate) mod superinstructions {
+    use super::*;
+
+    pub(crate) struct SuperInstruction {
+        pub(crate) pattern: &'static [steel_gen::Pattern],
+        pub(crate) handler: for<'r> fn(&'r mut VmCore<'_>, usize) -> Result<()>,
+    }
+
+    #[cfg(not(test))]
+    include!(concat!(env!("OUT_DIR"), "/superinstructions.rs"));
+
+    // Unit tests get a fixed set when there is no profile, see `build.rs`
+    #[cfg(test)]
+    include!(concat!(env!("OUT_DIR"), "/test_superinstructions.rs"));
+
+    // The instruction that a super instruction took the place of. Only the payloads that are
+    // part of the pattern matter to the handlers, so the pattern is enough to rebuild it.
+    pub(crate) fn original_instruction(instruction: DenseInstruction) -> DenseInstruction {
+        match instruction.op_code {
+            OpCode::SUPERINSTRUCTION => {
+                let (op_code, payload) =
+                    SUPER_INSTRUCTIONS[instruction.payload_size as usize].pattern[0].to_opcode();
+
+                DenseInstruction::new(op_code, payload as u32)
+            }
+            _ => instruction,
+        }
+    }
+}
@@ -249,6 +249,11 @@ fn spawn_thread_result(ctx: &mut VmCore, args: &[SteelVal]) -> Result<SteelVal>
                         .collect(),
                 )
             ),
+            // The deserializers are closures local to the parent's thread
+            custom_deserializers: Default::default(),
+            #[cfg(feature = "jit")]
+            jit: crate::jit::JitCache::default(),
+            id: EngineId::new(),
         };
 
         log::info!(target: "threads", "Time taken to spawn thread: {:?}", now.elapsed());
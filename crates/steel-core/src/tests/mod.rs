@@ -1,5 +1,7 @@
 use crate::steel_vm::engine::Engine;
 
+use std::{fs, path::PathBuf};
+
 fn generate_asserting_machine() -> Engine {
     let vm = Engine::new();
     // vm.compile_and_run_raw_program(PRELUDE).unwrap();
@@ -17,6 +19,24 @@ pub(crate) fn assert_script_error<T: AsRef<str>>(script: T) {
     assert!(vm.compile_and_run_raw_program(script.as_ref()).is_err());
 }
 
+// An empty scratch directory for a test, cleared of whatever an earlier run left behind. The group
+// keeps the names of different test modules apart.
+pub(crate) fn test_directory(group: &str, name: &str) -> PathBuf {
+    let directory = std::env::temp_dir()
+        .join(format!("steel-{group}"))
+        .join(name);
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+// Writes a file for a test, creating the directories leading up to it
+pub(crate) fn write_file(path: PathBuf, contents: &str) -> PathBuf {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, contents).unwrap();
+    path
+}
+
 macro_rules! test_harness_success {
     ($($file_name:ident),* $(,)?) => {
         #[cfg(test)]
@@ -78,6 +98,7 @@ test_harness_success! {
     heap_sort,
     help,
     html_table,
+    json,
     list_functions,
     letrec_mutual_recursion,
     letrec_simple_recursion,
@@ -95,6 +116,7 @@ test_harness_success! {
     require_prefix,
     result,
     search,
+    serialize_values,
     set_local,
     set_tail_call,
     shift_reset,
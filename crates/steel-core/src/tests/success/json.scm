@@ -0,0 +1,21 @@
+(struct Point (x y) #:transparent)
+
+(assert! (equal? (struct->json (Point 1 2)) "{\"x\":1,\"y\":2}"))
+
+(define point (json->struct struct:Point "{\"y\": 2, \"x\": 1}"))
+(assert! (= (Point-x point) 1))
+(assert! (= (Point-y point) 2))
+
+;; Nested structs are written as objects too
+(assert! (equal? (value->jsexpr-string (list (Point 1 (Point 2 3))))
+                 "[{\"x\":1,\"y\":{\"x\":2,\"y\":3}}]"))
+
+(assert! (equal? (value->pretty-jsexpr-string (list 1 2) 4) "[\n    1,\n    2\n]"))
+
+(define document (string->jsexpr "{\"users\": [{\"name\": \"a\"}, {\"name\": \"b\"}]}"))
+
+(assert! (equal? (jsexpr-pointer document "/users/1/name") "b"))
+(assert! (equal? (jsexpr-ref document 'users 0 'name) "a"))
+(assert! (not (jsexpr-ref document 'users 5)))
+
+(assert! (equal? (string->jsexpr "{\"a\": true}" (hash 'keys 'string)) (hash "a" #true)))
@@ -0,0 +1,24 @@
+(define (round-trip value)
+  (bytes->value (value->bytes value)))
+
+(assert! (equal? (round-trip (list 1 "two" 'three #\4))
+                 (list 1 "two" 'three #\4)))
+
+(define float (round-trip 2.5))
+(assert! (and (< 2.4 float) (< float 2.6)))
+
+(assert! (equal? (round-trip (hash 'a (vector 1 2) 'b (hashset 10 20)))
+                 (hash 'a (vector 1 2) 'b (hashset 10 20))))
+
+(assert! (equal? (round-trip (bytes 1 2 255)) (bytes 1 2 255)))
+
+(struct Point (x y) #:transparent)
+
+(assert! (equal? (round-trip (Point 10 20)) (Point 10 20)))
+(assert! (equal? (round-trip (Ok (Some 10))) (Ok (Some 10))))
+
+;; Mutable values which are shared are only written once
+(define shared (mutable-vector 1 2 3))
+(define copied (round-trip (list shared shared)))
+(vector-push! (car copied) 4)
+(assert! (equal? (mut-vec-len (cadr copied)) 4))
@@ -52,6 +52,17 @@ impl Heap {
         HeapRef { inner: weak_ptr }
     }
 
+    // Allocates without a collection first, for values that aren't reachable from any root
+    // yet, e.g. the captured variables of closures restored from an engine image
+    pub(crate) fn allocate_unrooted(&mut self, value: SteelVal) -> HeapRef {
+        let pointer = Rc::new(RefCell::new(HeapAllocated::new(value)));
+        let weak_ptr = Rc::downgrade(&pointer);
+
+        self.memory.push(pointer);
+
+        HeapRef { inner: weak_ptr }
+    }
+
     pub fn collect<'a>(
         &mut self,
         roots: impl Iterator<Item = &'a SteelVal>,
@@ -163,6 +174,11 @@ impl HeapRef {
         ret
     }
 
+    // Identifies the allocation, closures that share a variable share the allocation
+    pub(crate) fn as_ptr(&self) -> *const RefCell<HeapAllocated> {
+        self.inner.as_ptr()
+    }
+
     fn strong_ptr(&self) -> Rc<RefCell<HeapAllocated>> {
         self.inner.upgrade().unwrap()
     }
@@ -301,6 +317,23 @@ fn visit_contract_type(contract: &ContractType) {
         ContractType::Function(f) => {
             visit_function_contract(f);
         }
+        ContractType::Struct(s) => {
+            for field in s.fields.iter() {
+                visit_contract_type(field);
+            }
+        }
+        ContractType::Collection(c) => {
+            if let Some(key) = &c.key {
+                visit_contract_type(key);
+            }
+            visit_contract_type(&c.element);
+        }
+        ContractType::Combinator(c) => {
+            for contract in c.contracts.iter() {
+                visit_contract_type(contract);
+            }
+        }
+        ContractType::Parametric(_) => {}
     }
 }
 
@@ -1,8 +1,10 @@
 use crate::gc::Gc;
 use crate::rvals::{Result, SteelVal};
 // use itertools::Itertools;
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::parser::ast::IteratorExtensions;
 
@@ -98,6 +100,7 @@ pub struct DependentContract {
     pub(crate) pre_conditions: Box<[DependentPair]>,
     pub(crate) post_condition: DependentPair,
     pub(crate) contract_attachment_location: Option<String>,
+    pub(crate) boundary: Option<ContractBoundary>,
     parent: Option<Gc<FunctionKind>>,
 }
 
@@ -190,6 +193,7 @@ impl DependentContract {
             pre_conditions,
             post_condition,
             contract_attachment_location: None,
+            boundary: None,
             parent: None,
         };
 
@@ -215,15 +219,42 @@ impl Contract for DependentContract {
     fn set_attachment_location(&mut self, loc: Option<String>) {
         self.contract_attachment_location = loc
     }
+
+    fn set_boundary(&mut self, boundary: ContractBoundary) {
+        self.boundary = Some(boundary)
+    }
 }
 
 pub trait Contract {
     fn set_parent(&mut self, p: Gc<FunctionKind>);
     fn parent(&self) -> Option<Gc<FunctionKind>>;
     fn set_attachment_location(&mut self, loc: Option<String>);
+    fn set_boundary(&mut self, boundary: ContractBoundary);
     fn arity(&self) -> usize;
 }
 
+/// The modules on either side of a contract attached with `contract/out`
+#[derive(Clone, PartialEq, Debug)]
+pub struct ContractBoundary {
+    /// The module providing the value, which is blamed for bad results
+    pub(crate) provider: String,
+    /// The module that required the value, which is blamed for bad arguments
+    pub(crate) consumer: Option<String>,
+}
+
+impl ContractBoundary {
+    pub(crate) fn blame_domain(&self) -> String {
+        match &self.consumer {
+            Some(consumer) => format!("{consumer}, which required it from {}", self.provider),
+            None => format!("the caller of a value provided by {}", self.provider),
+        }
+    }
+
+    pub(crate) fn blame_range(&self) -> String {
+        self.provider.clone()
+    }
+}
+
 impl fmt::Display for DependentContract {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -246,6 +277,8 @@ pub struct FunctionContract {
     post_condition: Gc<ContractType>,
     /// Location/Name of contract attachment
     pub(crate) contract_attachment_location: Option<String>,
+    /// The modules on either side of the contract, if it was provided with `contract/out`
+    pub(crate) boundary: Option<ContractBoundary>,
     /// Stack of function contracts to also abide by, checked at application
     parent: Option<Gc<FunctionKind>>,
 }
@@ -266,6 +299,10 @@ impl Contract for FunctionContract {
     fn set_attachment_location(&mut self, loc: Option<String>) {
         self.contract_attachment_location = loc
     }
+
+    fn set_boundary(&mut self, boundary: ContractBoundary) {
+        self.boundary = Some(boundary)
+    }
 }
 
 impl fmt::Display for FunctionContract {
@@ -323,6 +360,7 @@ impl FunctionContract {
             pre_conditions,
             post_condition,
             contract_attachment_location,
+            boundary: None,
             parent,
         }
     }
@@ -334,12 +372,167 @@ impl From<FunctionContract> for SteelVal {
     }
 }
 
+/// Contracts on the fields of a struct instance, i.e. `(struct/c point int? int?)`
+#[derive(Clone, PartialEq)]
+pub struct StructContract {
+    /// Name of the struct the value must be an instance of
+    pub(crate) name: String,
+    /// One contract per field, in declaration order
+    pub(crate) fields: Box<[Gc<ContractType>]>,
+}
+
+impl StructContract {
+    pub fn new(name: String, fields: Box<[Gc<ContractType>]>) -> Self {
+        StructContract { name, fields }
+    }
+}
+
+impl fmt::Display for StructContract {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(struct/c {}", self.name)?;
+        for field in self.fields.iter() {
+            write!(f, " {}", **field)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CollectionKind {
+    List,
+    Vector,
+    Hash,
+    Stream,
+}
+
+/// Contracts on every element of a collection. When the elements only need a predicate run
+/// on them, lists, vectors and hashes are wrapped and each element is checked as it is read,
+/// otherwise they are checked (and their elements wrapped) when they cross the contract.
+/// Streams are always checked one element at a time as they are forced.
+#[derive(Clone, PartialEq)]
+pub struct CollectionContract {
+    pub(crate) kind: CollectionKind,
+    /// Contract on the keys, only present for hashes
+    pub(crate) key: Option<Gc<ContractType>>,
+    pub(crate) element: Gc<ContractType>,
+}
+
+impl CollectionContract {
+    pub fn new(
+        kind: CollectionKind,
+        key: Option<Gc<ContractType>>,
+        element: Gc<ContractType>,
+    ) -> Self {
+        CollectionContract { kind, key, element }
+    }
+
+    /// Whether the elements can be checked as they are read - this is only true when the
+    /// element contracts are first order, so checking an element hands back the element
+    pub(crate) fn is_lazy(&self) -> bool {
+        self.kind != CollectionKind::Stream
+            && self.element.is_first_order()
+            && self
+                .key
+                .as_ref()
+                .map(|x| x.is_first_order())
+                .unwrap_or(true)
+    }
+}
+
+impl fmt::Display for CollectionContract {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.kind, &self.key) {
+            (CollectionKind::List, _) => write!(f, "(listof {})", *self.element),
+            (CollectionKind::Vector, _) => write!(f, "(vectorof {})", *self.element),
+            (CollectionKind::Stream, _) => write!(f, "(streamof {})", *self.element),
+            (CollectionKind::Hash, Some(key)) => write!(f, "(hash/c {} {})", **key, *self.element),
+            (CollectionKind::Hash, None) => write!(f, "(hash/c any/c {})", *self.element),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CombinatorKind {
+    And,
+    Or,
+}
+
+/// `or/c` and `and/c` over any mix of contracts
+#[derive(Clone, PartialEq)]
+pub struct CombinatorContract {
+    pub(crate) kind: CombinatorKind,
+    pub(crate) contracts: Box<[Gc<ContractType>]>,
+}
+
+impl CombinatorContract {
+    pub fn new(kind: CombinatorKind, contracts: Box<[Gc<ContractType>]>) -> Self {
+        CombinatorContract { kind, contracts }
+    }
+}
+
+impl fmt::Display for CombinatorContract {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            CombinatorKind::And => write!(f, "(and/c")?,
+            CombinatorKind::Or => write!(f, "(or/c")?,
+        }
+        for contract in self.contracts.iter() {
+            write!(f, " {}", **contract)?;
+        }
+        write!(f, ")")
+    }
+}
+
+static NEXT_CONTRACT_VARIABLE: AtomicUsize = AtomicUsize::new(0);
+
+/// A contract variable made with `new-∀/c`. Any value is accepted in the domain of a
+/// function contract, but the range may only produce values the function was given
+/// for that same variable.
+#[derive(Clone, PartialEq)]
+pub struct ParametricContract {
+    pub(crate) id: usize,
+    pub(crate) name: String,
+}
+
+impl ParametricContract {
+    pub fn new(name: String) -> Self {
+        ParametricContract {
+            id: NEXT_CONTRACT_VARIABLE.fetch_add(1, Ordering::Relaxed),
+            name,
+        }
+    }
+}
+
+impl fmt::Display for ParametricContract {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
 /// The contract type. `Flat` contracts apply to reified values (non functions)
-/// `Function` contracts apply to exactly that - functions.
+/// `Function` contracts apply to exactly that - functions. The remaining kinds are
+/// built out of other contracts, and check the pieces of the value they are attached to.
 #[derive(Clone, PartialEq)]
 pub enum ContractType {
     Flat(FlatContract),
     Function(FunctionKind),
+    Struct(StructContract),
+    Collection(CollectionContract),
+    Combinator(CombinatorContract),
+    Parametric(ParametricContract),
+}
+
+impl ContractType {
+    /// Whether checking the contract leaves the value untouched, rather than wrapping it
+    pub(crate) fn is_first_order(&self) -> bool {
+        match self {
+            Self::Flat(_) => true,
+            Self::Function(_) | Self::Parametric(_) => false,
+            Self::Struct(s) => s.fields.iter().all(|x| x.is_first_order()),
+            Self::Collection(c) => c.is_lazy(),
+            Self::Combinator(c) => c.contracts.iter().all(|x| x.is_first_order()),
+        }
+    }
 }
 
 #[derive(Clone, PartialEq)]
@@ -376,6 +569,22 @@ impl Contract for FunctionKind {
             Self::Dependent(dc) => dc.set_attachment_location(loc),
         }
     }
+
+    fn set_boundary(&mut self, boundary: ContractBoundary) {
+        match self {
+            Self::Basic(fc) => fc.set_boundary(boundary),
+            Self::Dependent(dc) => dc.set_boundary(boundary),
+        }
+    }
+}
+
+impl FunctionKind {
+    pub(crate) fn boundary(&self) -> Option<&ContractBoundary> {
+        match self {
+            Self::Basic(fc) => fc.boundary.as_ref(),
+            Self::Dependent(dc) => dc.boundary.as_ref(),
+        }
+    }
 }
 
 impl fmt::Display for FunctionKind {
@@ -392,6 +601,10 @@ impl fmt::Display for ContractType {
         match self {
             Self::Flat(flat) => write!(f, "{flat}"),
             Self::Function(fc) => write!(f, "{fc}"),
+            Self::Struct(sc) => write!(f, "{sc}"),
+            Self::Collection(cc) => write!(f, "{cc}"),
+            Self::Combinator(cc) => write!(f, "{cc}"),
+            Self::Parametric(pc) => write!(f, "{pc}"),
         }
     }
 }
@@ -433,6 +646,7 @@ impl ContractedFunction {
         contract: SteelVal,
         function: SteelVal,
         name: Option<SteelVal>,
+        provider: Option<SteelVal>,
     ) -> Result<SteelVal> {
         let name = match name {
             Some(SteelVal::SymbolV(s)) => Some(s.to_string()),
@@ -467,8 +681,37 @@ impl ContractedFunction {
             }
         }
 
+        let mut contract = contract;
+
+        match provider {
+            Some(SteelVal::StringV(provider)) => contract.set_boundary(ContractBoundary {
+                provider: provider.to_string(),
+                consumer: None,
+            }),
+            Some(_) => {
+                stop!(TypeMismatch => "bind/c expected a string naming the providing module in the fourth position")
+            }
+            None => {}
+        }
+
         Ok(ContractedFunction::new(contract, function, name).into())
     }
+
+    /// Records the module on the receiving end of a contract provided with `contract/out`
+    pub fn with_consumer(&self, consumer: String) -> Self {
+        let mut contract = self.contract.clone();
+
+        if let Some(boundary) = contract.boundary() {
+            let boundary = ContractBoundary {
+                provider: boundary.provider.clone(),
+                consumer: Some(consumer),
+            };
+
+            contract.set_boundary(boundary);
+        }
+
+        ContractedFunction::new(contract, self.function.clone(), self.name.clone())
+    }
 }
 
 impl From<ContractedFunction> for SteelVal {
@@ -476,3 +719,70 @@ impl From<ContractedFunction> for SteelVal {
         SteelVal::ContractedFunction(Gc::new(val))
     }
 }
+
+/// A list, vector or hash map behind a collection contract, made when it crosses the contract.
+/// It is passed around and stored as is. Reading an element checks just that element, and a
+/// native function that needs to look inside of it any other way gets it checked in full first.
+pub struct ContractedCollection {
+    pub(crate) contract: CollectionContract,
+    /// The collection, which can be behind another contract itself
+    pub(crate) collection: SteelVal,
+    /// Name of the function whose contract this came through, for blaming purposes
+    pub(crate) name: Option<String>,
+    /// Who broke the contract, if an element turns out not to satisfy it
+    pub(crate) blame: String,
+    /// Set once every element has been checked
+    pub(crate) checked: Cell<bool>,
+}
+
+impl ContractedCollection {
+    pub fn new(
+        contract: CollectionContract,
+        collection: SteelVal,
+        name: Option<String>,
+        blame: String,
+    ) -> Self {
+        ContractedCollection {
+            contract,
+            collection,
+            name,
+            blame,
+            checked: Cell::new(false),
+        }
+    }
+
+    /// The collection underneath every contract, whether or not it has been checked
+    pub(crate) fn unchecked(&self) -> &SteelVal {
+        match &self.collection {
+            SteelVal::ContractedCollection(inner) => inner.unchecked(),
+            collection => collection,
+        }
+    }
+}
+
+/// The collection behind a lazily checked collection contract, or the value itself. For the
+/// predicates, which would otherwise answer for the contract instead of the collection.
+pub(crate) fn uncontracted(value: &SteelVal) -> &SteelVal {
+    match value {
+        SteelVal::ContractedCollection(c) => c.unchecked(),
+        value => value,
+    }
+}
+
+impl PartialEq for ContractedCollection {
+    fn eq(&self, other: &Self) -> bool {
+        self.unchecked() == other.unchecked()
+    }
+}
+
+impl fmt::Display for ContractedCollection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.unchecked())
+    }
+}
+
+impl From<ContractedCollection> for SteelVal {
+    fn from(val: ContractedCollection) -> Self {
+        SteelVal::ContractedCollection(Gc::new(val))
+    }
+}
@@ -28,7 +28,10 @@ use crate::{
     SteelErr, SteelVal,
 };
 
-use super::{closed::HeapRef, structs::UserDefinedStruct};
+use super::{
+    closed::HeapRef,
+    structs::{StructFunctionKind, StructTypeDescriptor, UserDefinedStruct},
+};
 
 // pub(crate) enum Function {
 //     BoxedFunction(BoxedFunctionSignature),
@@ -95,7 +98,7 @@ pub struct ByteCodeLambda {
     // pub(crate) body_exp: Rc<[DenseInstruction]>,
     arity: usize,
 
-    #[cfg(feature = "dynamic")]
+    #[cfg(any(feature = "dynamic", feature = "jit"))]
     call_count: Cell<usize>,
 
     pub(crate) is_multi_arity: bool,
@@ -199,7 +202,7 @@ impl ByteCodeLambda {
 
             arity,
 
-            #[cfg(feature = "dynamic")]
+            #[cfg(any(feature = "dynamic", feature = "jit"))]
             call_count: Cell::new(0),
 
             is_multi_arity,
@@ -296,14 +299,14 @@ impl ByteCodeLambda {
         &self.captures
     }
 
-    #[cfg(feature = "dynamic")]
+    #[cfg(any(feature = "dynamic", feature = "jit"))]
     #[inline(always)]
     pub fn increment_call_count(&self) {
         // self.call_count += 1;
         self.call_count.set(self.call_count.get() + 1);
     }
 
-    #[cfg(feature = "dynamic")]
+    #[cfg(any(feature = "dynamic", feature = "jit"))]
     pub fn call_count(&self) -> usize {
         self.call_count.get()
     }
@@ -413,6 +416,12 @@ pub struct BoxedDynFunction {
         Arc<dyn Fn(&[SteelVal]) -> crate::rvals::Result<SteelVal> + Send + Sync + 'static>,
     pub name: Option<StaticOrRcStr>,
     pub arity: Option<usize>,
+    // Set for native functions that call back into the VM while they run, see
+    // `VmCore::call_native_function`.
+    pub(crate) reentrant: bool,
+    // Set for the functions made by `make-struct-type`, so that they can be recreated from their
+    // struct type when written into an engine image.
+    pub(crate) struct_function: Option<(StructTypeDescriptor, StructFunctionKind)>,
 }
 
 impl BoxedDynFunction {
@@ -433,6 +442,8 @@ impl BoxedDynFunction {
                 .map(|x| Arc::new(x.to_string()))
                 .map(StaticOrRcStr::Owned),
             arity,
+            reentrant: false,
+            struct_function: None,
         }
     }
 
@@ -447,6 +458,8 @@ impl BoxedDynFunction {
             function,
             name: name.map(StaticOrRcStr::Owned),
             arity,
+            reentrant: false,
+            struct_function: None,
         }
     }
 
@@ -1,34 +1,117 @@
 use crate::{
     gc::Gc,
-    rerrs::SteelErr,
-    rvals::{FromSteelVal, IntoSteelVal, Result, SteelVal},
+    primitives::ports::EOF_OBJECT,
+    rerrs::{ErrorKind, SteelErr},
+    rvals::{FromSteelVal, IntoSteelVal, RestArgsIter, Result, SteelString, SteelVal},
     throw,
+    values::{
+        port::SteelPort,
+        structs::{StructTypeDescriptor, UserDefinedStruct},
+    },
 };
 use im_lists::list::List;
 use im_rc::HashMap;
-use serde_json::{Map, Number, Value};
+use serde::Serialize;
+use serde_json::{ser::PrettyFormatter, Deserializer, Map, Number, Value};
+use std::cell::RefCell;
 use std::convert::{TryFrom, TryInto};
+use std::io::{BufRead, Read};
+
+use steel_derive::function;
 
 // use list
 
+/// How the keys of json objects are turned into hash keys
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum KeyStyle {
+    Symbol,
+    String,
+}
+
+impl KeyStyle {
+    // Reads the `'keys` entry of an options hash, either `'symbol` (the default) or `'string`
+    pub(crate) fn from_options(options: Option<&SteelVal>) -> Result<Self> {
+        let options = match options {
+            Some(SteelVal::HashMapV(options)) => options,
+            Some(other) => {
+                stop!(TypeMismatch => "expected a hash of options, found: {}", other)
+            }
+            None => return Ok(KeyStyle::Symbol),
+        };
+
+        match options.get(&SteelVal::SymbolV("keys".into())) {
+            None => Ok(KeyStyle::Symbol),
+            Some(SteelVal::SymbolV(s)) if s.as_str() == "symbol" => Ok(KeyStyle::Symbol),
+            Some(SteelVal::SymbolV(s)) if s.as_str() == "string" => Ok(KeyStyle::String),
+            Some(other) => {
+                stop!(Generic => "the 'keys option must be 'symbol or 'string, found: {}", other)
+            }
+        }
+    }
+
+    pub(crate) fn key(self, key: String) -> SteelVal {
+        match self {
+            KeyStyle::Symbol => SteelVal::SymbolV(key.into()),
+            KeyStyle::String => SteelVal::StringV(key.into()),
+        }
+    }
+}
+
+fn json_to_steelval(val: Value, keys: KeyStyle) -> Result<SteelVal> {
+    match val {
+        Value::Array(v) => Ok(SteelVal::ListV(
+            v.into_iter()
+                .map(|x| json_to_steelval(x, keys))
+                .collect::<Result<List<SteelVal>>>()?,
+        )),
+        Value::Object(m) => {
+            let mut hm = HashMap::new();
+            for (key, value) in m {
+                hm.insert(keys.key(key), json_to_steelval(value, keys)?);
+            }
+            Ok(SteelVal::HashMapV(Gc::new(hm)))
+        }
+        other => other.try_into(),
+    }
+}
+
+// The location is already part of serde's message, this just moves it to the front
+fn parse_error(function: &str, error: serde_json::Error) -> SteelErr {
+    let message = error.to_string();
+    let location = format!(" at line {} column {}", error.line(), error.column());
+    let message = message.strip_suffix(&location).unwrap_or(&message);
+
+    SteelErr::new(
+        ErrorKind::Generic,
+        format!(
+            "{function} failed at line {}, column {}: {message}",
+            error.line(),
+            error.column()
+        ),
+    )
+}
+
 pub fn string_to_jsexpr() -> SteelVal {
     SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
-        if args.len() != 1 {
-            stop!(ArityMismatch => "string->jsexpr takes 1 argument");
+        if args.is_empty() || args.len() > 2 {
+            stop!(ArityMismatch => "string->jsexpr takes 1 or 2 arguments");
         } else {
             let arg =
                 &args[0].string_or_else(throw!(TypeMismatch => "string->jsexpr takes a string"))?;
+            let keys = KeyStyle::from_options(args.get(1))?;
             // let unescaped = unescape(arg);
             // let res: std::result::Result<Value, _> = serde_json::from_str(unescaped.as_str());
             let res: std::result::Result<Value, _> = serde_json::from_str(arg);
             match res {
-                Ok(res) => res.try_into(),
-                Err(e) => stop!(Generic => format!("string->jsexpr failed: {e}")),
+                Ok(res) => json_to_steelval(res, keys),
+                Err(e) => Err(parse_error("string->jsexpr", e)),
             }
         }
     })
 }
 
+// `void` is written as `null`, the same value that `null` is read as, so that documents can be
+// read and written back unchanged. Serializing it used to be an error.
 pub fn serialize_val_to_string() -> SteelVal {
     SteelVal::FuncV(|args: &[SteelVal]| -> Result<SteelVal> {
         if args.len() != 1 {
@@ -42,6 +125,290 @@ pub fn serialize_val_to_string() -> SteelVal {
     })
 }
 
+/// Serializes a value to json, spread over several lines and indented by the given number of
+/// spaces.
+///
+/// (value->pretty-jsexpr-string value [indent]) -> string?
+///
+/// * value : any/c
+/// * indent : int? = 2
+#[function(name = "value->pretty-jsexpr-string")]
+pub fn value_to_pretty_jsexpr_string(
+    value: &SteelVal,
+    mut rest: RestArgsIter<'_, isize>,
+) -> Result<SteelVal> {
+    let indent = rest.next().transpose()?.unwrap_or(2);
+
+    if indent < 0 {
+        stop!(ContractViolation => "value->pretty-jsexpr-string expects a non negative indent, found: {}", indent);
+    }
+
+    if rest.next().is_some() {
+        stop!(ArityMismatch => "value->pretty-jsexpr-string takes at most two arguments");
+    }
+
+    let value: Value = value.clone().try_into()?;
+    let indent = " ".repeat(indent as usize);
+
+    let mut output = Vec::new();
+    let mut serializer = serde_json::Serializer::with_formatter(
+        &mut output,
+        PrettyFormatter::with_indent(indent.as_bytes()),
+    );
+
+    value
+        .serialize(&mut serializer)
+        .map_err(|e| SteelErr::new(ErrorKind::Generic, e.to_string()))?;
+
+    Ok(SteelVal::StringV(
+        String::from_utf8(output)
+            .expect("serde_json only writes utf8")
+            .into(),
+    ))
+}
+
+/// Reads the next json value from an input port, leaving the rest of the input to be read later.
+/// This handles files with one value per line, or any other sequence of values separated by
+/// whitespace, without reading the whole file at once. Returns `'eof` once the input runs out.
+///
+/// (read-jsexpr port [options]) -> any/c
+///
+/// * port : input-port?
+/// * options : hash? - `'keys` is either `'symbol` (the default) or `'string`
+#[function(name = "read-jsexpr")]
+pub fn read_jsexpr(
+    port: &Gc<SteelPort>,
+    mut rest: RestArgsIter<'_, &SteelVal>,
+) -> Result<SteelVal> {
+    let keys = KeyStyle::from_options(rest.next().transpose()?)?;
+
+    if rest.next().is_some() {
+        stop!(ArityMismatch => "read-jsexpr takes at most two arguments");
+    }
+
+    let next = port.with_reader(|reader| {
+        let mut peeking = PeekingReader {
+            reader,
+            position: 0,
+            consumed: 0,
+        };
+
+        let mut values = Deserializer::from_reader(&mut peeking).into_iter::<Value>();
+        let next = values.next();
+        let offset = values.byte_offset();
+
+        // Only what the value was read from is taken out of the port. On errors everything that
+        // was looked at goes, so that reading again doesn't stop at the same place.
+        let end = match next {
+            Some(Err(_)) => peeking.consumed + peeking.position,
+            _ => offset,
+        };
+
+        peeking.reader.consume(end - peeking.consumed);
+
+        next
+    })?;
+
+    match next {
+        Some(Ok(value)) => json_to_steelval(value, keys),
+        Some(Err(e)) => Err(parse_error("read-jsexpr", e)),
+        None => Ok(SteelVal::SymbolV(EOF_OBJECT.with(|x| x.clone()))),
+    }
+}
+
+// Reads without taking anything out of the buffered reader until it has to refill, since the
+// parser looks one byte past the end of numbers, and that byte belongs to the next value
+struct PeekingReader<'a> {
+    reader: &'a mut dyn BufRead,
+    // How much of the reader's current buffer has been read
+    position: usize,
+    // How much has been taken out of the reader, from the buffers before the current one
+    consumed: usize,
+}
+
+impl Read for PeekingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position == self.reader.fill_buf()?.len() {
+            self.reader.consume(self.position);
+            self.consumed += self.position;
+            self.position = 0;
+        }
+
+        let available = &self.reader.fill_buf()?[self.position..];
+        let read = available.len().min(buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.position += read;
+
+        Ok(read)
+    }
+}
+
+// Looks up a key in a hash, trying both symbol and string keys, or an index in a list or vector
+fn lookup(value: &SteelVal, key: &SteelVal) -> Option<SteelVal> {
+    match (value, key) {
+        (SteelVal::HashMapV(map), SteelVal::SymbolV(key) | SteelVal::StringV(key)) => map
+            .get(&SteelVal::SymbolV(key.clone()))
+            .or_else(|| map.get(&SteelVal::StringV(key.clone())))
+            .cloned(),
+        (SteelVal::ListV(list), SteelVal::IntV(index)) if *index >= 0 => {
+            list.get(*index as usize).cloned()
+        }
+        (SteelVal::VectorV(vector), SteelVal::IntV(index)) if *index >= 0 => {
+            vector.get(*index as usize).cloned()
+        }
+        _ => None,
+    }
+}
+
+/// Finds a value by its JSON Pointer (RFC 6901), such as `"/users/0/name"`. Returns `#false`
+/// if there's nothing there.
+///
+/// (jsexpr-pointer value pointer) -> any/c
+///
+/// * value : any/c
+/// * pointer : string?
+#[function(name = "jsexpr-pointer")]
+pub fn jsexpr_pointer(value: &SteelVal, pointer: &SteelString) -> Result<SteelVal> {
+    if pointer.is_empty() {
+        return Ok(value.clone());
+    }
+
+    let Some(tokens) = pointer.strip_prefix('/') else {
+        stop!(Generic => "jsexpr-pointer: a pointer must be empty or start with a /, found: {}", pointer);
+    };
+
+    let mut current = value.clone();
+
+    for token in tokens.split('/') {
+        let token = token.replace("~1", "/").replace("~0", "~");
+
+        // Array indices are written without signs or leading zeros
+        let is_index = token == "0"
+            || (!token.starts_with('0')
+                && !token.is_empty()
+                && token.bytes().all(|x| x.is_ascii_digit()));
+
+        let key = match (&current, token.parse::<isize>()) {
+            (SteelVal::ListV(_) | SteelVal::VectorV(_), Ok(index)) if is_index => {
+                SteelVal::IntV(index)
+            }
+            _ => SteelVal::StringV(token.into()),
+        };
+
+        match lookup(&current, &key) {
+            Some(next) => current = next,
+            None => return Ok(SteelVal::BoolV(false)),
+        }
+    }
+
+    Ok(current)
+}
+
+/// Follows a path of keys and indices into a value. Returns `#false` if there's nothing there.
+///
+/// (jsexpr-ref value key ...) -> any/c
+///
+/// * value : any/c
+/// * key : (or/c symbol? string? int?)
+///
+/// # Examples
+/// ```scheme
+/// > (jsexpr-ref (string->jsexpr "{\"users\": [{\"name\": \"a\"}]}") 'users 0 'name) ;; => "a"
+/// ```
+#[function(name = "jsexpr-ref")]
+pub fn jsexpr_ref(value: &SteelVal, rest: RestArgsIter<'_, &SteelVal>) -> Result<SteelVal> {
+    let mut current = value.clone();
+
+    for key in rest {
+        match lookup(&current, key?) {
+            Some(next) => current = next,
+            None => return Ok(SteelVal::BoolV(false)),
+        }
+    }
+
+    Ok(current)
+}
+
+fn struct_to_object(value: &UserDefinedStruct) -> Result<Value> {
+    let Some(names) = value.field_names() else {
+        stop!(Generic => "the struct {} can't be serialized, since its field names are unknown", value.name);
+    };
+
+    let mut map = Map::new();
+    for (name, field) in names.iter().zip(value.fields.iter()) {
+        map.insert(name.to_string(), field.clone().try_into()?);
+    }
+
+    Ok(Value::Object(map))
+}
+
+/// Serializes a struct to a json object, keyed by the names of its fields. Structs nested inside
+/// of it are serialized the same way.
+///
+/// (struct->json value) -> string?
+///
+/// * value : struct?
+///
+/// # Examples
+/// ```scheme
+/// > (struct Point (x y))
+/// > (struct->json (Point 1 2)) ;; => "{\"x\":1,\"y\":2}"
+/// ```
+#[function(name = "struct->json")]
+pub fn struct_to_json(value: &SteelVal) -> Result<SteelVal> {
+    let SteelVal::CustomStruct(value) = value else {
+        stop!(TypeMismatch => "struct->json expects a struct, found: {}", value);
+    };
+
+    let object = struct_to_object(&value.borrow())?;
+    Ok(SteelVal::StringV(object.to_string().into()))
+}
+
+/// Builds a struct from json, or from a hash parsed out of it, matching the keys to the names
+/// of the struct's fields. Values nested inside are left as they are.
+///
+/// (json->struct struct-type json) -> struct?
+///
+/// * struct-type : struct-type? - the `struct:` binding made by `struct`
+/// * json : (or/c string? hash?)
+///
+/// # Examples
+/// ```scheme
+/// > (struct Point (x y))
+/// > (json->struct struct:Point "{\"x\": 1, \"y\": 2}") ;; => (Point 1 2)
+/// ```
+#[function(name = "json->struct")]
+pub fn json_to_struct(struct_type: &SteelVal, json: &SteelVal) -> Result<SteelVal> {
+    let descriptor = StructTypeDescriptor::from_steelval(struct_type)?;
+    let (name, names) = descriptor.name_and_fields();
+
+    let Some(names) = names else {
+        stop!(Generic => "json->struct: the field names of {} are unknown", name);
+    };
+
+    let object = match json {
+        SteelVal::StringV(s) => match serde_json::from_str::<Value>(s) {
+            Ok(value) => value.try_into()?,
+            Err(e) => return Err(parse_error("json->struct", e)),
+        },
+        SteelVal::HashMapV(_) => json.clone(),
+        _ => stop!(TypeMismatch => "json->struct expects a string or a hash, found: {}", json),
+    };
+
+    let fields = names
+        .iter()
+        .map(|field| {
+            lookup(&object, &SteelVal::SymbolV(field.clone())).ok_or_else(
+                throw!(Generic => "json->struct: missing the field {} of {}", field, name),
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(SteelVal::CustomStruct(Gc::new(RefCell::new(
+        UserDefinedStruct::new(name, descriptor, &fields),
+    ))))
+}
+
 // required to parse each string
 #[allow(unused)]
 fn unescape(s: &str) -> String {
@@ -75,11 +442,7 @@ fn unescape(s: &str) -> String {
 impl TryFrom<Map<String, Value>> for SteelVal {
     type Error = SteelErr;
     fn try_from(map: Map<String, Value>) -> std::result::Result<Self, Self::Error> {
-        let mut hm = HashMap::new();
-        for (key, value) in map {
-            hm.insert(SteelVal::SymbolV(key.into()), value.try_into()?);
-        }
-        Ok(SteelVal::HashMapV(Gc::new(hm)))
+        json_to_steelval(Value::Object(map), KeyStyle::Symbol)
     }
 }
 
@@ -101,12 +464,14 @@ impl TryFrom<Value> for SteelVal {
     }
 }
 
-// TODO
+// Whole numbers are read as integers, the rest as floats
 impl TryFrom<Number> for SteelVal {
     type Error = SteelErr;
     fn try_from(n: Number) -> std::result::Result<Self, Self::Error> {
-        let result = n.as_f64().unwrap();
-        Ok(SteelVal::NumV(result))
+        match n.as_i64().and_then(|x| isize::try_from(x).ok()) {
+            Some(n) => Ok(SteelVal::IntV(n)),
+            None => Ok(SteelVal::NumV(n.as_f64().unwrap())),
+        }
     }
 }
 
@@ -148,13 +513,15 @@ impl TryFrom<SteelVal> for Value {
                     .map(|x| x.clone().try_into())
                     .collect::<Result<Vec<_>>>()?,
             )),
-            SteelVal::Void => stop!(Generic => "void not serializable"),
+            // The inverse of reading `null`
+            SteelVal::Void => Ok(Value::Null),
             SteelVal::StringV(s) => Ok(Value::String(s.to_string())),
             SteelVal::FuncV(_) => stop!(Generic => "function not serializable"),
             // SteelVal::LambdaV(_) => stop!(Generic => "function not serializable"),
             // SteelVal::MacroV(_) => stop!(Generic => "macro not serializable"),
             SteelVal::SymbolV(s) => Ok(Value::String(s.to_string())),
             SteelVal::Custom(_) => stop!(Generic => "generic struct not serializable"),
+            SteelVal::CustomStruct(s) => struct_to_object(&s.borrow()),
             SteelVal::HashMapV(hm) => {
                 let mut map: Map<String, Value> = Map::new();
                 for (key, value) in hm.iter() {
@@ -182,6 +549,36 @@ impl TryFrom<SteelVal> for Value {
     }
 }
 
+/// Converts quoted syntax into JSON, with lists as arrays and symbols as strings. String
+/// literals are wrapped as `{"string": ...}` so they can be told apart from symbols.
+pub(crate) fn syntax_to_json(val: SteelVal) -> Result<Value> {
+    match val {
+        SteelVal::ListV(l) => Ok(Value::Array(
+            l.into_iter().map(syntax_to_json).collect::<Result<_>>()?,
+        )),
+        SteelVal::VectorV(v) => {
+            let mut map = Map::new();
+            map.insert(
+                "vector".to_string(),
+                Value::Array(
+                    v.iter()
+                        .cloned()
+                        .map(syntax_to_json)
+                        .collect::<Result<_>>()?,
+                ),
+            );
+            Ok(Value::Object(map))
+        }
+        SteelVal::StringV(s) => {
+            let mut map = Map::new();
+            map.insert("string".to_string(), Value::String(s.to_string()));
+            Ok(Value::Object(map))
+        }
+        SteelVal::Void => Ok(Value::Null),
+        other => other.try_into(),
+    }
+}
+
 #[cfg(test)]
 mod json_tests {
     use super::*;
@@ -209,4 +606,93 @@ mod json_tests {
 
         assert_eq!(result.unwrap(), expected);
     }
+
+    #[test]
+    fn test_string_keys() {
+        let args = vec![
+            StringV(r#"{"a": {"b": null}}"#.into()),
+            HashMapV(Gc::new(hashmap! {
+                SymbolV("keys".into()) => SymbolV("string".into())
+            })),
+        ];
+
+        let result = apply_function(string_to_jsexpr(), args);
+
+        let expected = HashMapV(Gc::new(hashmap! {
+            StringV("a".into()) => HashMapV(Gc::new(hashmap! {
+                StringV("b".into()) => Void
+            }))
+        }));
+
+        assert_eq!(result.unwrap(), expected);
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let args = vec![StringV("[1, 2\n  3]".into())];
+
+        let error = apply_function(string_to_jsexpr(), args).unwrap_err();
+
+        assert!(error
+            .to_string()
+            .contains("string->jsexpr failed at line 2, column 3: expected `,` or `]`"));
+    }
+
+    #[test]
+    fn test_read_jsexpr_keeps_what_follows_a_number() {
+        let path = crate::tests::write_file(
+            crate::tests::test_directory("json", "read_jsexpr").join("values.json"),
+            "1[2] 3.5{\"a\": 4}\n5",
+        );
+        let port = Gc::new(SteelPort::new_textual_file_input(path.to_str().unwrap()).unwrap());
+
+        let read = || read_jsexpr(&port, RestArgsIter::from_slice(&[]).unwrap()).unwrap();
+
+        assert_eq!(read(), IntV(1));
+        assert_eq!(read(), ListV(im_lists::list![IntV(2)]));
+        assert_eq!(read().to_string(), "3.5");
+        assert_eq!(
+            read(),
+            HashMapV(Gc::new(hashmap! { SymbolV("a".into()) => IntV(4) }))
+        );
+        assert_eq!(read(), IntV(5));
+        assert_eq!(read(), SymbolV(EOF_OBJECT.with(|x| x.clone())));
+    }
+
+    #[test]
+    fn test_pointer_and_ref() {
+        let value: SteelVal = serde_json::from_str::<Value>(r#"{"a": [10, {"b/c": 3, "d~": 4}]}"#)
+            .unwrap()
+            .try_into()
+            .unwrap();
+
+        assert_eq!(
+            jsexpr_pointer(&value, &"/a/1/b~1c".into()).unwrap(),
+            IntV(3)
+        );
+        assert_eq!(jsexpr_pointer(&value, &"/a/1/d~0".into()).unwrap(), IntV(4));
+        for pointer in ["/a/01", "/a/+1", "/a/-0"] {
+            assert_eq!(
+                jsexpr_pointer(&value, &pointer.into()).unwrap(),
+                BoolV(false)
+            );
+        }
+        assert_eq!(
+            jsexpr_pointer(&value, &"/a/5".into()).unwrap(),
+            BoolV(false)
+        );
+        assert_eq!(jsexpr_pointer(&value, &"".into()).unwrap(), value);
+        assert!(jsexpr_pointer(&value, &"a".into()).is_err());
+
+        assert_eq!(
+            lookup(&value, &StringV("a".into())).and_then(|x| lookup(&x, &IntV(0))),
+            Some(IntV(10))
+        );
+    }
+
+    #[test]
+    fn test_void_round_trips_as_null() {
+        let value: Value = ListV(im_lists::list![Void, IntV(1)]).try_into().unwrap();
+        assert_eq!(value.to_string(), "[null,1]");
+    }
 }
@@ -3,6 +3,7 @@ use std::fs::OpenOptions;
 use std::io;
 use std::io::prelude::*;
 use std::io::{BufReader, BufWriter, Stdin, Stdout};
+use std::process::ChildStderr;
 use std::process::ChildStdin;
 use std::process::ChildStdout;
 
@@ -42,6 +43,7 @@ pub enum SteelPort {
     StdOutput(RcRefCell<Stdout>),
     ChildStdOutput(RcRefCell<BufReader<ChildStdout>>),
     ChildStdInput(RcRefCell<BufWriter<ChildStdin>>),
+    ChildStdError(RcRefCell<BufReader<ChildStderr>>),
     // StringInput(RcRefCell<BufReader<&[u8]>>),
     StringOutput(RcRefCell<BufWriter<Vec<u8>>>),
     // DynWriter(Rc<RefCell<Box<dyn Write>>>),
@@ -159,6 +161,8 @@ impl SteelPort {
                 // buf_reader
             }
 
+            SteelPort::ChildStdError(br) => port_read_str_fn!(br, read_line),
+
             // SteelPort::ChildStdOutput(br) => port_read_str_fn!(br, read_line),
             // FIXME: fix this and the functions below
             _x => stop!(Generic => "read-line"),
@@ -169,10 +173,23 @@ impl SteelPort {
         match self {
             SteelPort::FileInput(_, br) => port_read_str_fn!(br, read_to_string),
             SteelPort::StdInput(br) => port_read_str_fn!(br, read_to_string),
+            SteelPort::ChildStdOutput(br) => port_read_str_fn!(br, read_to_string),
+            SteelPort::ChildStdError(br) => port_read_str_fn!(br, read_to_string),
             _x => stop!(Generic => "read-all-str"),
         }
     }
 
+    // Hands the underlying reader to `f`, for parsers that consume input directly
+    pub(crate) fn with_reader<T>(&self, f: impl FnOnce(&mut dyn BufRead) -> T) -> Result<T> {
+        match self {
+            SteelPort::FileInput(_, br) => Ok(f(&mut *br.borrow_mut())),
+            SteelPort::StdInput(br) => Ok(f(&mut br.borrow_mut().lock())),
+            SteelPort::ChildStdOutput(br) => Ok(f(&mut *br.borrow_mut())),
+            SteelPort::ChildStdError(br) => Ok(f(&mut *br.borrow_mut())),
+            _x => stop!(TypeMismatch => "expected an input port"),
+        }
+    }
+
     pub fn read_char(&mut self) -> Result<(usize, char)> {
         // FIXME: this only reads 1 u8 and casts it to char
         macro_rules! port_read_chr(
@@ -257,6 +274,30 @@ impl SteelPort {
         Ok(())
     }
 
+    // Hands the underlying writer to `f`, flushing once it's done
+    #[cfg(any(feature = "toml", feature = "yaml", feature = "csv"))]
+    pub(crate) fn with_writer<T>(
+        &self,
+        f: impl FnOnce(&mut dyn Write) -> io::Result<T>,
+    ) -> Result<T> {
+        macro_rules! with_writer(
+            ($br: ident) => {{
+                let br = &mut *$br.borrow_mut();
+                let result = f(br)?;
+                br.flush()?;
+                Ok(result)
+            }};
+        );
+
+        match self {
+            SteelPort::FileOutput(_, br) => with_writer!(br),
+            SteelPort::StdOutput(br) => with_writer!(br),
+            SteelPort::ChildStdInput(br) => with_writer!(br),
+            SteelPort::StringOutput(br) => with_writer!(br),
+            _x => stop!(TypeMismatch => "expected an output port"),
+        }
+    }
+
     pub fn write_string_line(&self, string: &str) -> Result<()> {
         macro_rules! write_string(
             ($br: ident) => {{
@@ -3,6 +3,7 @@
 
 use im_rc::HashMap;
 use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 
 use crate::compiler::map::SymbolMap;
 use crate::parser::interner::InternedString;
@@ -42,13 +43,15 @@ pub struct VTableEntry {
     proc: Option<usize>,
     transparent: bool,
     mutable: bool,
+    field_count: usize,
 }
 
 impl VTableEntry {
-    pub fn new(name: InternedString, proc: Option<usize>) -> Self {
+    pub fn new(name: InternedString, proc: Option<usize>, field_count: usize) -> Self {
         Self {
             name,
             proc,
+            field_count,
             properties: DEFAULT_PROPERTIES.with(|x| x.clone()),
             transparent: false,
             mutable: false,
@@ -79,6 +82,103 @@ pub struct StructTypeDescriptor(usize);
 
 impl Custom for StructTypeDescriptor {}
 
+impl StructTypeDescriptor {
+    // The name of the struct type, and the names of its fields if it was declared with `struct`
+    pub(crate) fn name_and_fields(&self) -> (InternedString, Option<Vec<SteelString>>) {
+        VTABLE.with(|x| {
+            let entry = &x.borrow().entries[self.0];
+            (entry.name, field_names(&entry.properties))
+        })
+    }
+
+    pub(crate) fn field_count(&self) -> usize {
+        VTABLE.with(|x| x.borrow().entries[self.0].field_count)
+    }
+
+    // Adds a new struct type with default properties, see `set_properties`
+    pub(crate) fn new_entry(name: InternedString, field_count: usize) -> Self {
+        VTable::new_entry(name, None, field_count)
+    }
+
+    pub(crate) fn proc(&self) -> Option<usize> {
+        VTABLE.with(|x| x.borrow().entries[self.0].proc)
+    }
+
+    pub(crate) fn properties(&self) -> Gc<im_rc::HashMap<SteelVal, SteelVal>> {
+        VTABLE.with(|x| x.borrow().entries[self.0].properties.clone())
+    }
+
+    pub(crate) fn set_properties(
+        &self,
+        proc: Option<usize>,
+        properties: Gc<im_rc::HashMap<SteelVal, SteelVal>>,
+    ) {
+        VTable::set_entry(self, proc, properties)
+    }
+
+    // Struct types that are set up by builtin modules rather than with `make-struct-type`. Every
+    // engine has these, so they can be found again with `lookup_struct_type_descriptor`.
+    pub(crate) fn is_builtin(&self) -> bool {
+        [
+            &OK_DESCRIPTOR,
+            &ERR_DESCRIPTOR,
+            &SOME_DESCRIPTOR,
+            &NONE_DESCRIPTOR,
+        ]
+        .iter()
+        .any(|x| x.with(|x| x == self))
+            || self.name_and_fields().0 == *TYPE_ID
+    }
+}
+
+/// The functions returned by `make-struct-type`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum StructFunctionKind {
+    Constructor,
+    Predicate,
+    Getter,
+    Setter,
+}
+
+/// Makes one of the functions of the struct type. The function remembers where it came from, so
+/// that it can be made again when it is written into an engine image.
+pub(crate) fn struct_function(
+    descriptor: StructTypeDescriptor,
+    kind: StructFunctionKind,
+) -> SteelVal {
+    let (name, _) = descriptor.name_and_fields();
+
+    let mut function = match kind {
+        StructFunctionKind::Constructor => {
+            UserDefinedStruct::constructor(name, descriptor.field_count(), descriptor)
+        }
+        StructFunctionKind::Predicate => UserDefinedStruct::predicate(name),
+        StructFunctionKind::Getter => UserDefinedStruct::getter_prototype(name),
+        StructFunctionKind::Setter => UserDefinedStruct::setter_prototype(name),
+    };
+
+    if let SteelVal::BoxedFunction(f) = &mut function {
+        if let Some(f) = Rc::get_mut(f) {
+            f.struct_function = Some((descriptor, kind));
+        }
+    }
+
+    function
+}
+
+fn field_names(properties: &im_rc::HashMap<SteelVal, SteelVal>) -> Option<Vec<SteelString>> {
+    match properties.get(&SteelVal::SymbolV("#:fields".into()))? {
+        SteelVal::ListV(fields) => fields
+            .iter()
+            .map(|field| match field {
+                SteelVal::SymbolV(field) => Some(field.clone()),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
 #[derive(Clone, Debug, Hash)]
 pub struct UserDefinedStruct {
     pub(crate) name: InternedString,
@@ -285,7 +385,7 @@ impl ImmutableMaybeHeapVec {
 }
 
 impl UserDefinedStruct {
-    fn new(
+    pub(crate) fn new(
         name: InternedString,
         type_descriptor: StructTypeDescriptor,
         fields: &[SteelVal],
@@ -336,6 +436,10 @@ impl UserDefinedStruct {
         // }
     }
 
+    pub(crate) fn field_names(&self) -> Option<Vec<SteelString>> {
+        VTABLE.with(|x| field_names(&x.borrow().entries[self.type_descriptor.0].properties))
+    }
+
     pub(crate) fn get(&self, val: &SteelVal) -> Option<SteelVal> {
         VTABLE.with(|x| {
             x.borrow().entries[self.type_descriptor.0]
@@ -371,7 +475,7 @@ impl UserDefinedStruct {
     }
 
     #[inline(always)]
-    fn is_err(&self) -> bool {
+    pub(crate) fn is_err(&self) -> bool {
         // todo!()
         self.name == *ERR_RESULT_LABEL
         // Arc::ptr_eq(&self.name, &ERR_RESULT_LABEL.with(|x| Rc::clone(x)))
@@ -681,15 +785,15 @@ pub fn make_struct_type(args: &[SteelVal]) -> Result<SteelVal> {
     }?;
 
     // Make a slot in the VTable for this struct
-    let struct_type_descriptor = VTable::new_entry(name, None);
+    let struct_type_descriptor = VTable::new_entry(name, None, *field_count as usize);
 
     // Build out the constructor and the predicate
     let struct_constructor =
-        UserDefinedStruct::constructor(name, *field_count as usize, struct_type_descriptor);
-    let struct_predicate = UserDefinedStruct::predicate(name);
+        struct_function(struct_type_descriptor, StructFunctionKind::Constructor);
+    let struct_predicate = struct_function(struct_type_descriptor, StructFunctionKind::Predicate);
 
-    let getter_prototype = UserDefinedStruct::getter_prototype(name);
-    let setter_prototype = UserDefinedStruct::setter_prototype(name);
+    let getter_prototype = struct_function(struct_type_descriptor, StructFunctionKind::Getter);
+    let setter_prototype = struct_function(struct_type_descriptor, StructFunctionKind::Setter);
 
     // We do not have the properties yet. Should probably intern the
     // let struct_type_id = new_type_id(name, address_or_name)
@@ -793,12 +897,18 @@ impl VTable {
     }
 
     // Returns a type descriptor, in this case it is just a usize
-    fn new_entry(name: InternedString, proc: Option<usize>) -> StructTypeDescriptor {
+    fn new_entry(
+        name: InternedString,
+        proc: Option<usize>,
+        field_count: usize,
+    ) -> StructTypeDescriptor {
         VTABLE.with(|x| {
             let mut guard = x.borrow_mut();
             let length = guard.entries.len();
 
-            guard.entries.push(VTableEntry::new(name, proc));
+            guard
+                .entries
+                .push(VTableEntry::new(name, proc, field_count));
 
             StructTypeDescriptor(length)
         })
@@ -836,6 +946,27 @@ impl VTable {
     // fn define_trait()
 }
 
+/// Find the type descriptor for the most recently defined struct type with the given name.
+pub(crate) fn lookup_struct_type_descriptor(name: InternedString) -> Option<StructTypeDescriptor> {
+    if name == *OK_RESULT_LABEL {
+        return Some(OK_DESCRIPTOR.with(|x| *x));
+    } else if name == *ERR_RESULT_LABEL {
+        return Some(ERR_DESCRIPTOR.with(|x| *x));
+    } else if name == *SOME_OPTION_LABEL {
+        return Some(SOME_DESCRIPTOR.with(|x| *x));
+    } else if name == *NONE_OPTION_LABEL {
+        return Some(NONE_DESCRIPTOR.with(|x| *x));
+    }
+
+    VTABLE.with(|x| {
+        x.borrow()
+            .entries
+            .iter()
+            .rposition(|entry| entry.name == name)
+            .map(StructTypeDescriptor)
+    })
+}
+
 pub static OK_RESULT_LABEL: Lazy<InternedString> = Lazy::new(|| "Ok".into());
 pub static SOME_OPTION_LABEL: Lazy<InternedString> = Lazy::new(|| "Some".into());
 pub static ERR_RESULT_LABEL: Lazy<InternedString> = Lazy::new(|| "Err".into());
@@ -881,10 +1012,10 @@ thread_local! {
     });
 
 
-    pub static OK_DESCRIPTOR: StructTypeDescriptor = VTable::new_entry(*OK_RESULT_LABEL, None);
-    pub static ERR_DESCRIPTOR: StructTypeDescriptor = VTable::new_entry(*ERR_RESULT_LABEL, None);
-    pub static SOME_DESCRIPTOR: StructTypeDescriptor = VTable::new_entry(*SOME_OPTION_LABEL, None);
-    pub static NONE_DESCRIPTOR: StructTypeDescriptor = VTable::new_entry(*NONE_OPTION_LABEL, None);
+    pub static OK_DESCRIPTOR: StructTypeDescriptor = VTable::new_entry(*OK_RESULT_LABEL, None, 1);
+    pub static ERR_DESCRIPTOR: StructTypeDescriptor = VTable::new_entry(*ERR_RESULT_LABEL, None, 1);
+    pub static SOME_DESCRIPTOR: StructTypeDescriptor = VTable::new_entry(*SOME_OPTION_LABEL, None, 1);
+    pub static NONE_DESCRIPTOR: StructTypeDescriptor = VTable::new_entry(*NONE_OPTION_LABEL, None, 0);
 
 
     // pub static OK_RESULT_LABEL: Rc<String> = Rc::new("Ok".into());
@@ -945,7 +1076,7 @@ pub(crate) fn build_type_id_module() -> BuiltInModule {
 
     let name = *TYPE_ID;
 
-    let type_descriptor = VTable::new_entry(name, None);
+    let type_descriptor = VTable::new_entry(name, None, 2);
 
     // Build the getter for the first index
     let getter = UserDefinedStruct::getter_prototype_index(name, 0);
@@ -0,0 +1,199 @@
+extern crate steel;
+
+use steel::steel_vm::builtin::{BuiltInModule, Documentation, MarkdownDoc};
+use steel::steel_vm::engine::Engine;
+use steel::SteelVal;
+use steel_derive::Steel;
+
+/// A counter that can be bumped from Steel
+#[derive(Clone, Debug, Steel, PartialEq)]
+pub struct Counter {
+    /// The current count
+    count: usize,
+    #[steel(rename = "label")]
+    name: String,
+    #[steel(skip)]
+    history: Vec<usize>,
+}
+
+#[steel::methods]
+impl Counter {
+    /// Adds one to the count
+    pub fn add_one(&mut self) -> usize {
+        self.history.push(self.count);
+        self.count += 1;
+        self.count
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.count == 0
+    }
+
+    #[steel(rename = "counter-reset!")]
+    pub fn reset(&mut self) {
+        self.count = 0;
+    }
+
+    #[steel(skip)]
+    pub fn skipped(&self) -> usize {
+        self.count
+    }
+
+    #[allow(dead_code)]
+    fn private(&self) -> usize {
+        self.count
+    }
+}
+
+#[derive(Clone, Debug, Steel, PartialEq)]
+#[steel(rename = "Server")]
+pub struct HttpServer {
+    port: usize,
+}
+
+#[steel::methods]
+impl HttpServer {
+    pub fn next_port(&self) -> usize {
+        self.port + 1
+    }
+}
+
+fn module() -> BuiltInModule {
+    let mut module = BuiltInModule::new("tests/derive");
+    module
+        .register_struct::<Counter>()
+        .register_methods::<Counter>()
+        .register_struct::<HttpServer>()
+        .register_methods::<HttpServer>();
+    module
+}
+
+fn engine() -> Engine {
+    let mut vm = Engine::new();
+    vm.register_module(module());
+    vm.run("(require-builtin tests/derive)").unwrap();
+    vm
+}
+
+#[test]
+fn derive_registers_constructor_predicate_and_getters() {
+    let mut vm = engine();
+
+    let results = vm
+        .run(
+            r#"(define counter (Counter 10 "clicks"))
+               (list (Counter? counter) (Counter? 10) (Counter-count counter) (Counter-label counter))"#,
+        )
+        .unwrap();
+
+    assert_eq!(
+        results.last().unwrap(),
+        &SteelVal::ListV(
+            vec![
+                SteelVal::BoolV(true),
+                SteelVal::BoolV(false),
+                SteelVal::IntV(10),
+                SteelVal::StringV("clicks".into()),
+            ]
+            .into()
+        )
+    );
+
+    // Skipped fields are filled in with their default value
+    assert_eq!(
+        vm.extract::<Counter>("counter").unwrap(),
+        Counter {
+            count: 10,
+            name: "clicks".to_string(),
+            history: Vec::new(),
+        }
+    );
+}
+
+#[test]
+fn derive_renames_and_skips_fields() {
+    let mut vm = engine();
+    vm.run(r#"(define counter (Counter 10 "clicks"))"#).unwrap();
+
+    assert!(vm.run("(Counter-name counter)").is_err());
+    assert!(vm.run("(Counter-history counter)").is_err());
+
+    vm.run("(define server (Server 8080))").unwrap();
+    assert_eq!(
+        vm.run("(Server-port server)").unwrap(),
+        vec![SteelVal::IntV(8080)]
+    );
+    assert!(vm.run("(HttpServer 8080)").is_err());
+}
+
+#[test]
+fn derive_registers_setters() {
+    let mut vm = engine();
+
+    vm.run(
+        r#"(define counter (Counter 10 "clicks"))
+           (set-Counter-count! counter 3)
+           (set-Counter-label! counter "taps")"#,
+    )
+    .unwrap();
+
+    let counter = vm.extract::<Counter>("counter").unwrap();
+    assert_eq!(counter.count, 3);
+    assert_eq!(counter.name, "taps");
+}
+
+#[test]
+fn methods_are_registered_with_a_kebab_case_prefix() {
+    let mut vm = engine();
+
+    vm.run(
+        r#"(define counter (Counter 10 "clicks"))
+           (define server (Server 8080))"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        vm.run("(counter-add-one counter)").unwrap(),
+        vec![SteelVal::IntV(11)]
+    );
+    assert_eq!(
+        vm.run("(counter-reset! counter) (counter-is-zero counter)")
+            .unwrap()
+            .last(),
+        Some(&SteelVal::BoolV(true))
+    );
+    assert_eq!(
+        vm.run("(http-server-next-port server)").unwrap(),
+        vec![SteelVal::IntV(8081)]
+    );
+
+    assert!(vm.run("(Counter-add-one counter)").is_err());
+    assert!(vm.run("(counter-skipped counter)").is_err());
+    assert!(vm.run("(counter-private counter)").is_err());
+
+    assert_eq!(vm.extract::<Counter>("counter").unwrap().history, vec![10]);
+}
+
+#[test]
+fn doc_comments_are_registered() {
+    let module = module();
+    let docs = module.documentation();
+
+    assert_eq!(
+        docs.get("Counter"),
+        Some(&Documentation::Markdown(MarkdownDoc(
+            "A counter that can be bumped from Steel"
+        )))
+    );
+    assert_eq!(
+        docs.get("Counter-count"),
+        Some(&Documentation::Markdown(MarkdownDoc("The current count")))
+    );
+    assert_eq!(
+        docs.get("counter-add-one"),
+        Some(&Documentation::Markdown(MarkdownDoc(
+            "Adds one to the count"
+        )))
+    );
+    assert_eq!(docs.get("counter-is-zero"), None);
+}
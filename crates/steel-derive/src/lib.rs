@@ -6,18 +6,48 @@ extern crate quote;
 use std::collections::HashMap;
 
 use proc_macro::TokenStream;
-use quote::{quote, ToTokens};
+use quote::{quote, quote_spanned, ToTokens};
 use syn::{
-    punctuated::Punctuated, Data, DeriveInput, Expr, ExprLit, FnArg, Ident, ItemFn, Lit, Meta,
-    ReturnType, Signature, Type,
+    punctuated::Punctuated, spanned::Spanned, Attribute, Data, DataStruct, DeriveInput, Expr,
+    ExprLit, Fields, FieldsNamed, FnArg, Ident, ImplItem, ItemFn, ItemImpl, Lit, LitStr, Meta,
+    ReturnType, Signature, Type, Visibility,
 };
 
-#[proc_macro_derive(Steel)]
+/// Implements `Custom` for a type, so it can be passed to and from Steel.
+///
+/// Structs with named fields can also be registered on a module with
+/// `BuiltInModule::register_struct`, which adds a constructor named after the struct, a
+/// `Struct?` predicate, and a `Struct-field` getter and `set-Struct-field!` setter for each
+/// field. Getters return a clone of the field, so every field type has to implement `Clone`.
+///
+/// Fields can be renamed with `#[steel(rename = "...")]`, and left out with `#[steel(skip)]`,
+/// in which case the constructor fills them in with `Default::default()`. A field that isn't
+/// `Clone` has to be skipped. The struct itself can be renamed the same way, and doc comments
+/// are registered as the documentation of the constructor and getters.
+#[proc_macro_derive(Steel, attributes(steel))]
 pub fn derive_steel(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     let name = &input.ident;
 
     match &input.data {
+        // Structs with named fields also get a constructor, a predicate and field accessors
+        Data::Struct(DataStruct {
+            fields: Fields::Named(fields),
+            ..
+        }) if input.generics.params.is_empty() => {
+            let register_struct = match derive_register_struct(&input, fields) {
+                Ok(register_struct) => register_struct,
+                Err(e) => return e.to_compile_error().into(),
+            };
+
+            let gen = quote! {
+                impl steel::rvals::Custom for #name {}
+
+                #register_struct
+            };
+
+            gen.into()
+        }
         Data::Struct(_) | Data::Enum(_) => {
             let gen = quote! {
                 impl steel::rvals::Custom for #name {}
@@ -32,6 +62,249 @@ pub fn derive_steel(input: TokenStream) -> TokenStream {
     }
 }
 
+#[derive(Default)]
+struct SteelAttributes {
+    skip: bool,
+    rename: Option<String>,
+}
+
+// Reads `#[steel(skip)]` and `#[steel(rename = "...")]`
+fn parse_steel_attributes(attrs: &[Attribute]) -> syn::Result<SteelAttributes> {
+    let mut parsed = SteelAttributes::default();
+
+    for attr in attrs.iter().filter(|x| x.path().is_ident("steel")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                parsed.skip = true;
+                Ok(())
+            } else if meta.path.is_ident("rename") {
+                let name: LitStr = meta.value()?.parse()?;
+                parsed.rename = Some(name.value());
+                Ok(())
+            } else {
+                Err(meta.error("expected `skip` or `rename = \"...\"`"))
+            }
+        })?;
+    }
+
+    Ok(parsed)
+}
+
+// `add_one` becomes `add-one`, and `HTTPServer` becomes `http-server`
+fn kebab_case(name: &str) -> String {
+    let chars = name.trim_start_matches("r#").chars().collect::<Vec<_>>();
+    let mut output = String::with_capacity(chars.len() + 4);
+
+    for (index, c) in chars.iter().copied().enumerate() {
+        if c == '_' {
+            output.push('-');
+            continue;
+        }
+
+        if c.is_uppercase() && index > 0 {
+            let previous = chars[index - 1];
+            let next = chars.get(index + 1).copied();
+
+            // A word starts at a capital after a lowercase letter or digit, or at the last
+            // capital of an acronym
+            let starts_word = previous.is_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_uppercase() && next.map(char::is_lowercase).unwrap_or(false));
+
+            if starts_word {
+                output.push('-');
+            }
+        }
+
+        output.extend(c.to_lowercase());
+    }
+
+    output
+}
+
+fn register_doc(name: &str, attrs: &[Attribute]) -> proc_macro2::TokenStream {
+    match parse_doc_attributes(attrs) {
+        Some(doc) => quote! {
+            module.register_doc(#name, steel::steel_vm::builtin::MarkdownDoc(#doc));
+        },
+        None => quote! {},
+    }
+}
+
+fn derive_register_struct(
+    input: &DeriveInput,
+    fields: &FieldsNamed,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let steel_name = parse_steel_attributes(&input.attrs)?
+        .rename
+        .unwrap_or_else(|| name.to_string());
+
+    let predicate = format!("{steel_name}?");
+
+    let mut arguments = Vec::new();
+    let mut argument_types = Vec::new();
+    let mut skipped = Vec::new();
+    let mut accessors = Vec::new();
+
+    for field in &fields.named {
+        let ident = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        let attributes = parse_steel_attributes(&field.attrs)?;
+
+        if attributes.skip {
+            skipped.push(ident);
+            continue;
+        }
+
+        let field_name = attributes
+            .rename
+            .unwrap_or_else(|| kebab_case(&ident.to_string()));
+
+        let getter = format!("{steel_name}-{field_name}");
+        let setter = format!("set-{steel_name}-{field_name}!");
+        let doc = register_doc(&getter, &field.attrs);
+
+        // Getters hand out a clone of the field, so a field that isn't `Clone` is reported at
+        // the field itself
+        let get = quote_spanned! {ty.span()=>
+            |value: &#name| -> #ty { ::std::clone::Clone::clone(&value.#ident) }
+        };
+
+        accessors.push(quote! {
+            module.register_fn(#getter, #get);
+            module.register_fn(#setter, |value: &mut #name, field: #ty| {
+                value.#ident = field;
+            });
+            #doc
+        });
+
+        arguments.push(ident);
+        argument_types.push(ty);
+    }
+
+    let doc = register_doc(&steel_name, &input.attrs);
+
+    Ok(quote! {
+        impl steel::steel_vm::builtin::RegisterStruct for #name {
+            fn register_struct(module: &mut steel::steel_vm::builtin::BuiltInModule) {
+                use steel::steel_vm::register_fn::RegisterFn;
+
+                module.register_type::<#name>(#predicate);
+                module.register_fn(#steel_name, |#(#arguments: #argument_types),*| #name {
+                    #(#arguments,)*
+                    #(#skipped: ::std::default::Default::default(),)*
+                });
+                #doc
+
+                #(#accessors)*
+            }
+        }
+    })
+}
+
+/// Registers every public method in an `impl` block with a kebab-case name prefixed by the
+/// kebab-case name of the type, so `Counter::add_one` becomes `counter-add-one`. Methods can be renamed with
+/// `#[steel(rename = "...")]` or left out with `#[steel(skip)]`, and the prefix can be changed
+/// with `#[steel::methods(name = "...")]`. Doc comments are registered as the documentation
+/// of each method.
+///
+/// The methods are added to a module with `BuiltInModule::register_methods`.
+#[proc_macro_attribute]
+pub fn methods(
+    args: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let args = parse_macro_input!(args with Punctuated::<Meta, Token![,]>::parse_terminated);
+    let mut input = parse_macro_input!(input as ItemImpl);
+    let keyword_map = parse_key_value_pairs(&args);
+
+    match register_methods(&mut input, keyword_map.get("name").cloned()) {
+        Ok(register_methods) => quote! {
+            #input
+
+            #register_methods
+        }
+        .into(),
+        Err(e) => {
+            let e = e.to_compile_error();
+
+            quote! {
+                #input
+
+                #e
+            }
+            .into()
+        }
+    }
+}
+
+fn register_methods(
+    input: &mut ItemImpl,
+    prefix: Option<String>,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let self_ty = input.self_ty.clone();
+
+    let prefix = match (prefix, self_ty.as_ref()) {
+        (Some(prefix), _) => prefix,
+        (None, Type::Path(path)) => {
+            kebab_case(&path.path.segments.last().unwrap().ident.to_string())
+        }
+        (None, ty) => {
+            return Err(syn::Error::new_spanned(
+                ty,
+                "expected a named type, or a prefix with `#[steel::methods(name = \"...\")]`",
+            ))
+        }
+    };
+
+    let mut registrations = Vec::new();
+
+    for item in input.items.iter_mut() {
+        let method = match item {
+            ImplItem::Fn(method) => method,
+            _ => continue,
+        };
+
+        let attributes = parse_steel_attributes(&method.attrs)?;
+
+        // The helper attributes aren't real attributes, so they can't be left in the output
+        method.attrs.retain(|x| !x.path().is_ident("steel"));
+
+        if attributes.skip || !matches!(method.vis, Visibility::Public(_)) {
+            continue;
+        }
+
+        if !method.sig.generics.params.is_empty() {
+            return Err(syn::Error::new_spanned(
+                &method.sig,
+                "generic methods can't be registered, skip it with `#[steel(skip)]`",
+            ));
+        }
+
+        let ident = &method.sig.ident;
+        let name = attributes
+            .rename
+            .unwrap_or_else(|| format!("{prefix}-{}", kebab_case(&ident.to_string())));
+        let doc = register_doc(&name, &method.attrs);
+
+        registrations.push(quote! {
+            module.register_fn(#name, <#self_ty>::#ident);
+            #doc
+        });
+    }
+
+    Ok(quote! {
+        impl steel::steel_vm::builtin::RegisterMethods for #self_ty {
+            fn register_methods(module: &mut steel::steel_vm::builtin::BuiltInModule) {
+                use steel::steel_vm::register_fn::RegisterFn;
+
+                #(#registrations)*
+            }
+        }
+    })
+}
+
 fn _parse_key_value_pair(args: &Punctuated<Meta, Token![,]>) -> (String, String) {
     for nested_meta in args.iter() {
         if let Meta::NameValue(n) = nested_meta {
@@ -77,12 +350,15 @@ fn parse_key_value_pairs(args: &Punctuated<Meta, Token![,]>) -> HashMap<String,
 }
 
 fn parse_doc_comment(input: ItemFn) -> Option<String> {
-    let maybe_str_literals = input
-        .attrs
-        .into_iter()
-        .filter_map(|attr| match attr.meta {
+    parse_doc_attributes(&input.attrs)
+}
+
+fn parse_doc_attributes(attrs: &[Attribute]) -> Option<String> {
+    let maybe_str_literals = attrs
+        .iter()
+        .filter_map(|attr| match &attr.meta {
             Meta::NameValue(name_value) if name_value.path.is_ident("doc") => {
-                Some(name_value.value)
+                Some(name_value.value.clone())
             }
             _ => None,
         })
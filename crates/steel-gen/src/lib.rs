@@ -3,6 +3,7 @@
 
 pub mod opcode;
 pub mod permutations;
+pub mod profile;
 use std::{borrow::Cow, fmt::Write};
 
 pub use opcode::OpCode;
@@ -61,8 +62,46 @@ impl std::fmt::Display for LocalVariable {
     }
 }
 
-fn op_code_to_handler(op_code: Pattern) -> String {
-    format!("opcode_to_ssa_handler!({op_code})")
+// The op codes `opcode_to_ssa_handler!` has a handler for in the vm
+const SSA_HANDLERS: &[&str] = &[
+    "READLOCAL0",
+    "READLOCAL1",
+    "READLOCAL2",
+    "READLOCAL3",
+    "MOVEREADLOCAL0",
+    "MOVEREADLOCAL1",
+    "MOVEREADLOCAL2",
+    "MOVEREADLOCAL3",
+];
+
+fn op_code_to_handler(op_code: Pattern) -> Option<String> {
+    let name = op_code.to_string();
+
+    if SSA_HANDLERS.contains(&name.as_str()) {
+        Some(format!("opcode_to_ssa_handler!({name})"))
+    } else {
+        None
+    }
+}
+
+// Whether `binop_opcode_to_ssa_handler!` has a handler for these operands. Only the handlers
+// that return a `Result` are used, since that's what the generated call expects. Equality
+// between two values with the same type hint is done inline instead.
+fn has_binop_handler(op: Pattern, left: TypeHint, right: TypeHint) -> bool {
+    use OpCode::*;
+
+    match op {
+        Pattern::Double(EQUAL, 2) => left == right,
+        Pattern::Double(SUB, 2) => matches!(
+            (left, right),
+            (TypeHint::Int, TypeHint::Int)
+                | (TypeHint::Int, TypeHint::None)
+                | (TypeHint::None, TypeHint::Int)
+        ),
+        Pattern::Double(MUL, 2) => matches!((left, right), (TypeHint::Int, TypeHint::None)),
+        Pattern::Double(LTE, 2) => matches!((left, right), (TypeHint::None, TypeHint::Int)),
+        _ => false,
+    }
 }
 
 struct StackToSSAConverter {
@@ -129,11 +168,12 @@ impl StackToSSAConverter {
         var
     }
 
-    pub fn pop(&mut self) -> LocalVariable {
-        self.stack.pop().unwrap()
+    pub fn pop(&mut self) -> Option<LocalVariable> {
+        self.stack.pop()
     }
 
-    pub fn process_sequence(&mut self, op_codes: &[Pattern]) -> Function {
+    // Returns `None` if there isn't a handler for one of the op codes in the sequence
+    pub fn process_sequence(&mut self, op_codes: &[Pattern]) -> Option<Function> {
         use OpCode::*;
         use Pattern::*;
 
@@ -210,6 +250,11 @@ impl StackToSSAConverter {
                 Double(CALLGLOBAL, n) => {
                     // println!("Stack: {:?}", self.stack);
 
+                    // The arguments were pushed before the sequence started
+                    if self.stack.len() < *n {
+                        return None;
+                    }
+
                     let args = self
                         .stack
                         .split_off(self.stack.len() - n)
@@ -250,13 +295,13 @@ impl StackToSSAConverter {
                         lines.line(format!(
                             "let {} = {}(ctx)?;",
                             local,
-                            op_code_to_handler(*op)
+                            op_code_to_handler(*op)?
                         ));
 
                         max_local_offset_read = max_local_offset_read.max(*n);
                     } else {
                         let local = self.push();
-                        let var = self.stack.get(*n).unwrap();
+                        let var = self.stack.get(*n)?;
                         lines.line(format!("let {local} = {var}.clone();"));
                     }
                 }
@@ -275,13 +320,13 @@ impl StackToSSAConverter {
                         lines.line(format!(
                             "let {} = {}(ctx)?;",
                             local,
-                            op_code_to_handler(*op)
+                            op_code_to_handler(*op)?
                         ));
 
                         max_local_offset_read = max_local_offset_read.max(0);
                     } else {
                         let local = self.push();
-                        let var = self.stack.get(0).unwrap();
+                        let var = self.stack.get(0)?;
                         lines.line(format!("let {local} = {var}.clone();"));
                     }
                 }
@@ -293,13 +338,13 @@ impl StackToSSAConverter {
                         lines.line(format!(
                             "let {} = {}(ctx)?;",
                             local,
-                            op_code_to_handler(*op)
+                            op_code_to_handler(*op)?
                         ));
 
                         max_local_offset_read = max_local_offset_read.max(1);
                     } else {
                         let local = self.push();
-                        let var = self.stack.get(1).unwrap();
+                        let var = self.stack.get(1)?;
                         lines.line(format!("let {local} = {var}.clone();"));
                     }
                 }
@@ -311,13 +356,13 @@ impl StackToSSAConverter {
                         lines.line(format!(
                             "let {} = {}(ctx)?;",
                             local,
-                            op_code_to_handler(*op)
+                            op_code_to_handler(*op)?
                         ));
 
                         max_local_offset_read = max_local_offset_read.max(2);
                     } else {
                         let local = self.push();
-                        let var = self.stack.get(2).unwrap();
+                        let var = self.stack.get(2)?;
                         lines.line(format!("let {local} = {var}.clone();"));
                     }
                 }
@@ -329,13 +374,13 @@ impl StackToSSAConverter {
                         lines.line(format!(
                             "let {} = {}(ctx)?;",
                             local,
-                            op_code_to_handler(*op)
+                            op_code_to_handler(*op)?
                         ));
 
                         max_local_offset_read = max_local_offset_read.max(3);
                     } else {
                         let local = self.push();
-                        let var = self.stack.get(3).unwrap();
+                        let var = self.stack.get(3)?;
                         lines.line(format!("let {local} = {var}.clone();"));
                     }
                 }
@@ -347,13 +392,13 @@ impl StackToSSAConverter {
                         lines.line(format!(
                             "let {} = {}(ctx)?;",
                             local,
-                            op_code_to_handler(*op)
+                            op_code_to_handler(*op)?
                         ));
 
                         max_local_offset_read = max_local_offset_read.max(*n);
                     } else {
                         let local = self.push();
-                        let var = self.stack.get(*n).unwrap();
+                        let var = self.stack.get(*n)?;
                         lines.line(format!("let {local} = {var};"));
                     }
                 }
@@ -372,13 +417,13 @@ impl StackToSSAConverter {
                         lines.line(format!(
                             "let {} = {}(ctx)?;",
                             local,
-                            op_code_to_handler(*op)
+                            op_code_to_handler(*op)?
                         ));
 
                         max_local_offset_read = max_local_offset_read.max(0);
                     } else {
                         let local = self.push();
-                        let var = self.stack.get(0).unwrap();
+                        let var = self.stack.get(0)?;
                         lines.line(format!("let {local} = {var};"));
                     }
                 }
@@ -390,13 +435,13 @@ impl StackToSSAConverter {
                         lines.line(format!(
                             "let {} = {}(ctx)?;",
                             local,
-                            op_code_to_handler(*op)
+                            op_code_to_handler(*op)?
                         ));
 
                         max_local_offset_read = max_local_offset_read.max(1);
                     } else {
                         let local = self.push();
-                        let var = self.stack.get(1).unwrap();
+                        let var = self.stack.get(1)?;
                         lines.line(format!("let {local} = {var};"));
                     }
                 }
@@ -408,13 +453,13 @@ impl StackToSSAConverter {
                         lines.line(format!(
                             "let {} = {}(ctx)?;",
                             local,
-                            op_code_to_handler(*op)
+                            op_code_to_handler(*op)?
                         ));
 
                         max_local_offset_read = max_local_offset_read.max(2);
                     } else {
                         let local = self.push();
-                        let var = self.stack.get(2).unwrap();
+                        let var = self.stack.get(2)?;
                         lines.line(format!("let {local} = {var};"));
                     }
                 }
@@ -426,13 +471,13 @@ impl StackToSSAConverter {
                         lines.line(format!(
                             "let {} = {}(ctx)?;",
                             local,
-                            op_code_to_handler(*op)
+                            op_code_to_handler(*op)?
                         ));
 
                         max_local_offset_read = max_local_offset_read.max(3);
                     } else {
                         let local = self.push();
-                        let var = self.stack.get(3).unwrap();
+                        let var = self.stack.get(3)?;
                         lines.line(format!("let {local} = {var};"));
                     }
                 }
@@ -441,7 +486,7 @@ impl StackToSSAConverter {
                     lines.line(format!(
                         "let {} = {}(ctx)?;",
                         local,
-                        op_code_to_handler(*op)
+                        op_code_to_handler(*op)?
                     ));
                 }
                 // Single(PUSH) => {
@@ -455,7 +500,7 @@ impl StackToSSAConverter {
                 // }
                 Single(IF) => {
                     // (if <test> <then> <else>)
-                    let test_condition = self.pop();
+                    let test_condition = self.pop()?;
 
                     // If we're dealing with an int, just unbox it directly
                     match test_condition.type_hint {
@@ -472,20 +517,22 @@ impl StackToSSAConverter {
                     }
                 }
                 Double(LTE | EQUAL, 2) => {
-                    let right = self.pop();
-                    let left = self.pop();
+                    let right = self.pop()?;
+                    let left = self.pop()?;
+
+                    if !has_binop_handler(*op, left.type_hint, right.type_hint) {
+                        return None;
+                    }
 
                     lines.line("ctx.ip += 2;");
 
                     max_ip_read += 2;
 
                     match (left.type_hint, right.type_hint) {
-                        (a, b) if a == b => {
+                        (a, b) if a == b && matches!(op, Double(EQUAL, 2)) => {
                             let local = self.push_with_hint(TypeHint::Bool);
 
                             lines.line(format!("let {local} = {left} == {right};"));
-                            lines.line("ctx.ip += 1;");
-                            max_ip_read += 1;
                         }
                         (TypeHint::Int, TypeHint::Int) => {
                             // Delegate to the binary handler to return an int
@@ -554,15 +601,19 @@ impl StackToSSAConverter {
 
                             push_binop(&mut lines, local, call, left, right);
                         }
-                        (_, _) => todo!(),
+                        (_, _) => return None,
                     }
                 }
 
                 // TODO: Need to handle the actual op code as well
                 // READLOCAL0, LOADINT2, LTE, IF
                 Double(ADD | MUL | SUB | DIV, 2) => {
-                    let right = self.pop();
-                    let left = self.pop();
+                    let right = self.pop()?;
+                    let left = self.pop()?;
+
+                    if !has_binop_handler(*op, left.type_hint, right.type_hint) {
+                        return None;
+                    }
 
                     lines.line("ctx.ip += 2;");
 
@@ -570,10 +621,17 @@ impl StackToSSAConverter {
 
                     match (left.type_hint, right.type_hint) {
                         (TypeHint::Int, TypeHint::Int) => {
-                            // Delegate to the binary handler to return an int
+                            // Delegate to the binary handler. Subtraction can overflow into
+                            // a big int, so the result isn't known to be an int
                             let call = format!("binop_opcode_to_ssa_handler!({op}, Int, Int)");
 
-                            let local = self.push_with_hint(TypeHint::Int);
+                            let hint = if matches!(op, Double(SUB, 2)) {
+                                TypeHint::None
+                            } else {
+                                TypeHint::Int
+                            };
+
+                            let local = self.push_with_hint(hint);
 
                             push_binop(&mut lines, local, call, left, right);
                         }
@@ -636,11 +694,11 @@ impl StackToSSAConverter {
 
                             push_binop(&mut lines, local, call, left, right);
                         }
-                        (_, _) => todo!(),
+                        (_, _) => return None,
                     }
                 }
                 _ => {
-                    todo!()
+                    return None;
                 }
             }
         }
@@ -678,7 +736,7 @@ impl StackToSSAConverter {
 
         // scope.to_string()
 
-        function
+        Some(function)
     }
 }
 
@@ -736,6 +794,14 @@ impl<'a> std::fmt::Display for Call<'a> {
 // READCAPTURED, TAILCALL
 
 impl Pattern {
+    // The op code and payload this was made from. Payloads that don't matter are left as 0.
+    pub fn to_opcode(&self) -> (OpCode, usize) {
+        match self {
+            Pattern::Single(op) => (*op, 0),
+            Pattern::Double(op, payload) => (*op, *payload),
+        }
+    }
+
     pub fn from_opcodes(op_codes: &[(OpCode, usize)]) -> Vec<Pattern> {
         use OpCode::*;
 
@@ -815,7 +881,13 @@ pub fn generate_opcode_map(patterns: Vec<Vec<(OpCode, usize)>>) -> String {
             .iter()
             .map(|x| format!("{x}").to_lowercase())
             .join("_");
-        let generated_function = converter.process_sequence(&pattern);
+        let generated_function = match converter.process_sequence(&pattern) {
+            Some(function) => function,
+            None => {
+                converter.reset();
+                continue;
+            }
+        };
 
         let mut scope = Scope::new();
 
@@ -867,6 +939,97 @@ pub fn generate_opcode_map(patterns: Vec<Vec<(OpCode, usize)>>) -> String {
     format!("{}\n{}", top_level_definition, global_scope.to_string())
 }
 
+/// Whether a super instruction can be generated for this sequence of op codes.
+pub fn can_fuse(op_codes: &[(OpCode, usize)]) -> bool {
+    let pattern = Pattern::from_opcodes(op_codes);
+
+    // Anything that `from_opcodes` doesn't know about gets dropped from the pattern
+    if pattern.len() != op_codes.len() || pattern.len() < 2 {
+        return false;
+    }
+
+    // Control can only leave the sequence at the very end. Scopes are left alone, since the
+    // generated code treats the locals of a scope as if the sequence started the frame.
+    let branches_early = pattern[..pattern.len() - 1]
+        .iter()
+        .any(|x| x.to_opcode().0 == OpCode::IF);
+
+    if branches_early
+        || pattern
+            .iter()
+            .any(|x| x.to_opcode().0 == OpCode::BEGINSCOPE)
+    {
+        return false;
+    }
+
+    StackToSSAConverter::new()
+        .process_sequence(&pattern)
+        .is_some()
+}
+
+fn pattern_path(pattern: &Pattern) -> String {
+    match pattern {
+        Pattern::Single(op) => format!("steel_gen::Pattern::Single(OpCode::{op:?})"),
+        Pattern::Double(op, payload) => {
+            format!("steel_gen::Pattern::Double(OpCode::{op:?}, {payload})")
+        }
+    }
+}
+
+/// Generates a handler for each sequence of op codes, fusing the whole sequence into a single
+/// super instruction, along with the `SUPER_INSTRUCTIONS` table the compiler uses to rewrite
+/// those sequences into `OpCode::SUPERINSTRUCTION`. Sequences that can't be fused are skipped.
+///
+/// The table is sorted so that longer sequences are tried first.
+pub fn generate_super_instructions(patterns: Vec<Vec<(OpCode, usize)>>) -> String {
+    let mut scope = Scope::new();
+    let mut entries = Vec::new();
+    let mut names = std::collections::HashSet::new();
+
+    let mut converter = StackToSSAConverter::new();
+
+    for op_codes in patterns {
+        if !can_fuse(&op_codes) {
+            continue;
+        }
+
+        let pattern = Pattern::from_opcodes(&op_codes);
+        let name = pattern
+            .iter()
+            .map(|x| format!("{x}").to_lowercase())
+            .join("_");
+
+        // `READLOCAL0` and `READLOCAL 0` end up with the same handler
+        if !names.insert(name.clone()) {
+            continue;
+        }
+
+        converter.reset();
+
+        if let Some(function) = converter.process_sequence(&pattern) {
+            scope.push_fn(function);
+            entries.push((pattern, name));
+        }
+    }
+
+    entries.sort_by_key(|(pattern, _)| std::cmp::Reverse(pattern.len()));
+
+    let table = entries
+        .iter()
+        .map(|(pattern, name)| {
+            format!(
+                "    SuperInstruction {{ pattern: &[{}], handler: {name} }},\n",
+                pattern.iter().map(pattern_path).join(", ")
+            )
+        })
+        .collect::<String>();
+
+    format!(
+        "pub(crate) static SUPER_INSTRUCTIONS: &[SuperInstruction] = &[\n{table}];\n\n{}",
+        scope.to_string()
+    )
+}
+
 #[test]
 fn test() {
     // let op_codes = vec![
@@ -902,7 +1065,7 @@ fn test() {
 
     let mut stack_to_ssa = StackToSSAConverter::new();
 
-    let result = stack_to_ssa.process_sequence(&op_codes);
+    let result = stack_to_ssa.process_sequence(&op_codes).unwrap();
 
     let mut scope = Scope::new();
 
@@ -927,3 +1090,37 @@ fn test_generation() {
 
     println!("{}", generate_opcode_map(patterns));
 }
+
+#[test]
+fn test_super_instruction_generation() {
+    use OpCode::*;
+
+    let fusable = vec![
+        (MOVEREADLOCAL0, 0),
+        (LOADINT2, 225),
+        (SUB, 2),
+        (CALLGLOBAL, 1),
+    ];
+
+    // There's nothing on the stack to add to
+    let unfusable = vec![(LOADINT1, 0), (ADD, 2)];
+
+    assert!(can_fuse(&fusable));
+    assert!(!can_fuse(&unfusable));
+
+    let generated = generate_super_instructions(vec![unfusable, fusable]);
+
+    assert!(generated.contains("handler: movereadlocal0_loadint2_sub2_callglobal1"));
+    assert!(!generated.contains("add2"));
+}
+
+#[test]
+fn test_int_subtraction_is_not_assumed_to_stay_an_int() {
+    use OpCode::*;
+
+    // Subtracting two ints can overflow into a big int
+    let generated = generate_super_instructions(vec![vec![(LOADINT2, 0), (LOADINT1, 0), (SUB, 2)]]);
+
+    assert!(generated.contains("binop_opcode_to_ssa_handler!(SUB2, Int, Int)"));
+    assert!(generated.contains("ctx.thread.stack.push(x2);"));
+}
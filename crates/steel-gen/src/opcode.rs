@@ -69,6 +69,19 @@ pub enum OpCode {
     LTEIMMEDIATE,
     BINOPADD,
     LTEIMMEDIATEIF,
+    // Runs a sequence of op codes fused at build time, see `generate_super_instructions`
+    SUPERINSTRUCTION,
+}
+
+// Op codes are named the same way they are printed
+impl std::str::FromStr for OpCode {
+    type Err = serde::de::value::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use serde::de::IntoDeserializer;
+
+        OpCode::deserialize(s.into_deserializer())
+    }
 }
 
 impl OpCode {
@@ -0,0 +1,133 @@
+// The file that connects a profiled run of a workload to the build. The vm writes out the op
+// code sequences it saw most often, and the build script of steel-core reads them back in to
+// generate super instructions for them.
+//
+// Each line holds one sequence, most frequent first, as the number of times it ran followed by
+// its op codes. An op code is followed by `:payload` when the payload is part of the pattern:
+//
+// ; op code sequences, most frequent first
+// 102334 READLOCAL0 LOADINT2 LTE:2 IF
+// 99820 MOVEREADLOCAL0 LOADINT1 SUB:2 CALLGLOBAL:1
+//
+// Lines starting with `;` are comments.
+
+use crate::{OpCode, Pattern};
+
+const HEADER: &str = "; op code sequences, most frequent first";
+
+/// Writes out the sequences, along with how many times each of them ran.
+pub fn write_profile(sequences: &[(Vec<Pattern>, usize)]) -> String {
+    let mut output = String::from(HEADER);
+    output.push('\n');
+
+    for (sequence, count) in sequences {
+        output.push_str(&count.to_string());
+
+        for pattern in sequence {
+            output.push(' ');
+
+            match pattern {
+                Pattern::Single(op) => output.push_str(&format!("{op:?}")),
+                Pattern::Double(op, payload) => output.push_str(&format!("{op:?}:{payload}")),
+            }
+        }
+
+        output.push('\n');
+    }
+
+    output
+}
+
+/// Reads the sequences back out of a profile, in the order they were written.
+pub fn read_profile(contents: &str) -> Result<Vec<Vec<(OpCode, usize)>>, String> {
+    let mut sequences = Vec::new();
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+
+        let error = |message: String| format!("line {}: {}", number + 1, message);
+
+        let mut words = line.split_whitespace();
+
+        // The count is only there for whoever reads the file
+        let count = words.next().unwrap_or_default();
+        if count.parse::<usize>().is_err() {
+            return Err(error(format!("expected a count, found: {count}")));
+        }
+
+        let mut sequence = Vec::new();
+
+        for word in words {
+            let (name, payload) = match word.split_once(':') {
+                Some((name, payload)) => {
+                    let payload = payload
+                        .parse::<usize>()
+                        .map_err(|_| error(format!("invalid payload: {payload}")))?;
+
+                    (name, payload)
+                }
+                None => (word, 0),
+            };
+
+            let op_code = name
+                .parse::<OpCode>()
+                .map_err(|_| error(format!("unknown op code: {name}")))?;
+
+            sequence.push((op_code, payload));
+        }
+
+        if sequence.is_empty() {
+            return Err(error("expected a sequence of op codes".to_string()));
+        }
+
+        sequences.push(sequence);
+    }
+
+    Ok(sequences)
+}
+
+#[test]
+fn test_profile_round_trip() {
+    use OpCode::*;
+
+    let sequences = vec![
+        (
+            vec![
+                Pattern::Single(READLOCAL0),
+                Pattern::Single(LOADINT2),
+                Pattern::Double(LTE, 2),
+                Pattern::Single(IF),
+            ],
+            1000,
+        ),
+        (
+            vec![
+                Pattern::Single(MOVEREADLOCAL1),
+                Pattern::Double(CALLGLOBAL, 1),
+            ],
+            10,
+        ),
+    ];
+
+    let profile = write_profile(&sequences);
+
+    assert_eq!(
+        read_profile(&profile).unwrap(),
+        vec![
+            vec![(READLOCAL0, 0), (LOADINT2, 0), (LTE, 2), (IF, 0)],
+            vec![(MOVEREADLOCAL1, 0), (CALLGLOBAL, 1)],
+        ]
+    );
+}
+
+#[test]
+fn test_profile_errors() {
+    assert!(read_profile("12 READLOCAL0 NOTANOPCODE").is_err());
+    assert!(read_profile("READLOCAL0 LOADINT1").is_err());
+    assert!(read_profile("12 SUB:two").is_err());
+    assert!(read_profile("12").is_err());
+}
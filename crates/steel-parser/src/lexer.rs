@@ -214,6 +214,22 @@ mod tests {
         assert_eq!(s.next(), None);
     }
 
+    #[test]
+    fn test_forall_identifier() {
+        let mut s = TokenStream::new("new-∀/c", true, None);
+
+        assert_eq!(
+            s.next(),
+            Some(Token {
+                ty: Identifier("new-∀/c"),
+                source: "new-∀/c",
+                span: Span::new(0, 9, None)
+            })
+        );
+
+        assert_eq!(s.next(), None);
+    }
+
     #[test]
     fn test_number() {
         let mut s = TokenStream::new("0 -0 -1.2 +2.3 999 1.", true, None);
@@ -274,6 +290,16 @@ mod tests {
         assert_eq!(s.next(), None);
     }
 
+    #[test]
+    fn test_string_escapes() {
+        let mut s = TokenStream::new(r#""a\\nb\tc\n""#, true, None);
+
+        assert_eq!(
+            s.next().map(|x| x.ty),
+            Some(StringLiteral("a\\nb\tc\n".to_owned()))
+        );
+    }
+
     #[test]
     fn test_string() {
         let mut s = TokenStream::new(r#" "" "Foo bar" "\"\\" "#, true, None);
@@ -296,21 +322,12 @@ mod tests {
             })
         );
 
-        // TODO come check out this test
-        // assert_eq!(
-        //     s.next(),
-        //     Some(Token {
-        //         ty: StringLiteral(r#""\"\\""#.to_owned()),
-        //         source: r#""\"\\""#,
-        //         span: Span::new(14, 20),
-        //     })
-        // );
         assert_eq!(
             s.next(),
             Some(Token {
-                ty: Error,
-                source: "\"\\\"",
-                span: Span::new(14, 17, None),
+                ty: StringLiteral("\"\\".to_owned()),
+                source: r#""\"\\""#,
+                span: Span::new(14, 20, None),
             })
         );
 
@@ -107,15 +107,39 @@ fn parse_str<'a>(lex: &mut Lexer<'a, TokenType<&'a str>>) -> Option<String> {
 
     // Trim off the start and end of the string
     // We don't need that inside the lexer at all
-    slice
+    let inner = slice
         .strip_suffix('\"')
         .or(Some(slice))
         .and_then(|x| x.strip_prefix('\"'))
-        .or(Some(slice))
-        .map(|x| x.replace("\\\"", "\""))
-        .map(|x| x.replace("\\n", "\n"))
-        .map(|x| x.replace("\\r", "\r"))
-    // .map(|x| x.to_string())
+        .unwrap_or(slice);
+
+    // Unescape in a single pass, so that an escaped backslash isn't read as the start
+    // of another escape
+    let mut output = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('"') => output.push('"'),
+            Some('\\') => output.push('\\'),
+            Some('n') => output.push('\n'),
+            Some('r') => output.push('\r'),
+            Some('t') => output.push('\t'),
+            // Unicode escapes are left for the reader
+            Some(other) => {
+                output.push('\\');
+                output.push(other);
+            }
+            None => output.push('\\'),
+        }
+    }
+
+    Some(output)
 }
 
 // TODO the character parsing is not quite right
@@ -219,7 +243,7 @@ pub enum TokenType<S> {
     // /// An identifier literal.
     // #[regex(r#"(?&ident)"#)]
     // Identifier(String),
-    #[regex(r#"[_:\#\+\-\*\x2F%\&\|!?\~<>=@\.\p{XID_Start}\p{Emoji_Presentation}]['_:\+\-\*\x2F%\&\|!?\~<>=@\.\p{XID_Continue}\p{Emoji_Presentation}]*"#, callback = |lex| lex.slice())]
+    #[regex(r#"[_:\#\+\-\*\x2F%\&\|!?\~<>=@\.\p{XID_Start}\p{Emoji_Presentation}∀]['_:\+\-\*\x2F%\&\|!?\~<>=@\.\p{XID_Continue}\p{Emoji_Presentation}∀]*"#, callback = |lex| lex.slice())]
     // "
     Identifier(S),
 
@@ -243,7 +267,7 @@ pub enum TokenType<S> {
 
     // #[regex(r#"b?"(\\.|[^\\"])*""#, parse_str)] // "
     // #[regex(r#"(?:[^"]|\\")*", parse_str)] // "
-    #[regex(r#""([^"\\]|\\t|\\u|\\n|\\r|\\")*""#, parse_str)]
+    #[regex(r#""([^"\\]|\\t|\\u|\\n|\\r|\\"|\\\\)*""#, parse_str)]
     StringLiteral(String),
 
     #[error]
@@ -1,4 +1,4 @@
-use abi_stable::std_types::RVec;
+use abi_stable::std_types::{RHashMap, RString, RVec};
 
 use toml::Value;
 
@@ -46,19 +46,23 @@ fn as_ffi_value(value: &Value) -> FFIValue {
         Value::Integer(i) => (*i as isize).into(),
         Value::Float(f) => (*f).into(),
         Value::Boolean(b) => (*b).into(),
-        Value::Datetime(_) => todo!(),
+        // Custom values don't survive the trip across the dylib boundary, so these come through
+        // in their TOML form. Offset date times are RFC 3339 and dates are ISO 8601, so
+        // `datetime/parse` and `date/parse` from steel/time turn them into calendar values.
+        // Local date times have no offset, and `datetime/parse` takes them to be in UTC. Local
+        // times have no date at all, so neither function accepts them.
+        Value::Datetime(d) => d.to_string().into(),
         Value::Array(a) => a.iter().map(as_ffi_value).collect::<RVec<_>>().into(),
-        // Value::Table(m) => Ok(SteelVal::HashMapV(steel::gc::Gc::new(
-        //     m.into_iter()
-        //         .map(|x| {
-        //             let key: steel::SteelVal = x.0.clone().into();
-        //             let value = as_ffi_value(x.1);
-
-        //             Ok((key, value))
-        //         })
-        //         .collect::<steel::rvals::Result<steel::HashMap<_, _>>>()?,
-        // ))),
-        _ => todo!(),
+        Value::Table(m) => FFIValue::HashMap(
+            m.iter()
+                .map(|(key, value)| {
+                    (
+                        FFIValue::StringV(RString::from(key.as_str())),
+                        as_ffi_value(value),
+                    )
+                })
+                .collect::<RHashMap<_, _>>(),
+        ),
     }
 }
 // impl FromSteelVal for SteelTomlValue {
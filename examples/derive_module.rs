@@ -0,0 +1,78 @@
+use steel::steel_vm::builtin::BuiltInModule;
+use steel::steel_vm::engine::Engine;
+
+use steel_derive::Steel;
+
+/// A counter that can be bumped from Steel
+#[derive(Clone, Debug, Steel, PartialEq)]
+pub struct Counter {
+    /// The current count
+    count: usize,
+    #[steel(rename = "label")]
+    name: String,
+    // Not visible from Steel, the constructor fills it in with `Default::default()`
+    #[steel(skip)]
+    history: Vec<usize>,
+}
+
+#[steel::methods]
+impl Counter {
+    /// Adds one to the count
+    pub fn add_one(&mut self) -> usize {
+        self.history.push(self.count);
+        self.count += 1;
+        self.count
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.count == 0
+    }
+
+    #[steel(rename = "counter-reset!")]
+    pub fn reset(&mut self) {
+        self.count = 0;
+    }
+
+    // Private methods are not registered
+    #[allow(dead_code)]
+    fn secret(&self) -> usize {
+        self.count
+    }
+}
+
+// `#[derive(Steel)]` and `#[steel::methods]` generate the registration code that would
+// otherwise have to be written by hand with `register_fn`.
+pub fn main() {
+    let mut module = BuiltInModule::new("example/counter");
+    module
+        .register_struct::<Counter>()
+        .register_methods::<Counter>();
+
+    let mut vm = Engine::new();
+    vm.register_module(module);
+
+    vm.compile_and_run_raw_program(
+        r#"
+        (require-builtin example/counter)
+
+        (define counter (Counter 10 "clicks"))
+
+        (assert! (Counter? counter))
+        (assert! (equal? (Counter-label counter) "clicks"))
+
+        (counter-add-one counter)
+        (assert! (equal? (Counter-count counter) 11))
+
+        (set-Counter-count! counter 0)
+        (assert! (counter-is-zero counter))
+
+        (counter-add-one counter)
+        (counter-reset! counter)
+        (assert! (counter-is-zero counter))
+    "#,
+    )
+    .unwrap();
+
+    let counter = vm.extract::<Counter>("counter").unwrap();
+    assert_eq!(counter.history, vec![10, 0]);
+}
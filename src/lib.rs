@@ -2,12 +2,18 @@ extern crate steel;
 extern crate steel_derive;
 extern crate steel_repl;
 
-use steel::steel_vm::engine::Engine;
+use steel::parser::formatter::{format_source, FormatConfig};
+use steel::steel_vm::{
+    engine::Engine,
+    packages::{LockedPackage, PackageManager},
+    test_runner::{discover_test_files, TestRunner},
+};
 use steel_doc::walk_dir;
 use steel_repl::repl::repl_base;
 
 use std::path::PathBuf;
 use std::process;
+use std::time::Duration;
 use std::{error::Error, fs};
 
 use clap::Parser;
@@ -26,30 +32,172 @@ pub struct Args {
 
     /// Arguments to the input file
     arguments: Vec<String>,
+
+    /// Start from an engine image written with `--save-image`, instead of a fresh engine
+    #[clap(long)]
+    image: Option<PathBuf>,
+
+    /// Write the state of the engine to an image after running the input file
+    #[clap(long)]
+    save_image: Option<PathBuf>,
+
+    /// A directory to search when resolving requires, ahead of the ones in STEEL_PATH.
+    /// May be given more than once.
+    #[clap(long = "search-path", short = 'I', global = true)]
+    search_paths: Vec<PathBuf>,
+
+    /// Count the sequences of op codes executed while running the input file, and write the
+    /// most frequent ones to this file. Building steel with STEEL_SUPERINSTRUCTIONS set to this
+    /// file fuses each of them into a single instruction.
+    #[cfg(feature = "profiling")]
+    #[clap(long)]
+    profile_superinstructions: Option<PathBuf>,
+
+    /// How many sequences to write with `--profile-superinstructions`
+    #[cfg(feature = "profiling")]
+    #[clap(long, default_value_t = 16)]
+    superinstructions: usize,
 }
 
 #[derive(clap::Subcommand, Debug)]
 enum EmitAction {
-    /// Output a debug display of the fully transformed bytecode
-    Bytecode { default_file: Option<PathBuf> },
-    /// Print a debug display of the fully expanded AST
-    Ast { default_file: Option<PathBuf> },
+    /// Print a listing of the compiled bytecode for each function
+    Bytecode {
+        default_file: Option<PathBuf>,
+        #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+    /// Print the fully expanded AST
+    Ast {
+        default_file: Option<PathBuf>,
+        #[clap(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
     /// Enter the repl with the given file loaded
     Interactive {
         default_file: Option<PathBuf>,
         arguments: Vec<String>,
     },
-    /// Test the module
-    Test { default_file: Option<String> },
+    /// Run the tests in a file, or in every file under a directory that uses `test-module`
+    Test {
+        default_file: Option<PathBuf>,
+        /// Only run tests whose name, or test module name, contains this string
+        #[clap(long)]
+        filter: Option<String>,
+        /// Number of tests to run at the same time
+        #[clap(long, short, default_value_t = 1)]
+        jobs: usize,
+        /// Stop after the first test with a failure
+        #[clap(long)]
+        fail_fast: bool,
+        /// Give up on a test after this many seconds
+        #[clap(long)]
+        timeout: Option<u64>,
+        #[clap(long, value_enum, default_value_t = TestFormat::Pretty)]
+        format: TestFormat,
+        /// Write the report to this file instead of stdout
+        #[clap(long)]
+        output: Option<PathBuf>,
+    },
+    /// Format the given files, or every .scm file under the given directories. Reads from
+    /// stdin and writes to stdout if no paths are given
+    Fmt {
+        paths: Vec<PathBuf>,
+        /// Don't write anything, and fail if any file isn't formatted
+        #[clap(long)]
+        check: bool,
+        /// The line width to try to stay under
+        #[clap(long, default_value_t = 80)]
+        width: usize,
+        /// Format a macro like a body form, with `NAME` or `NAME=N` for a macro with N
+        /// distinguished arguments before the body (1 by default)
+        #[clap(long = "body-form")]
+        body_forms: Vec<String>,
+    },
     /// Generate the documentation for a file
     Doc { default_file: Option<PathBuf> },
+    /// Install the packages a project depends on, as declared in its cog.scm
+    Pkg {
+        #[clap(subcommand)]
+        command: PkgCommand,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum PkgCommand {
+    /// Install the dependencies of a project, sticking to the versions in its cog-lock.scm
+    Install {
+        #[clap(flatten)]
+        location: PkgLocation,
+    },
+    /// Move every dependency to the newest version allowed, and rewrite cog-lock.scm
+    Update {
+        #[clap(flatten)]
+        location: PkgLocation,
+    },
+    /// List the installed packages
+    List {
+        #[clap(flatten)]
+        location: PkgLocation,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct PkgLocation {
+    /// The directory containing the project's cog.scm
+    #[clap(default_value = ".")]
+    project: PathBuf,
+    /// Install to $STEEL_HOME, rather than to the project's .steel directory
+    #[clap(long)]
+    global: bool,
+}
+
+impl PkgLocation {
+    fn manager(&self) -> steel::rvals::Result<PackageManager> {
+        if self.global {
+            PackageManager::global()
+        } else {
+            Ok(PackageManager::project(&self.project))
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum TestFormat {
+    Pretty,
+    Junit,
+    Tap,
 }
 
 pub fn run(clap_args: Args) -> Result<(), Box<dyn Error>> {
-    let mut vm = Engine::new();
+    let mut vm = match &clap_args.image {
+        Some(image) => Engine::from_snapshot(image)?,
+        None => Engine::new(),
+    };
 
     vm.register_value("std::env::args", steel::SteelVal::ListV(vec![].into()));
 
+    for directory in &clap_args.search_paths {
+        vm.add_search_directory(directory.clone());
+    }
+
+    #[cfg(feature = "profiling")]
+    let sequence_profile = clap_args
+        .profile_superinstructions
+        .clone()
+        .map(|path| (path, clap_args.superinstructions));
+
+    #[cfg(feature = "profiling")]
+    if sequence_profile.is_some() {
+        vm.record_op_code_sequences();
+    }
+
     match clap_args {
         Args {
             default_file: None,
@@ -64,6 +212,8 @@ pub fn run(clap_args: Args) -> Result<(), Box<dyn Error>> {
             default_file: Some(path),
             action: None,
             arguments,
+            save_image,
+            ..
         } => {
             vm.register_value(
                 "std::env::args",
@@ -85,15 +235,70 @@ pub fn run(clap_args: Args) -> Result<(), Box<dyn Error>> {
                 return Err(Box::new(e));
             }
 
+            if let Some(image) = save_image {
+                vm.snapshot(image)?;
+            }
+
+            #[cfg(feature = "profiling")]
+            if let Some((profile, count)) = sequence_profile {
+                fs::write(profile, vm.op_code_sequence_profile(count))?;
+            }
+
             Ok(())
         }
 
         Args {
             default_file: None,
-            action: Some(EmitAction::Test { default_file: _ }),
+            action:
+                Some(EmitAction::Test {
+                    default_file,
+                    filter,
+                    jobs,
+                    fail_fast,
+                    timeout,
+                    format,
+                    output,
+                }),
+            search_paths,
             ..
         } => {
-            todo!()
+            let mut runner = TestRunner::new()
+                .with_jobs(jobs)
+                .with_fail_fast(fail_fast)
+                .with_search_directories(search_paths);
+
+            if let Some(filter) = filter {
+                runner = runner.with_filter(filter);
+            }
+
+            if let Some(timeout) = timeout {
+                runner = runner.with_timeout(Duration::from_secs(timeout));
+            }
+
+            let files = discover_test_files(default_file.unwrap_or_else(|| PathBuf::from(".")))?;
+
+            let report = runner.run_with_progress(&files, |file| {
+                if format == TestFormat::Pretty {
+                    print!("{file}");
+                }
+            });
+
+            let rendered = match format {
+                TestFormat::Pretty => report.to_string(),
+                TestFormat::Junit => report.to_junit_xml(),
+                TestFormat::Tap => report.to_tap(),
+            };
+
+            match output {
+                Some(output) => fs::write(output, rendered)?,
+                None => print!("{rendered}"),
+            }
+
+            if !report.is_success() {
+                process::exit(1);
+            }
+
+            Ok(())
         }
 
         Args {
@@ -114,23 +319,119 @@ pub fn run(clap_args: Args) -> Result<(), Box<dyn Error>> {
             // todo!()
         }
 
+        Args {
+            default_file: None,
+            action: Some(EmitAction::Pkg { command }),
+            ..
+        } => {
+            match command {
+                PkgCommand::Install { location } => {
+                    let installed = location.manager()?.install(&location.project)?;
+                    print_packages(&installed);
+                }
+                PkgCommand::Update { location } => {
+                    let installed = location.manager()?.update(&location.project)?;
+                    print_packages(&installed);
+                }
+                PkgCommand::List { location } => {
+                    for manifest in location.manager()?.installed()? {
+                        println!("{} {}", manifest.name, manifest.version);
+                    }
+                }
+            }
+
+            Ok(())
+        }
+
+        Args {
+            default_file: None,
+            action:
+                Some(EmitAction::Fmt {
+                    paths,
+                    check,
+                    width,
+                    body_forms,
+                }),
+            ..
+        } => {
+            let mut config = FormatConfig::default();
+            config.width = width;
+
+            for body_form in body_forms {
+                config = match body_form.split_once('=') {
+                    Some((name, distinguished)) => {
+                        config.with_body_form(name, distinguished.parse::<usize>()?)
+                    }
+                    None => config.with_body_form(body_form, 1),
+                };
+            }
+
+            if paths.is_empty() {
+                let source = std::io::read_to_string(std::io::stdin())?;
+                let formatted = format_source(&source, &config)?;
+
+                if check && formatted != source {
+                    process::exit(1);
+                }
+
+                if !check {
+                    print!("{formatted}");
+                }
+
+                return Ok(());
+            }
+
+            let mut unformatted = Vec::new();
+
+            for path in paths {
+                for file in scheme_files(&path)? {
+                    let source = fs::read_to_string(&file)?;
+
+                    let formatted = match format_source(&source, &config) {
+                        Ok(formatted) => formatted,
+                        Err(e) => {
+                            e.emit_result(&file.to_string_lossy(), &source);
+                            return Err(Box::new(e));
+                        }
+                    };
+
+                    if formatted != source {
+                        if check {
+                            unformatted.push(file);
+                        } else {
+                            fs::write(&file, formatted)?;
+                        }
+                    }
+                }
+            }
+
+            if !unformatted.is_empty() {
+                for file in unformatted {
+                    println!("Not formatted: {}", file.display());
+                }
+
+                process::exit(1);
+            }
+
+            Ok(())
+        }
+
         Args {
             default_file: None,
             action:
                 Some(EmitAction::Bytecode {
                     default_file: Some(path),
+                    format,
                 }),
             ..
         } => {
             let contents = fs::read_to_string(&path)?;
 
-            let program = vm.emit_raw_program(&contents, path.clone());
-
-            match program {
-                Ok(program) => {
-                    vm.debug_print_build(path.to_str().unwrap().to_string(), program)
-                        .unwrap();
+            match vm.disassemble(&contents, Some(path.clone())) {
+                Ok(listing) if format == OutputFormat::Json => {
+                    println!("{}", serde_json::to_string_pretty(&listing)?)
                 }
+                Ok(listing) => print!("{listing}"),
                 Err(e) => e.emit_result(path.to_str().unwrap(), &contents),
             }
 
@@ -139,14 +440,23 @@ pub fn run(clap_args: Args) -> Result<(), Box<dyn Error>> {
 
         Args {
             default_file: None,
-            action: Some(EmitAction::Ast {
-                default_file: Some(path),
-            }),
+            action:
+                Some(EmitAction::Ast {
+                    default_file: Some(path),
+                    format,
+                }),
             ..
         } => {
             let contents = fs::read_to_string(path.clone())?;
 
-            let res = vm.emit_fully_expanded_ast_to_string(&contents, Some(path.clone()));
+            let res = match format {
+                OutputFormat::Text => {
+                    vm.emit_fully_expanded_ast_to_string(&contents, Some(path.clone()))
+                }
+                OutputFormat::Json => vm
+                    .emit_fully_expanded_ast_to_json(&contents, Some(path.clone()))
+                    .map(|ast| serde_json::to_string_pretty(&ast).unwrap()),
+            };
 
             match res {
                 Ok(ast) => println!("{ast}"),
@@ -198,6 +508,38 @@ pub fn run(clap_args: Args) -> Result<(), Box<dyn Error>> {
     }
 }
 
+fn print_packages(packages: &[LockedPackage]) {
+    for package in packages {
+        println!(
+            "Installed {} {} from {}",
+            package.name, package.version, package.source
+        );
+    }
+}
+
+// The file itself, or every .scm file under a directory
+fn scheme_files(path: &PathBuf) -> std::io::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.clone()]);
+    }
+
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            files.extend(scheme_files(&path)?);
+        } else if path.extension().map(|x| x == "scm").unwrap_or(false) {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
 pub fn finish(result: Result<(), std::io::Error>) -> ! {
     let code = match result {
         Ok(()) => 0,
@@ -214,24 +556,17 @@ pub fn finish(result: Result<(), std::io::Error>) -> ! {
     process::exit(code);
 }
 
+// Parsed from the command line, so that new options don't have to be added here as well
 #[test]
 fn test_runner() {
-    let args = Args {
-        action: None,
-        default_file: Some(PathBuf::from("cogs/test-runner.scm")),
-        arguments: vec!["cogs/".to_string()],
-    };
+    let args = Args::parse_from(["steel", "cogs/test-runner.scm", "cogs/"]);
 
     run(args).unwrap()
 }
 
 #[test]
 fn r5rs_test_suite() {
-    let args = Args {
-        action: None,
-        default_file: Some(PathBuf::from("cogs/r5rs.scm")),
-        arguments: vec![],
-    };
+    let args = Args::parse_from(["steel", "cogs/r5rs.scm"]);
 
     run(args).unwrap()
 }